@@ -0,0 +1,97 @@
+// A benchmark for the signal path of a real bus watcher, on a workload of a thousand units.
+//
+// Unlike the benchmarks in `monitoring.rs`, which reproduce parts of what a bus watcher does, this
+// one runs a bus watcher, and feeds it the signals of a simulated systemd. Each iteration
+// reactivates and then fails every unit, and waits for the bus watcher to produce an event for
+// each failure. As signals are handled in order, all of them have been handled by then. That
+// covers decoding signals, matching rules, updating state machines and producing events, i.e.
+// everything a bus watcher does per signal except talking to a real bus.
+//
+// Run it with `cargo bench --bench mock_bus --features mock-bus`. As for `monitoring.rs`, save a
+// baseline before making a change, and compare against it afterwards.
+
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dbus::BusType;
+
+use killjoy::error::Error as CrateError;
+use killjoy::{ActiveState, Event, MockSystemd, Monitor, RuleBuilder, RunOptions, SettingsBuilder};
+
+// The number of units which the simulated systemd has loaded, all of which are watched.
+const UNIT_COUNT: usize = 1000;
+
+// The number of rules in effect. One matches every unit, and the others match none.
+const RULE_COUNT: usize = 100;
+
+// How long to wait for an event before concluding that the bus watcher is stuck.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The channel on which a monitor sends events.
+type Events = Receiver<Result<Event, CrateError>>;
+
+// Start watching the units of a simulated systemd which has `UNIT_COUNT` active units, and wait
+// until every unit is watched.
+fn spawn_monitor() -> (MockSystemd, Events, Vec<String>) {
+    let unit_names: Vec<String> = (0..UNIT_COUNT)
+        .map(|i| format!("unit-{}.service", i))
+        .collect();
+    let mock = MockSystemd::new();
+    for unit_name in &unit_names {
+        mock.add_unit(unit_name, ActiveState::Active);
+    }
+
+    let mut settings = SettingsBuilder::new().rule(
+        RuleBuilder::new(BusType::Session)
+            .watch_unit_type(".service")
+            .on_states(vec![ActiveState::Failed]),
+    );
+    for i in 1..RULE_COUNT {
+        settings = settings.rule(
+            RuleBuilder::new(BusType::Session)
+                .watch_unit_name(&format!("other-{}.service", i))
+                .on_states(vec![ActiveState::Failed]),
+        );
+    }
+    let settings = settings.build().expect("Failed to build settings.");
+    let events = Monitor::with_mock(settings, RunOptions::default(), mock.clone()).events();
+    while !unit_names
+        .iter()
+        .all(|unit_name| mock.is_watched(unit_name))
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    (mock, events, unit_names)
+}
+
+// Reactivate and fail every unit, and wait for the bus watcher to report each failure.
+//
+// Throughput is measured in signals, of which there are two per unit.
+fn bench_signal_path(c: &mut Criterion) {
+    let (mock, events, unit_names) = spawn_monitor();
+    let mut group = c.benchmark_group("signal_path");
+    group.throughput(Throughput::Elements(2 * UNIT_COUNT as u64));
+    group.sample_size(20);
+    group.bench_function(UNIT_COUNT.to_string(), |b| {
+        b.iter(|| {
+            for unit_name in &unit_names {
+                mock.set_active_state(unit_name, ActiveState::Active);
+            }
+            for unit_name in &unit_names {
+                mock.set_active_state(unit_name, ActiveState::Failed);
+            }
+            for _ in &unit_names {
+                events
+                    .recv_timeout(EVENT_TIMEOUT)
+                    .expect("No event arrived.")
+                    .expect("The bus watcher failed.");
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_signal_path);
+criterion_main!(benches);
@@ -0,0 +1,315 @@
+// Benchmarks for the work killjoy does per unit and per signal.
+//
+// D-Bus round trips dominate in practice, but they're out of killjoy's hands, so these benchmarks
+// leave them out. They measure what killjoy does with the replies and signals it receives: matching
+// unit names against rules, decoding PropertiesChanged signals and updating unit state machines,
+// and learning about extant units at startup. The units and signals are synthesized by
+// `SignalGenerator`.
+//
+// Run them with `cargo bench`. To judge a change, save a baseline before making it, with `cargo
+// bench -- --save-baseline before`, and compare against it afterwards, with `cargo bench --
+// --baseline before`.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use dbus::arg::Variant;
+use dbus::{BusType, Message};
+
+use killjoy::bus;
+use killjoy::bus::UnitProps;
+use killjoy::error::Error as CrateError;
+use killjoy::settings::Rule;
+use killjoy::timestamp;
+use killjoy::unit;
+use killjoy::unit::{ActiveState, UnitStateMachine};
+use killjoy::RuleBuilder;
+
+const INTERFACE_FOR_PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const INTERFACE_FOR_UNIT: &str = "org.freedesktop.systemd1.Unit";
+
+// The numbers of rules to match unit names against.
+const RULE_COUNTS: [usize; 4] = [1, 10, 100, 1000];
+
+// The numbers of units to learn about at startup, and to spread signals across.
+const UNIT_COUNTS: [usize; 3] = [10, 100, 1000];
+
+// The number of rules in effect while signals are handled and units are learned about.
+const RULE_COUNT: usize = 100;
+
+// The number of signals handled per iteration.
+const SIGNAL_COUNT: usize = 1000;
+
+// The timestamp keys read by `timestamp::get_monotonic_timestamp` and `get_realtime_timestamp`.
+const TIMESTAMP_KEYS: [&str; 5] = [
+    "ActiveEnterTimestamp",
+    "ActiveExitTimestamp",
+    "InactiveEnterTimestamp",
+    "InactiveExitTimestamp",
+    "StateChangeTimestamp",
+];
+
+// The states which synthesized units cycle through. Each signal moves a unit to the next one.
+const ACTIVE_STATES: [&str; 4] = ["activating", "active", "deactivating", "failed"];
+
+// Units' state machines, keyed by unit name.
+type UnitStates = HashMap<String, UnitStateMachine>;
+
+// Synthesizes the units that systemd would list, and the signals that it would send about them.
+//
+// Timestamps advance with every set of properties generated, so that each signal is newer than the
+// state machine it's applied to, as it would be on a live system.
+struct SignalGenerator {
+    clock: u64,
+    next_unit: usize,
+    unit_names: Vec<String>,
+}
+
+impl SignalGenerator {
+    // Create a generator for `unit_count` units, named like "unit-3.service".
+    fn new(unit_count: usize) -> Self {
+        SignalGenerator {
+            clock: 0,
+            next_unit: 0,
+            unit_names: (0..unit_count)
+                .map(|i| format!("unit-{}.service", i))
+                .collect(),
+        }
+    }
+
+    // Generate each unit's properties, as returned by GetAll while killjoy starts up.
+    fn gen_listed_units(&mut self) -> Vec<(String, UnitProps)> {
+        let unit_names = self.unit_names.clone();
+        unit_names
+            .into_iter()
+            .map(|unit_name| {
+                let unit_props = self.gen_unit_props(ACTIVE_STATES[0]);
+                (unit_name, unit_props)
+            })
+            .collect()
+    }
+
+    // Generate `count` PropertiesChanged signals, spread across the units in turn.
+    fn gen_properties_changed(&mut self, count: usize) -> Vec<Message> {
+        (0..count)
+            .map(|_| {
+                let unit_index = self.next_unit % self.unit_names.len();
+                let round = self.next_unit / self.unit_names.len();
+                self.next_unit += 1;
+                let active_state = ACTIVE_STATES[(round + 1) % ACTIVE_STATES.len()];
+                let unit_props = self.gen_unit_props(active_state);
+                Message::new_signal(
+                    unit::get_unit_path(&self.unit_names[unit_index]),
+                    INTERFACE_FOR_PROPERTIES,
+                    "PropertiesChanged",
+                )
+                .expect("Failed to create signal.")
+                .append3(INTERFACE_FOR_UNIT, unit_props, Vec::<String>::new())
+            })
+            .collect()
+    }
+
+    // Generate the properties of a unit which just entered `active_state`.
+    fn gen_unit_props(&mut self, active_state: &str) -> UnitProps {
+        self.clock += 1;
+        let mut unit_props = UnitProps::new();
+        unit_props.insert(
+            "ActiveState".to_string(),
+            Variant(Box::new(active_state.to_string())),
+        );
+        unit_props.insert(
+            "SubState".to_string(),
+            Variant(Box::new(active_state.to_string())),
+        );
+        for key in &TIMESTAMP_KEYS {
+            unit_props.insert(key.to_string(), Variant(Box::new(self.clock)));
+            unit_props.insert(format!("{}Monotonic", key), Variant(Box::new(self.clock)));
+        }
+        unit_props
+    }
+}
+
+// Generate `count` rules, which use each kind of unit name expression in turn.
+//
+// Every fourth rule watches all services, so every synthesized unit is watched.
+fn gen_rules(count: usize) -> Vec<Rule> {
+    (0..count)
+        .map(|i| {
+            let builder = RuleBuilder::new(BusType::System);
+            let builder = match i % 4 {
+                0 => builder.watch_unit_name(&format!("unit-{}.service", i)),
+                1 => builder.watch_unit_template(&format!("unit-{}@.service", i)),
+                2 => builder.watch_regex(&format!(r"^unit-{}-[a-z]+\.service$", i)),
+                _ => builder.watch_unit_type(".service"),
+            };
+            builder
+                .on_states(vec![ActiveState::Failed])
+                .build()
+                .expect("Failed to build rule.")
+        })
+        .collect()
+}
+
+// Upsert a unit's state machine, much like a bus watcher does.
+//
+// `events` counts the transitions into states which matching rules watch, i.e. those which a bus
+// watcher would dispatch.
+fn upsert_unit_state(
+    unit_name: &str,
+    unit_props: &UnitProps,
+    rules: &[&Rule],
+    unit_states: &mut UnitStates,
+    events: &Cell<usize>,
+) -> Result<(), CrateError> {
+    let matching_rules = bus::get_rules_matching_name(rules, unit_name);
+    let active_state = bus::get_active_state(unit_props)?;
+    let mono_ts = timestamp::get_monotonic_timestamp(&active_state, unit_props)?;
+    let real_ts = timestamp::get_realtime_timestamp(&active_state, unit_props)?;
+    let on_change = |usm: &UnitStateMachine, _: Option<ActiveState>| -> Result<(), CrateError> {
+        if matching_rules
+            .iter()
+            .any(|rule| rule.active_states.contains(usm.active_state()))
+        {
+            events.set(events.get() + 1);
+        }
+        Ok(())
+    };
+    let on_sub_change = |_: &UnitStateMachine, _: String| -> Result<(), CrateError> { Ok(()) };
+    match unit_states.get_mut(unit_name) {
+        Some(usm) => usm.update(
+            active_state,
+            None,
+            mono_ts,
+            real_ts,
+            &on_change,
+            &on_sub_change,
+        )?,
+        None => {
+            let usm = UnitStateMachine::new(active_state, None, mono_ts, real_ts, &on_change)?;
+            unit_states.insert(unit_name.to_string(), usm);
+        }
+    }
+    Ok(())
+}
+
+// Handle a PropertiesChanged signal, much like a bus watcher does.
+//
+// Every synthesized unit is watched, so the signal's path is always decoded, and systemd is never
+// asked for a unit's Id.
+fn handle_properties_changed(
+    msg: &Message,
+    rules: &[&Rule],
+    unit_states: &mut UnitStates,
+    events: &Cell<usize>,
+) -> Result<(), CrateError> {
+    let (interface, unit_props) = msg
+        .read2::<&str, UnitProps>()
+        .expect("Failed to read signal.");
+    if interface != INTERFACE_FOR_UNIT {
+        return Ok(());
+    }
+    let unit_path = msg.path().expect("Signal lacks a path.");
+    let unit_name = unit::get_unit_name(&unit_path)
+        .filter(|unit_name| unit_states.contains_key(unit_name))
+        .expect("Signal is about an unknown unit.");
+    upsert_unit_state(&unit_name, &unit_props, rules, unit_states, events)
+}
+
+// Match a hundred unit names against increasingly many rules.
+fn bench_rule_matching(c: &mut Criterion) {
+    let unit_names: Vec<String> = (0..100)
+        .map(|i| match i % 3 {
+            0 => format!("unit-{}.service", i),
+            1 => format!("unit-{}@instance.service", i),
+            _ => format!("unit-{}.timer", i),
+        })
+        .collect();
+    let mut group = c.benchmark_group("rule_matching");
+    group.throughput(Throughput::Elements(unit_names.len() as u64));
+    for &rule_count in &RULE_COUNTS {
+        let rules = gen_rules(rule_count);
+        let rules: Vec<&Rule> = rules.iter().collect();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(rule_count),
+            &rules,
+            |b, rules| {
+                b.iter(|| {
+                    unit_names
+                        .iter()
+                        .map(|unit_name| bus::get_rules_matching_name(rules, unit_name).len())
+                        .sum::<usize>()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// Handle a batch of PropertiesChanged signals, spread across increasingly many units.
+fn bench_properties_changed(c: &mut Criterion) {
+    let rules = gen_rules(RULE_COUNT);
+    let rules: Vec<&Rule> = rules.iter().collect();
+    let mut group = c.benchmark_group("properties_changed");
+    group.throughput(Throughput::Elements(SIGNAL_COUNT as u64));
+    for &unit_count in &UNIT_COUNTS {
+        let mut generator = SignalGenerator::new(unit_count);
+        let events = Cell::new(0);
+        let mut unit_states = UnitStates::new();
+        for (unit_name, unit_props) in generator.gen_listed_units() {
+            upsert_unit_state(&unit_name, &unit_props, &rules, &mut unit_states, &events)
+                .expect("Failed to learn about unit.");
+        }
+        group.bench_function(BenchmarkId::from_parameter(unit_count), |b| {
+            b.iter_batched(
+                || generator.gen_properties_changed(SIGNAL_COUNT),
+                |signals| {
+                    for msg in &signals {
+                        handle_properties_changed(msg, &rules, &mut unit_states, &events)
+                            .expect("Failed to handle signal.");
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Learn about increasingly many extant units, as killjoy does at startup.
+fn bench_startup_enumeration(c: &mut Criterion) {
+    let rules = gen_rules(RULE_COUNT);
+    let rules: Vec<&Rule> = rules.iter().collect();
+    let mut group = c.benchmark_group("startup_enumeration");
+    for &unit_count in &UNIT_COUNTS {
+        let mut generator = SignalGenerator::new(unit_count);
+        group.throughput(Throughput::Elements(unit_count as u64));
+        group.bench_function(BenchmarkId::from_parameter(unit_count), |b| {
+            b.iter_batched(
+                || generator.gen_listed_units(),
+                |listed_units| {
+                    let events = Cell::new(0);
+                    let mut unit_states = UnitStates::new();
+                    for (unit_name, unit_props) in &listed_units {
+                        if bus::get_rules_matching_name(&rules, unit_name).is_empty() {
+                            continue;
+                        }
+                        upsert_unit_state(unit_name, unit_props, &rules, &mut unit_states, &events)
+                            .expect("Failed to learn about unit.");
+                    }
+                    unit_states
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_rule_matching,
+    bench_properties_changed,
+    bench_startup_enumeration
+);
+criterion_main!(benches);
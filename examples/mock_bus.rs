@@ -0,0 +1,50 @@
+// Watch a unit of a simulated systemd, and print the event which its failure causes.
+//
+// Run with: cargo run --example mock_bus --features mock-bus
+
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use dbus::BusType;
+use killjoy::{ActiveState, MockSystemd, Monitor, RuleBuilder, RunOptions, SettingsBuilder};
+
+fn main() {
+    let settings = SettingsBuilder::new()
+        .rule(
+            RuleBuilder::new(BusType::Session)
+                .watch_unit_name("foo.service")
+                .on_states(vec![ActiveState::Failed]),
+        )
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+
+    let mock = MockSystemd::new();
+    mock.add_unit("foo.service", ActiveState::Active);
+
+    let monitor = Monitor::with_mock(settings, RunOptions::default(), mock.clone());
+    let stop = monitor.stop_handle();
+    let events = monitor.events();
+
+    // The unit must be watched before it fails, or its failure goes unnoticed.
+    while !mock.is_watched("foo.service") {
+        thread::sleep(Duration::from_millis(10));
+    }
+    mock.set_active_state("foo.service", ActiveState::Failed);
+
+    match events.recv_timeout(Duration::from_secs(5)) {
+        Ok(Ok(event)) => println!("{} is now {:?}", event.unit_name, event.new_state),
+        Ok(Err(err)) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("No event arrived: {}", err);
+            process::exit(1);
+        }
+    }
+    stop.stop();
+}
@@ -0,0 +1,17 @@
+// Feed arbitrary bytes to the settings file parsers.
+//
+// Settings files are written by hand, so they may contain anything. Parsing one may fail, but it
+// must not panic.
+
+#![no_main]
+
+use killjoy::settings;
+use killjoy::Settings;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Settings::new(data);
+    if let Ok(contents) = std::str::from_utf8(data) {
+        let _ = settings::validate_str(contents);
+    }
+});
@@ -0,0 +1,22 @@
+// Feed arbitrary strings to the unit path encoder and decoder.
+//
+// Object paths arrive from the bus, so they may contain anything. Decoding one may fail, but it
+// must not panic, and encoding and decoding a unit name must give back the same name.
+
+#![no_main]
+
+use killjoy::unit;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let value = match std::str::from_utf8(data) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    if let Some(unit_name) = unit::get_unit_name(value) {
+        let unit_path = unit::get_unit_path(&unit_name);
+        assert_eq!(unit::get_unit_name(&unit_path), Some(unit_name));
+    }
+    let unit_path = unit::get_unit_path(value);
+    assert_eq!(unit::get_unit_name(&unit_path).as_deref(), Some(value));
+});
@@ -0,0 +1,489 @@
+// Logic for taking remedial actions against units.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::os::unix::net::UnixDatagram;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use dbus::{BusType, ConnPath, Connection};
+use serde::Deserialize;
+
+use crate::bus;
+use crate::error::{ActionError, BusError, Error as CrateError, SettingsError};
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1Manager;
+use crate::settings;
+use crate::settings::Severity;
+use crate::unit::ActiveState;
+
+// Where systemd-journald listens for log entries using its native protocol.
+const JOURNAL_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+// The kinds of actions that killjoy may take.
+//
+// The unit actions correspond to methods on org.freedesktop.systemd1.Manager, e.g. `RestartUnit`
+// corresponds to `RestartUnit`. `RunCommand` holds a program and its arguments. `WriteJournalEntry`
+// holds an optional message to log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ActionType {
+    RestartUnit,
+    RunCommand(Vec<String>),
+    StartUnit,
+    StopUnit,
+    WriteJournalEntry(Option<String>),
+}
+
+impl Display for ActionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let msg = match self {
+            ActionType::RestartUnit => "restart-unit",
+            ActionType::RunCommand(_) => "run-command",
+            ActionType::StartUnit => "start-unit",
+            ActionType::StopUnit => "stop-unit",
+            ActionType::WriteJournalEntry(_) => "write-journal-entry",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+// A remediation to apply when a rule matches.
+//
+// The action is only taken if the unit entered one of `active_states`, or if `active_states` is
+// empty. To prevent a broken unit from being acted upon forever, this action is taken at most
+// `max_attempts` times per unit within `window`.
+#[derive(Clone, Debug)]
+pub struct Action {
+    pub action_type: ActionType,
+    pub active_states: HashSet<ActiveState>,
+    pub max_attempts: u32,
+    pub window: Duration,
+}
+
+impl Action {
+    // Get the action which a rule's `action` key stands for.
+    //
+    // `action` is the older form of `actions`, from when a rule could only restart, start or stop
+    // its units. E.g. `"action": "restart"` is short for `"actions": [{"type": "restart-unit"}]`,
+    // and `action_max_attempts` and `action_window` stand in for `max_attempts` and `window`.
+    pub fn from_shorthand(
+        action: &str,
+        max_attempts: Option<u32>,
+        window: Option<u64>,
+    ) -> Result<Self, CrateError> {
+        let action_type = match action {
+            "restart" => ActionType::RestartUnit,
+            "start" => ActionType::StartUnit,
+            "stop" => ActionType::StopUnit,
+            other => {
+                return Err(CrateError::Settings(SettingsError::InvalidActionType(
+                    other.to_owned(),
+                )))
+            }
+        };
+        Ok(Action {
+            action_type,
+            active_states: HashSet::new(),
+            max_attempts: max_attempts.unwrap_or_else(default_max_attempts),
+            window: Duration::from_secs(window.unwrap_or_else(default_window)),
+        })
+    }
+
+    // Tell whether this action should be taken when a unit enters the given state.
+    pub fn applies_to(&self, active_state: &ActiveState) -> bool {
+        self.active_states.is_empty() || self.active_states.contains(active_state)
+    }
+}
+
+impl TryFrom<SerdeAction> for Action {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeAction) -> Result<Self, Self::Error> {
+        let action_type = match &value.action_type[..] {
+            "restart-unit" => ActionType::RestartUnit,
+            "run-command" => {
+                if value.command.is_empty() {
+                    return Err(CrateError::Settings(SettingsError::MissingActionCommand));
+                }
+                ActionType::RunCommand(value.command)
+            }
+            "start-unit" => ActionType::StartUnit,
+            "stop-unit" => ActionType::StopUnit,
+            "write-journal-entry" => ActionType::WriteJournalEntry(value.message),
+            other => {
+                return Err(CrateError::Settings(SettingsError::InvalidActionType(
+                    other.to_owned(),
+                )))
+            }
+        };
+
+        let mut active_states: HashSet<ActiveState> = HashSet::new();
+        for active_state_string in &value.active_states {
+            active_states.extend(settings::parse_active_states(active_state_string)?);
+        }
+
+        Ok(Action {
+            action_type,
+            active_states,
+            max_attempts: value.max_attempts,
+            window: Duration::from_secs(value.window),
+        })
+    }
+}
+
+// See settings::SerdeSettings.
+#[derive(Deserialize)]
+pub struct SerdeAction {
+    #[serde(rename = "type")]
+    action_type: String,
+    #[serde(default)]
+    active_states: Vec<String>,
+    #[serde(default)]
+    command: Vec<String>,
+    message: Option<String>,
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+    #[serde(default = "default_window")]
+    window: u64,
+}
+
+// See SerdeAction.
+fn default_max_attempts() -> u32 {
+    3
+}
+
+// See SerdeAction. In seconds.
+fn default_window() -> u64 {
+    600
+}
+
+// A request to take an action against a unit which has just entered `active_state`.
+//
+// `severity` is that of the rule which requested the action.
+pub struct ActionRequest {
+    pub action: Action,
+    pub active_state: ActiveState,
+    pub severity: Severity,
+    pub unit_name: String,
+}
+
+// Execute actions on a dedicated thread.
+//
+// Actions may be slow (a command may take a long time to run), and they must not stall the thread
+// that watches units. Requests are therefore queued, and executed in order by a worker thread.
+// Unit actions are sent to the systemd instance on `bus_type`.
+//
+// Upon drop, pending requests are executed, and then the worker thread is joined.
+pub struct ActionExecutor {
+    handle: Option<JoinHandle<()>>,
+    sender: Option<Sender<ActionRequest>>,
+}
+
+impl ActionExecutor {
+    // Spawn a worker thread.
+    pub fn new(bus_type: BusType) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || execute_requests(bus_type, receiver));
+        ActionExecutor {
+            handle: Some(handle),
+            sender: Some(sender),
+        }
+    }
+
+    // Queue a request for execution.
+    pub fn submit(&self, request: ActionRequest) {
+        let sent = match &self.sender {
+            Some(sender) => sender.send(request).is_ok(),
+            None => false,
+        };
+        if !sent {
+            eprintln!("Failed to queue action: the action executor thread has exited.");
+        }
+    }
+}
+
+impl Drop for ActionExecutor {
+    fn drop(&mut self) {
+        // Dropping the sender makes the worker thread's receive loop end.
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                eprintln!("The action executor thread panicked.");
+            }
+        }
+    }
+}
+
+// The times at which actions were recently taken, keyed by an arbitrary string.
+//
+// Used to enforce each action's `max_attempts` and `window`.
+#[derive(Default)]
+struct ActionAttempts(HashMap<String, VecDeque<Instant>>);
+
+impl ActionAttempts {
+    // Record an attempt against `key` at time `now`, if doing so is permitted.
+    //
+    // Attempts older than `window` are forgotten. If fewer than `max_attempts` attempts remain,
+    // record this one and return true. Otherwise, return false.
+    fn try_record(&mut self, key: &str, max_attempts: u32, window: Duration, now: Instant) -> bool {
+        let attempts = self.0.entry(key.to_string()).or_default();
+        while let Some(oldest) = attempts.front() {
+            if now.duration_since(*oldest) >= window {
+                attempts.pop_front();
+            } else {
+                break;
+            }
+        }
+        if attempts.len() < max_attempts as usize {
+            attempts.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Execute requests until the sending half of the channel is dropped.
+//
+// Errors are printed rather than returned, as one failed action shouldn't prevent others.
+fn execute_requests(bus_type: BusType, receiver: Receiver<ActionRequest>) {
+    let mut attempts = ActionAttempts::default();
+    let mut connection: Option<Connection> = None;
+    for request in receiver {
+        let action = &request.action;
+        let key = format!("{} {}", action.action_type, request.unit_name);
+        if !attempts.try_record(&key, action.max_attempts, action.window, Instant::now()) {
+            eprintln!(
+                "Not applying {} action to \"{}\": {} attempts have been made in the last {}s.",
+                action.action_type,
+                request.unit_name,
+                action.max_attempts,
+                action.window.as_secs()
+            );
+            continue;
+        }
+        if let Err(err) = execute_request(&request, bus_type, &mut connection) {
+            eprintln!(
+                "Error occurred when applying {} action to \"{}\": {}",
+                action.action_type,
+                request.unit_name,
+                err.with_code()
+            );
+        }
+    }
+}
+
+// Execute a single request.
+//
+// A connection to `bus_type` is lazily established and stored in `connection`.
+fn execute_request(
+    request: &ActionRequest,
+    bus_type: BusType,
+    connection: &mut Option<Connection>,
+) -> Result<(), CrateError> {
+    let unit_name = &request.unit_name[..];
+    match &request.action.action_type {
+        ActionType::RestartUnit | ActionType::StartUnit | ActionType::StopUnit => {
+            if connection.is_none() {
+                *connection = Some(
+                    Connection::get_private(bus_type)
+                        .map_err(|err| CrateError::Bus(BusError::ConnectToBus(err)))?,
+                );
+            }
+            let conn = connection
+                .as_ref()
+                .expect("connection was just established");
+            call_manager_action(conn, unit_name, &request.action.action_type)
+        }
+        ActionType::RunCommand(command) => run_command(command, request),
+        ActionType::WriteJournalEntry(message) => write_journal_entry(message.as_deref(), request),
+    }
+}
+
+// Call `org.freedesktop.systemd1.Manager.RestartUnit`, `StartUnit` or `StopUnit`.
+//
+// The job mode is "replace". Jobs are queued, not awaited.
+fn call_manager_action(
+    conn: &Connection,
+    unit_name: &str,
+    action_type: &ActionType,
+) -> Result<(), CrateError> {
+    let conn_path = ConnPath {
+        conn,
+        dest: bus::wrap_bus_name_for_systemd(),
+        path: bus::wrap_path_for_systemd(),
+        timeout: 5000, // milliseconds
+    };
+    let mode = "replace";
+    match action_type {
+        ActionType::RestartUnit => conn_path.restart_unit(unit_name, mode).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerRestartUnit(err))
+        }),
+        ActionType::StartUnit => conn_path.start_unit(unit_name, mode).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerStartUnit(err))
+        }),
+        ActionType::StopUnit => conn_path.stop_unit(unit_name, mode).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerStopUnit(err))
+        }),
+        _ => unreachable!("not a unit action: {}", action_type),
+    }
+    .map(|_| ())
+}
+
+// Run the given command, and wait for it to exit.
+//
+// The command is told about the unit through the `KILLJOY_UNIT` and `KILLJOY_ACTIVE_STATE`
+// environment variables. A non-zero exit status is an error.
+fn run_command(command: &[String], request: &ActionRequest) -> Result<(), CrateError> {
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .env("KILLJOY_UNIT", &request.unit_name)
+        .env("KILLJOY_ACTIVE_STATE", request.active_state.to_string())
+        .env("KILLJOY_SEVERITY", String::from(request.severity))
+        .status()
+        .map_err(|err| CrateError::Action(ActionError::RunCommand(command[0].to_owned(), err)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CrateError::Action(ActionError::RunCommandFailed(
+            command[0].to_owned(),
+            status,
+        )))
+    }
+}
+
+// Write an entry to the systemd journal.
+//
+// If `message` is `None`, a message describing the state change is generated. The entry's priority
+// is derived from the request's severity.
+fn write_journal_entry(message: Option<&str>, request: &ActionRequest) -> Result<(), CrateError> {
+    let default_message = format!(
+        "{} entered the {} state.",
+        request.unit_name, request.active_state
+    );
+    let entry = format_journal_entry(&[
+        ("MESSAGE", message.unwrap_or(&default_message)),
+        ("PRIORITY", &request.severity.syslog_priority().to_string()),
+        ("SYSLOG_IDENTIFIER", "killjoy"),
+        ("KILLJOY_UNIT", &request.unit_name),
+        ("KILLJOY_ACTIVE_STATE", &request.active_state.to_string()),
+        ("KILLJOY_SEVERITY", &String::from(request.severity)),
+    ]);
+    let socket = UnixDatagram::unbound()
+        .map_err(|err| CrateError::Action(ActionError::WriteJournalEntry(err)))?;
+    socket
+        .send_to(entry.as_bytes(), JOURNAL_SOCKET_PATH)
+        .map_err(|err| CrateError::Action(ActionError::WriteJournalEntry(err)))?;
+    Ok(())
+}
+
+// Format fields for journald's native protocol.
+//
+// Each field is a KEY=value line. The protocol can carry multi-line values, but only with a binary
+// encoding, so newlines are replaced with spaces instead.
+fn format_journal_entry(fields: &[(&str, &str)]) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value.replace('\n', " ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exhaust an action's attempts, then let them expire.
+    #[test]
+    fn test_action_attempts_try_record() {
+        let mut attempts = ActionAttempts::default();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        assert!(attempts.try_record("a.service", 2, window, start));
+        assert!(attempts.try_record("a.service", 2, window, start + Duration::from_secs(1)));
+        assert!(!attempts.try_record("a.service", 2, window, start + Duration::from_secs(2)));
+        assert!(attempts.try_record("b.service", 2, window, start + Duration::from_secs(2)));
+        assert!(attempts.try_record("a.service", 2, window, start + Duration::from_secs(60)));
+        assert!(!attempts.try_record("a.service", 2, window, start + Duration::from_secs(60)));
+    }
+
+    // Action::applies_to()
+    #[test]
+    fn test_action_applies_to() {
+        let mut action = Action {
+            action_type: ActionType::RestartUnit,
+            active_states: HashSet::new(),
+            max_attempts: 1,
+            window: Duration::from_secs(1),
+        };
+        assert!(action.applies_to(&ActiveState::Active));
+        action.active_states.insert(ActiveState::Failed);
+        assert!(action.applies_to(&ActiveState::Failed));
+        assert!(!action.applies_to(&ActiveState::Active));
+    }
+
+    // Action::try_from()
+    #[test]
+    fn test_action_try_from_run_command() {
+        let serde_action: SerdeAction =
+            serde_json::from_str(r#"{"type": "run-command", "command": ["true"]}"#)
+                .expect("Failed to deserialize action.");
+        let action = Action::try_from(serde_action).expect("Failed to convert action.");
+        assert_eq!(
+            action.action_type,
+            ActionType::RunCommand(vec!["true".to_string()])
+        );
+        assert_eq!(action.max_attempts, default_max_attempts());
+    }
+
+    // Action::try_from()
+    #[test]
+    fn test_action_try_from_missing_command() {
+        let serde_action: SerdeAction = serde_json::from_str(r#"{"type": "run-command"}"#)
+            .expect("Failed to deserialize action.");
+        match Action::try_from(serde_action) {
+            Err(CrateError::Settings(SettingsError::MissingActionCommand)) => {}
+            _ => panic!("expected MissingActionCommand; no command has been given"),
+        }
+    }
+
+    // Action::try_from()
+    #[test]
+    fn test_action_try_from_invalid_type() {
+        let serde_action: SerdeAction =
+            serde_json::from_str(r#"{"type": "reboot"}"#).expect("Failed to deserialize action.");
+        match Action::try_from(serde_action) {
+            Err(CrateError::Settings(SettingsError::InvalidActionType(_))) => {}
+            _ => panic!("expected InvalidActionType; an unsupported action has been requested"),
+        }
+    }
+
+    // run_command()
+    #[test]
+    fn test_run_command() {
+        let request = ActionRequest {
+            action: Action {
+                action_type: ActionType::RunCommand(vec!["true".to_string()]),
+                active_states: HashSet::new(),
+                max_attempts: 3,
+                window: Duration::from_secs(600),
+            },
+            active_state: ActiveState::Failed,
+            severity: Severity::Warning,
+            unit_name: "a.service".to_string(),
+        };
+        run_command(&["true".to_string()], &request).expect("Failed to run true.");
+        match run_command(&["false".to_string()], &request) {
+            Err(CrateError::Action(ActionError::RunCommandFailed(_, _))) => {}
+            _ => panic!("expected RunCommandFailed; false exits non-zero"),
+        }
+    }
+
+    // format_journal_entry()
+    #[test]
+    fn test_format_journal_entry() {
+        let entry = format_journal_entry(&[("MESSAGE", "one\ntwo"), ("PRIORITY", "4")]);
+        assert_eq!(entry, "MESSAGE=one two\nPRIORITY=4\n");
+    }
+}
@@ -0,0 +1,267 @@
+// Logic for posting events to Prometheus Alertmanager, as an alert source.
+//
+// Each event is posted to Alertmanager's API as a single alert. An alert's labels identify the unit
+// and the host it lives on, so each event about a unit updates the same alert. When a unit enters
+// one of the notifier's resolve states, the alert is posted with an end time, which resolves it.
+//
+// See: https://prometheus.io/docs/alerting/latest/clients/
+
+use std::collections::BTreeMap;
+use std::io::{Error as IOError, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::backend::{Notification, NotifierBackend};
+use crate::error::{Error as CrateError, NotifierError};
+use crate::settings;
+use crate::settings::Alertmanager;
+
+// The name of every alert posted by killjoy.
+const ALERT_NAME: &str = "KilljoyUnitState";
+
+// The path, relative to the Alertmanager URL, to which alerts are posted.
+const ALERTS_PATH: &str = "/api/v2/alerts";
+
+// The path, relative to the Alertmanager URL, which answers while Alertmanager is healthy.
+const HEALTHY_PATH: &str = "/-/healthy";
+
+impl NotifierBackend for Alertmanager {
+    fn name(&self) -> &'static str {
+        "alertmanager"
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        let body = Value::Array(vec![gen_alert(self, notification)]);
+        request(
+            notifier_name,
+            &format!("{}{}", self.url.trim_end_matches('/'), ALERTS_PATH),
+            Some(&body),
+            timeout,
+        )
+    }
+
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        request(
+            notifier_name,
+            &format!("{}{}", self.url.trim_end_matches('/'), HEALTHY_PATH),
+            None,
+            timeout,
+        )
+    }
+}
+
+// Generate an alert describing the given notification, in the form accepted by Alertmanager.
+//
+// The host's tags and the rule's labels are added to the alert's labels, but may not replace the
+// labels which identify the unit. The alert starts at the time of the event. If the unit's new
+// state is one of the resolve states, the alert also ends then.
+pub fn gen_alert(alertmanager: &Alertmanager, notification: &Notification) -> Value {
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+    labels.extend(notification.host.tags.clone());
+    labels.extend(notification.labels.clone());
+    labels.insert("alertname".to_string(), ALERT_NAME.to_string());
+    labels.insert(
+        "bus".to_string(),
+        settings::encode_bus_type(notification.bus_type).to_string(),
+    );
+    labels.insert(
+        "instance".to_string(),
+        notification.host.hostname.to_owned(),
+    );
+    labels.insert("severity".to_string(), String::from(notification.severity));
+    labels.insert("unit".to_string(), notification.unit_name.to_owned());
+
+    let new_state = notification.states.first().map(String::as_str);
+    let mut annotations: Map<String, Value> = Map::new();
+    let summary = match &notification.message {
+        Some(message) => message.to_owned(),
+        None => format!(
+            "{} is {}",
+            notification.unit_name,
+            new_state.unwrap_or_default()
+        ),
+    };
+    annotations.insert("summary".to_string(), Value::from(summary));
+    if let Some(new_state) = new_state {
+        annotations.insert("new_state".to_string(), Value::from(new_state));
+    }
+    if let Some(old_state) = notification.states.get(1) {
+        annotations.insert("old_state".to_string(), Value::from(&old_state[..]));
+    }
+    if let Some(rule_name) = notification.rule_name {
+        annotations.insert("rule".to_string(), Value::from(rule_name));
+    }
+    if let Some(machine_id) = &notification.host.machine_id {
+        annotations.insert("machine_id".to_string(), Value::from(&machine_id[..]));
+    }
+    if let Some(journal_lines) = notification.journal_lines {
+        annotations.insert("journal".to_string(), Value::from(journal_lines.join("\n")));
+    }
+
+    let timestamp = notification.real_ts.to_iso8601();
+    let mut alert = json!({
+        "labels": labels,
+        "annotations": annotations,
+        "startsAt": timestamp,
+    });
+    let resolved = new_state.map_or(false, |new_state| {
+        alertmanager
+            .resolve_states
+            .iter()
+            .any(|state| state.to_string() == new_state)
+    });
+    if resolved {
+        alert["endsAt"] = Value::from(timestamp);
+    }
+    alert
+}
+
+// Request the given URL, posting `body` as JSON if given, and fail unless the request succeeds.
+//
+// The request is made by calling curl(1), which supports HTTPS without further dependencies.
+fn request(
+    notifier_name: &str,
+    url: &str,
+    body: Option<&Value>,
+    timeout: Duration,
+) -> Result<(), CrateError> {
+    let run_err = |err: IOError| {
+        CrateError::Notifier(NotifierError::ContactAlertmanager(
+            notifier_name.to_string(),
+            err,
+        ))
+    };
+    let mut command = Command::new("curl");
+    command
+        .args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--output",
+            "/dev/null",
+        ])
+        .args(["--max-time", &timeout.as_secs().max(1).to_string()]);
+    if body.is_some() {
+        command.args([
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ]);
+    }
+    let mut child = command
+        .arg(url)
+        .stdin(if body.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn()
+        .map_err(run_err)?;
+    if let (Some(body), Some(mut stdin)) = (body, child.stdin.take()) {
+        if let Err(err) = stdin.write_all(body.to_string().as_bytes()) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(run_err(err));
+        }
+    }
+    let status = child.wait().map_err(run_err)?;
+    if !status.success() {
+        return Err(CrateError::Notifier(NotifierError::AlertmanagerFailed(
+            notifier_name.to_string(),
+            status,
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use dbus::BusType;
+
+    use crate::backend::Host;
+    use crate::settings::Severity;
+    use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
+    use crate::unit::ActiveState;
+
+    // gen_alert()
+    #[test]
+    fn test_gen_alert() {
+        let alertmanager = Alertmanager::new("http://localhost:9093").unwrap();
+        let mut tags = HashMap::new();
+        tags.insert("site".to_string(), "ams".to_string());
+        tags.insert("unit".to_string(), "ignored".to_string());
+        let host = Host {
+            hostname: "web1".to_string(),
+            machine_id: None,
+            tags,
+        };
+        let mut labels = HashMap::new();
+        labels.insert("team".to_string(), "ops".to_string());
+        let payload = HashMap::new();
+        let states = vec!["failed".to_string(), "active".to_string()];
+        let mut notification = Notification {
+            bus_type: BusType::System,
+            dependents: None,
+            host: &host,
+            journal_lines: None,
+            labels: &labels,
+            message: None,
+            mono_ts: &MonotonicTimestamp(0),
+            payload: &payload,
+            real_ts: &RealtimeTimestamp(0),
+            rule_name: Some("web"),
+            rule_names: &[],
+            severity: Severity::Critical,
+            states: &states,
+            unit_name: "foo.service",
+        };
+        assert_eq!(
+            gen_alert(&alertmanager, &notification),
+            json!({
+                "labels": {
+                    "alertname": "KilljoyUnitState",
+                    "bus": "system",
+                    "instance": "web1",
+                    "severity": "critical",
+                    "site": "ams",
+                    "team": "ops",
+                    "unit": "foo.service",
+                },
+                "annotations": {
+                    "new_state": "failed",
+                    "old_state": "active",
+                    "rule": "web",
+                    "summary": "foo.service is failed",
+                },
+                "startsAt": "1970-01-01T00:00:00Z",
+            })
+        );
+
+        let states = vec!["active".to_string()];
+        notification.states = &states;
+        notification.message = Some("foo.service is back".to_string());
+        let alert = gen_alert(&alertmanager, &notification);
+        assert_eq!(alert["endsAt"], "1970-01-01T00:00:00Z");
+        assert_eq!(alert["annotations"]["summary"], "foo.service is back");
+
+        let alertmanager = Alertmanager {
+            resolve_states: vec![ActiveState::Inactive],
+            ..alertmanager
+        };
+        assert!(gen_alert(&alertmanager, &notification)
+            .get("endsAt")
+            .is_none());
+    }
+}
@@ -0,0 +1,124 @@
+// Logic for contacting notifiers, whatever kind of service they are.
+//
+// Each kind of notifier implements `NotifierBackend`, and `get_backend` acts as the registry of
+// kinds. To add a kind of notifier, add a variant to `NotifierTarget` (behind a cargo feature, if
+// the kind needs more dependencies), teach `Notifier::try_from` to parse it, implement
+// `NotifierBackend` for it, and return it from `get_backend`. Notifiers that live outside of this
+// repository may instead be run as plugins, which speak JSON over stdin and stdout, as described in
+// the `plugin` module.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use dbus::BusType;
+
+use crate::bus;
+use crate::bus::DBusBackend;
+use crate::error::Error as CrateError;
+use crate::settings::{Identity, Notifier, NotifierTarget, Severity};
+use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
+
+// The host on which events occur, as described to notifiers. See `Identity`.
+//
+// `machine_id` is `None` unless the settings ask for it, or if it can't be read.
+pub struct Host {
+    pub hostname: String,
+    pub machine_id: Option<String>,
+    pub tags: HashMap<String, String>,
+}
+
+impl Host {
+    // Describe this host, according to the given identity settings.
+    pub fn new(identity: &Identity) -> Self {
+        Self {
+            hostname: identity.hostname.clone().unwrap_or_else(bus::get_hostname),
+            machine_id: if identity.include_machine_id {
+                bus::get_machine_id()
+            } else {
+                None
+            },
+            tags: identity.tags.clone(),
+        }
+    }
+}
+
+// The information sent to a notifier about an event.
+//
+// `host` describes the host on which the unit lives. `states` lists the unit's new state, followed
+// by its old state, if known. `dependents` and `journal_lines` are `None` if the rule didn't ask
+// for them. `message` is `None` if the notifier lacks a message template. `rule_name` names the
+// rule on whose behalf the notifier is contacted, and `rule_names` lists the names of every rule
+// which matched the event, i.e. its tags. See `Notifier::accepts`.
+pub struct Notification<'a> {
+    pub bus_type: BusType,
+    pub dependents: Option<&'a [String]>,
+    pub host: &'a Host,
+    pub journal_lines: Option<&'a [String]>,
+    pub labels: &'a HashMap<String, String>,
+    pub message: Option<String>,
+    pub mono_ts: &'a MonotonicTimestamp,
+    pub payload: &'a HashMap<String, String>,
+    pub real_ts: &'a RealtimeTimestamp,
+    pub rule_name: Option<&'a str>,
+    pub rule_names: &'a [String],
+    pub severity: Severity,
+    pub states: &'a [String],
+    pub unit_name: &'a str,
+}
+
+// A kind of notifier, such as a D-Bus service or an MQTT broker.
+//
+// `notifier_name` is the name of the notifier in the settings file, and is used to describe
+// failures. `timeout` is how long to wait for the notifier, e.g. for each reply. Failing to connect
+// to a notifier is as much a failure to reach it as the notifier rejecting a notification, so both
+// are errors.
+pub trait NotifierBackend {
+    // Get the name of this kind of notifier, as used by the `type` key of settings files.
+    fn name(&self) -> &'static str;
+
+    // Send a notification.
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError>;
+
+    // Check that the notifier is reachable, as far as is possible without sending a notification.
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError>;
+}
+
+impl<T: NotifierBackend + ?Sized> NotifierBackend for &T {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        (**self).send(notifier_name, notification, timeout)
+    }
+
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        (**self).ping(notifier_name, timeout)
+    }
+}
+
+// Get the backend with which to contact the given notifier.
+pub fn get_backend(notifier: &Notifier) -> Box<dyn NotifierBackend + '_> {
+    match &notifier.target {
+        NotifierTarget::Alertmanager(alertmanager) => Box::new(alertmanager),
+        NotifierTarget::DBus(bus_type, bus_name) => Box::new(DBusBackend {
+            bus_name,
+            bus_type: *bus_type,
+            notifier,
+        }),
+        NotifierTarget::Mqtt(broker) => Box::new(broker),
+        NotifierTarget::Plugin(plugin) => Box::new(plugin),
+        NotifierTarget::Snmp(manager) => Box::new(manager),
+        NotifierTarget::Syslog(server) => Box::new(server),
+    }
+}
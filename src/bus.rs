@@ -1,39 +1,235 @@
 // Logic for interacting with D-Bus buses.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::env;
+use std::error::Error as StdError;
+use std::fs;
+use std::io::{Error as IOError, ErrorKind};
+use std::iter;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use dbus::arg::{RefArg, Variant};
+use dbus::arg::{Get, RefArg, Variant};
 use dbus::{
-    BusName, BusType, ConnPath, Connection, Error as DBusError, Interface, Member, Message, Path,
-    SignalArgs,
+    BusName, BusType, Connection, Error as DBusError, Interface, Member, Message, MessageType,
+    Path, SignalArgs,
 };
+use serde_json::{Map, Value};
 
-use crate::error::Error as CrateError;
-use crate::generated::org_freedesktop_systemd1::OrgFreedesktopDBusProperties;
+use crate::actions::{ActionExecutor, ActionRequest};
+use crate::backend;
+use crate::backend::{Host, Notification, NotifierBackend};
+use crate::discovery;
+use crate::error::{BusError, Error as CrateError, NotifierError, SettingsError};
+use crate::event_log;
+#[cfg(feature = "sqlite")]
+use crate::event_store;
+#[cfg(feature = "sqlite")]
+use crate::event_store::{Database, NotificationAttempt};
 use crate::generated::org_freedesktop_systemd1::OrgFreedesktopDBusPropertiesPropertiesChanged as PropertiesChanged;
-use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1Manager;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerReloading as Reloading;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerStartupFinished as StartupFinished;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitFilesChanged as UnitFilesChanged;
 use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitNew as UnitNew;
 use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitRemoved as UnitRemoved;
-use crate::settings::{Rule, Settings};
+use crate::heartbeat::Health;
+use crate::history;
+use crate::history::HistoryEntry;
+use crate::journal;
+use crate::monitor::{Event, RunOptions, StopHandle};
+use crate::mountinfo;
+use crate::pause;
+use crate::pause::PausedBuses;
+use crate::property::{PropertyHistory, PropertyValue};
+use crate::runtime_rules;
+use crate::runtime_rules::RuntimeRules;
+use crate::schedule::LocalTime;
+#[cfg(feature = "scripting")]
+use crate::script;
+use crate::settings;
+use crate::settings::{
+    BootSummary, Expression, GroupBy, Identity, Notifier, NotifierMode, NotifierPolicy,
+    NotifierTarget, ProtocolVersion, Rule, RuleEvaluation, Settings, Severity, SystemdCallClass,
+    WatchLimit,
+};
+use crate::silence;
+use crate::silence::Silences;
+use crate::sleep::SleepState;
+use crate::supervisor::Metrics;
+use crate::systemd::{ListedUnit, SystemdConnection, SystemdManager};
+use crate::template;
 use crate::timestamp;
-use crate::timestamp::RealtimeTimestamp;
-use crate::unit::{ActiveState, UnitStateMachine};
+use crate::timestamp::{Clock, MonotonicTimestamp, RealtimeTimestamp};
+use crate::unit;
+use crate::unit::{
+    ActiveState, LoadState, LoadStateMachine, PortableState, SubState, SystemState, UnitFileState,
+    UnitStateMachine,
+};
 
-const BUS_NAME_FOR_SYSTEMD: &str = "org.freedesktop.systemd1";
+pub const BUS_NAME_FOR_SYSTEMD: &str = "org.freedesktop.systemd1";
 const PATH_FOR_SYSTEMD: &str = "/org/freedesktop/systemd1";
+pub const INTERFACE_FOR_SYSTEMD_MANAGER: &str = "org.freedesktop.systemd1.Manager";
 const INTERFACE_FOR_SYSTEMD_UNIT: &str = "org.freedesktop.systemd1.Unit";
+pub const INTERFACE_FOR_PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+// How often the runtime rules file is checked for changes.
+const RUNTIME_RULES_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How often digest schedules are checked in low-power mode. Schedules are precise to the minute.
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// The state which notifiers are sent when a unit appears. See `notify_appeared`.
+const APPEARED_STATE: &str = "appeared";
+
+// The directories from which the system bus reads policies.
+const SYSTEM_BUS_POLICY_DIRS: &[&str] = &["/etc/dbus-1/system.d", "/usr/share/dbus-1/system.d"];
+
+// The fake unit about which boot summaries are sent.
+const BOOT_SUMMARY_UNIT_NAME: &str = "killjoy-boot-summary";
+const BOOT_SUMMARY_DESCRIPTION: &str = "killjoy boot summary";
+
+// The fake unit about which digests are sent.
+const DIGEST_UNIT_NAME: &str = "killjoy-digest";
+
+// The fake unit name under which notifiers are told about the system state.
+const SYSTEM_STATE_UNIT_NAME: &str = "killjoy-system-state";
+const DIGEST_DESCRIPTION: &str = "killjoy digest";
+
+// The fake unit about which warnings that the watch limit has been hit are sent.
+const WATCH_LIMIT_UNIT_NAME: &str = "killjoy-watch-limit";
+const WATCH_LIMIT_DESCRIPTION: &str = "killjoy watch limit";
+
+// The fake unit about which test notifications are sent.
+const TEST_UNIT_NAME: &str = "killjoy-test.service";
+const TEST_UNIT_DESCRIPTION: &str = "killjoy test notification";
+
+// The properties of org.freedesktop.systemd1.Unit which list the units that depend on a unit.
+const REVERSE_DEPENDENCY_PROPERTIES: [&str; 4] =
+    ["RequiredBy", "RequisiteOf", "WantedBy", "BoundBy"];
+
+// How many calls to systemd may await replies at once while enumerating units at startup. The
+// system bus limits how many replies a connection may await, commonly to 128.
+const PIPELINE_DEPTH: usize = 64;
+
+// How long to wait for each message while awaiting replies to pipelined calls, in milliseconds.
+const PIPELINE_POLL_TIMEOUT: u32 = 100;
+
+// The D-Bus errors which mean that a call may succeed if retried, e.g. because systemd was too busy
+// to answer in time.
+const TRANSIENT_DBUS_ERRORS: [&str; 4] = [
+    "org.freedesktop.DBus.Error.LimitsExceeded",
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.TimedOut",
+    "org.freedesktop.DBus.Error.Timeout",
+];
+
+// The D-Bus errors which mean that the connection to the bus was lost.
+const DISCONNECTED_DBUS_ERRORS: [&str; 2] = [
+    "org.freedesktop.DBus.Error.Disconnected",
+    "org.freedesktop.DBus.Error.NoServer",
+];
 
 // A unit's properties, as returned by a PropertiesChanged signal, or a call to
 // org.freedesktop.systemd1.Unit.GetAll.
 pub type UnitProps = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
 
+// The state machines for the units being watched on a bus, keyed by unit name.
+//
+// `unit_files` holds the states of the unit files seen by the latest unit file scan, if any.
+// `portable_images` holds the states of the portable images seen by the latest check, if any.
+// `properties` holds what has been seen of the properties that rules have conditions on, keyed by
+// unit name, and `sampled` holds when each unit's properties were last sampled. `signal_matches`
+// holds the match rules added for each unit's PropertiesChanged signals, keyed by unit name. If the
+// settings have a watch limit, `updated` holds when each unit's state machine was last updated.
+#[derive(Default)]
+struct UnitStates {
+    active_states: HashMap<String, UnitStateMachine>,
+    load_states: HashMap<String, LoadStateMachine>,
+    portable_images: HashMap<String, PortableState>,
+    properties: HashMap<String, PropertyHistory>,
+    sampled: HashMap<String, Instant>,
+    signal_matches: HashMap<String, Vec<String>>,
+    system_state: Option<SystemState>,
+    unit_files: HashMap<String, UnitFileState>,
+    unmounted: HashSet<String>,
+    updated: HashMap<String, Instant>,
+}
+
+// Notifications which a rule grouped, and which are waiting to be sent to a notifier as one.
+//
+// `entries` describe the grouped units and their states, e.g. "foo.service=failed". The group is
+// sent once `deadline` passes, with the highest severity of its notifications.
+struct PendingGroup {
+    deadline: Instant,
+    entries: Vec<String>,
+    severity: Severity,
+}
+
+// An event which matched rules with a `for_duration`, and the rules which haven't fired yet, each
+// with the time after which it fires if the unit hasn't changed state.
+struct PendingEvent {
+    event: Event,
+    rules: Vec<(Instant, Rule)>,
+}
+
+// What a bus watcher's message loop keeps track of from one iteration to the next. See
+// `BusWatcher::run`.
+//
+// `deferred` holds messages which were received while awaiting replies, and which are handled next.
+// The `last_*` fields say when each kind of periodic work was last done. `last_wakes` and
+// `settling` are what the sleep tracker said last time.
+struct LoopState {
+    unit_states: UnitStates,
+    deferred: Vec<Message>,
+    watching_unit_files: bool,
+    last_digest_check: LocalTime,
+    last_mount_check: Instant,
+    last_reconcile: Instant,
+    last_runtime_rules_check: Instant,
+    last_sample: Instant,
+    last_scan: Instant,
+    last_wakes: u64,
+    settling: bool,
+}
+
 // Watch units appear and disappear on a bus, and take actions in response.
-pub struct BusWatcher {
+//
+// Systemd is talked to through `backend`, which is a connection to the bus or to systemd's Varlink
+// sockets, as the settings' `transport` asks, unless the bus watcher was created with
+// `with_backend`.
+pub struct BusWatcher<B = SystemdConnection> {
+    bus_type: BusType,
+    events: Option<Sender<Result<Event, CrateError>>>,
     loop_once: bool,
     loop_timeout: u32,
-    connection: Connection,
+    low_power: bool,
+    oneshot: bool,
+    backend: B,
     settings: Settings,
+    file_rule_count: usize,
+    runtime_rules: RuntimeRules,
+    runtime_rules_mtime: Option<SystemTime>,
+    discovered_rules: Vec<(String, String)>,
+    action_executor: ActionExecutor,
+    digests: RefCell<HashMap<String, Vec<String>>>,
+    #[cfg(feature = "sqlite")]
+    event_store: Option<Database>,
+    groups: RefCell<HashMap<(String, String), PendingGroup>>,
+    health: Option<Health>,
+    host: Host,
+    limit_warned: Cell<bool>,
+    metrics: Option<Metrics>,
+    pending: RefCell<HashMap<String, PendingEvent>>,
+    sleep: Option<SleepState>,
+    suppressed: RefCell<HashMap<String, Option<ActiveState>>>,
+    stop: StopHandle,
+    transient: RefCell<HashSet<String>>,
+    userspace_ts: Option<MonotonicTimestamp>,
 }
 
 impl BusWatcher {
@@ -41,20 +237,91 @@ impl BusWatcher {
     //
     // To watch for units of interest, and to take action when those units of interest transition to
     // states of interest, call `run`. Return an error if unable to connect to the given `bus_type`.
+    //
+    // Whenever a unit's ActiveState changes in a way that matches a rule, an `Event` is produced.
+    // If `events` is given, the event is sent to it. Otherwise, the event is dispatched, i.e.
+    // notifiers are contacted and actions are taken.
+    //
+    // `run` returns once `options.stop` is stopped. If the settings ask for an event store, and
+    // events are dispatched, the event store is opened, and an error is returned if that fails. If
+    // `health` is given, `run` reports to it each time it goes through its message loop. If
+    // `metrics` is given, events and attempts to contact notifiers are counted. If `sleep`
+    // is given, and the settings have a resume grace period, dispatching is held back while the
+    // host suspends and resumes. See `dispatch_suppressed`. Rules added at runtime are appended to
+    // the settings' rules, and `run` picks up later changes to them. See `reload_runtime_rules`.
+    // Rules discovered in unit files are appended after them. See `discover_rules`.
     pub fn new(
         bus_type: BusType,
         settings: Settings,
-        loop_once: bool,
-        loop_timeout: u32,
+        options: RunOptions,
+        events: Option<Sender<Result<Event, CrateError>>>,
+        health: Option<Health>,
+        metrics: Option<Metrics>,
+        sleep: Option<SleepState>,
+    ) -> Result<Self, CrateError> {
+        let backend = SystemdConnection::new(bus_type, settings.transport)?;
+        let mut bus_watcher = Self::with_backend(backend, bus_type, settings, options, events)?;
+        bus_watcher.health = health;
+        bus_watcher.metrics = metrics;
+        bus_watcher.sleep = sleep;
+        Ok(bus_watcher)
+    }
+}
+
+impl<B: SystemdManager> BusWatcher<B> {
+    // Like `new`, but talk to systemd through `backend`, rather than through a connection to
+    // `bus_type`, and without health reports, metrics or sleep tracking.
+    //
+    // `bus_type` still says which rules apply, and which bus notifiers and actions use. This suits
+    // a simulated systemd. See `MockSystemd`.
+    pub fn with_backend(
+        backend: B,
+        bus_type: BusType,
+        settings: Settings,
+        options: RunOptions,
+        events: Option<Sender<Result<Event, CrateError>>>,
     ) -> Result<Self, CrateError> {
-        let connection = Connection::get_private(bus_type).map_err(CrateError::ConnectToBus)?;
-        let settings = settings;
-        Ok(BusWatcher {
-            loop_once,
-            loop_timeout,
-            connection,
+        #[cfg(feature = "sqlite")]
+        let event_store = match (&settings.event_store, &events) {
+            (Some(event_store), None) => Some(Database::open(&match &event_store.path {
+                Some(path) => path.to_owned(),
+                None => event_store::get_event_store_path()?,
+            })?),
+            _ => None,
+        };
+        let host = Host::new(&settings.identity);
+        let file_rule_count = settings.rules.len();
+        let mut bus_watcher = BusWatcher {
+            bus_type,
+            events,
+            loop_once: options.loop_once,
+            loop_timeout: options.loop_timeout,
+            low_power: options.low_power,
+            oneshot: options.oneshot,
+            backend,
             settings,
-        })
+            file_rule_count,
+            runtime_rules: RuntimeRules::default(),
+            runtime_rules_mtime: None,
+            discovered_rules: Vec::new(),
+            action_executor: ActionExecutor::new(bus_type),
+            digests: RefCell::new(HashMap::new()),
+            #[cfg(feature = "sqlite")]
+            event_store,
+            groups: RefCell::new(HashMap::new()),
+            health: None,
+            host,
+            limit_warned: Cell::new(false),
+            metrics: None,
+            pending: RefCell::new(HashMap::new()),
+            sleep: None,
+            suppressed: RefCell::new(HashMap::new()),
+            stop: options.stop,
+            transient: RefCell::new(HashSet::new()),
+            userspace_ts: None,
+        };
+        bus_watcher.reload_runtime_rules();
+        Ok(bus_watcher)
     }
 
     // Track units of interest.
@@ -68,14 +335,27 @@ impl BusWatcher {
     //     2.  Subscribe to the `PropertiesChanged` signal for that unit.
     //     3.  Get the unit's current state, and update the corresponding state machine.
     //
-    // 3.  Infinitely process signals:
+    //     Interesting units are handled in batches, so that startup is quick on hosts with many
+    //     units: every unit is subscribed to, then every unit's state is fetched. Calls are
+    //     pipelined, i.e. many are sent before any reply is awaited. Each unit is still subscribed
+    //     to before its state is fetched.
+    //
+    //     Listing units also yields their paths and states. The paths are used as-is, but the
+    //     states can't seed the state machines, as they're read before the units are subscribed
+    //     to.
+    //
+    // 3.  Process signals until stopped:
     //
     //     *   `UnitRemoved`: Delete the corresponding state machine, if it exists.
     //     *   `UnitNew`: If the unit is interesting, do the same as step 2, above.
     //     *   `PropertiesChanged`: Get the unit's current state, and update the corresponding
     //         state machine.
     //
-    // An "interesting" unit is one that matches any of the monitoring rules provided by the user.
+    // An "interesting" unit is one that matches any of the monitoring rules provided by the user,
+    // either in the settings file, at runtime or in unit files. While processing signals, step 2 is
+    // periodically repeated for units whose signals may have been missed. See `enumerate_units`.
+    // Whenever the runtime rules or the discovered rules change, the units being tracked are
+    // reconciled with them. See `reconcile_rules`.
     //
     // Ordering matters. If the first two steps are swapped, then killjoy's behaviour could become
     // degenerate: it could miss units which appear while the list of extant units is being
@@ -136,12 +416,77 @@ impl BusWatcher {
     // Startup is complete when all unicast messages requesting unit states have been received a
     // response and been processed. After that point, all `PropertiesChanged` signals are either
     // out-of-date and discarded, or newer and useful.
-    pub fn run(&self) -> Result<(), CrateError> {
+    pub fn run(&mut self) -> Result<(), CrateError> {
+        let mut state = self.start()?;
+        loop {
+            if self.run_once(&mut state)? {
+                return Ok(());
+            }
+            self.wait_for_messages(&self.backend.get_pollfds(), self.get_wait_timeout())?;
+            if self.loop_once {
+                return self.handle_arrived(&mut state);
+            }
+        }
+    }
+
+    // Like `run`, but for several bus watchers at once, all in the calling thread.
+    //
+    // The bus watchers go through their message loops in turn. Then, rather than each waiting for
+    // messages on its own connection, every connection is polled at once, until a message arrives
+    // on any of them, or until the soonest of the bus watchers' wait timeouts passes. This saves a
+    // thread per bus, e.g. on small devices. The bus watchers are expected to share a stop handle.
+    // Return once every bus watcher is done, or as soon as any of them fails.
+    pub fn run_together(bus_watchers: &mut [Self]) -> Result<(), CrateError> {
+        let mut states: Vec<LoopState> = bus_watchers
+            .iter_mut()
+            .map(Self::start)
+            .collect::<Result<_, _>>()?;
+        let mut done: Vec<bool> = vec![false; bus_watchers.len()];
+        loop {
+            for ((bus_watcher, state), done) in
+                bus_watchers.iter_mut().zip(&mut states).zip(&mut done)
+            {
+                if !*done {
+                    *done = bus_watcher.run_once(state)?;
+                }
+            }
+            let active: Vec<(&Self, &mut LoopState)> = bus_watchers
+                .iter()
+                .zip(&mut states)
+                .zip(&done)
+                .filter(|(_, done)| !**done)
+                .map(|(active, _)| active)
+                .collect();
+            let first = match active.first() {
+                Some((first, _)) => *first,
+                None => return Ok(()),
+            };
+            let pollfds: Vec<libc::pollfd> = active
+                .iter()
+                .flat_map(|(bus_watcher, _)| bus_watcher.backend.get_pollfds())
+                .collect();
+            let timeout = active
+                .iter()
+                .filter_map(|(bus_watcher, _)| bus_watcher.get_wait_timeout())
+                .min();
+            first.wait_for_messages(&pollfds, timeout)?;
+            if first.loop_once {
+                for (bus_watcher, state) in active {
+                    bus_watcher.handle_arrived(state)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // Do steps 1 and 2 of `run`, and return the state of the message loop.
+    fn start(&mut self) -> Result<LoopState, CrateError> {
         self.call_manager_subscribe()?;
+        self.userspace_ts = self.get_userspace_timestamp();
 
         // D-Bus inserts a org.freedesktop.DBus.NameAcquired signal into the message queue of new
         // connections. Discard it before subscribing to any other signals.
-        self.connection.incoming(1000).next();
+        self.backend.pop_message(1000);
 
         // It's important to subscribe to UnitRemoved before UnitNew. Doing so prevents the
         // following scenario:
@@ -156,45 +501,682 @@ impl BusWatcher {
         self.subscribe_manager_unit_removed()?;
         self.subscribe_manager_unit_new()?;
 
-        // Learn about interesting extant units. If any calls to systemd fail, assume the unit has
-        // been unloaded and a UnitRemoved signal has been broadcast. The UnitRemoved handler should
-        // clean up the subscription to PropertiesChanged for that unit, if any.
-        let mut unit_states: HashMap<String, UnitStateMachine> = HashMap::new();
+        // Learn about interesting extant units. Signals received while doing so are deferred, and
+        // handled by the message loop. Rules declared in unit files are discovered first, so that
+        // the units they match are learned about too.
+        let mut unit_states = UnitStates::default();
+        let mut deferred: Vec<Message> = Vec::new();
+        self.discover_rules(&mut deferred)?;
+        self.enumerate_units(&mut unit_states, &mut deferred)?;
+
+        // Unit files are listed periodically, as systemd doesn't say when a unit file appears or
+        // disappears. They're also listed whenever systemd says that unit files have been enabled,
+        // disabled, masked and so on, or that it has finished reloading. Subscribe first, so that
+        // no changes are missed. Portable images are checked likewise, as systemd reloads whenever
+        // one is attached or detached.
+        let watching_unit_files = self.watches_unit_files();
+        if watching_unit_files {
+            self.subscribe_manager_unit_files_changed()?;
+            self.subscribe_manager_reloading()?;
+        }
+        self.scan_unit_files(&mut unit_states)?;
+        self.check_portable_images(&mut unit_states)?;
+        let last_scan = Instant::now();
+        self.check_mount_points(&mut unit_states)?;
+        let last_mount_check = Instant::now();
+        self.check_system_state(&mut unit_states)?;
+
+        if self.get_boot_summary().is_some() {
+            self.subscribe_manager_startup_finished()?;
+        }
+
+        Ok(LoopState {
+            unit_states,
+            deferred,
+            watching_unit_files,
+            last_digest_check: LocalTime::now(),
+            last_mount_check,
+            last_reconcile: Instant::now(),
+            last_runtime_rules_check: Instant::now(),
+            last_sample: Instant::now(),
+            last_scan,
+            last_wakes: self.sleep.as_ref().map_or(0, SleepState::wakes),
+            settling: self.is_settling(),
+        })
+    }
+
+    // Go through the message loop once, up to the point where it waits for messages: do whatever
+    // periodic work is due, and handle the messages which have already arrived. Return whether
+    // the bus watcher is done, i.e. whether it has been stopped, or has finished in oneshot mode.
+    fn run_once(&mut self, state: &mut LoopState) -> Result<bool, CrateError> {
+        if let Some(health) = &self.health {
+            health.report(self.bus_type);
+        }
+        let now = LocalTime::now();
+        self.send_due_digests(state.last_digest_check, now)?;
+        state.last_digest_check = now;
+        self.send_due_groups(false)?;
+        self.dispatch_pending(&state.unit_states)?;
+        // Rules declared in unit files are discovered as often as unit files are scanned.
+        // Reconciling the rules scans unit files too.
+        let mut rules_changed = false;
+        if state.last_runtime_rules_check.elapsed() >= RUNTIME_RULES_CHECK_INTERVAL {
+            rules_changed |= self.reload_runtime_rules();
+            state.last_runtime_rules_check = Instant::now();
+        }
+        if !self.low_power
+            && state.last_scan.elapsed() >= self.settings.polling.unit_file_scan_interval
+        {
+            rules_changed |= self.discover_rules(&mut state.deferred)?;
+            if !rules_changed {
+                self.scan_unit_files(&mut state.unit_states)?;
+                self.check_portable_images(&mut state.unit_states)?;
+            }
+            state.last_scan = Instant::now();
+        }
+        if rules_changed {
+            if !state.watching_unit_files && self.watches_unit_files() {
+                self.subscribe_manager_unit_files_changed()?;
+                self.subscribe_manager_reloading()?;
+                state.watching_unit_files = true;
+            }
+            self.reconcile_rules(&mut state.unit_states, &mut state.deferred)?;
+            state.last_scan = Instant::now();
+            state.last_reconcile = Instant::now();
+        }
+        self.sample_all_properties(&mut state.unit_states)?;
+        if state.last_sample.elapsed() >= self.settings.polling.property_sample_interval {
+            self.check_system_state(&mut state.unit_states)?;
+            state.last_sample = Instant::now();
+        }
+        if !self.low_power
+            && state.last_mount_check.elapsed() >= self.settings.polling.mount_check_interval
+        {
+            self.check_mount_points(&mut state.unit_states)?;
+            state.last_mount_check = Instant::now();
+        }
+        if let Some(interval) = self.settings.reconcile_interval.filter(|_| !self.low_power) {
+            if state.last_reconcile.elapsed() >= interval {
+                self.enumerate_units(&mut state.unit_states, &mut state.deferred)?;
+                state.last_reconcile = Instant::now();
+            }
+        }
+        if let Some(sleep) = &self.sleep {
+            let wakes = sleep.wakes();
+            if wakes != state.last_wakes {
+                self.enumerate_units(&mut state.unit_states, &mut state.deferred)?;
+                state.last_reconcile = Instant::now();
+                state.last_wakes = wakes;
+            }
+        }
+        let was_settling = state.settling;
+        state.settling = self.is_settling();
+        if was_settling && !state.settling {
+            self.dispatch_suppressed(&state.unit_states)?;
+        }
+        // Messages which have already arrived are handled before waiting, as libdbus may have
+        // queued some while the periodic work above awaited replies, and polling the connection
+        // doesn't notice those. In oneshot mode, there's no waiting at all.
+        self.handle_arrived(state)?;
+        if self.oneshot {
+            self.send_due_groups(true)?;
+            self.send_all_digests()?;
+            return Ok(true);
+        }
+        Ok(self.stop.is_stopped())
+    }
+
+    // Handle the deferred messages, and the messages which have already arrived on the connection.
+    fn handle_arrived(&self, state: &mut LoopState) -> Result<(), CrateError> {
+        let arrived = iter::from_fn(|| self.backend.pop_message(0));
+        let msgs = state.deferred.drain(..).chain(arrived);
+        self.handle_messages(msgs, &mut state.unit_states, &mut state.last_scan)
+    }
+
+    // Handle the given messages, until they run out or the bus watcher is stopped.
+    //
+    // Messages other than the signals that killjoy subscribes to are ignored. `last_scan` is reset
+    // whenever unit files are scanned.
+    fn handle_messages(
+        &self,
+        msgs: impl Iterator<Item = Message>,
+        unit_states: &mut UnitStates,
+        last_scan: &mut Instant,
+    ) -> Result<(), CrateError> {
+        for msg in msgs {
+            self.handle_message(&msg, unit_states, last_scan)?;
+            if self.stop.is_stopped() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // Handle one message. See `handle_messages`.
+    //
+    // Signals are told apart by their member before being decoded, as reading a message's headers
+    // copies them, and decoding a PropertiesChanged signal copies every property in it. Most
+    // messages are PropertiesChanged signals, so those about interfaces which nothing looks at are
+    // dropped before their properties are decoded.
+    fn handle_message(
+        &self,
+        msg: &Message,
+        unit_states: &mut UnitStates,
+        last_scan: &mut Instant,
+    ) -> Result<(), CrateError> {
+        if msg.msg_type() != MessageType::Signal {
+            return Ok(());
+        }
+        let member = match msg.member() {
+            Some(member) => member,
+            None => return Ok(()),
+        };
+        match &*member {
+            "PropertiesChanged" => {
+                let result = self.handle_properties_changed(msg, unit_states);
+                survive_unit_error("PropertiesChanged", result)?;
+            }
+            "UnitNew" => {
+                if let Some(msg_body) = UnitNew::from_message(msg) {
+                    let result = self.handle_unit_new(&msg_body, unit_states);
+                    survive_unit_error("UnitNew", result)?;
+                }
+            }
+            "UnitRemoved" => {
+                if let Some(msg_body) = UnitRemoved::from_message(msg) {
+                    let result = self.handle_unit_removed(&msg_body, unit_states);
+                    survive_unit_error("UnitRemoved", result)?;
+                }
+            }
+            "UnitFilesChanged" | "Reloading" => {
+                if is_unit_files_change(msg) {
+                    self.scan_unit_files(unit_states)?;
+                    self.check_portable_images(unit_states)?;
+                    *last_scan = Instant::now();
+                }
+            }
+            "StartupFinished" => {
+                if StartupFinished::from_message(msg).is_some() {
+                    self.send_boot_summary()?;
+                }
+            }
+            // We don't care about other messages. We could log them at a low-level priority.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Wait until one of `pollfds` is ready, `timeout` passes, or the bus watcher is stopped,
+    // whichever comes first. If `timeout` is `None`, don't time out.
+    //
+    // The connections' file descriptors are polled directly, rather than by libdbus, so that the
+    // stop handle's file descriptor and other connections' may be polled alongside them. Being
+    // interrupted by a signal isn't an error.
+    fn wait_for_messages(
+        &self,
+        pollfds: &[libc::pollfd],
+        timeout: Option<Duration>,
+    ) -> Result<(), CrateError> {
+        let mut pollfds = pollfds.to_vec();
+        if let Some(fd) = self.stop.fd() {
+            pollfds.push(libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+        let timeout: libc::c_int = match timeout {
+            Some(timeout) => libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX),
+            None => -1,
+        };
+        let result =
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+        if result < 0 {
+            let err = IOError::last_os_error();
+            if err.kind() != ErrorKind::Interrupted {
+                return Err(CrateError::Bus(BusError::PollConnection(err)));
+            }
+        }
+        Ok(())
+    }
+
+    // Get how long to wait for messages before going through the message loop again, or `None` to
+    // wait until one arrives.
+    //
+    // That's the loop timeout, or less if a rule whose `for_duration` has passed or a group whose
+    // window has closed is due sooner. In low-power mode, the loop timeout is replaced by the
+    // interval at which the rules and settings ask to be polled, if any. See
+    // `get_low_power_interval`.
+    fn get_wait_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let pending = self.pending.borrow();
+        let groups = self.groups.borrow();
+        let due = pending
+            .values()
+            .flat_map(|pending| pending.rules.iter().map(|(deadline, _)| *deadline))
+            .chain(groups.values().map(|group| group.deadline))
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now));
+        let interval = if self.low_power {
+            self.get_low_power_interval()
+        } else {
+            Some(Duration::from_millis(self.get_loop_timeout().into()))
+        };
+        due.into_iter()
+            .chain(interval)
+            .chain(self.backend.get_poll_interval())
+            .min()
+    }
+
+    // Get how often a bus watcher in low-power mode must wake up, even if no messages arrive, or
+    // `None` if it needn't.
+    //
+    // Rules with property conditions or on the system state are polled as they would otherwise be,
+    // rules with a `loop_timeout` are honoured, and digest schedules are checked once a minute. The
+    // loop timeout applies while the host settles after resuming, while the heartbeat or control
+    // socket need to know that the bus watcher is healthy, in loop-once mode, and if the stop
+    // handle can't wake the bus watcher up.
+    fn get_low_power_interval(&self) -> Option<Duration> {
+        if self.loop_once
+            || self.is_settling()
+            || self.settings.heartbeat.is_some()
+            || self.settings.control_socket.is_some()
+            || self.stop.fd().is_none()
         {
-            let borrowed_rules: Vec<&Rule> = self.settings.rules.iter().collect();
-            let unit_names: Vec<String> = self.call_manager_list_units()?;
-            for unit_name in unit_names {
-                if rules_match_name(&borrowed_rules, &unit_name) {
-                    let unit_path = match self.call_manager_get_unit(&unit_name) {
-                        Ok(unit_path) => unit_path,
-                        Err(_) => continue,
-                    };
-                    self.subscribe_properties_changed(&unit_path)?;
-                    let unit_props = match self.call_properties_get_all(&unit_path) {
-                        Ok(unit_props) => unit_props,
-                        Err(_) => continue,
-                    };
-                    self.upsert_unit_states(&unit_name, &unit_props, &mut unit_states)?;
+            return Some(Duration::from_millis(self.loop_timeout.into()));
+        }
+        let polling = &self.settings.polling;
+        let rule_intervals = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| rule.bus_type == self.bus_type)
+            .flat_map(|rule| {
+                let sample_interval = if !rule.properties.is_empty() {
+                    Some(
+                        rule.property_sample_interval
+                            .unwrap_or(polling.property_sample_interval),
+                    )
+                } else if matches!(rule.expression, Expression::Manager(_)) {
+                    Some(polling.property_sample_interval)
+                } else {
+                    None
+                };
+                rule.loop_timeout.into_iter().chain(sample_interval)
+            });
+        let digest_interval = self
+            .settings
+            .notifiers
+            .values()
+            .find(|notifier| notifier.digest_schedule.is_some())
+            .map(|_| DIGEST_CHECK_INTERVAL);
+        rule_intervals.chain(digest_interval).min()
+    }
+
+    // Learn about interesting extant units once, without processing signals, and describe each
+    // unit's current state with an event whose `old_state` is `None`.
+    //
+    // Each unit which is in one of its rules' `active_states` also produces an event, as it would
+    // at startup. This is meant for bus watchers which send events rather than dispatch them.
+    pub fn check(&self) -> Result<Vec<Event>, CrateError> {
+        let mut unit_states = UnitStates::default();
+        let mut deferred: Vec<Message> = Vec::new();
+        self.enumerate_units(&mut unit_states, &mut deferred)?;
+        Ok(unit_states
+            .active_states
+            .iter()
+            .map(|(unit_name, usm)| Event {
+                bus_type: self.bus_type,
+                mono_ts: usm.mono_ts().clone(),
+                new_state: usm.active_state().clone(),
+                old_state: None,
+                real_ts: usm.real_ts().clone(),
+                unit_name: unit_name.to_owned(),
+            })
+            .collect())
+    }
+
+    // Learn about interesting extant units, and update their state machines.
+    //
+    // Units are subscribed to first, for any interfaces they aren't subscribed for yet, e.g.
+    // because a new rule has a property condition on one. For units which have state machines,
+    // this catches up on transitions whose signals were missed: if a unit's state has changed
+    // since its state machine was last updated, the state machine is updated, and an event is
+    // produced as usual. Transitions in between are lost, though. Messages received meanwhile
+    // which aren't replies, like signals, are appended to `deferred`.
+    //
+    // If any calls to systemd fail, assume the unit has been unloaded and a UnitRemoved signal has
+    // been broadcast. The UnitRemoved handler should clean up the subscription to
+    // PropertiesChanged for that unit, if any. Calls which fail transiently are retried first, so
+    // that a slow systemd doesn't cause units to be skipped.
+    fn enumerate_units(
+        &self,
+        unit_states: &mut UnitStates,
+        deferred: &mut Vec<Message>,
+    ) -> Result<(), CrateError> {
+        let units: Vec<ListedUnit> = self
+            .retry_call(|| self.call_manager_list_units())?
+            .into_iter()
+            .filter(|unit| self.watches_unit(&unit.name))
+            .collect();
+        let units = self.filter_watch_limit(units, unit_states)?;
+        for unit in &units {
+            self.subscribe_properties_changed(&unit.name, &unit.path, unit_states)?;
+        }
+
+        let calls = units.iter().map(|unit| gen_get_all_call(&unit.path));
+        let replies = self.call_pipelined(calls, SystemdCallClass::Properties, deferred);
+        for (unit, reply) in units.iter().zip(replies) {
+            let unit_props = reply
+                .and_then(|msg| Ok(msg.read1::<UnitProps>()?))
+                .map_err(|err| {
+                    CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGetAll(err))
+                });
+            let unit_props = match self.retry_failed_call(unit_props, || {
+                self.call_properties_get_all(&unit.path, INTERFACE_FOR_SYSTEMD_UNIT)
+            }) {
+                Ok(unit_props) => unit_props,
+                Err(_) => continue,
+            };
+            self.upsert_unit_states(&unit.name, &unit_props, unit_states)?;
+            self.sample_properties(&unit.name, &unit.path, unit_states)?;
+        }
+        Ok(())
+    }
+
+    // Tell whether any rule matches the given unit name.
+    //
+    // Like `rules_match_name`, for this bus watcher's rules. These are looked at for every signal,
+    // so they're iterated over directly, rather than collected first.
+    fn watches_unit(&self, unit_name: &str) -> bool {
+        self.settings
+            .rules
+            .iter()
+            .any(|rule| rule.expression.matches(unit_name))
+    }
+
+    // Get the rules which match the given unit name. See `watches_unit`.
+    //
+    // Rules with `ignore_transient` set don't match units which are known to be transient. Whether
+    // a unit is transient is learnt when its properties are first read. See `upsert_unit_states`.
+    fn get_rules_matching_unit(&self, unit_name: &str) -> Vec<&Rule> {
+        let transient = self.transient.borrow().contains(unit_name);
+        self.settings
+            .rules
+            .iter()
+            .filter(|rule| rule.expression.matches(unit_name))
+            .filter(|rule| !(transient && rule.ignore_transient))
+            .collect()
+    }
+
+    // Tell whether changes to the given interface's properties are looked at for the given unit,
+    // i.e. whether `get_signal_interfaces` includes it. Unlike the former, this doesn't allocate a
+    // set, as it's done for every PropertiesChanged signal.
+    fn is_interface_watched(&self, unit_name: &str, interface: &str) -> bool {
+        interface == INTERFACE_FOR_SYSTEMD_UNIT
+            || self
+                .settings
+                .properties_changed_interfaces
+                .iter()
+                .any(|watched| watched == interface)
+            || self
+                .get_rules_matching_unit(unit_name)
+                .iter()
+                .flat_map(|rule| &rule.properties)
+                .any(|condition| condition.is_on_interface(unit_name, interface))
+    }
+
+    // Tell whether any rule is interested in unit file states or portable image states.
+    fn watches_unit_files(&self) -> bool {
+        self.settings
+            .rules
+            .iter()
+            .any(|rule| !rule.unit_file_states.is_empty() || !rule.portable_states.is_empty())
+    }
+
+    // Replace the rules added at runtime, if the runtime rules file has changed since it was last
+    // read, and return whether it had.
+    //
+    // Failing to read the runtime rules file is reported, and the rules in effect are kept. See
+    // `rebuild_rules`.
+    fn reload_runtime_rules(&mut self) -> bool {
+        let path = match runtime_rules::get_runtime_rules_path() {
+            Ok(path) => path,
+            Err(err) => {
+                eprintln!("Failed to read runtime rules: {}", err.with_code());
+                return false;
+            }
+        };
+        let mtime = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if mtime == self.runtime_rules_mtime {
+            return false;
+        }
+        let runtime_rules = match RuntimeRules::load(&path) {
+            Ok(runtime_rules) => runtime_rules,
+            Err(err) => {
+                eprintln!("Failed to read runtime rules: {}", err.with_code());
+                return false;
+            }
+        };
+        self.runtime_rules_mtime = mtime;
+        self.runtime_rules = runtime_rules;
+        self.rebuild_rules();
+        true
+    }
+
+    // Replace the rules discovered in unit files, if discovery is enabled for this bus and the
+    // directives in loaded units' files have changed since they were last read, and return whether
+    // they had.
+    //
+    // Each unit's fragment and drop-ins are read, in the order in which systemd applies them. A
+    // unit which is unloaded meanwhile is skipped. See `discovery` and `rebuild_rules`.
+    fn discover_rules(&mut self, deferred: &mut Vec<Message>) -> Result<bool, CrateError> {
+        match &self.settings.discovery {
+            Some(discovery) if discovery.bus_type == self.bus_type => {}
+            _ => return Ok(false),
+        }
+        let units = self.retry_call(|| self.call_manager_list_units())?;
+        let calls = units.iter().map(|unit| gen_get_all_call(&unit.path));
+        let replies = self.call_pipelined(calls, SystemdCallClass::Properties, deferred);
+        let mut discovered_rules: Vec<(String, String)> = Vec::new();
+        for (unit, reply) in units.iter().zip(replies) {
+            let unit_props = match reply.and_then(|msg| Ok(msg.read1::<UnitProps>()?)) {
+                Ok(unit_props) => unit_props,
+                Err(_) => continue,
+            };
+            let mut paths: Vec<String> = unit_props
+                .get("FragmentPath")
+                .and_then(|variant| variant.0.as_str())
+                .filter(|path| !path.is_empty())
+                .map(String::from)
+                .into_iter()
+                .collect();
+            if let Some(variant) = unit_props.get("DropInPaths") {
+                paths.extend(cast_ref_arg_to_strings(&variant.0).unwrap_or_default());
+            }
+            for directive in discovery::read_directives(&paths) {
+                discovered_rules.push((unit.name.to_owned(), directive));
+            }
+        }
+        discovered_rules.sort();
+        if discovered_rules == self.discovered_rules {
+            return Ok(false);
+        }
+        self.discovered_rules = discovered_rules;
+        self.rebuild_rules();
+        Ok(true)
+    }
+
+    // Rebuild the rules from the rules in the settings file, the rules added at runtime, and the
+    // rules discovered in unit files, in that order.
+    //
+    // A discovered rule which can't be converted, e.g. because it references a notifier which the
+    // settings lack, is reported and skipped.
+    fn rebuild_rules(&mut self) {
+        self.settings.rules.truncate(self.file_rule_count);
+        self.runtime_rules.apply(&mut self.settings);
+        for (unit_name, directive) in &self.discovered_rules {
+            let rule = discovery::gen_rule(unit_name, self.bus_type, directive)
+                .map_err(|err| vec![err])
+                .and_then(|value| self.settings.parse_rule(&value));
+            match rule {
+                Ok(rule) => self.settings.rules.push(rule),
+                Err(errs) => {
+                    for err in errs {
+                        eprintln!(
+                            "Ignoring rule discovered in \"{}\": {}",
+                            unit_name,
+                            err.with_code()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Reconcile the units being tracked with the rules, after the rules have changed.
+    //
+    // Units which no longer match any rule are unsubscribed from and forgotten. Units which newly
+    // match a rule are subscribed to, as at startup, and so are unit files, portable images and
+    // the system state. Failing to unsubscribe from a unit is reported but is not an error.
+    fn reconcile_rules(
+        &self,
+        unit_states: &mut UnitStates,
+        deferred: &mut Vec<Message>,
+    ) -> Result<(), CrateError> {
+        let unmatched: Vec<String> = unit_states
+            .active_states
+            .keys()
+            .filter(|unit_name| !self.watches_unit(unit_name))
+            .cloned()
+            .collect();
+        for unit_name in &unmatched {
+            if let Err(err) = self.unsubscribe_properties_changed(unit_name, unit_states) {
+                eprintln!(
+                    "Failed to stop watching \"{}\": {}",
+                    unit_name,
+                    err.with_code()
+                );
+            }
+            self.forget_unit_state(unit_name, unit_states);
+        }
+
+        self.enumerate_units(unit_states, deferred)?;
+        self.scan_unit_files(unit_states)?;
+        self.check_portable_images(unit_states)?;
+        self.check_system_state(unit_states)
+    }
+
+    // Make a call to systemd, and retry it as configured while it fails transiently.
+    fn retry_call<T, F>(&self, mut call: F) -> Result<T, CrateError>
+    where
+        F: FnMut() -> Result<T, CrateError>,
+    {
+        let result = call();
+        self.retry_failed_call(result, call)
+    }
+
+    // Like `retry_call`, where `result` is the result of the first attempt.
+    fn retry_failed_call<T, F>(
+        &self,
+        mut result: Result<T, CrateError>,
+        mut call: F,
+    ) -> Result<T, CrateError>
+    where
+        F: FnMut() -> Result<T, CrateError>,
+    {
+        let systemd_calls = &self.settings.systemd_calls;
+        let mut retries = 0;
+        loop {
+            match result {
+                Err(err) if retries < systemd_calls.retries && is_transient(&err) => {
+                    retries += 1;
+                    thread::sleep(systemd_calls.retry_delay);
+                    result = call();
                 }
+                result => return result,
             }
         }
+    }
 
-        // Infinitely process Unit{Removed,New} signals.
+    // Send the given method calls to systemd, and return their replies, in the same order.
+    //
+    // Up to `PIPELINE_DEPTH` calls await replies at once. Calls time out as configured for `class`.
+    // Other messages received meanwhile, like signals, are appended to `deferred`.
+    fn call_pipelined<I>(
+        &self,
+        calls: I,
+        class: SystemdCallClass,
+        deferred: &mut Vec<Message>,
+    ) -> Vec<Result<Message, DBusError>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let timeout = self.settings.systemd_calls.get_timeout(class);
+        let mut calls = calls.into_iter().enumerate();
+        let mut replies: Vec<Option<Result<Message, DBusError>>> = Vec::new();
+        let mut awaiting: HashMap<u32, (usize, Instant)> = HashMap::new();
         loop {
-            for msg in self.connection.incoming(self.loop_timeout) {
-                if let Some(msg_body) = UnitNew::from_message(&msg) {
-                    self.handle_unit_new(&msg_body, &mut unit_states)?;
-                } else if let Some(msg_body) = UnitRemoved::from_message(&msg) {
-                    self.handle_unit_removed(&msg_body, &mut unit_states);
-                } else if let Some(msg_body) = PropertiesChanged::from_message(&msg) {
-                    self.handle_properties_changed(&msg, &msg_body, &mut unit_states)?;
+            while awaiting.len() < PIPELINE_DEPTH {
+                let (i, call) = match calls.next() {
+                    Some(call) => call,
+                    None => break,
                 };
-                // We don't care about other messages. We could log them at a low-level priority.
+                replies.push(None);
+                match self.backend.send(call) {
+                    Some(serial) => {
+                        awaiting.insert(serial, (i, Instant::now()));
+                    }
+                    None => {
+                        replies[i] = Some(Err(DBusError::new_custom(
+                            "org.freedesktop.DBus.Error.Failed",
+                            "Failed to send message",
+                        )))
+                    }
+                }
             }
-            if self.loop_once {
-                return Ok(());
+            if awaiting.is_empty() {
+                break;
+            }
+            if let Some(mut msg) = self.backend.pop_message(PIPELINE_POLL_TIMEOUT) {
+                match msg
+                    .get_reply_serial()
+                    .and_then(|serial| awaiting.remove(&serial))
+                {
+                    Some((i, _)) => {
+                        replies[i] = Some(match msg.as_result().err() {
+                            Some(err) => Err(err),
+                            None => Ok(msg),
+                        })
+                    }
+                    None => deferred.push(msg),
+                }
             }
+            awaiting.retain(|_, (i, sent)| {
+                if sent.elapsed() < timeout {
+                    return true;
+                }
+                replies[*i] = Some(Err(DBusError::new_custom(
+                    "org.freedesktop.DBus.Error.NoReply",
+                    "Did not receive a reply within the timeout",
+                )));
+                false
+            });
         }
+        // Every call has a reply or an error by now, but a missing reply needn't be fatal.
+        replies
+            .into_iter()
+            .map(|reply| {
+                reply.unwrap_or_else(|| {
+                    Err(DBusError::new_custom(
+                        "org.freedesktop.DBus.Error.NoReply",
+                        "Did not receive a reply",
+                    ))
+                })
+            })
+            .collect()
     }
 
     // Call `org.freedesktop.DBus.Properties.GetAll`.
@@ -208,380 +1190,3807 @@ impl BusWatcher {
     fn call_properties_get_all(
         &self,
         unit_path: &Path,
-    ) -> Result<HashMap<String, Variant<Box<dyn RefArg + 'static>>>, CrateError> {
-        self.get_conn_path(unit_path)
-            .get_all("org.freedesktop.systemd1.Unit")
-            .map_err(CrateError::CallOrgFreedesktopDBusPropertiesGetAll)
+        interface_name: &str,
+    ) -> Result<UnitProps, CrateError> {
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::Properties);
+        self.backend
+            .get_all_properties(unit_path, interface_name, timeout_ms)
+            .map_err(|err| CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGetAll(err)))
+    }
+
+    // Call `org.freedesktop.DBus.Properties.Get`.
+    //
+    // Call it on bus name `org.freedesktop.systemd1`, path name `path`, and return the value of the
+    // given property of the given interface, or an error if the call fails or the value isn't a
+    // `T`.
+    fn call_properties_get<T>(
+        &self,
+        path: &Path,
+        interface_name: &str,
+        property_name: &str,
+    ) -> Result<T, DBusError>
+    where
+        T: for<'a> Get<'a>,
+    {
+        let msg = gen_systemd_call(path, INTERFACE_FOR_PROPERTIES, "Get")
+            .append2(interface_name, property_name);
+        let reply = self.call_systemd(msg, SystemdCallClass::Properties)?;
+        Ok(reply.read1::<Variant<T>>()?.0)
     }
 
     // Call `org.freedesktop.systemd1.Manager.GetUnit`.
     //
     // Return the systemd unit path for `unit_name`, or an error if the unit is not loaded.
-    fn call_manager_get_unit(&self, unit_name: &str) -> Result<Path, CrateError> {
-        self.get_conn_path(&wrap_path_for_systemd())
-            .get_unit(unit_name)
-            .map_err(CrateError::CallOrgFreedesktopSystemd1ManagerGetUnit)
+    fn call_manager_get_unit(&self, unit_name: &str) -> Result<Path<'static>, CrateError> {
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::Unit);
+        self.backend
+            .get_unit(unit_name, timeout_ms)
+            .map_err(|err| CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerGetUnit(err)))
     }
 
     // Call `org.freedesktop.systemd1.Manager.Subscribe`.
     //
     // By default, the manager will *not* emit most signals. Enable them.
     fn call_manager_subscribe(&self) -> Result<(), CrateError> {
-        self.get_conn_path(&wrap_path_for_systemd())
-            .subscribe()
-            .map_err(CrateError::CallOrgFreedesktopSystemd1ManagerSubscribe)
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::Unit);
+        self.backend.subscribe(timeout_ms).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerSubscribe(err))
+        })
     }
 
     // Delete the given unit's state from `unit_states`, if present.
-    fn forget_unit_state(unit_name: &str, unit_states: &mut HashMap<String, UnitStateMachine>) {
-        unit_states.remove(unit_name);
+    fn forget_unit_state(&self, unit_name: &str, unit_states: &mut UnitStates) {
+        self.transient.borrow_mut().remove(unit_name);
+        unit_states.active_states.remove(unit_name);
+        unit_states.load_states.remove(unit_name);
+        unit_states.properties.remove(unit_name);
+        unit_states.sampled.remove(unit_name);
+        unit_states.unmounted.remove(unit_name);
+        unit_states.updated.remove(unit_name);
     }
 
     // Generate callback for use in case a unit state machine changes.
     //
-    // The callback updates the given unit state machine to the given state, and contacts a notifier
-    // if any rules match this state change. An error is returned if contacting the notifier fails.
+    // If any rules match the state change, the callback produces an event, and either sends it to
+    // `events` or dispatches it. An error is returned if dispatching the event fails. While the
+    // host is settling, the event isn't dispatched, and the unit is remembered for
+    // `dispatch_suppressed`.
     fn gen_on_change<'a>(
         &'a self,
         unit_name: &'a str,
-        real_ts: RealtimeTimestamp,
     ) -> impl Fn(&UnitStateMachine, Option<ActiveState>) -> Result<(), CrateError> + 'a {
         move |usm: &UnitStateMachine, old_state: Option<ActiveState>| -> Result<(), CrateError> {
-            let active_state = usm.active_state();
-            let matching_rules: Vec<&Rule> = self.settings.rules.iter().collect();
-            let matching_rules = get_rules_matching_name(&matching_rules, &unit_name);
-            let matching_rules = get_rules_matching_active_state(&matching_rules, active_state);
-
-            for matching_rule in &matching_rules {
-                for notifier_name in &matching_rule.notifiers {
-                    // This error can be eliminated by restructuring the settings object. See:
-                    // https://github.com/Ichimonji10/killjoy/issues/3
-                    let notifier =
-                        self.settings.notifiers.get(notifier_name).ok_or_else(|| {
-                            CrateError::InvalidNotifier(notifier_name.to_string())
-                        })?;
-
-                    let header_bus_name = notifier.get_bus_name();
-                    let header_path = cast_bus_name_to_path(&header_bus_name)?;
-                    let header_interface = wrap_interface_for_killjoy_notifier();
-                    let header_member = wrap_member_for_notify();
-
-                    let body_timestamp = real_ts.0;
-                    let body_unit_name = &unit_name;
-                    // order from newest to oldest
-                    let mut body_active_states: Vec<String> = vec![String::from(active_state)];
-                    if let Some(old_state) = old_state {
-                        body_active_states.push(String::from(old_state));
-                    }
-
-                    let msg = Message::method_call(
-                        &header_bus_name,
-                        &header_path,
-                        &header_interface,
-                        &header_member,
-                    )
-                    .append3::<u64, &str, &Vec<String>>(
-                        body_timestamp,
-                        body_unit_name,
-                        &body_active_states,
-                    );
+            let (new_state, matching_rules) = self.get_rules_matching_state(unit_name, usm);
+            let event = Event {
+                bus_type: self.bus_type,
+                mono_ts: usm.mono_ts().clone(),
+                new_state,
+                old_state,
+                real_ts: usm.real_ts().clone(),
+                unit_name: unit_name.to_string(),
+            };
+            if self.events.is_none() {
+                self.append_event_log(&event);
+                #[cfg(feature = "sqlite")]
+                self.store_transition(&event);
+            }
+            self.pending.borrow_mut().remove(unit_name);
+            let suppress = self.events.is_none() && self.is_settling();
+            if suppress {
+                self.suppressed
+                    .borrow_mut()
+                    .entry(unit_name.to_string())
+                    .or_insert_with(|| event.old_state.clone());
+            }
 
-                    let conn = Connection::get_private(notifier.bus_type)
-                        .map_err(CrateError::ConnectToBus)?;
-                    if let Err(err) = conn.send_with_reply_and_block(msg, 5000) {
-                        eprintln!(
-                            "Error occurred when contacting notifier \"{}\": {}",
-                            notifier_name, err
-                        );
-                    }
+            if matching_rules.is_empty() {
+                return Ok(());
+            }
+            if self.events.is_none() {
+                self.record_history(&event);
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.record_event();
+            }
+            if suppress {
+                return Ok(());
+            }
+            match &self.events {
+                Some(events) => {
+                    // If nobody is listening any more, there's nobody to tell.
+                    let _ = events.send(Ok(event));
+                    Ok(())
                 }
+                None => self.dispatch(&event, &matching_rules),
             }
-            Ok(())
         }
     }
 
-    // Get a `ConnPath` for `org.freedesktop.systemd1` and the given object path.
-    fn get_conn_path<'a: 'b, 'b>(&'a self, path: &'b Path) -> ConnPath<'b, &Connection> {
-        let conn = &self.connection;
-        let bus_name = wrap_bus_name_for_systemd();
-        let path = path.to_owned();
-        let timeout = 1000; // milliseconds
-        ConnPath {
-            conn,
-            dest: bus_name,
-            path,
-            timeout,
+    // Add an event to the history file, if the settings ask for one.
+    //
+    // Failing to record an event is reported but is not an error.
+    fn record_history(&self, event: &Event) {
+        if self.settings.history_size == 0 {
+            return;
+        }
+        if let Err(err) = history::record(gen_history_entry(event), self.settings.history_size) {
+            eprintln!("Failed to record event: {}", err.with_code());
         }
     }
 
-    // Call `org.freedesktop.systemd1.Manager.ListUnits`.
+    // Append an event to the event log, if the settings ask for one.
     //
-    // This method "returns an array with all currently loaded units."
-    fn call_manager_list_units(&self) -> Result<Vec<String>, CrateError> {
-        self.get_conn_path(&wrap_path_for_systemd())
-            .list_units()
-            .map(|units| units.into_iter().map(|unit| unit.0).collect())
-            .map_err(CrateError::CallOrgFreedesktopSystemd1ManagerListUnits)
+    // Failing to log an event is reported but is not an error.
+    fn append_event_log(&self, event: &Event) {
+        if let Some(event_log) = &self.settings.event_log {
+            if let Err(err) = event_log::append(event_log, &gen_history_entry(event)) {
+                eprintln!("Failed to log event: {}", err.with_code());
+            }
+        }
     }
 
-    // Handle the UnitNew signal.
+    // Record an event in the event store, if the settings ask for one.
     //
-    // If any calls to systemd fail, assume the unit has been unloaded, and return Ok. If any calls
-    // to D-Bus fail, assume something worse has happened, and return Err.
-    fn handle_unit_new(
+    // Failing to record an event is reported but is not an error.
+    #[cfg(feature = "sqlite")]
+    fn store_transition(&self, event: &Event) {
+        if let Some(event_store) = &self.event_store {
+            if let Err(err) = event_store.record_transition(&gen_history_entry(event)) {
+                eprintln!("Failed to store event: {}", err.with_code());
+            }
+        }
+    }
+
+    // Record an attempt to contact a notifier in the event store, if the settings ask for one.
+    //
+    // Failing to record an attempt is reported but is not an error.
+    #[cfg(feature = "sqlite")]
+    fn store_notification(
         &self,
-        msg_body: &UnitNew,
-        unit_states: &mut HashMap<String, UnitStateMachine>,
-    ) -> Result<(), CrateError> {
-        let borrowed_rules: Vec<&Rule> = self.settings.rules.iter().collect();
-        let unit_name: &String = &msg_body.arg0;
-        let unit_path: &Path = &msg_body.arg1;
-        if rules_match_name(&borrowed_rules, unit_name) {
-            self.subscribe_properties_changed(&unit_path)?;
-            let unit_props = match self.call_properties_get_all(&unit_path) {
-                Ok(unit_props) => unit_props,
-                Err(_) => return Ok(()),
+        notifier_name: &str,
+        unit_name: &str,
+        state: &str,
+        error: Option<String>,
+    ) {
+        if let Some(event_store) = &self.event_store {
+            let attempt = NotificationAttempt {
+                bus_type: settings::encode_bus_type(self.bus_type).to_string(),
+                unit: unit_name.to_owned(),
+                state: state.to_owned(),
+                notifier: notifier_name.to_owned(),
+                error,
+                timestamp: timestamp::get_current_realtime_timestamp().0,
             };
-            self.upsert_unit_states(unit_name, &unit_props, unit_states)?;
+            if let Err(err) = event_store.record_notification(&attempt) {
+                eprintln!("Failed to store notification attempt: {}", err.with_code());
+            }
         }
-        Ok(())
     }
 
-    // Handle the UnitRemoved signal.
-    fn handle_unit_removed(
+    #[cfg(not(feature = "sqlite"))]
+    fn store_notification(
         &self,
-        msg_body: &UnitRemoved,
-        unit_states: &mut HashMap<String, UnitStateMachine>,
+        _notifier_name: &str,
+        _unit_name: &str,
+        _state: &str,
+        _error: Option<String>,
     ) {
-        let borrowed_rules: Vec<&Rule> = self.settings.rules.iter().collect();
-        let unit_name: &String = &msg_body.arg0;
-        let unit_path: &Path = &msg_body.arg1;
-        if rules_match_name(&borrowed_rules, unit_name) {
-            if let Err(err) = self.unsubscribe_properties_changed(&unit_path) {
-                panic!("Failed to handle UnitRemoved signal: {}", err);
+    }
+
+    // Get the state in which to report the given unit, and the rules which match it.
+    //
+    // A unit whose last run was unsuccessful is only asked about it if it's `inactive`, and any
+    // rule matching its name has `treat_unsuccessful_inactive_as_failed` set. Rules with
+    // `sub_states` only match if the unit's SubState is one of them.
+    fn get_rules_matching_state(
+        &self,
+        unit_name: &str,
+        usm: &UnitStateMachine,
+    ) -> (ActiveState, Vec<&Rule>) {
+        let active_state = usm.active_state();
+        let matching_rules = self.get_rules_matching_unit(unit_name);
+        let matching_rules = get_rules_matching_sub_state(
+            &matching_rules,
+            usm.sub_state().map(|sub_state| &sub_state.name[..]),
+        );
+        let unsuccessful = *active_state == ActiveState::Inactive
+            && matching_rules
+                .iter()
+                .any(|rule| rule.treat_unsuccessful_inactive_as_failed)
+            && self.is_unsuccessful(unit_name);
+        get_rules_matching_result(&matching_rules, active_state, unsuccessful)
+    }
+
+    // Get how long to wait for a message, in ms.
+    //
+    // That's the loop timeout, lowered by any rule on this bus with a lower `loop_timeout`, or with
+    // property conditions and a lower `property_sample_interval`, so that the rule is served on
+    // time.
+    fn get_loop_timeout(&self) -> u32 {
+        self.settings
+            .rules
+            .iter()
+            .filter(|rule| rule.bus_type == self.bus_type)
+            .flat_map(|rule| {
+                let sample_interval = rule
+                    .property_sample_interval
+                    .filter(|_| !rule.properties.is_empty());
+                rule.loop_timeout.into_iter().chain(sample_interval)
+            })
+            .map(settings::duration_to_millis)
+            .fold(self.loop_timeout, u32::min)
+    }
+
+    // Tell whether any rule on this bus asks about units' SubState.
+    fn tracks_sub_states(&self) -> bool {
+        self.settings
+            .rules
+            .iter()
+            .any(|rule| rule.bus_type == self.bus_type && !rule.sub_states.is_empty())
+    }
+
+    // Tell whether the given unit's last run was unsuccessful, according to the `Result` property
+    // of its type-specific interface, e.g. `org.freedesktop.systemd1.Service`.
+    //
+    // Not every unit type has a `Result` property, and the unit may have been unloaded meanwhile,
+    // so failing to get it isn't an error, and the run is assumed to have succeeded.
+    fn is_unsuccessful(&self, unit_name: &str) -> bool {
+        let unit_path = match self.call_manager_get_unit(unit_name) {
+            Ok(unit_path) => unit_path,
+            Err(_) => return false,
+        };
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::Properties);
+        self.backend
+            .get_unit_result(unit_name, &unit_path, timeout_ms)
+            .is_ok_and(|result| result != "success")
+    }
+
+    // Get when the service manager started, e.g. at boot, if any rule has a startup grace period.
+    //
+    // Failing to get it is reported, and startup grace periods are then ignored.
+    fn get_userspace_timestamp(&self) -> Option<MonotonicTimestamp> {
+        let needed = self
+            .settings
+            .rules
+            .iter()
+            .any(|rule| rule.bus_type == self.bus_type && rule.startup_grace_period.is_some());
+        if !needed {
+            return None;
+        }
+        match self.call_properties_get::<u64>(
+            &wrap_path_for_systemd(),
+            INTERFACE_FOR_SYSTEMD_MANAGER,
+            "UserspaceTimestampMonotonic",
+        ) {
+            Ok(usec) => Some(MonotonicTimestamp(usec)),
+            Err(err) => {
+                let err = CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGet(err));
+                eprintln!(
+                    "Failed to get when the service manager started: {}",
+                    err.with_code()
+                );
+                None
             }
-            Self::forget_unit_state(unit_name, unit_states);
         }
     }
 
-    // Handle the PropertiesChanged signal.
-    //
-    // The message headers tell us where the signal is coming from:
+    // Tell whether the given rule's notifications are dropped, because the service manager started
+    // less than the rule's startup grace period ago.
+    fn is_in_startup_grace_period(&self, rule: &Rule) -> bool {
+        match &self.userspace_ts {
+            Some(userspace_ts) => {
+                let now = timestamp::get_current_monotonic_timestamp();
+                rule.is_in_startup_grace_period(Duration::from_micros(
+                    now.0.saturating_sub(userspace_ts.0),
+                ))
+            }
+            None => false,
+        }
+    }
+
+    // Tell whether the host is asleep, or resumed less than the resume grace period ago.
+    fn is_settling(&self) -> bool {
+        match (&self.sleep, self.settings.resume_grace_period) {
+            (Some(sleep), Some(grace_period)) => sleep.is_settling(grace_period),
+            _ => false,
+        }
+    }
+
+    // Dispatch the transitions which were held back while the host was settling.
     //
-    // * msg.sender should be org.freedesktop.systemd1, as that's the only sender we've subscribed
-    //   to.
-    // * msg.path varies. It's a value like /org/freedesktop/systemd1/unit/syncthing_2eservice.
-    // * msg.interface is org.freedesktop.dbus.Properties.
-    // * msg.member is PropertiesChanged.
+    // Each unit whose ActiveState changed meanwhile is dispatched once, as if it went straight
+    // from its state before the host suspended to its current state. Units which ended up back
+    // where they started, or which were removed, aren't dispatched.
+    fn dispatch_suppressed(&self, unit_states: &UnitStates) -> Result<(), CrateError> {
+        let suppressed: Vec<(String, Option<ActiveState>)> =
+            self.suppressed.borrow_mut().drain().collect();
+        for (unit_name, old_state) in suppressed {
+            let usm = match unit_states.active_states.get(&unit_name) {
+                Some(usm) => usm,
+                None => continue,
+            };
+            if old_state.as_ref() == Some(usm.active_state()) {
+                continue;
+            }
+            let (new_state, matching_rules) = self.get_rules_matching_state(&unit_name, usm);
+            if matching_rules.is_empty() {
+                continue;
+            }
+            let event = Event {
+                bus_type: self.bus_type,
+                mono_ts: usm.mono_ts().clone(),
+                new_state,
+                old_state,
+                real_ts: usm.real_ts().clone(),
+                unit_name,
+            };
+            self.dispatch(&event, &matching_rules)?;
+        }
+        Ok(())
+    }
+
+    // Contact notifiers and take actions in response to an event, unless the unit is silenced.
     //
-    // Finally, msg_body.interface tells us which other interface on the same sender + path has
-    // changed. It's a value like org.freedesktop.systemd1.Unit or org.freedesktop.systemd1.Service.
-    fn handle_properties_changed(
-        &self,
-        msg: &Message,
-        msg_body: &PropertiesChanged,
-        unit_states: &mut HashMap<String, UnitStateMachine>,
-    ) -> Result<(), CrateError> {
-        // We only care about the properties exposed by this interface.
-        if msg_body.interface != INTERFACE_FOR_SYSTEMD_UNIT {
+    // Only the rules which fire according to `rule_evaluation` take actions. Rules with a
+    // `for_duration` are held back, and fire later if the unit stays in its new state. See
+    // `dispatch_pending`.
+    fn dispatch(&self, event: &Event, matching_rules: &[&Rule]) -> Result<(), CrateError> {
+        if self.is_silenced(&event.unit_name) {
             return Ok(());
         }
+        let matching_rules = get_rules_to_fire(matching_rules, self.settings.rule_evaluation);
+        let (held, matching_rules): (Vec<&Rule>, Vec<&Rule>) = matching_rules
+            .into_iter()
+            .partition(|rule| rule.for_duration.is_some());
+        if !held.is_empty() {
+            let now = Instant::now();
+            let rules = held
+                .into_iter()
+                .map(|rule| (now + rule.for_duration.unwrap_or_default(), rule.clone()))
+                .collect();
+            self.pending.borrow_mut().insert(
+                event.unit_name.to_owned(),
+                PendingEvent {
+                    event: event.clone(),
+                    rules,
+                },
+            );
+        }
+        self.fire(event, &matching_rules)
+    }
 
-        // Get path of unit that changed.
-        let unit_path: Path = msg.path().ok_or_else(|| CrateError::MessageLacksPath)?;
+    // Fire the given rules in response to an event, i.e. contact their notifiers and take their
+    // actions.
+    fn fire(&self, event: &Event, matching_rules: &[&Rule]) -> Result<(), CrateError> {
+        if matching_rules.is_empty() {
+            return Ok(());
+        }
 
-        // Translate the signal's path into a unit name.
-        //
-        // One can ask systemd for the properties of a fictitious unit, e.g.
-        // /org/freedesktop/systemd1/unit/dbusss_2eservice, and it will respond. Thus, we can rely
-        // on systemd to respond here.
-        let unit_name: String = self
-            .get_conn_path(&unit_path)
-            .get(INTERFACE_FOR_SYSTEMD_UNIT, "Id")
-            .map_err(CrateError::GetOrgFreedesktopSystemd1UnitId)?
-            .0
-            .as_str()
-            .ok_or_else(|| CrateError::CastOrgFreedesktopSystemd1UnitId)?
-            .to_string();
-
-        // If the ActiveState property is missing, assume it didn't change.
-        match self.upsert_unit_states(&unit_name[..], &msg_body.changed_properties, unit_states) {
-            Ok(_) => Ok(()),
-            Err(err) => match err {
-                CrateError::PropertiesLacksActiveState => Ok(()),
-                _ => Err(err),
-            },
+        // order from newest to oldest
+        let mut body_states: Vec<String> = vec![event.new_state.to_string()];
+        if let Some(old_state) = &event.old_state {
+            body_states.push(old_state.to_string());
+        }
+        self.notify(
+            matching_rules,
+            &event.unit_name,
+            &event.mono_ts,
+            &event.real_ts,
+            &body_states,
+        )?;
+
+        for matching_rule in matching_rules {
+            for action in &matching_rule.actions {
+                if action.applies_to(&event.new_state) {
+                    self.action_executor.submit(ActionRequest {
+                        action: action.clone(),
+                        active_state: event.new_state.clone(),
+                        severity: matching_rule.severity,
+                        unit_name: event.unit_name.to_owned(),
+                    });
+                }
+            }
         }
+        Ok(())
     }
 
-    // Upsert the state machines in `unit_states` as appropriate.
-    fn upsert_unit_states(
+    // Fire the rules whose `for_duration` has passed since a unit entered a state.
+    //
+    // A unit which changed state meanwhile has no pending rules, as `gen_on_change` forgets them.
+    // Neither does a unit which is no longer being watched, or which has been silenced meanwhile.
+    fn dispatch_pending(&self, unit_states: &UnitStates) -> Result<(), CrateError> {
+        let now = Instant::now();
+        let mut due: Vec<(Event, Vec<Rule>)> = Vec::new();
+        self.pending.borrow_mut().retain(|_, pending| {
+            let (ready, waiting): (Vec<(Instant, Rule)>, Vec<(Instant, Rule)>) = pending
+                .rules
+                .drain(..)
+                .partition(|(deadline, _)| *deadline <= now);
+            pending.rules = waiting;
+            if !ready.is_empty() {
+                let rules = ready.into_iter().map(|(_, rule)| rule).collect();
+                due.push((pending.event.clone(), rules));
+            }
+            !pending.rules.is_empty()
+        });
+        for (event, rules) in due {
+            if !unit_states.active_states.contains_key(&event.unit_name)
+                || self.is_silenced(&event.unit_name)
+            {
+                continue;
+            }
+            let rules: Vec<&Rule> = rules.iter().collect();
+            self.fire(&event, &rules)?;
+        }
+        Ok(())
+    }
+
+    // Generate callback for use in case a unit's SubState changes while its ActiveState doesn't.
+    //
+    // Like `gen_on_load_change`, but rules are matched as by `gen_on_change`, and only those with
+    // `sub_states` fire. The notifiers are sent the new and old SubState. No actions are taken.
+    fn gen_on_sub_change<'a>(
+        &'a self,
+        unit_name: &'a str,
+    ) -> impl Fn(&UnitStateMachine, String) -> Result<(), CrateError> + 'a {
+        move |usm: &UnitStateMachine, old_sub_state: String| -> Result<(), CrateError> {
+            let sub_state = match usm.sub_state() {
+                Some(sub_state) => sub_state,
+                None => return Ok(()),
+            };
+            let (_, matching_rules) = self.get_rules_matching_state(unit_name, usm);
+            let matching_rules: Vec<&Rule> = matching_rules
+                .into_iter()
+                .filter(|rule| !rule.sub_states.is_empty())
+                .collect();
+            if matching_rules.is_empty() || self.events.is_some() || self.is_silenced(unit_name) {
+                return Ok(());
+            }
+
+            // order from newest to oldest
+            let body_states: Vec<String> = vec![sub_state.name.to_owned(), old_sub_state];
+            self.notify(
+                &matching_rules,
+                unit_name,
+                &sub_state.mono_ts,
+                &sub_state.real_ts,
+                &body_states,
+            )
+        }
+    }
+
+    // Generate callback for use in case a load state machine changes.
+    //
+    // Like `gen_on_change`, but rules are matched against the unit's LoadState, and the notifiers
+    // are sent load states instead of active states.
+    fn gen_on_load_change<'a>(
+        &'a self,
+        unit_name: &'a str,
+        mono_ts: MonotonicTimestamp,
+        real_ts: RealtimeTimestamp,
+    ) -> impl Fn(&LoadStateMachine, Option<LoadState>) -> Result<(), CrateError> + 'a {
+        move |lsm: &LoadStateMachine, old_state: Option<LoadState>| -> Result<(), CrateError> {
+            let load_state = lsm.load_state();
+            let matching_rules = self.get_rules_matching_unit(unit_name);
+            let matching_rules = get_rules_matching_load_state(&matching_rules, load_state);
+            if matching_rules.is_empty() || self.events.is_some() || self.is_silenced(unit_name) {
+                return Ok(());
+            }
+
+            // order from newest to oldest
+            let mut body_states: Vec<String> = vec![String::from(load_state)];
+            if let Some(old_state) = old_state {
+                body_states.push(String::from(old_state));
+            }
+            self.notify(&matching_rules, unit_name, &mono_ts, &real_ts, &body_states)
+        }
+    }
+
+    // Contact the notifiers referenced by `matching_rules`.
+    //
+    // Version 1 notifiers are called with a timestamp from the notifier's clock, the unit name and
+    // states. If a rule has `include_dependents` set, the units depending on `unit_name` are
+    // appended as an array. If the notifier has a `message_template`, the rendered message is
+    // appended last.
+    //
+    // Version 2 notifiers are called with a dictionary, which also carries the unit's recent log
+    // messages if a rule has `journal_lines` set. See `gen_notify_v2_body`.
+    //
+    // A rule's notifiers aren't contacted during its quiet hours, unless it has a
+    // `suppress_severity`. See `send_notifications` for the order in which they're contacted.
+    //
+    // Of the matching rules, only those which fire according to `rule_evaluation` are considered.
+    // If `deduplicate_notifications` is set, a notifier referenced by several matching rules is
+    // only contacted by the first of them which isn't vetoed or in its quiet hours. The names of
+    // the rules which fire are the event's tags, and notifiers which don't accept them aren't
+    // contacted, not even for digests. See `Notifier::accepts`.
+    //
+    // Failing to reach a notifier is reported but is not an error.
+    fn notify(
         &self,
+        matching_rules: &[&Rule],
         unit_name: &str,
-        unit_props: &UnitProps,
-        unit_states: &mut HashMap<String, UnitStateMachine>,
+        mono_ts: &MonotonicTimestamp,
+        real_ts: &RealtimeTimestamp,
+        body_states: &[String],
     ) -> Result<(), CrateError> {
-        // Get unit's current ActiveState, and time at which it entered that state.
-        let active_state: ActiveState = get_active_state(&unit_props)?;
-        let real_ts = timestamp::get_realtime_timestamp(active_state, unit_props)?;
-        let mono_ts = timestamp::get_monotonic_timestamp(active_state, unit_props)?;
+        let matching_rules = get_rules_to_fire(matching_rules, self.settings.rule_evaluation);
+        let rule_names: Vec<String> = matching_rules
+            .iter()
+            .filter_map(|rule| rule.name.clone())
+            .collect();
+        // Walking the dependency graph costs several round trips to systemd, and reading the journal
+        // means running a command, so do each at most once.
+        let mut dependents: Option<Vec<String>> = None;
+        let mut journal_lines: Option<Vec<String>> = None;
+        let mut template_values: Option<HashMap<String, String>> = None;
+        #[cfg(feature = "scripting")]
+        let mut properties: Option<HashMap<String, PropertyValue>> = None;
+        let max_journal_lines: u32 = matching_rules
+            .iter()
+            .map(|rule| rule.journal_lines)
+            .max()
+            .unwrap_or(0);
+        let now = LocalTime::now();
+        let mut notified: HashSet<&str> = HashSet::new();
+        for matching_rule in matching_rules {
+            if self.is_in_startup_grace_period(matching_rule) {
+                continue;
+            }
+            let severity = matching_rule.get_severity_at(now);
+            #[cfg(feature = "scripting")]
+            let labels = match self.run_filter(
+                matching_rule,
+                unit_name,
+                body_states,
+                severity.unwrap_or(matching_rule.severity),
+                &mut properties,
+            ) {
+                Some(labels) => labels,
+                None => continue,
+            };
+            #[cfg(not(feature = "scripting"))]
+            let labels = Cow::Borrowed(&matching_rule.labels);
+            let mut recipients: Vec<(&str, &Notifier)> = Vec::new();
+            for notifier_name in &matching_rule.notifiers {
+                // This error can be eliminated by restructuring the settings object. See:
+                // https://github.com/Ichimonji10/killjoy/issues/3
+                let notifier = self.settings.notifiers.get(notifier_name).ok_or_else(|| {
+                    CrateError::Settings(SettingsError::InvalidNotifier(notifier_name.to_string()))
+                })?;
+                if !notifier.accepts(&rule_names) {
+                    continue;
+                }
 
-        // Upsert unit state machine.
-        let on_change = self.gen_on_change(&unit_name, real_ts);
-        match unit_states.get_mut(unit_name) {
-            Some(usm) => {
-                usm.update(active_state, mono_ts.clone(), &on_change)?;
+                // Digests cover quiet hours too, as they're read at leisure.
+                if notifier.mode != NotifierMode::Realtime {
+                    let entry = gen_digest_entry(unit_name, real_ts, body_states);
+                    let mut digests = self.digests.borrow_mut();
+                    let entries = digests.entry(notifier_name.to_owned()).or_default();
+                    if entries.last() != Some(&entry) {
+                        entries.push(entry);
+                    }
+                    if notifier.mode == NotifierMode::Digest {
+                        continue;
+                    }
+                }
+                recipients.push((notifier_name, notifier));
             }
-            None => {
-                unit_states.insert(
-                    unit_name.to_string(),
-                    UnitStateMachine::new(active_state, mono_ts.clone(), &on_change)?,
+            let severity = match severity {
+                Some(severity) if !recipients.is_empty() => severity,
+                _ => continue, // quiet hours, or digests only
+            };
+            if self.settings.deduplicate_notifications {
+                recipients.retain(|(notifier_name, _)| notified.insert(notifier_name));
+                if recipients.is_empty() {
+                    continue;
+                }
+            }
+
+            // A unit which belongs to no group, e.g. a target when grouping by slice, is notified
+            // about on its own.
+            if let Some(group_by) = matching_rule.group_by {
+                if let Some(group_name) = self.get_group_name(unit_name, group_by) {
+                    let entry = format!("{}={}", unit_name, body_states[0]);
+                    let deadline = Instant::now() + matching_rule.group_window;
+                    let mut groups = self.groups.borrow_mut();
+                    for (notifier_name, _) in recipients {
+                        let group = groups
+                            .entry((notifier_name.to_owned(), group_name.clone()))
+                            .or_insert_with(|| PendingGroup {
+                                deadline,
+                                entries: Vec::new(),
+                                severity,
+                            });
+                        if !group.entries.contains(&entry) {
+                            group.entries.push(entry.clone());
+                        }
+                        group.severity = group.severity.max(severity);
+                    }
+                    continue;
+                }
+            }
+
+            let body_dependents: Option<&[String]> = if matching_rule.include_dependents {
+                Some(dependents.get_or_insert_with(|| self.get_dependents(unit_name)))
+            } else {
+                None
+            };
+            let body_journal_lines: Option<&[String]> = if matching_rule.journal_lines > 0 {
+                let journal_lines = journal_lines
+                    .get_or_insert_with(|| self.get_journal_lines(unit_name, max_journal_lines));
+                let skip = journal_lines
+                    .len()
+                    .saturating_sub(matching_rule.journal_lines as usize);
+                Some(&journal_lines[skip..])
+            } else {
+                None
+            };
+            let mut deliveries: Vec<(&str, &Notifier, Notification)> = Vec::new();
+            for (notifier_name, notifier) in recipients {
+                let body_message: Option<String> =
+                    notifier.message_template.as_ref().map(|template| {
+                        let values = template_values.get_or_insert_with(|| {
+                            let host = &self.host;
+                            get_template_values(host, unit_name, mono_ts, real_ts, body_states)
+                        });
+                        if template.uses("description") && !values.contains_key("description") {
+                            values
+                                .insert("description".to_string(), self.get_description(unit_name));
+                        }
+                        let mut values = values.clone();
+                        add_rule_template_values(&mut values, matching_rule, &labels, severity);
+                        template.render(&values)
+                    });
+                let args = Notification {
+                    bus_type: self.bus_type,
+                    dependents: body_dependents,
+                    host: &self.host,
+                    journal_lines: body_journal_lines,
+                    labels: &labels,
+                    message: body_message,
+                    mono_ts,
+                    payload: &matching_rule.payload,
+                    real_ts,
+                    rule_name: matching_rule.name.as_deref(),
+                    rule_names: &rule_names,
+                    severity,
+                    states: body_states,
+                    unit_name,
+                };
+                deliveries.push((notifier_name, notifier, args));
+            }
+
+            let results = send_notifications(matching_rule.notifier_policy, &deliveries);
+            for ((notifier_name, _, _), result) in deliveries.iter().zip(results) {
+                self.store_notification(
+                    notifier_name,
+                    unit_name,
+                    &body_states[0],
+                    result.as_ref().err().map(|err| err.to_string()),
                 );
+                self.record_notification(&result);
+                if let Err(err) = result {
+                    eprintln!("{}", err.with_code());
+                }
             }
         }
         Ok(())
     }
 
-    // Subscribe to the `org.freedesktop.systemd1.Manager.UnitNew` signal.
-    fn subscribe_manager_unit_new(&self) -> Result<(), CrateError> {
-        let bus_name = wrap_bus_name_for_systemd();
-        let path = wrap_path_for_systemd();
-        let match_str: String = UnitNew::match_str(Some(&bus_name), Some(&path));
-        self.connection
-            .add_match(&match_str)
-            .map_err(|err: DBusError| CrateError::AddSignalMatch(match_str, err))
+    // List unit files, and contact notifiers about unit files whose states are of interest.
+    //
+    // Only rules with `unit_file_states` are considered. If there are none, nothing is done. A unit
+    // file is considered `Removed` if it was previously listed, or if a `unit name` rule names it,
+    // and it isn't currently listed. Like load state changes, unit file state changes don't
+    // produce events, and don't trigger actions.
+    fn scan_unit_files(&self, unit_states: &mut UnitStates) -> Result<(), CrateError> {
+        let rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| !rule.unit_file_states.is_empty())
+            .collect();
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let unit_files = get_unit_file_states(
+            self.call_manager_list_unit_files()?,
+            &unit_states.unit_files,
+            &rules,
+        );
+
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        for (unit_name, state) in &unit_files {
+            let old_state = unit_states.unit_files.get(unit_name);
+            if old_state == Some(state) {
+                continue;
+            }
+            let matching_rules = get_rules_matching_name(&rules, unit_name);
+            let matching_rules = get_rules_matching_unit_file_state(&matching_rules, *state);
+            if matching_rules.is_empty() || self.events.is_some() || self.is_silenced(unit_name) {
+                continue;
+            }
+
+            // order from newest to oldest
+            let mut body_states: Vec<String> = vec![String::from(*state)];
+            if let Some(old_state) = old_state {
+                body_states.push(String::from(*old_state));
+            }
+            self.notify(&matching_rules, unit_name, &mono_ts, &real_ts, &body_states)?;
+        }
+        unit_states.unit_files = unit_files;
+        Ok(())
     }
 
-    // Subscribe to the `org.freedesktop.systemd1.Manager.UnitRemoved` signal.
-    fn subscribe_manager_unit_removed(&self) -> Result<(), CrateError> {
-        let bus_name = wrap_bus_name_for_systemd();
-        let path = wrap_path_for_systemd();
-        let match_str: String = UnitRemoved::match_str(Some(&bus_name), Some(&path));
-        self.connection
-            .add_match(&UnitRemoved::match_str(Some(&bus_name), Some(&path)))
-            .map_err(|err: DBusError| CrateError::AddSignalMatch(match_str, err))
+    // Look up the mount points of active mount units in the kernel's mount table, and contact
+    // notifiers about those which are missing.
+    //
+    // Only rules with `verify_mount_point` are considered. If there are none, nothing is done.
+    // Notifiers are sent the states `not-mounted` and `active`, once per disappearance. Failing to
+    // read the mount table is reported but is not an error. Like load state changes, missing mount
+    // points don't produce events, and don't trigger actions.
+    fn check_mount_points(&self, unit_states: &mut UnitStates) -> Result<(), CrateError> {
+        let rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| rule.verify_mount_point)
+            .collect();
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let mount_points = match mountinfo::read_mount_points() {
+            Ok(mount_points) => mount_points,
+            Err(err) => {
+                eprintln!("Failed to read mount points: {}", err);
+                return Ok(());
+            }
+        };
+
+        let mut unit_names: Vec<&String> = unit_states
+            .active_states
+            .iter()
+            .filter(|(unit_name, usm)| {
+                unit_name.ends_with(".mount")
+                    && *usm.active_state() == ActiveState::Active
+                    && rules_match_name(&rules, unit_name)
+            })
+            .map(|(unit_name, _)| unit_name)
+            .collect();
+        unit_names.sort();
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        let mut unmounted: HashSet<String> = HashSet::new();
+        for unit_name in unit_names {
+            let mount_point = match self.get_mount_point(unit_name) {
+                Some(mount_point) => mount_point,
+                None => continue,
+            };
+            if mount_points.contains(&mount_point) {
+                continue;
+            }
+            unmounted.insert(unit_name.to_owned());
+            if unit_states.unmounted.contains(unit_name)
+                || self.events.is_some()
+                || self.is_silenced(unit_name)
+            {
+                continue;
+            }
+            let matching_rules = get_rules_matching_name(&rules, unit_name);
+            let body_states = vec!["not-mounted".to_string(), ActiveState::Active.to_string()];
+            self.notify(&matching_rules, unit_name, &mono_ts, &real_ts, &body_states)?;
+        }
+        unit_states.unmounted = unmounted;
+        Ok(())
     }
 
-    // Subscribe to the `org.freedesktop.DBus.Properties.PropertiesChanged` signal.
-    fn subscribe_properties_changed(&self, unit_path: &Path) -> Result<(), CrateError> {
-        let bus_name = wrap_bus_name_for_systemd();
-        let match_str: String = PropertiesChanged::match_str(Some(&bus_name), Some(&unit_path));
-        self.connection
-            .add_match(&match_str)
-            .map_err(|err: DBusError| CrateError::AddSignalMatch(match_str, err))
+    // List portable images, and contact notifiers about those which have transitioned to a state of
+    // interest, e.g. because they've been attached or detached.
+    //
+    // Only rules for this bus with a `PortableImage` expression and `portable_states` are
+    // considered. If there are none, nothing is done. portabled may not be installed, so failing to
+    // list images is reported, but is not an error. Notifiers are sent the image's name in place of
+    // a unit name, and the new and old states. Like load state changes, portable image state
+    // changes don't produce events, and don't trigger actions.
+    fn check_portable_images(&self, unit_states: &mut UnitStates) -> Result<(), CrateError> {
+        let rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.bus_type == self.bus_type
+                    && !rule.portable_states.is_empty()
+                    && matches!(rule.expression, Expression::PortableImage(_))
+            })
+            .collect();
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let listed = match self.retry_call(|| self.call_portable_manager_list_images()) {
+            Ok(listed) => listed,
+            Err(err) => {
+                eprintln!("{}", err.with_code());
+                return Ok(());
+            }
+        };
+
+        let images = get_portable_image_states(listed, &unit_states.portable_images, &rules);
+        let mut image_names: Vec<&String> = images.keys().collect();
+        image_names.sort();
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        for image_name in image_names {
+            let state = images[image_name];
+            let old_state = unit_states.portable_images.get(image_name);
+            if old_state == Some(&state) {
+                continue;
+            }
+            let matching_rules: Vec<&Rule> = rules
+                .iter()
+                .cloned() // &&Rule → &Rule
+                .filter(|rule| {
+                    rule.expression.as_str() == image_name && rule.portable_states.contains(&state)
+                })
+                .collect();
+            if matching_rules.is_empty() || self.events.is_some() || self.is_silenced(image_name) {
+                continue;
+            }
+
+            // order from newest to oldest
+            let mut body_states: Vec<String> = vec![String::from(state)];
+            if let Some(old_state) = old_state {
+                body_states.push(String::from(*old_state));
+            }
+            self.notify(
+                &matching_rules,
+                image_name,
+                &mono_ts,
+                &real_ts,
+                &body_states,
+            )?;
+        }
+        unit_states.portable_images = images;
+        Ok(())
+    }
+
+    // Get the system state, and contact notifiers if it has transitioned to a state of interest.
+    //
+    // Only rules for this bus with a `Manager` expression are considered. If there are none,
+    // nothing is done. As with units, notifiers are contacted at startup if the system is already
+    // in a state of interest. Notifiers are sent a fake unit name, and the new and old states. Like
+    // load state changes, system state changes don't produce events, and don't trigger actions.
+    fn check_system_state(&self, unit_states: &mut UnitStates) -> Result<(), CrateError> {
+        let rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.bus_type == self.bus_type && matches!(rule.expression, Expression::Manager(_))
+            })
+            .collect();
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let state_str = self.retry_call(|| {
+            self.call_properties_get::<String>(
+                &wrap_path_for_systemd(),
+                INTERFACE_FOR_SYSTEMD_MANAGER,
+                "SystemState",
+            )
+            .map_err(|err| CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGet(err)))
+        })?;
+        // Newer versions of systemd may introduce states. Ignore them.
+        let state = match SystemState::try_from(&state_str[..]) {
+            Ok(state) => state,
+            Err(_) => return Ok(()),
+        };
+        let old_state = unit_states.system_state.replace(state);
+        if old_state == Some(state) {
+            return Ok(());
+        }
+        let matching_rules: Vec<&Rule> = rules
+            .into_iter()
+            .filter(|rule| rule.system_states.contains(&state))
+            .collect();
+        if matching_rules.is_empty()
+            || self.events.is_some()
+            || self.is_silenced(SYSTEM_STATE_UNIT_NAME)
+        {
+            return Ok(());
+        }
+
+        // order from newest to oldest
+        let mut body_states: Vec<String> = vec![String::from(state)];
+        if let Some(old_state) = old_state {
+            body_states.push(String::from(old_state));
+        }
+        self.notify(
+            &matching_rules,
+            SYSTEM_STATE_UNIT_NAME,
+            &timestamp::get_current_monotonic_timestamp(),
+            &timestamp::get_current_realtime_timestamp(),
+            &body_states,
+        )
+    }
+
+    // Get the given mount unit's mount point, or `None` if the unit can't be reached, e.g. because
+    // it has been unloaded.
+    fn get_mount_point(&self, unit_name: &str) -> Option<String> {
+        let unit_path = self.call_manager_get_unit(unit_name).ok()?;
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::Properties);
+        self.backend.get_mount_point(&unit_path, timeout_ms).ok()
+    }
+
+    // Get the boot summary settings, if a summary is to be sent for this watcher's bus.
+    fn get_boot_summary(&self) -> Option<&BootSummary> {
+        self.settings
+            .boot_summary
+            .as_ref()
+            .filter(|boot_summary| boot_summary.bus_type == self.bus_type)
+    }
+
+    // Contact the boot summary's notifiers about units which have failed, and units matched by
+    // rules which aren't active.
+    //
+    // Notifiers are sent a fake unit name, and a list of unit names and states in place of states,
+    // e.g. `["foo.service=failed", "bar.service=inactive"]`. If all is well, the list is empty, and
+    // the severity is `info` rather than `warning`. Like load state changes, boot summaries don't
+    // produce events.
+    fn send_boot_summary(&self) -> Result<(), CrateError> {
+        let boot_summary = match self.get_boot_summary() {
+            Some(boot_summary) if self.events.is_none() => boot_summary,
+            _ => return Ok(()),
+        };
+        let borrowed_rules: Vec<&Rule> = self.settings.rules.iter().collect();
+        let units = self
+            .call_manager_list_units()?
+            .into_iter()
+            .map(|unit| (unit.name, unit.active_state))
+            .collect();
+        let body_states = get_boot_summary_states(units, &borrowed_rules);
+        let severity = if body_states.is_empty() {
+            Severity::Info
+        } else {
+            Severity::Warning
+        };
+        for notifier_name in &boot_summary.notifiers {
+            self.send_summary(
+                notifier_name,
+                BOOT_SUMMARY_UNIT_NAME,
+                BOOT_SUMMARY_DESCRIPTION,
+                severity,
+                &body_states,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Send each notifier whose digest schedule came due after `since` the events collected for it.
+    //
+    // Notifiers are sent a fake unit name, and a list of events in place of states, e.g.
+    // `["2024-01-01T03:00:00Z foo.service: active -> failed"]`. If no events were collected for a
+    // notifier, no digest is sent.
+    fn send_due_digests(&self, since: LocalTime, now: LocalTime) -> Result<(), CrateError> {
+        let mut notifier_names: Vec<&String> = self.settings.notifiers.keys().collect();
+        notifier_names.sort();
+        for notifier_name in notifier_names {
+            let is_due = self.settings.notifiers[notifier_name]
+                .digest_schedule
+                .as_ref()
+                .is_some_and(|schedule| schedule.occurs_between(since, now));
+            if is_due {
+                self.send_digest(notifier_name)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Send each notifier the events collected for its digest so far, if any, regardless of its
+    // digest schedule.
+    fn send_all_digests(&self) -> Result<(), CrateError> {
+        let mut notifier_names: Vec<String> = self.digests.borrow().keys().cloned().collect();
+        notifier_names.sort();
+        for notifier_name in notifier_names {
+            self.send_digest(&notifier_name)?;
+        }
+        Ok(())
+    }
+
+    // Send a notifier the events collected for its digest, if any, and forget them.
+    fn send_digest(&self, notifier_name: &str) -> Result<(), CrateError> {
+        let entries = self.digests.borrow_mut().remove(notifier_name);
+        match entries {
+            Some(entries) => self.send_summary(
+                notifier_name,
+                DIGEST_UNIT_NAME,
+                DIGEST_DESCRIPTION,
+                Severity::Info,
+                &entries,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Send each group whose window has closed to its notifier, or every group if `all` is set.
+    //
+    // Notifiers are sent the group's name in place of a unit name, e.g. `user-1000.slice`, and a
+    // list of the grouped units and their states in place of states, e.g.
+    // `["user@1000.service=failed", "dbus.service=failed"]`.
+    fn send_due_groups(&self, all: bool) -> Result<(), CrateError> {
+        let now = Instant::now();
+        let mut due: Vec<(String, String)> = self
+            .groups
+            .borrow()
+            .iter()
+            .filter(|(_, group)| all || group.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        due.sort();
+        for (notifier_name, group_name) in due {
+            let group = self
+                .groups
+                .borrow_mut()
+                .remove(&(notifier_name.clone(), group_name.clone()));
+            if let Some(group) = group {
+                self.send_summary(
+                    &notifier_name,
+                    &group_name,
+                    &format!("killjoy group for {}", group_name),
+                    group.severity,
+                    &group.entries,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Contact a notifier about something other than a unit, such as a boot summary or a digest.
+    //
+    // Failing to reach the notifier is reported but is not an error.
+    fn send_summary(
+        &self,
+        notifier_name: &str,
+        unit_name: &str,
+        description: &str,
+        severity: Severity,
+        body_states: &[String],
+    ) -> Result<(), CrateError> {
+        let notifier = self.settings.notifiers.get(notifier_name).ok_or_else(|| {
+            CrateError::Settings(SettingsError::InvalidNotifier(notifier_name.to_string()))
+        })?;
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        let labels: HashMap<String, String> = HashMap::new();
+        let payload: HashMap<String, String> = HashMap::new();
+        let message: Option<String> = notifier.message_template.as_ref().map(|template| {
+            let mut values =
+                get_template_values(&self.host, unit_name, &mono_ts, &real_ts, body_states);
+            values.insert("description".to_string(), description.to_string());
+            values.insert("severity".to_string(), String::from(severity));
+            template.render(&values)
+        });
+        let args = Notification {
+            bus_type: self.bus_type,
+            dependents: None,
+            host: &self.host,
+            journal_lines: None,
+            labels: &labels,
+            message,
+            mono_ts: &mono_ts,
+            payload: &payload,
+            real_ts: &real_ts,
+            rule_name: None,
+            rule_names: &[],
+            severity,
+            states: body_states,
+            unit_name,
+        };
+        let result = backend::get_backend(notifier).send(notifier_name, &args, notifier.timeout);
+        self.record_notification(&result);
+        if let Err(err) = result {
+            eprintln!("{}", err.with_code());
+        }
+        Ok(())
+    }
+
+    // Count an attempt to contact a notifier, if metrics are being kept.
+    fn record_notification(&self, result: &Result<(), CrateError>) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_notification(result.is_ok());
+        }
+    }
+
+    // Tell whether the given unit is silenced, e.g. due to maintenance, or this bus is paused.
+    //
+    // Failing to read the silences file or the paused buses file is reported, and the unit is
+    // assumed not to be silenced.
+    fn is_silenced(&self, unit_name: &str) -> bool {
+        match pause::get_paused_buses_path().and_then(|path| PausedBuses::load(&path)) {
+            Ok(paused_buses) if paused_buses.is_paused(self.bus_type) => return true,
+            Ok(_) => {}
+            Err(err) => eprintln!("Failed to read paused buses: {}", err.with_code()),
+        }
+        let now = timestamp::get_current_realtime_timestamp();
+        match silence::get_silences_path().and_then(|path| Silences::load(&path)) {
+            Ok(silences) => silences.silences(unit_name, &now),
+            Err(err) => {
+                eprintln!("Failed to read silences: {}", err.with_code());
+                false
+            }
+        }
+    }
+
+    // Get the given unit's description, e.g. "Rotate log files".
+    //
+    // Failing to get the description is reported, and an empty string is returned.
+    fn get_description(&self, unit_name: &str) -> String {
+        let description = self.call_manager_get_unit(unit_name).and_then(|unit_path| {
+            self.call_properties_get::<Box<dyn RefArg>>(
+                &unit_path,
+                INTERFACE_FOR_SYSTEMD_UNIT,
+                "Description",
+            )
+            .map_err(|err| CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGet(err)))
+        });
+        match description {
+            Ok(value) => value.as_str().unwrap_or_default().to_string(),
+            Err(err) => {
+                eprintln!(
+                    "Failed to get description of \"{}\": {}",
+                    unit_name,
+                    err.with_code()
+                );
+                String::new()
+            }
+        }
+    }
+
+    // Get the given unit's properties from the `org.freedesktop.systemd1.Unit` interface.
+    //
+    // Filters may work without properties, e.g. if the unit has been unloaded, so failing to get
+    // them is reported but is not an error.
+    #[cfg(feature = "scripting")]
+    fn get_unit_properties(&self, unit_name: &str) -> HashMap<String, PropertyValue> {
+        let unit_props = self.call_manager_get_unit(unit_name).and_then(|unit_path| {
+            self.call_properties_get_all(&unit_path, INTERFACE_FOR_SYSTEMD_UNIT)
+        });
+        match unit_props {
+            Ok(unit_props) => unit_props
+                .iter()
+                .map(|(name, variant)| (name.to_owned(), PropertyValue::from_ref_arg(&variant.0)))
+                .collect(),
+            Err(err) => {
+                eprintln!(
+                    "Failed to get properties of \"{}\": {}",
+                    unit_name,
+                    err.with_code()
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    // Run the given rule's filter, if it has one, and return the labels with which to notify, or
+    // `None` if the filter says not to notify.
+    //
+    // `properties` caches the unit's properties, which are only read if a filter runs. A failing
+    // filter is reported, and doesn't stop notifications, as a broken filter shouldn't hide
+    // failures.
+    #[cfg(feature = "scripting")]
+    fn run_filter<'a>(
+        &self,
+        rule: &'a Rule,
+        unit_name: &str,
+        body_states: &[String],
+        severity: Severity,
+        properties: &mut Option<HashMap<String, PropertyValue>>,
+    ) -> Option<Cow<'a, HashMap<String, String>>> {
+        let filter = match &rule.filter {
+            Some(filter) => filter,
+            None => return Some(Cow::Borrowed(&rule.labels)),
+        };
+        let properties = properties.get_or_insert_with(|| self.get_unit_properties(unit_name));
+        let input = script::FilterInput {
+            bus_type: self.bus_type,
+            hostname: &self.host.hostname,
+            labels: &rule.labels,
+            properties,
+            rule_name: rule.name.as_deref(),
+            severity,
+            states: body_states,
+            unit_name,
+        };
+        match script::run(&filter.ast, &rule.describe(), &input) {
+            Ok(output) if !output.notify => None,
+            Ok(output) if output.fields.is_empty() => Some(Cow::Borrowed(&rule.labels)),
+            Ok(output) => {
+                let mut labels = rule.labels.clone();
+                labels.extend(output.fields);
+                Some(Cow::Owned(labels))
+            }
+            Err(err) => {
+                eprintln!("{}", err.with_code());
+                Some(Cow::Borrowed(&rule.labels))
+            }
+        }
+    }
+
+    // Get up to `count` of the given unit's most recent log messages, oldest first.
+    //
+    // A notification is more useful without log messages than not at all, so failing to read the
+    // journal is reported but is not an error.
+    fn get_journal_lines(&self, unit_name: &str, count: u32) -> Vec<String> {
+        if count == 0 {
+            return Vec::new();
+        }
+        journal::get_journal_lines(unit_name, count, self.bus_type).unwrap_or_else(|err| {
+            eprintln!(
+                "Failed to read journal for \"{}\": {}",
+                unit_name,
+                err.with_code()
+            );
+            Vec::new()
+        })
+    }
+
+    // Get the names of the units which depend on the given unit, directly or transitively.
+    //
+    // The graph is walked breadth-first, following the `RequiredBy`, `RequisiteOf`, `WantedBy`
+    // and `BoundBy` properties. A unit that can't be queried (e.g. because it isn't loaded) is
+    // listed, but its own dependents aren't.
+    fn get_dependents(&self, unit_name: &str) -> Vec<String> {
+        let mut dependents: Vec<String> = Vec::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(unit_name.to_string());
+        while let Some(current) = queue.pop_front() {
+            let unit_path = match self.call_manager_get_unit(&current) {
+                Ok(unit_path) => unit_path,
+                Err(_) => continue,
+            };
+            for property in &REVERSE_DEPENDENCY_PROPERTIES {
+                let names = match self.call_properties_get::<Box<dyn RefArg>>(
+                    &unit_path,
+                    INTERFACE_FOR_SYSTEMD_UNIT,
+                    property,
+                ) {
+                    Ok(value) => cast_ref_arg_to_strings(&value).unwrap_or_default(),
+                    Err(_) => continue,
+                };
+                for name in names {
+                    if name != unit_name && !dependents.contains(&name) {
+                        dependents.push(name.clone());
+                        queue.push_back(name);
+                    }
+                }
+            }
+        }
+        dependents
+    }
+
+    // Get the name of the group that the given unit belongs to, or `None` if it belongs to none.
+    //
+    // A unit's slice is read from its type-specific interface, as units of some types, such as
+    // targets, run no processes and have no slice. A unit's target is the first target, in
+    // alphabetical order, that its `WantedBy` property lists. A unit that can't be queried (e.g.
+    // because it isn't loaded) belongs to no group.
+    fn get_group_name(&self, unit_name: &str, group_by: GroupBy) -> Option<String> {
+        let unit_path = self.call_manager_get_unit(unit_name).ok()?;
+        match group_by {
+            GroupBy::Slice => {
+                let slice = self
+                    .call_properties_get::<String>(
+                        &unit_path,
+                        get_slice_interface(unit_name)?,
+                        "Slice",
+                    )
+                    .ok()?;
+                Some(slice).filter(|slice| !slice.is_empty())
+            }
+            GroupBy::Target => {
+                let value = self
+                    .call_properties_get::<Box<dyn RefArg>>(
+                        &unit_path,
+                        INTERFACE_FOR_SYSTEMD_UNIT,
+                        "WantedBy",
+                    )
+                    .ok()?;
+                cast_ref_arg_to_strings(&value)?
+                    .into_iter()
+                    .filter(|name| name.ends_with(".target"))
+                    .min()
+            }
+        }
+    }
+
+    // Send the given method call to systemd, and return its reply. The call times out as
+    // configured for the given class of call.
+    fn call_systemd(&self, msg: Message, class: SystemdCallClass) -> Result<Message, DBusError> {
+        self.backend.call(msg, self.get_call_timeout_ms(class))
+    }
+
+    // Get how long calls of the given class may take, in ms, as configured.
+    fn get_call_timeout_ms(&self, class: SystemdCallClass) -> i32 {
+        get_timeout_ms(self.settings.systemd_calls.get_timeout(class))
+    }
+
+    // Call `org.freedesktop.systemd1.Manager.ListUnits`.
+    //
+    // This method "returns an array with all currently loaded units." Return the name, ActiveState
+    // and object path of each.
+    fn call_manager_list_units(&self) -> Result<Vec<ListedUnit>, CrateError> {
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::List);
+        self.backend.list_units(timeout_ms).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerListUnits(err))
+        })
+    }
+
+    // Call `org.freedesktop.systemd1.Manager.ListUnitFiles`.
+    //
+    // This method returns the path and state of every unit file, whether or not its unit is loaded.
+    fn call_manager_list_unit_files(&self) -> Result<Vec<(String, String)>, CrateError> {
+        let msg = gen_systemd_call(
+            &wrap_path_for_systemd(),
+            INTERFACE_FOR_SYSTEMD_MANAGER,
+            "ListUnitFiles",
+        );
+        self.call_systemd(msg, SystemdCallClass::List)
+            .and_then(|reply| Ok(reply.read1::<Vec<(String, String)>>()?))
+            .map_err(|err| {
+                CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerListUnitFiles(
+                    err,
+                ))
+            })
+    }
+
+    // Call `org.freedesktop.portable1.Manager.ListImages`, and return each image's name and state.
+    fn call_portable_manager_list_images(&self) -> Result<Vec<(String, String)>, CrateError> {
+        let timeout_ms = self.get_call_timeout_ms(SystemdCallClass::List);
+        self.backend.list_images(timeout_ms).map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopPortable1ManagerListImages(err))
+        })
+    }
+
+    // Handle the UnitNew signal.
+    //
+    // If the unit wasn't being watched already, the notifiers of matching `on_appear` rules are
+    // contacted first. See `notify_appeared`.
+    //
+    // If any calls to systemd fail, assume the unit has been unloaded, and return Ok. If any calls
+    // to D-Bus fail, assume something worse has happened, and return Err.
+    fn handle_unit_new(
+        &self,
+        msg_body: &UnitNew,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let unit_name: &String = &msg_body.arg0;
+        let unit_path: &Path = &msg_body.arg1;
+        if !unit_states.active_states.contains_key(unit_name) {
+            self.notify_appeared(unit_name)?;
+        }
+        if self.watches_unit(unit_name) {
+            self.make_room_for(unit_name, unit_states)?;
+            self.subscribe_properties_changed(unit_name, unit_path, unit_states)?;
+            let unit_props =
+                match self.call_properties_get_all(unit_path, INTERFACE_FOR_SYSTEMD_UNIT) {
+                    Ok(unit_props) => unit_props,
+                    Err(_) => return Ok(()),
+                };
+            self.upsert_unit_states(unit_name, &unit_props, unit_states)?;
+            self.sample_properties(unit_name, unit_path, unit_states)?;
+        }
+        Ok(())
+    }
+
+    // Contact the notifiers of the `on_appear` rules for this bus which match a unit that systemd
+    // has just loaded, unless the unit is one of a rule's `allowed_units`.
+    //
+    // Notifiers are sent the state `appeared`. Like load state changes, appearances don't produce
+    // events, and don't trigger actions.
+    fn notify_appeared(&self, unit_name: &str) -> Result<(), CrateError> {
+        let rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.on_appear
+                    && rule.bus_type == self.bus_type
+                    && !rule.allowed_units.contains(unit_name)
+            })
+            .collect();
+        let matching_rules = get_rules_matching_name(&rules, unit_name);
+        if matching_rules.is_empty() || self.events.is_some() || self.is_silenced(unit_name) {
+            return Ok(());
+        }
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        let body_states = vec![APPEARED_STATE.to_string()];
+        self.notify(&matching_rules, unit_name, &mono_ts, &real_ts, &body_states)
+    }
+
+    // Handle the UnitRemoved signal.
+    //
+    // The unit's state is forgotten even if unsubscribing from its signals fails, in which case an
+    // error is returned.
+    fn handle_unit_removed(
+        &self,
+        msg_body: &UnitRemoved,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let unit_name: &String = &msg_body.arg0;
+        if self.watches_unit(unit_name) {
+            self.forget_unit_state(unit_name, unit_states);
+            self.unsubscribe_properties_changed(unit_name, unit_states)?;
+        }
+        Ok(())
+    }
+
+    // Handle the PropertiesChanged signal.
+    //
+    // The message headers tell us where the signal is coming from:
+    //
+    // * msg.sender should be org.freedesktop.systemd1, as that's the only sender we've subscribed
+    //   to.
+    // * msg.path varies. It's a value like /org/freedesktop/systemd1/unit/syncthing_2eservice.
+    // * msg.interface is org.freedesktop.dbus.Properties.
+    // * msg.member is PropertiesChanged.
+    //
+    // Finally, msg_body.interface tells us which other interface on the same sender + path has
+    // changed. It's a value like org.freedesktop.systemd1.Unit or org.freedesktop.systemd1.Service.
+    // Changes to the unit's states are only exposed by the former, but rules' property conditions
+    // may concern any interface. The match rules only ask for the interfaces returned by
+    // `get_signal_interfaces`, but backends without a bus may send signals for any interface. The
+    // changed properties are only read once the interface is known to be watched for the unit.
+    fn handle_properties_changed(
+        &self,
+        msg: &Message,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let interface = match msg.get1::<&str>() {
+            Some(interface) => interface,
+            None => return Ok(()),
+        };
+
+        // Get path of unit that changed.
+        let unit_path: Path = msg
+            .path()
+            .ok_or_else(|| CrateError::Bus(BusError::MessageLacksPath))?;
+
+        // Translate the signal's path into a unit name.
+        //
+        // If the path decodes to the name of a watched unit, that name is used, which spares a
+        // round trip per signal. Otherwise, systemd is asked for the unit's Id. One can ask systemd
+        // for the properties of a fictitious unit, e.g.
+        // /org/freedesktop/systemd1/unit/dbusss_2eservice, and it will respond. Thus, we can rely
+        // on systemd to respond here.
+        let unit_name: String = match unit::get_unit_name(&unit_path)
+            .filter(|unit_name| unit_states.active_states.contains_key(unit_name))
+        {
+            Some(unit_name) => unit_name,
+            None => self
+                .call_properties_get::<Box<dyn RefArg>>(
+                    &unit_path,
+                    INTERFACE_FOR_SYSTEMD_UNIT,
+                    "Id",
+                )
+                .map_err(|err| CrateError::Bus(BusError::GetOrgFreedesktopSystemd1UnitId(err)))?
+                .as_str()
+                .ok_or_else(|| CrateError::Bus(BusError::CastOrgFreedesktopSystemd1UnitId))?
+                .to_string(),
+        };
+        if !self.is_interface_watched(&unit_name, interface) {
+            return Ok(());
+        }
+        let msg_body = match PropertiesChanged::from_message(msg) {
+            Some(msg_body) => msg_body,
+            None => return Ok(()),
+        };
+
+        self.upsert_properties(
+            &unit_name,
+            &msg_body.interface,
+            &msg_body.changed_properties,
+            unit_states,
+        )?;
+        if msg_body.interface != INTERFACE_FOR_SYSTEMD_UNIT {
+            return Ok(());
+        }
+
+        // If the ActiveState property is missing, assume it didn't change. The LoadState property,
+        // if present, has already been handled by this point.
+        match self.upsert_unit_states(&unit_name[..], &msg_body.changed_properties, unit_states) {
+            Ok(_) => Ok(()),
+            Err(err) => match err {
+                CrateError::Bus(BusError::PropertiesLacksActiveState) => Ok(()),
+                _ => Err(err),
+            },
+        }
+    }
+
+    // Upsert the state machines in `unit_states` as appropriate.
+    fn upsert_unit_states(
+        &self,
+        unit_name: &str,
+        unit_props: &UnitProps,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        // The Transient property never changes, so it's only present when all of a unit's
+        // properties are read, and not in PropertiesChanged signals. It must be known before the
+        // state machines are created, as their callbacks look for matching rules.
+        if let Some(transient) = get_transient(unit_props) {
+            let mut transient_units = self.transient.borrow_mut();
+            if transient {
+                transient_units.insert(unit_name.to_string());
+            } else {
+                transient_units.remove(unit_name);
+            }
+        }
+
+        // A unit's LoadState may change independently of its ActiveState. Systemd doesn't record
+        // when that happens, so the current time is the best available approximation.
+        if let Some(load_state) = get_load_state(unit_props)? {
+            let on_load_change = self.gen_on_load_change(
+                unit_name,
+                timestamp::get_current_monotonic_timestamp(),
+                timestamp::get_current_realtime_timestamp(),
+            );
+            match unit_states.load_states.get_mut(unit_name) {
+                Some(lsm) => {
+                    lsm.update(load_state, &on_load_change)?;
+                }
+                None => {
+                    unit_states.load_states.insert(
+                        unit_name.to_string(),
+                        LoadStateMachine::new(load_state, &on_load_change)?,
+                    );
+                }
+            }
+        }
+
+        // Get unit's current ActiveState, and time at which it entered that state. Its SubState is
+        // only tracked if a rule asks about it.
+        let active_state: ActiveState = get_active_state(unit_props)?;
+        let real_ts = timestamp::get_realtime_timestamp(&active_state, unit_props)?;
+        let mono_ts = timestamp::get_monotonic_timestamp(&active_state, unit_props)?;
+        let sub_state = if self.tracks_sub_states() {
+            get_sub_state(unit_props)
+        } else {
+            None
+        };
+
+        // Upsert unit state machine.
+        let on_change = self.gen_on_change(unit_name);
+        let on_sub_change = self.gen_on_sub_change(unit_name);
+        match unit_states.active_states.get_mut(unit_name) {
+            Some(usm) => {
+                usm.update(
+                    active_state,
+                    sub_state,
+                    mono_ts,
+                    real_ts,
+                    &on_change,
+                    &on_sub_change,
+                )?;
+            }
+            None => {
+                self.make_room_for(unit_name, unit_states)?;
+                unit_states.active_states.insert(
+                    unit_name.to_string(),
+                    UnitStateMachine::new(active_state, sub_state, mono_ts, real_ts, &on_change)?,
+                );
+            }
+        }
+        if self.settings.watch_limit.is_some() {
+            let now = Instant::now();
+            match unit_states.updated.get_mut(unit_name) {
+                Some(updated) => *updated = now,
+                None => {
+                    unit_states.updated.insert(unit_name.to_string(), now);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Forget units until there's room to keep track of the given unit, if the settings have a
+    // watch limit and the unit isn't kept track of already.
+    //
+    // The least recently updated unit is forgotten and unsubscribed from first. Units in a state
+    // which a rule is interested in, and units with pending events, are never forgotten, so the
+    // limit is exceeded if every unit is. The watch limit's notifiers are warned the first time
+    // the limit is hit. This is called before a new unit is subscribed to, so that there's never
+    // a subscription for more units than the limit allows.
+    fn make_room_for(
+        &self,
+        unit_name: &str,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let watch_limit = match &self.settings.watch_limit {
+            Some(watch_limit) => watch_limit,
+            None => return Ok(()),
+        };
+        if unit_states.active_states.len() < watch_limit.max_units
+            || unit_states.active_states.contains_key(unit_name)
+        {
+            return Ok(());
+        }
+        self.warn_watch_limit(watch_limit)?;
+        while unit_states.active_states.len() >= watch_limit.max_units {
+            if self.forget_least_recently_updated(unit_states).is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Drop the listed units which a scan shouldn't keep track of because of the watch limit, and
+    // make room for the rest. See `make_room_for`.
+    //
+    // Units which are kept track of already are kept, and so are new units while there's room for
+    // them. Past that, a new unit is only kept if it's in a state which a rule is interested in,
+    // and the least recently updated unit is forgotten to make room for it. Other new units are
+    // mostly ones which were forgotten to make room before, and would be among the next to be
+    // forgotten again, so they're skipped rather than subscribed to. Otherwise, each scan would
+    // trade one idle unit for another.
+    fn filter_watch_limit(
+        &self,
+        units: Vec<ListedUnit>,
+        unit_states: &mut UnitStates,
+    ) -> Result<Vec<ListedUnit>, CrateError> {
+        let watch_limit = match &self.settings.watch_limit {
+            Some(watch_limit) => watch_limit,
+            None => return Ok(units),
+        };
+        let (mut kept, new_units): (Vec<ListedUnit>, Vec<ListedUnit>) = units
+            .into_iter()
+            .partition(|unit| unit_states.active_states.contains_key(&unit.name));
+        let mut room = watch_limit
+            .max_units
+            .saturating_sub(unit_states.active_states.len());
+        for unit in new_units {
+            if room > 0 {
+                room -= 1;
+            } else {
+                self.warn_watch_limit(watch_limit)?;
+                match unit.active_state.parse::<ActiveState>() {
+                    Ok(active_state) if self.is_in_watched_state(&unit.name, &active_state) => {}
+                    _ => continue,
+                }
+                if let Some(forgotten) = self.forget_least_recently_updated(unit_states) {
+                    kept.retain(|kept_unit| kept_unit.name != forgotten);
+                }
+            }
+            kept.push(unit);
+        }
+        Ok(kept)
+    }
+
+    // Forget and unsubscribe from the least recently updated unit which may be forgotten, and
+    // return its name, if there is one. See `make_room_for`.
+    fn forget_least_recently_updated(&self, unit_states: &mut UnitStates) -> Option<String> {
+        let unit_name = {
+            let pending = self.pending.borrow();
+            unit_states
+                .active_states
+                .iter()
+                .filter(|(unit_name, usm)| {
+                    !pending.contains_key(*unit_name)
+                        && !self.is_in_watched_state(unit_name, usm.active_state())
+                })
+                .min_by_key(|(unit_name, _)| unit_states.updated.get(*unit_name))
+                .map(|(unit_name, _)| unit_name.to_owned())?
+        };
+        self.forget_unit_state(&unit_name, unit_states);
+        if let Err(err) = self.unsubscribe_properties_changed(&unit_name, unit_states) {
+            eprintln!(
+                "Failed to stop watching \"{}\": {}",
+                unit_name,
+                err.with_code()
+            );
+        }
+        Some(unit_name)
+    }
+
+    // Tell whether a rule on this bus which matches the unit is interested in the given state.
+    fn is_in_watched_state(&self, unit_name: &str, active_state: &ActiveState) -> bool {
+        self.settings.rules.iter().any(|rule| {
+            rule.bus_type == self.bus_type
+                && rule.active_states.contains(active_state)
+                && rule.expression.matches(unit_name)
+        })
+    }
+
+    // Warn the watch limit's notifiers that the limit has been hit, unless they've been warned
+    // already.
+    //
+    // Notifiers are sent a fake unit name, and the limit in place of states, e.g.
+    // `["max_units=1000"]`. Like boot summaries, these warnings don't produce events.
+    fn warn_watch_limit(&self, watch_limit: &WatchLimit) -> Result<(), CrateError> {
+        if self.limit_warned.replace(true) {
+            return Ok(());
+        }
+        eprintln!(
+            "Keeping track of {} units, which is the watch limit. The least recently updated units \
+             are forgotten to make room for others.",
+            watch_limit.max_units
+        );
+        if self.events.is_some() {
+            return Ok(());
+        }
+        let body_states = vec![format!("max_units={}", watch_limit.max_units)];
+        for notifier_name in &watch_limit.notifiers {
+            self.send_summary(
+                notifier_name,
+                WATCH_LIMIT_UNIT_NAME,
+                WATCH_LIMIT_DESCRIPTION,
+                Severity::Warning,
+                &body_states,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Sample the properties of every watched unit which is due. See `sample_properties`.
+    //
+    // A unit is due once its sample interval has passed since it was last sampled. That's the
+    // lowest `property_sample_interval` of the rules with property conditions which match it, with
+    // the settings' `polling` standing in for rules which don't set one. Units which no such rule
+    // matches are never sampled.
+    fn sample_all_properties(&self, unit_states: &mut UnitStates) -> Result<(), CrateError> {
+        let property_rules: Vec<&Rule> = self
+            .settings
+            .rules
+            .iter()
+            .filter(|rule| rule.bus_type == self.bus_type && !rule.properties.is_empty())
+            .collect();
+        if property_rules.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let unit_names: Vec<String> = unit_states
+            .active_states
+            .keys()
+            .filter(|unit_name| {
+                let interval = get_rules_matching_name(&property_rules, unit_name)
+                    .iter()
+                    .map(|rule| {
+                        rule.property_sample_interval
+                            .unwrap_or(self.settings.polling.property_sample_interval)
+                    })
+                    .min();
+                match (interval, unit_states.sampled.get(*unit_name)) {
+                    (Some(interval), Some(sampled)) => now.duration_since(*sampled) >= interval,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            })
+            .cloned()
+            .collect();
+        for unit_name in unit_names {
+            // A unit which can't be looked up is tried again once it's due again.
+            unit_states.sampled.insert(unit_name.to_owned(), now);
+            let unit_path = match self.call_manager_get_unit(&unit_name) {
+                Ok(unit_path) => unit_path.into_static(),
+                Err(_) => continue,
+            };
+            self.sample_properties(&unit_name, &unit_path, unit_states)?;
+        }
+        Ok(())
+    }
+
+    // Get the properties that matching rules have conditions on, and check and record them.
+    //
+    // This is done when a unit is discovered, so that threshold conditions are checked against
+    // units' initial property values, and periodically thereafter. If any calls to systemd fail,
+    // assume the unit has been unloaded, and return Ok.
+    fn sample_properties(
+        &self,
+        unit_name: &str,
+        unit_path: &Path,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let mut interfaces: Vec<String> = self
+            .get_rules_matching_unit(unit_name)
+            .iter()
+            .flat_map(|rule| &rule.properties)
+            .filter_map(|condition| condition.get_interface(unit_name))
+            .collect();
+        if interfaces.is_empty() {
+            return Ok(());
+        }
+        interfaces.sort();
+        interfaces.dedup();
+        unit_states
+            .sampled
+            .insert(unit_name.to_owned(), Instant::now());
+        for interface in interfaces {
+            let unit_props = match self.call_properties_get_all(unit_path, &interface) {
+                Ok(unit_props) => unit_props,
+                Err(_) => return Ok(()),
+            };
+            self.upsert_properties(unit_name, &interface, &unit_props, unit_states)?;
+        }
+        Ok(())
+    }
+
+    // Check the given properties against rules' property conditions, and record them.
+    //
+    // Notifiers are contacted about each condition that's met. The notifiers are sent the
+    // property's new and old values, e.g. `["TasksCurrent=12", "TasksCurrent=3"]`. Like load state
+    // changes, property changes don't produce events, and don't trigger actions.
+    fn upsert_properties(
+        &self,
+        unit_name: &str,
+        interface: &str,
+        unit_props: &UnitProps,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let matching_rules: Vec<&Rule> = self
+            .get_rules_matching_unit(unit_name)
+            .into_iter()
+            .filter(|rule| {
+                rule.properties
+                    .iter()
+                    .any(|condition| condition.is_on_interface(unit_name, interface))
+            })
+            .collect();
+        if matching_rules.is_empty() {
+            return Ok(());
+        }
+        if !unit_states.properties.contains_key(unit_name) {
+            unit_states
+                .properties
+                .insert(unit_name.to_string(), PropertyHistory::default());
+        }
+        let history = unit_states
+            .properties
+            .get_mut(unit_name)
+            .expect("The unit's property history was just inserted.");
+        let now = Instant::now();
+        let triggers = get_property_triggers(
+            &matching_rules,
+            unit_name,
+            interface,
+            unit_props,
+            history,
+            now,
+        );
+        for rule in &matching_rules {
+            for condition in &rule.properties {
+                if !condition.is_on_interface(unit_name, interface) {
+                    continue;
+                }
+                if let Some(variant) = unit_props.get(&condition.property) {
+                    let value = PropertyValue::from_ref_arg(&variant.0);
+                    history.record(interface, condition, value, now);
+                }
+            }
+        }
+
+        if triggers.is_empty() || self.events.is_some() || self.is_silenced(unit_name) {
+            return Ok(());
+        }
+        let mono_ts = timestamp::get_current_monotonic_timestamp();
+        let real_ts = timestamp::get_current_realtime_timestamp();
+        // Rules triggered by the same change are notified together, so they can be deduplicated.
+        let mut changes: Vec<(Vec<String>, Vec<&Rule>)> = Vec::new();
+        for (rule, body_states) in triggers {
+            match changes
+                .iter_mut()
+                .find(|(states, _)| *states == body_states)
+            {
+                Some((_, rules)) => rules.push(rule),
+                None => changes.push((body_states, vec![rule])),
+            }
+        }
+        for (body_states, rules) in changes {
+            self.notify(&rules, unit_name, &mono_ts, &real_ts, &body_states)?;
+        }
+        Ok(())
+    }
+
+    // Subscribe to the `org.freedesktop.systemd1.Manager.UnitNew` signal.
+    fn subscribe_manager_unit_new(&self) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let path = wrap_path_for_systemd();
+        let match_str: String = UnitNew::match_str(Some(&bus_name), Some(&path));
+        self.backend
+            .add_match(&match_str)
+            .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))
+    }
+
+    // Subscribe to the `org.freedesktop.systemd1.Manager.UnitFilesChanged` signal.
+    fn subscribe_manager_unit_files_changed(&self) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let path = wrap_path_for_systemd();
+        let match_str: String = UnitFilesChanged::match_str(Some(&bus_name), Some(&path));
+        self.backend
+            .add_match(&match_str)
+            .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))
+    }
+
+    // Subscribe to the `org.freedesktop.systemd1.Manager.Reloading` signal.
+    fn subscribe_manager_reloading(&self) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let path = wrap_path_for_systemd();
+        let match_str: String = Reloading::match_str(Some(&bus_name), Some(&path));
+        self.backend
+            .add_match(&match_str)
+            .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))
+    }
+
+    // Subscribe to the `org.freedesktop.systemd1.Manager.StartupFinished` signal.
+    fn subscribe_manager_startup_finished(&self) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let path = wrap_path_for_systemd();
+        let match_str: String = StartupFinished::match_str(Some(&bus_name), Some(&path));
+        self.backend
+            .add_match(&match_str)
+            .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))
+    }
+
+    // Subscribe to the `org.freedesktop.systemd1.Manager.UnitRemoved` signal.
+    fn subscribe_manager_unit_removed(&self) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let path = wrap_path_for_systemd();
+        let match_str: String = UnitRemoved::match_str(Some(&bus_name), Some(&path));
+        self.backend
+            .add_match(&UnitRemoved::match_str(Some(&bus_name), Some(&path)))
+            .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))
+    }
+
+    // Get the interfaces whose changes a unit's PropertiesChanged signals are wanted for.
+    //
+    // That's `org.freedesktop.systemd1.Unit`, which exposes the unit's states, the interfaces which
+    // the property conditions of matching rules concern, and the `properties_changed_interfaces`.
+    fn get_signal_interfaces(&self, unit_name: &str) -> BTreeSet<String> {
+        let mut interfaces: BTreeSet<String> = self
+            .settings
+            .properties_changed_interfaces
+            .iter()
+            .cloned()
+            .collect();
+        interfaces.insert(INTERFACE_FOR_SYSTEMD_UNIT.to_string());
+        for rule in self.get_rules_matching_unit(unit_name) {
+            for condition in &rule.properties {
+                interfaces.extend(condition.get_interface(unit_name));
+            }
+        }
+        interfaces
+    }
+
+    // Subscribe to the `org.freedesktop.DBus.Properties.PropertiesChanged` signal, for the
+    // interfaces returned by `get_signal_interfaces` which the unit isn't subscribed for yet.
+    //
+    // Each match rule filters on the signal's first argument, which names the interface that
+    // changed, so that the bus doesn't wake killjoy for changes nothing looks at, like the
+    // frequent changes to `org.freedesktop.systemd1.Service`'s resource usage properties.
+    fn subscribe_properties_changed(
+        &self,
+        unit_name: &str,
+        unit_path: &Path,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let bus_name = wrap_bus_name_for_systemd();
+        let base_match_str: String = PropertiesChanged::match_str(Some(&bus_name), Some(unit_path));
+        let signal_matches = unit_states
+            .signal_matches
+            .entry(unit_name.to_string())
+            .or_default();
+        for interface in self.get_signal_interfaces(unit_name) {
+            let match_str = format!("{},arg0='{}'", base_match_str, interface);
+            if signal_matches.contains(&match_str) {
+                continue;
+            }
+            self.backend
+                .add_match(&match_str)
+                .map_err(|err: DBusError| {
+                    CrateError::Bus(BusError::AddSignalMatch(match_str.clone(), err))
+                })?;
+            signal_matches.push(match_str);
+        }
+        Ok(())
+    }
+
+    // Unsubscribe from the `org.freedesktop.DBus.Properties.PropertiesChanged` signal, by
+    // removing every match rule added for the unit. If removing any fails, the rest are removed
+    // nonetheless, and the first error is returned.
+    fn unsubscribe_properties_changed(
+        &self,
+        unit_name: &str,
+        unit_states: &mut UnitStates,
+    ) -> Result<(), CrateError> {
+        let signal_matches = unit_states
+            .signal_matches
+            .remove(unit_name)
+            .unwrap_or_default();
+        let mut result = Ok(());
+        for match_str in signal_matches {
+            let removed = self
+                .backend
+                .remove_match(&match_str)
+                .map_err(|err: DBusError| {
+                    CrateError::Bus(BusError::RemoveSignalMatch(match_str, err))
+                });
+            result = result.and(removed);
+        }
+        result
+    }
+}
+
+// Open a private connection to the given bus, for a bus watcher to talk to systemd through.
+pub fn connect(bus_type: BusType) -> Result<Connection, CrateError> {
+    Connection::get_private(bus_type).map_err(|err| CrateError::Bus(BusError::ConnectToBus(err)))
+}
+
+// Tell which rules match the given unit name.
+pub fn get_rules_matching_name<'a>(rules: &[&'a Rule], unit_name: &str) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| rule.expression.matches(unit_name))
+        .collect()
+}
+
+// Tell which rules match the given unit state.
+fn get_rules_matching_active_state<'a>(rules: &[&'a Rule], target: &ActiveState) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| {
+            rule.active_states
+                .iter()
+                .any(|active_state| active_state == target)
+        })
+        .collect()
+}
+
+// Get the rules in `rules` which match the given SubState, i.e. those without `sub_states`, and
+// those whose `sub_states` include it. If the unit's SubState isn't tracked, it's `None`, and
+// rules with `sub_states` don't match.
+fn get_rules_matching_sub_state<'a>(rules: &[&'a Rule], sub_state: Option<&str>) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| {
+            rule.sub_states.is_empty()
+                || sub_state.map_or(false, |sub_state| rule.sub_states.contains(sub_state))
+        })
+        .collect()
+}
+
+// Tell which rules match the given unit state, and which state to report the unit in.
+//
+// If the unit is `inactive` and `unsuccessful`, rules with `treat_unsuccessful_inactive_as_failed`
+// set match it as if it were `failed`, and it's reported as `failed` if any such rule exists. Other
+// rules match it as `inactive` all the same.
+fn get_rules_matching_result<'a>(
+    rules: &[&'a Rule],
+    active_state: &ActiveState,
+    unsuccessful: bool,
+) -> (ActiveState, Vec<&'a Rule>) {
+    let treat_as_failed = |rule: &Rule| {
+        unsuccessful
+            && *active_state == ActiveState::Inactive
+            && rule.treat_unsuccessful_inactive_as_failed
+    };
+    if !rules.iter().any(|rule| treat_as_failed(rule)) {
+        return (
+            active_state.clone(),
+            get_rules_matching_active_state(rules, active_state),
+        );
+    }
+    let matching_rules = rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| {
+            if treat_as_failed(rule) {
+                rule.active_states.contains(&ActiveState::Failed)
+            } else {
+                rule.active_states.contains(active_state)
+            }
+        })
+        .collect();
+    (ActiveState::Failed, matching_rules)
+}
+
+// Tell which of the given matching rules fire.
+//
+// With `RuleEvaluation::FirstMatch`, this is the rule with the highest priority, and ties go to the
+// rule listed first.
+fn get_rules_to_fire<'a>(rules: &[&'a Rule], rule_evaluation: RuleEvaluation) -> Vec<&'a Rule> {
+    match rule_evaluation {
+        RuleEvaluation::All => rules.to_vec(),
+        // `max_by_key` returns the last of several maximums.
+        RuleEvaluation::FirstMatch => rules
+            .iter()
+            .rev()
+            .max_by_key(|rule| rule.priority)
+            .into_iter()
+            .cloned() // &&Rule → &Rule
+            .collect(),
+    }
+}
+
+// Tell which rules match the given unit load state.
+fn get_rules_matching_load_state<'a>(rules: &[&'a Rule], target: LoadState) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| rule.load_states.contains(&target))
+        .collect()
+}
+
+// Get the states of the unit files matching `rules`, given the listed unit files.
+//
+// `listed` holds the paths and states of unit files, as returned by ListUnitFiles. A unit file is
+// `Removed` if it isn't listed, but it was in `previous`, or a `unit name` rule names it.
+fn get_unit_file_states(
+    listed: Vec<(String, String)>,
+    previous: &HashMap<String, UnitFileState>,
+    rules: &[&Rule],
+) -> HashMap<String, UnitFileState> {
+    let mut unit_files: HashMap<String, UnitFileState> = HashMap::new();
+    for (unit_file_path, state_str) in listed {
+        let unit_name = match unit_file_path.rsplit('/').next() {
+            Some(unit_name) => unit_name.to_string(),
+            None => continue,
+        };
+        // Newer versions of systemd may introduce states. Ignore them.
+        if let Ok(state) = UnitFileState::try_from(&state_str[..]) {
+            if rules_match_name(rules, &unit_name) {
+                unit_files.insert(unit_name, state);
+            }
+        }
+    }
+    let expected_names = rules.iter().filter_map(|rule| match &rule.expression {
+        Expression::UnitName(unit_name) => Some(unit_name),
+        _ => None,
+    });
+    for unit_name in previous.keys().chain(expected_names) {
+        if !unit_files.contains_key(unit_name) {
+            unit_files.insert(unit_name.to_owned(), UnitFileState::Removed);
+        }
+    }
+    unit_files
+}
+
+// Get the states of the portable images named by `rules`, given the listed images.
+//
+// `listed` holds the names and states of images, as returned by ListImages. An image is `Detached`
+// if it isn't listed, but it was in `previous`, or a rule names it.
+fn get_portable_image_states(
+    listed: Vec<(String, String)>,
+    previous: &HashMap<String, PortableState>,
+    rules: &[&Rule],
+) -> HashMap<String, PortableState> {
+    let expected_names: Vec<&str> = rules.iter().map(|rule| rule.expression.as_str()).collect();
+    let mut images: HashMap<String, PortableState> = HashMap::new();
+    for (image_name, state_str) in listed {
+        // Newer versions of systemd may introduce states. Ignore them.
+        if let Ok(state) = PortableState::try_from(&state_str[..]) {
+            if expected_names.contains(&&image_name[..]) {
+                images.insert(image_name, state);
+            }
+        }
+    }
+    for image_name in previous.keys().map(String::as_str).chain(expected_names) {
+        if !images.contains_key(image_name) {
+            images.insert(image_name.to_owned(), PortableState::Detached);
+        }
+    }
+    images
+}
+
+// Describe an event for a digest, e.g. "2024-01-01T03:00:00Z foo.service: active -> failed".
+//
+// `body_states` is ordered from newest to oldest, as for notifiers.
+fn gen_digest_entry(
+    unit_name: &str,
+    real_ts: &RealtimeTimestamp,
+    body_states: &[String],
+) -> String {
+    let new_state = body_states.first().cloned().unwrap_or_default();
+    match body_states.get(1) {
+        Some(old_state) => format!(
+            "{} {}: {} -> {}",
+            real_ts.to_iso8601(),
+            unit_name,
+            old_state,
+            new_state
+        ),
+        None => format!("{} {}: {}", real_ts.to_iso8601(), unit_name, new_state),
+    }
+}
+
+// Convert an event to the form in which it's stored in the history file and event log.
+fn gen_history_entry(event: &Event) -> HistoryEntry {
+    HistoryEntry {
+        bus_type: settings::encode_bus_type(event.bus_type).to_string(),
+        unit: event.unit_name.to_owned(),
+        old_state: event.old_state.as_ref().map(ActiveState::to_string),
+        new_state: event.new_state.to_string(),
+        timestamp: event.real_ts.0,
+    }
+}
+
+// Get the interface which has the `Slice` property of the given unit, according to its type, or
+// `None` if units of its type have no slice.
+fn get_slice_interface(unit_name: &str) -> Option<&'static str> {
+    match unit_name.rsplit_once('.').map(|(_, unit_type)| unit_type) {
+        Some("mount") => Some("org.freedesktop.systemd1.Mount"),
+        Some("scope") => Some("org.freedesktop.systemd1.Scope"),
+        Some("service") => Some("org.freedesktop.systemd1.Service"),
+        Some("slice") => Some("org.freedesktop.systemd1.Slice"),
+        Some("socket") => Some("org.freedesktop.systemd1.Socket"),
+        Some("swap") => Some("org.freedesktop.systemd1.Swap"),
+        _ => None,
+    }
+}
+
+// Get the units to list in a boot summary, given the names and ActiveStates of loaded units.
+//
+// Units which have failed are listed, as are units matching `rules` which aren't active. Each is
+// listed as e.g. "foo.service=failed", in order of unit name.
+fn get_boot_summary_states(units: Vec<(String, String)>, rules: &[&Rule]) -> Vec<String> {
+    let mut units: Vec<(String, String)> = units
+        .into_iter()
+        .filter(|(unit_name, active_state)| {
+            active_state == "failed"
+                || (active_state != "active" && rules_match_name(rules, unit_name))
+        })
+        .collect();
+    units.sort();
+    units
+        .into_iter()
+        .map(|(unit_name, active_state)| format!("{}={}", unit_name, active_state))
+        .collect()
+}
+
+// Tell whether a message says that unit files may have changed state.
+//
+// That's the case for the UnitFilesChanged signal, and for the Reloading signal when a reload has
+// finished, as a reload may be due to unit files having been edited.
+fn is_unit_files_change(msg: &Message) -> bool {
+    UnitFilesChanged::from_message(msg).is_some()
+        || Reloading::from_message(msg).is_some_and(|msg_body| !msg_body.arg0)
+}
+
+fn get_rules_matching_unit_file_state<'a>(
+    rules: &[&'a Rule],
+    target: UnitFileState,
+) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .cloned() // &&Rule → &Rule
+        .filter(|rule: &&Rule| rule.unit_file_states.contains(&target))
+        .collect()
+}
+
+// Tell which of the property conditions of `rules` are met by the given properties.
+//
+// `unit_props` holds properties of `interface` as of `now`, and `history` holds what was previously
+// seen of them. Return each rule with a met condition, along with the property's new and old
+// values, ordered from newest to oldest.
+fn get_property_triggers<'a>(
+    rules: &[&'a Rule],
+    unit_name: &str,
+    interface: &str,
+    unit_props: &UnitProps,
+    history: &PropertyHistory,
+    now: Instant,
+) -> Vec<(&'a Rule, Vec<String>)> {
+    let mut triggers: Vec<(&'a Rule, Vec<String>)> = Vec::new();
+    for rule in rules {
+        for condition in &rule.properties {
+            if !condition.is_on_interface(unit_name, interface) {
+                continue;
+            }
+            let new = match unit_props.get(&condition.property) {
+                Some(variant) => PropertyValue::from_ref_arg(&variant.0),
+                None => continue,
+            };
+            let old = history.get_value(interface, &condition.property);
+            if condition.is_met(history, interface, &new, now) {
+                let mut body_states = vec![format!("{}={}", condition.property, new)];
+                if let Some(old) = old {
+                    body_states.push(format!("{}={}", condition.property, old));
+                }
+                triggers.push((rule, body_states));
+            }
+        }
+    }
+    triggers
+}
+
+// Return the value of the ActiveState property.
+pub fn get_active_state(unit_props: &UnitProps) -> Result<ActiveState, CrateError> {
+    let active_state_str: &str = unit_props
+        .get("ActiveState")
+        .ok_or_else(|| CrateError::Bus(BusError::PropertiesLacksActiveState))?
+        .0
+        .as_str()
+        .ok_or_else(|| CrateError::Bus(BusError::CastOrgFreedesktopSystemd1UnitActiveState))?;
+    ActiveState::try_from(active_state_str)
+}
+
+// Return the value of the SubState property, along with the time of the unit's latest state change
+// of any kind, or `None` if either is absent.
+fn get_sub_state(unit_props: &UnitProps) -> Option<SubState> {
+    let name = unit_props.get("SubState")?.0.as_str()?.to_string();
+    let mono_ts = unit_props
+        .get("StateChangeTimestampMonotonic")?
+        .0
+        .as_u64()?;
+    let real_ts = unit_props.get("StateChangeTimestamp")?.0.as_u64()?;
+    Some(SubState {
+        name,
+        mono_ts: MonotonicTimestamp(mono_ts),
+        real_ts: RealtimeTimestamp(real_ts),
+    })
+}
+
+// Return the value of the Transient property, or `None` if it is absent. D-Bus booleans read as
+// 0 or 1.
+fn get_transient(unit_props: &UnitProps) -> Option<bool> {
+    let transient = unit_props.get("Transient")?.0.as_u64()?;
+    Some(transient != 0)
+}
+
+// Return the value of the LoadState property, or `None` if it is absent.
+fn get_load_state(unit_props: &UnitProps) -> Result<Option<LoadState>, CrateError> {
+    match unit_props.get("LoadState") {
+        None => Ok(None),
+        Some(variant) => {
+            let load_state_str: &str = variant.0.as_str().ok_or(CrateError::Bus(
+                BusError::CastOrgFreedesktopSystemd1UnitLoadState,
+            ))?;
+            LoadState::try_from(load_state_str).map(Some)
+        }
+    }
+}
+
+// Cast a D-Bus array of strings (signature `as`) to a vector of strings.
+//
+// Return `None` if `value` is not an array, or if any of its items aren't strings.
+fn cast_ref_arg_to_strings(value: &dyn RefArg) -> Option<Vec<String>> {
+    value
+        .as_iter()?
+        .map(|item| item.as_str().map(String::from))
+        .collect()
+}
+
+// Given a bus name foo.bar.Biz1, make path /foo/bar/Biz1.
+//
+// Will return an error if unable to make a string from the contents of `bus_name`, or if the Path
+// object being created does not contain a valid path name.
+fn cast_bus_name_to_path(bus_name: &BusName) -> Result<Path<'static>, CrateError> {
+    let mut path_str = bus_name
+        .as_cstr()
+        .to_str()
+        .map_err(|err| CrateError::Bus(BusError::CastBusNameToStr(err)))?
+        .replace(".", "/");
+    path_str.insert(0, '/');
+    let path = Path::new(path_str)
+        .map_err(|err| CrateError::Bus(BusError::CastStrToPath(err)))?
+        .to_owned();
+    Ok(path)
+}
+
+// Get the values of the variables a notifier's message template may reference.
+//
+// `host` provides the host's name, machine ID and tags. `body_states` is ordered from newest to
+// oldest. The unit's description is expensive to fetch, and so is omitted.
+// The variables specific to a rule are added by `add_rule_template_values`.
+fn get_template_values(
+    host: &Host,
+    unit_name: &str,
+    mono_ts: &MonotonicTimestamp,
+    real_ts: &RealtimeTimestamp,
+    body_states: &[String],
+) -> HashMap<String, String> {
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut insert = |key: &str, value: String| values.insert(key.to_string(), value);
+    insert("hostname", host.hostname.to_owned());
+    insert("machine_id", host.machine_id.to_owned().unwrap_or_default());
+    insert(
+        "new_state",
+        body_states.first().cloned().unwrap_or_default(),
+    );
+    insert("old_state", body_states.get(1).cloned().unwrap_or_default());
+    insert("timestamp", real_ts.0.to_string());
+    insert("timestamp_iso8601", real_ts.to_iso8601());
+    insert("timestamp_monotonic", mono_ts.0.to_string());
+    insert("unit", unit_name.to_string());
+    let (template_name, instance) = unit::split_instance_name(unit_name).unwrap_or_default();
+    insert("template", template_name);
+    insert("instance", instance);
+    for (tag_name, tag_value) in &host.tags {
+        insert(
+            &format!("{}{}", template::TAGS_PREFIX, tag_name),
+            tag_value.to_owned(),
+        );
+    }
+    values
+}
+
+// Add the template variables describing the given rule, i.e. its name, severity and labels.
+//
+// `severity` may differ from the rule's severity, e.g. during quiet hours, and `labels` may differ
+// from the rule's labels, e.g. if its filter added some.
+fn add_rule_template_values(
+    values: &mut HashMap<String, String>,
+    rule: &Rule,
+    labels: &HashMap<String, String>,
+    severity: Severity,
+) {
+    values.insert("rule".to_string(), rule.name.to_owned().unwrap_or_default());
+    values.insert("severity".to_string(), String::from(severity));
+    for (label_name, label_value) in labels {
+        values.insert(
+            format!("{}{}", template::LABELS_PREFIX, label_name),
+            label_value.to_owned(),
+        );
+    }
+}
+
+// Get the template variables describing a notification, including its rule's.
+pub fn get_notification_template_values(notification: &Notification) -> HashMap<String, String> {
+    let mut values = get_template_values(
+        notification.host,
+        notification.unit_name,
+        notification.mono_ts,
+        notification.real_ts,
+        notification.states,
+    );
+    values.insert(
+        "rule".to_string(),
+        notification.rule_name.unwrap_or_default().to_string(),
+    );
+    values.insert("severity".to_string(), String::from(notification.severity));
+    for (label_name, label_value) in notification.labels {
+        values.insert(
+            format!("{}{}", template::LABELS_PREFIX, label_name),
+            label_value.to_owned(),
+        );
+    }
+    values
+}
+
+// Get this host's name, or an empty string if it can't be read.
+pub fn get_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_default()
+}
+
+// Get this host's machine ID, or `None` if it can't be read. See machine-id(5).
+pub fn get_machine_id() -> Option<String> {
+    fs::read_to_string("/etc/machine-id")
+        .map(|machine_id| machine_id.trim().to_string())
+        .ok()
+        .filter(|machine_id| !machine_id.is_empty())
+}
+
+// A D-Bus notifier, which is called with the `Notify` method of `protocol_version`.
+//
+// Failing to connect to the notifier's bus is as much a failure to reach the notifier as the call
+// failing or timing out, so both are errors.
+pub struct DBusBackend<'a> {
+    pub bus_name: &'a str,
+    pub bus_type: BusType,
+    pub notifier: &'a Notifier,
+}
+
+impl DBusBackend<'_> {
+    fn get_bus_name(&self) -> Result<BusName<'_>, CrateError> {
+        BusName::new(self.bus_name).map_err(|_| {
+            CrateError::Settings(SettingsError::InvalidBusName(self.bus_name.to_owned()))
+        })
+    }
+}
+
+impl NotifierBackend for DBusBackend<'_> {
+    fn name(&self) -> &'static str {
+        "dbus"
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        let msg = gen_notify_message(&self.get_bus_name()?, self.notifier, notification)?;
+        with_notifier_connection(self.bus_type, |conn| {
+            conn.send_with_reply_and_block(msg, get_timeout_ms(timeout))
+        })
+        .map(|_| ())
+        .map_err(|err| {
+            CrateError::Notifier(NotifierError::NotifyNotifier(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+
+    // Call `org.freedesktop.DBus.Peer.Ping`.
+    //
+    // Every D-Bus peer implements this method, so a successful ping means that the notifier's bus
+    // is reachable and that something owns (or can be activated to own) its bus name.
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        let header_bus_name = self.get_bus_name()?;
+        let header_path = cast_bus_name_to_path(&header_bus_name)?;
+        let msg = Message::method_call(
+            &header_bus_name,
+            &header_path,
+            &wrap_interface_for_peer(),
+            &wrap_member_for_ping(),
+        );
+        with_notifier_connection(self.bus_type, |conn| {
+            conn.send_with_reply_and_block(msg, get_timeout_ms(timeout))
+        })
+        .map(|_| ())
+        .map_err(|err| {
+            CrateError::Notifier(NotifierError::PingNotifier(notifier_name.to_string(), err))
+        })
+    }
+}
+
+thread_local! {
+    // Connections to the buses on which D-Bus notifiers live, keyed by bus type.
+    //
+    // Connecting to a bus costs several round trips, so each thread reuses its connections to
+    // notifiers rather than connecting anew for each notification. Connections can't be shared
+    // between threads. A bus is only connected to once a notifier on it is contacted, so that a
+    // rule may use notifiers on another bus than the one it watches, without costing the other
+    // rules anything.
+    static NOTIFIER_CONNECTIONS: RefCell<HashMap<&'static str, Connection>> =
+        RefCell::new(HashMap::new());
+}
+
+// Call `call` with this thread's connection to the given bus, connecting first if necessary.
+//
+// If the call fails, the connection is dropped, so that the next call reconnects. This way, a
+// connection which was broken, e.g. by the bus restarting, isn't reused.
+fn with_notifier_connection<T, F>(bus_type: BusType, call: F) -> Result<T, DBusError>
+where
+    F: FnOnce(&Connection) -> Result<T, DBusError>,
+{
+    NOTIFIER_CONNECTIONS.with(|connections| {
+        let mut connections = connections.borrow_mut();
+        let key = settings::encode_bus_type(bus_type);
+        let conn = match connections.remove(key) {
+            Some(conn) => conn,
+            None => Connection::get_private(bus_type)?,
+        };
+        let result = call(&conn);
+        if result.is_ok() {
+            connections.insert(key, conn);
+        }
+        result
+    })
+}
+
+// Generate a call to `org.freedesktop.DBus.Properties.GetAll` for the unit at the given path.
+fn gen_get_all_call(unit_path: &Path) -> Message {
+    gen_systemd_call(unit_path, INTERFACE_FOR_PROPERTIES, "GetAll")
+        .append1(INTERFACE_FOR_SYSTEMD_UNIT)
+}
+
+// Create a call to the given method of `org.freedesktop.systemd1`, at the given object path.
+pub fn gen_systemd_call(path: &Path, interface: &str, member: &str) -> Message {
+    Message::new_method_call(BUS_NAME_FOR_SYSTEMD, path.clone(), interface, member)
+        .unwrap_or_else(|err| panic!("Failed to create {} call: {}", member, err))
+}
+
+// Report a failure to handle a signal, unless the connection to the bus was lost.
+//
+// Most failures concern one unit, e.g. a unit which was unloaded while it was being looked at, or
+// whose properties are malformed. Such failures are reported on stderr, and `Ok` is returned, so
+// that one bad unit can't stop the monitoring of a whole bus. If the connection to the bus was
+// lost, the error is returned, and the bus watcher stops. See `Supervisor`.
+fn survive_unit_error(
+    signal: &'static str,
+    result: Result<(), CrateError>,
+) -> Result<(), CrateError> {
+    match result {
+        Err(err) if !is_disconnected(&err) => {
+            eprintln!(
+                "{}",
+                CrateError::Bus(BusError::HandleSignal(signal, Box::new(err))).with_code()
+            );
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// Check whether the given error means that the connection to the bus was lost.
+fn is_disconnected(err: &CrateError) -> bool {
+    err.source()
+        .and_then(|source| source.downcast_ref::<DBusError>())
+        .and_then(DBusError::name)
+        .is_some_and(|name| DISCONNECTED_DBUS_ERRORS.contains(&name))
+}
+
+// Check whether the given error came from a call to systemd that may succeed if retried.
+fn is_transient(err: &CrateError) -> bool {
+    let dbus_err = match err {
+        CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGet(err))
+        | CrateError::Bus(BusError::CallOrgFreedesktopDBusPropertiesGetAll(err))
+        | CrateError::Bus(BusError::CallOrgFreedesktopPortable1ManagerListImages(err))
+        | CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerGetUnit(err))
+        | CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerListUnitFiles(err))
+        | CrateError::Bus(BusError::CallOrgFreedesktopSystemd1ManagerListUnits(err)) => err,
+        _ => return false,
+    };
+    dbus_err
+        .name()
+        .is_some_and(|name| TRANSIENT_DBUS_ERRORS.contains(&name))
+}
+
+// Get a timeout in milliseconds, as accepted by `Connection::send_with_reply_and_block`.
+fn get_timeout_ms(timeout: Duration) -> i32 {
+    i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX)
+}
+
+// Send notifications, and return the outcome of each attempt, in order.
+//
+// With the `All` policy, the notifiers are contacted concurrently, so that a slow notifier doesn't
+// delay the others. With the `Fallback` policy, they're tried in order until one is reached, and
+// the rest aren't contacted, so fewer outcomes may be returned.
+fn send_notifications(
+    policy: NotifierPolicy,
+    deliveries: &[(&str, &Notifier, Notification)],
+) -> Vec<Result<(), CrateError>> {
+    let send = |(notifier_name, notifier, args): &(&str, &Notifier, Notification)| {
+        backend::get_backend(notifier).send(notifier_name, args, notifier.timeout)
+    };
+    match policy {
+        NotifierPolicy::All if deliveries.len() > 1 => thread::scope(|scope| {
+            let handles: Vec<_> = deliveries
+                .iter()
+                .map(|delivery| scope.spawn(move || send(delivery)))
+                .collect();
+            handles
+                .into_iter()
+                .zip(deliveries)
+                .map(|(handle, (notifier_name, _, _))| {
+                    handle.join().unwrap_or_else(|_| {
+                        Err(CrateError::Notifier(NotifierError::NotifierThreadPanicked(
+                            notifier_name.to_string(),
+                        )))
+                    })
+                })
+                .collect()
+        }),
+        NotifierPolicy::All => deliveries.iter().map(send).collect(),
+        NotifierPolicy::Fallback => {
+            let mut results = Vec::new();
+            for delivery in deliveries {
+                let result = send(delivery);
+                let reached = result.is_ok();
+                results.push(result);
+                if reached {
+                    break;
+                }
+            }
+            results
+        }
+    }
+}
+
+// Generate a call to the `Notify` method of the given D-Bus notifier, which has `bus_name`.
+fn gen_notify_message(
+    bus_name: &BusName,
+    notifier: &Notifier,
+    args: &Notification,
+) -> Result<Message, CrateError> {
+    let header_bus_name = bus_name;
+    let header_path = cast_bus_name_to_path(header_bus_name)?;
+    let header_interface = wrap_interface_for_killjoy_notifier(notifier.protocol_version);
+    let header_member = wrap_member_for_notify();
+
+    let msg = Message::method_call(
+        header_bus_name,
+        &header_path,
+        &header_interface,
+        &header_member,
+    );
+    let msg = match notifier.protocol_version {
+        ProtocolVersion::V1 => append_notify_v1_body(msg, notifier.clock, args),
+        ProtocolVersion::V2 => msg.append1(gen_notify_v2_body(args)),
+    };
+    Ok(msg)
+}
+
+// Append the arguments for `name.jerebear.KilljoyNotifier1.Notify` to `msg`.
+//
+// The signature is `(t, s, as)`, optionally followed by `as`, optionally followed by `s`. Log
+// messages aren't part of this version of the protocol, so `args.journal_lines` is ignored.
+fn append_notify_v1_body(msg: Message, clock: Clock, args: &Notification) -> Message {
+    let timestamp = match clock {
+        Clock::Monotonic => args.mono_ts.0,
+        Clock::Realtime => args.real_ts.0,
+    };
+    let msg = msg.append3::<u64, &str, &[String]>(timestamp, args.unit_name, args.states);
+    let msg = match args.dependents {
+        Some(dependents) => msg.append1::<&[String]>(dependents),
+        None => msg,
+    };
+    match &args.message {
+        Some(message) => msg.append1::<&str>(message),
+        None => msg,
+    }
+}
+
+// Generate the argument for `name.jerebear.KilljoyNotifier2.Notify`, a dictionary (`a{sv}`).
+//
+// The following keys are always present:
+//
+// *   `unit` (`s`): The unit's name.
+// *   `states` (`as`): The unit's states, from newest to oldest.
+// *   `new_state` (`s`): The unit's new state.
+// *   `timestamp_monotonic` (`t`) and `timestamp_realtime` (`t`): When the state was entered.
+// *   `hostname` (`s`): The name of the host on which the unit lives.
+// *   `tags` (`a{ss}`): The tags of the host, from the settings' `identity`. May be empty.
+// *   `bus` (`s`): The bus on which the unit lives, e.g. "session".
+// *   `labels` (`a{ss}`): The labels of the rule that matched. May be empty.
+// *   `payload` (`a{ss}`): The payload of the rule that matched, verbatim. May be empty.
+// *   `severity` (`s`): The severity of the rule that matched, e.g. "warning".
+// *   `urgency` (`y`): The severity, as a Desktop Notifications Specification urgency level.
+// *   `rules` (`as`): The names of the rules that matched, i.e. the event's tags. May be empty.
+//
+// The following keys are present only if applicable:
+//
+// *   `rule` (`s`): The name of the rule that matched, if it has one.
+// *   `old_state` (`s`): The unit's previous state, if known.
+// *   `machine_id` (`s`): The host's machine ID, if the settings' `identity` asks for it.
+// *   `dependents` (`as`): The units depending on the unit, if the rule asked for them.
+// *   `journal_lines` (`as`): The unit's recent log messages, if the rule asked for them.
+// *   `message` (`s`): A human-readable message, if the notifier has a message template.
+fn gen_notify_v2_body(args: &Notification) -> UnitProps {
+    fn wrap<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg + 'static>> {
+        Variant(Box::new(value))
+    }
+
+    let mut body: UnitProps = HashMap::new();
+    body.insert("unit".to_string(), wrap(args.unit_name.to_string()));
+    if let Some((template_name, instance)) = unit::split_instance_name(args.unit_name) {
+        body.insert("template".to_string(), wrap(template_name));
+        body.insert("instance".to_string(), wrap(instance));
+    }
+    body.insert("states".to_string(), wrap(args.states.to_vec()));
+    if let Some(new_state) = args.states.first() {
+        body.insert("new_state".to_string(), wrap(new_state.to_owned()));
+    }
+    if let Some(old_state) = args.states.get(1) {
+        body.insert("old_state".to_string(), wrap(old_state.to_owned()));
+    }
+    body.insert("timestamp_monotonic".to_string(), wrap(args.mono_ts.0));
+    body.insert("timestamp_realtime".to_string(), wrap(args.real_ts.0));
+    body.insert("hostname".to_string(), wrap(args.host.hostname.to_owned()));
+    if let Some(machine_id) = &args.host.machine_id {
+        body.insert("machine_id".to_string(), wrap(machine_id.to_owned()));
+    }
+    body.insert("tags".to_string(), wrap(args.host.tags.clone()));
+    body.insert(
+        "bus".to_string(),
+        wrap(settings::encode_bus_type(args.bus_type).to_string()),
+    );
+    body.insert("labels".to_string(), wrap(args.labels.clone()));
+    body.insert("payload".to_string(), wrap(args.payload.clone()));
+    body.insert("severity".to_string(), wrap(String::from(args.severity)));
+    body.insert("urgency".to_string(), wrap(args.severity.urgency()));
+    if let Some(rule_name) = args.rule_name {
+        body.insert("rule".to_string(), wrap(rule_name.to_string()));
+    }
+    body.insert("rules".to_string(), wrap(args.rule_names.to_vec()));
+    if let Some(dependents) = args.dependents {
+        body.insert("dependents".to_string(), wrap(dependents.to_vec()));
+    }
+    if let Some(journal_lines) = args.journal_lines {
+        body.insert("journal_lines".to_string(), wrap(journal_lines.to_vec()));
+    }
+    if let Some(message) = &args.message {
+        body.insert("message".to_string(), wrap(message.to_owned()));
+    }
+    body
+}
+
+// Generate the JSON object sent to MQTT notifiers and plugins.
+//
+// The object has the same keys as the dictionary sent to version 2 D-Bus notifiers. See
+// `gen_notify_v2_body`.
+pub fn gen_notify_json_body(args: &Notification) -> Value {
+    let mut body: Map<String, Value> = Map::new();
+    body.insert("unit".to_string(), Value::from(args.unit_name));
+    if let Some((template_name, instance)) = unit::split_instance_name(args.unit_name) {
+        body.insert("template".to_string(), Value::from(template_name));
+        body.insert("instance".to_string(), Value::from(instance));
+    }
+    body.insert("states".to_string(), Value::from(args.states.to_vec()));
+    if let Some(new_state) = args.states.first() {
+        body.insert("new_state".to_string(), Value::from(&new_state[..]));
+    }
+    if let Some(old_state) = args.states.get(1) {
+        body.insert("old_state".to_string(), Value::from(&old_state[..]));
+    }
+    body.insert(
+        "timestamp_monotonic".to_string(),
+        Value::from(args.mono_ts.0),
+    );
+    body.insert(
+        "timestamp_realtime".to_string(),
+        Value::from(args.real_ts.0),
+    );
+    body.insert("hostname".to_string(), Value::from(&args.host.hostname[..]));
+    if let Some(machine_id) = &args.host.machine_id {
+        body.insert("machine_id".to_string(), Value::from(&machine_id[..]));
+    }
+    let tags: Map<String, Value> = args
+        .host
+        .tags
+        .iter()
+        .map(|(name, value)| (name.to_owned(), Value::from(&value[..])))
+        .collect();
+    body.insert("tags".to_string(), Value::Object(tags));
+    body.insert(
+        "bus".to_string(),
+        Value::from(settings::encode_bus_type(args.bus_type)),
+    );
+    let labels: Map<String, Value> = args
+        .labels
+        .iter()
+        .map(|(name, value)| (name.to_owned(), Value::from(&value[..])))
+        .collect();
+    body.insert("labels".to_string(), Value::Object(labels));
+    let payload: Map<String, Value> = args
+        .payload
+        .iter()
+        .map(|(name, value)| (name.to_owned(), Value::from(&value[..])))
+        .collect();
+    body.insert("payload".to_string(), Value::Object(payload));
+    body.insert(
+        "severity".to_string(),
+        Value::from(String::from(args.severity)),
+    );
+    body.insert("urgency".to_string(), Value::from(args.severity.urgency()));
+    if let Some(rule_name) = args.rule_name {
+        body.insert("rule".to_string(), Value::from(rule_name));
+    }
+    body.insert("rules".to_string(), Value::from(args.rule_names.to_vec()));
+    if let Some(dependents) = args.dependents {
+        body.insert("dependents".to_string(), Value::from(dependents.to_vec()));
+    }
+    if let Some(journal_lines) = args.journal_lines {
+        body.insert(
+            "journal_lines".to_string(),
+            Value::from(journal_lines.to_vec()),
+        );
+    }
+    if let Some(message) = &args.message {
+        body.insert("message".to_string(), Value::from(&message[..]));
+    }
+    Value::Object(body)
+}
+
+// Ping a notifier, to check that it's reachable.
+//
+// D-Bus notifiers are pinged by calling `org.freedesktop.DBus.Peer.Ping`. Other notifiers are
+// pinged as their backends see fit, e.g. by connecting to a broker. `notifier_name` is used to
+// describe failures.
+pub fn ping_notifier(notifier_name: &str, notifier: &Notifier) -> Result<(), CrateError> {
+    backend::get_backend(notifier).ping(notifier_name, notifier.timeout)
+}
+
+// Send a synthetic notification to a notifier, as if the unit `TEST_UNIT_NAME` had failed.
+//
+// Unlike when a real event occurs, failing to reach the notifier is an error. `notifier_name` is
+// used to describe failures. The host is described according to `identity`.
+pub fn send_test_notification(
+    notifier_name: &str,
+    notifier: &Notifier,
+    identity: &Identity,
+) -> Result<(), CrateError> {
+    let host = Host::new(identity);
+    let mono_ts = timestamp::get_current_monotonic_timestamp();
+    let real_ts = timestamp::get_current_realtime_timestamp();
+    let body_states: Vec<String> = vec![String::from(ActiveState::Failed)];
+    let severity = Severity::Warning;
+    let labels: HashMap<String, String> = HashMap::new();
+    let payload: HashMap<String, String> = HashMap::new();
+    let message: Option<String> = notifier.message_template.as_ref().map(|template| {
+        let mut values =
+            get_template_values(&host, TEST_UNIT_NAME, &mono_ts, &real_ts, &body_states);
+        values.insert("description".to_string(), TEST_UNIT_DESCRIPTION.to_string());
+        values.insert("severity".to_string(), String::from(severity));
+        template.render(&values)
+    });
+    // The fake unit lives on the notifier's bus, or on the session bus if the notifier has none.
+    let args = Notification {
+        bus_type: match &notifier.target {
+            NotifierTarget::DBus(bus_type, _) => *bus_type,
+            _ => BusType::Session,
+        },
+        dependents: None,
+        host: &host,
+        journal_lines: None,
+        labels: &labels,
+        message,
+        mono_ts: &mono_ts,
+        payload: &payload,
+        real_ts: &real_ts,
+        rule_name: None,
+        rule_names: &[],
+        severity,
+        states: &body_states,
+        unit_name: TEST_UNIT_NAME,
+    };
+    backend::get_backend(notifier)
+        .send(notifier_name, &args, notifier.timeout)
+        .map_err(|err| match err {
+            CrateError::Notifier(NotifierError::NotifyNotifier(notifier_name, source)) => {
+                CrateError::Notifier(NotifierError::SendTestNotification(notifier_name, source))
+            }
+            err => err,
+        })
+}
+
+// Cross-check settings against the running system, and return every problem found.
+//
+// Each bus referenced by the settings is connected to. A rule is a problem if it matches none of
+// the units currently loaded on its bus. Notifiers are checked as by `check_notifier_buses` and
+// `check_notifiers_against_system`. Apart from notifiers on a bus killjoy can't connect to, none of
+// these is necessarily a mistake, as a unit may not be loaded yet, and a notifier may not be
+// running yet, but all are often caused by typos.
+pub fn check_settings_against_system(settings: &Settings) -> Vec<CrateError> {
+    let mut errs: Vec<CrateError> = Vec::new();
+    let mut connections: HashMap<&'static str, Option<Connection>> = HashMap::new();
+
+    for bus_type in settings::get_bus_types(&settings.rules) {
+        let conn = match get_cached_connection(&mut connections, bus_type, &mut errs) {
+            Some(conn) => conn,
+            None => continue,
+        };
+        let unit_names: Vec<String> = match conn
+            .with_path(
+                BUS_NAME_FOR_SYSTEMD,
+                PATH_FOR_SYSTEMD,
+                get_timeout_ms(settings.systemd_calls.list_timeout),
+            )
+            .list_units()
+        {
+            Ok(units) => units.into_iter().map(|unit| unit.0).collect(),
+            Err(err) => {
+                errs.push(CrateError::Bus(
+                    BusError::CallOrgFreedesktopSystemd1ManagerListUnits(err),
+                ));
+                continue;
+            }
+        };
+        // Rules which watch the manager don't match units, and portable images may be detached.
+        for rule in settings.rules.iter().filter(|rule| {
+            rule.bus_type == bus_type
+                && !matches!(
+                    rule.expression,
+                    Expression::Manager(_) | Expression::PortableImage(_)
+                )
+        }) {
+            if !unit_names.iter().any(|name| rule.expression.matches(name)) {
+                errs.push(CrateError::Bus(BusError::RuleMatchesNoUnits(
+                    rule.describe(),
+                    settings::encode_bus_type(bus_type),
+                )));
+            }
+        }
+    }
+
+    errs.extend(check_notifier_buses(settings));
+    check_notifier_owners(settings, &mut connections, &mut errs);
+    errs.extend(check_notifier_policies(settings));
+    errs
+}
+
+// Cross-check notifiers against the running system, and return every problem found.
+//
+// A notifier is a problem if nothing owns its bus name and nothing can be activated to own it, or
+// if it's on the system bus and no policy allows sending to it. See `check_notifier_policies`.
+pub fn check_notifiers_against_system(settings: &Settings) -> Vec<CrateError> {
+    let mut errs: Vec<CrateError> = Vec::new();
+    let mut connections: HashMap<&'static str, Option<Connection>> = HashMap::new();
+    check_notifier_owners(settings, &mut connections, &mut errs);
+    errs.extend(check_notifier_policies(settings));
+    errs
+}
+
+// Check that killjoy can connect to the bus of every notifier used by a rule or the boot summary,
+// and return every problem found.
+//
+// A rule may use notifiers on any bus, whatever bus it watches, as notifiers are contacted over
+// their own connections. But a notifier on the session bus can't work if killjoy has no session
+// bus, e.g. because it runs as a system service. See `check_notifier_buses_with`.
+pub fn check_notifier_buses(settings: &Settings) -> Vec<CrateError> {
+    check_notifier_buses_with(settings, has_session_bus())
+}
+
+// Like `check_notifier_buses`, but with `session_bus` telling whether a session bus is available.
+//
+// Each notifier is reported once, along with the first user of it.
+fn check_notifier_buses_with(settings: &Settings, session_bus: bool) -> Vec<CrateError> {
+    let mut users: Vec<(String, &String)> = Vec::new();
+    if let Some(boot_summary) = &settings.boot_summary {
+        users.extend(
+            boot_summary
+                .notifiers
+                .iter()
+                .map(|notifier_name| ("The boot summary".to_string(), notifier_name)),
+        );
+    }
+    for rule in &settings.rules {
+        users.extend(
+            rule.notifiers
+                .iter()
+                .map(|notifier_name| (rule.describe(), notifier_name)),
+        );
+    }
+    let mut reported: HashSet<&String> = HashSet::new();
+    let mut errs: Vec<CrateError> = Vec::new();
+    for (user, notifier_name) in users {
+        let on_session_bus = matches!(
+            settings
+                .notifiers
+                .get(notifier_name)
+                .and_then(Notifier::get_dbus_address),
+            Some((BusType::Session, _))
+        );
+        if on_session_bus && !session_bus && reported.insert(notifier_name) {
+            errs.push(CrateError::Settings(
+                SettingsError::SessionNotifierWithoutSessionBus(notifier_name.to_owned(), user),
+            ));
+        }
+    }
+    errs
+}
+
+// Tell whether this process can find a session bus, as libdbus does.
+//
+// libdbus looks for the session bus at $DBUS_SESSION_BUS_ADDRESS, or else at $XDG_RUNTIME_DIR/bus.
+// Neither is usually set for system services.
+fn has_session_bus() -> bool {
+    if env::var_os("DBUS_SESSION_BUS_ADDRESS").map_or(false, |address| !address.is_empty()) {
+        return true;
+    }
+    env::var_os("XDG_RUNTIME_DIR").map_or(false, |dir| PathBuf::from(dir).join("bus").exists())
+}
+
+// Check whether something owns or can be activated to own each notifier's bus name, and push
+// every problem found onto `errs`.
+fn check_notifier_owners(
+    settings: &Settings,
+    connections: &mut HashMap<&'static str, Option<Connection>>,
+    errs: &mut Vec<CrateError>,
+) {
+    let mut notifier_names: Vec<&String> = settings.notifiers.keys().collect();
+    notifier_names.sort();
+    for notifier_name in notifier_names {
+        // MQTT notifiers have no bus name to look for.
+        let (bus_type, bus_name) = match settings.notifiers[notifier_name].get_dbus_address() {
+            Some(address) => address,
+            None => continue,
+        };
+        let conn = match get_cached_connection(connections, bus_type, errs) {
+            Some(conn) => conn,
+            None => continue,
+        };
+        match has_owner_or_activator(conn, &bus_name) {
+            Ok(true) => {}
+            Ok(false) => errs.push(CrateError::Notifier(NotifierError::NotifierHasNoOwner(
+                notifier_name.to_owned(),
+                bus_name.to_string(),
+            ))),
+            Err(err) => errs.push(err),
+        }
     }
+}
 
-    // Unsubscribe from the `org.freedesktop.DBus.Properties.PropertiesChanged` signal.
-    fn unsubscribe_properties_changed(&self, unit_path: &Path) -> Result<(), CrateError> {
-        let bus_name = wrap_bus_name_for_systemd();
-        let match_str: String = PropertiesChanged::match_str(Some(&bus_name), Some(&unit_path));
-        self.connection
-            .remove_match(&match_str)
-            .map(|_| ())
-            .map_err(|err: DBusError| CrateError::RemoveSignalMatch(match_str, err))
+// Check whether D-Bus policy lets killjoy contact each notifier on the system bus, and return
+// every problem found.
+//
+// Unlike the session bus, the system bus rejects method calls unless a policy allows them. A
+// notifier is a problem if none of the policy files in `SYSTEM_BUS_POLICY_DIRS` allows sending to
+// its bus name. This is a heuristic: policies may also be set elsewhere, e.g. in system.conf, and
+// which users a policy applies to isn't checked. Each problem includes a policy which would fix
+// it. See `gen_policy_snippet`.
+pub fn check_notifier_policies(settings: &Settings) -> Vec<CrateError> {
+    let mut notifier_names: Vec<&String> = settings.notifiers.keys().collect();
+    notifier_names.sort();
+    let mut policies: Option<Vec<String>> = None;
+    let mut errs: Vec<CrateError> = Vec::new();
+    for notifier_name in notifier_names {
+        let bus_name = match settings.notifiers[notifier_name].get_dbus_address() {
+            Some((BusType::System, bus_name)) => bus_name,
+            _ => continue,
+        };
+        let policies = policies.get_or_insert_with(read_system_bus_policies);
+        if !policies
+            .iter()
+            .any(|policy| policy_allows_send(policy, &bus_name))
+        {
+            errs.push(CrateError::Notifier(NotifierError::NotifierDeniedByPolicy(
+                notifier_name.to_owned(),
+                bus_name.to_string(),
+                gen_policy_snippet(&bus_name),
+            )));
+        }
     }
+    errs
 }
 
-// Tell which rules match the given unit name.
-fn get_rules_matching_name<'a>(rules: &[&'a Rule], unit_name: &str) -> Vec<&'a Rule> {
-    rules
+// Read the policy files in `SYSTEM_BUS_POLICY_DIRS`. Files which can't be read are skipped.
+fn read_system_bus_policies() -> Vec<String> {
+    SYSTEM_BUS_POLICY_DIRS
         .iter()
-        .cloned() // &&Rule → &Rule
-        .filter(|rule: &&Rule| rule.expression.matches(unit_name))
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "conf"))
+        .filter_map(|path| fs::read_to_string(path).ok())
         .collect()
 }
 
-// Tell which rules match the given unit state.
-fn get_rules_matching_active_state<'a>(rules: &[&'a Rule], target: ActiveState) -> Vec<&'a Rule> {
-    rules
-        .iter()
-        .cloned() // &&Rule → &Rule
-        .filter(|rule: &&Rule| {
-            rule.active_states
-                .iter()
-                .any(|active_state| *active_state == target)
+// Tell whether the given D-Bus policy file has an `allow` element whose `send_destination` is
+// `bus_name`.
+fn policy_allows_send(policy: &str, bus_name: &str) -> bool {
+    policy
+        .split("<allow")
+        .skip(1)
+        .filter_map(|element| element.split('>').next())
+        .any(|attrs| {
+            attrs.contains(&format!("send_destination=\"{}\"", bus_name))
+                || attrs.contains(&format!("send_destination='{}'", bus_name))
         })
-        .collect()
 }
 
-// Return the value of the ActiveState property.
-fn get_active_state(unit_props: &UnitProps) -> Result<ActiveState, CrateError> {
-    let active_state_str: &str = unit_props
-        .get("ActiveState")
-        .ok_or_else(|| CrateError::PropertiesLacksActiveState)?
-        .0
-        .as_str()
-        .ok_or_else(|| CrateError::CastOrgFreedesktopSystemd1UnitActiveState)?;
-    ActiveState::try_from(active_state_str)
+// Generate a D-Bus policy file which allows anyone to send to `bus_name` on the system bus.
+//
+// The file is meant to be saved as e.g. `/etc/dbus-1/system.d/BUS_NAME.conf`. The notifier must
+// also be allowed to own `bus_name`, which its own policy usually does.
+fn gen_policy_snippet(bus_name: &str) -> String {
+    format!(
+        r#"<!-- /etc/dbus-1/system.d/{0}.conf -->
+<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <policy context="default">
+    <allow send_destination="{0}"/>
+  </policy>
+</busconfig>"#,
+        bus_name
+    )
 }
 
-// Given a bus name foo.bar.Biz1, make path /foo/bar/Biz1.
+// Get a connection to the given bus, connecting if this hasn't yet been attempted.
 //
-// Will return an error if unable to make a string from the contents of `bus_name`, or if the Path
-// object being created does not contain a valid path name.
-fn cast_bus_name_to_path(bus_name: &BusName) -> Result<Path<'static>, CrateError> {
-    let mut path_str = bus_name
-        .as_cstr()
-        .to_str()
-        .map_err(CrateError::CastBusNameToStr)?
-        .replace(".", "/");
-    path_str.insert(0, '/');
-    let path = Path::new(path_str)
-        .map_err(CrateError::CastStrToPath)?
-        .to_owned();
-    Ok(path)
+// If connecting fails, an error is pushed onto `errs`, and `None` is returned from then on.
+fn get_cached_connection<'a>(
+    connections: &'a mut HashMap<&'static str, Option<Connection>>,
+    bus_type: BusType,
+    errs: &mut Vec<CrateError>,
+) -> Option<&'a Connection> {
+    let bus_type_str = settings::encode_bus_type(bus_type);
+    connections
+        .entry(bus_type_str)
+        .or_insert_with(|| match Connection::get_private(bus_type) {
+            Ok(conn) => Some(conn),
+            Err(err) => {
+                errs.push(CrateError::Bus(BusError::ConnectToBusType(
+                    bus_type_str,
+                    err,
+                )));
+                None
+            }
+        })
+        .as_ref()
+}
+
+// Tell whether something owns the given bus name, or can be activated to own it.
+fn has_owner_or_activator(conn: &Connection, bus_name: &BusName) -> Result<bool, CrateError> {
+    let msg = gen_dbus_method_call("NameHasOwner").append1(&bus_name[..]);
+    let has_owner: bool = conn
+        .send_with_reply_and_block(msg, 1000)
+        .and_then(|reply| reply.read1().map_err(DBusError::from))
+        .map_err(|err| CrateError::Bus(BusError::CallOrgFreedesktopDBusNameHasOwner(err)))?;
+    if has_owner {
+        return Ok(true);
+    }
+    let msg = gen_dbus_method_call("ListActivatableNames");
+    let activatable_names: Vec<String> = conn
+        .send_with_reply_and_block(msg, 1000)
+        .and_then(|reply| reply.read1().map_err(DBusError::from))
+        .map_err(|err| {
+            CrateError::Bus(BusError::CallOrgFreedesktopDBusListActivatableNames(err))
+        })?;
+    Ok(activatable_names
+        .iter()
+        .any(|name| name[..] == bus_name[..]))
+}
+
+// Generate a call to a method of the message bus itself, i.e. of `org.freedesktop.DBus`.
+fn gen_dbus_method_call(member_str: &str) -> Message {
+    let name_str = "org.freedesktop.DBus";
+    let path_str = "/org/freedesktop/DBus";
+    Message::method_call(
+        &BusName::new(name_str)
+            .unwrap_or_else(|_| panic!("Failed to create BusName from '{}'", name_str)),
+        &Path::new(path_str)
+            .unwrap_or_else(|_| panic!("Failed to create Path from '{}'", path_str)),
+        &Interface::new(name_str)
+            .unwrap_or_else(|_| panic!("Failed to create Interface from '{}'", name_str)),
+        &Member::new(member_str)
+            .unwrap_or_else(|_| panic!("Failed to create Member from '{}'", member_str)),
+    )
 }
 
 // Tell whether at least one rule matches the given unit name.
 fn rules_match_name(rules: &[&Rule], unit_name: &str) -> bool {
-    !get_rules_matching_name(rules, unit_name).is_empty()
+    rules.iter().any(|rule| rule.expression.matches(unit_name))
 }
 
 // Wrap BUS_NAME_FOR_SYSTEMD.
-fn wrap_bus_name_for_systemd() -> BusName<'static> {
+pub fn wrap_bus_name_for_systemd() -> BusName<'static> {
     BusName::new(BUS_NAME_FOR_SYSTEMD)
-        .expect(&format!("Failed to create BusName from '{}'", BUS_NAME_FOR_SYSTEMD)[..])
+        .unwrap_or_else(|_| panic!("Failed to create BusName from '{}'", BUS_NAME_FOR_SYSTEMD))
 }
 
 // Wrap PATH_FOR_SYSTEMD.
-fn wrap_path_for_systemd() -> Path<'static> {
+pub fn wrap_path_for_systemd() -> Path<'static> {
     Path::new(PATH_FOR_SYSTEMD)
-        .expect(&format!("Failed to create Path from '{}'", PATH_FOR_SYSTEMD)[..])
+        .unwrap_or_else(|_| panic!("Failed to create Path from '{}'", PATH_FOR_SYSTEMD))
 }
 
-fn wrap_interface_for_killjoy_notifier() -> Interface<'static> {
-    let interface_str = "name.jerebear.KilljoyNotifier1";
+fn wrap_interface_for_killjoy_notifier(protocol_version: ProtocolVersion) -> Interface<'static> {
+    let interface_str = match protocol_version {
+        ProtocolVersion::V1 => "name.jerebear.KilljoyNotifier1",
+        ProtocolVersion::V2 => "name.jerebear.KilljoyNotifier2",
+    };
     Interface::new(interface_str)
-        .expect(&format!("Failed to create Interface from '{}'", interface_str)[..])
+        .unwrap_or_else(|_| panic!("Failed to create Interface from '{}'", interface_str))
 }
 
 fn wrap_member_for_notify() -> Member<'static> {
     let member_str = "Notify";
-    Member::new(member_str).expect(&format!("Failed to create Member from '{}'", member_str)[..])
+    Member::new(member_str)
+        .unwrap_or_else(|_| panic!("Failed to create Member from '{}'", member_str))
+}
+
+fn wrap_interface_for_peer() -> Interface<'static> {
+    let interface_str = "org.freedesktop.DBus.Peer";
+    Interface::new(interface_str)
+        .unwrap_or_else(|_| panic!("Failed to create Interface from '{}'", interface_str))
+}
+
+fn wrap_member_for_ping() -> Member<'static> {
+    let member_str = "Ping";
+    Member::new(member_str)
+        .unwrap_or_else(|_| panic!("Failed to create Member from '{}'", member_str))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use crate::settings::{test_utils, Expression};
+    use crate::property::{Comparison, PropertyCondition};
+    use crate::settings::test_utils;
+    use crate::settings::{Plugin, RuleBuilder, SettingsBuilder};
+
+    fn gen_notify_args<'a>(
+        mono_ts: &'a MonotonicTimestamp,
+        real_ts: &'a RealtimeTimestamp,
+        states: &'a [String],
+        labels: &'a HashMap<String, String>,
+        payload: &'a HashMap<String, String>,
+        host: &'a Host,
+    ) -> Notification<'a> {
+        Notification {
+            bus_type: BusType::Session,
+            dependents: None,
+            host,
+            journal_lines: None,
+            labels,
+            message: None,
+            mono_ts,
+            payload,
+            real_ts,
+            rule_name: None,
+            rule_names: &[],
+            severity: Severity::Critical,
+            states,
+            unit_name: "foo.service",
+        }
+    }
+
+    fn gen_host() -> Host {
+        let mut tags = HashMap::new();
+        tags.insert(String::from("site"), String::from("ams"));
+        Host {
+            hostname: String::from("web1"),
+            machine_id: None,
+            tags,
+        }
+    }
+
+    fn gen_notify_msg() -> Message {
+        Message::method_call(
+            &BusName::new("name.jerebear.KilljoyNotifierLogfile1").unwrap(),
+            &Path::new("/name/jerebear/KilljoyNotifierLogfile1").unwrap(),
+            &wrap_interface_for_killjoy_notifier(ProtocolVersion::V1),
+            &wrap_member_for_notify(),
+        )
+    }
+
+    // append_notify_v1_body()
+    #[test]
+    fn test_append_notify_v1_body_v1() {
+        let states = vec![String::from("failed")];
+        let labels = HashMap::new();
+        let payload = HashMap::new();
+        let host = gen_host();
+        let args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Realtime, &args);
+        assert_eq!(msg.get_items().len(), 3);
+        assert_eq!(msg.get1::<u64>(), Some(9));
+
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Monotonic, &args);
+        assert_eq!(msg.get1::<u64>(), Some(7));
+    }
+
+    // append_notify_v1_body()
+    #[test]
+    fn test_append_notify_v1_body_v2() {
+        let states = vec![String::from("failed")];
+        let labels = HashMap::new();
+        let payload = HashMap::new();
+        let host = gen_host();
+        let dependents = vec![String::from("bar.service")];
+        let mut args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        let journal_lines = vec![String::from("Oops.")];
+        args.journal_lines = Some(&journal_lines);
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Realtime, &args);
+        assert_eq!(msg.get_items().len(), 3);
+
+        args.dependents = Some(&dependents);
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Realtime, &args);
+        assert_eq!(msg.get_items().len(), 4);
+
+        args.message = Some(String::from("foo.service failed"));
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Realtime, &args);
+        assert_eq!(msg.get_items().len(), 5);
+
+        args.dependents = None;
+        let msg = append_notify_v1_body(gen_notify_msg(), Clock::Realtime, &args);
+        assert_eq!(msg.get_items().len(), 4);
+    }
+
+    // gen_notify_message()
+    #[test]
+    fn test_gen_notify_message() {
+        let states = vec![String::from("failed")];
+        let labels = HashMap::new();
+        let payload = HashMap::new();
+        let host = gen_host();
+        let args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        let mut notifier = Notifier::new("name.jerebear.KilljoyNotifierLogfile1", BusType::Session)
+            .expect("Failed to create notifier.");
+        let bus_name = BusName::new("name.jerebear.KilljoyNotifierLogfile1").unwrap();
+        let msg =
+            gen_notify_message(&bus_name, &notifier, &args).expect("Failed to generate message.");
+        assert_eq!(
+            msg.interface(),
+            Some(wrap_interface_for_killjoy_notifier(ProtocolVersion::V1))
+        );
+        assert_eq!(msg.get_items().len(), 3);
+
+        notifier.protocol_version = ProtocolVersion::V2;
+        let msg =
+            gen_notify_message(&bus_name, &notifier, &args).expect("Failed to generate message.");
+        assert_eq!(
+            msg.interface(),
+            Some(wrap_interface_for_killjoy_notifier(ProtocolVersion::V2))
+        );
+        assert_eq!(msg.get_items().len(), 1);
+    }
+
+    // send_notifications()
+    #[test]
+    fn test_send_notifications() {
+        let gen_notifier = |script: &str| {
+            let mut notifier = Notifier::new_plugin(Plugin {
+                command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+                options: Map::new(),
+            });
+            notifier.timeout = Duration::from_secs(1);
+            notifier
+        };
+        let failing = gen_notifier("exit 1");
+        let reachable = gen_notifier(r#"echo '{"ok": true}'"#);
+        let slow = gen_notifier(r#"sleep 5; echo '{"ok": true}'"#);
+        let states = vec![String::from("failed")];
+        let labels = HashMap::new();
+        let payload = HashMap::new();
+        let host = gen_host();
+        let gen_args = || {
+            gen_notify_args(
+                &MonotonicTimestamp(7),
+                &RealtimeTimestamp(9),
+                &states,
+                &labels,
+                &payload,
+                &host,
+            )
+        };
+        let deliveries = vec![
+            ("failing", &failing, gen_args()),
+            ("reachable", &reachable, gen_args()),
+            ("slow", &slow, gen_args()),
+        ];
+
+        let results = send_notifications(NotifierPolicy::Fallback, &deliveries);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert!(results[1].is_ok());
+
+        let start = Instant::now();
+        let results = send_notifications(NotifierPolicy::All, &deliveries);
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_err());
+        assert!(results[1].is_ok());
+        match &results[2] {
+            Err(CrateError::Notifier(NotifierError::PluginFailed(notifier, _))) => {
+                assert_eq!(notifier, "slow")
+            }
+            _ => panic!("expected PluginFailed; the plugin is slower than its timeout"),
+        }
+    }
+
+    // gen_notify_v2_body()
+    #[test]
+    fn test_gen_notify_v2_body() {
+        let states = vec![String::from("failed"), String::from("active")];
+        let journal_lines = vec![String::from("Oops.")];
+        let mut labels = HashMap::new();
+        labels.insert(String::from("team"), String::from("db"));
+        let mut payload = HashMap::new();
+        payload.insert(String::from("runbook"), String::from("https://example.com"));
+        let host = gen_host();
+        let mut args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        let rule_names = vec![String::from("database down"), String::from("catch-all")];
+        args.journal_lines = Some(&journal_lines);
+        args.rule_name = Some("database down");
+        args.rule_names = &rule_names;
+        let body = gen_notify_v2_body(&args);
+        assert_eq!(body["rule"].0.as_str(), Some("database down"));
+        assert_eq!(cast_ref_arg_to_strings(&body["rules"].0), Some(rule_names));
+        assert_eq!(body["severity"].0.as_str(), Some("critical"));
+        assert_eq!(body["urgency"].0.as_u64(), Some(2));
+        assert!(body["labels"].0.as_iter().is_some());
+        assert!(body["payload"].0.as_iter().is_some());
+        assert_eq!(body["unit"].0.as_str(), Some("foo.service"));
+        assert_eq!(body["new_state"].0.as_str(), Some("failed"));
+        assert_eq!(body["old_state"].0.as_str(), Some("active"));
+        assert_eq!(body["timestamp_monotonic"].0.as_u64(), Some(7));
+        assert_eq!(body["timestamp_realtime"].0.as_u64(), Some(9));
+        assert_eq!(body["bus"].0.as_str(), Some("session"));
+        assert_eq!(body["hostname"].0.as_str(), Some("web1"));
+        assert!(body["tags"].0.as_iter().is_some());
+        assert!(!body.contains_key("machine_id"));
+        assert_eq!(
+            cast_ref_arg_to_strings(&body["journal_lines"].0),
+            Some(journal_lines)
+        );
+        assert!(!body.contains_key("dependents"));
+        assert!(!body.contains_key("message"));
+        assert!(!body.contains_key("template"));
+        assert!(!body.contains_key("instance"));
+    }
+
+    // gen_notify_json_body()
+    #[test]
+    fn test_gen_notify_json_body() {
+        let states = vec![String::from("failed"), String::from("active")];
+        let mut labels = HashMap::new();
+        labels.insert(String::from("team"), String::from("db"));
+        let mut payload = HashMap::new();
+        payload.insert(String::from("runbook"), String::from("https://example.com"));
+        let host = gen_host();
+        let mut args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        args.message = Some(String::from("foo.service failed"));
+        let body = gen_notify_json_body(&args);
+        assert_eq!(body["unit"], "foo.service");
+        assert_eq!(body["states"], serde_json::json!(["failed", "active"]));
+        assert_eq!(body["new_state"], "failed");
+        assert_eq!(body["old_state"], "active");
+        assert_eq!(body["timestamp_realtime"], 9);
+        assert_eq!(body["labels"]["team"], "db");
+        assert_eq!(body["payload"]["runbook"], "https://example.com");
+        assert_eq!(body["hostname"], "web1");
+        assert_eq!(body["tags"]["site"], "ams");
+        assert_eq!(body["severity"], "critical");
+        assert_eq!(body["urgency"], 2);
+        assert_eq!(body["message"], "foo.service failed");
+        assert!(body.get("rule").is_none());
+        assert_eq!(body["rules"], serde_json::json!([]));
+        assert!(body.get("dependents").is_none());
+    }
+
+    // gen_notify_v2_body()
+    #[test]
+    fn test_gen_notify_v2_body_instance() {
+        let states = vec![String::from("failed")];
+        let labels = HashMap::new();
+        let payload = HashMap::new();
+        let host = gen_host();
+        let mut args = gen_notify_args(
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(9),
+            &states,
+            &labels,
+            &payload,
+            &host,
+        );
+        args.unit_name = "getty@tty1.service";
+        let body = gen_notify_v2_body(&args);
+        assert_eq!(body["unit"].0.as_str(), Some("getty@tty1.service"));
+        assert_eq!(body["template"].0.as_str(), Some("getty@.service"));
+        assert_eq!(body["instance"].0.as_str(), Some("tty1"));
+    }
+
+    // get_template_values()
+    #[test]
+    fn test_get_template_values() {
+        let body_states = vec![String::from("failed"), String::from("active")];
+        let values = get_template_values(
+            &gen_host(),
+            "foo.service",
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(0),
+            &body_states,
+        );
+        assert_eq!(values["unit"], "foo.service");
+        assert_eq!(values["new_state"], "failed");
+        assert_eq!(values["old_state"], "active");
+        assert_eq!(values["timestamp"], "0");
+        assert_eq!(values["timestamp_iso8601"], "1970-01-01T00:00:00Z");
+        assert_eq!(values["timestamp_monotonic"], "7");
+        assert_eq!(values["template"], "");
+        assert_eq!(values["instance"], "");
+        assert_eq!(values["hostname"], "web1");
+        assert_eq!(values["machine_id"], "");
+        assert_eq!(values["tags.site"], "ams");
+        assert!(!values.contains_key("description"));
+    }
+
+    // gen_get_all_call()
+    #[test]
+    fn test_gen_get_all_call() {
+        let unit_path = Path::new("/org/freedesktop/systemd1/unit/foo_2eservice").unwrap();
+        let msg = gen_get_all_call(&unit_path);
+        assert_eq!(msg.member(), Some(Member::new("GetAll").unwrap()));
+        assert_eq!(msg.path(), Some(unit_path));
+        assert_eq!(msg.read1::<&str>().unwrap(), INTERFACE_FOR_SYSTEMD_UNIT);
+    }
+
+    // survive_unit_error()
+    #[test]
+    fn test_survive_unit_error() {
+        assert!(survive_unit_error("UnitNew", Ok(())).is_ok());
+        let result =
+            survive_unit_error("UnitNew", Err(CrateError::Bus(BusError::MessageLacksPath)));
+        assert!(result.is_ok());
+        let err = DBusError::new_custom("org.freedesktop.systemd1.NoSuchUnit", "gone");
+        let result = survive_unit_error(
+            "UnitRemoved",
+            Err(CrateError::Bus(BusError::RemoveSignalMatch(
+                "foo".to_string(),
+                err,
+            ))),
+        );
+        assert!(result.is_ok());
+        let err = DBusError::new_custom("org.freedesktop.DBus.Error.Disconnected", "gone");
+        let result = survive_unit_error(
+            "PropertiesChanged",
+            Err(CrateError::Bus(BusError::GetOrgFreedesktopSystemd1UnitId(
+                err,
+            ))),
+        );
+        assert!(result.is_err());
+    }
+
+    // is_transient()
+    #[test]
+    fn test_is_transient() {
+        let err = DBusError::new_custom("org.freedesktop.DBus.Error.NoReply", "too slow");
+        assert!(is_transient(&CrateError::Bus(
+            BusError::CallOrgFreedesktopSystemd1ManagerListUnits(err)
+        )));
+        let err = DBusError::new_custom("org.freedesktop.systemd1.NoSuchUnit", "gone");
+        assert!(!is_transient(&CrateError::Bus(
+            BusError::CallOrgFreedesktopSystemd1ManagerGetUnit(err)
+        )));
+        let err = DBusError::new_custom("org.freedesktop.DBus.Error.NoReply", "too slow");
+        assert!(!is_transient(&CrateError::Bus(
+            BusError::CallOrgFreedesktopSystemd1ManagerSubscribe(err)
+        )));
+    }
+
+    // is_unit_files_change()
+    #[test]
+    fn test_is_unit_files_change() {
+        let path = wrap_path_for_systemd();
+        let unit_files_changed = UnitFilesChanged {}.to_emit_message(&path);
+        let reloading_started = Reloading { arg0: true }.to_emit_message(&path);
+        let reloading_finished = Reloading { arg0: false }.to_emit_message(&path);
+        let unit_new = UnitNew {
+            arg0: "foo.service".to_owned(),
+            arg1: path.clone(),
+        }
+        .to_emit_message(&path);
+        assert!(is_unit_files_change(&unit_files_changed));
+        assert!(!is_unit_files_change(&reloading_started));
+        assert!(is_unit_files_change(&reloading_finished));
+        assert!(!is_unit_files_change(&unit_new));
+    }
+
+    // get_portable_image_states()
+    #[test]
+    fn test_get_portable_image_states() {
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        rules[0].expression = Expression::PortableImage("foo".to_owned());
+        rules[1].expression = Expression::PortableImage("bar".to_owned());
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+        let listed = vec![
+            ("foo".to_owned(), "running".to_owned()),
+            ("baz".to_owned(), "attached".to_owned()),
+        ];
+        let mut previous = HashMap::new();
+        previous.insert("old".to_owned(), PortableState::Attached);
+
+        let images = get_portable_image_states(listed, &previous, &borrowed_rules);
+        assert_eq!(images.len(), 3);
+        assert_eq!(images["foo"], PortableState::Running);
+        assert_eq!(images["bar"], PortableState::Detached);
+        assert_eq!(images["old"], PortableState::Detached);
+    }
+
+    // get_unit_file_states()
+    #[test]
+    fn test_get_unit_file_states() {
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        rules[0].expression = Expression::UnitName("foo.service".to_owned());
+        rules[1].expression = Expression::UnitType(".timer".to_owned());
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+        let listed = vec![
+            (
+                "/etc/systemd/system/bar.timer".to_owned(),
+                "enabled".to_owned(),
+            ),
+            (
+                "/etc/systemd/system/baz.service".to_owned(),
+                "enabled".to_owned(),
+            ),
+            (
+                "/etc/systemd/system/qux.timer".to_owned(),
+                "no-such-state".to_owned(),
+            ),
+        ];
+        let mut previous = HashMap::new();
+        previous.insert("old.timer".to_owned(), UnitFileState::Masked);
+
+        let unit_files = get_unit_file_states(listed, &previous, &borrowed_rules);
+        assert_eq!(unit_files.len(), 3);
+        assert_eq!(unit_files["bar.timer"], UnitFileState::Enabled);
+        assert_eq!(unit_files["foo.service"], UnitFileState::Removed);
+        assert_eq!(unit_files["old.timer"], UnitFileState::Removed);
+    }
+
+    // get_template_values()
+    #[test]
+    fn test_get_template_values_instance() {
+        let body_states = vec![String::from("failed")];
+        let values = get_template_values(
+            &gen_host(),
+            "getty@tty1.service",
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(0),
+            &body_states,
+        );
+        assert_eq!(values["template"], "getty@.service");
+        assert_eq!(values["instance"], "tty1");
+    }
+
+    // add_rule_template_values()
+    #[test]
+    fn test_add_rule_template_values() {
+        let mut rule = test_utils::gen_session_rule();
+        rule.name = Some(String::from("database down"));
+        rule.labels.insert(String::from("team"), String::from("db"));
+        let mut values = HashMap::new();
+        add_rule_template_values(&mut values, &rule, &rule.labels, Severity::Warning);
+        assert_eq!(values["rule"], "database down");
+        assert_eq!(values["severity"], "warning");
+        assert_eq!(values["labels.team"], "db");
+    }
+
+    // get_template_values()
+    #[test]
+    fn test_get_template_values_no_old_state() {
+        let body_states = vec![String::from("active")];
+        let values = get_template_values(
+            &gen_host(),
+            "foo.service",
+            &MonotonicTimestamp(7),
+            &RealtimeTimestamp(0),
+            &body_states,
+        );
+        assert_eq!(values["old_state"], "");
+    }
 
     #[test]
     fn test_cast_bus_name_to_path() {
         let bus_name = BusName::new(BUS_NAME_FOR_SYSTEMD)
-            .expect(&format!("Failed to create BusName from {}", BUS_NAME_FOR_SYSTEMD)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create BusName from {}", BUS_NAME_FOR_SYSTEMD));
         let path = cast_bus_name_to_path(&bus_name).expect("Failed to cast bus name to path.");
         let path_str = path
             .as_cstr()
             .to_str()
-            .expect(&format!("Failed to create string from {}", path));
+            .unwrap_or_else(|_| panic!("Failed to create string from {}", path));
         assert_eq!(path_str, "/org/freedesktop/systemd1");
     }
 
     // Let the unit name match zero of two rules.
     #[test]
     fn test_match_rules_and_names_v1() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].expression = Expression::UnitName("foo.mount".to_owned());
         rules[1].expression = Expression::UnitType(".mount".to_owned());
         let borrowed_rules: Vec<&Rule> = rules.iter().collect();
@@ -597,7 +5006,7 @@ mod tests {
     // Let the unit name match one of two rules.
     #[test]
     fn test_match_rules_and_names_v2() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].expression = Expression::UnitName("foo.mount".to_owned());
         rules[1].expression = Expression::UnitType(".mount".to_owned());
         let borrowed_rules: Vec<&Rule> = rules.iter().collect();
@@ -613,7 +5022,7 @@ mod tests {
     // Let the unit name match two of two rules.
     #[test]
     fn test_match_rules_and_names_v3() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].expression = Expression::UnitName("foo.mount".to_owned());
         rules[1].expression = Expression::UnitType(".mount".to_owned());
         let borrowed_rules: Vec<&Rule> = rules.iter().collect();
@@ -629,7 +5038,7 @@ mod tests {
     // Let the unit ActiveState match zero of two rules.
     #[test]
     fn test_match_rules_and_active_state_v1() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].active_states.insert(ActiveState::Activating);
         rules[0].active_states.insert(ActiveState::Active);
         rules[1].active_states.insert(ActiveState::Active);
@@ -637,14 +5046,14 @@ mod tests {
 
         let active_state = ActiveState::Inactive;
 
-        let matching_rules = get_rules_matching_active_state(&borrowed_rules, active_state);
+        let matching_rules = get_rules_matching_active_state(&borrowed_rules, &active_state);
         assert_eq!(matching_rules.len(), 0);
     }
 
     // Let the unit ActiveState match one of two rules.
     #[test]
     fn test_match_rules_and_active_state_v2() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].active_states.insert(ActiveState::Activating);
         rules[0].active_states.insert(ActiveState::Active);
         rules[1].active_states.insert(ActiveState::Active);
@@ -652,22 +5061,136 @@ mod tests {
 
         let active_state = ActiveState::Activating;
 
-        let matching_rules = get_rules_matching_active_state(&borrowed_rules, active_state);
+        let matching_rules = get_rules_matching_active_state(&borrowed_rules, &active_state);
         assert_eq!(matching_rules.len(), 1);
     }
 
     // Let the unit ActiveState match two of two rules.
     #[test]
     fn test_match_rules_and_active_state_v3() {
-        let mut rules = vec![test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
         rules[0].active_states.insert(ActiveState::Activating);
         rules[0].active_states.insert(ActiveState::Active);
         rules[1].active_states.insert(ActiveState::Active);
         let borrowed_rules: Vec<&Rule> = rules.iter().collect();
         let active_state = ActiveState::Active;
 
-        let matching_rules = get_rules_matching_active_state(&borrowed_rules, active_state);
+        let matching_rules = get_rules_matching_active_state(&borrowed_rules, &active_state);
+        assert_eq!(matching_rules.len(), 2);
+    }
+
+    // Let an unsuccessful inactive unit match the rule which treats it as failed.
+    #[test]
+    fn test_match_rules_and_result() {
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        rules[0].active_states.insert(ActiveState::Failed);
+        rules[0].treat_unsuccessful_inactive_as_failed = true;
+        rules[1].active_states.insert(ActiveState::Inactive);
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+
+        let (new_state, matching_rules) =
+            get_rules_matching_result(&borrowed_rules, &ActiveState::Inactive, true);
+        assert_eq!(new_state, ActiveState::Failed);
+        assert_eq!(matching_rules.len(), 2);
+
+        let (new_state, matching_rules) =
+            get_rules_matching_result(&borrowed_rules, &ActiveState::Inactive, false);
+        assert_eq!(new_state, ActiveState::Inactive);
+        assert_eq!(matching_rules.len(), 1);
+        assert!(!matching_rules[0].treat_unsuccessful_inactive_as_failed);
+
+        let (new_state, matching_rules) =
+            get_rules_matching_result(&borrowed_rules, &ActiveState::Active, true);
+        assert_eq!(new_state, ActiveState::Active);
+        assert!(matching_rules.is_empty());
+    }
+
+    // get_rules_to_fire()
+    #[test]
+    fn test_get_rules_to_fire() {
+        let mut rules = [
+            test_utils::gen_system_rule(),
+            test_utils::gen_system_rule(),
+            test_utils::gen_system_rule(),
+        ];
+        rules[0].name = Some("catch-all".to_string());
+        rules[1].name = Some("specific".to_string());
+        rules[1].priority = 10;
+        rules[2].name = Some("also specific".to_string());
+        rules[2].priority = 10;
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+
+        let firing_rules = get_rules_to_fire(&borrowed_rules, RuleEvaluation::All);
+        assert_eq!(firing_rules.len(), 3);
+
+        let firing_rules = get_rules_to_fire(&borrowed_rules, RuleEvaluation::FirstMatch);
+        assert_eq!(firing_rules.len(), 1);
+        assert_eq!(firing_rules[0].name.as_deref(), Some("specific"));
+
+        let firing_rules = get_rules_to_fire(&[], RuleEvaluation::FirstMatch);
+        assert!(firing_rules.is_empty());
+    }
+
+    // Let the unit LoadState match one of two rules.
+    #[test]
+    fn test_match_rules_and_load_state() {
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        rules[0].load_states.insert(LoadState::NotFound);
+        rules[0].load_states.insert(LoadState::Error);
+        rules[1].active_states.insert(ActiveState::Failed);
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+
+        let matching_rules = get_rules_matching_load_state(&borrowed_rules, LoadState::Error);
+        assert_eq!(matching_rules.len(), 1);
+
+        let matching_rules = get_rules_matching_load_state(&borrowed_rules, LoadState::Loaded);
+        assert_eq!(matching_rules.len(), 0);
+    }
+
+    // Let the unit SubState match one of two rules.
+    #[test]
+    fn test_match_rules_and_sub_state() {
+        let mut rules = [test_utils::gen_system_rule(), test_utils::gen_system_rule()];
+        rules[0].sub_states.insert("exited".to_string());
+        let borrowed_rules: Vec<&Rule> = rules.iter().collect();
+
+        let matching_rules = get_rules_matching_sub_state(&borrowed_rules, Some("exited"));
         assert_eq!(matching_rules.len(), 2);
+
+        let matching_rules = get_rules_matching_sub_state(&borrowed_rules, Some("running"));
+        assert_eq!(matching_rules.len(), 1);
+        assert!(matching_rules[0].sub_states.is_empty());
+
+        let matching_rules = get_rules_matching_sub_state(&borrowed_rules, None);
+        assert_eq!(matching_rules.len(), 1);
+    }
+
+    // Get the LoadState property from a unit's properties.
+    #[test]
+    fn test_get_load_state() {
+        let mut unit_props: UnitProps = HashMap::new();
+        assert_eq!(get_load_state(&unit_props).ok(), Some(None));
+
+        unit_props.insert(
+            "LoadState".to_string(),
+            Variant(Box::new("not-found".to_string())),
+        );
+        assert_eq!(
+            get_load_state(&unit_props).ok(),
+            Some(Some(LoadState::NotFound))
+        );
+
+        unit_props.insert("LoadState".to_string(), Variant(Box::new(5u32)));
+        get_load_state(&unit_props).expect_err("LoadState should not be castable to a string.");
+    }
+
+    // Cast an array of strings, and some things that aren't.
+    #[test]
+    fn test_cast_ref_arg_to_strings() {
+        let strings = vec!["a.target".to_string(), "b.service".to_string()];
+        assert_eq!(cast_ref_arg_to_strings(&strings), Some(strings.clone()));
+        assert_eq!(cast_ref_arg_to_strings(&vec![1u32, 2u32]), None);
+        assert_eq!(cast_ref_arg_to_strings(&"a.target".to_string()), None);
     }
 
     #[test]
@@ -682,7 +5205,8 @@ mod tests {
 
     #[test]
     fn test_wrap_interface_for_killjoy_notifier() {
-        wrap_interface_for_killjoy_notifier();
+        wrap_interface_for_killjoy_notifier(ProtocolVersion::V1);
+        wrap_interface_for_killjoy_notifier(ProtocolVersion::V2);
     }
 
     #[test]
@@ -692,11 +5216,442 @@ mod tests {
 
     #[test]
     fn test_interface_for_systemd_unit() {
-        Interface::new(INTERFACE_FOR_SYSTEMD_UNIT).expect(
-            &format!(
+        Interface::new(INTERFACE_FOR_SYSTEMD_UNIT).unwrap_or_else(|_| {
+            panic!(
                 "Failed to create Interface from {}",
                 INTERFACE_FOR_SYSTEMD_UNIT
-            )[..],
+            )
+        });
+    }
+
+    // get_property_triggers()
+    #[test]
+    fn test_get_property_triggers() {
+        let gen_condition = |property: &str, comparison: Comparison| PropertyCondition {
+            comparison,
+            duration: Duration::from_secs(0),
+            interface: None,
+            property: property.to_owned(),
+        };
+        let mut rule = test_utils::gen_session_rule();
+        rule.expression = Expression::UnitName("foo.service".to_owned());
+        rule.properties = vec![
+            gen_condition("MemoryCurrent", Comparison::GreaterThan(1000)),
+            gen_condition("TasksCurrent", Comparison::Changed),
+            gen_condition("NRestarts", Comparison::GreaterThan(0)),
+        ];
+        let rules = vec![&rule];
+        let interface = "org.freedesktop.systemd1.Service";
+
+        let mut unit_props: UnitProps = HashMap::new();
+        unit_props.insert("MemoryCurrent".to_owned(), Variant(Box::new(2000u64)));
+        unit_props.insert("TasksCurrent".to_owned(), Variant(Box::new(4u64)));
+        let now = Instant::now();
+        let mut history = PropertyHistory::default();
+        history.record(
+            interface,
+            &rule.properties[0],
+            PropertyValue::Number(500),
+            now,
+        );
+        history.record(
+            interface,
+            &rule.properties[1],
+            PropertyValue::Number(4),
+            now,
+        );
+
+        let triggers =
+            get_property_triggers(&rules, "foo.service", interface, &unit_props, &history, now);
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(
+            triggers[0].1,
+            vec![
+                "MemoryCurrent=2000".to_owned(),
+                "MemoryCurrent=500".to_owned()
+            ]
+        );
+
+        let triggers = get_property_triggers(
+            &rules,
+            "foo.service",
+            "org.freedesktop.systemd1.Unit",
+            &unit_props,
+            &history,
+            now,
         );
+        assert!(triggers.is_empty());
+    }
+
+    // get_boot_summary_states()
+    #[test]
+    fn test_get_boot_summary_states() {
+        let mut rule = test_utils::gen_system_rule();
+        rule.expression = Expression::UnitType(".service".to_owned());
+        let rules = vec![&rule];
+        let units = vec![
+            ("foo.service".to_owned(), "active".to_owned()),
+            ("bar.service".to_owned(), "inactive".to_owned()),
+            ("baz.timer".to_owned(), "inactive".to_owned()),
+            ("qux.timer".to_owned(), "failed".to_owned()),
+        ];
+        assert_eq!(
+            get_boot_summary_states(units, &rules),
+            vec![
+                "bar.service=inactive".to_owned(),
+                "qux.timer=failed".to_owned()
+            ]
+        );
+    }
+
+    // check_notifier_buses_with()
+    #[test]
+    fn test_check_notifier_buses_with() {
+        let session = Notifier::new("name.jerebear.KilljoyNotifierLogfile1", BusType::Session)
+            .expect("Failed to create notifier.");
+        let system = Notifier::new("name.jerebear.KilljoyNotifierPager1", BusType::System)
+            .expect("Failed to create notifier.");
+        let settings = SettingsBuilder::new()
+            .notifier("logfile", session)
+            .notifier("pager", system)
+            .rule(
+                RuleBuilder::new(BusType::System)
+                    .name("foo")
+                    .watch_regex("^foo\\.service$")
+                    .on_states(vec![ActiveState::Failed])
+                    .notify("logfile")
+                    .notify("pager"),
+            )
+            .rule(
+                RuleBuilder::new(BusType::System)
+                    .name("bar")
+                    .watch_regex("^bar\\.service$")
+                    .on_states(vec![ActiveState::Failed])
+                    .notify("logfile"),
+            )
+            .build()
+            .expect("Failed to build settings.");
+        assert!(check_notifier_buses_with(&settings, true).is_empty());
+
+        let errs = check_notifier_buses_with(&settings, false);
+        assert_eq!(errs.len(), 1);
+        match &errs[0] {
+            CrateError::Settings(SettingsError::SessionNotifierWithoutSessionBus(
+                notifier_name,
+                user,
+            )) => {
+                assert_eq!(notifier_name, "logfile");
+                assert_eq!(user, "Rule \"foo\"");
+            }
+            err => panic!("Unexpected error: {}", err),
+        }
+    }
+
+    // policy_allows_send()
+    #[test]
+    fn test_policy_allows_send() {
+        let bus_name = "name.jerebear.KilljoyNotifierNotification1";
+        assert!(policy_allows_send(&gen_policy_snippet(bus_name), bus_name));
+        assert!(policy_allows_send(
+            &format!(
+                "<policy user='root'><allow send_destination='{}'/></policy>",
+                bus_name
+            ),
+            bus_name
+        ));
+        assert!(!policy_allows_send(
+            &format!(
+                "<policy context=\"default\"><deny send_destination=\"{}\"/>",
+                bus_name
+            ),
+            bus_name
+        ));
+        assert!(!policy_allows_send(
+            &gen_policy_snippet("name.jerebear.Other1"),
+            bus_name
+        ));
+    }
+
+    // get_slice_interface()
+    #[test]
+    fn test_get_slice_interface() {
+        assert_eq!(
+            get_slice_interface("user@1000.service"),
+            Some("org.freedesktop.systemd1.Service")
+        );
+        assert_eq!(
+            get_slice_interface("session-2.scope"),
+            Some("org.freedesktop.systemd1.Scope")
+        );
+        assert_eq!(get_slice_interface("multi-user.target"), None);
+        assert_eq!(get_slice_interface("foo"), None);
+    }
+
+    // gen_digest_entry()
+    #[test]
+    fn test_gen_digest_entry() {
+        let real_ts = RealtimeTimestamp(0);
+        let body_states = vec![String::from("failed"), String::from("active")];
+        assert_eq!(
+            gen_digest_entry("foo.service", &real_ts, &body_states),
+            format!("{} foo.service: active -> failed", real_ts.to_iso8601())
+        );
+        assert_eq!(
+            gen_digest_entry("foo.service", &real_ts, &body_states[..1]),
+            format!("{} foo.service: failed", real_ts.to_iso8601())
+        );
+    }
+
+    // Run a bus watcher on the session bus of the given simulated systemd, with a rule which
+    // watches foo.service for failures. Return its events, the handle which stops it, and its
+    // thread.
+    #[cfg(feature = "mock-bus")]
+    fn spawn_mock_watcher(
+        mock: &crate::mock::MockSystemd,
+    ) -> (
+        std::sync::mpsc::Receiver<Result<Event, CrateError>>,
+        StopHandle,
+        thread::JoinHandle<Result<(), CrateError>>,
+    ) {
+        let settings = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_name("foo.service")
+                    .on_states(vec![ActiveState::Failed]),
+            )
+            .build()
+            .expect("Failed to build settings.");
+        spawn_mock_watcher_with(mock, settings)
+    }
+
+    // Like `spawn_mock_watcher`, but with the given settings.
+    #[cfg(feature = "mock-bus")]
+    fn spawn_mock_watcher_with(
+        mock: &crate::mock::MockSystemd,
+        settings: Settings,
+    ) -> (
+        std::sync::mpsc::Receiver<Result<Event, CrateError>>,
+        StopHandle,
+        thread::JoinHandle<Result<(), CrateError>>,
+    ) {
+        let options = RunOptions {
+            loop_timeout: 10,
+            ..RunOptions::default()
+        };
+        let stop = options.stop.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mock = mock.clone();
+        let handle = thread::spawn(move || {
+            BusWatcher::with_backend(mock, BusType::Session, settings, options, Some(sender))?.run()
+        });
+        (receiver, stop, handle)
+    }
+
+    // Wait up to five seconds for `condition` to hold.
+    #[cfg(feature = "mock-bus")]
+    fn wait_for<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "Timed out waiting for the bus watcher."
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // BusWatcher::handle_message(), for a PropertiesChanged signal on one of the
+    // `properties_changed_interfaces`.
+    #[cfg(feature = "mock-bus")]
+    #[test]
+    fn test_handle_message_mock_configured_interface() {
+        use crate::systemd::SystemdBackend;
+
+        let interface = "org.freedesktop.systemd1.Service";
+        let mock = crate::mock::MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+        let mut settings = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_name("foo.service")
+                    .on_property("NRestarts", "changed", None),
+            )
+            .build()
+            .expect("Failed to build settings.");
+        settings.properties_changed_interfaces = vec![interface.to_string()];
+        let watcher = BusWatcher::with_backend(
+            mock.clone(),
+            BusType::Session,
+            settings,
+            RunOptions::default(),
+            None,
+        )
+        .expect("Failed to create bus watcher.");
+        let mut unit_states = UnitStates::default();
+        let unit_path = Path::new(unit::get_unit_path("foo.service"))
+            .expect("Failed to create Path from unit path.");
+        watcher
+            .subscribe_properties_changed("foo.service", &unit_path, &mut unit_states)
+            .expect("Failed to subscribe to PropertiesChanged.");
+
+        mock.set_property(
+            "foo.service",
+            interface,
+            "NRestarts",
+            crate::mock::MockValue::U32(3),
+        );
+        let msg = std::iter::from_fn(|| mock.pop_message(0))
+            .find(|msg| {
+                msg.member()
+                    .map_or(false, |member| &*member == "PropertiesChanged")
+            })
+            .expect("No PropertiesChanged signal arrived.");
+        watcher
+            .handle_message(&msg, &mut unit_states, &mut Instant::now())
+            .expect("Failed to handle PropertiesChanged.");
+        let value = unit_states.properties["foo.service"].get_value(interface, "NRestarts");
+        assert_eq!(value, Some(&PropertyValue::Number(3)));
+    }
+
+    // BusWatcher::run(), for a unit which is loaded at startup and then fails.
+    #[cfg(feature = "mock-bus")]
+    #[test]
+    fn test_run_mock_unit_fails() {
+        let mock = crate::mock::MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+        mock.add_unit("bar.service", ActiveState::Active);
+        let (events, stop, handle) = spawn_mock_watcher(&mock);
+        wait_for(|| mock.is_watched("foo.service"));
+        assert!(!mock.is_watched("bar.service"));
+
+        mock.set_active_state("bar.service", ActiveState::Failed);
+        mock.set_active_state("foo.service", ActiveState::Failed);
+        let event = events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("No event arrived.")
+            .expect("The bus watcher failed.");
+        assert_eq!(event.unit_name, "foo.service");
+        assert_eq!(event.old_state, Some(ActiveState::Active));
+        assert_eq!(event.new_state, ActiveState::Failed);
+
+        stop.stop();
+        handle
+            .join()
+            .expect("The bus watcher panicked.")
+            .expect("The bus watcher failed.");
+        assert!(events.try_recv().is_err());
+    }
+
+    // BusWatcher::run(), for a unit which is loaded and unloaded while the bus watcher runs.
+    #[cfg(feature = "mock-bus")]
+    #[test]
+    fn test_run_mock_unit_new_removed() {
+        let mock = crate::mock::MockSystemd::new();
+        let (_events, stop, handle) = spawn_mock_watcher(&mock);
+        mock.add_unit("foo.service", ActiveState::Active);
+        wait_for(|| mock.is_watched("foo.service"));
+        mock.remove_unit("foo.service");
+        wait_for(|| !mock.is_watched("foo.service"));
+
+        stop.stop();
+        handle
+            .join()
+            .expect("The bus watcher panicked.")
+            .expect("The bus watcher failed.");
+    }
+
+    // BusWatcher::run(), for more units than the watch limit allows, across several scans.
+    #[cfg(feature = "mock-bus")]
+    #[test]
+    fn test_run_mock_watch_limit() {
+        let mock = crate::mock::MockSystemd::new();
+        mock.add_unit("a.service", ActiveState::Active);
+        mock.add_unit("b.service", ActiveState::Active);
+        let settings = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_type(".service")
+                    .on_states(vec![ActiveState::Failed]),
+            )
+            .reconcile_interval(Some(Duration::from_millis(10)))
+            .watch_limit(2, Vec::<String>::new())
+            .build()
+            .expect("Failed to build settings.");
+        let (events, stop, handle) = spawn_mock_watcher_with(&mock, settings);
+        wait_for(|| mock.is_watched("a.service") && mock.is_watched("b.service"));
+
+        // UnitNew makes room for c.service by forgetting whichever of the others was updated
+        // least recently, and the scans which follow don't trade one idle unit for another.
+        mock.add_unit("c.service", ActiveState::Active);
+        wait_for(|| {
+            mock.is_watched("c.service")
+                && !(mock.is_watched("a.service") && mock.is_watched("b.service"))
+        });
+        let forgotten = if mock.is_watched("a.service") {
+            "b.service"
+        } else {
+            "a.service"
+        };
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(20));
+            assert!(mock.is_watched("c.service"));
+            assert!(!mock.is_watched(forgotten));
+        }
+
+        // A forgotten unit which fails is picked up by the next scan, at the expense of another.
+        mock.set_active_state(forgotten, ActiveState::Failed);
+        let event = events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("No event arrived.")
+            .expect("The bus watcher failed.");
+        assert_eq!(event.unit_name, forgotten);
+        assert!(mock.is_watched(forgotten));
+
+        stop.stop();
+        handle
+            .join()
+            .expect("The bus watcher panicked.")
+            .expect("The bus watcher failed.");
+    }
+
+    // BusWatcher::run(), for a rule which ignores transient units.
+    #[cfg(feature = "mock-bus")]
+    #[test]
+    fn test_run_mock_ignore_transient() {
+        let mock = crate::mock::MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+        mock.add_unit("run-r1.service", ActiveState::Active);
+        mock.set_property(
+            "run-r1.service",
+            INTERFACE_FOR_SYSTEMD_UNIT,
+            "Transient",
+            crate::mock::MockValue::Bool(true),
+        );
+        let settings = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_type(".service")
+                    .on_states(vec![ActiveState::Failed])
+                    .ignore_transient(true),
+            )
+            .build()
+            .expect("Failed to build settings.");
+        let (events, stop, handle) = spawn_mock_watcher_with(&mock, settings);
+        wait_for(|| mock.is_watched("foo.service") && mock.is_watched("run-r1.service"));
+
+        mock.set_active_state("run-r1.service", ActiveState::Failed);
+        mock.set_active_state("foo.service", ActiveState::Failed);
+        let event = events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("No event arrived.")
+            .expect("The bus watcher failed.");
+        assert_eq!(event.unit_name, "foo.service");
+
+        stop.stop();
+        handle
+            .join()
+            .expect("The bus watcher panicked.")
+            .expect("The bus watcher failed.");
+        assert!(events.try_recv().is_err());
     }
 }
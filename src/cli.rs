@@ -1,11 +1,28 @@
 // Logic for interacting with the CLI.
 
-use clap;
-use clap::{Command, Arg, ArgAction, ArgMatches, value_parser};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use regex::Regex;
 
+// The arguments which are hidden from --help, as they're only meant for development.
+const DEV_ARGS: [&str; 2] = ["loop-once", "loop-timeout"];
+
 // Consume CLI arguments, parse them, validate them, and return the digested result.
 pub fn get_cli_args() -> ArgMatches {
+    build_cli().get_matches()
+}
+
+// Build the CLI definition used for man pages.
+//
+// It's the same as the one used to parse arguments, except that development arguments are shown,
+// so that the man pages are complete.
+pub fn build_man_cli() -> Command {
+    DEV_ARGS.iter().fold(build_cli(), |cmd, arg| {
+        cmd.mut_arg(arg, |arg| arg.hide(false))
+    })
+}
+
+// Build the CLI definition used to parse arguments.
+pub fn build_cli() -> Command {
     let help_messages = HelpMessagesFactory::new().gen_help_messages();
     Command::new("killjoy")
         .version(clap::crate_version!())
@@ -24,7 +41,181 @@ pub fn get_cli_args() -> ArgMatches {
                 .default_value("10000")
                 .help("FOR DEVELOPMENT ONLY! The main loop message wait timeout, in ms.")
                 .hide(true),
+            Arg::new("oneshot")
+                .long("oneshot")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("loop-once")
+                .help("Handle units' current states and any pending digests, then exit, e.g. from a timer."),
+            Arg::new("low-power")
+                .long("low-power")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("oneshot")
+                .help("Sleep until systemd has news or work is due, and skip periodic reconciliation."),
+            Arg::new("single-thread")
+                .long("single-thread")
+                .action(ArgAction::SetTrue)
+                .help("Watch every bus from one thread, rather than one thread per bus."),
+            Arg::new("check-notifiers")
+                .long("check-notifiers")
+                .action(ArgAction::SetTrue)
+                .help("Ping each notifier at startup, and report those which can't be reached."),
         ])
+        .subcommand(
+            Command::new("check")
+                .about("Check whether any units are in a state matched by the rules, and exit.")
+                .after_help(help_messages.check.clone())
+                .args(&[
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "nagios", "zabbix-lld"])
+                        .default_value("text")
+                        .help("How to report units: as a list, as a Nagios plugin does, or as Zabbix low-level discovery data."),
+                    Arg::new("nagios")
+                        .long("nagios")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("format")
+                        .help("Shorthand for --format nagios."),
+                    Arg::new("zabbix-server")
+                        .long("zabbix-server")
+                        .requires("zabbix-host")
+                        .help("Send every unit's state to this Zabbix server or proxy."),
+                    Arg::new("zabbix-port")
+                        .long("zabbix-port")
+                        .value_parser(value_parser!(u16))
+                        .default_value("10051")
+                        .help("The port on which the Zabbix server listens for senders."),
+                    Arg::new("zabbix-host")
+                        .long("zabbix-host")
+                        .help("The name of the host to which items belong, as configured in Zabbix."),
+                ]),
+        )
+        .subcommand(
+            Command::new("events")
+                .about("List recent events.")
+                .after_help(help_messages.events.clone())
+                .args_conflicts_with_subcommands(true)
+                .arg(
+                    Arg::new("last")
+                        .long("last")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                        .help("How many of the most recent events to list."),
+                )
+                .subcommand(
+                    Command::new("query")
+                        .about("Query the event store.")
+                        .after_help(help_messages.events_query.clone())
+                        .args(&[
+                            Arg::new("unit")
+                                .long("unit")
+                                .help("A glob pattern matching the units to list. Default: all."),
+                            Arg::new("state")
+                                .long("state")
+                                .help("Only list events in which units entered this state."),
+                            Arg::new("since").long("since").help(
+                                "Only list events at or after this time, e.g. \"2h\" (ago) or \
+                                 \"2019-03-14T15:09:26Z\".",
+                            ),
+                            Arg::new("until").long("until").help(
+                                "Only list events before this time, in the same form as --since.",
+                            ),
+                            Arg::new("notifications")
+                                .long("notifications")
+                                .action(ArgAction::SetTrue)
+                                .help("List attempts to contact notifiers instead of transitions."),
+                        ]),
+                ),
+        )
+        .subcommand(
+            Command::new("install")
+                .about("Install a systemd service unit which runs killjoy.")
+                .after_help(help_messages.install.clone())
+                .args(&[
+                    Arg::new("user")
+                        .long("user")
+                        .action(ArgAction::SetTrue)
+                        .help("Run killjoy as part of the current user's session."),
+                    Arg::new("system")
+                        .long("system")
+                        .action(ArgAction::SetTrue)
+                        .help("Run killjoy as part of the system."),
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite the service unit if it already exists."),
+                    Arg::new("print")
+                        .long("print")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the service unit to stdout instead of installing it."),
+                ])
+                .group(
+                    ArgGroup::new("scope")
+                        .args(["user", "system"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("notifiers")
+                .about("Manage notifiers.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("check")
+                        .about("Check whether each notifier can be reached.")
+                        .after_help(help_messages.notifiers_check.clone()),
+                )
+                .subcommand(
+                    Command::new("test")
+                        .about("Send a test notification through a notifier.")
+                        .after_help(help_messages.notifiers_test.clone())
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .help("The name of the notifier, as given in the settings file."),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("pause")
+                .about("Pause monitoring of a bus, or list paused buses.")
+                .after_help(help_messages.pause.clone())
+                .arg(
+                    Arg::new("bus-type")
+                        .value_parser(["session", "starter", "system"])
+                        .help("The bus to pause. Default: list paused buses."),
+                ),
+        )
+        .subcommand(
+            Command::new("resume").about("Resume monitoring of a bus.").arg(
+                Arg::new("bus-type")
+                    .value_parser(["session", "starter", "system"])
+                    .required(true)
+                    .help("The bus to resume."),
+            ),
+        )
+        .subcommand(
+            Command::new("rules")
+                .about("Add and remove rules without editing the settings file.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a rule, and print its ID.")
+                        .after_help(help_messages.rules_add.clone())
+                        .arg(
+                            Arg::new("rule")
+                                .required(true)
+                                .help("The rule, as it would appear in the settings file."),
+                        ),
+                )
+                .subcommand(Command::new("list").about("List rules added at runtime."))
+                .subcommand(
+                    Command::new("remove").about("Remove a rule added at runtime.").arg(
+                        Arg::new("id")
+                            .value_parser(value_parser!(u32))
+                            .required(true)
+                            .help("The ID of the rule to remove."),
+                    ),
+                ),
+        )
         .subcommand(
             Command::new("settings")
                 .about("Manage the settings file.")
@@ -34,23 +225,88 @@ pub fn get_cli_args() -> ArgMatches {
                         .about("Print the path to the file from which settings are loaded.")
                         .after_help(help_messages.settings_load_path.clone()),
                 )
+                .subcommand(
+                    Command::new("migrate")
+                        .about("Upgrade the settings file to the newest version.")
+                        .after_help(help_messages.settings_migrate.clone())
+                        .args(&[
+                            Arg::new("path").help("The path to the settings file to migrate."),
+                            Arg::new("in-place")
+                                .long("in-place")
+                                .action(ArgAction::SetTrue)
+                                .help("Overwrite the settings file instead of printing to stdout."),
+                        ]),
+                )
                 .subcommand(
                     Command::new("validate")
                         .about("Validate the settings file.")
                         .after_help(help_messages.settings_validate.clone())
-                        .arg(
-                            Arg::new("path")
-                                .help("The path to the settings file to validate."),
-                        ),
+                        .args(&[
+                            Arg::new("path").help("The path to the settings file to validate."),
+                            Arg::new("strict")
+                                .long("strict")
+                                .action(ArgAction::SetTrue)
+                                .help("Also cross-check the settings against the live system."),
+                        ]),
+                ),
+        )
+        .subcommand(
+            Command::new("silence")
+                .about("Temporarily silence notifications and actions, e.g. during maintenance.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a silence, and print its ID.")
+                        .after_help(help_messages.silence_add.clone())
+                        .args(&[
+                            Arg::new("duration").long("duration").required(true).help(
+                                "How long the silence lasts, e.g. \"90s\", \"1h30m\" or \"2d\".",
+                            ),
+                            Arg::new("unit").long("unit").help(
+                                "A glob pattern matching the units to silence. Default: all.",
+                            ),
+                            Arg::new("comment")
+                                .long("comment")
+                                .help("Why the silence was added."),
+                        ]),
+                )
+                .subcommand(Command::new("list").about("List unexpired silences."))
+                .subcommand(
+                    Command::new("remove").about("Remove a silence.").arg(
+                        Arg::new("id")
+                            .value_parser(value_parser!(u32))
+                            .required(true)
+                            .help("The ID of the silence to remove."),
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("man")
+                .about("Print killjoy's man page, or write a man page for every subcommand.")
+                .after_help(help_messages.man.clone())
+                .arg(
+                    Arg::new("output-dir")
+                        .long("output-dir")
+                        .help("The directory in which to write the man pages."),
                 ),
         )
-        .get_matches()
 }
 
 // Help messages for use by a CLI parser.
 struct HelpMessages {
+    check: String,
+    events: String,
+    events_query: String,
+    install: String,
+    man: String,
+    notifiers_check: String,
+    notifiers_test: String,
+    pause: String,
+    rules_add: String,
     settings_load_path: String,
+    settings_migrate: String,
     settings_validate: String,
+    silence_add: String,
 }
 
 // A factory for generating `HelpMessages` structs.
@@ -68,11 +324,33 @@ impl HelpMessagesFactory {
 
     // Create a struct containing help messages formatted for the current terminal.
     fn gen_help_messages(&self) -> HelpMessages {
+        let check = self.format(Self::get_help_for_check());
+        let events = self.format(Self::get_help_for_events());
+        let events_query = self.format(Self::get_help_for_events_query());
+        let install = self.format(Self::get_help_for_install());
+        let man = self.format(Self::get_help_for_man());
+        let notifiers_check = self.format(Self::get_help_for_notifiers_check());
+        let notifiers_test = self.format(Self::get_help_for_notifiers_test());
+        let pause = self.format(Self::get_help_for_pause());
+        let rules_add = self.format(Self::get_help_for_rules_add());
         let settings_load_path = self.format(Self::get_help_for_settings_load_path());
+        let settings_migrate = self.format(Self::get_help_for_settings_migrate());
         let settings_validate = self.format(Self::get_help_for_settings_validate());
+        let silence_add = self.format(Self::get_help_for_silence_add());
         HelpMessages {
+            check,
+            events,
+            events_query,
+            install,
+            man,
+            notifiers_check,
+            notifiers_test,
+            pause,
+            rules_add,
             settings_load_path,
+            settings_migrate,
             settings_validate,
+            silence_add,
         }
     }
 
@@ -104,6 +382,121 @@ impl HelpMessagesFactory {
         Regex::new(r"(?P<pre>\S)\n(?P<post>\S)").expect("Failed to compile regex.")
     }
 
+    // Return the unformatted help message for the `check` subcommand.
+    fn get_help_for_check() -> &'static str {
+        r###"
+        Load the settings file, read the state of every unit matched by its rules once, and print
+        each unit which is currently in one of its rules' active states, one per line. Each line
+        lists the time at which the unit entered that state, the bus, the unit, its state, and the
+        highest severity among the rules it matches, separated by tabs. Notifiers, actions and
+        silences are ignored. If no units are found, return zero. Otherwise, return non-zero.
+
+        If --format nagios is passed, follow the Nagios plugin API, which Icinga also uses: print a
+        single line stating the status, the units found, and performance data counting the units
+        found, and how many of them are failed or activating. Return 0 (OK) if no units are found,
+        2 (CRITICAL) if any unit matches a critical rule, 1 (WARNING) if units are found but none
+        match a critical rule, or 3 (UNKNOWN) if the check fails.
+
+        If --format zabbix-lld is passed, print Zabbix low-level discovery data instead, listing
+        every unit matched by the rules with the {#BUS} and {#UNIT} macros, and return zero unless
+        the check fails. If --zabbix-server is passed, first send every unit's state to that Zabbix
+        server or proxy, as the value of the trapper item killjoy.unit.state[{#BUS},{#UNIT}] of the
+        host named by --zabbix-host.
+        "###
+    }
+
+    // Return the unformatted help message for the `events` subcommand.
+    fn get_help_for_events() -> &'static str {
+        r###"
+        Print the most recent events, oldest first, one per line. Each line lists the time at which
+        the unit changed state, the bus, the unit, and its old and new states, separated by tabs.
+        Running instances of killjoy record events in $XDG_STATE_HOME/killjoy/history.json, which
+        holds as many events as the settings file's "history_size" key allows.
+        "###
+    }
+
+    // Return the unformatted help message for the `events query` subcommand.
+    fn get_help_for_events_query() -> &'static str {
+        r###"
+        Print the state transitions recorded in the event store which match the given criteria,
+        oldest first, in the same form as "killjoy events". If --notifications is passed, print the
+        recorded attempts to contact notifiers instead. Each line lists the time of the attempt,
+        the bus, the unit, the state the notifier was told about, the notifier, and either "sent"
+        or the reason the attempt failed, separated by tabs.
+
+        Running instances of killjoy record events in the event store if the settings file has an
+        "event_store" key. The store's location is read from the settings file. The event store is
+        only available if killjoy was built with the "sqlite" feature.
+        "###
+    }
+
+    // Return the unformatted help message for the `install` subcommand.
+    fn get_help_for_install() -> &'static str {
+        r###"
+        Generate a systemd service unit which runs this killjoy executable, and install it as
+        killjoy.service. With --user, the unit is placed in $XDG_CONFIG_HOME/systemd/user, and
+        starts along with the user's session. With --system, it's placed in /etc/systemd/system,
+        starts at boot, and keeps its state in /var/lib/killjoy. Either way, it's restarted if it
+        fails, and is sandboxed with systemd's hardening options. An existing unit isn't
+        overwritten unless --force is passed. Once installed, print the systemctl command which
+        enables and starts the unit.
+        "###
+    }
+
+    // Return the unformatted help message for the `man` subcommand.
+    fn get_help_for_man() -> &'static str {
+        r###"
+        Render killjoy's command line interface as man pages, in roff format. By default, print the
+        page for killjoy itself, killjoy.1, to stdout, e.g. for "killjoy man | man -l -". If
+        --output-dir is passed, write one page per command and subcommand into that directory
+        instead, named like killjoy-settings-validate.1. The pages also document the options which
+        are only meant for development, and which --help hides.
+        "###
+    }
+
+    // Return the unformatted help message for the `notifiers check` subcommand.
+    fn get_help_for_notifiers_check() -> &'static str {
+        r###"
+        Load the settings file, and ping each notifier it declares, by calling
+        org.freedesktop.DBus.Peer.Ping, by connecting to its server, or by sending a plugin a "ping"
+        request. For each notifier which responds, print its name to stdout.
+        If all notifiers respond, return zero. Otherwise, print an error message to stderr for each
+        notifier which can't be reached, and return non-zero.
+        "###
+    }
+
+    // Return the unformatted help message for the `notifiers test` subcommand.
+    fn get_help_for_notifiers_test() -> &'static str {
+        r###"
+        Load the settings file, and send the named notifier a notification stating that the fake
+        unit killjoy-test.service has entered the "failed" state. This makes it possible to check
+        that a notifier works from end to end, without breaking a real unit. If the notifier
+        accepts the notification, return zero. Otherwise, print an error message to stderr and
+        return non-zero.
+        "###
+    }
+
+    // Return the unformatted help message for the `pause` subcommand.
+    fn get_help_for_pause() -> &'static str {
+        r###"
+        While a bus is paused, killjoy stays connected to it and keeps track of its units, but
+        neither contacts notifiers nor takes actions for them. Paused buses stay paused until they
+        are resumed with `killjoy resume`, and are stored in $XDG_STATE_HOME/killjoy/paused.json,
+        where running instances of killjoy will find them.
+        "###
+    }
+
+    // Return the unformatted help message for the `rules add` subcommand.
+    fn get_help_for_rules_add() -> &'static str {
+        r###"
+        The rule is a JSON object, as found in the settings file's "rules" list, and is checked
+        against the settings file. The settings file's "defaults" don't apply. Rules are stored in
+        $XDG_STATE_HOME/killjoy/rules.json, where running instances of killjoy will find them
+        within a few seconds. A rule on a bus which a running instance isn't watching takes effect
+        when that instance restarts.
+        "###
+    }
+
     // Return the unformatted help message for the `settings load-path` subcommand.
     fn get_help_for_settings_load_path() -> &'static str {
         r###"
@@ -113,11 +506,39 @@ impl HelpMessagesFactory {
         "###
     }
 
+    // Return the unformatted help message for the `settings migrate` subcommand.
+    fn get_help_for_settings_migrate() -> &'static str {
+        r###"
+        Read the settings file, and convert it to the newest settings file version, as named by its
+        "version" key. Print the result to stdout, or if --in-place is passed, overwrite the
+        settings file. Keys are written in alphabetical order. If the settings file can't be
+        migrated, or if the result is invalid, print an error message to stderr and return
+        non-zero.
+        "###
+    }
+
     // Return the unformatted help message for the `settings validate` subcommand.
     fn get_help_for_settings_validate() -> &'static str {
         r###"
         Check to see whether the settings file conforms with a schema. If so, silently exit.
         Otherwise, print an error message to stderr and return non-zero.
+
+        If --strict is passed, also connect to the buses named in the settings file, and treat the
+        following as errors: a bus which can't be reached, a rule which matches none of the units
+        currently loaded on its bus, a notifier whose bus name nothing owns or can activate, and a
+        notifier on the system bus to which no D-Bus policy in /etc/dbus-1/system.d or
+        /usr/share/dbus-1/system.d allows sending. This catches typos such as "ngnix.service", but
+        may report units which aren't loaded yet, notifiers which aren't running yet, or policies
+        set elsewhere. Each policy problem includes a policy file which would fix it.
+        "###
+    }
+
+    // Return the unformatted help message for the `silence add` subcommand.
+    fn get_help_for_silence_add() -> &'static str {
+        r###"
+        While a silence is in effect, killjoy neither contacts notifiers nor takes actions for the
+        units it matches. Silences expire automatically, and are stored in
+        $XDG_STATE_HOME/killjoy/silences.json, where running instances of killjoy will find them.
         "###
     }
 }
@@ -135,4 +556,15 @@ mod tests {
     fn test_help_messages_factory_new() {
         HelpMessagesFactory::new();
     }
+
+    #[test]
+    fn test_build_cli() {
+        build_cli().debug_assert();
+        let cmd = build_man_cli();
+        for arg in DEV_ARGS {
+            assert!(cmd
+                .get_arguments()
+                .any(|a| a.get_id() == arg && !a.is_hide_set()));
+        }
+    }
 }
@@ -0,0 +1,551 @@
+// Logic for controlling a running killjoy over a Unix domain socket.
+//
+// Clients connect to the control socket, and send JSON-RPC 2.0 requests, one per line. Each
+// request is answered with one line. Access is governed by the socket's file permissions, so no
+// D-Bus policy is needed, and an unprivileged user may control their own killjoy. The following
+// methods are supported:
+//
+// *   `status`: Tell whether each bus watcher is healthy and how often it has been restarted, which
+//     buses are paused, and what the bus watchers have done. See `supervisor`.
+// *   `silences.list`, `silences.add` and `silences.remove`: Manage silences. See `silence`.
+// *   `rules.list`, `rules.add` and `rules.remove`: Manage runtime rules. See `runtime_rules`.
+// *   `events.list`: List recent events. See `history`.
+// *   `events.query`: Query the event store, if killjoy is built with one. See `event_store`.
+//
+// Silences, runtime rules and paused buses are kept in the same files as used by the CLI, so
+// changes made over the control socket are seen by `killjoy silence list` and so on, and vice
+// versa.
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Error as IOError, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+use xdg::BaseDirectories;
+
+use crate::error::{ControlError, Error as CrateError, StateError};
+#[cfg(feature = "sqlite")]
+use crate::event_store;
+#[cfg(feature = "sqlite")]
+use crate::event_store::{Database, Query};
+use crate::heartbeat::Health;
+use crate::history;
+use crate::history::History;
+use crate::monitor::StopHandle;
+use crate::pause;
+use crate::pause::PausedBuses;
+use crate::runtime_rules;
+use crate::runtime_rules::RuntimeRules;
+use crate::settings::{ControlSocket, Settings};
+use crate::silence;
+use crate::silence::Silences;
+use crate::supervisor::{Metrics, Supervisor};
+use crate::timestamp;
+
+// The first file descriptor passed by a service manager which socket activates killjoy.
+const LISTEN_FDS_START: RawFd = 3;
+
+// How long a client may take to send a request before it's disconnected.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How many events `events.list` returns, unless told otherwise.
+const DEFAULT_EVENT_COUNT: usize = 20;
+
+// JSON-RPC 2.0 error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+// A control socket, ready to accept connections.
+//
+// If killjoy created the socket, rather than being passed it, the socket's file is removed when
+// this is dropped.
+pub struct ControlListener {
+    listener: UnixListener,
+    path: Option<PathBuf>,
+}
+
+impl ControlListener {
+    // Get the control socket, if killjoy is socket activated or `control_socket` is given.
+    //
+    // If killjoy is socket activated, the first socket passed to it is used. Otherwise, a socket is
+    // created at `control_socket.path`, replacing any stale socket there, and only its owner may
+    // connect to it. Return an error if the socket can't be created.
+    pub fn new(control_socket: Option<&ControlSocket>) -> Result<Option<Self>, CrateError> {
+        if is_socket_activated() {
+            // SAFETY: The service manager passes sockets to socket activated processes starting
+            // at LISTEN_FDS_START, and nothing else in killjoy takes ownership of them.
+            let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+            return Ok(Some(Self {
+                listener,
+                path: None,
+            }));
+        }
+        let control_socket = match control_socket {
+            Some(control_socket) => control_socket,
+            None => return Ok(None),
+        };
+        let path = match &control_socket.path {
+            Some(path) => path.to_owned(),
+            None => get_control_socket_path()?,
+        };
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                return Err(CrateError::Control(ControlError::BindControlSocket(err)))
+            }
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)
+            .map_err(|err| CrateError::Control(ControlError::BindControlSocket(err)))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .map_err(|err| CrateError::Control(ControlError::BindControlSocket(err)))?;
+        Ok(Some(Self {
+            listener,
+            path: Some(path),
+        }))
+    }
+}
+
+impl Drop for ControlListener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Answer requests on the control socket until `stop` is stopped.
+//
+// Clients are served one at a time. A bus watcher is considered unhealthy if it hasn't reported to
+// the supervisor within `max_age`. Whether to stop is checked every `poll_interval`. Failing to
+// serve a client is reported on stderr, and the client is disconnected.
+pub fn run(
+    control_listener: &ControlListener,
+    settings: &Settings,
+    supervisor: &Supervisor,
+    max_age: Duration,
+    poll_interval: Duration,
+    stop: &StopHandle,
+) {
+    let listener = &control_listener.listener;
+    if let Err(err) = listener.set_nonblocking(true) {
+        eprintln!(
+            "{}",
+            CrateError::Control(ControlError::AcceptControlConnection(err)).with_code()
+        );
+        return;
+    }
+    let context = Context {
+        health: &supervisor.health,
+        max_age,
+        metrics: &supervisor.metrics,
+        settings,
+    };
+    while !stop.is_stopped() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = serve(stream, &context) {
+                    eprintln!("{}", err.with_code());
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(poll_interval),
+            Err(err) => {
+                eprintln!(
+                    "{}",
+                    CrateError::Control(ControlError::AcceptControlConnection(err)).with_code()
+                );
+                thread::sleep(poll_interval);
+            }
+        }
+    }
+}
+
+// Get the default path to the control socket, creating parent directories if necessary.
+pub fn get_control_socket_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::Control(ControlError::BindControlSocket(IOError::other(err))))?
+        .place_runtime_file("control.sock")
+        .map_err(|err| CrateError::Control(ControlError::BindControlSocket(err)))
+}
+
+// Tell whether a service manager has passed this process any sockets.
+fn is_socket_activated() -> bool {
+    let for_this_process = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == process::id());
+    let fd_count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+    for_this_process && fd_count > 0
+}
+
+// What a request may need to be answered.
+struct Context<'a> {
+    health: &'a Health,
+    max_age: Duration,
+    metrics: &'a Metrics,
+    settings: &'a Settings,
+}
+
+// Answer each request sent by a client, until the client disconnects.
+fn serve(stream: UnixStream, context: &Context) -> Result<(), CrateError> {
+    stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(READ_TIMEOUT)))
+        .map_err(|err| CrateError::Control(ControlError::AcceptControlConnection(err)))?;
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line =
+            line.map_err(|err| CrateError::Control(ControlError::ServeControlConnection(err)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_request(&line, context);
+        writeln!(writer, "{}", response)
+            .map_err(|err| CrateError::Control(ControlError::ServeControlConnection(err)))?;
+    }
+    Ok(())
+}
+
+// An error to be sent to a client, as described by JSON-RPC 2.0.
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<CrateError> for RpcError {
+    fn from(err: CrateError) -> Self {
+        Self::new(SERVER_ERROR, err.to_string())
+    }
+}
+
+impl From<Vec<CrateError>> for RpcError {
+    fn from(errs: Vec<CrateError>) -> Self {
+        let messages: Vec<String> = errs.iter().map(CrateError::to_string).collect();
+        Self::new(SERVER_ERROR, messages.join("\n"))
+    }
+}
+
+// Answer a request, and return the response.
+fn handle_request(line: &str, context: &Context) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return gen_error_response(Value::Null, RpcError::new(PARSE_ERROR, err.to_string()))
+        }
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => {
+            let err = RpcError::new(INVALID_REQUEST, "the request has no method");
+            return gen_error_response(id, err);
+        }
+    };
+    let empty = Map::new();
+    let params = match request.get("params") {
+        Some(Value::Object(params)) => params,
+        None | Some(Value::Null) => &empty,
+        Some(_) => {
+            let err = RpcError::new(INVALID_PARAMS, "params must be an object");
+            return gen_error_response(id, err);
+        }
+    };
+    match call(method, params, context) {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => gen_error_response(id, err),
+    }
+}
+
+// Build a response which reports an error.
+fn gen_error_response(id: Value, err: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": err.code, "message": err.message},
+    })
+}
+
+// Call the given method, and return its result.
+fn call(method: &str, params: &Map<String, Value>, context: &Context) -> Result<Value, RpcError> {
+    match method {
+        "status" => get_status(context),
+        "silences.list" => list_silences(),
+        "silences.add" => add_silence(params),
+        "silences.remove" => remove_silence(params),
+        "rules.list" => list_rules(),
+        "rules.add" => add_rule(params, context),
+        "rules.remove" => remove_rule(params),
+        "events.list" => list_events(params),
+        "events.query" => query_events(params, context),
+        other => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method \"{}\"", other),
+        )),
+    }
+}
+
+// Handle the `status` method.
+fn get_status(context: &Context) -> Result<Value, RpcError> {
+    let paused_buses = PausedBuses::load(&pause::get_paused_buses_path()?)?;
+    let buses: Map<String, Value> = context
+        .health
+        .statuses(context.max_age)
+        .into_iter()
+        .map(|status| {
+            let value = json!({ "healthy": status.healthy, "restarts": status.restarts });
+            (status.bus_type.to_string(), value)
+        })
+        .collect();
+    Ok(json!({
+        "buses": buses,
+        "metrics": context.metrics.to_json(),
+        "paused": paused_buses.iter().collect::<Vec<_>>(),
+    }))
+}
+
+// Handle the `silences.list` method. Expired silences are omitted.
+fn list_silences() -> Result<Value, RpcError> {
+    let now = timestamp::get_current_realtime_timestamp();
+    let mut silences = Silences::load(&silence::get_silences_path()?)?;
+    silences.prune(&now);
+    Ok(json!(silences.iter().collect::<Vec<_>>()))
+}
+
+// Handle the `silences.add` method, and return the new silence's ID.
+fn add_silence(params: &Map<String, Value>) -> Result<Value, RpcError> {
+    let duration = silence::parse_duration(&get_str_param(params, "duration")?)?;
+    let unit = get_optional_str_param(params, "unit")?;
+    let comment = get_optional_str_param(params, "comment")?;
+    let now = timestamp::get_current_realtime_timestamp();
+    let path = silence::get_silences_path()?;
+    let mut silences = Silences::load(&path)?;
+    silences.prune(&now);
+    let id = silences.add(unit, comment, duration, &now);
+    silences.save(&path)?;
+    Ok(json!(id))
+}
+
+// Handle the `silences.remove` method.
+fn remove_silence(params: &Map<String, Value>) -> Result<Value, RpcError> {
+    let id = get_id_param(params)?;
+    let path = silence::get_silences_path()?;
+    let mut silences = Silences::load(&path)?;
+    silences.remove(id)?;
+    silences.save(&path)?;
+    Ok(Value::Null)
+}
+
+// Handle the `rules.list` method.
+fn list_rules() -> Result<Value, RpcError> {
+    let runtime_rules = RuntimeRules::load(&runtime_rules::get_runtime_rules_path()?)?;
+    Ok(json!(runtime_rules.iter().collect::<Vec<_>>()))
+}
+
+// Handle the `rules.add` method, and return the new rule's ID.
+fn add_rule(params: &Map<String, Value>, context: &Context) -> Result<Value, RpcError> {
+    let rule = params
+        .get("rule")
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing param \"rule\""))?;
+    let id = runtime_rules::add(context.settings, rule.to_owned())?;
+    Ok(json!(id))
+}
+
+// Handle the `rules.remove` method.
+fn remove_rule(params: &Map<String, Value>) -> Result<Value, RpcError> {
+    let id = get_id_param(params)?;
+    let path = runtime_rules::get_runtime_rules_path()?;
+    let mut runtime_rules = RuntimeRules::load(&path)?;
+    runtime_rules.remove(id)?;
+    runtime_rules.save(&path)?;
+    Ok(Value::Null)
+}
+
+// Handle the `events.list` method, which takes the number of events to list as `last`.
+fn list_events(params: &Map<String, Value>) -> Result<Value, RpcError> {
+    let count = match params.get("last") {
+        Some(last) => last
+            .as_u64()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "param \"last\" must be an integer"))?
+            as usize,
+        None => DEFAULT_EVENT_COUNT,
+    };
+    let history = History::load(&history::get_history_path()?)?;
+    Ok(json!(history.last(count).collect::<Vec<_>>()))
+}
+
+// Handle the `events.query` method, which takes the same filters as `killjoy events query`.
+//
+// `since` and `until` are realtime timestamps, in usec.
+#[cfg(feature = "sqlite")]
+fn query_events(params: &Map<String, Value>, context: &Context) -> Result<Value, RpcError> {
+    let get_timestamp = |key: &str| -> Result<Option<u64>, RpcError> {
+        params
+            .get(key)
+            .map(|value| {
+                value.as_u64().ok_or_else(|| {
+                    RpcError::new(
+                        INVALID_PARAMS,
+                        format!("param \"{}\" must be an integer", key),
+                    )
+                })
+            })
+            .transpose()
+    };
+    let query = Query {
+        unit: get_optional_str_param(params, "unit")?,
+        state: get_optional_str_param(params, "state")?,
+        since: get_timestamp("since")?,
+        until: get_timestamp("until")?,
+    };
+    let path = match context
+        .settings
+        .event_store
+        .as_ref()
+        .and_then(|event_store| event_store.path.to_owned())
+    {
+        Some(path) => path,
+        None => event_store::get_event_store_path()?,
+    };
+    let database = Database::open(&path)?;
+    Ok(json!(database.query_transitions(&query)?))
+}
+
+// Handle the `events.query` method, in a build without an event store.
+#[cfg(not(feature = "sqlite"))]
+fn query_events(_params: &Map<String, Value>, _context: &Context) -> Result<Value, RpcError> {
+    Err(CrateError::State(StateError::EventStoreNotSupported).into())
+}
+
+// Get a required string param.
+fn get_str_param(params: &Map<String, Value>, key: &str) -> Result<String, RpcError> {
+    get_optional_str_param(params, key)?
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing param \"{}\"", key)))
+}
+
+// Get an optional string param.
+fn get_optional_str_param(
+    params: &Map<String, Value>,
+    key: &str,
+) -> Result<Option<String>, RpcError> {
+    match params.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.to_owned())),
+        Some(_) => Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("param \"{}\" must be a string", key),
+        )),
+    }
+}
+
+// Get the `id` param, which names a silence or a runtime rule.
+fn get_id_param(params: &Map<String, Value>) -> Result<u32, RpcError> {
+    params
+        .get("id")
+        .and_then(Value::as_u64)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "param \"id\" must be an integer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use dbus::BusType;
+
+    fn gen_context_parts() -> (Supervisor, Settings) {
+        let settings = Settings::new(r#"{"notifiers": {}, "rules": []}"#.as_bytes())
+            .expect("Failed to create settings.");
+        (Supervisor::new(&[BusType::System]), settings)
+    }
+
+    // handle_request()
+    #[test]
+    fn test_handle_request_errors() {
+        let (supervisor, settings) = gen_context_parts();
+        let context = Context {
+            health: &supervisor.health,
+            max_age: Duration::from_secs(30),
+            metrics: &supervisor.metrics,
+            settings: &settings,
+        };
+        let cases = [
+            ("{", Value::Null, PARSE_ERROR),
+            (r#"{"id": 1}"#, json!(1), INVALID_REQUEST),
+            (r#"{"id": 2, "method": "foo"}"#, json!(2), METHOD_NOT_FOUND),
+            (
+                r#"{"id": 3, "method": "status", "params": []}"#,
+                json!(3),
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"id": "a", "method": "silences.remove"}"#,
+                json!("a"),
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"method": "rules.add", "params": {}}"#,
+                Value::Null,
+                INVALID_PARAMS,
+            ),
+        ];
+        for (request, id, code) in cases {
+            let response = handle_request(request, &context);
+            assert_eq!(response["id"], id, "{}", request);
+            assert_eq!(response["error"]["code"], json!(code), "{}", request);
+        }
+    }
+
+    // serve()
+    #[test]
+    fn test_serve() {
+        let (supervisor, settings) = gen_context_parts();
+        supervisor.health.report(BusType::System);
+        let context = Context {
+            health: &supervisor.health,
+            max_age: Duration::from_secs(30),
+            metrics: &supervisor.metrics,
+            settings: &settings,
+        };
+        let (server, mut client) = UnixStream::pair().expect("Failed to create socket pair.");
+        client
+            .write_all(b"{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"foo\"}\n\n")
+            .expect("Failed to write request.");
+        client
+            .shutdown(std::net::Shutdown::Write)
+            .expect("Failed to shut down socket.");
+        serve(server, &context).expect("Failed to serve client.");
+
+        let mut response = String::new();
+        client
+            .read_to_string(&mut response)
+            .expect("Failed to read response.");
+        assert_eq!(response.lines().count(), 1);
+        let response: Value = serde_json::from_str(&response).expect("Failed to parse response.");
+        assert_eq!(response["error"]["code"], json!(METHOD_NOT_FOUND));
+    }
+}
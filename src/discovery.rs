@@ -0,0 +1,172 @@
+// Logic for discovering rules which are declared in unit files, alongside the units themselves.
+//
+// A unit file or drop-in may have `X-KilljoyNotify=` lines in its `[Unit]` section. systemd ignores
+// keys which start with `X-`, but keeps them in the unit's files, so killjoy can read them back. A
+// line such as `X-KilljoyNotify=failed,inactive:desktop popup,pager` yields a rule which watches
+// that unit for the `failed` and `inactive` states, and contacts the `desktop popup` and `pager`
+// notifiers. As in systemd, a line with an empty value discards the lines before it, so a drop-in
+// may replace the directives in the unit file.
+
+use std::fs;
+
+use dbus::BusType;
+use serde_json::{json, Value};
+
+use crate::error::{Error as CrateError, SettingsError};
+use crate::settings;
+
+// The key which declares a rule.
+const DIRECTIVE_KEY: &str = "X-KilljoyNotify";
+
+// The section in which directives are read.
+const DIRECTIVE_SECTION: &str = "[Unit]";
+
+// Read the directives in the given unit file and drop-ins, in order.
+//
+// Files which can't be read are skipped, as the unit may have been reloaded meanwhile.
+pub fn read_directives(paths: &[String]) -> Vec<String> {
+    let mut directives: Vec<String> = Vec::new();
+    for path in paths {
+        if let Ok(contents) = fs::read_to_string(path) {
+            parse_directives(&contents, &mut directives);
+        }
+    }
+    directives
+}
+
+// Parse the directives in the given unit file contents, and add them to `directives`.
+//
+// Comments and continuation lines are handled as by systemd. An empty directive clears
+// `directives`.
+pub fn parse_directives(contents: &str, directives: &mut Vec<String>) {
+    let mut in_section = false;
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let mut line = line.trim().to_string();
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        while line.ends_with('\\') {
+            line.pop();
+            line.truncate(line.trim_end().len());
+            match lines.next() {
+                Some(next) => {
+                    line.push(' ');
+                    line.push_str(next.trim());
+                }
+                None => break,
+            }
+        }
+        if line.starts_with('[') {
+            in_section = line == DIRECTIVE_SECTION;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if key != DIRECTIVE_KEY {
+            continue;
+        }
+        if value.is_empty() {
+            directives.clear();
+        } else {
+            directives.push(value.to_string());
+        }
+    }
+}
+
+// Generate a rule from a directive for the given unit, as it would appear in the settings file.
+//
+// A directive is a comma-separated list of active states, a colon, and a comma-separated list of
+// notifier names. Return an error if either list is missing or empty. The states and notifiers
+// themselves are checked when the rule is parsed. See `Settings::parse_rule`.
+pub fn gen_rule(unit_name: &str, bus_type: BusType, directive: &str) -> Result<Value, CrateError> {
+    let invalid = || {
+        CrateError::Settings(SettingsError::InvalidDiscoveryDirective(
+            unit_name.to_string(),
+            directive.to_string(),
+        ))
+    };
+    let (states, notifiers) = directive.split_once(':').ok_or_else(invalid)?;
+    let split = |list: &str| -> Vec<String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let states = split(states);
+    let notifiers = split(notifiers);
+    if states.is_empty() || notifiers.is_empty() {
+        return Err(invalid());
+    }
+    Ok(json!({
+        "active_states": states,
+        "bus_type": settings::encode_bus_type(bus_type),
+        "expression": unit_name,
+        "expression_type": "unit name",
+        "notifiers": notifiers,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parse_directives()
+    #[test]
+    fn test_parse_directives() {
+        let contents = "\
+[Unit]
+Description=Foo
+X-KilljoyNotify=failed:desktop popup
+# X-KilljoyNotify=inactive:commented
+X-KilljoyNotify = failed, \\
+    inactive:pager
+
+[Service]
+X-KilljoyNotify=failed:ignored
+";
+        let mut directives = Vec::new();
+        parse_directives(contents, &mut directives);
+        assert_eq!(
+            directives,
+            vec![
+                "failed:desktop popup".to_string(),
+                "failed, inactive:pager".to_string(),
+            ]
+        );
+
+        parse_directives("[Unit]\nX-KilljoyNotify=\n", &mut directives);
+        assert!(directives.is_empty());
+    }
+
+    // gen_rule()
+    #[test]
+    fn test_gen_rule() {
+        let rule = gen_rule(
+            "foo.service",
+            BusType::Session,
+            "failed, inactive:desktop popup,pager",
+        )
+        .expect("Failed to generate rule.");
+        assert_eq!(
+            rule,
+            json!({
+                "active_states": ["failed", "inactive"],
+                "bus_type": "session",
+                "expression": "foo.service",
+                "expression_type": "unit name",
+                "notifiers": ["desktop popup", "pager"],
+            })
+        );
+
+        for directive in &["failed", ":pager", "failed:", "failed:,"] {
+            assert!(gen_rule("foo.service", BusType::Session, directive).is_err());
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// Logic for watching the system's units before the system bus is up.
+//
+// systemd talks D-Bus to peers on a private socket, without a bus in between, so that tools like
+// systemctl work before dbus-daemon or dbus-broker has started. Only root may connect to it. If the
+// system bus can't be reached, but the private socket can, bus watchers for the system bus connect
+// to the private socket instead, so that units which fail during early boot are noticed. Once the
+// system bus is up, `EarlyBootConnection` moves over to it, as the private socket is an
+// implementation detail of systemd.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::path::Path as FsPath;
+use std::time::{Duration, Instant};
+
+use dbus::{BusType, Connection, Error as DBusError, Message, MessageType};
+
+use crate::systemd;
+use crate::systemd::{SystemdBackend, SystemdManager};
+
+// The socket on which systemd talks D-Bus to peers.
+const PRIVATE_SOCKET_PATH: &str = "/run/systemd/private";
+
+// How often to check whether the system bus is up.
+const SYSTEM_BUS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long Subscribe may take on the system bus, in ms.
+const SUBSCRIBE_TIMEOUT: i32 = 25_000;
+
+// How long a reply may be awaited before it's given up on. Matches libdbus' default timeout.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(25);
+
+// The unique name which bus watchers are told they were given, as if by a bus.
+const UNIQUE_NAME: &str = ":private";
+
+// A D-Bus connection to systemd's private socket, which moves over to the system bus once it's up.
+//
+// There's no bus to filter signals on the private socket, so systemd sends every signal to every
+// subscribed peer. Signals which no match rule asks for are dropped here, as a bus would. Moving
+// waits until no replies are awaited, so that none are lost. Match rules are added to the system
+// bus, and Subscribe called, before the private socket is let go of, so that no signals are missed.
+pub struct EarlyBootConnection {
+    awaiting: RefCell<HashMap<u32, Instant>>,
+    checked: Cell<Instant>,
+    connection: RefCell<Connection>,
+    matches: RefCell<Vec<String>>,
+    on_bus: Cell<bool>,
+    pending: RefCell<VecDeque<Message>>,
+}
+
+impl EarlyBootConnection {
+    // Connect to systemd's private socket.
+    pub fn connect() -> Result<Self, DBusError> {
+        let address = format!("unix:path={}", PRIVATE_SOCKET_PATH);
+        let connection = Connection::open_private(&address)?;
+        let mut pending = VecDeque::new();
+        pending.push_back(systemd::gen_name_acquired(UNIQUE_NAME));
+        Ok(EarlyBootConnection {
+            awaiting: RefCell::new(HashMap::new()),
+            checked: Cell::new(Instant::now()),
+            connection: RefCell::new(connection),
+            matches: RefCell::new(Vec::new()),
+            on_bus: Cell::new(false),
+            pending: RefCell::new(pending),
+        })
+    }
+
+    // Tell whether systemd's private socket exists.
+    pub fn is_available() -> bool {
+        FsPath::new(PRIVATE_SOCKET_PATH).exists()
+    }
+
+    // Move over to the system bus, if `SYSTEM_BUS_CHECK_INTERVAL` has passed since the last
+    // attempt, it's up, and no replies are awaited. Signals which arrived on the private socket
+    // meanwhile are handed back first.
+    fn move_to_bus(&self) {
+        if self.on_bus.get() || self.checked.get().elapsed() < SYSTEM_BUS_CHECK_INTERVAL {
+            return;
+        }
+        self.checked.set(Instant::now());
+        {
+            let mut awaiting = self.awaiting.borrow_mut();
+            awaiting.retain(|_, sent| sent.elapsed() < REPLY_TIMEOUT);
+            if !awaiting.is_empty() {
+                return;
+            }
+        }
+        let connection = match Connection::get_private(BusType::System) {
+            Ok(connection) => connection,
+            Err(_) => return,
+        };
+        if SystemdManager::subscribe(&connection, SUBSCRIBE_TIMEOUT).is_err() {
+            return;
+        }
+        for match_str in self.matches.borrow().iter() {
+            if Connection::add_match(&connection, match_str).is_err() {
+                return;
+            }
+        }
+        let old_connection = self.connection.replace(connection);
+        let mut pending = self.pending.borrow_mut();
+        for msg in old_connection.incoming(0) {
+            if self.is_wanted(&msg) {
+                pending.push_back(msg);
+            }
+        }
+        self.on_bus.set(true);
+    }
+
+    // Tell whether a message should be handed back, i.e. whether it isn't a signal, or a match
+    // rule asks for it.
+    fn is_wanted(&self, msg: &Message) -> bool {
+        if self.on_bus.get() || msg.msg_type() != MessageType::Signal {
+            return true;
+        }
+        let member = msg.member().map(|member| member.to_string());
+        let path = msg.path().map(|path| path.to_string());
+        systemd::is_matched(
+            &self.matches.borrow(),
+            &member.unwrap_or_default(),
+            &path.unwrap_or_default(),
+            msg.get1::<&str>(),
+        )
+    }
+}
+
+impl SystemdBackend for EarlyBootConnection {
+    fn call(&self, msg: Message, timeout_ms: i32) -> Result<Message, DBusError> {
+        self.connection
+            .borrow()
+            .send_with_reply_and_block(msg, timeout_ms)
+    }
+
+    fn send(&self, msg: Message) -> Option<u32> {
+        let serial = self.connection.borrow().send(msg).ok()?;
+        self.awaiting.borrow_mut().insert(serial, Instant::now());
+        Some(serial)
+    }
+
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message> {
+        if let Some(msg) = self.pending.borrow_mut().pop_front() {
+            return Some(msg);
+        }
+        self.move_to_bus();
+        if let Some(msg) = self.pending.borrow_mut().pop_front() {
+            return Some(msg);
+        }
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let remaining = u32::try_from(remaining.as_millis()).unwrap_or(u32::MAX);
+            let msg = self.connection.borrow().incoming(remaining).next()?;
+            if let Some(serial) = msg.get_reply_serial() {
+                self.awaiting.borrow_mut().remove(&serial);
+            }
+            if self.is_wanted(&msg) {
+                return Some(msg);
+            }
+        }
+    }
+
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError> {
+        if self.on_bus.get() {
+            Connection::add_match(&self.connection.borrow(), match_str)?;
+        }
+        self.matches.borrow_mut().push(match_str.to_string());
+        Ok(())
+    }
+
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError> {
+        if self.on_bus.get() {
+            Connection::remove_match(&self.connection.borrow(), match_str)?;
+        }
+        let mut matches = self.matches.borrow_mut();
+        if let Some(i) = matches.iter().position(|other| other == match_str) {
+            matches.remove(i);
+        }
+        Ok(())
+    }
+
+    fn get_pollfds(&self) -> Vec<libc::pollfd> {
+        SystemdBackend::get_pollfds(&*self.connection.borrow())
+    }
+
+    // Until the connection has moved over to the system bus, bus watchers must come back to check
+    // whether it's up.
+    fn get_poll_interval(&self) -> Option<Duration> {
+        if self.on_bus.get() {
+            None
+        } else {
+            Some(SYSTEM_BUS_CHECK_INTERVAL)
+        }
+    }
+}
+
+impl SystemdManager for EarlyBootConnection {}
@@ -1,193 +1,830 @@
 // Custom exceptions.
+//
+// Each kind of error has a code, like "KJ2005", which is printed in logs. Codes are never reused or
+// renumbered, so that they may be searched for, alerted on, and looked up across releases: a new
+// kind of error gets the next free code in its group.
 
-use std::error::Error as StdError;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::Error as IOError;
 use std::num::ParseIntError;
+use std::process::ExitStatus;
 use std::str::Utf8Error;
 
 use crate::unit::ActiveState;
 use dbus::Error as ExternDBusError;
 
 use regex::Error as RegexError;
+#[cfg(feature = "sqlite")]
+use rusqlite::Error as SqliteError;
 use serde_json::error::Error as SerdeJsonError;
+use thiserror::Error as ThisError;
+
+// The process exit statuses for each class of error. See `Error::exit_code`.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_BUS_CONNECTION: i32 = 3;
+pub const EXIT_MONITORING: i32 = 4;
+pub const EXIT_NOTIFIER: i32 = 5;
 
 // This application's error type.
-#[derive(Debug)]
+//
+// Errors about the settings file, about talking to D-Bus and systemd, about contacting notifiers,
+// about state files, about the control socket, and about actions are grouped into their own types,
+// which this type wraps. The rest come from the command line. Every error has a code, which
+// `with_code` prefixes to the error's message.
+#[derive(Debug, ThisError)]
 pub enum Error {
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+    #[error(transparent)]
+    Bus(#[from] BusError),
+    #[error(transparent)]
+    Notifier(#[from] NotifierError),
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error(transparent)]
+    Control(#[from] ControlError),
+    #[error(transparent)]
+    Action(#[from] ActionError),
+
+    #[error("Failed to get --loop-timeout argument. A default should've been set in the argument parser.")]
     MissingLoopTimeoutArg,
+    #[error("A monitoring thread panicked. Source: {0:?}")]
     MonitoringThreadPanicked(Box<dyn std::any::Any + std::marker::Send>),
-    ParseLoopTimeoutArg(ParseIntError),
-    UnexpectedSubcommand(Option<String>), // Typically Some(subcmd), but clap doesn't guarantee it.
+    #[error("Failed to parse --loop-timeout argument: {0:?}")]
+    ParseLoopTimeoutArg(#[source] ParseIntError),
+    #[error("An unexpected subcommand was encountered{}", fmt_subcommand(.0))]
+    UnexpectedSubcommand(Option<String>),
+    #[error("Failed to write man page: {0}")]
+    WriteManPage(#[source] IOError),
+    #[error("Failed to get the path to the killjoy executable: {0}")]
+    GetExecutablePath(#[source] IOError),
+    #[error("Failed to find a place for the service unit: {0}")]
+    PlaceServiceUnit(#[source] IOError),
+    #[error("Service unit {0} already exists. Pass --force to overwrite it.")]
+    ServiceUnitExists(String),
+    #[error("Failed to write service unit {0}: {1}")]
+    WriteServiceUnit(String, #[source] IOError),
+}
 
-    SettingsFileDeserializationFailed(SerdeJsonError),
-    SettingsFileNotFound(String),
-    SettingsFileNotReadable(IOError),
+// An error in one of the files where killjoy keeps state, such as silences, paused buses, runtime
+// rules and history, or in the event store. Codes are KJ5xxx.
+#[derive(Debug, ThisError)]
+pub enum StateError {
+    #[error("Failed to deserialize silences file: {0}")]
+    DeserializeSilencesFile(#[source] SerdeJsonError),
+    #[error("Found invalid duration: {0}")]
+    InvalidDuration(String),
+    #[error("Found no silence with ID {0}")]
+    NoSuchSilence(u32),
+    #[error("Failed to find a place for the silences file: {0}")]
+    PlaceSilencesFile(#[source] IOError),
+    #[error("Failed to read silences file: {0}")]
+    ReadSilencesFile(#[source] IOError),
+    #[error("Failed to serialize silences file: {0}")]
+    SerializeSilencesFile(#[source] SerdeJsonError),
+    #[error("Failed to write silences file: {0}")]
+    WriteSilencesFile(#[source] IOError),
+    #[error("Failed to deserialize paused buses file: {0}")]
+    DeserializePausedBusesFile(#[source] SerdeJsonError),
+    #[error("Failed to find a place for the paused buses file: {0}")]
+    PlacePausedBusesFile(#[source] IOError),
+    #[error("Failed to read paused buses file: {0}")]
+    ReadPausedBusesFile(#[source] IOError),
+    #[error("Failed to serialize paused buses file: {0}")]
+    SerializePausedBusesFile(#[source] SerdeJsonError),
+    #[error("Failed to write paused buses file: {0}")]
+    WritePausedBusesFile(#[source] IOError),
+    #[error("Failed to deserialize runtime rules file: {0}")]
+    DeserializeRuntimeRulesFile(#[source] SerdeJsonError),
+    #[error("Found no runtime rule with ID {0}")]
+    NoSuchRuntimeRule(u32),
+    #[error("Failed to parse rule: {0}")]
+    ParseRuntimeRule(#[source] SerdeJsonError),
+    #[error("Failed to find a place for the runtime rules file: {0}")]
+    PlaceRuntimeRulesFile(#[source] IOError),
+    #[error("Failed to read runtime rules file: {0}")]
+    ReadRuntimeRulesFile(#[source] IOError),
+    #[error("Failed to serialize runtime rules file: {0}")]
+    SerializeRuntimeRulesFile(#[source] SerdeJsonError),
+    #[error("Failed to write runtime rules file: {0}")]
+    WriteRuntimeRulesFile(#[source] IOError),
+    #[error("Failed to deserialize history file: {0}")]
+    DeserializeHistoryFile(#[source] SerdeJsonError),
+    #[error("Failed to find a place for the history file: {0}")]
+    PlaceHistoryFile(#[source] IOError),
+    #[error("Failed to read history file: {0}")]
+    ReadHistoryFile(#[source] IOError),
+    #[error("Failed to serialize event for event log: {0}")]
+    SerializeEventLog(#[source] SerdeJsonError),
+    #[error("Failed to serialize history file: {0}")]
+    SerializeHistoryFile(#[source] SerdeJsonError),
+    #[error("Failed to write event log: {0}")]
+    WriteEventLog(#[source] IOError),
+    #[error("Failed to write history file: {0}")]
+    WriteHistoryFile(#[source] IOError),
+    #[error("The event store isn't supported, as killjoy was built without the sqlite feature")]
+    EventStoreNotSupported,
+    #[error("Found invalid timestamp: {0} (expected e.g. 2019-03-14T15:09:26Z)")]
+    InvalidTimestamp(String),
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to open event store: {0}")]
+    OpenEventStore(#[source] SqliteError),
+    #[error("Failed to find a place for the event store: {0}")]
+    PlaceEventStore(#[source] IOError),
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to query event store: {0}")]
+    QueryEventStore(#[source] SqliteError),
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to write to event store: {0}")]
+    WriteEventStore(#[source] SqliteError),
+}
 
+// An error while serving the control socket. Codes are KJ6xxx.
+#[derive(Debug, ThisError)]
+pub enum ControlError {
+    #[error("Failed to accept a connection on the control socket: {0}")]
+    AcceptControlConnection(#[source] IOError),
+    #[error("Failed to create the control socket: {0}")]
+    BindControlSocket(#[source] IOError),
+    #[error("Failed to serve a client of the control socket: {0}")]
+    ServeControlConnection(#[source] IOError),
+}
+
+// An error while running an action, a filter, or an external command. Codes are KJ7xxx.
+#[derive(Debug, ThisError)]
+pub enum ActionError {
+    #[error("Failed to send heartbeat: curl failed: {0}")]
+    HeartbeatFailed(ExitStatus),
+    #[error("journalctl failed: {0}")]
+    JournalctlFailed(ExitStatus),
+    #[error("journalctl didn't finish within {0}s")]
+    JournalctlTimedOut(u64),
+    #[error("Failed to run command '{0}': {1}")]
+    RunCommand(String, #[source] IOError),
+    #[error("Command '{0}' failed: {1}")]
+    RunCommandFailed(String, ExitStatus),
+    #[error("Failed to run curl: {0}")]
+    RunCurl(#[source] IOError),
+    #[error("Failed to run journalctl: {0}")]
+    RunJournalctl(#[source] IOError),
+    #[error("Failed to write journal entry: {0}")]
+    WriteJournalEntry(#[source] IOError),
+    #[error("Failed to send data to Zabbix server \"{0}\": {1}")]
+    ContactZabbixServer(String, #[source] IOError),
+    #[error("{0} has a filter which failed: {1}")]
+    FilterFailed(String, String),
+}
+
+// An error in the settings file, or in a rule or notifier built from it. Codes are KJ1xxx.
+#[derive(Debug, ThisError)]
+pub enum SettingsError {
+    #[error("{}: {} is not a valid {}{}", fmt_pointer(.0), .1, .2, fmt_suggestion(.3))]
+    InvalidSettingsValue(String, String, &'static str, Option<String>),
+    #[error("{}: required key is missing", fmt_pointer(.0))]
+    MissingSettingsKey(String),
+    #[error("Failed to deserialize the settings file: {0}")]
+    SettingsFileDeserializationFailed(#[source] SerdeJsonError),
+    #[error(
+        "Failed to find a configuration file in $XDG_CONFIG_HOME or $XDG_CONFIG_DIRS with path {0}"
+    )]
+    SettingsFileNotFound(String),
+    #[error("Failed to read settings file: {0}")]
+    SettingsFileNotReadable(#[source] IOError),
+    #[error("Failed to serialize the settings file: {0}")]
+    SettingsFileNotSerializable(#[source] SerdeJsonError),
+    #[error("Failed to write settings file: {0}")]
+    SettingsFileNotWritable(#[source] IOError),
+    #[error("{}: unknown key{}", fmt_pointer(.0), fmt_suggestion(.1))]
+    UnknownSettingsKey(String, Option<String>),
+    #[error("Settings file version {} is not supported. Supported versions are 1 through {}.", .0, crate::settings::CURRENT_VERSION)]
+    UnsupportedSettingsVersion(u64),
+    #[error("{}: expected {}", fmt_pointer(.0), .1)]
+    WrongSettingsType(String, &'static str),
+    #[error("Found more than one rule named: {0}")]
+    DuplicateRuleName(String),
+    #[error("Found both {0} and {0}_credential; set only one of them")]
+    ConflictingCredential(String),
+    #[error(
+        "Failed to read credential {0}: $CREDENTIALS_DIRECTORY is unset (see LoadCredential=)"
+    )]
+    CredentialsDirectoryNotSet(String),
+    #[error("Found invalid credential name (expected a file name): {0}")]
+    InvalidCredentialName(String),
+    #[error("Failed to read credential {0}: {1}")]
+    ReadCredential(String, #[source] IOError),
+    #[error(
+        "{1} uses notifier \"{0}\" on the session bus, but killjoy has no session bus to connect to"
+    )]
+    SessionNotifierWithoutSessionBus(String, String),
+    #[error("Found invalid sub-state: {0}")]
+    InvalidSubState(String),
+    #[error("Rule for '{0}' lists sub-states, but no active states.")]
+    MissingActiveStatesForSubStates(String),
+    #[error("Found invalid {0}: it must be greater than zero")]
+    InvalidPollingInterval(&'static str),
+    #[error("Found invalid transport: {0}")]
+    InvalidTransport(String),
+    #[error("Found invalid D-Bus interface name: {0}")]
+    InvalidInterface(String),
+    #[error("Found invalid watch limit: max_units must be greater than zero")]
+    InvalidWatchLimit,
+    #[error(
+        "MQTT notifier for '{0}' has credentials, which are sent unencrypted (set \
+         plaintext_credentials to allow this)"
+    )]
+    PlaintextMqttCredentials(String),
+    #[error("Found invalid action type: {0}")]
+    InvalidActionType(String),
+    #[error("Found invalid active state: {0}")]
     InvalidActiveState(String),
+    #[error("Found invalid bus name: {0}")]
     InvalidBusName(String),
+    #[error("Found invalid bus type: {0}")]
     InvalidBusType(String),
+    #[error("Found invalid clock: {0}")]
+    InvalidClock(String),
+    #[error("Found invalid X-KilljoyNotify directive in {0} (expected states:notifiers): {1}")]
+    InvalidDiscoveryDirective(String, String),
+    #[error("Found invalid event log format: {0}")]
+    InvalidEventLogFormat(String),
+    #[error("Found invalid expression type: {0}")]
     InvalidExpressionType(String),
+    #[error("Found invalid Alertmanager URL (expected http:// or https://): {0}")]
+    InvalidAlertmanagerUrl(String),
+    #[error("Found invalid grouping: {0}")]
+    InvalidGroupBy(String),
+    #[error("Found invalid group window: it must be at least one second")]
+    InvalidGroupWindow,
+    #[error("Found invalid heartbeat interval: it must be at least one second")]
+    InvalidHeartbeatInterval,
+    #[error("Found invalid heartbeat URL (expected http:// or https://): {0}")]
+    InvalidHeartbeatUrl(String),
+    #[error("Found invalid hostname (expected no whitespace): '{0}'")]
+    InvalidHostname(String),
+    #[error("Found invalid label name: {0}")]
+    InvalidLabelName(String),
+    #[error("Found invalid load state: {0}")]
+    InvalidLoadState(String),
+    #[error("Found invalid manager property: {0}. Only SystemState may be watched.")]
+    InvalidManagerProperty(String),
+    #[error("Found invalid MQTT quality of service: {0} (expected 0, 1 or 2)")]
+    InvalidMqttQos(u32),
+    #[error("Found invalid MQTT topic '{0}': wildcards may not be published to")]
+    InvalidMqttTopic(String),
+    #[error("Rule references non-existent notifier: {0}")]
     InvalidNotifier(String),
-    InvalidRegex(RegexError),
-
-    // Like dbus::Error, but with more granular semantics, and implements Send.
-    AddSignalMatch(String, ExternDBusError),
-    CallOrgFreedesktopDBusPropertiesGetAll(ExternDBusError),
-    CallOrgFreedesktopSystemd1ManagerGetUnit(ExternDBusError),
-    CallOrgFreedesktopSystemd1ManagerListUnits(ExternDBusError),
-    CallOrgFreedesktopSystemd1ManagerSubscribe(ExternDBusError),
-    CastBusNameToStr(Utf8Error),
+    #[error("Found invalid notifier mode: {0}")]
+    InvalidNotifierMode(String),
+    #[error("Found invalid notifier policy: {0}")]
+    InvalidNotifierPolicy(String),
+    #[error("Found invalid notifier timeout: it must be at least one second")]
+    InvalidNotifierTimeout,
+    #[error("Found invalid notifier type: {0}")]
+    InvalidNotifierType(String),
+    #[error("Found invalid portable image state: {0}")]
+    InvalidPortableState(String),
+    #[error("Found invalid property condition: {0}")]
+    InvalidPropertyCondition(String),
+    #[error("Found invalid notifier protocol version: {0}")]
+    InvalidProtocolVersion(u32),
+    #[error("Found invalid regular expression: {0}")]
+    InvalidRegex(#[source] RegexError),
+    #[error("Found invalid rule evaluation mode: {0}")]
+    InvalidRuleEvaluation(String),
+    #[error("Found invalid rule name: '{0}'")]
+    InvalidRuleName(String),
+    #[error("Found invalid time window '{0}': {1}")]
+    InvalidSchedule(String, String),
+    #[error("Found invalid severity: {0}")]
+    InvalidSeverity(String),
+    #[error("Found invalid SNMP OID: {0}")]
+    InvalidSnmpOid(String),
+    #[error("Found invalid syslog facility: {0}")]
+    InvalidSyslogFacility(String),
+    #[error("Found invalid syslog transport: {0}")]
+    InvalidSyslogTransport(String),
+    #[error("Found invalid system state: {0}")]
+    InvalidSystemState(String),
+    #[error("Found invalid systemd call timeout: it must be at least one millisecond")]
+    InvalidSystemdCallTimeout,
+    #[error("Found invalid template '{0}': {1}")]
+    InvalidTemplate(String, String),
+    #[error("Found invalid unit file state: {0}")]
+    InvalidUnitFileState(String),
+    #[error("Found invalid unit template: {0} (expected a name like \"foo@.service\")")]
+    InvalidUnitTemplate(String),
+    #[error("A run-command action lacks a command.")]
+    MissingActionCommand,
+    #[error("Rule for '{0}' lacks a bus type, and no default is set.")]
+    MissingBusType(String),
+    #[error("Notifier {0} sends digests, but lacks a digest schedule.")]
+    MissingDigestSchedule(String),
+    #[error("A {0} notifier lacks the required key: {1}")]
+    MissingNotifierKey(&'static str, &'static str),
+    #[error("Rule for '{0}' lacks notifiers, and no default is set.")]
+    MissingNotifiers(String),
+    #[error("A condition on property {0} lacks a value to compare against.")]
+    MissingPropertyThreshold(String),
+    #[error("Rule for '{0}' has no states of interest.")]
+    MissingStates(String),
+    #[error("Filters aren't supported, as killjoy was built without the scripting feature")]
+    FilterNotSupported,
+    #[error("Found invalid filter: {0}")]
+    InvalidFilter(String),
+}
+
+// An error while talking to D-Bus or systemd. Codes are KJ2xxx.
+//
+// Like dbus::Error, but with more granular semantics, and implements Send.
+#[derive(Debug, ThisError)]
+pub enum BusError {
+    #[error("Failed to add match string '{0}': {1}")]
+    AddSignalMatch(String, #[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.DBus.ListActivatableNames: {0}")]
+    CallOrgFreedesktopDBusListActivatableNames(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.DBus.NameHasOwner: {0}")]
+    CallOrgFreedesktopDBusNameHasOwner(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.DBus.Properties.Get: {0}")]
+    CallOrgFreedesktopDBusPropertiesGet(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.DBus.Properties.GetAll: {0}")]
+    CallOrgFreedesktopDBusPropertiesGetAll(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.portable1.Manager.ListImages: {0}")]
+    CallOrgFreedesktopPortable1ManagerListImages(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.GetUnit: {0}")]
+    CallOrgFreedesktopSystemd1ManagerGetUnit(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.ListUnitFiles: {0}")]
+    CallOrgFreedesktopSystemd1ManagerListUnitFiles(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.ListUnits: {0}")]
+    CallOrgFreedesktopSystemd1ManagerListUnits(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.RestartUnit: {0}")]
+    CallOrgFreedesktopSystemd1ManagerRestartUnit(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.StartUnit: {0}")]
+    CallOrgFreedesktopSystemd1ManagerStartUnit(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.StopUnit: {0}")]
+    CallOrgFreedesktopSystemd1ManagerStopUnit(#[source] ExternDBusError),
+    #[error("Failed to call org.freedesktop.systemd1.Manager.Subscribe: {0}")]
+    CallOrgFreedesktopSystemd1ManagerSubscribe(#[source] ExternDBusError),
+    #[error("Failed to cast bus name to UTF-8 string: {0}")]
+    CastBusNameToStr(#[source] Utf8Error),
+    #[error("Failed to cast org.freedesktop.systemd1.Unit.ActiveState to a string.")]
     CastOrgFreedesktopSystemd1UnitActiveState,
+    #[error("Failed to cast org.freedesktop.systemd1.Unit.Id to a string.")]
     CastOrgFreedesktopSystemd1UnitId,
+    #[error("Failed to cast org.freedesktop.systemd1.Unit.LoadState to a string.")]
+    CastOrgFreedesktopSystemd1UnitLoadState,
+    #[error("Failed to cast org.freedesktop.systemd1.Unit.{0} to a u64.")]
     CastOrgFreedesktopSystemd1UnitTimestamp(&'static str),
+    #[error("{0}")]
     CastStrToPath(String),
-    ConnectToBus(ExternDBusError),
-    GetOrgFreedesktopSystemd1UnitId(ExternDBusError),
+    #[error("Failed to connect to D-Bus bus. Cause: {0}")]
+    ConnectToBus(#[source] ExternDBusError),
+    #[error("Failed to connect to the {0} bus. Cause: {1}")]
+    ConnectToBusType(&'static str, #[source] ExternDBusError),
+    #[error("Failed to connect to systemd's Varlink socket at {0}. Cause: {1}")]
+    ConnectToVarlink(String, #[source] ExternDBusError),
+    #[error("Failed to get org.freedesktop.systemd1.Unit.Id for: {0}")]
+    GetOrgFreedesktopSystemd1UnitId(#[source] ExternDBusError),
+    #[error("Failed to handle {0} signal: {1}")]
+    HandleSignal(&'static str, #[source] Box<Error>),
+    #[error("Failed to get path from message headers.")]
     MessageLacksPath,
+    #[error("A unit's properties lacks the ActiveState property.")]
     PropertiesLacksActiveState,
+    #[error("A unit has entered the {0:?} state, but that unit's properties lack a timestamp named '{1}'.")]
     PropertiesLacksTimestamp(ActiveState, &'static str),
-    RemoveSignalMatch(String, ExternDBusError),
+    #[error("Failed to remove match string '{0}': {1}")]
+    RemoveSignalMatch(String, #[source] ExternDBusError),
+    #[error("{0} matches no units loaded on the {1} bus")]
+    RuleMatchesNoUnits(String, &'static str),
+    #[error("Failed to wait for messages from the bus: {0}")]
+    PollConnection(#[source] IOError),
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+// An error while looking up or contacting a notifier. Codes are KJ3xxx.
+#[derive(Debug, ThisError)]
+pub enum NotifierError {
+    #[error("Found no notifier named \"{0}\" in the settings file")]
+    NoSuchNotifier(String),
+    #[error("Notifier \"{0}\" has a bus name which nothing owns or can activate: {1}")]
+    NotifierHasNoOwner(String, String),
+    #[error("Error occurred when contacting notifier \"{0}\": {1}")]
+    NotifyNotifier(String, #[source] ExternDBusError),
+    #[error("Failed to ping notifier \"{0}\": {1}")]
+    PingNotifier(String, #[source] ExternDBusError),
+    #[error("Failed to send test notification to notifier \"{0}\": {1}")]
+    SendTestNotification(String, #[source] ExternDBusError),
+    #[error("Failed to publish to MQTT notifier \"{0}\": {1}")]
+    ContactMqttBroker(String, #[source] IOError),
+    #[error("Failed to send trap to SNMP notifier \"{0}\": {1}")]
+    ContactSnmpManager(String, #[source] IOError),
+    #[error("Failed to log to syslog notifier \"{0}\": {1}")]
+    ContactSyslogServer(String, #[source] IOError),
+    #[error("The thread contacting notifier \"{0}\" panicked")]
+    NotifierThreadPanicked(String),
+    #[error("Plugin for notifier \"{0}\" failed: {1}")]
+    PluginFailed(String, String),
+    #[error("Failed to run plugin for notifier \"{0}\": {1}")]
+    RunPlugin(String, #[source] IOError),
+    #[error("Failed to run curl for Alertmanager notifier \"{0}\": {1}")]
+    ContactAlertmanager(String, #[source] IOError),
+    #[error("Alertmanager notifier \"{0}\" rejected the alert: curl exited with {1}")]
+    AlertmanagerFailed(String, ExitStatus),
+    #[error(
+        "Notifier \"{0}\" is on the system bus, but no D-Bus policy allows sending to {1}, so \
+         notifications will likely be rejected. Add a policy such as:\n{2}"
+    )]
+    NotifierDeniedByPolicy(String, String, String),
+}
+
+impl Error {
+    // Get the status that killjoy exits with because of this error.
+    //
+    // Problems with the settings file or with command line arguments give `EXIT_USAGE`, failing to
+    // connect to a bus gives `EXIT_BUS_CONNECTION`, failing while watching units gives
+    // `EXIT_MONITORING`, and failing to contact a notifier gives `EXIT_NOTIFIER`. Anything else
+    // gives `EXIT_FAILURE`.
+    pub fn exit_code(&self) -> i32 {
         match self {
-            Error::MissingLoopTimeoutArg => {
-                write!(f, "Failed to get --loop-timeout argument. A default should've been set in the argument parser.")
-            }
-            Error::MonitoringThreadPanicked(err) => {
-                write!(f, "A monitoring thread panicked. Source: {:?}", err)
-            }
-            Error::ParseLoopTimeoutArg(err) => {
-                write!(f, "Failed to parse --loop-timeout argument: {:?}", err)
-            }
-            Error::UnexpectedSubcommand(subcmd_opt) => match subcmd_opt {
-                Some(subcmd) => write!(f, "An unexpected subcommand was encountered: {}", subcmd),
-                None => write!(f, "An unexpected subcommand was encountered."),
-            }
-
-            Error::SettingsFileDeserializationFailed(err) => {
-                write!(f, "Failed to deserialize the settings file: {}", err,)
-            }
-            Error::SettingsFileNotFound(path) => write!(
-                f,
-                "Failed to find a configuration file in $XDG_CONFIG_HOME or $XDG_CONFIG_DIRS with path {}",
-                path
-            ),
-            Error::SettingsFileNotReadable(err) => {
-                write!(f, "Failed to read settings file: {}", err)
-            }
-
-            Error::InvalidActiveState(as_str) => {
-                write!(f, "Found invalid active state: {}", as_str)
-            }
-            Error::InvalidBusName(bn_str) => {
-                write!(f, "Found invalid bus name: {}", bn_str)
-            }
-            Error::InvalidBusType(bt_str) => {
-                write!(f, "Found invalid bus type: {}", bt_str)
-            }
-            Error::InvalidExpressionType(et_str) => {
-                write!(f, "Found invalid expression type: {}", et_str)
-            }
-            Error::InvalidRegex(err) => {
-                write!(f, "Found invalid regular expression: {}", err)
-            }
-            Error::InvalidNotifier(notifier) => {
-                write!(f, "Rule references non-existent notifier: {}", notifier)
-            }
-
-            Error::AddSignalMatch(match_str, source) => {
-                write!(f, "Failed to add match string '{}': {}", match_str, source)
-            }
-            Error::CallOrgFreedesktopDBusPropertiesGetAll(source) => {
-                write!(f, "Failed to call org.freedesktop.DBus.Properties.GetAll: {}", source)
-            }
-            Error::CallOrgFreedesktopSystemd1ManagerGetUnit(source) => {
-                write!(f, "Failed to call org.freedesktop.systemd1.Manager.GetUnit: {}", source)
-            }
-            Error::CallOrgFreedesktopSystemd1ManagerListUnits(source) => {
-                write!(f, "Failed to call org.freedesktop.systemd1.Manager.ListUnits: {}", source)
-            }
-            Error::CallOrgFreedesktopSystemd1ManagerSubscribe(source) => {
-                write!(f, "Failed to call org.freedesktop.systemd1.Manager.Subscribe: {}", source)
-            }
-            Error::CastBusNameToStr(source) => {
-                write!(f, "Failed to cast bus name to UTF-8 string: {}", source)
-            }
-            Error::CastOrgFreedesktopSystemd1UnitTimestamp(timestamp_key) => {
-                write!(f, "Failed to cast org.freedesktop.systemd1.Unit.{} to a u64.", timestamp_key)
-            }
-            Error::CastOrgFreedesktopSystemd1UnitActiveState => {
-                write!(f, "Failed to cast org.freedesktop.systemd1.Unit.ActiveState to a string.")
-            }
-            Error::CastOrgFreedesktopSystemd1UnitId => {
-                write!(f, "Failed to cast org.freedesktop.systemd1.Unit.Id to a string.")
-            }
-            Error::CastStrToPath(source) => {
-                write!(f, "{}", source)
-            }
-            Error::ConnectToBus(source) => {
-                write!(f, "Failed to connect to D-Bus bus. Cause: {}", source)
-            }
-            Error::GetOrgFreedesktopSystemd1UnitId(source) => {
-                write!(f, "Failed to get org.freedesktop.systemd1.Unit.Id for: {}", source)
-            }
-            Error::MessageLacksPath => {
-                write!(f, "Failed to get path from message headers.")
-            }
-            Error::PropertiesLacksActiveState => {
-                write!(f, "A unit's properties lacks the ActiveState property.")
-            }
-            Error::PropertiesLacksTimestamp(active_state, timestamp_key) => write!(
-                f,
-                "A unit has entered the {:?} state, but that unit's properties lack a timestamp named '{}'.",
-                active_state, timestamp_key
-            ),
-            Error::RemoveSignalMatch(match_str, source) => {
-                write!(f, "Failed to remove match string '{}': {}", match_str, source)
-            }
+            Error::Settings(_) => EXIT_USAGE,
+            Error::Bus(BusError::ConnectToBus(_))
+            | Error::Bus(BusError::ConnectToBusType(..))
+            | Error::Bus(BusError::ConnectToVarlink(..)) => EXIT_BUS_CONNECTION,
+            Error::Bus(_) => EXIT_MONITORING,
+            Error::Notifier(NotifierError::NoSuchNotifier(_)) => EXIT_USAGE,
+            Error::Notifier(_) => EXIT_NOTIFIER,
+            Error::MissingLoopTimeoutArg
+            | Error::ParseLoopTimeoutArg(_)
+            | Error::State(StateError::InvalidDuration(_))
+            | Error::State(StateError::InvalidTimestamp(_))
+            | Error::State(StateError::ParseRuntimeRule(_))
+            | Error::State(StateError::EventStoreNotSupported) => EXIT_USAGE,
+            Error::MonitoringThreadPanicked(_) => EXIT_MONITORING,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Settings(err) => err.code(),
+            Error::Bus(err) => err.code(),
+            Error::Notifier(err) => err.code(),
+            Error::State(err) => err.code(),
+            Error::Control(err) => err.code(),
+            Error::Action(err) => err.code(),
+            Error::MissingLoopTimeoutArg => "KJ4001",
+            Error::MonitoringThreadPanicked(..) => "KJ4002",
+            Error::ParseLoopTimeoutArg(..) => "KJ4003",
+            Error::UnexpectedSubcommand(..) => "KJ4004",
+            Error::WriteManPage(..) => "KJ4005",
+            Error::GetExecutablePath(..) => "KJ4006",
+            Error::PlaceServiceUnit(..) => "KJ4007",
+            Error::ServiceUnitExists(..) => "KJ4008",
+            Error::WriteServiceUnit(..) => "KJ4009",
+        }
+    }
+
+    // Display this error with its code in front, like "[KJ1005] Failed to read settings file: ...".
+    pub fn with_code(&self) -> WithCode {
+        WithCode(self)
+    }
+}
+
+impl SettingsError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SettingsError::InvalidSettingsValue(..) => "KJ1001",
+            SettingsError::MissingSettingsKey(..) => "KJ1002",
+            SettingsError::SettingsFileDeserializationFailed(..) => "KJ1003",
+            SettingsError::SettingsFileNotFound(..) => "KJ1004",
+            SettingsError::SettingsFileNotReadable(..) => "KJ1005",
+            SettingsError::SettingsFileNotSerializable(..) => "KJ1006",
+            SettingsError::SettingsFileNotWritable(..) => "KJ1007",
+            SettingsError::UnknownSettingsKey(..) => "KJ1008",
+            SettingsError::UnsupportedSettingsVersion(..) => "KJ1009",
+            SettingsError::WrongSettingsType(..) => "KJ1010",
+            SettingsError::DuplicateRuleName(..) => "KJ1011",
+            SettingsError::InvalidActionType(..) => "KJ1012",
+            SettingsError::InvalidActiveState(..) => "KJ1013",
+            SettingsError::InvalidBusName(..) => "KJ1014",
+            SettingsError::InvalidBusType(..) => "KJ1015",
+            SettingsError::InvalidClock(..) => "KJ1016",
+            SettingsError::InvalidEventLogFormat(..) => "KJ1017",
+            SettingsError::InvalidExpressionType(..) => "KJ1018",
+            SettingsError::InvalidHeartbeatInterval => "KJ1019",
+            SettingsError::InvalidHeartbeatUrl(..) => "KJ1020",
+            SettingsError::InvalidHostname(..) => "KJ1021",
+            SettingsError::InvalidLabelName(..) => "KJ1022",
+            SettingsError::InvalidLoadState(..) => "KJ1023",
+            SettingsError::InvalidManagerProperty(..) => "KJ1024",
+            SettingsError::InvalidMqttQos(..) => "KJ1025",
+            SettingsError::InvalidMqttTopic(..) => "KJ1026",
+            SettingsError::InvalidNotifier(..) => "KJ1027",
+            SettingsError::InvalidNotifierMode(..) => "KJ1028",
+            SettingsError::InvalidNotifierPolicy(..) => "KJ1029",
+            SettingsError::InvalidNotifierTimeout => "KJ1030",
+            SettingsError::InvalidNotifierType(..) => "KJ1031",
+            SettingsError::InvalidPortableState(..) => "KJ1032",
+            SettingsError::InvalidPropertyCondition(..) => "KJ1033",
+            SettingsError::InvalidProtocolVersion(..) => "KJ1034",
+            SettingsError::InvalidRegex(..) => "KJ1035",
+            SettingsError::InvalidRuleEvaluation(..) => "KJ1036",
+            SettingsError::InvalidRuleName(..) => "KJ1037",
+            SettingsError::InvalidSchedule(..) => "KJ1038",
+            SettingsError::InvalidSeverity(..) => "KJ1039",
+            SettingsError::InvalidSnmpOid(..) => "KJ1040",
+            SettingsError::InvalidSyslogFacility(..) => "KJ1041",
+            SettingsError::InvalidSyslogTransport(..) => "KJ1042",
+            SettingsError::InvalidSystemState(..) => "KJ1043",
+            SettingsError::InvalidSystemdCallTimeout => "KJ1044",
+            SettingsError::InvalidTemplate(..) => "KJ1045",
+            SettingsError::InvalidUnitFileState(..) => "KJ1046",
+            SettingsError::InvalidUnitTemplate(..) => "KJ1047",
+            SettingsError::MissingActionCommand => "KJ1048",
+            SettingsError::MissingBusType(..) => "KJ1049",
+            SettingsError::MissingDigestSchedule(..) => "KJ1050",
+            SettingsError::MissingNotifierKey(..) => "KJ1051",
+            SettingsError::MissingNotifiers(..) => "KJ1052",
+            SettingsError::MissingPropertyThreshold(..) => "KJ1053",
+            SettingsError::MissingStates(..) => "KJ1054",
+            SettingsError::FilterNotSupported => "KJ1055",
+            SettingsError::InvalidFilter(..) => "KJ1056",
+            SettingsError::InvalidAlertmanagerUrl(..) => "KJ1057",
+            SettingsError::InvalidGroupBy(..) => "KJ1058",
+            SettingsError::InvalidGroupWindow => "KJ1059",
+            SettingsError::InvalidDiscoveryDirective(..) => "KJ1060",
+            SettingsError::ConflictingCredential(..) => "KJ1061",
+            SettingsError::CredentialsDirectoryNotSet(..) => "KJ1062",
+            SettingsError::InvalidCredentialName(..) => "KJ1063",
+            SettingsError::ReadCredential(..) => "KJ1064",
+            SettingsError::SessionNotifierWithoutSessionBus(..) => "KJ1065",
+            SettingsError::InvalidSubState(..) => "KJ1066",
+            SettingsError::MissingActiveStatesForSubStates(..) => "KJ1067",
+            SettingsError::InvalidPollingInterval(..) => "KJ1068",
+            SettingsError::InvalidTransport(..) => "KJ1069",
+            SettingsError::InvalidInterface(..) => "KJ1070",
+            SettingsError::InvalidWatchLimit => "KJ1071",
+            SettingsError::PlaintextMqttCredentials(..) => "KJ1072",
+        }
+    }
+}
+
+impl BusError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BusError::AddSignalMatch(..) => "KJ2001",
+            BusError::CallOrgFreedesktopDBusListActivatableNames(..) => "KJ2002",
+            BusError::CallOrgFreedesktopDBusNameHasOwner(..) => "KJ2003",
+            BusError::CallOrgFreedesktopDBusPropertiesGet(..) => "KJ2004",
+            BusError::CallOrgFreedesktopDBusPropertiesGetAll(..) => "KJ2005",
+            BusError::CallOrgFreedesktopPortable1ManagerListImages(..) => "KJ2006",
+            BusError::CallOrgFreedesktopSystemd1ManagerGetUnit(..) => "KJ2007",
+            BusError::CallOrgFreedesktopSystemd1ManagerListUnitFiles(..) => "KJ2008",
+            BusError::CallOrgFreedesktopSystemd1ManagerListUnits(..) => "KJ2009",
+            BusError::CallOrgFreedesktopSystemd1ManagerRestartUnit(..) => "KJ2010",
+            BusError::CallOrgFreedesktopSystemd1ManagerStartUnit(..) => "KJ2011",
+            BusError::CallOrgFreedesktopSystemd1ManagerStopUnit(..) => "KJ2012",
+            BusError::CallOrgFreedesktopSystemd1ManagerSubscribe(..) => "KJ2013",
+            BusError::CastBusNameToStr(..) => "KJ2014",
+            BusError::CastOrgFreedesktopSystemd1UnitActiveState => "KJ2015",
+            BusError::CastOrgFreedesktopSystemd1UnitId => "KJ2016",
+            BusError::CastOrgFreedesktopSystemd1UnitLoadState => "KJ2017",
+            BusError::CastOrgFreedesktopSystemd1UnitTimestamp(..) => "KJ2018",
+            BusError::CastStrToPath(..) => "KJ2019",
+            BusError::ConnectToBus(..) => "KJ2020",
+            BusError::ConnectToBusType(..) => "KJ2021",
+            BusError::GetOrgFreedesktopSystemd1UnitId(..) => "KJ2022",
+            BusError::HandleSignal(..) => "KJ2023",
+            BusError::MessageLacksPath => "KJ2024",
+            BusError::PropertiesLacksActiveState => "KJ2025",
+            BusError::PropertiesLacksTimestamp(..) => "KJ2026",
+            BusError::RemoveSignalMatch(..) => "KJ2027",
+            BusError::RuleMatchesNoUnits(..) => "KJ2028",
+            BusError::PollConnection(..) => "KJ2029",
+            BusError::ConnectToVarlink(..) => "KJ2030",
+        }
+    }
+}
+
+impl NotifierError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            NotifierError::NoSuchNotifier(..) => "KJ3001",
+            NotifierError::NotifierHasNoOwner(..) => "KJ3002",
+            NotifierError::NotifyNotifier(..) => "KJ3003",
+            NotifierError::PingNotifier(..) => "KJ3004",
+            NotifierError::SendTestNotification(..) => "KJ3005",
+            NotifierError::ContactMqttBroker(..) => "KJ3006",
+            NotifierError::ContactSnmpManager(..) => "KJ3007",
+            NotifierError::ContactSyslogServer(..) => "KJ3008",
+            NotifierError::NotifierThreadPanicked(..) => "KJ3009",
+            NotifierError::PluginFailed(..) => "KJ3010",
+            NotifierError::RunPlugin(..) => "KJ3011",
+            NotifierError::ContactAlertmanager(..) => "KJ3012",
+            NotifierError::AlertmanagerFailed(..) => "KJ3013",
+            NotifierError::NotifierDeniedByPolicy(..) => "KJ3014",
         }
     }
 }
 
-impl StdError for Error {
-    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+impl StateError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
         match self {
-            Error::MissingLoopTimeoutArg => None,
-            Error::MonitoringThreadPanicked(_) => None,
-            Error::ParseLoopTimeoutArg(err) => Some(err),
-            Error::UnexpectedSubcommand(_) => None,
-
-            Error::SettingsFileDeserializationFailed(err) => Some(err),
-            Error::SettingsFileNotFound(_) => None,
-            Error::SettingsFileNotReadable(err) => Some(err),
-
-            Error::InvalidActiveState(_) => None,
-            Error::InvalidBusName(_) => None,
-            Error::InvalidBusType(_) => None,
-            Error::InvalidExpressionType(_) => None,
-            Error::InvalidNotifier(_) => None,
-            Error::InvalidRegex(err) => Some(err),
-
-            // To be flattened.
-            Error::AddSignalMatch(_, err) => Some(err),
-            Error::CallOrgFreedesktopDBusPropertiesGetAll(err) => Some(err),
-            Error::CallOrgFreedesktopSystemd1ManagerGetUnit(err) => Some(err),
-            Error::CallOrgFreedesktopSystemd1ManagerListUnits(err) => Some(err),
-            Error::CallOrgFreedesktopSystemd1ManagerSubscribe(err) => Some(err),
-            Error::CastBusNameToStr(err) => Some(err),
-            Error::CastOrgFreedesktopSystemd1UnitActiveState => None,
-            Error::CastOrgFreedesktopSystemd1UnitId => None,
-            Error::CastOrgFreedesktopSystemd1UnitTimestamp(_) => None,
-            Error::CastStrToPath(_) => None,
-            Error::ConnectToBus(err) => Some(err),
-            Error::GetOrgFreedesktopSystemd1UnitId(err) => Some(err),
-            Error::MessageLacksPath => None,
-            Error::PropertiesLacksActiveState => None,
-            Error::PropertiesLacksTimestamp(_, _) => None,
-            Error::RemoveSignalMatch(_, err) => Some(err),
+            StateError::DeserializeSilencesFile(..) => "KJ5001",
+            StateError::InvalidDuration(..) => "KJ5002",
+            StateError::NoSuchSilence(..) => "KJ5003",
+            StateError::PlaceSilencesFile(..) => "KJ5004",
+            StateError::ReadSilencesFile(..) => "KJ5005",
+            StateError::SerializeSilencesFile(..) => "KJ5006",
+            StateError::WriteSilencesFile(..) => "KJ5007",
+            StateError::DeserializePausedBusesFile(..) => "KJ5008",
+            StateError::PlacePausedBusesFile(..) => "KJ5009",
+            StateError::ReadPausedBusesFile(..) => "KJ5010",
+            StateError::SerializePausedBusesFile(..) => "KJ5011",
+            StateError::WritePausedBusesFile(..) => "KJ5012",
+            StateError::DeserializeRuntimeRulesFile(..) => "KJ5013",
+            StateError::NoSuchRuntimeRule(..) => "KJ5014",
+            StateError::ParseRuntimeRule(..) => "KJ5015",
+            StateError::PlaceRuntimeRulesFile(..) => "KJ5016",
+            StateError::ReadRuntimeRulesFile(..) => "KJ5017",
+            StateError::SerializeRuntimeRulesFile(..) => "KJ5018",
+            StateError::WriteRuntimeRulesFile(..) => "KJ5019",
+            StateError::DeserializeHistoryFile(..) => "KJ5020",
+            StateError::PlaceHistoryFile(..) => "KJ5021",
+            StateError::ReadHistoryFile(..) => "KJ5022",
+            StateError::SerializeEventLog(..) => "KJ5023",
+            StateError::SerializeHistoryFile(..) => "KJ5024",
+            StateError::WriteEventLog(..) => "KJ5025",
+            StateError::WriteHistoryFile(..) => "KJ5026",
+            StateError::EventStoreNotSupported => "KJ5027",
+            StateError::InvalidTimestamp(..) => "KJ5028",
+            #[cfg(feature = "sqlite")]
+            StateError::OpenEventStore(..) => "KJ5029",
+            StateError::PlaceEventStore(..) => "KJ5030",
+            #[cfg(feature = "sqlite")]
+            StateError::QueryEventStore(..) => "KJ5031",
+            #[cfg(feature = "sqlite")]
+            StateError::WriteEventStore(..) => "KJ5032",
+        }
+    }
+}
+
+impl ControlError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ControlError::AcceptControlConnection(..) => "KJ6001",
+            ControlError::BindControlSocket(..) => "KJ6002",
+            ControlError::ServeControlConnection(..) => "KJ6003",
+        }
+    }
+}
+
+impl ActionError {
+    // Get the code which identifies this kind of error in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ActionError::HeartbeatFailed(..) => "KJ7001",
+            ActionError::JournalctlFailed(..) => "KJ7002",
+            ActionError::RunCommand(..) => "KJ7003",
+            ActionError::RunCommandFailed(..) => "KJ7004",
+            ActionError::RunCurl(..) => "KJ7005",
+            ActionError::RunJournalctl(..) => "KJ7006",
+            ActionError::WriteJournalEntry(..) => "KJ7007",
+            ActionError::ContactZabbixServer(..) => "KJ7008",
+            ActionError::FilterFailed(..) => "KJ7009",
+            ActionError::JournalctlTimedOut(..) => "KJ7010",
+        }
+    }
+}
+
+// An error, displayed with its code in front. See `Error::with_code`.
+pub struct WithCode<'a>(&'a Error);
+
+impl Display for WithCode<'_> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "[{}] {}", self.0.code(), self.0)
+    }
+}
+
+// Format a JSON pointer into the settings file. The empty pointer refers to the whole file.
+fn fmt_pointer(pointer: &str) -> &str {
+    if pointer.is_empty() {
+        "(top level)"
+    } else {
+        pointer
+    }
+}
+
+// Format the hint which finishes a message about the settings file, if there is one.
+fn fmt_suggestion(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("; did you mean \"{}\"?", suggestion),
+        None => String::new(),
+    }
+}
+
+// Format the end of a message about an unexpected subcommand. clap doesn't guarantee a name.
+fn fmt_subcommand(subcmd_opt: &Option<String>) -> String {
+    match subcmd_opt {
+        Some(subcmd) => format!(": {}", subcmd),
+        None => ".".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_display_is_unchanged_by_wrapping() {
+        let err: Error = SettingsError::UnknownSettingsKey(
+            "/rules/0/expresion".to_string(),
+            Some("expression".to_string()),
+        )
+        .into();
+        assert_eq!(
+            err.to_string(),
+            "/rules/0/expresion: unknown key; did you mean \"expression\"?"
+        );
+        let err: Error = SettingsError::MissingSettingsKey(String::new()).into();
+        assert_eq!(err.to_string(), "(top level): required key is missing");
+        let err = Error::UnexpectedSubcommand(None);
+        assert_eq!(err.to_string(), "An unexpected subcommand was encountered.");
+    }
+
+    #[test]
+    fn test_with_code() {
+        let err: Error = BusError::MessageLacksPath.into();
+        assert_eq!(
+            err.with_code().to_string(),
+            "[KJ2024] Failed to get path from message headers."
+        );
+        let err: Error = NotifierError::NoSuchNotifier("pager".to_string()).into();
+        assert_eq!(
+            err.with_code().to_string(),
+            "[KJ3001] Found no notifier named \"pager\" in the settings file"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err: Error = SettingsError::MissingStates("foo.service".to_string()).into();
+        assert_eq!(err.exit_code(), EXIT_USAGE);
+        let err: Error = BusError::MessageLacksPath.into();
+        assert_eq!(err.exit_code(), EXIT_MONITORING);
+        let err: Error = NotifierError::PluginFailed("pager".to_string(), String::new()).into();
+        assert_eq!(err.exit_code(), EXIT_NOTIFIER);
+        let err: Error = StateError::InvalidDuration(String::new()).into();
+        assert_eq!(err.exit_code(), EXIT_USAGE);
+        let err: Error = StateError::NoSuchSilence(1).into();
+        assert_eq!(err.exit_code(), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_codes_are_unique() {
+        // Every code is a string literal in one of the `code()` methods above, so check them all.
+        let source = include_str!("error.rs");
+        let source = &source[..source.find("#[cfg(test)]\nmod tests").unwrap()];
+        let codes: Vec<&str> = source
+            .lines()
+            .filter_map(|line| line.split("=> \"KJ").nth(1))
+            .map(|rest| rest.trim_end_matches("\","))
+            .collect();
+        assert!(codes.len() > 150);
+        let unique: HashSet<&str> = codes.iter().cloned().collect();
+        assert_eq!(unique.len(), codes.len());
+        for code in codes {
+            assert!(code.len() == 4 && code.parse::<u16>().is_ok(), "KJ{}", code);
+        }
+    }
+
+    #[test]
+    fn test_wrapped_codes() {
+        let errs: Vec<(Error, &str)> = vec![
+            (Error::MissingLoopTimeoutArg, "KJ4001"),
+            (SettingsError::MissingActionCommand.into(), "KJ1"),
+            (BusError::MessageLacksPath.into(), "KJ2"),
+            (NotifierError::NoSuchNotifier(String::new()).into(), "KJ3"),
+            (StateError::EventStoreNotSupported.into(), "KJ5"),
+            (
+                ControlError::BindControlSocket(IOError::from_raw_os_error(1)).into(),
+                "KJ6",
+            ),
+            (
+                ActionError::FilterFailed(String::new(), String::new()).into(),
+                "KJ7",
+            ),
+        ];
+        for (err, prefix) in errs {
+            assert!(err.code().starts_with(prefix), "{}", err.code());
         }
     }
 }
@@ -0,0 +1,158 @@
+// Logic for appending events to a user-chosen file, as an audit trail.
+//
+// Unlike the history file, the event log records every state transition that killjoy observes,
+// whether or not a rule's active states match it, and it's never rewritten. Instead, once it grows
+// too large, it's renamed by appending ".1" to its name, replacing any older log, and a new log is
+// started.
+
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::{Error as CrateError, StateError};
+use crate::history::HistoryEntry;
+use crate::settings::{EventLog, EventLogFormat};
+use crate::timestamp::RealtimeTimestamp;
+
+// Serializes writes to event logs by the bus watchers of this process.
+static EVENT_LOG_LOCK: Mutex<()> = Mutex::new(());
+
+// The first line of a CSV event log.
+const CSV_HEADER: &str = "timestamp,bus_type,unit,old_state,new_state\n";
+
+// Append an event to the event log, rotating it first if the event would make it too large.
+pub fn append(event_log: &EventLog, entry: &HistoryEntry) -> Result<(), CrateError> {
+    let _guard = EVENT_LOG_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let line = format_entry(event_log.format, entry)?;
+    let len = match fs::metadata(&event_log.path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == ErrorKind::NotFound => 0,
+        Err(err) => return Err(CrateError::State(StateError::WriteEventLog(err))),
+    };
+    let len = if len > 0 && len + line.len() as u64 > event_log.max_size {
+        fs::rename(&event_log.path, get_rotated_path(&event_log.path))
+            .map_err(|err| CrateError::State(StateError::WriteEventLog(err)))?;
+        0
+    } else {
+        len
+    };
+
+    let mut contents = String::new();
+    if len == 0 && event_log.format == EventLogFormat::Csv {
+        contents.push_str(CSV_HEADER);
+    }
+    contents.push_str(&line);
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&event_log.path)
+        .and_then(|mut handle| handle.write_all(contents.as_bytes()))
+        .map_err(|err| CrateError::State(StateError::WriteEventLog(err)))
+}
+
+// Format an event as a line of the event log, including the trailing newline.
+fn format_entry(format: EventLogFormat, entry: &HistoryEntry) -> Result<String, CrateError> {
+    match format {
+        EventLogFormat::Csv => {
+            let fields = [
+                RealtimeTimestamp(entry.timestamp).to_iso8601(),
+                entry.bus_type.to_owned(),
+                entry.unit.to_owned(),
+                entry.old_state.to_owned().unwrap_or_default(),
+                entry.new_state.to_owned(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|field| quote_csv(field)).collect();
+            Ok(format!("{}\n", fields.join(",")))
+        }
+        EventLogFormat::Jsonl => {
+            let line = serde_json::to_string(entry)
+                .map_err(|err| CrateError::State(StateError::SerializeEventLog(err)))?;
+            Ok(format!("{}\n", line))
+        }
+    }
+}
+
+// Quote a CSV field, as per RFC 4180, if it contains special characters.
+fn quote_csv(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Get the path an event log is moved to when it's rotated, e.g. "events.csv.1".
+fn get_rotated_path(path: &Path) -> PathBuf {
+    let mut rotated: OsString = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn gen_entry(unit: &str, old_state: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            bus_type: "session".to_string(),
+            unit: unit.to_string(),
+            old_state: old_state.map(String::from),
+            new_state: "failed".to_string(),
+            timestamp: 0,
+        }
+    }
+
+    // format_entry()
+    #[test]
+    fn test_format_entry() {
+        let entry = gen_entry("foo.service", None);
+        assert_eq!(
+            format_entry(EventLogFormat::Csv, &entry).unwrap(),
+            "1970-01-01T00:00:00Z,session,foo.service,,failed\n"
+        );
+        assert_eq!(
+            format_entry(EventLogFormat::Jsonl, &entry).unwrap(),
+            concat!(
+                r#"{"bus_type":"session","unit":"foo.service","old_state":null,"#,
+                r#""new_state":"failed","timestamp":0}"#,
+                "\n"
+            )
+        );
+    }
+
+    // quote_csv()
+    #[test]
+    fn test_quote_csv() {
+        assert_eq!(quote_csv("foo.service"), "foo.service");
+        assert_eq!(quote_csv("a,b"), "\"a,b\"");
+        assert_eq!(quote_csv("a\"b"), "\"a\"\"b\"");
+    }
+
+    // append()
+    #[test]
+    fn test_append_rotates() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let event_log = EventLog {
+            format: EventLogFormat::Csv,
+            max_size: 100,
+            path: dir.path().join("events.csv"),
+        };
+        for _ in 0..3 {
+            append(&event_log, &gen_entry("foo.service", Some("active"))).unwrap();
+        }
+        let current = fs::read_to_string(&event_log.path).unwrap();
+        let rotated = fs::read_to_string(dir.path().join("events.csv.1")).unwrap();
+        assert_eq!(
+            current.lines().collect::<Vec<_>>()[0],
+            CSV_HEADER.trim_end()
+        );
+        assert_eq!(current.lines().count(), 2);
+        assert_eq!(rotated.lines().count(), 2);
+    }
+}
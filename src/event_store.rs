@@ -0,0 +1,302 @@
+// Logic for recording events in an SQLite database, so that they may be kept and queried at length.
+//
+// Unlike the history file, which holds a bounded number of recent events, the event store holds
+// every state transition that killjoy observes, and every attempt to contact a notifier, until
+// the database is deleted. The database may be written by several killjoy processes and bus
+// watchers at once, and SQLite serializes their writes.
+
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, Row};
+use xdg::BaseDirectories;
+
+use crate::error::{Error as CrateError, StateError};
+use crate::history::HistoryEntry;
+
+// How long to wait for another process to finish writing to the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The database schema. Timestamps are realtime timestamps, in usec.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS transitions (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        bus_type TEXT NOT NULL,
+        unit TEXT NOT NULL,
+        old_state TEXT,
+        new_state TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS transitions_timestamp ON transitions (timestamp);
+    CREATE TABLE IF NOT EXISTS notifications (
+        id INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        bus_type TEXT NOT NULL,
+        unit TEXT NOT NULL,
+        state TEXT NOT NULL,
+        notifier TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX IF NOT EXISTS notifications_timestamp ON notifications (timestamp);
+";
+
+// An attempt to contact a notifier about a unit.
+//
+// `state` is the state the notifier was told about, and `error` is `None` if the attempt
+// succeeded. `bus_type` and `timestamp` are as for a `HistoryEntry`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotificationAttempt {
+    pub bus_type: String,
+    pub unit: String,
+    pub state: String,
+    pub notifier: String,
+    pub error: Option<String>,
+    pub timestamp: u64,
+}
+
+// Criteria for selecting records from the event store. `None` matches everything.
+//
+// `unit` is a glob pattern. For transitions, `state` is matched against the new state. `since` and
+// `until` are realtime timestamps, in usec, and form a half-open range.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub unit: Option<String>,
+    pub state: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl Query {
+    // Generate an SQL WHERE clause and its parameters. `state_column` is the column `state` is
+    // matched against.
+    fn gen_where_clause(&self, state_column: &str) -> (String, Vec<&dyn ToSql>) {
+        let mut clauses: Vec<String> = vec!["1".to_string()];
+        let mut values: Vec<&dyn ToSql> = Vec::new();
+        if let Some(unit) = &self.unit {
+            clauses.push("unit GLOB ?".to_string());
+            values.push(unit);
+        }
+        if let Some(state) = &self.state {
+            clauses.push(format!("{} = ?", state_column));
+            values.push(state);
+        }
+        if let Some(since) = &self.since {
+            clauses.push("timestamp >= ?".to_string());
+            values.push(since);
+        }
+        if let Some(until) = &self.until {
+            clauses.push("timestamp < ?".to_string());
+            values.push(until);
+        }
+        (clauses.join(" AND "), values)
+    }
+}
+
+// A connection to the event store.
+pub struct Database {
+    connection: Connection,
+}
+
+impl Database {
+    // Open the database at the given path, creating it and its tables if necessary.
+    pub fn open(path: &Path) -> Result<Self, CrateError> {
+        let connection = Connection::open(path)
+            .map_err(|err| CrateError::State(StateError::OpenEventStore(err)))?;
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .map_err(|err| CrateError::State(StateError::OpenEventStore(err)))?;
+        Ok(Database { connection })
+    }
+
+    // Record a unit's transition from one state to another.
+    pub fn record_transition(&self, entry: &HistoryEntry) -> Result<(), CrateError> {
+        self.connection
+            .execute(
+                "INSERT INTO transitions (timestamp, bus_type, unit, old_state, new_state)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![
+                    entry.timestamp,
+                    entry.bus_type,
+                    entry.unit,
+                    entry.old_state,
+                    entry.new_state
+                ],
+            )
+            .map(|_| ())
+            .map_err(|err| CrateError::State(StateError::WriteEventStore(err)))
+    }
+
+    // Record an attempt to contact a notifier.
+    pub fn record_notification(&self, attempt: &NotificationAttempt) -> Result<(), CrateError> {
+        self.connection
+            .execute(
+                "INSERT INTO notifications (timestamp, bus_type, unit, state, notifier, error)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    attempt.timestamp,
+                    attempt.bus_type,
+                    attempt.unit,
+                    attempt.state,
+                    attempt.notifier,
+                    attempt.error
+                ],
+            )
+            .map(|_| ())
+            .map_err(|err| CrateError::State(StateError::WriteEventStore(err)))
+    }
+
+    // Get the transitions matching `query`, oldest first.
+    pub fn query_transitions(&self, query: &Query) -> Result<Vec<HistoryEntry>, CrateError> {
+        let (clause, values) = query.gen_where_clause("new_state");
+        let sql = format!(
+            "SELECT timestamp, bus_type, unit, old_state, new_state FROM transitions
+             WHERE {} ORDER BY timestamp, id",
+            clause
+        );
+        self.query(&sql, &values, |row| {
+            Ok(HistoryEntry {
+                timestamp: row.get(0)?,
+                bus_type: row.get(1)?,
+                unit: row.get(2)?,
+                old_state: row.get(3)?,
+                new_state: row.get(4)?,
+            })
+        })
+    }
+
+    // Get the attempts to contact notifiers matching `query`, oldest first.
+    pub fn query_notifications(
+        &self,
+        query: &Query,
+    ) -> Result<Vec<NotificationAttempt>, CrateError> {
+        let (clause, values) = query.gen_where_clause("state");
+        let sql = format!(
+            "SELECT timestamp, bus_type, unit, state, notifier, error FROM notifications
+             WHERE {} ORDER BY timestamp, id",
+            clause
+        );
+        self.query(&sql, &values, |row| {
+            Ok(NotificationAttempt {
+                timestamp: row.get(0)?,
+                bus_type: row.get(1)?,
+                unit: row.get(2)?,
+                state: row.get(3)?,
+                notifier: row.get(4)?,
+                error: row.get(5)?,
+            })
+        })
+    }
+
+    // Run a query, and convert each resulting row with `map`.
+    fn query<T, F>(&self, sql: &str, values: &[&dyn ToSql], map: F) -> Result<Vec<T>, CrateError>
+    where
+        F: FnMut(&Row) -> rusqlite::Result<T>,
+    {
+        let mut statement = self
+            .connection
+            .prepare(sql)
+            .map_err(|err| CrateError::State(StateError::QueryEventStore(err)))?;
+        let rows = statement
+            .query_map(values, map)
+            .map_err(|err| CrateError::State(StateError::QueryEventStore(err)))?;
+        rows.collect::<Result<Vec<T>, _>>()
+            .map_err(|err| CrateError::State(StateError::QueryEventStore(err)))
+    }
+}
+
+// Get the default path to the event store, creating parent directories if necessary.
+//
+// The database is placed in `$XDG_STATE_HOME/killjoy`, alongside the history file.
+pub fn get_event_store_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::State(StateError::PlaceEventStore(IOError::other(err))))?
+        .place_state_file("events.sqlite3")
+        .map_err(|err| CrateError::State(StateError::PlaceEventStore(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn gen_entry(unit: &str, new_state: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            bus_type: "session".to_string(),
+            unit: unit.to_string(),
+            old_state: None,
+            new_state: new_state.to_string(),
+            timestamp,
+        }
+    }
+
+    // Database::record_transition(), Database::query_transitions()
+    #[test]
+    fn test_transitions() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let database = Database::open(&dir.path().join("events.sqlite3")).unwrap();
+        database
+            .record_transition(&gen_entry("foo.service", "failed", 3))
+            .unwrap();
+        database
+            .record_transition(&gen_entry("foo.service", "active", 2))
+            .unwrap();
+        database
+            .record_transition(&gen_entry("bar.timer", "failed", 1))
+            .unwrap();
+
+        let units = |query: Query| -> Vec<(String, u64)> {
+            database
+                .query_transitions(&query)
+                .unwrap()
+                .into_iter()
+                .map(|entry| (entry.unit, entry.timestamp))
+                .collect()
+        };
+        assert_eq!(units(Query::default()).len(), 3);
+        assert_eq!(units(Query::default())[0], ("bar.timer".to_string(), 1));
+        let query = Query {
+            unit: Some("*.service".to_string()),
+            state: Some("failed".to_string()),
+            ..Query::default()
+        };
+        assert_eq!(units(query), vec![("foo.service".to_string(), 3)]);
+        let query = Query {
+            since: Some(1),
+            until: Some(3),
+            ..Query::default()
+        };
+        assert_eq!(
+            units(query),
+            vec![("bar.timer".to_string(), 1), ("foo.service".to_string(), 2)]
+        );
+    }
+
+    // Database::record_notification(), Database::query_notifications()
+    #[test]
+    fn test_notifications() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let database = Database::open(&dir.path().join("events.sqlite3")).unwrap();
+        let attempt = NotificationAttempt {
+            bus_type: "system".to_string(),
+            unit: "foo.service".to_string(),
+            state: "failed".to_string(),
+            notifier: "logfile".to_string(),
+            error: Some("no reply".to_string()),
+            timestamp: 1,
+        };
+        database.record_notification(&attempt).unwrap();
+        assert_eq!(
+            database.query_notifications(&Query::default()).unwrap(),
+            vec![attempt]
+        );
+        let query = Query {
+            state: Some("active".to_string()),
+            ..Query::default()
+        };
+        assert!(database.query_notifications(&query).unwrap().is_empty());
+    }
+}
@@ -1,2 +1,4 @@
 #![allow(clippy::all)]
+pub mod org_freedesktop_portable1;
 pub mod org_freedesktop_systemd1;
+pub mod org_freedesktop_systemd1_units;
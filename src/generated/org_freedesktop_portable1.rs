@@ -0,0 +1,84 @@
+// A typed proxy for the manager of systemd-portabled, which attaches and detaches portable service
+// images. Like `org_freedesktop_systemd1_units`, this follows the client half of what
+// dbus-codegen-rust generates, but is written by hand, as only the methods killjoy may use are
+// covered.
+
+#![allow(dead_code)]
+use dbus;
+use dbus::arg;
+
+pub trait OrgFreedesktopPortable1Manager {
+    type Err;
+    fn list_images(
+        &self,
+    ) -> Result<
+        Vec<(
+            String,
+            String,
+            bool,
+            u64,
+            u64,
+            u64,
+            String,
+            dbus::Path<'static>,
+        )>,
+        Self::Err,
+    >;
+    fn get_image_state(&self, arg0: &str) -> Result<String, Self::Err>;
+}
+
+impl<'a, C: ::std::ops::Deref<Target = dbus::Connection>> OrgFreedesktopPortable1Manager
+    for dbus::ConnPath<'a, C>
+{
+    type Err = dbus::Error;
+
+    fn list_images(
+        &self,
+    ) -> Result<
+        Vec<(
+            String,
+            String,
+            bool,
+            u64,
+            u64,
+            u64,
+            String,
+            dbus::Path<'static>,
+        )>,
+        Self::Err,
+    > {
+        let mut m = self.method_call_with_args(
+            &"org.freedesktop.portable1.Manager".into(),
+            &"ListImages".into(),
+            |_| {},
+        )?;
+        m.as_result()?;
+        let mut i = m.iter_init();
+        let arg0: Vec<(
+            String,
+            String,
+            bool,
+            u64,
+            u64,
+            u64,
+            String,
+            dbus::Path<'static>,
+        )> = i.read()?;
+        Ok(arg0)
+    }
+
+    fn get_image_state(&self, arg0: &str) -> Result<String, Self::Err> {
+        let mut m = self.method_call_with_args(
+            &"org.freedesktop.portable1.Manager".into(),
+            &"GetImageState".into(),
+            |msg| {
+                let mut i = arg::IterAppend::new(msg);
+                i.append(arg0);
+            },
+        )?;
+        m.as_result()?;
+        let mut i = m.iter_init();
+        let arg0: String = i.read()?;
+        Ok(arg0)
+    }
+}
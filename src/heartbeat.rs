@@ -0,0 +1,236 @@
+// Logic for telling an external service, such as healthchecks.io, that killjoy is alive.
+//
+// Each bus watcher reports to a shared `Health` whenever it goes through its message loop. The
+// heartbeat thread requests the heartbeat URL only while every bus watcher has reported recently,
+// so the requests stop if killjoy dies, loses a bus, or gets stuck.
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dbus::BusType;
+
+use crate::error::{ActionError, Error as CrateError};
+use crate::monitor::StopHandle;
+use crate::settings;
+use crate::settings::Heartbeat;
+
+// How long a request for the heartbeat URL may take, in seconds.
+const REQUEST_TIMEOUT_SECS: u32 = 10;
+
+// When each bus watcher last reported, and how often it has been restarted, keyed by bus type.
+//
+// A bus watcher which hasn't reported yet, or which has stopped, has no last report. Clones share
+// state, so a clone may be handed to each bus watcher thread.
+#[derive(Clone, Debug)]
+pub struct Health {
+    buses: Arc<Mutex<HashMap<&'static str, BusHealth>>>,
+}
+
+// The health of one bus watcher. See `Health`.
+#[derive(Clone, Copy, Debug, Default)]
+struct BusHealth {
+    last_report: Option<Instant>,
+    restarts: u32,
+}
+
+// Whether a bus watcher has reported recently, and how often it has been restarted.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct BusStatus {
+    pub bus_type: &'static str,
+    pub healthy: bool,
+    pub restarts: u32,
+}
+
+impl Health {
+    // Create a tracker for the watchers of the given buses, none of which have reported yet.
+    pub fn new(bus_types: &[BusType]) -> Self {
+        let buses = bus_types
+            .iter()
+            .map(|bus_type| (settings::encode_bus_type(*bus_type), BusHealth::default()))
+            .collect();
+        Self {
+            buses: Arc::new(Mutex::new(buses)),
+        }
+    }
+
+    // Record that the watcher of the given bus is connected and responsive.
+    pub fn report(&self, bus_type: BusType) {
+        self.update(bus_type, |bus| bus.last_report = Some(Instant::now()));
+    }
+
+    // Record that the watcher of the given bus has stopped.
+    pub fn mark_down(&self, bus_type: BusType) {
+        self.update(bus_type, |bus| bus.last_report = None);
+    }
+
+    // Record that the watcher of the given bus failed, and has been restarted.
+    pub fn record_restart(&self, bus_type: BusType) {
+        self.update(bus_type, |bus| bus.restarts += 1);
+    }
+
+    // Tell whether the watcher of the given bus has reported since it last stopped.
+    pub fn has_reported(&self, bus_type: BusType) -> bool {
+        self.lock()
+            .get(settings::encode_bus_type(bus_type))
+            .is_some_and(|bus| bus.last_report.is_some())
+    }
+
+    // Tell whether every bus watcher has reported within the last `max_age`.
+    pub fn is_healthy(&self, max_age: Duration) -> bool {
+        self.lock()
+            .values()
+            .all(|bus| is_recent(bus.last_report, max_age))
+    }
+
+    // Describe each bus watcher, in order of bus name. See `is_healthy`.
+    pub fn statuses(&self, max_age: Duration) -> Vec<BusStatus> {
+        let mut statuses: Vec<BusStatus> = self
+            .lock()
+            .iter()
+            .map(|(bus_type, bus)| BusStatus {
+                bus_type: *bus_type,
+                healthy: is_recent(bus.last_report, max_age),
+                restarts: bus.restarts,
+            })
+            .collect();
+        statuses.sort();
+        statuses
+    }
+
+    fn update<F: FnOnce(&mut BusHealth)>(&self, bus_type: BusType, update: F) {
+        update(
+            self.lock()
+                .entry(settings::encode_bus_type(bus_type))
+                .or_default(),
+        );
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<&'static str, BusHealth>> {
+        self.buses
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Tell whether a bus watcher last reported at `last_report`, within the last `max_age`.
+fn is_recent(last_report: Option<Instant>, max_age: Duration) -> bool {
+    last_report.is_some_and(|last_report| last_report.elapsed() < max_age)
+}
+
+// Request the heartbeat URL every `heartbeat.interval` while `health` is healthy, until stopped.
+//
+// A bus watcher is considered unresponsive if it hasn't reported within `max_age`. Whether to stop
+// is checked every `poll_interval`. Failed requests are reported on stderr, and retried at the
+// next interval.
+pub fn run(
+    heartbeat: &Heartbeat,
+    health: &Health,
+    max_age: Duration,
+    poll_interval: Duration,
+    stop: &StopHandle,
+) {
+    let mut last_sent: Option<Instant> = None;
+    while !stop.is_stopped() {
+        let due = match last_sent {
+            Some(last_sent) => last_sent.elapsed() >= heartbeat.interval,
+            None => true,
+        };
+        if due && health.is_healthy(max_age) {
+            if let Err(err) = send(&heartbeat.url) {
+                eprintln!("{}", err.with_code());
+            }
+            last_sent = Some(Instant::now());
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+// Request the given URL.
+//
+// The request is made by calling curl(1), which supports HTTPS without further dependencies.
+fn send(url: &str) -> Result<(), CrateError> {
+    let status = Command::new("curl")
+        .args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--output",
+            "/dev/null",
+        ])
+        .args(["--max-time", &REQUEST_TIMEOUT_SECS.to_string()])
+        .arg(url)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|err| CrateError::Action(ActionError::RunCurl(err)))?;
+    if !status.success() {
+        return Err(CrateError::Action(ActionError::HeartbeatFailed(status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Health::report(), Health::mark_down(), Health::is_healthy(), Health::has_reported()
+    #[test]
+    fn test_health() {
+        let max_age = Duration::from_secs(60);
+        let health = Health::new(&[BusType::Session, BusType::System]);
+        assert!(!health.is_healthy(max_age));
+        health.report(BusType::Session);
+        assert!(!health.is_healthy(max_age));
+        health.clone().report(BusType::System);
+        assert!(health.is_healthy(max_age));
+        assert!(!health.is_healthy(Duration::from_secs(0)));
+        assert!(health.has_reported(BusType::Session));
+        health.mark_down(BusType::Session);
+        assert!(!health.is_healthy(max_age));
+        assert!(!health.has_reported(BusType::Session));
+    }
+
+    // Health::record_restart(), Health::statuses()
+    #[test]
+    fn test_health_statuses() {
+        let max_age = Duration::from_secs(60);
+        let health = Health::new(&[BusType::System, BusType::Session]);
+        health.report(BusType::System);
+        health.record_restart(BusType::Session);
+        health.record_restart(BusType::Session);
+        let statuses = health.statuses(max_age);
+        assert_eq!(
+            statuses,
+            vec![
+                BusStatus {
+                    bus_type: "session",
+                    healthy: false,
+                    restarts: 2,
+                },
+                BusStatus {
+                    bus_type: "system",
+                    healthy: true,
+                    restarts: 0,
+                },
+            ]
+        );
+    }
+
+    // run()
+    #[test]
+    fn test_run_stopped() {
+        let heartbeat = Heartbeat::new("http://127.0.0.1:1/", Duration::from_secs(1)).unwrap();
+        let health = Health::new(&[BusType::Session]);
+        let stop = StopHandle::new();
+        stop.stop();
+        run(
+            &heartbeat,
+            &health,
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            &stop,
+        );
+    }
+}
@@ -0,0 +1,153 @@
+// Logic for remembering recent events, so that they may be reviewed later.
+//
+// Events are stored in a file, so that they may be recorded by one killjoy process (i.e. the one
+// watching units) and listed by another (i.e. `killjoy events`).
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IOError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use xdg::BaseDirectories;
+
+use crate::error::{Error as CrateError, StateError};
+
+// Serializes updates to the history file by the bus watchers of this process.
+static HISTORY_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+// A unit's transition from one state to another.
+//
+// `bus_type` is "session" or "system", and `timestamp` is a realtime timestamp, in usec.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub bus_type: String,
+    pub unit: String,
+    #[serde(default)]
+    pub old_state: Option<String>,
+    pub new_state: String,
+    pub timestamp: u64,
+}
+
+// The most recent events, oldest first, as stored in the history file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct History {
+    events: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    // Read events from the given file. If the file doesn't exist, there are no events.
+    pub fn load(path: &Path) -> Result<Self, CrateError> {
+        match File::open(path) {
+            Ok(handle) => serde_json::from_reader(BufReader::new(handle))
+                .map_err(|err| CrateError::State(StateError::DeserializeHistoryFile(err))),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CrateError::State(StateError::ReadHistoryFile(err))),
+        }
+    }
+
+    // Write events to the given file.
+    //
+    // The file is replaced atomically, so that concurrent readers never see a partial file.
+    pub fn save(&self, path: &Path) -> Result<(), CrateError> {
+        let contents = serde_json::to_string(self)
+            .map_err(|err| CrateError::State(StateError::SerializeHistoryFile(err)))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .map_err(|err| CrateError::State(StateError::WriteHistoryFile(err)))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|err| CrateError::State(StateError::WriteHistoryFile(err)))
+    }
+
+    // Add an event, and forget the oldest events, so that at most `size` are kept.
+    pub fn push(&mut self, entry: HistoryEntry, size: usize) {
+        self.events.push_back(entry);
+        while self.events.len() > size {
+            self.events.pop_front();
+        }
+    }
+
+    // Get up to `count` of the most recent events, oldest first.
+    pub fn last(&self, count: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.events
+            .iter()
+            .skip(self.events.len().saturating_sub(count))
+    }
+}
+
+// Add an event to the history file, keeping at most `size` events.
+pub fn record(entry: HistoryEntry, size: usize) -> Result<(), CrateError> {
+    let _guard = HISTORY_FILE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let path = get_history_path()?;
+    let mut history = History::load(&path)?;
+    history.push(entry, size);
+    history.save(&path)
+}
+
+// Get the path to the history file, creating parent directories if necessary.
+//
+// The file is placed in `$XDG_STATE_HOME/killjoy`, so that events survive restarts.
+pub fn get_history_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::State(StateError::PlaceHistoryFile(IOError::other(err))))?
+        .place_state_file("history.json")
+        .map_err(|err| CrateError::State(StateError::PlaceHistoryFile(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn gen_entry(unit: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            bus_type: "session".to_string(),
+            unit: unit.to_string(),
+            old_state: Some("active".to_string()),
+            new_state: "failed".to_string(),
+            timestamp,
+        }
+    }
+
+    // History::push(), History::last()
+    #[test]
+    fn test_history_push_last() {
+        let mut history = History::default();
+        for i in 0..5 {
+            history.push(gen_entry("foo.service", i), 3);
+        }
+        let timestamps: Vec<u64> = history.last(10).map(|entry| entry.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+        let timestamps: Vec<u64> = history.last(2).map(|entry| entry.timestamp).collect();
+        assert_eq!(timestamps, vec![3, 4]);
+        assert_eq!(history.last(0).count(), 0);
+    }
+
+    // History::save(), History::load()
+    #[test]
+    fn test_history_save_load() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let path = dir.path().join("history.json");
+        assert_eq!(
+            History::load(&path)
+                .expect("Failed to load history.")
+                .last(10)
+                .count(),
+            0
+        );
+
+        let mut history = History::default();
+        history.push(gen_entry("foo.service", 1), 10);
+        history.push(gen_entry("bar.service", 2), 10);
+        history.save(&path).expect("Failed to save history.");
+        let loaded = History::load(&path).expect("Failed to load history.");
+        assert_eq!(
+            loaded.last(10).collect::<Vec<_>>(),
+            history.last(10).collect::<Vec<_>>()
+        );
+    }
+}
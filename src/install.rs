@@ -0,0 +1,159 @@
+// Logic for installing a systemd service unit which runs killjoy.
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use xdg::BaseDirectories;
+
+use crate::error::Error as CrateError;
+
+// The name of the generated service unit.
+pub const SERVICE_UNIT_NAME: &str = "killjoy.service";
+
+// Where system service units are installed by the administrator.
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+
+// Whether killjoy runs as part of a user's session, or as part of the system.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InstallScope {
+    System,
+    User,
+}
+
+impl InstallScope {
+    // Get the flag with which `systemctl` manages units of this scope, if any.
+    pub fn systemctl_flag(self) -> &'static str {
+        match self {
+            InstallScope::System => "",
+            InstallScope::User => " --user",
+        }
+    }
+}
+
+// Generate a service unit which runs the killjoy executable at `exec_path`.
+//
+// A user unit starts along with the user's session, and is ordered after the session bus. A
+// system unit starts at boot, and is ordered after the system bus and the network, as notifiers may
+// be remote. It keeps its state in `/var/lib/killjoy`, as root's home directory may be read-only.
+// Both are restarted if they fail, unless the settings are invalid, as restarting won't help then.
+// Both are sandboxed in ways which don't stop notifiers, plugins and actions from working.
+pub fn gen_service_unit(scope: InstallScope, exec_path: &Path) -> String {
+    let (after, wanted_by, extra_service, extra_hardening) = match scope {
+        InstallScope::System => (
+            "After=dbus.service network-online.target\nWants=network-online.target\n",
+            "multi-user.target",
+            "Environment=XDG_STATE_HOME=/var/lib\nStateDirectory=killjoy\n",
+            "PrivateTmp=true\nProtectControlGroups=true\nProtectKernelModules=true\n\
+             ProtectKernelTunables=true\nProtectSystem=full\n",
+        ),
+        InstallScope::User => ("After=dbus.socket\n", "default.target", "", ""),
+    };
+    format!(
+        "[Unit]\n\
+         Description=Monitor systemd units\n\
+         Documentation=https://github.com/kennep/killjoy\n\
+         {}\n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         RestartPreventExitStatus=2\n\
+         {}\
+         \n\
+         # hardening\n\
+         LockPersonality=true\n\
+         MemoryDenyWriteExecute=true\n\
+         NoNewPrivileges=true\n\
+         RestrictRealtime=true\n\
+         RestrictSUIDSGID=true\n\
+         SystemCallArchitectures=native\n\
+         {}\n\
+         [Install]\n\
+         WantedBy={}\n",
+        after,
+        exec_path.display(),
+        extra_service,
+        extra_hardening,
+        wanted_by
+    )
+}
+
+// Get the path at which to install the service unit, creating parent directories if necessary.
+//
+// User units are placed in `$XDG_CONFIG_HOME/systemd/user`, and system units in
+// `/etc/systemd/system`.
+pub fn get_service_unit_path(scope: InstallScope) -> Result<PathBuf, CrateError> {
+    match scope {
+        InstallScope::System => {
+            let dir = Path::new(SYSTEM_UNIT_DIR);
+            fs::create_dir_all(dir).map_err(CrateError::PlaceServiceUnit)?;
+            Ok(dir.join(SERVICE_UNIT_NAME))
+        }
+        InstallScope::User => BaseDirectories::new()
+            .map_err(|err| CrateError::PlaceServiceUnit(std::io::Error::other(err)))?
+            .place_config_file(Path::new("systemd/user").join(SERVICE_UNIT_NAME))
+            .map_err(CrateError::PlaceServiceUnit),
+    }
+}
+
+// Write `contents` to the service unit at `path`.
+//
+// Unless `force` is set, return an error if the file already exists, as it may have been
+// customized.
+pub fn write_service_unit(path: &Path, contents: &str, force: bool) -> Result<(), CrateError> {
+    let path_str = path.display().to_string();
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = options.open(path).map_err(|err| match err.kind() {
+        ErrorKind::AlreadyExists => CrateError::ServiceUnitExists(path_str.clone()),
+        _ => CrateError::WriteServiceUnit(path_str.clone(), err),
+    })?;
+    file.write_all(contents.as_bytes())
+        .map_err(|err| CrateError::WriteServiceUnit(path_str, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    // gen_service_unit()
+    #[test]
+    fn test_gen_service_unit() {
+        let exec_path = Path::new("/usr/local/bin/killjoy");
+        let unit = gen_service_unit(InstallScope::User, exec_path);
+        assert!(unit.contains("\nExecStart=/usr/local/bin/killjoy\n"));
+        assert!(unit.contains("\nAfter=dbus.socket\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=default.target\n"));
+        assert!(!unit.contains("StateDirectory"));
+        assert!(unit.contains("\nRestartPreventExitStatus=2\n"));
+
+        let unit = gen_service_unit(InstallScope::System, exec_path);
+        assert!(unit.contains("\nWants=network-online.target\n"));
+        assert!(unit.contains("\nStateDirectory=killjoy\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=multi-user.target\n"));
+    }
+
+    // write_service_unit()
+    #[test]
+    fn test_write_service_unit() {
+        let dir = TempDir::new().expect("Failed to create a temporary directory.");
+        let path = dir.path().join(SERVICE_UNIT_NAME);
+        write_service_unit(&path, "old", false).expect("Failed to write service unit.");
+        match write_service_unit(&path, "new", false) {
+            Err(CrateError::ServiceUnitExists(_)) => {}
+            _ => panic!("expected ServiceUnitExists; the service unit was already written"),
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        write_service_unit(&path, "new", true).expect("Failed to overwrite service unit.");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+}
@@ -0,0 +1,114 @@
+// Logic for reading the systemd journal.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use dbus::BusType;
+use serde_json::Value;
+
+use crate::error::{ActionError, Error as CrateError};
+use crate::plugin;
+
+// How long to wait for journalctl. Notifications about the unit are held up until it finishes.
+const JOURNALCTL_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Get the most recent `count` log messages for the given unit, oldest first.
+//
+// Messages are read by calling journalctl(1). If `bus_type` is the session bus, then the unit is
+// assumed to be a user unit. journalctl is killed if it takes longer than `JOURNALCTL_TIMEOUT`.
+pub fn get_journal_lines(
+    unit_name: &str,
+    count: u32,
+    bus_type: BusType,
+) -> Result<Vec<String>, CrateError> {
+    let unit_arg = match bus_type {
+        BusType::Session => "--user-unit",
+        _ => "--unit",
+    };
+    let mut child = Command::new("journalctl")
+        .args([unit_arg, unit_name])
+        .args(["--lines", &count.to_string()])
+        .args(["--output", "json", "--no-pager", "--quiet"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| CrateError::Action(ActionError::RunJournalctl(err)))?;
+
+    // Read stdout while waiting, so that journalctl can't block on a full pipe.
+    let mut stdout = child.stdout.take().expect("journalctl's stdout is piped.");
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let status = plugin::wait_with_timeout(&mut child, JOURNALCTL_TIMEOUT)
+        .map_err(|err| CrateError::Action(ActionError::RunJournalctl(err)))?
+        .ok_or_else(|| {
+            CrateError::Action(ActionError::JournalctlTimedOut(
+                JOURNALCTL_TIMEOUT.as_secs(),
+            ))
+        })?;
+    if !status.success() {
+        return Err(CrateError::Action(ActionError::JournalctlFailed(status)));
+    }
+    let output = reader
+        .join()
+        .unwrap_or_else(|_| Ok(Vec::new()))
+        .map_err(|err| CrateError::Action(ActionError::RunJournalctl(err)))?;
+    Ok(parse_journal_output(&String::from_utf8_lossy(&output)))
+}
+
+// Extract the MESSAGE field from each line of `journalctl --output json` output.
+//
+// Journald stores messages that aren't valid UTF-8 as arrays of bytes; these are decoded lossily.
+// Lines that can't be parsed, or that lack a message, are skipped.
+fn parse_journal_output(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|entry| match entry.get("MESSAGE") {
+            Some(Value::String(message)) => Some(message.to_owned()),
+            Some(Value::Array(bytes)) => {
+                let bytes: Vec<u8> = bytes
+                    .iter()
+                    .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                    .collect();
+                Some(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parse_journal_output()
+    #[test]
+    fn test_parse_journal_output() {
+        let output = concat!(
+            r#"{"MESSAGE": "Starting foo...", "_PID": "1"}"#,
+            "\n",
+            r#"{"MESSAGE": [104, 105]}"#,
+            "\n",
+            r#"{"MESSAGE": null}"#,
+            "\n",
+            "not json\n",
+            r#"{"MESSAGE": "foo.service: Failed."}"#,
+            "\n",
+        );
+        assert_eq!(
+            parse_journal_output(output),
+            vec!["Starting foo...", "hi", "foo.service: Failed."]
+        );
+    }
+
+    // parse_journal_output()
+    #[test]
+    fn test_parse_journal_output_empty() {
+        assert!(parse_journal_output("").is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+//! Monitor systemd units.
+//!
+//! This library lets other programs embed killjoy's unit watching logic. Load a `Settings` object,
+//! hand it to a `Monitor`, and either run the monitor with a callback:
+//!
+//! ```no_run
+//! use killjoy::{Monitor, Settings};
+//!
+//! let settings = Settings::new(std::fs::File::open("settings.json").unwrap()).unwrap();
+//! let result = Monitor::new(settings).run(|event| {
+//!     println!("{} is now {:?}", event.unit_name, event.new_state);
+//! });
+//! ```
+//!
+//! Or consume a channel of events:
+//!
+//! ```no_run
+//! use killjoy::{Monitor, Settings};
+//!
+//! let settings = Settings::new(std::fs::File::open("settings.json").unwrap()).unwrap();
+//! for result in Monitor::new(settings).events() {
+//!     match result {
+//!         Ok(event) => println!("{} is now {:?}", event.unit_name, event.new_state),
+//!         Err(err) => eprintln!("{}", err),
+//!     }
+//! }
+//! ```
+//!
+//! A monitor runs until it's stopped with a `StopHandle`, or until its bus watchers fail. Unlike
+//! the killjoy daemon, a monitor doesn't restart bus watchers which fail:
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! use killjoy::{Monitor, Settings};
+//!
+//! let settings = Settings::new(std::fs::File::open("settings.json").unwrap()).unwrap();
+//! let monitor = Monitor::new(settings);
+//! let stop = monitor.stop_handle();
+//! thread::spawn(move || {
+//!     thread::sleep(Duration::from_secs(60));
+//!     stop.stop();
+//! });
+//! if let Err(errs) = monitor.run(|event| println!("{:?}", event)) {
+//!     for err in errs {
+//!         eprintln!("{}", err);
+//!     }
+//! }
+//! ```
+//!
+//! See the readme for a description of the settings file.
+//!
+//! killjoy watches systemd, so it only builds for Linux. With the `mock-bus` feature, it also
+//! builds for other Unix-like systems, where it may watch a simulated systemd instead. See
+//! `MockSystemd`.
+
+#[cfg(not(unix))]
+compile_error!("killjoy talks to systemd over D-Bus, and only builds for Unix-like systems.");
+
+#[cfg(all(unix, not(target_os = "linux"), not(feature = "mock-bus")))]
+compile_error!(
+    "killjoy watches systemd, which only runs on Linux. Enable the `mock-bus` feature to build \
+     killjoy against a simulated systemd instead."
+);
+
+mod actions;
+mod alertmanager;
+#[doc(hidden)]
+pub mod backend;
+#[doc(hidden)]
+pub mod bus;
+mod control;
+mod discovery;
+mod early_boot;
+#[doc(hidden)]
+pub mod error;
+mod event_log;
+#[cfg(feature = "sqlite")]
+#[doc(hidden)]
+pub mod event_store;
+mod generated;
+mod heartbeat;
+#[doc(hidden)]
+pub mod history;
+#[doc(hidden)]
+pub mod install;
+mod journal;
+#[cfg(feature = "mock-bus")]
+mod mock;
+mod monitor;
+mod mountinfo;
+mod mqtt;
+#[doc(hidden)]
+pub mod pause;
+mod plugin;
+mod property;
+#[doc(hidden)]
+pub mod runtime_rules;
+mod schedule;
+#[cfg(feature = "scripting")]
+mod script;
+#[doc(hidden)]
+pub mod settings;
+#[doc(hidden)]
+pub mod silence;
+mod sleep;
+mod snmp;
+mod supervisor;
+mod syslog;
+#[doc(hidden)]
+pub mod systemd;
+mod template;
+#[doc(hidden)]
+pub mod timestamp;
+#[doc(hidden)]
+pub mod unit;
+mod varlink;
+#[doc(hidden)]
+pub mod zabbix;
+
+pub use crate::error::{
+    ActionError, BusError, ControlError, Error, NotifierError, SettingsError, StateError,
+};
+#[cfg(feature = "mock-bus")]
+pub use crate::mock::{MockSystemd, MockValue};
+pub use crate::monitor::{check, run, CheckReport, Event, Monitor, RunOptions, StopHandle};
+pub use crate::settings::{RuleBuilder, Settings, SettingsBuilder};
+pub use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
+pub use crate::unit::ActiveState;
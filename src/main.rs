@@ -2,59 +2,508 @@
 //!
 //! See the readme for full documentation.
 
-mod bus;
 mod cli;
-mod error;
-mod generated;
-mod settings;
-mod timestamp;
-mod unit;
 
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::thread;
-use std::thread::JoinHandle;
 
-use clap::ArgMatches;
+use clap::{ArgMatches, Command};
+use clap_mangen::Man;
 
-use crate::bus::BusWatcher;
-use crate::error::Error as CrateError;
-use crate::settings::Settings;
+use killjoy::bus;
+use killjoy::error;
+use killjoy::error::{ActionError, Error as CrateError, NotifierError, SettingsError, StateError};
+#[cfg(feature = "sqlite")]
+use killjoy::event_store;
+#[cfg(feature = "sqlite")]
+use killjoy::event_store::{Database, Query};
+use killjoy::history;
+use killjoy::history::History;
+use killjoy::install;
+use killjoy::install::InstallScope;
+use killjoy::pause;
+use killjoy::pause::PausedBuses;
+use killjoy::runtime_rules;
+use killjoy::runtime_rules::RuntimeRules;
+use killjoy::settings;
+use killjoy::settings::{Settings, Severity};
+use killjoy::silence;
+use killjoy::silence::Silences;
+use killjoy::timestamp;
+use killjoy::timestamp::RealtimeTimestamp;
+use killjoy::zabbix;
+use killjoy::{ActiveState, Event, RunOptions};
 
 // The entry point for the application.
 fn main() {
     if let Err(errs) = handle_args() {
-        for err in errs {
-            eprintln!("{}", err);
+        for err in &errs {
+            eprintln!("{}", err.with_code());
         }
-        process::exit(1);
+        process::exit(get_exit_code(&errs));
     }
 }
 
+// Get the status to exit with because of the given errors.
+//
+// If the errors are of several classes, the most specific class wins, i.e. the one whose status is
+// lowest, other than the catch-all `error::EXIT_FAILURE`. For example, a broken settings file
+// outranks the bus watchers which failed because of it.
+fn get_exit_code(errs: &[CrateError]) -> i32 {
+    errs.iter()
+        .map(CrateError::exit_code)
+        .filter(|code| *code != error::EXIT_FAILURE)
+        .min()
+        .unwrap_or(error::EXIT_FAILURE)
+}
+
 // Fetch and handle CLI arguments. On error may be returned per thread.
 fn handle_args() -> Result<(), Vec<CrateError>> {
     let args = cli::get_cli_args();
     match args.subcommand() {
-        Some(("settings", sub_args)) => {
-            handle_settings_subcommand(&sub_args).map_err(|err| vec![err])?
+        Some(("check", sub_args)) => handle_check_subcommand(sub_args)?,
+        Some(("events", sub_args)) => {
+            handle_events_subcommand(sub_args).map_err(|err| vec![err])?
+        }
+        Some(("install", sub_args)) => {
+            handle_install_subcommand(sub_args).map_err(|err| vec![err])?
+        }
+        Some(("man", sub_args)) => handle_man_subcommand(sub_args).map_err(|err| vec![err])?,
+        Some(("notifiers", sub_args)) => handle_notifiers_subcommand(sub_args)?,
+        Some(("pause", sub_args)) => handle_pause_subcommand(sub_args).map_err(|err| vec![err])?,
+        Some(("resume", sub_args)) => {
+            handle_resume_subcommand(sub_args).map_err(|err| vec![err])?
+        }
+        Some(("rules", sub_args)) => handle_rules_subcommand(sub_args)?,
+        Some(("settings", sub_args)) => handle_settings_subcommand(sub_args)?,
+        Some(("silence", sub_args)) => {
+            handle_silence_subcommand(sub_args).map_err(|err| vec![err])?
         }
         _ => {
-            let loop_once = args.get_one::<bool>("loop-once").unwrap();
-            let loop_timeout = get_loop_timeout(&args).map_err(|err| vec![err])?;
-            handle_no_subcommand(*loop_once, loop_timeout)?;
+            let options = RunOptions {
+                loop_once: *args.get_one::<bool>("loop-once").unwrap(),
+                loop_timeout: get_loop_timeout(&args).map_err(|err| vec![err])?,
+                low_power: *args.get_one::<bool>("low-power").unwrap(),
+                oneshot: *args.get_one::<bool>("oneshot").unwrap(),
+                single_thread: *args.get_one::<bool>("single-thread").unwrap(),
+                ..RunOptions::default()
+            };
+            let check_notifiers = args.get_one::<bool>("check-notifiers").unwrap();
+            handle_no_subcommand(&options, *check_notifiers)?;
+        }
+    };
+    Ok(())
+}
+
+// Handle the 'check' subcommand.
+//
+// If any units are found, exit non-zero. In Nagios mode, errors are reported on stdout, and the
+// exit status follows Nagios plugin conventions. In Zabbix discovery mode, every unit is listed,
+// and finding units isn't an error. If a Zabbix server is given, every unit's state is sent to it
+// first.
+fn handle_check_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
+    let result = settings::load(None)
+        .map_err(|err| vec![err])
+        .and_then(|settings| {
+            let report = killjoy::check(&settings)?;
+            if let Some(server) = args.get_one::<String>("zabbix-server") {
+                zabbix::send(
+                    server,
+                    *args.get_one::<u16>("zabbix-port").unwrap(),
+                    args.get_one::<String>("zabbix-host").unwrap(),
+                    &report.units,
+                    zabbix::DEFAULT_TIMEOUT,
+                )
+                .map_err(|err| {
+                    vec![CrateError::Action(ActionError::ContactZabbixServer(
+                        server.to_owned(),
+                        err,
+                    ))]
+                })?;
+            }
+            let findings: Vec<(Event, Severity)> = report
+                .events
+                .into_iter()
+                .map(|event| {
+                    let severity = get_event_severity(&settings, &event);
+                    (event, severity)
+                })
+                .collect();
+            Ok((findings, report.units))
+        });
+    let format = match args.get_one::<bool>("nagios") {
+        Some(true) => "nagios",
+        _ => args.get_one::<String>("format").unwrap(),
+    };
+    if format == "zabbix-lld" {
+        let (_, units) = result?;
+        println!("{}", zabbix::gen_discovery(&units));
+        return Ok(());
+    }
+    if format == "nagios" {
+        let (status, report) = match result {
+            Ok((findings, _)) => gen_nagios_report(&findings),
+            Err(errs) => {
+                let errs: Vec<String> = errs.iter().map(|err| err.to_string()).collect();
+                (3, format!("KILLJOY UNKNOWN - {}", errs.join("; ")))
+            }
+        };
+        println!("{}", report);
+        process::exit(status);
+    }
+    let (findings, _) = result?;
+    for (event, severity) in &findings {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            event.real_ts.to_iso8601(),
+            settings::encode_bus_type(event.bus_type),
+            event.unit_name,
+            event.new_state,
+            String::from(*severity),
+        );
+    }
+    if !findings.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// Get the highest severity among the rules which match an event.
+fn get_event_severity(settings: &Settings, event: &Event) -> Severity {
+    settings
+        .rules
+        .iter()
+        .filter(|rule| {
+            rule.bus_type == event.bus_type
+                && rule.expression.matches(&event.unit_name)
+                && rule.active_states.contains(&event.new_state)
+        })
+        .map(|rule| rule.severity)
+        .max()
+        .unwrap_or(Severity::Info)
+}
+
+// Summarize the units found by a check on one line, and get the matching Nagios exit status.
+//
+// The line ends with performance data, as per the Nagios plugin API.
+fn gen_nagios_report(findings: &[(Event, Severity)]) -> (i32, String) {
+    let count_in = |state: ActiveState| {
+        findings
+            .iter()
+            .filter(|(event, _)| event.new_state == state)
+            .count()
+    };
+    let perfdata = format!(
+        "units={};;;0 failed={};;;0 activating={};;;0",
+        findings.len(),
+        count_in(ActiveState::Failed),
+        count_in(ActiveState::Activating),
+    );
+    if findings.is_empty() {
+        return (
+            0,
+            format!("KILLJOY OK - no units of concern | {}", perfdata),
+        );
+    }
+    let (status, label) = match findings.iter().map(|(_, severity)| *severity).max() {
+        Some(Severity::Critical) => (2, "CRITICAL"),
+        _ => (1, "WARNING"),
+    };
+    let units: Vec<String> = findings
+        .iter()
+        .map(|(event, _)| format!("{} is {}", event.unit_name, event.new_state))
+        .collect();
+    (
+        status,
+        format!(
+            "KILLJOY {} - {} units of concern: {} | {}",
+            label,
+            findings.len(),
+            units.join(", "),
+            perfdata
+        ),
+    )
+}
+
+// Handle the 'events' subcommand.
+fn handle_events_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    if let Some(("query", sub_args)) = args.subcommand() {
+        return handle_events_query_subcommand(sub_args);
+    }
+    let count = *args.get_one::<usize>("last").unwrap();
+    let history = History::load(&history::get_history_path()?)?;
+    for entry in history.last(count) {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            RealtimeTimestamp(entry.timestamp).to_iso8601(),
+            entry.bus_type,
+            entry.unit,
+            entry.old_state.as_deref().unwrap_or("-"),
+            entry.new_state,
+        );
+    }
+    Ok(())
+}
+
+// Handle the 'events query' subcommand.
+#[cfg(feature = "sqlite")]
+fn handle_events_query_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let now = timestamp::get_current_realtime_timestamp();
+    let query = Query {
+        unit: args.get_one::<String>("unit").cloned(),
+        state: args.get_one::<String>("state").cloned(),
+        since: parse_time_arg(args.get_one::<String>("since"), &now)?,
+        until: parse_time_arg(args.get_one::<String>("until"), &now)?,
+    };
+    let settings: Settings = settings::load(None)?;
+    let path = match settings
+        .event_store
+        .and_then(|event_store| event_store.path)
+    {
+        Some(path) => path,
+        None => event_store::get_event_store_path()?,
+    };
+    let database = Database::open(&path)?;
+    if *args.get_one::<bool>("notifications").unwrap() {
+        for attempt in database.query_notifications(&query)? {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                RealtimeTimestamp(attempt.timestamp).to_iso8601(),
+                attempt.bus_type,
+                attempt.unit,
+                attempt.state,
+                attempt.notifier,
+                attempt.error.as_deref().unwrap_or("sent"),
+            );
+        }
+    } else {
+        for entry in database.query_transitions(&query)? {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                RealtimeTimestamp(entry.timestamp).to_iso8601(),
+                entry.bus_type,
+                entry.unit,
+                entry.old_state.as_deref().unwrap_or("-"),
+                entry.new_state,
+            );
         }
+    }
+    Ok(())
+}
+
+// Handle the 'events query' subcommand, in a build without an event store.
+#[cfg(not(feature = "sqlite"))]
+fn handle_events_query_subcommand(_args: &ArgMatches) -> Result<(), CrateError> {
+    Err(CrateError::State(StateError::EventStoreNotSupported))
+}
+
+// Parse a time given on the command line, as either a duration before `now`, or an ISO 8601 time.
+#[cfg(feature = "sqlite")]
+fn parse_time_arg(
+    value: Option<&String>,
+    now: &RealtimeTimestamp,
+) -> Result<Option<u64>, CrateError> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
     };
+    match silence::parse_duration(value) {
+        Ok(duration) => Ok(Some(now.0.saturating_sub(duration.as_micros() as u64))),
+        Err(_) => RealtimeTimestamp::from_iso8601(value).map(|timestamp| Some(timestamp.0)),
+    }
+}
+
+// Handle the 'install' subcommand.
+fn handle_install_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let scope = if *args.get_one::<bool>("system").unwrap() {
+        InstallScope::System
+    } else {
+        InstallScope::User
+    };
+    let exec_path = env::current_exe().map_err(CrateError::GetExecutablePath)?;
+    let unit = install::gen_service_unit(scope, &exec_path);
+    if *args.get_one::<bool>("print").unwrap() {
+        print!("{}", unit);
+        return Ok(());
+    }
+    let path = install::get_service_unit_path(scope)?;
+    install::write_service_unit(&path, &unit, *args.get_one::<bool>("force").unwrap())?;
+    println!(
+        "Installed {}. To start killjoy now and at every {}, run:",
+        path.display(),
+        match scope {
+            InstallScope::System => "boot",
+            InstallScope::User => "login",
+        }
+    );
+    println!(
+        "systemctl{} daemon-reload && systemctl{} enable --now {}",
+        scope.systemctl_flag(),
+        scope.systemctl_flag(),
+        install::SERVICE_UNIT_NAME
+    );
+    Ok(())
+}
+
+// Handle the 'man' subcommand.
+fn handle_man_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let mut cmd = cli::build_man_cli();
+    cmd.build();
+    match args.get_one::<String>("output-dir") {
+        Some(output_dir) => write_man_pages(&cmd, Path::new(output_dir)),
+        None => Man::new(cmd).render(&mut io::stdout()),
+    }
+    .map_err(CrateError::WriteManPage)
+}
+
+// Write a man page for a built command, and for each of its subcommands, into `output_dir`.
+//
+// Pages are named after the command's display name, e.g. `killjoy-settings-validate.1`, so that
+// they may refer to each other. clap's `help` subcommands are skipped, as they're self-explanatory.
+fn write_man_pages(cmd: &Command, output_dir: &Path) -> io::Result<()> {
+    for sub_cmd in cmd.get_subcommands() {
+        if sub_cmd.get_name() != "help" {
+            write_man_pages(sub_cmd, output_dir)?;
+        }
+    }
+    let name = cmd
+        .get_display_name()
+        .unwrap_or_else(|| cmd.get_name())
+        .to_owned();
+    let mut file = File::create(output_dir.join(format!("{}.1", name)))?;
+    Man::new(cmd.clone().name(name)).render(&mut file)
+}
+
+// Handle the 'notifiers' subcommand.
+fn handle_notifiers_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
+    match args.subcommand() {
+        Some(("check", _)) => handle_notifiers_check_subcommand(),
+        Some(("test", sub_args)) => {
+            handle_notifiers_test_subcommand(sub_args).map_err(|err| vec![err])
+        }
+        _ => Err(vec![CrateError::UnexpectedSubcommand(
+            args.subcommand_name().map(String::from),
+        )]),
+    }?;
+    Ok(())
+}
+
+// Handle the 'notifiers check' subcommand.
+fn handle_notifiers_check_subcommand() -> Result<(), Vec<CrateError>> {
+    let settings: Settings = settings::load(None).map_err(|err| vec![err])?;
+    let mut errs: Vec<CrateError> = Vec::new();
+    for (notifier_name, result) in ping_notifiers(&settings) {
+        match result {
+            Ok(()) => println!("{}", notifier_name),
+            Err(err) => errs.push(err),
+        }
+    }
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(errs)
+    }
+}
+
+// Handle the 'notifiers test' subcommand.
+fn handle_notifiers_test_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let notifier_name = args.get_one::<String>("name").unwrap();
+    let settings: Settings = settings::load(None)?;
+    let notifier = settings.notifiers.get(notifier_name).ok_or_else(|| {
+        CrateError::Notifier(NotifierError::NoSuchNotifier(notifier_name.to_owned()))
+    })?;
+    bus::send_test_notification(notifier_name, notifier, &settings.identity)
+}
+
+// Handle the 'pause' subcommand.
+//
+// If no bus is given, print the paused buses, one per line.
+fn handle_pause_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let path = pause::get_paused_buses_path()?;
+    let mut paused_buses = PausedBuses::load(&path)?;
+    match args.get_one::<String>("bus-type") {
+        Some(bus_type_str) => {
+            paused_buses.pause(settings::decode_bus_type_str(bus_type_str)?);
+            paused_buses.save(&path)
+        }
+        None => {
+            for bus_type_str in paused_buses.iter() {
+                println!("{}", bus_type_str);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Handle the 'resume' subcommand.
+fn handle_resume_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let bus_type = settings::decode_bus_type_str(args.get_one::<String>("bus-type").unwrap())?;
+    let path = pause::get_paused_buses_path()?;
+    let mut paused_buses = PausedBuses::load(&path)?;
+    paused_buses.resume(bus_type);
+    paused_buses.save(&path)
+}
+
+// Handle the 'rules' subcommand.
+fn handle_rules_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
+    match args.subcommand() {
+        Some(("add", sub_args)) => handle_rules_add_subcommand(sub_args),
+        Some(("list", _)) => handle_rules_list_subcommand().map_err(|err| vec![err]),
+        Some(("remove", sub_args)) => {
+            handle_rules_remove_subcommand(sub_args).map_err(|err| vec![err])
+        }
+        _ => Err(vec![CrateError::UnexpectedSubcommand(
+            args.subcommand_name().map(String::from),
+        )]),
+    }?;
+    Ok(())
+}
+
+// Handle the 'rules add' subcommand.
+//
+// The rule is checked against the settings file, and against the rules already added at runtime.
+fn handle_rules_add_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
+    let rule: serde_json::Value = serde_json::from_str(args.get_one::<String>("rule").unwrap())
+        .map_err(|err| vec![CrateError::State(StateError::ParseRuntimeRule(err))])?;
+    let settings: Settings = settings::load(None).map_err(|err| vec![err])?;
+    let id = runtime_rules::add(&settings, rule)?;
+    println!("{}", id);
     Ok(())
 }
 
+// Handle the 'rules list' subcommand.
+fn handle_rules_list_subcommand() -> Result<(), CrateError> {
+    let runtime_rules = RuntimeRules::load(&runtime_rules::get_runtime_rules_path()?)?;
+    for runtime_rule in runtime_rules.iter() {
+        println!("{}\t{}", runtime_rule.id, runtime_rule.rule);
+    }
+    Ok(())
+}
+
+// Handle the 'rules remove' subcommand.
+fn handle_rules_remove_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let id = *args.get_one::<u32>("id").unwrap();
+    let path = runtime_rules::get_runtime_rules_path()?;
+    let mut runtime_rules = RuntimeRules::load(&path)?;
+    runtime_rules.remove(id)?;
+    runtime_rules.save(&path)
+}
+
 // Handle the 'settings' subcommand.
-fn handle_settings_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+fn handle_settings_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
     match args.subcommand() {
-        Some(("load-path", _)) => handle_settings_load_path_subcommand(),
-        Some(("validate", sub_args)) => handle_settings_validate_subcommand(&sub_args),
-        _ => Err(CrateError::UnexpectedSubcommand(
+        Some(("load-path", _)) => handle_settings_load_path_subcommand().map_err(|err| vec![err]),
+        Some(("migrate", sub_args)) => {
+            handle_settings_migrate_subcommand(sub_args).map_err(|err| vec![err])
+        }
+        Some(("validate", sub_args)) => handle_settings_validate_subcommand(sub_args),
+        _ => Err(vec![CrateError::UnexpectedSubcommand(
             args.subcommand_name().map(String::from),
-        )),
+        )]),
     }?;
     Ok(())
 }
@@ -66,50 +515,134 @@ fn handle_settings_load_path_subcommand() -> Result<(), CrateError> {
     Ok(())
 }
 
+// Handle the 'settings migrate' subcommand.
+fn handle_settings_migrate_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let path = args.get_one::<String>("path").map(PathBuf::from);
+    let migrated = settings::migrate(path.as_deref())?;
+    if *args.get_one::<bool>("in-place").unwrap() {
+        let path = match path {
+            Some(path) => path,
+            None => settings::get_load_path()?,
+        };
+        fs::write(path, migrated + "\n")
+            .map_err(|err| CrateError::Settings(SettingsError::SettingsFileNotWritable(err)))?;
+    } else {
+        println!("{}", migrated);
+    }
+    Ok(())
+}
+
 // Handle the 'settings validate' subcommand.
-fn handle_settings_validate_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
-    let path = args.get_one::<String>("path").map(|path_str| Path::new(path_str));
-    settings::load(path)?;
+fn handle_settings_validate_subcommand(args: &ArgMatches) -> Result<(), Vec<CrateError>> {
+    let path = args.get_one::<String>("path").map(Path::new);
+    let settings = settings::validate(path)?;
+    if *args.get_one::<bool>("strict").unwrap() {
+        let errs = bus::check_settings_against_system(&settings);
+        if !errs.is_empty() {
+            return Err(errs);
+        }
+    }
+    Ok(())
+}
+
+// Handle the 'silence' subcommand.
+fn handle_silence_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    match args.subcommand() {
+        Some(("add", sub_args)) => handle_silence_add_subcommand(sub_args),
+        Some(("list", _)) => handle_silence_list_subcommand(),
+        Some(("remove", sub_args)) => handle_silence_remove_subcommand(sub_args),
+        _ => Err(CrateError::UnexpectedSubcommand(
+            args.subcommand_name().map(String::from),
+        )),
+    }?;
+    Ok(())
+}
+
+// Handle the 'silence add' subcommand.
+fn handle_silence_add_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let duration = silence::parse_duration(args.get_one::<String>("duration").unwrap())?;
+    let unit = args.get_one::<String>("unit").cloned();
+    let comment = args.get_one::<String>("comment").cloned();
+    let now = timestamp::get_current_realtime_timestamp();
+    let path = silence::get_silences_path()?;
+    let mut silences = Silences::load(&path)?;
+    silences.prune(&now);
+    let id = silences.add(unit, comment, duration, &now);
+    silences.save(&path)?;
+    println!("{}", id);
+    Ok(())
+}
+
+// Handle the 'silence list' subcommand.
+fn handle_silence_list_subcommand() -> Result<(), CrateError> {
+    let now = timestamp::get_current_realtime_timestamp();
+    let mut silences = Silences::load(&silence::get_silences_path()?)?;
+    silences.prune(&now);
+    for silence in silences.iter() {
+        println!(
+            "{}\t{}\t{}\t{}",
+            silence.id,
+            RealtimeTimestamp(silence.expires).to_iso8601(),
+            silence.unit.as_deref().unwrap_or("*"),
+            silence.comment.as_deref().unwrap_or(""),
+        );
+    }
     Ok(())
 }
 
+// Handle the 'silence remove' subcommand.
+fn handle_silence_remove_subcommand(args: &ArgMatches) -> Result<(), CrateError> {
+    let id = *args.get_one::<u32>("id").unwrap();
+    let path = silence::get_silences_path()?;
+    let mut silences = Silences::load(&path)?;
+    silences.remove(id)?;
+    silences.save(&path)
+}
+
 // Handle no subcommand at all.
 //
 // For each unique D-Bus bus listed in the settings file, spawn a thread. Each thread connects to a
-// D-Bus bus, and talks to the instance of systemd available on that bus, and the notifiers
-// available on that bus.
-fn handle_no_subcommand(loop_once: bool, loop_timeout: u32) -> Result<(), Vec<CrateError>> {
+// D-Bus bus, and talks to the instance of systemd available on that bus, and the notifiers used by
+// its rules, on whichever bus each is. In oneshot mode, each thread exits once it has handled
+// units' current states. Bail out if a notifier is on a bus killjoy can't connect to.
+fn handle_no_subcommand(
+    options: &RunOptions,
+    check_notifiers: bool,
+) -> Result<(), Vec<CrateError>> {
     let settings: Settings = settings::load(None).map_err(|err: CrateError| vec![err])?;
-    let handles: Vec<JoinHandle<_>> = settings::get_bus_types(&settings.rules)
-        .into_iter()
-        .map(|bus_type| {
-            let settings_clone = settings.clone();
-            thread::spawn(move || {
-                BusWatcher::new(bus_type, settings_clone, loop_once, loop_timeout)?.run()
-            })
-        })
-        .collect();
-
-    // Handles are joined in the order they appear in the vector, not the order in which they exit,
-    // meaning that there may be a long delay between an error occurring and this main thread
-    // learning about it. Consequently, the monitoring threads should print their own error messages
-    // whenever possible.
-    let mut errs: Vec<CrateError> = Vec::new();
-    for handle in handles {
-        match handle.join() {
-            Err(err) => errs.push(CrateError::MonitoringThreadPanicked(err)),
-            Ok(result) => {
-                if let Err(err) = result {
-                    errs.push(err);
-                }
+    let errs = bus::check_notifier_buses(&settings);
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    // A notifier might be started later, or allowed by a policy killjoy doesn't know of, so report
+    // problems rather than bailing out.
+    for err in bus::check_notifiers_against_system(&settings) {
+        eprintln!("{}", err.with_code());
+    }
+    if check_notifiers {
+        for (_, result) in ping_notifiers(&settings) {
+            if let Err(err) = result {
+                eprintln!("{}", err.with_code());
             }
         }
     }
-    if errs.is_empty() {
-        Ok(())
-    } else {
-        Err(errs)
-    }
+    killjoy::run(settings, options)
+}
+
+// Ping each notifier, in order of name, and return the name and outcome of each ping.
+fn ping_notifiers(settings: &Settings) -> Vec<(&str, Result<(), CrateError>)> {
+    let mut notifier_names: Vec<&String> = settings.notifiers.keys().collect();
+    notifier_names.sort();
+    notifier_names
+        .into_iter()
+        .map(|notifier_name| {
+            let notifier = &settings.notifiers[notifier_name];
+            (
+                &notifier_name[..],
+                bus::ping_notifier(notifier_name, notifier),
+            )
+        })
+        .collect()
 }
 
 // Get the `loop-timeout` argument, or return an error explaining why the getting failed.
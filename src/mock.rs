@@ -0,0 +1,845 @@
+// A simulated systemd, which bus watchers may talk to instead of a D-Bus connection.
+//
+// Requires the `mock-bus` feature. A `MockSystemd` holds a table of units, which the caller fills
+// in and changes, and answers the method calls which bus watchers make about them, as systemd
+// would. Changes are announced with the signals systemd would emit, i.e. `UnitNew`, `UnitRemoved`
+// and `PropertiesChanged`, if a match rule asks for them. This allows for developing killjoy on
+// hosts without systemd, and for deterministic examples and tests.
+//
+// Time is simulated too: each change happens a second after the one before, so that the same
+// changes always produce the same timestamps. The simulation is shallow. Unit files, portable
+// images and jobs aren't simulated: no unit files are listed, and portabled can't be reached.
+// Match rules are told apart by their member and path only.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::{Error as DBusError, Message, Path, SignalArgs};
+
+use crate::bus::UnitProps;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopDBusPropertiesPropertiesChanged as PropertiesChanged;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitNew as UnitNew;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitRemoved as UnitRemoved;
+use crate::monitor::WakePipe;
+use crate::systemd;
+use crate::systemd::{SystemdBackend, SystemdManager};
+use crate::timestamp;
+use crate::unit;
+use crate::unit::ActiveState;
+
+const BUS_NAME_FOR_SYSTEMD: &str = "org.freedesktop.systemd1";
+const PATH_FOR_SYSTEMD: &str = "/org/freedesktop/systemd1";
+const INTERFACE_FOR_SYSTEMD_MANAGER: &str = "org.freedesktop.systemd1.Manager";
+const INTERFACE_FOR_SYSTEMD_UNIT: &str = "org.freedesktop.systemd1.Unit";
+const INTERFACE_FOR_PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+// The unique name which the simulated bus gives the connection.
+const UNIQUE_NAME: &str = ":1.1";
+
+// How far the simulated clock advances with each change, in µs.
+const TICK: u64 = 1_000_000;
+
+// When the simulated host booted, in µs since the epoch: 2020-01-01T00:00:00Z.
+const BOOT_TIME: u64 = 1_577_836_800_000_000;
+
+// The timestamps which org.freedesktop.systemd1.Unit has, each of which systemd also reports as a
+// monotonic timestamp, e.g. `ActiveEnterTimestampMonotonic`.
+const TIMESTAMP_PROPERTIES: [&str; 5] = [
+    "ActiveEnterTimestamp",
+    "ActiveExitTimestamp",
+    "InactiveEnterTimestamp",
+    "InactiveExitTimestamp",
+    "StateChangeTimestamp",
+];
+
+// The value of a simulated property.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockValue {
+    Bool(bool),
+    Str(String),
+    Strs(Vec<String>),
+    U32(u32),
+    U64(u64),
+}
+
+impl MockValue {
+    fn to_ref_arg(&self) -> Box<dyn RefArg> {
+        match self {
+            MockValue::Bool(value) => Box::new(*value),
+            MockValue::Str(value) => Box::new(value.clone()),
+            MockValue::Strs(value) => Box::new(value.clone()),
+            MockValue::U32(value) => Box::new(*value),
+            MockValue::U64(value) => Box::new(*value),
+        }
+    }
+}
+
+// A simulated systemd. See the top of this module.
+//
+// Clones share state, so one clone may be handed to a bus watcher, while another changes units,
+// e.g. from another thread. See `BusWatcher::with_backend`. A pipe is readable whenever messages
+// are waiting to be received, so that bus watchers sleep until then. If the pipe can't be created,
+// bus watchers poll the mock once per loop timeout instead.
+#[derive(Clone, Debug)]
+pub struct MockSystemd {
+    state: Arc<Mutex<MockState>>,
+    pipe: Option<Arc<WakePipe>>,
+}
+
+// The state shared by a mock's clones.
+//
+// `queue` holds the messages waiting to be received, described as plain data, so that the mock is
+// `Send`. They're turned into D-Bus messages as they're received.
+#[derive(Debug)]
+struct MockState {
+    clock: u64,
+    matches: Vec<String>,
+    queue: VecDeque<Queued>,
+    serial: u32,
+    system_state: String,
+    units: BTreeMap<String, MockUnit>,
+}
+
+// A simulated unit.
+//
+// `timestamps` holds when the unit last entered each state, keyed by property name. Timestamps of
+// states it hasn't entered yet are 0, as in systemd. `properties` holds properties set with
+// `MockSystemd::set_property`, keyed by interface, then by property name. The properties of
+// org.freedesktop.systemd1.Unit which killjoy tracks are kept in the other fields.
+#[derive(Debug)]
+struct MockUnit {
+    active_state: ActiveState,
+    sub_state: String,
+    timestamps: BTreeMap<&'static str, u64>,
+    properties: BTreeMap<String, BTreeMap<String, MockValue>>,
+}
+
+// A message waiting to be received.
+#[derive(Debug)]
+enum Queued {
+    // The reply to the call with the given serial.
+    Reply(u32, Answer),
+    // The NameAcquired signal which the bus sends new connections.
+    NameAcquired,
+    // A PropertiesChanged signal for the given unit and interface.
+    PropertiesChanged(String, String, BTreeMap<String, MockValue>),
+    // A UnitNew signal for the given unit.
+    UnitNew(String),
+    // A UnitRemoved signal for the given unit.
+    UnitRemoved(String),
+}
+
+// What a method call is answered with: the reply's arguments, or an error's name and message.
+type Answer = Result<Reply, (String, String)>;
+
+// The arguments of a reply.
+#[derive(Debug)]
+enum Reply {
+    Empty,
+    Path(String),
+    Properties(BTreeMap<String, MockValue>),
+    UnitFiles(Vec<(String, String)>),
+    Units(Vec<(String, String, String, String)>),
+    Value(MockValue),
+}
+
+impl Default for MockSystemd {
+    fn default() -> Self {
+        let mut queue = VecDeque::new();
+        queue.push_back(Queued::NameAcquired);
+        let pipe = WakePipe::new().map(Arc::new);
+        if let Some(pipe) = &pipe {
+            pipe.wake();
+        }
+        MockSystemd {
+            state: Arc::new(Mutex::new(MockState {
+                clock: TICK,
+                matches: Vec::new(),
+                queue,
+                serial: 0,
+                system_state: "running".to_string(),
+                units: BTreeMap::new(),
+            })),
+            pipe,
+        }
+    }
+}
+
+impl MockSystemd {
+    // Create a simulated systemd without any units, whose system state is "running".
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Load a unit in the given state, or change its state if it's loaded already.
+    //
+    // The unit's sub state is the one systemd commonly pairs with the given state, e.g. "running"
+    // for "active".
+    pub fn add_unit(&self, unit_name: &str, active_state: ActiveState) {
+        if self.lock().units.contains_key(unit_name) {
+            self.set_active_state(unit_name, active_state);
+            return;
+        }
+        let mut state = self.lock();
+        let now = state.tick();
+        let mut mock_unit = MockUnit {
+            active_state: active_state.clone(),
+            sub_state: get_sub_state(&active_state).to_string(),
+            timestamps: BTreeMap::new(),
+            properties: BTreeMap::new(),
+        };
+        mock_unit.enter(active_state, now);
+        state.units.insert(unit_name.to_string(), mock_unit);
+        self.emit(&mut state, "UnitNew", PATH_FOR_SYSTEMD, unit_name, || {
+            Queued::UnitNew(unit_name.to_string())
+        });
+    }
+
+    // Change a unit's active state, and its sub state to the one systemd commonly pairs with it.
+    // Do nothing if the unit isn't loaded.
+    pub fn set_active_state(&self, unit_name: &str, active_state: ActiveState) {
+        let sub_state = get_sub_state(&active_state).to_string();
+        self.change_unit(unit_name, move |mock_unit, now| {
+            mock_unit.sub_state = sub_state;
+            mock_unit.enter(active_state, now);
+        });
+    }
+
+    // Change a unit's sub state, e.g. to "auto-restart". Do nothing if the unit isn't loaded.
+    pub fn set_sub_state(&self, unit_name: &str, sub_state: &str) {
+        self.change_unit(unit_name, |mock_unit, now| {
+            mock_unit.sub_state = sub_state.to_string();
+            mock_unit
+                .timestamps
+                .insert("StateChangeTimestamp", BOOT_TIME + now);
+            mock_unit
+                .timestamps
+                .insert("StateChangeTimestampMonotonic", now);
+        });
+    }
+
+    // Set a property of one of a unit's interfaces, e.g. `NRestarts` of
+    // org.freedesktop.systemd1.Service. Do nothing if the unit isn't loaded.
+    pub fn set_property(&self, unit_name: &str, interface: &str, property: &str, value: MockValue) {
+        let mut state = self.lock();
+        let mock_unit = match state.units.get_mut(unit_name) {
+            Some(mock_unit) => mock_unit,
+            None => return,
+        };
+        mock_unit
+            .properties
+            .entry(interface.to_string())
+            .or_default()
+            .insert(property.to_string(), value.clone());
+        let mut changed = BTreeMap::new();
+        changed.insert(property.to_string(), value);
+        let unit_path = unit::get_unit_path(unit_name);
+        self.emit(
+            &mut state,
+            "PropertiesChanged",
+            &unit_path,
+            interface,
+            || Queued::PropertiesChanged(unit_name.to_string(), interface.to_string(), changed),
+        );
+    }
+
+    // Unload a unit. Do nothing if it isn't loaded.
+    pub fn remove_unit(&self, unit_name: &str) {
+        let mut state = self.lock();
+        if state.units.remove(unit_name).is_none() {
+            return;
+        }
+        self.emit(
+            &mut state,
+            "UnitRemoved",
+            PATH_FOR_SYSTEMD,
+            unit_name,
+            || Queued::UnitRemoved(unit_name.to_string()),
+        );
+    }
+
+    // Change the system state, e.g. to "degraded".
+    pub fn set_system_state(&self, system_state: &str) {
+        self.lock().system_state = system_state.to_string();
+    }
+
+    // Tell whether a match rule asks for the given unit's PropertiesChanged signals, i.e. whether
+    // a bus watcher is watching the unit.
+    pub fn is_watched(&self, unit_name: &str) -> bool {
+        self.lock().is_matched(
+            "PropertiesChanged",
+            &unit::get_unit_path(unit_name),
+            INTERFACE_FOR_SYSTEMD_UNIT,
+        )
+    }
+
+    // Change a loaded unit's state with `change`, which is given the time of the change, and
+    // announce the unit's new state. Do nothing if the unit isn't loaded.
+    fn change_unit<F>(&self, unit_name: &str, change: F)
+    where
+        F: FnOnce(&mut MockUnit, u64),
+    {
+        let mut state = self.lock();
+        let now = state.tick();
+        let mock_unit = match state.units.get_mut(unit_name) {
+            Some(mock_unit) => mock_unit,
+            None => return,
+        };
+        change(mock_unit, now);
+        let changed = mock_unit.get_unit_properties(unit_name);
+        let unit_path = unit::get_unit_path(unit_name);
+        let interface = INTERFACE_FOR_SYSTEMD_UNIT;
+        self.emit(
+            &mut state,
+            "PropertiesChanged",
+            &unit_path,
+            interface,
+            || Queued::PropertiesChanged(unit_name.to_string(), interface.to_string(), changed),
+        );
+    }
+
+    // Queue the signal made by `signal`, if a match rule asks for signals with the given member
+    // from the given path, whose first argument is `arg0`.
+    fn emit<F>(&self, state: &mut MockState, member: &str, path: &str, arg0: &str, signal: F)
+    where
+        F: FnOnce() -> Queued,
+    {
+        if state.is_matched(member, path, arg0) {
+            self.push(state, signal());
+        }
+    }
+
+    // Queue a message to be received, and make the pipe readable.
+    fn push(&self, state: &mut MockState, queued: Queued) {
+        state.queue.push_back(queued);
+        if let Some(pipe) = &self.pipe {
+            pipe.wake();
+        }
+    }
+
+    // Take the next message to be received, if any. Once none are left, the pipe is drained.
+    fn pop(&self) -> Option<Message> {
+        let mut state = self.lock();
+        let queued = state.queue.pop_front()?;
+        if state.queue.is_empty() {
+            if let Some(pipe) = &self.pipe {
+                drain(pipe);
+            }
+        }
+        Some(gen_message(queued))
+    }
+
+    // Lock the shared state, even if a thread panicked while holding the lock.
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl SystemdBackend for MockSystemd {
+    fn call(&self, mut msg: Message, _timeout_ms: i32) -> Result<Message, DBusError> {
+        let mut state = self.lock();
+        msg.set_serial(state.next_serial());
+        match state.answer(&msg) {
+            Ok(reply) => Ok(append_reply(
+                Message::new_method_return(&msg).expect("Failed to create reply."),
+                reply,
+            )),
+            Err((name, message)) => Err(DBusError::new_custom(&name, &message)),
+        }
+    }
+
+    fn send(&self, mut msg: Message) -> Option<u32> {
+        let mut state = self.lock();
+        let serial = state.next_serial();
+        msg.set_serial(serial);
+        let answer = state.answer(&msg);
+        self.push(&mut state, Queued::Reply(serial, answer));
+        Some(serial)
+    }
+
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message> {
+        if let Some(msg) = self.pop() {
+            return Some(msg);
+        }
+        let mut pollfds = self.get_pollfds();
+        if !pollfds.is_empty() {
+            let timeout = libc::c_int::try_from(timeout_ms).unwrap_or(libc::c_int::MAX);
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+        }
+        self.pop()
+    }
+
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError> {
+        self.lock().matches.push(match_str.to_string());
+        Ok(())
+    }
+
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError> {
+        let mut state = self.lock();
+        match state.matches.iter().position(|other| other == match_str) {
+            Some(i) => {
+                state.matches.remove(i);
+                Ok(())
+            }
+            None => Err(DBusError::new_custom(
+                "org.freedesktop.DBus.Error.MatchRuleNotFound",
+                "The given match rule wasn't found and can't be removed",
+            )),
+        }
+    }
+
+    fn get_pollfds(&self) -> Vec<libc::pollfd> {
+        self.pipe
+            .iter()
+            .map(|pipe| libc::pollfd {
+                fd: pipe.fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect()
+    }
+}
+
+impl SystemdManager for MockSystemd {}
+
+impl MockState {
+    // Advance the simulated clock, and return the new monotonic time, in µs.
+    fn tick(&mut self) -> u64 {
+        self.clock += TICK;
+        self.clock
+    }
+
+    // Get a serial for a message being sent.
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    // Tell whether a match rule asks for signals with the given member from the given path, whose
+    // first argument is `arg0`.
+    fn is_matched(&self, member: &str, path: &str, arg0: &str) -> bool {
+        systemd::is_matched(&self.matches, member, path, Some(arg0))
+    }
+
+    // Answer a method call, as systemd would.
+    fn answer(&self, msg: &Message) -> Answer {
+        let destination = msg.destination().map(|name| name.to_string());
+        if destination.as_deref() != Some(BUS_NAME_FOR_SYSTEMD) {
+            return Err((
+                "org.freedesktop.DBus.Error.ServiceUnknown".to_string(),
+                format!(
+                    "The name {} is not simulated",
+                    destination.unwrap_or_default()
+                ),
+            ));
+        }
+        let path = msg.path().map(|path| path.to_string()).unwrap_or_default();
+        let interface = msg.interface().map(|interface| interface.to_string());
+        let member = msg.member().map(|member| member.to_string());
+        match (interface.as_deref(), member.as_deref()) {
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("Subscribe")) => Ok(Reply::Empty),
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("ListUnits")) => Ok(Reply::Units(
+                self.units
+                    .iter()
+                    .map(|(unit_name, mock_unit)| {
+                        (
+                            unit_name.to_string(),
+                            mock_unit.active_state.to_string(),
+                            mock_unit.sub_state.to_string(),
+                            unit::get_unit_path(unit_name),
+                        )
+                    })
+                    .collect(),
+            )),
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("ListUnitFiles")) => {
+                Ok(Reply::UnitFiles(Vec::new()))
+            }
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("GetUnit")) => {
+                let unit_name: &str = msg.read1().map_err(gen_invalid_args)?;
+                if !self.units.contains_key(unit_name) {
+                    return Err((
+                        "org.freedesktop.systemd1.NoSuchUnit".to_string(),
+                        format!("Unit {} not loaded.", unit_name),
+                    ));
+                }
+                Ok(Reply::Path(unit::get_unit_path(unit_name)))
+            }
+            (Some(INTERFACE_FOR_PROPERTIES), Some("GetAll")) => {
+                let interface: &str = msg.read1().map_err(gen_invalid_args)?;
+                self.get_properties(&path, interface).map(Reply::Properties)
+            }
+            (Some(INTERFACE_FOR_PROPERTIES), Some("Get")) => {
+                let (interface, property): (&str, &str) = msg.read2().map_err(gen_invalid_args)?;
+                self.get_properties(&path, interface)?
+                    .remove(property)
+                    .map(Reply::Value)
+                    .ok_or_else(|| {
+                        (
+                            "org.freedesktop.DBus.Error.UnknownProperty".to_string(),
+                            format!("Unknown property {}.{}", interface, property),
+                        )
+                    })
+            }
+            _ => Err((
+                "org.freedesktop.DBus.Error.UnknownMethod".to_string(),
+                format!(
+                    "Unknown method {} of interface {}",
+                    member.unwrap_or_default(),
+                    interface.unwrap_or_default(),
+                ),
+            )),
+        }
+    }
+
+    // Get the properties of the given interface, at the given object path.
+    fn get_properties(
+        &self,
+        path: &str,
+        interface: &str,
+    ) -> Result<BTreeMap<String, MockValue>, (String, String)> {
+        if path == PATH_FOR_SYSTEMD {
+            let mut properties = BTreeMap::new();
+            if interface == INTERFACE_FOR_SYSTEMD_MANAGER {
+                properties.insert(
+                    "SystemState".to_string(),
+                    MockValue::Str(self.system_state.clone()),
+                );
+                properties.insert("UserspaceTimestampMonotonic".to_string(), MockValue::U64(0));
+            }
+            return Ok(properties);
+        }
+        let unit_name = unit::get_unit_name(path).ok_or_else(|| gen_unknown_object(path))?;
+        let mock_unit = self
+            .units
+            .get(&unit_name)
+            .ok_or_else(|| gen_unknown_object(path))?;
+        let mut properties = match interface {
+            INTERFACE_FOR_SYSTEMD_UNIT => mock_unit.get_unit_properties(&unit_name),
+            _ => BTreeMap::new(),
+        };
+        if let Some(set) = mock_unit.properties.get(interface) {
+            properties.extend(set.clone());
+        }
+        Ok(properties)
+    }
+}
+
+impl MockUnit {
+    // Enter the given active state at the given monotonic time, in µs.
+    fn enter(&mut self, active_state: ActiveState, now: u64) {
+        for key in &[
+            timestamp::get_monotonic_timestamp_key(&active_state),
+            "StateChangeTimestampMonotonic",
+        ] {
+            self.timestamps.insert(*key, now);
+        }
+        for key in &[
+            timestamp::get_realtime_timestamp_key(&active_state),
+            "StateChangeTimestamp",
+        ] {
+            self.timestamps.insert(*key, BOOT_TIME + now);
+        }
+        self.active_state = active_state;
+    }
+
+    // Get the properties of org.freedesktop.systemd1.Unit which killjoy tracks.
+    fn get_unit_properties(&self, unit_name: &str) -> BTreeMap<String, MockValue> {
+        let mut properties = BTreeMap::new();
+        properties.insert("Id".to_string(), MockValue::Str(unit_name.to_string()));
+        properties.insert(
+            "ActiveState".to_string(),
+            MockValue::Str(self.active_state.to_string()),
+        );
+        properties.insert(
+            "LoadState".to_string(),
+            MockValue::Str("loaded".to_string()),
+        );
+        properties.insert(
+            "SubState".to_string(),
+            MockValue::Str(self.sub_state.clone()),
+        );
+        for name in &TIMESTAMP_PROPERTIES {
+            let monotonic = format!("{}Monotonic", name);
+            let realtime = self.timestamps.get(name).copied().unwrap_or(0);
+            let monotonic_value = self.timestamps.get(&monotonic[..]).copied().unwrap_or(0);
+            properties.insert(name.to_string(), MockValue::U64(realtime));
+            properties.insert(monotonic, MockValue::U64(monotonic_value));
+        }
+        properties
+    }
+}
+
+// Get the sub state which systemd commonly pairs with the given active state.
+fn get_sub_state(active_state: &ActiveState) -> &str {
+    match active_state {
+        ActiveState::Activating => "start",
+        ActiveState::Active => "running",
+        ActiveState::Deactivating => "stop",
+        ActiveState::Failed => "failed",
+        ActiveState::Inactive => "dead",
+        ActiveState::Other(state) => state,
+    }
+}
+
+// Read whatever has been written to the given pipe, so that it isn't readable until woken again.
+fn drain(pipe: &WakePipe) {
+    let mut buf = [0u8; 64];
+    loop {
+        let result =
+            unsafe { libc::read(pipe.fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if result <= 0 {
+            break;
+        }
+    }
+}
+
+// Turn a queued message into a D-Bus message.
+fn gen_message(queued: Queued) -> Message {
+    match queued {
+        Queued::Reply(serial, answer) => {
+            // Replies are created from the calls they answer, so recreate the call.
+            let mut call = gen_method_call("/", INTERFACE_FOR_PROPERTIES, "Get");
+            call.set_serial(serial);
+            match answer {
+                Ok(reply) => append_reply(
+                    Message::new_method_return(&call).expect("Failed to create reply."),
+                    reply,
+                ),
+                Err((name, message)) => Message::new_error(&call, &name, &message)
+                    .expect("Failed to create error reply."),
+            }
+        }
+        Queued::NameAcquired => Message::new_signal(
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "NameAcquired",
+        )
+        .expect("Failed to create NameAcquired signal.")
+        .append1(UNIQUE_NAME),
+        Queued::PropertiesChanged(unit_name, interface, changed) => PropertiesChanged {
+            interface,
+            changed_properties: gen_props(changed),
+            invalidated_properties: Vec::new(),
+        }
+        .to_emit_message(&gen_path(&unit::get_unit_path(&unit_name))),
+        Queued::UnitNew(unit_name) => UnitNew {
+            arg1: gen_path(&unit::get_unit_path(&unit_name)),
+            arg0: unit_name,
+        }
+        .to_emit_message(&gen_path(PATH_FOR_SYSTEMD)),
+        Queued::UnitRemoved(unit_name) => UnitRemoved {
+            arg1: gen_path(&unit::get_unit_path(&unit_name)),
+            arg0: unit_name,
+        }
+        .to_emit_message(&gen_path(PATH_FOR_SYSTEMD)),
+    }
+}
+
+// Append a reply's arguments to a method return.
+fn append_reply(msg: Message, reply: Reply) -> Message {
+    match reply {
+        Reply::Empty => msg,
+        Reply::Path(path) => msg.append1(gen_path(&path)),
+        Reply::Properties(properties) => msg.append1(gen_props(properties)),
+        Reply::UnitFiles(unit_files) => msg.append1(unit_files),
+        Reply::Units(units) => msg.append1(
+            units
+                .into_iter()
+                .map(|(unit_name, active_state, sub_state, unit_path)| {
+                    (
+                        unit_name,
+                        String::new(),
+                        "loaded".to_string(),
+                        active_state,
+                        sub_state,
+                        String::new(),
+                        gen_path(&unit_path),
+                        0u32,
+                        String::new(),
+                        gen_path("/"),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Reply::Value(value) => msg.append1(Variant(value.to_ref_arg())),
+    }
+}
+
+// Turn simulated properties into D-Bus properties.
+fn gen_props(properties: BTreeMap<String, MockValue>) -> UnitProps {
+    properties
+        .into_iter()
+        .map(|(name, value)| (name, Variant(value.to_ref_arg())))
+        .collect()
+}
+
+// Create a method call to systemd.
+fn gen_method_call(path: &str, interface: &str, member: &str) -> Message {
+    Message::new_method_call(BUS_NAME_FOR_SYSTEMD, path, interface, member)
+        .unwrap_or_else(|err| panic!("Failed to create {} call: {}", member, err))
+}
+
+// Create an object path from a string which is known to be valid.
+fn gen_path(path: &str) -> Path<'static> {
+    Path::new(path.to_string()).unwrap_or_else(|_| panic!("Failed to create Path from '{}'", path))
+}
+
+// Describe a call whose arguments can't be read.
+fn gen_invalid_args(err: impl Display) -> (String, String) {
+    (
+        "org.freedesktop.DBus.Error.InvalidArgs".to_string(),
+        err.to_string(),
+    )
+}
+
+// Describe a call to an object path which doesn't exist.
+fn gen_unknown_object(path: &str) -> (String, String) {
+    (
+        "org.freedesktop.DBus.Error.UnknownObject".to_string(),
+        format!("Unknown object '{}'.", path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bus;
+
+    // Receive the messages which are waiting, but skip the NameAcquired signal.
+    fn pop_all(mock: &MockSystemd) -> Vec<Message> {
+        std::iter::from_fn(|| mock.pop_message(0))
+            .filter(|msg| {
+                msg.member()
+                    .map_or(true, |member| &*member != "NameAcquired")
+            })
+            .collect()
+    }
+
+    // MockSystemd::call()
+    #[test]
+    fn test_call() {
+        let mock = MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+
+        let call = gen_method_call(PATH_FOR_SYSTEMD, INTERFACE_FOR_SYSTEMD_MANAGER, "GetUnit")
+            .append1("foo.service");
+        let reply = mock.call(call, 0).expect("Failed to get unit.");
+        let unit_path: Path = reply.read1().expect("Failed to read unit path.");
+        assert_eq!(&*unit_path, "/org/freedesktop/systemd1/unit/foo_2eservice");
+
+        let call = gen_method_call(&unit_path, INTERFACE_FOR_PROPERTIES, "GetAll")
+            .append1(INTERFACE_FOR_SYSTEMD_UNIT);
+        let reply = mock.call(call, 0).expect("Failed to get properties.");
+        let unit_props: bus::UnitProps = reply.read1().expect("Failed to read properties.");
+        assert_eq!(
+            bus::get_active_state(&unit_props).unwrap(),
+            ActiveState::Active
+        );
+        assert_eq!(
+            unit_props["ActiveEnterTimestampMonotonic"].0.as_u64(),
+            Some(2 * TICK)
+        );
+
+        let call = gen_method_call(PATH_FOR_SYSTEMD, INTERFACE_FOR_SYSTEMD_MANAGER, "GetUnit")
+            .append1("bar.service");
+        let err = mock.call(call, 0).unwrap_err();
+        assert_eq!(err.name(), Some("org.freedesktop.systemd1.NoSuchUnit"));
+    }
+
+    // MockSystemd::send()
+    #[test]
+    fn test_send() {
+        let mock = MockSystemd::new();
+        let call = gen_method_call(PATH_FOR_SYSTEMD, INTERFACE_FOR_SYSTEMD_MANAGER, "ListUnits");
+        let serial = mock.send(call).expect("Failed to send call.");
+        let replies = pop_all(&mock);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].get_reply_serial(), Some(serial));
+    }
+
+    // MockSystemd::add_unit(), MockSystemd::set_active_state(), MockSystemd::remove_unit()
+    #[test]
+    fn test_signals() {
+        let mock = MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+        assert!(pop_all(&mock).is_empty());
+
+        let unit_path = gen_path(&unit::get_unit_path("foo.service"));
+        let manager_path = gen_path(PATH_FOR_SYSTEMD);
+        for match_str in &[
+            PropertiesChanged::match_str(None, Some(&unit_path)),
+            UnitNew::match_str(None, Some(&manager_path)),
+            UnitRemoved::match_str(None, Some(&manager_path)),
+        ] {
+            mock.add_match(match_str).expect("Failed to add match.");
+        }
+        assert!(mock.is_watched("foo.service"));
+        assert!(!mock.is_watched("bar.service"));
+
+        mock.set_active_state("foo.service", ActiveState::Failed);
+        mock.add_unit("bar.service", ActiveState::Active);
+        mock.set_active_state("bar.service", ActiveState::Failed);
+        mock.remove_unit("foo.service");
+        let msgs = pop_all(&mock);
+        assert_eq!(msgs.len(), 3);
+        let changed = PropertiesChanged::from_message(&msgs[0]).expect("Not PropertiesChanged.");
+        assert_eq!(
+            bus::get_active_state(&changed.changed_properties).unwrap(),
+            ActiveState::Failed
+        );
+        assert_eq!(
+            UnitNew::from_message(&msgs[1]).map(|signal| signal.arg0),
+            Some("bar.service".to_string())
+        );
+        assert_eq!(
+            UnitRemoved::from_message(&msgs[2]).map(|signal| signal.arg0),
+            Some("foo.service".to_string())
+        );
+    }
+
+    // MockSystemd::set_property(), for a match rule which asks for one interface's changes
+    #[test]
+    fn test_signals_arg0() {
+        let mock = MockSystemd::new();
+        mock.add_unit("foo.service", ActiveState::Active);
+        let unit_path = gen_path(&unit::get_unit_path("foo.service"));
+        let match_str = format!(
+            "{},arg0='{}'",
+            PropertiesChanged::match_str(None, Some(&unit_path)),
+            INTERFACE_FOR_SYSTEMD_UNIT
+        );
+        mock.add_match(&match_str).expect("Failed to add match.");
+        assert!(mock.is_watched("foo.service"));
+
+        let interface = "org.freedesktop.systemd1.Service";
+        mock.set_property("foo.service", interface, "NRestarts", MockValue::U32(1));
+        assert!(pop_all(&mock).is_empty());
+        mock.set_active_state("foo.service", ActiveState::Failed);
+        assert_eq!(pop_all(&mock).len(), 1);
+    }
+
+    // MockSystemd::get_pollfds()
+    #[test]
+    fn test_get_pollfds() {
+        let mock = MockSystemd::new();
+        let poll = |mock: &MockSystemd| {
+            let mut pollfds = mock.get_pollfds();
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, 0) }
+        };
+        assert_eq!(poll(&mock), 1);
+        pop_all(&mock);
+        assert_eq!(poll(&mock), 0);
+        mock.send(gen_method_call(
+            PATH_FOR_SYSTEMD,
+            INTERFACE_FOR_SYSTEMD_MANAGER,
+            "Subscribe",
+        ));
+        assert_eq!(poll(&mock), 1);
+    }
+}
@@ -0,0 +1,622 @@
+// Logic for embedding killjoy's unit watching in other programs.
+
+use std::os::unix::io::RawFd;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use dbus::BusType;
+
+use crate::bus::BusWatcher;
+use crate::control;
+use crate::control::ControlListener;
+use crate::error::Error as CrateError;
+use crate::heartbeat;
+#[cfg(feature = "mock-bus")]
+use crate::mock::MockSystemd;
+use crate::runtime_rules;
+use crate::runtime_rules::RuntimeRules;
+use crate::settings;
+use crate::settings::Settings;
+use crate::sleep;
+use crate::sleep::SleepState;
+use crate::supervisor::Supervisor;
+use crate::systemd::{SystemdConnection, SystemdManager};
+use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
+use crate::unit::ActiveState;
+
+// How long a bus watcher waits for a message before checking whether it should stop, in ms.
+const DEFAULT_LOOP_TIMEOUT: u32 = 1000;
+
+// How long a bus watcher may go without reporting before it's considered unresponsive, and the
+// heartbeat stops. Bus watchers report at least once per loop timeout, unless busy.
+const MIN_HEALTH_MAX_AGE: Duration = Duration::from_secs(30);
+
+// A unit's ActiveState changed, in a way that matches one of the rules being monitored.
+//
+// `old_state` is `None` if the unit was just discovered. `mono_ts` and `real_ts` state when systemd
+// says the change occurred.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub bus_type: BusType,
+    pub mono_ts: MonotonicTimestamp,
+    pub new_state: ActiveState,
+    pub old_state: Option<ActiveState>,
+    pub real_ts: RealtimeTimestamp,
+    pub unit_name: String,
+}
+
+// A handle with which to ask bus watchers to stop.
+//
+// Clones share state, so a handle may be cloned and handed to another thread, e.g. a signal
+// handler. Bus watchers check the handle between messages, and at least once per loop timeout.
+// Bus watchers which sleep until a message arrives poll the handle's file descriptor too, so that
+// they wake up when stopped. See `fd`.
+#[derive(Clone, Debug)]
+pub struct StopHandle {
+    stopped: Arc<AtomicBool>,
+    pipe: Option<Arc<WakePipe>>,
+}
+
+impl StopHandle {
+    // Create a handle which hasn't been stopped.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Ask bus watchers to stop.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(pipe) = &self.pipe {
+            pipe.wake();
+        }
+    }
+
+    // Tell whether bus watchers have been asked to stop.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    // Get a file descriptor which becomes readable once the handle is stopped, for use with
+    // poll(2). Return `None` if no pipe could be created for the handle, e.g. because killjoy has
+    // too many open files. Bus watchers then wake up at least once per loop timeout instead.
+    pub fn fd(&self) -> Option<RawFd> {
+        self.pipe.as_ref().map(|pipe| pipe.fd())
+    }
+}
+
+impl Default for StopHandle {
+    fn default() -> Self {
+        StopHandle {
+            stopped: Arc::new(AtomicBool::new(false)),
+            pipe: WakePipe::new().map(Arc::new),
+        }
+    }
+}
+
+// A pipe which is written to in order to wake up a thread polling its read end, e.g. when a stop
+// handle is stopped. Both ends are non-blocking, so that waking never blocks, however often it's
+// done.
+#[derive(Debug)]
+pub struct WakePipe {
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl WakePipe {
+    // Create a pipe, or return `None` if that fails.
+    //
+    // pipe2(2) isn't portable, so the flags are set once the pipe is created.
+    pub fn new() -> Option<Self> {
+        let mut fds: [libc::c_int; 2] = [-1, -1];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let pipe = WakePipe {
+            read_fd: fds[0],
+            write_fd: fds[1],
+        };
+        for fd in &fds {
+            unsafe {
+                if libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1
+                    || libc::fcntl(*fd, libc::F_SETFL, libc::O_NONBLOCK) == -1
+                {
+                    return None;
+                }
+            }
+        }
+        Some(pipe)
+    }
+
+    // Get the read end, for use with poll(2).
+    pub fn fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    // Make the read end readable. The byte written is never read by the pipe itself, so the read
+    // end stays readable until whoever polls it reads it.
+    pub fn wake(&self) {
+        let byte: u8 = 1;
+        unsafe {
+            libc::write(self.write_fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
+
+impl Drop for WakePipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+// Options for running bus watchers.
+//
+// `loop_timeout` is how long a bus watcher waits for a message before checking whether it should
+// stop, in ms. If `loop_once` is set, bus watchers stop after the first such wait. Bus watchers
+// also stop when `stop` is stopped.
+//
+// If `low_power` is set, bus watchers don't wake up once per loop timeout. They sleep until a
+// message arrives, or until work which the settings ask for is due, such as sampling properties or
+// firing a rule whose `for_duration` has passed. Periodic reconciliation, i.e. listing unit files,
+// checking mount points and enumerating units again, is skipped. This suits hosts on battery.
+//
+// If `single_thread` is set, one thread watches every bus, rather than one thread per bus. See
+// `BusWatcher::run_together`.
+//
+// If `oneshot` is set, bus watchers don't wait for signals at all. They enumerate units, handle
+// units which are in one of their rule's `active_states` as usual, handle transitions which were
+// signalled meanwhile, send every pending digest, and stop once their actions are done. This suits
+// running killjoy from a timer.
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    pub loop_once: bool,
+    pub loop_timeout: u32,
+    pub low_power: bool,
+    pub oneshot: bool,
+    pub single_thread: bool,
+    pub stop: StopHandle,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            loop_once: false,
+            loop_timeout: DEFAULT_LOOP_TIMEOUT,
+            low_power: false,
+            oneshot: false,
+            single_thread: false,
+            stop: StopHandle::new(),
+        }
+    }
+}
+
+// Watch units, contact notifiers and take actions, as the killjoy daemon does.
+//
+// One thread is spawned per bus referenced by the rules, or one for every bus if
+// `options.single_thread` is set, and is supervised: if its bus watcher fails, it's restarted with
+// backoff, unless it failed before it ever started. See `Supervisor`. If the settings ask for a
+// heartbeat, one more thread is spawned to send it while every bus watcher is healthy. If the
+// settings ask for notifications to be held back while the host suspends and resumes, one more
+// thread is spawned to listen for that. Return when every bus watcher has stopped, i.e. when
+// `options.stop` is stopped. If `options.loop_once` is set, bus watchers aren't restarted, and
+// every error encountered by the bus watchers is returned.
+//
+// In oneshot mode, neither extra thread is spawned, as killjoy doesn't keep running, and bus
+// watchers aren't restarted either.
+pub fn run(settings: Settings, options: &RunOptions) -> Result<(), Vec<CrateError>> {
+    // The settings' loop timeout takes precedence over the options'.
+    let options = &RunOptions {
+        loop_timeout: settings.polling.get_loop_timeout(options.loop_timeout),
+        ..options.clone()
+    };
+    if options.oneshot {
+        let bus_types = get_bus_types(&settings);
+        let supervisor = Supervisor::new(&bus_types);
+        return join_bus_watchers(supervisor.spawn(bus_types, &settings, options, None));
+    }
+    let sleep_stop = StopHandle::new();
+    let (sleep, sleep_handle) = match settings.resume_grace_period {
+        Some(_) => {
+            let (sleep, handle) = spawn_sleep_watcher(options.loop_timeout, &sleep_stop);
+            (Some(sleep), Some(handle))
+        }
+        None => (None, None),
+    };
+    let result = run_with_sleep(settings, options, sleep);
+    sleep_stop.stop();
+    if let Some(handle) = sleep_handle {
+        let _ = handle.join();
+    }
+    result
+}
+
+// Like `run`, but with the given sleep tracker, if any.
+fn run_with_sleep(
+    settings: Settings,
+    options: &RunOptions,
+    sleep: Option<SleepState>,
+) -> Result<(), Vec<CrateError>> {
+    let control_listener =
+        ControlListener::new(settings.control_socket.as_ref()).map_err(|err| vec![err])?;
+    let bus_types = get_bus_types(&settings);
+    let supervisor = Supervisor::new(&bus_types);
+    let handles = supervisor.spawn(bus_types, &settings, options, sleep);
+    if settings.heartbeat.is_none() && control_listener.is_none() {
+        return join_bus_watchers(handles);
+    }
+
+    let helper_stop = StopHandle::new();
+    let poll_interval = Duration::from_millis(options.loop_timeout.into());
+    let max_age = MIN_HEALTH_MAX_AGE.max(poll_interval * 3);
+    let mut helper_handles: Vec<JoinHandle<()>> = Vec::new();
+    if let Some(heartbeat) = settings.heartbeat.clone() {
+        let health = supervisor.health.clone();
+        let helper_stop = helper_stop.clone();
+        helper_handles.push(thread::spawn(move || {
+            heartbeat::run(&heartbeat, &health, max_age, poll_interval, &helper_stop)
+        }));
+    }
+    if let Some(control_listener) = control_listener {
+        let settings = settings.clone();
+        let helper_stop = helper_stop.clone();
+        helper_handles.push(thread::spawn(move || {
+            control::run(
+                &control_listener,
+                &settings,
+                &supervisor,
+                max_age,
+                poll_interval,
+                &helper_stop,
+            )
+        }));
+    }
+    let result = join_bus_watchers(handles);
+    helper_stop.stop();
+    for handle in helper_handles {
+        let _ = handle.join();
+    }
+    result
+}
+
+// The outcome of `check`.
+//
+// `units` describes the current state of every unit matched by the rules, and `events` describes
+// the units of concern, i.e. those which are in one of their rule's `active_states`. Each unit is
+// described as if it had just been discovered, i.e. `old_state` is `None`. Both are ordered by bus,
+// then unit name.
+#[derive(Clone, Debug)]
+pub struct CheckReport {
+    pub events: Vec<Event>,
+    pub units: Vec<Event>,
+}
+
+// Read the state of every unit matched by the rules once, and report on them.
+//
+// Notifiers, actions and silences are ignored. Return every error encountered.
+pub fn check(settings: &Settings) -> Result<CheckReport, Vec<CrateError>> {
+    let (sender, receiver) = mpsc::channel::<Result<Event, CrateError>>();
+    let mut units: Vec<Event> = Vec::new();
+    for bus_type in get_bus_types(settings) {
+        let result = BusWatcher::new(
+            bus_type,
+            settings.clone(),
+            RunOptions::default(),
+            Some(sender.clone()),
+            None,
+            None,
+            None,
+        )
+        .and_then(|bus_watcher| bus_watcher.check());
+        match result {
+            Ok(bus_units) => units.extend(bus_units),
+            Err(err) => {
+                let _ = sender.send(Err(err));
+            }
+        }
+    }
+    drop(sender);
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut errs: Vec<CrateError> = Vec::new();
+    for result in receiver {
+        match result {
+            Ok(event) => events.push(event),
+            Err(err) => errs.push(err),
+        }
+    }
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    sort_events(&mut events);
+    sort_events(&mut units);
+    Ok(CheckReport { events, units })
+}
+
+// Sort events by bus, then unit name.
+fn sort_events(events: &mut [Event]) {
+    events.sort_by(|a, b| {
+        settings::encode_bus_type(a.bus_type)
+            .cmp(settings::encode_bus_type(b.bus_type))
+            .then_with(|| a.unit_name.cmp(&b.unit_name))
+    });
+}
+
+// Watch units, and report events of interest.
+//
+// The units to watch are defined by the rules in `settings`. Whenever a unit enters one of its
+// rule's `active_states`, an `Event` is produced. A rule's notifiers, actions, severity and so on
+// are ignored, as are silences: what to do about an event is up to the caller.
+//
+// To stop watching units, call `stop_handle` before running the monitor, and call `stop` on the
+// returned handle.
+//
+// With the `mock-bus` feature, a monitor may watch a simulated systemd instead. See `with_mock`.
+pub struct Monitor {
+    #[cfg(feature = "mock-bus")]
+    mock: Option<MockSystemd>,
+    options: RunOptions,
+    settings: Settings,
+}
+
+impl Monitor {
+    // Create a new monitor, but do not start watching units.
+    pub fn new(settings: Settings) -> Self {
+        Self::with_options(settings, RunOptions::default())
+    }
+
+    // Create a new monitor with the given options, but do not start watching units.
+    pub fn with_options(settings: Settings, options: RunOptions) -> Self {
+        Monitor {
+            #[cfg(feature = "mock-bus")]
+            mock: None,
+            options,
+            settings,
+        }
+    }
+
+    // Create a new monitor which watches the given simulated systemd rather than a bus, but do not
+    // start watching units.
+    //
+    // The bus watcher of every bus referenced by the rules talks to the same simulated systemd,
+    // so rules should reference a single bus.
+    #[cfg(feature = "mock-bus")]
+    pub fn with_mock(settings: Settings, options: RunOptions, mock: MockSystemd) -> Self {
+        Monitor {
+            mock: Some(mock),
+            options,
+            settings,
+        }
+    }
+
+    // Get a handle with which to stop this monitor.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.options.stop.clone()
+    }
+
+    // Watch units, and call `callback` whenever an event occurs.
+    //
+    // The callback is called on the current thread, so it needn't be `Send`. Return when every bus
+    // watcher has stopped, i.e. when the monitor is stopped or the bus watchers fail. Return every
+    // error encountered by the bus watchers.
+    pub fn run<F>(self, mut callback: F) -> Result<(), Vec<CrateError>>
+    where
+        F: FnMut(Event),
+    {
+        let mut errs: Vec<CrateError> = Vec::new();
+        for result in self.events() {
+            match result {
+                Ok(event) => callback(event),
+                Err(err) => errs.push(err),
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+
+    // Watch units in the background, and return a channel of events.
+    //
+    // One thread is spawned per bus referenced by the rules. If a thread fails, its error is sent
+    // down the channel. The channel is closed when every thread has stopped, i.e. when the monitor
+    // is stopped or the bus watchers fail.
+    pub fn events(self) -> Receiver<Result<Event, CrateError>> {
+        let (sender, receiver) = mpsc::channel::<Result<Event, CrateError>>();
+        // The threads send their own errors, so there's no need to join them.
+        #[cfg(feature = "mock-bus")]
+        {
+            if let Some(mock) = self.mock {
+                let connect = move |_| Ok(mock.clone());
+                spawn_event_watchers(&self.settings, &self.options, sender, connect);
+                return receiver;
+            }
+        }
+        let transport = self.settings.transport;
+        let connect = move |bus_type| SystemdConnection::new(bus_type, transport);
+        spawn_event_watchers(&self.settings, &self.options, sender, connect);
+        receiver
+    }
+}
+
+// Get the buses referenced by the rules, the rules added at runtime, the boot summary, or
+// discovery.
+//
+// Failing to read the runtime rules is reported, and only the other rules are considered.
+fn get_bus_types(settings: &Settings) -> Vec<BusType> {
+    let mut with_runtime_rules = settings.clone();
+    match runtime_rules::get_runtime_rules_path().and_then(|path| RuntimeRules::load(&path)) {
+        Ok(runtime_rules) => runtime_rules.apply(&mut with_runtime_rules),
+        Err(err) => eprintln!("Failed to read runtime rules: {}", err.with_code()),
+    }
+    let mut bus_types = settings::get_bus_types(&with_runtime_rules.rules);
+    let extra_bus_types = settings
+        .boot_summary
+        .iter()
+        .map(|boot_summary| boot_summary.bus_type)
+        .chain(
+            settings
+                .discovery
+                .iter()
+                .map(|discovery| discovery.bus_type),
+        );
+    for bus_type in extra_bus_types {
+        if !bus_types.contains(&bus_type) {
+            bus_types.push(bus_type);
+        }
+    }
+    bus_types
+}
+
+// Spawn a thread which tracks whether the host is asleep, until `stop` is stopped.
+//
+// If the thread can't listen for the host suspending, it says so and exits, and the returned
+// tracker never reports the host as asleep.
+fn spawn_sleep_watcher(loop_timeout: u32, stop: &StopHandle) -> (SleepState, JoinHandle<()>) {
+    let sleep = SleepState::new();
+    let handle = {
+        let sleep = sleep.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(err) = sleep::run(&sleep, loop_timeout, &stop) {
+                eprintln!(
+                    "Failed to watch for the host suspending: {}",
+                    err.with_code()
+                );
+            }
+        })
+    };
+    (sleep, handle)
+}
+
+// Spawn one bus watcher thread per bus referenced by the rules or the boot summary.
+//
+// Each thread gets the backend through which its bus watcher talks to systemd by calling `connect`
+// with its bus. Events are sent to `events`, and so are errors, as soon as they occur. Bus watchers
+// aren't restarted: a failure ends the thread.
+fn spawn_event_watchers<B, F>(
+    settings: &Settings,
+    options: &RunOptions,
+    events: Sender<Result<Event, CrateError>>,
+    connect: F,
+) where
+    B: SystemdManager,
+    F: Fn(BusType) -> Result<B, CrateError> + Clone + Send + 'static,
+{
+    for bus_type in get_bus_types(settings) {
+        let settings_clone = settings.clone();
+        let options_clone = options.clone();
+        let events_clone = events.clone();
+        let connect_clone = connect.clone();
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                BusWatcher::with_backend(
+                    connect_clone(bus_type)?,
+                    bus_type,
+                    settings_clone,
+                    options_clone,
+                    Some(events_clone.clone()),
+                )?
+                .run()
+            }))
+            .unwrap_or_else(|err| Err(CrateError::MonitoringThreadPanicked(err)));
+            if let Err(err) = result {
+                let _ = events_clone.send(Err(err));
+            }
+        });
+    }
+}
+
+// Wait for bus watcher threads to stop, and collect their errors.
+//
+// Handles are joined in the order they appear in the vector, not the order in which they exit,
+// meaning that there may be a long delay between an error occurring and this thread learning about
+// it. Consequently, the monitoring threads should print their own error messages whenever
+// possible.
+fn join_bus_watchers(
+    handles: Vec<JoinHandle<Result<(), CrateError>>>,
+) -> Result<(), Vec<CrateError>> {
+    let mut errs: Vec<CrateError> = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Err(err) => errs.push(CrateError::MonitoringThreadPanicked(err)),
+            Ok(result) => {
+                if let Err(err) = result {
+                    errs.push(err);
+                }
+            }
+        }
+    }
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(errs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_empty_settings() -> Settings {
+        Settings::new(&br#"{"rules": [], "notifiers": {}, "version": 1}"#[..])
+            .expect("Failed to parse settings.")
+    }
+
+    // Monitor::events()
+    #[test]
+    fn test_monitor_events_no_rules() {
+        let receiver = Monitor::new(gen_empty_settings()).events();
+        assert!(receiver.recv().is_err());
+    }
+
+    // StopHandle::stop()
+    #[test]
+    fn test_stop_handle_stop() {
+        let monitor = Monitor::new(gen_empty_settings());
+        let stop = monitor.stop_handle();
+        assert!(!stop.is_stopped());
+        monitor.stop_handle().stop();
+        assert!(stop.is_stopped());
+    }
+
+    // StopHandle::fd()
+    #[test]
+    fn test_stop_handle_fd() {
+        let stop = StopHandle::new();
+        let mut pollfd = libc::pollfd {
+            fd: stop.fd().expect("Failed to create stop pipe."),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 0) }, 0);
+        stop.clone().stop();
+        stop.stop();
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 0) }, 1);
+    }
+
+    // run()
+    #[test]
+    fn test_run_no_rules() {
+        run(gen_empty_settings(), &RunOptions::default()).expect("Failed to run.");
+    }
+
+    // Monitor::run()
+    #[test]
+    fn test_monitor_run_no_rules() {
+        let mut count = 0;
+        Monitor::new(gen_empty_settings())
+            .run(|_| count += 1)
+            .expect("Failed to run monitor.");
+        assert_eq!(count, 0);
+    }
+}
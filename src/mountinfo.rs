@@ -0,0 +1,82 @@
+// Logic for telling which mount points are actually mounted.
+//
+// systemd occasionally believes that a mount unit is active after the file system has gone away,
+// e.g. if an NFS server vanished, so the kernel's mount table is consulted directly.
+//
+// See: https://www.kernel.org/doc/Documentation/filesystems/proc.txt, section 3.5.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error as IOError;
+
+// The mount table of killjoy's mount namespace.
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+// Read the mount table, and return the mount points in it.
+pub fn read_mount_points() -> Result<HashSet<String>, IOError> {
+    Ok(parse_mount_points(&fs::read_to_string(MOUNTINFO_PATH)?))
+}
+
+// Get the mount points listed in the given mount table.
+//
+// Each line describes a mount, and its fifth field is the mount point. Lines which are too short
+// are ignored.
+fn parse_mount_points(mountinfo: &str) -> HashSet<String> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+        .collect()
+}
+
+// Undo the escaping of a field of the mount table.
+//
+// Spaces, tabs, newlines and backslashes are written as three-digit octal escapes, like `\040`.
+fn unescape(field: &str) -> String {
+    let mut bytes: Vec<u8> = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (byte, escaped) {
+            (b'\\', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // parse_mount_points()
+    #[test]
+    fn test_parse_mount_points() {
+        let mountinfo = "\
+            22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+            41 22 0:38 / /srv/nfs rw,relatime shared:20 - nfs4 server:/export rw\n\
+            42 22 8:2 / /mnt/my\\040disk rw,relatime shared:21 - ext4 /dev/sda2 rw\n\
+            truncated line\n";
+        let mount_points = parse_mount_points(mountinfo);
+        assert_eq!(mount_points.len(), 3);
+        assert!(mount_points.contains("/"));
+        assert!(mount_points.contains("/srv/nfs"));
+        assert!(mount_points.contains("/mnt/my disk"));
+    }
+
+    // unescape()
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/mnt/a\\011b\\134c"), "/mnt/a\tb\\c");
+        assert_eq!(unescape("/mnt/trailing\\04"), "/mnt/trailing\\04");
+    }
+}
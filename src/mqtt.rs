@@ -0,0 +1,375 @@
+// A minimal MQTT 3.1.1 client, for publishing events to a broker.
+//
+// Events are rare, so each one is published over a new connection, which is closed once the
+// broker has acknowledged the message as required by its quality of service. This avoids keeping
+// a connection alive, and reconnecting when the broker restarts.
+//
+// See: https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/mqtt-v3.1.1.html
+
+use std::convert::TryFrom;
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::time::Duration;
+
+use crate::backend::{Notification, NotifierBackend};
+use crate::bus;
+use crate::error::{Error as CrateError, NotifierError};
+use crate::settings::{MqttBroker, MqttQos};
+
+// How often the broker should expect to hear from killjoy. Connections are short-lived, so this
+// only matters if the broker is slow to acknowledge a message.
+const KEEP_ALIVE_SECS: u16 = 60;
+
+// The ID of the one message published over each connection.
+const PACKET_ID: u16 = 1;
+
+// Control packet types, as found in the upper half of a packet's first byte.
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const DISCONNECT: u8 = 14;
+
+// Events are published to the topic rendered from the broker's topic template, as JSON objects.
+impl NotifierBackend for MqttBroker {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        let topic = self
+            .topic
+            .render(&bus::get_notification_template_values(notification));
+        let payload = bus::gen_notify_json_body(notification).to_string();
+        publish(self, &topic, payload.as_bytes(), timeout).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactMqttBroker(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        ping(self, timeout).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactMqttBroker(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+}
+
+// Publish `payload` to `topic` on the given broker, and wait for the broker to acknowledge it.
+//
+// `timeout` is how long to wait for the broker when connecting, reading or writing.
+pub fn publish(
+    broker: &MqttBroker,
+    topic: &str,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<(), IOError> {
+    let packet = gen_publish_packet(topic, payload, broker.qos, broker.retain)?;
+    let mut stream = connect(broker, timeout)?;
+    stream.write_all(&packet)?;
+    match broker.qos {
+        MqttQos::AtMostOnce => {}
+        MqttQos::AtLeastOnce => expect_ack(&mut stream, PUBACK)?,
+        MqttQos::ExactlyOnce => {
+            expect_ack(&mut stream, PUBREC)?;
+            stream.write_all(&gen_packet(PUBREL << 4 | 0b0010, &PACKET_ID.to_be_bytes()))?;
+            expect_ack(&mut stream, PUBCOMP)?;
+        }
+    }
+    stream.write_all(&gen_packet(DISCONNECT << 4, &[]))
+}
+
+// Connect to the given broker and disconnect again, to check that it accepts connections.
+pub fn ping(broker: &MqttBroker, timeout: Duration) -> Result<(), IOError> {
+    connect(broker, timeout)?.write_all(&gen_packet(DISCONNECT << 4, &[]))
+}
+
+// Open a connection to the given broker, and wait for the broker to accept it.
+fn connect(broker: &MqttBroker, timeout: Duration) -> Result<TcpStream, IOError> {
+    let mut last_err = IOError::new(ErrorKind::NotFound, "host has no addresses");
+    let mut stream: Option<TcpStream> = None;
+    for addr in (&broker.host[..], broker.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    let mut stream = stream.ok_or(last_err)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let client_id = match &broker.client_id {
+        Some(client_id) => client_id.to_owned(),
+        None => format!("killjoy-{}", process::id()),
+    };
+    stream.write_all(&gen_connect_packet(broker, &client_id)?)?;
+    let (header, body) = read_packet(&mut stream)?;
+    if header >> 4 != CONNACK || body.len() != 2 {
+        return Err(gen_protocol_error("expected CONNACK"));
+    }
+    match body[1] {
+        0 => Ok(stream),
+        code => Err(IOError::new(
+            ErrorKind::ConnectionRefused,
+            format!("broker refused connection: {}", describe_connack_code(code)),
+        )),
+    }
+}
+
+// Read a packet, and return an error unless it acknowledges the message that was published.
+fn expect_ack(stream: &mut TcpStream, packet_type: u8) -> Result<(), IOError> {
+    let (header, body) = read_packet(stream)?;
+    if header >> 4 != packet_type || body != PACKET_ID.to_be_bytes() {
+        return Err(gen_protocol_error(
+            "expected acknowledgement of published message",
+        ));
+    }
+    Ok(())
+}
+
+// Generate a CONNECT packet, which asks for a clean session. Return an error if a string in it is
+// too long. See `append_string`.
+fn gen_connect_packet(broker: &MqttBroker, client_id: &str) -> Result<Vec<u8>, IOError> {
+    let mut flags: u8 = 0b0000_0010;
+    if broker.username.is_some() {
+        flags |= 0b1000_0000;
+    }
+    if broker.password.is_some() {
+        flags |= 0b0100_0000;
+    }
+    let mut body: Vec<u8> = Vec::new();
+    append_string(&mut body, "MQTT")?;
+    body.push(4); // protocol level
+    body.push(flags);
+    body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+    append_string(&mut body, client_id)?;
+    if let Some(username) = &broker.username {
+        append_string(&mut body, username)?;
+    }
+    if let Some(password) = &broker.password {
+        append_string(&mut body, password)?;
+    }
+    Ok(gen_packet(CONNECT << 4, &body))
+}
+
+// Generate a PUBLISH packet. Return an error if the topic is too long. See `append_string`.
+fn gen_publish_packet(
+    topic: &str,
+    payload: &[u8],
+    qos: MqttQos,
+    retain: bool,
+) -> Result<Vec<u8>, IOError> {
+    let qos = u8::from(qos);
+    let mut body: Vec<u8> = Vec::new();
+    append_string(&mut body, topic)?;
+    if qos > 0 {
+        body.extend_from_slice(&PACKET_ID.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    let header = PUBLISH << 4 | qos << 1 | u8::from(retain);
+    Ok(gen_packet(header, &body))
+}
+
+// Generate a packet with the given first byte and body.
+//
+// The length of the body is encoded in between, seven bits per byte, least significant first, with
+// the top bit set on all but the last byte.
+fn gen_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet: Vec<u8> = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            packet.push(byte | 0x80);
+        } else {
+            packet.push(byte);
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+// Read a packet, and return its first byte and its body.
+fn read_packet<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>), IOError> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut len: usize = 0;
+    for i in 0..4 {
+        reader.read_exact(&mut byte)?;
+        len += ((byte[0] & 0x7f) as usize) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body)?;
+            return Ok((header, body));
+        }
+    }
+    Err(gen_protocol_error("malformed remaining length"))
+}
+
+// Append a string, prefixed with its length as a two-byte integer. Return an error if the string
+// is longer than 65535 bytes, as its length wouldn't fit.
+fn append_string(buf: &mut Vec<u8>, value: &str) -> Result<(), IOError> {
+    let len = u16::try_from(value.len()).map_err(|_| {
+        IOError::new(
+            ErrorKind::InvalidInput,
+            format!("MQTT string is {} bytes long, over 65535", value.len()),
+        )
+    })?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+// Describe why a broker refused a connection, given the return code from its CONNACK packet.
+fn describe_connack_code(code: u8) -> String {
+    match code {
+        1 => "unacceptable protocol version".to_string(),
+        2 => "client identifier rejected".to_string(),
+        3 => "server unavailable".to_string(),
+        4 => "bad user name or password".to_string(),
+        5 => "not authorized".to_string(),
+        _ => format!("return code {}", code),
+    }
+}
+
+// Generate an error stating that the broker broke the protocol.
+fn gen_protocol_error(reason: &str) -> IOError {
+    IOError::new(
+        ErrorKind::InvalidData,
+        format!("MQTT protocol error: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    // gen_packet(), read_packet()
+    #[test]
+    fn test_gen_read_packet() {
+        for len in [0, 127, 128, 16_383, 16_384] {
+            let body = vec![7u8; len];
+            let packet = gen_packet(PUBLISH << 4, &body);
+            let (header, read_body) = read_packet(&mut &packet[..]).unwrap();
+            assert_eq!(header, PUBLISH << 4);
+            assert_eq!(read_body, body);
+        }
+        assert_eq!(gen_packet(DISCONNECT << 4, &[]), vec![0xe0, 0x00]);
+        assert_eq!(
+            &gen_packet(PUBLISH << 4, &[0; 200])[..3],
+            &[0x30, 0xc8, 0x01]
+        );
+    }
+
+    // gen_connect_packet()
+    #[test]
+    fn test_gen_connect_packet() {
+        let mut broker = MqttBroker::new("localhost");
+        broker.username = Some("u".to_string());
+        assert_eq!(
+            gen_connect_packet(&broker, "id").unwrap(),
+            vec![
+                0x10,
+                17,
+                0,
+                4,
+                b'M',
+                b'Q',
+                b'T',
+                b'T',
+                4,
+                0b1000_0010,
+                0,
+                60,
+                0,
+                2,
+                b'i',
+                b'd',
+                0,
+                1,
+                b'u'
+            ]
+        );
+    }
+
+    // append_string()
+    #[test]
+    fn test_append_string() {
+        let mut buf: Vec<u8> = Vec::new();
+        append_string(&mut buf, &"a".repeat(65_535)).expect("Failed to append string.");
+        assert_eq!(&buf[..2], &[0xff, 0xff]);
+        let err = append_string(&mut buf, &"a".repeat(65_536))
+            .expect_err("Appending an over-long string should've failed.");
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(buf.len(), 65_537);
+    }
+
+    // publish()
+    #[test]
+    fn test_publish_qos_1() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen.");
+        let mut broker = MqttBroker::new("127.0.0.1");
+        broker.port = listener.local_addr().unwrap().port();
+        broker.qos = MqttQos::AtLeastOnce;
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut headers: Vec<u8> = Vec::new();
+            let (header, _) = read_packet(&mut stream).unwrap();
+            headers.push(header);
+            stream.write_all(&[CONNACK << 4, 2, 0, 0]).unwrap();
+            let (header, body) = read_packet(&mut stream).unwrap();
+            headers.push(header);
+            stream.write_all(&[PUBACK << 4, 2, 0, 1]).unwrap();
+            let (header, _) = read_packet(&mut stream).unwrap();
+            headers.push(header);
+            (headers, body)
+        });
+        publish(&broker, "a/b", b"{}", Duration::from_secs(5)).expect("Failed to publish.");
+        let (headers, body) = handle.join().unwrap();
+        assert_eq!(
+            headers,
+            vec![CONNECT << 4, PUBLISH << 4 | 0b10, DISCONNECT << 4]
+        );
+        assert_eq!(body, vec![0, 3, b'a', b'/', b'b', 0, 1, b'{', b'}']);
+    }
+
+    // publish()
+    #[test]
+    fn test_publish_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen.");
+        let mut broker = MqttBroker::new("127.0.0.1");
+        broker.port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_packet(&mut stream).unwrap();
+            stream.write_all(&[CONNACK << 4, 2, 0, 5]).unwrap();
+        });
+        let err = publish(&broker, "a/b", b"{}", Duration::from_secs(5))
+            .expect_err("Publishing should've failed.");
+        handle.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("not authorized"));
+    }
+}
@@ -0,0 +1,132 @@
+// Logic for pausing monitoring of a bus, e.g. during intrusive maintenance of the user session.
+//
+// Like silences, paused buses are stored in a file, so that they may be managed by one killjoy
+// process (i.e. `killjoy pause ...`) and obeyed by another (i.e. the one watching units). While a
+// bus is paused, killjoy stays connected to it and keeps tracking its units, so that no state
+// changes are misread when monitoring resumes.
+
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IOError, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use dbus::BusType;
+use serde::{Deserialize, Serialize};
+use xdg::BaseDirectories;
+
+use crate::error::{Error as CrateError, StateError};
+use crate::settings;
+
+// A collection of paused buses, as stored in the paused buses file.
+//
+// Buses are stored by name, e.g. "system", in the order in which they were paused.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PausedBuses {
+    bus_types: Vec<String>,
+}
+
+impl PausedBuses {
+    // Read paused buses from the given file. If the file doesn't exist, no buses are paused.
+    pub fn load(path: &Path) -> Result<Self, CrateError> {
+        match File::open(path) {
+            Ok(handle) => serde_json::from_reader(BufReader::new(handle))
+                .map_err(|err| CrateError::State(StateError::DeserializePausedBusesFile(err))),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CrateError::State(StateError::ReadPausedBusesFile(err))),
+        }
+    }
+
+    // Write paused buses to the given file.
+    //
+    // The file is replaced atomically, so that concurrent readers never see a partial file.
+    pub fn save(&self, path: &Path) -> Result<(), CrateError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|err| CrateError::State(StateError::SerializePausedBusesFile(err)))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .map_err(|err| CrateError::State(StateError::WritePausedBusesFile(err)))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|err| CrateError::State(StateError::WritePausedBusesFile(err)))
+    }
+
+    // Pause the given bus. Pausing a paused bus does nothing.
+    pub fn pause(&mut self, bus_type: BusType) {
+        if !self.is_paused(bus_type) {
+            self.bus_types
+                .push(settings::encode_bus_type(bus_type).to_string());
+        }
+    }
+
+    // Resume the given bus. Resuming a bus which isn't paused does nothing.
+    pub fn resume(&mut self, bus_type: BusType) {
+        let bus_type_str = settings::encode_bus_type(bus_type);
+        self.bus_types.retain(|paused| paused != bus_type_str);
+    }
+
+    // Tell whether the given bus is paused.
+    pub fn is_paused(&self, bus_type: BusType) -> bool {
+        let bus_type_str = settings::encode_bus_type(bus_type);
+        self.bus_types.iter().any(|paused| paused == bus_type_str)
+    }
+
+    // Get the names of all paused buses, in the order in which they were paused.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.bus_types.iter().map(String::as_str)
+    }
+}
+
+// Get the path to the paused buses file, creating parent directories if necessary.
+//
+// The file is placed in `$XDG_STATE_HOME/killjoy`, so that paused buses stay paused across
+// restarts.
+pub fn get_paused_buses_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::State(StateError::PlacePausedBusesFile(IOError::other(err))))?
+        .place_state_file("paused.json")
+        .map_err(|err| CrateError::State(StateError::PlacePausedBusesFile(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    // PausedBuses::pause(), PausedBuses::resume(), PausedBuses::is_paused()
+    #[test]
+    fn test_paused_buses() {
+        let mut paused_buses = PausedBuses::default();
+        assert!(!paused_buses.is_paused(BusType::Session));
+
+        paused_buses.pause(BusType::Session);
+        paused_buses.pause(BusType::Session);
+        assert!(paused_buses.is_paused(BusType::Session));
+        assert!(!paused_buses.is_paused(BusType::System));
+        assert_eq!(paused_buses.iter().collect::<Vec<_>>(), vec!["session"]);
+
+        paused_buses.resume(BusType::System);
+        paused_buses.resume(BusType::Session);
+        assert!(!paused_buses.is_paused(BusType::Session));
+    }
+
+    // PausedBuses::save(), PausedBuses::load()
+    #[test]
+    fn test_paused_buses_save_load() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let path = dir.path().join("paused.json");
+        assert_eq!(
+            PausedBuses::load(&path)
+                .expect("Failed to load paused buses.")
+                .iter()
+                .count(),
+            0
+        );
+
+        let mut paused_buses = PausedBuses::default();
+        paused_buses.pause(BusType::System);
+        paused_buses
+            .save(&path)
+            .expect("Failed to save paused buses.");
+        let loaded = PausedBuses::load(&path).expect("Failed to load paused buses.");
+        assert!(loaded.is_paused(BusType::System));
+    }
+}
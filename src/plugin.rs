@@ -0,0 +1,223 @@
+// Logic for running out-of-tree notifiers as helper programs.
+//
+// For each request, the plugin's command is spawned, and sent one JSON object on stdin, followed by
+// end of file:
+//
+//     {"version": 1, "method": "notify", "notifier": "pager", "options": {...}, "notification": {...}}
+//
+// `method` is "notify" or "ping". `notifier` is the notifier's name in the settings file, and
+// `options` are the notifier's options from the settings file. For "notify" requests,
+// `notification` has the same keys as the dictionary sent to version 2 D-Bus notifiers. The plugin
+// must answer by printing one JSON object on stdout, either `{"ok": true}` or `{"error": "..."}`,
+// and exit successfully. Anything it prints on stderr is passed through.
+
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Map, Value};
+
+use crate::backend::{Notification, NotifierBackend};
+use crate::bus;
+use crate::error::{Error as CrateError, NotifierError};
+use crate::settings::Plugin;
+
+// The version of the protocol spoken with plugins.
+const PROTOCOL_VERSION: u64 = 1;
+
+// How often to check whether a plugin has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl NotifierBackend for Plugin {
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        call(
+            self,
+            notifier_name,
+            "notify",
+            Some(bus::gen_notify_json_body(notification)),
+            timeout,
+        )
+    }
+
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        call(self, notifier_name, "ping", None, timeout)
+    }
+}
+
+// Send a request to a plugin, and wait up to `timeout` for it to answer.
+fn call(
+    plugin: &Plugin,
+    notifier_name: &str,
+    method: &str,
+    notification: Option<Value>,
+    timeout: Duration,
+) -> Result<(), CrateError> {
+    let request = gen_request(plugin, notifier_name, method, notification);
+    let mut child = Command::new(&plugin.command[0])
+        .args(&plugin.command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            CrateError::Notifier(NotifierError::RunPlugin(notifier_name.to_string(), err))
+        })?;
+    // A plugin which exits without reading its request closes the pipe, which isn't an error in
+    // itself: its exit status and response tell what happened.
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(format!("{}\n", request).as_bytes()) {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(CrateError::Notifier(NotifierError::RunPlugin(
+                    notifier_name.to_string(),
+                    err,
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    let fail = |reason: String| {
+        Err(CrateError::Notifier(NotifierError::PluginFailed(
+            notifier_name.to_string(),
+            reason,
+        )))
+    };
+    // A plugin which timed out may have left children holding its stdout open, so don't read it.
+    let status = match wait_with_timeout(&mut child, timeout).map_err(|err| {
+        CrateError::Notifier(NotifierError::RunPlugin(notifier_name.to_string(), err))
+    })? {
+        Some(status) => status,
+        None => return fail(format!("didn't answer within {}s", timeout.as_secs())),
+    };
+    let mut response = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut response).map_err(|err| {
+            CrateError::Notifier(NotifierError::RunPlugin(notifier_name.to_string(), err))
+        })?;
+    }
+    match parse_response(&response) {
+        Err(Some(reason)) => fail(reason),
+        Ok(()) if status.success() => Ok(()),
+        _ if !status.success() => fail(format!("exited with {}", status)),
+        _ => fail(format!("sent an invalid response: {:?}", response.trim())),
+    }
+}
+
+// Generate a request to send to a plugin.
+fn gen_request(
+    plugin: &Plugin,
+    notifier_name: &str,
+    method: &str,
+    notification: Option<Value>,
+) -> Value {
+    let mut request: Map<String, Value> = Map::new();
+    request.insert("version".to_string(), Value::from(PROTOCOL_VERSION));
+    request.insert("method".to_string(), Value::from(method));
+    request.insert("notifier".to_string(), Value::from(notifier_name));
+    request.insert(
+        "options".to_string(),
+        Value::Object(plugin.options.to_owned()),
+    );
+    if let Some(notification) = notification {
+        request.insert("notification".to_string(), notification);
+    }
+    Value::Object(request)
+}
+
+// Parse a plugin's response.
+//
+// Return `Ok` if the plugin succeeded, `Err(Some(reason))` if it reported an error, or `Err(None)`
+// if the response is invalid.
+fn parse_response(response: &str) -> Result<(), Option<String>> {
+    let response: Value = serde_json::from_str(response.trim()).map_err(|_| None)?;
+    if let Some(reason) = response.get("error") {
+        return Err(Some(match reason {
+            Value::String(reason) => reason.to_owned(),
+            reason => reason.to_string(),
+        }));
+    }
+    match response.get("ok") {
+        Some(Value::Bool(true)) => Ok(()),
+        _ => Err(None),
+    }
+}
+
+// Wait for a child to exit, and return its exit status. Kill it and return `None` if it takes longer
+// than `timeout`.
+pub fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<Option<ExitStatus>, IOError> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn gen_plugin(script: &str) -> Plugin {
+        Plugin {
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            options: Map::new(),
+        }
+    }
+
+    // parse_response()
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(parse_response("{\"ok\": true}\n"), Ok(()));
+        assert_eq!(
+            parse_response(r#"{"error": "no route"}"#),
+            Err(Some("no route".to_string()))
+        );
+        assert_eq!(parse_response(r#"{"ok": false}"#), Err(None));
+        assert_eq!(parse_response(""), Err(None));
+    }
+
+    // Plugin::ping()
+    #[test]
+    fn test_ping() {
+        let plugin = gen_plugin(r#"grep -q '"method":"ping"' && echo '{"ok": true}'"#);
+        plugin
+            .ping("pager", TIMEOUT)
+            .expect("Failed to ping plugin.");
+
+        let plugin = gen_plugin(r#"echo '{"error": "no route"}'"#);
+        match plugin.ping("pager", TIMEOUT) {
+            Err(CrateError::Notifier(NotifierError::PluginFailed(_, reason))) => {
+                assert_eq!(reason, "no route")
+            }
+            _ => panic!("expected PluginFailed; the plugin reported an error"),
+        }
+
+        let plugin = gen_plugin("exit 3");
+        match plugin.ping("pager", TIMEOUT) {
+            Err(CrateError::Notifier(NotifierError::PluginFailed(_, _))) => {}
+            _ => panic!("expected PluginFailed; the plugin failed without answering"),
+        }
+    }
+}
@@ -0,0 +1,548 @@
+// Logic for watching arbitrary properties of units.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{Duration, Instant};
+
+use dbus::arg::RefArg;
+use serde::Deserialize;
+
+use crate::error::{Error as CrateError, SettingsError};
+
+// The prefix of the names of systemd's type-specific unit interfaces.
+const INTERFACE_PREFIX: &str = "org.freedesktop.systemd1.";
+
+// The value of a unit property, as far as conditions are concerned.
+//
+// Unsigned integers may be compared against thresholds. Other values may only be checked for
+// changes, so they're kept in a printable form.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PropertyValue {
+    Number(u64),
+    Other(String),
+}
+
+impl PropertyValue {
+    // Convert a property value from a D-Bus message.
+    //
+    // systemd uses the largest unsigned 64-bit integer to mean "not set", e.g. for the
+    // MemoryCurrent of a unit without memory accounting, so that value is kept as `Other`.
+    pub fn from_ref_arg(value: &dyn RefArg) -> Self {
+        match value.as_u64() {
+            Some(number) if number != u64::MAX => PropertyValue::Number(number),
+            Some(_) => PropertyValue::Other("[not set]".to_string()),
+            None => match value.as_str() {
+                Some(text) => PropertyValue::Other(text.to_string()),
+                None => PropertyValue::Other(format!("{:?}", value)),
+            },
+        }
+    }
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            PropertyValue::Number(number) => write!(f, "{}", number),
+            PropertyValue::Other(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// How a property's value is checked.
+//
+// `RateAbove` compares how much a counter, like a socket's NAccepted, has increased per minute.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Comparison {
+    Changed,
+    GreaterThan(u64),
+    LessThan(u64),
+    RateAbove(u64),
+}
+
+// A condition on a property of a unit, e.g. "MemoryCurrent > 1073741824".
+//
+// The property is read from `interface`, or if that's `None`, from the unit's type-specific
+// interface, e.g. `org.freedesktop.systemd1.Service` for services. A `Changed` condition is met
+// whenever the property's value changes. A threshold condition is met once the property's value,
+// or for a `RateAbove` condition its rate of increase, has been beyond the threshold for
+// `duration`, so that a property which stays above a threshold is reported once, not on every
+// change.
+#[derive(Clone, Debug)]
+pub struct PropertyCondition {
+    pub comparison: Comparison,
+    pub duration: Duration,
+    pub interface: Option<String>,
+    pub property: String,
+}
+
+impl PropertyCondition {
+    // Get the name of the interface holding the property, for the given unit.
+    pub fn get_interface(&self, unit_name: &str) -> Option<String> {
+        match &self.interface {
+            Some(interface) => Some(interface.to_owned()),
+            None => get_type_interface(unit_name),
+        }
+    }
+
+    // Tell whether the property is on the given interface, for the given unit. Like comparing
+    // against `get_interface`, but without allocating, as it's done for every PropertiesChanged
+    // signal.
+    pub fn is_on_interface(&self, unit_name: &str, interface: &str) -> bool {
+        match &self.interface {
+            Some(own_interface) => own_interface == interface,
+            None => is_type_interface(unit_name, interface),
+        }
+    }
+
+    // Get the number which this condition's threshold applies to, for the property taking value
+    // `new` at time `now`.
+    //
+    // That's the value itself, or for a `RateAbove` condition, how much the value has increased per
+    // minute since it was last recorded in `history`. Return `None` if there's no such number, e.g.
+    // if the value isn't a number, or if a counter has no previous value or was reset.
+    pub fn measure(
+        &self,
+        history: &PropertyHistory,
+        interface: &str,
+        new: &PropertyValue,
+        now: Instant,
+    ) -> Option<u64> {
+        let number = match new {
+            PropertyValue::Number(number) => *number,
+            PropertyValue::Other(_) => return None,
+        };
+        if let Comparison::RateAbove(_) = self.comparison {
+            return history.get_rate(interface, &self.property, number, now);
+        }
+        Some(number)
+    }
+
+    // Tell whether `measure` is beyond this condition's threshold.
+    //
+    // A missing measure is never beyond a threshold, and a `Changed` condition has none.
+    pub fn is_beyond(&self, measure: Option<u64>) -> bool {
+        match (self.comparison, measure) {
+            (Comparison::GreaterThan(threshold), Some(number))
+            | (Comparison::RateAbove(threshold), Some(number)) => number > threshold,
+            (Comparison::LessThan(threshold), Some(number)) => number < threshold,
+            _ => false,
+        }
+    }
+
+    // Tell whether the property taking value `new` at time `now` meets this condition.
+    //
+    // `history` holds what was previously seen of the property's values. If the property wasn't
+    // seen before, a threshold condition without a duration is met if `new` is beyond the
+    // threshold, and a `Changed` condition isn't met.
+    pub fn is_met(
+        &self,
+        history: &PropertyHistory,
+        interface: &str,
+        new: &PropertyValue,
+        now: Instant,
+    ) -> bool {
+        if self.comparison == Comparison::Changed {
+            return history
+                .get_value(interface, &self.property)
+                .is_some_and(|old| old != new);
+        }
+        if !self.is_beyond(self.measure(history, interface, new, now)) {
+            return false;
+        }
+        match history.get_excursion(interface, self) {
+            None => self.duration.is_zero(),
+            Some(excursion) => {
+                now.duration_since(excursion.since) >= self.duration
+                    && excursion.checked.duration_since(excursion.since) < self.duration
+            }
+        }
+    }
+}
+
+impl TryFrom<SerdePropertyCondition> for PropertyCondition {
+    type Error = CrateError;
+
+    fn try_from(value: SerdePropertyCondition) -> Result<Self, Self::Error> {
+        let threshold = || {
+            value.value.ok_or_else(|| {
+                CrateError::Settings(SettingsError::MissingPropertyThreshold(
+                    value.property.to_owned(),
+                ))
+            })
+        };
+        let comparison = match &value.condition[..] {
+            "changed" => Comparison::Changed,
+            ">" => Comparison::GreaterThan(threshold()?),
+            "<" => Comparison::LessThan(threshold()?),
+            "rate >" => Comparison::RateAbove(threshold()?),
+            other => {
+                return Err(CrateError::Settings(
+                    SettingsError::InvalidPropertyCondition(other.to_owned()),
+                ))
+            }
+        };
+        Ok(PropertyCondition {
+            comparison,
+            duration: Duration::from_secs(value.duration),
+            interface: value.interface,
+            property: value.property,
+        })
+    }
+}
+
+// Like a `PropertyCondition`, but fields are simple types instead of domain-specific types.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SerdePropertyCondition {
+    pub condition: String,
+    #[serde(default)]
+    pub duration: u64,
+    #[serde(default)]
+    pub interface: Option<String>,
+    pub property: String,
+    #[serde(default)]
+    pub value: Option<u64>,
+}
+
+// A stretch of time during which a property has been beyond a condition's threshold.
+//
+// `since` is when the property was first seen beyond the threshold, and `checked` is when it was
+// most recently seen beyond the threshold.
+#[derive(Clone, Copy, Debug)]
+pub struct Excursion {
+    pub since: Instant,
+    pub checked: Instant,
+}
+
+// What has been seen of a unit's properties.
+//
+// `values` holds the last seen value of each property, and when it was seen, keyed by interface and
+// property name. `excursions` holds the properties currently beyond thresholds, keyed by interface,
+// property name and comparison.
+#[derive(Debug, Default)]
+pub struct PropertyHistory {
+    values: HashMap<(String, String), (PropertyValue, Instant)>,
+    excursions: HashMap<(String, String, Comparison), Excursion>,
+}
+
+impl PropertyHistory {
+    // Get the last seen value of a property.
+    pub fn get_value(&self, interface: &str, property: &str) -> Option<&PropertyValue> {
+        self.values
+            .get(&(interface.to_string(), property.to_string()))
+            .map(|(value, _)| value)
+    }
+
+    // Get how much a property has increased per minute, from its last seen value to `new` at time
+    // `now`.
+    //
+    // Return `None` if the property wasn't seen before as a number, if it was seen no earlier than
+    // `now`, or if it has decreased, e.g. because the unit was restarted and its counters reset.
+    pub fn get_rate(&self, interface: &str, property: &str, new: u64, now: Instant) -> Option<u64> {
+        let (old, seen) = self
+            .values
+            .get(&(interface.to_string(), property.to_string()))?;
+        let old = match old {
+            PropertyValue::Number(old) => *old,
+            PropertyValue::Other(_) => return None,
+        };
+        let elapsed = now.checked_duration_since(*seen)?.as_millis();
+        if elapsed == 0 || new < old {
+            return None;
+        }
+        u64::try_from(u128::from(new - old) * 60_000 / elapsed).ok()
+    }
+
+    // Get the excursion beyond the given condition's threshold, if the property is beyond it.
+    pub fn get_excursion(
+        &self,
+        interface: &str,
+        condition: &PropertyCondition,
+    ) -> Option<&Excursion> {
+        self.excursions.get(&(
+            interface.to_string(),
+            condition.property.to_owned(),
+            condition.comparison,
+        ))
+    }
+
+    // Record that the property named by `condition` took value `value` at time `now`.
+    pub fn record(
+        &mut self,
+        interface: &str,
+        condition: &PropertyCondition,
+        value: PropertyValue,
+        now: Instant,
+    ) {
+        let key = (
+            interface.to_string(),
+            condition.property.to_owned(),
+            condition.comparison,
+        );
+        let measure = condition.measure(self, interface, &value, now);
+        if condition.is_beyond(measure) {
+            self.excursions
+                .entry(key)
+                .or_insert(Excursion {
+                    since: now,
+                    checked: now,
+                })
+                .checked = now;
+        } else {
+            self.excursions.remove(&key);
+        }
+        self.values.insert(
+            (interface.to_string(), condition.property.to_owned()),
+            (value, now),
+        );
+    }
+}
+
+// Get the name of the type-specific interface of the given unit.
+//
+// For example, "foo.service" has the interface `org.freedesktop.systemd1.Service`. Return `None`
+// if the unit name lacks a type suffix.
+pub fn get_type_interface(unit_name: &str) -> Option<String> {
+    let (_, unit_type) = unit_name.rsplit_once('.')?;
+    let mut chars = unit_type.chars();
+    let first = chars.next()?;
+    Some(format!(
+        "{}{}{}",
+        INTERFACE_PREFIX,
+        first.to_ascii_uppercase(),
+        chars.as_str()
+    ))
+}
+
+// Tell whether `interface` is the type-specific interface of the given unit, i.e. the one which
+// `get_type_interface` returns.
+pub fn is_type_interface(unit_name: &str, interface: &str) -> bool {
+    let unit_type = match unit_name.rsplit_once('.') {
+        Some((_, unit_type)) => unit_type,
+        None => return false,
+    };
+    let interface_type = match interface.strip_prefix(INTERFACE_PREFIX) {
+        Some(interface_type) => interface_type,
+        None => return false,
+    };
+    let mut chars = unit_type.chars();
+    let mut interface_chars = interface_type.chars();
+    match (chars.next(), interface_chars.next()) {
+        (Some(first), Some(interface_first)) => {
+            first.to_ascii_uppercase() == interface_first
+                && chars.as_str() == interface_chars.as_str()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERFACE: &str = "org.freedesktop.systemd1.Service";
+
+    fn gen_condition(comparison: Comparison) -> PropertyCondition {
+        PropertyCondition {
+            comparison,
+            duration: Duration::from_secs(0),
+            interface: None,
+            property: "MemoryCurrent".to_string(),
+        }
+    }
+
+    // Check whether `condition` is met by `new`, after `old` was recorded, if any.
+    fn is_met(condition: &PropertyCondition, old: Option<u64>, new: &PropertyValue) -> bool {
+        let now = Instant::now();
+        let mut history = PropertyHistory::default();
+        if let Some(old) = old {
+            history.record(INTERFACE, condition, PropertyValue::Number(old), now);
+        }
+        condition.is_met(&history, INTERFACE, new, now)
+    }
+
+    // PropertyValue::from_ref_arg()
+    #[test]
+    fn test_property_value_from_ref_arg() {
+        assert_eq!(PropertyValue::from_ref_arg(&7u64), PropertyValue::Number(7));
+        assert_eq!(PropertyValue::from_ref_arg(&7u32), PropertyValue::Number(7));
+        assert_eq!(
+            PropertyValue::from_ref_arg(&u64::MAX),
+            PropertyValue::Other("[not set]".to_string())
+        );
+        assert_eq!(
+            PropertyValue::from_ref_arg(&"running".to_string()),
+            PropertyValue::Other("running".to_string())
+        );
+    }
+
+    // PropertyCondition::is_met()
+    #[test]
+    fn test_property_condition_is_met_greater_than() {
+        let condition = gen_condition(Comparison::GreaterThan(10));
+        let low = PropertyValue::Number(5);
+        let high = PropertyValue::Number(15);
+        assert!(is_met(&condition, None, &high));
+        assert!(!is_met(&condition, None, &low));
+        assert!(is_met(&condition, Some(5), &high));
+        assert!(!is_met(&condition, Some(15), &PropertyValue::Number(20)));
+        assert!(!is_met(&condition, Some(15), &low));
+        assert!(!is_met(
+            &condition,
+            None,
+            &PropertyValue::Other("x".to_string())
+        ));
+    }
+
+    // PropertyCondition::is_met()
+    #[test]
+    fn test_property_condition_is_met_less_than() {
+        let condition = gen_condition(Comparison::LessThan(10));
+        let low = PropertyValue::Number(5);
+        let high = PropertyValue::Number(15);
+        assert!(is_met(&condition, Some(15), &low));
+        assert!(!is_met(&condition, Some(5), &PropertyValue::Number(1)));
+        assert!(!is_met(&condition, Some(5), &high));
+    }
+
+    // PropertyCondition::is_met()
+    #[test]
+    fn test_property_condition_is_met_changed() {
+        let condition = gen_condition(Comparison::Changed);
+        let one = PropertyValue::Number(1);
+        let two = PropertyValue::Number(2);
+        assert!(!is_met(&condition, None, &one));
+        assert!(!is_met(&condition, Some(1), &one));
+        assert!(is_met(&condition, Some(1), &two));
+    }
+
+    // PropertyCondition::is_met(), PropertyHistory::get_rate()
+    #[test]
+    fn test_property_condition_is_met_rate_above() {
+        let mut condition = gen_condition(Comparison::RateAbove(100));
+        condition.property = "NAccepted".to_string();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut history = PropertyHistory::default();
+
+        // A counter isn't measured until it has been seen twice.
+        let value = PropertyValue::Number(1000);
+        assert!(!condition.is_met(&history, INTERFACE, &value, at(0)));
+        history.record(INTERFACE, &condition, value, at(0));
+
+        // 40 in 30 seconds is 80 per minute, and 60 in 30 seconds is 120 per minute.
+        assert_eq!(
+            history.get_rate(INTERFACE, "NAccepted", 1040, at(30)),
+            Some(80)
+        );
+        let value = PropertyValue::Number(1040);
+        assert!(!condition.is_met(&history, INTERFACE, &value, at(30)));
+        history.record(INTERFACE, &condition, value, at(30));
+        let value = PropertyValue::Number(1100);
+        assert!(condition.is_met(&history, INTERFACE, &value, at(60)));
+        history.record(INTERFACE, &condition, value, at(60));
+
+        // A counter which was reset isn't beyond the threshold.
+        assert_eq!(history.get_rate(INTERFACE, "NAccepted", 5, at(90)), None);
+        assert!(!condition.is_met(&history, INTERFACE, &PropertyValue::Number(5), at(90)));
+    }
+
+    // PropertyCondition::is_met()
+    #[test]
+    fn test_property_condition_is_met_duration() {
+        let mut condition = gen_condition(Comparison::GreaterThan(10));
+        condition.duration = Duration::from_secs(300);
+        let high = PropertyValue::Number(15);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut history = PropertyHistory::default();
+
+        assert!(!condition.is_met(&history, INTERFACE, &high, at(0)));
+        history.record(INTERFACE, &condition, high.clone(), at(0));
+        assert!(!condition.is_met(&history, INTERFACE, &high, at(200)));
+        history.record(INTERFACE, &condition, high.clone(), at(200));
+        assert!(condition.is_met(&history, INTERFACE, &high, at(300)));
+        history.record(INTERFACE, &condition, high.clone(), at(300));
+        assert!(!condition.is_met(&history, INTERFACE, &high, at(400)));
+
+        // Dropping below the threshold starts the clock over.
+        history.record(INTERFACE, &condition, PropertyValue::Number(5), at(400));
+        assert!(!condition.is_met(&history, INTERFACE, &high, at(500)));
+        history.record(INTERFACE, &condition, high.clone(), at(500));
+        assert!(condition.is_met(&history, INTERFACE, &high, at(800)));
+    }
+
+    // PropertyCondition::try_from()
+    #[test]
+    fn test_property_condition_try_from() {
+        let gen = |condition: &str, value: Option<u64>| SerdePropertyCondition {
+            condition: condition.to_string(),
+            duration: 0,
+            interface: None,
+            property: "TasksCurrent".to_string(),
+            value,
+        };
+        let condition = PropertyCondition::try_from(gen(">", Some(3))).expect("Failed to parse.");
+        assert_eq!(condition.comparison, Comparison::GreaterThan(3));
+        let condition =
+            PropertyCondition::try_from(gen("changed", None)).expect("Failed to parse.");
+        assert_eq!(condition.comparison, Comparison::Changed);
+        let condition =
+            PropertyCondition::try_from(gen("rate >", Some(60))).expect("Failed to parse.");
+        assert_eq!(condition.comparison, Comparison::RateAbove(60));
+        match PropertyCondition::try_from(gen("<", None)) {
+            Err(CrateError::Settings(SettingsError::MissingPropertyThreshold(_))) => {}
+            _ => panic!("expected MissingPropertyThreshold; a threshold condition lacks a value"),
+        }
+        match PropertyCondition::try_from(gen(">=", Some(3))) {
+            Err(CrateError::Settings(SettingsError::InvalidPropertyCondition(_))) => {}
+            _ => panic!("expected InvalidPropertyCondition; the condition is unsupported"),
+        }
+    }
+
+    // get_type_interface()
+    #[test]
+    fn test_get_type_interface() {
+        assert_eq!(
+            get_type_interface("foo.service"),
+            Some("org.freedesktop.systemd1.Service".to_string())
+        );
+        assert_eq!(
+            get_type_interface("getty@tty1.service"),
+            Some("org.freedesktop.systemd1.Service".to_string())
+        );
+        assert_eq!(
+            get_type_interface("-.mount"),
+            Some("org.freedesktop.systemd1.Mount".to_string())
+        );
+        assert_eq!(get_type_interface("foo"), None);
+        assert_eq!(get_type_interface("foo."), None);
+    }
+
+    // is_type_interface()
+    #[test]
+    fn test_is_type_interface() {
+        for unit_name in &[
+            "foo.service",
+            "getty@tty1.service",
+            "-.mount",
+            "foo",
+            "foo.",
+        ] {
+            for interface in &[
+                "org.freedesktop.systemd1.Service",
+                "org.freedesktop.systemd1.Mount",
+                "org.freedesktop.systemd1.service",
+                "org.freedesktop.systemd1.",
+            ] {
+                assert_eq!(
+                    is_type_interface(unit_name, interface),
+                    get_type_interface(unit_name).as_deref() == Some(*interface),
+                    "{} {}",
+                    unit_name,
+                    interface
+                );
+            }
+        }
+    }
+}
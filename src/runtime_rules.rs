@@ -0,0 +1,189 @@
+// Logic for adding and removing rules while killjoy runs, without editing the settings file.
+//
+// Like silences, runtime rules are stored in a file, so that they may be managed by one killjoy
+// process (i.e. `killjoy rules ...`) and obeyed by another (i.e. the one watching units). Each rule
+// is stored as it would appear in the settings file, and is converted whenever it's loaded, so that
+// it's checked against the settings in effect at that time.
+
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IOError, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use xdg::BaseDirectories;
+
+use crate::error::{Error as CrateError, StateError};
+use crate::settings::Settings;
+
+// A rule added at runtime, as it would appear in the settings file's `rules` list.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RuntimeRule {
+    pub id: u32,
+    pub rule: Value,
+}
+
+// A collection of runtime rules, as stored in the runtime rules file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RuntimeRules {
+    rules: Vec<RuntimeRule>,
+}
+
+impl RuntimeRules {
+    // Read runtime rules from the given file. If the file doesn't exist, there are no such rules.
+    pub fn load(path: &Path) -> Result<Self, CrateError> {
+        match File::open(path) {
+            Ok(handle) => serde_json::from_reader(BufReader::new(handle))
+                .map_err(|err| CrateError::State(StateError::DeserializeRuntimeRulesFile(err))),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CrateError::State(StateError::ReadRuntimeRulesFile(err))),
+        }
+    }
+
+    // Write runtime rules to the given file.
+    //
+    // The file is replaced atomically, so that concurrent readers never see a partial file.
+    pub fn save(&self, path: &Path) -> Result<(), CrateError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|err| CrateError::State(StateError::SerializeRuntimeRulesFile(err)))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .map_err(|err| CrateError::State(StateError::WriteRuntimeRulesFile(err)))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|err| CrateError::State(StateError::WriteRuntimeRulesFile(err)))
+    }
+
+    // Add a rule, and return its ID. The rule isn't checked; see `Settings::parse_rule`.
+    pub fn add(&mut self, rule: Value) -> u32 {
+        let id = self.rules.iter().map(|rule| rule.id).max().unwrap_or(0) + 1;
+        self.rules.push(RuntimeRule { id, rule });
+        id
+    }
+
+    // Remove the rule with the given ID. Return an error if there is no such rule.
+    pub fn remove(&mut self, id: u32) -> Result<(), CrateError> {
+        let len = self.rules.len();
+        self.rules.retain(|rule| rule.id != id);
+        if self.rules.len() == len {
+            return Err(CrateError::State(StateError::NoSuchRuntimeRule(id)));
+        }
+        Ok(())
+    }
+
+    // Append each rule to the rules in `settings`, in order of creation.
+    //
+    // A rule which can't be converted, e.g. because it references a notifier which has since been
+    // removed from the settings file, is reported and skipped.
+    pub fn apply(&self, settings: &mut Settings) {
+        for runtime_rule in &self.rules {
+            match settings.parse_rule(&runtime_rule.rule) {
+                Ok(rule) => settings.rules.push(rule),
+                Err(errs) => {
+                    for err in errs {
+                        eprintln!(
+                            "Ignoring runtime rule {}: {}",
+                            runtime_rule.id,
+                            err.with_code()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Get all runtime rules, in order of creation.
+    pub fn iter(&self) -> impl Iterator<Item = &RuntimeRule> {
+        self.rules.iter()
+    }
+}
+
+// Add a rule to the runtime rules file, and return its ID.
+//
+// The rule is checked against `settings`, and against the rules already added at runtime. Every
+// problem found is returned.
+pub fn add(settings: &Settings, rule: Value) -> Result<u32, Vec<CrateError>> {
+    let path = get_runtime_rules_path().map_err(|err| vec![err])?;
+    let mut runtime_rules = RuntimeRules::load(&path).map_err(|err| vec![err])?;
+    let mut settings = settings.clone();
+    runtime_rules.apply(&mut settings);
+    settings.parse_rule(&rule)?;
+    let id = runtime_rules.add(rule);
+    runtime_rules.save(&path).map_err(|err| vec![err])?;
+    Ok(id)
+}
+
+// Get the path to the runtime rules file, creating parent directories if necessary.
+//
+// The file is placed in `$XDG_STATE_HOME/killjoy`, so that runtime rules survive restarts.
+pub fn get_runtime_rules_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::State(StateError::PlaceRuntimeRulesFile(IOError::other(err))))?
+        .place_state_file("rules.json")
+        .map_err(|err| CrateError::State(StateError::PlaceRuntimeRulesFile(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    // RuntimeRules::add(), RuntimeRules::remove()
+    #[test]
+    fn test_runtime_rules_remove() {
+        let mut runtime_rules = RuntimeRules::default();
+        let id1 = runtime_rules.add(json!({}));
+        let id2 = runtime_rules.add(json!({}));
+        assert_ne!(id1, id2);
+        runtime_rules.remove(id1).expect("Failed to remove rule.");
+        match runtime_rules.remove(id1) {
+            Err(CrateError::State(StateError::NoSuchRuntimeRule(_))) => {}
+            _ => panic!("expected NoSuchRuntimeRule; the rule has already been removed"),
+        }
+        assert_eq!(runtime_rules.iter().count(), 1);
+    }
+
+    // RuntimeRules::apply()
+    #[test]
+    fn test_runtime_rules_apply() {
+        let mut settings = Settings::new(r#"{"notifiers": {}, "rules": []}"#.as_bytes())
+            .expect("Failed to create settings.");
+        let mut runtime_rules = RuntimeRules::default();
+        runtime_rules.add(json!({
+            "expression": "foo.service",
+            "expression_type": "unit name",
+            "bus_type": "system",
+            "active_states": ["failed"],
+            "notifiers": []
+        }));
+        runtime_rules.add(json!({"expression": "bar.service"}));
+        runtime_rules.apply(&mut settings);
+        assert_eq!(settings.rules.len(), 1);
+    }
+
+    // RuntimeRules::save(), RuntimeRules::load()
+    #[test]
+    fn test_runtime_rules_save_load() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let path = dir.path().join("rules.json");
+        assert_eq!(
+            RuntimeRules::load(&path)
+                .expect("Failed to load runtime rules.")
+                .iter()
+                .count(),
+            0
+        );
+
+        let mut runtime_rules = RuntimeRules::default();
+        runtime_rules.add(json!({"expression": "foo.service"}));
+        runtime_rules
+            .save(&path)
+            .expect("Failed to save runtime rules.");
+        let loaded = RuntimeRules::load(&path).expect("Failed to load runtime rules.");
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            runtime_rules.iter().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,358 @@
+// Logic for recurring windows of time, such as quiet hours, and recurring moments, such as the
+// times at which digests are sent.
+
+use std::convert::TryFrom;
+
+use crate::error::{Error as CrateError, SettingsError};
+
+// The names of the days of the week, starting with Monday.
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+// A moment in local time, reduced to the parts that windows care about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalTime {
+    weekday: usize,     // 0 is Monday
+    minute_of_day: u32, // 0 is midnight
+}
+
+impl LocalTime {
+    // Create a local time. `weekday` is 0 for Monday, and `minute_of_day` is 0 for midnight.
+    pub fn new(weekday: usize, minute_of_day: u32) -> Self {
+        LocalTime {
+            weekday: weekday % 7,
+            minute_of_day: minute_of_day % MINUTES_PER_DAY,
+        }
+    }
+
+    // Get the current local time, as per the system's time zone.
+    pub fn now() -> Self {
+        // SAFETY: `time` accepts a null pointer, and `localtime_r` writes to the given `tm`, which
+        // is valid and writable.
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        // tm_wday is 0 for Sunday.
+        LocalTime::new(
+            (tm.tm_wday as usize + 6) % 7,
+            tm.tm_hour as u32 * 60 + tm.tm_min as u32,
+        )
+    }
+
+    // Get the number of minutes since the start of the week.
+    fn minute_of_week(self) -> u32 {
+        self.weekday as u32 * MINUTES_PER_DAY + self.minute_of_day
+    }
+}
+
+// A recurring window of time, such as "Sat..Sun" or "Mon..Fri 22:00-07:00".
+//
+// A window is written as a set of days, a range of times, or both, separated by a space. Days are
+// written like systemd.time(7) weekdays: a single day (`Mon`), a range (`Mon..Fri`), or a
+// comma-separated list of either (`Mon,Wed,Fri..Sun`). Times are written as `HH:MM-HH:MM`. If the
+// end time is earlier than the start time, the range extends past midnight, and belongs to the day
+// on which it starts. For example, `Fri 22:00-07:00` covers Friday night and Saturday morning.
+#[derive(Clone, Debug)]
+pub struct Window {
+    days: [bool; 7],
+    times: Option<(u32, u32)>, // minutes since midnight; [start, end)
+}
+
+impl Window {
+    // Tell whether the given time falls within this window.
+    pub fn contains(&self, time: LocalTime) -> bool {
+        match self.times {
+            None => self.days[time.weekday],
+            Some((start, end)) if start <= end => {
+                self.days[time.weekday] && start <= time.minute_of_day && time.minute_of_day < end
+            }
+            Some((start, end)) => {
+                let yesterday = (time.weekday + 6) % 7;
+                (self.days[time.weekday] && start <= time.minute_of_day)
+                    || (self.days[yesterday] && time.minute_of_day < end)
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for Window {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = |reason: &str| {
+            CrateError::Settings(SettingsError::InvalidSchedule(
+                value.to_string(),
+                reason.into(),
+            ))
+        };
+        let mut days: Option<[bool; 7]> = None;
+        let mut times: Option<(u32, u32)> = None;
+        for part in value.split_whitespace() {
+            if part.contains(':') {
+                if times.is_some() {
+                    return Err(invalid("more than one time range"));
+                }
+                times = Some(parse_times(part).ok_or_else(|| invalid("malformed time range"))?);
+            } else {
+                if days.is_some() {
+                    return Err(invalid("more than one set of days"));
+                }
+                days = Some(parse_days(part).ok_or_else(|| invalid("malformed days"))?);
+            }
+        }
+        if days.is_none() && times.is_none() {
+            return Err(invalid("empty window"));
+        }
+        Ok(Window {
+            days: days.unwrap_or([true; 7]),
+            times,
+        })
+    }
+}
+
+// A recurring moment, such as "08:00" or "Mon..Fri 08:00,17:00".
+//
+// A recurrence is written as an optional set of days, written as for a `Window`, followed by a
+// comma-separated list of times, written as `HH:MM`. Without days, the recurrence happens daily.
+#[derive(Clone, Debug)]
+pub struct Recurrence {
+    days: [bool; 7],
+    times: Vec<u32>, // minutes since midnight
+}
+
+impl Recurrence {
+    // Tell whether this recurrence happens after `since`, and no later than `now`.
+    //
+    // Times are compared to the minute, and at most a week is considered. If `since` and `now` are
+    // equal, the answer is no.
+    pub fn occurs_between(&self, since: LocalTime, now: LocalTime) -> bool {
+        let since = since.minute_of_week();
+        let elapsed = (now.minute_of_week() + MINUTES_PER_WEEK - since) % MINUTES_PER_WEEK;
+        (1..=elapsed).any(|offset| {
+            let minute = (since + offset) % MINUTES_PER_WEEK;
+            self.days[(minute / MINUTES_PER_DAY) as usize]
+                && self.times.contains(&(minute % MINUTES_PER_DAY))
+        })
+    }
+}
+
+impl TryFrom<&str> for Recurrence {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = |reason: &str| {
+            CrateError::Settings(SettingsError::InvalidSchedule(
+                value.to_string(),
+                reason.into(),
+            ))
+        };
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        let (days_str, times_str) = match parts[..] {
+            [times_str] => (None, times_str),
+            [days_str, times_str] => (Some(days_str), times_str),
+            [] => return Err(invalid("empty recurrence")),
+            _ => return Err(invalid("expected days and times")),
+        };
+        let days = match days_str {
+            Some(days_str) => parse_days(days_str).ok_or_else(|| invalid("malformed days"))?,
+            None => [true; 7],
+        };
+        let mut times: Vec<u32> = Vec::new();
+        for time_str in times_str.split(',') {
+            match parse_time(time_str) {
+                Some(time) if time < MINUTES_PER_DAY => times.push(time),
+                _ => return Err(invalid("malformed time")),
+            }
+        }
+        Ok(Recurrence { days, times })
+    }
+}
+
+// Parse a day specification such as "Mon,Wed..Fri". Return `None` if it is malformed.
+fn parse_days(days_str: &str) -> Option<[bool; 7]> {
+    let mut days = [false; 7];
+    for item in days_str.split(',') {
+        let (first, last) = match item.find("..") {
+            Some(index) => (
+                parse_weekday(&item[..index])?,
+                parse_weekday(&item[index + 2..])?,
+            ),
+            None => {
+                let day = parse_weekday(item)?;
+                (day, day)
+            }
+        };
+        let mut day = first;
+        loop {
+            days[day] = true;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+// Parse a weekday name such as "Mon" or "monday". Return the day's index, where 0 is Monday.
+fn parse_weekday(weekday_str: &str) -> Option<usize> {
+    let weekday_str = weekday_str.to_ascii_lowercase();
+    WEEKDAYS.iter().position(|weekday| {
+        *weekday == weekday_str || (weekday_str.len() == 3 && weekday.starts_with(&weekday_str))
+    })
+}
+
+// Parse a time range such as "22:00-07:00". Return `None` if it is malformed.
+fn parse_times(times_str: &str) -> Option<(u32, u32)> {
+    let mut parts = times_str.splitn(2, '-');
+    let start = parse_time(parts.next()?)?;
+    let end = parse_time(parts.next()?)?;
+    if start == end {
+        return None;
+    }
+    Some((start, end))
+}
+
+// Parse a time such as "07:00" into a number of minutes since midnight. "24:00" is accepted.
+fn parse_time(time_str: &str) -> Option<u32> {
+    let mut parts = time_str.splitn(2, ':');
+    let hours: u32 = parts.next()?.parse().ok()?;
+    let minutes: u32 = parts.next()?.parse().ok()?;
+    if minutes >= 60 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MON: usize = 0;
+    const FRI: usize = 4;
+    const SAT: usize = 5;
+    const SUN: usize = 6;
+
+    fn at(weekday: usize, hour: u32, minute: u32) -> LocalTime {
+        LocalTime::new(weekday, hour * 60 + minute)
+    }
+
+    // Window::try_from()
+    #[test]
+    fn test_window_try_from_days() {
+        let window = Window::try_from("Sat..Sun").expect("Failed to parse window.");
+        assert!(window.contains(at(SAT, 0, 0)));
+        assert!(window.contains(at(SUN, 23, 59)));
+        assert!(!window.contains(at(MON, 12, 0)));
+    }
+
+    // Window::try_from()
+    #[test]
+    fn test_window_try_from_days_wrapping() {
+        let window = Window::try_from("Fri..mon").expect("Failed to parse window.");
+        assert!(window.contains(at(FRI, 12, 0)));
+        assert!(window.contains(at(MON, 12, 0)));
+        assert!(!window.contains(at(FRI - 1, 12, 0)));
+    }
+
+    // Window::try_from()
+    #[test]
+    fn test_window_try_from_times() {
+        let window = Window::try_from("09:00-17:30").expect("Failed to parse window.");
+        assert!(window.contains(at(MON, 9, 0)));
+        assert!(window.contains(at(SUN, 17, 29)));
+        assert!(!window.contains(at(MON, 17, 30)));
+        assert!(!window.contains(at(MON, 8, 59)));
+    }
+
+    // Window::try_from()
+    #[test]
+    fn test_window_try_from_overnight() {
+        let window = Window::try_from("Fri 22:00-07:00").expect("Failed to parse window.");
+        assert!(window.contains(at(FRI, 23, 0)));
+        assert!(window.contains(at(SAT, 6, 59)));
+        assert!(!window.contains(at(SAT, 23, 0)));
+        assert!(!window.contains(at(FRI, 6, 0)));
+    }
+
+    // Window::try_from()
+    #[test]
+    fn test_window_try_from_invalid() {
+        for window_str in [
+            "",
+            "Someday",
+            "Mo",
+            "Mon..Funday",
+            "25:00-26:00",
+            "10:00-10:00",
+            "10:00",
+            "Mon Tue",
+            "09:00-10:00 11:00-12:00",
+        ] {
+            match Window::try_from(window_str) {
+                Err(CrateError::Settings(SettingsError::InvalidSchedule(_, _))) => {}
+                _ => panic!("expected InvalidSchedule for {:?}", window_str),
+            }
+        }
+    }
+
+    // parse_days()
+    #[test]
+    fn test_parse_days() {
+        let days = parse_days("Mon,Wed..Fri,Sunday").expect("Failed to parse days.");
+        assert_eq!(days, [true, false, true, true, true, false, true]);
+    }
+
+    // LocalTime::now()
+    #[test]
+    fn test_local_time_now() {
+        let now = LocalTime::now();
+        assert!(now.weekday < 7);
+        assert!(now.minute_of_day < MINUTES_PER_DAY);
+    }
+
+    // Recurrence::occurs_between()
+    #[test]
+    fn test_recurrence_occurs_between() {
+        let recurrence = Recurrence::try_from("08:00,17:30").expect("Failed to parse recurrence.");
+        assert!(recurrence.occurs_between(at(MON, 7, 59), at(MON, 8, 0)));
+        assert!(!recurrence.occurs_between(at(MON, 8, 0), at(MON, 8, 1)));
+        assert!(recurrence.occurs_between(at(MON, 9, 0), at(MON, 17, 30)));
+        assert!(!recurrence.occurs_between(at(MON, 9, 0), at(MON, 9, 0)));
+        assert!(recurrence.occurs_between(at(SUN, 23, 0), at(MON, 8, 0)));
+    }
+
+    // Recurrence::occurs_between()
+    #[test]
+    fn test_recurrence_occurs_between_days() {
+        let recurrence =
+            Recurrence::try_from("Mon..Fri 08:00").expect("Failed to parse recurrence.");
+        assert!(recurrence.occurs_between(at(FRI, 7, 0), at(FRI, 9, 0)));
+        assert!(!recurrence.occurs_between(at(SAT, 7, 0), at(SUN, 9, 0)));
+        assert!(recurrence.occurs_between(at(SAT, 7, 0), at(MON, 9, 0)));
+    }
+
+    // Recurrence::try_from()
+    #[test]
+    fn test_recurrence_try_from_invalid() {
+        for recurrence_str in ["", "Mon", "24:00", "08:00-09:00", "Mon 08:00 09:00", "8am"] {
+            match Recurrence::try_from(recurrence_str) {
+                Err(CrateError::Settings(SettingsError::InvalidSchedule(_, _))) => {}
+                _ => panic!("expected InvalidSchedule for {:?}", recurrence_str),
+            }
+        }
+    }
+}
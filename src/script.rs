@@ -0,0 +1,277 @@
+// Logic for running the filter scripts of rules.
+//
+// Filters are written in Rhai (https://rhai.rs). A rule's filter is run whenever the rule matches a
+// state change, with a description of the change in the constant `event`. The filter returns
+// either a boolean, which states whether to notify, or a map, like:
+//
+//     #{notify: true, fields: #{owner: "ops"}}
+//
+// `notify` defaults to true. `fields` are added to the rule's labels for this notification only, so
+// their names must be valid label names.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use dbus::BusType;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use crate::error::{ActionError, Error as CrateError, SettingsError};
+use crate::property::PropertyValue;
+use crate::settings;
+use crate::settings::Severity;
+
+// How many operations a filter may perform before it's aborted, e.g. because it loops forever.
+const MAX_OPERATIONS: u64 = 100_000;
+
+// How deeply a filter's expressions may nest.
+const MAX_EXPR_DEPTH: usize = 64;
+
+// A state change, as described to a filter.
+//
+// `states` lists the unit's new state, followed by its old state, if known. `properties` holds the
+// unit's properties from the `org.freedesktop.systemd1.Unit` interface, and is empty if they
+// couldn't be read.
+pub struct FilterInput<'a> {
+    pub bus_type: BusType,
+    pub hostname: &'a str,
+    pub labels: &'a HashMap<String, String>,
+    pub properties: &'a HashMap<String, PropertyValue>,
+    pub rule_name: Option<&'a str>,
+    pub severity: Severity,
+    pub states: &'a [String],
+    pub unit_name: &'a str,
+}
+
+// What a filter decided.
+#[derive(Debug, Eq, PartialEq)]
+pub struct FilterOutput {
+    pub fields: HashMap<String, String>,
+    pub notify: bool,
+}
+
+// Compile a filter, so that syntax errors are found when the settings are loaded.
+pub fn compile(source: &str) -> Result<AST, CrateError> {
+    gen_engine()
+        .compile(source)
+        .map_err(|err| CrateError::Settings(SettingsError::InvalidFilter(err.to_string())))
+}
+
+// Run a compiled filter.
+//
+// `rule_description` is used to describe failures.
+pub fn run(
+    ast: &AST,
+    rule_description: &str,
+    input: &FilterInput,
+) -> Result<FilterOutput, CrateError> {
+    let fail = |reason: String| {
+        CrateError::Action(ActionError::FilterFailed(
+            rule_description.to_string(),
+            reason,
+        ))
+    };
+    let mut scope = Scope::new();
+    scope.push_constant("event", gen_event(input));
+    let result: Dynamic = gen_engine()
+        .eval_ast_with_scope(&mut scope, ast)
+        .map_err(|err| fail(err.to_string()))?;
+    parse_result(result).map_err(fail)
+}
+
+// Create an engine with limits, so that a misbehaving filter can't stall a bus watcher.
+fn gen_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH);
+    engine
+}
+
+// Generate the `event` constant seen by filters.
+//
+// Numeric properties are integers, and all other properties are strings. `old_state` is `()` if
+// unknown.
+fn gen_event(input: &FilterInput) -> Map {
+    let mut properties = Map::new();
+    for (name, value) in input.properties {
+        let value = match value {
+            PropertyValue::Number(number) => match i64::try_from(*number) {
+                Ok(number) => Dynamic::from(number),
+                Err(_) => Dynamic::from(number.to_string()),
+            },
+            PropertyValue::Other(text) => Dynamic::from(text.to_owned()),
+        };
+        properties.insert(name.into(), value);
+    }
+    let mut labels = Map::new();
+    for (name, value) in input.labels {
+        labels.insert(name.into(), Dynamic::from(value.to_owned()));
+    }
+    let states: Array = input
+        .states
+        .iter()
+        .map(|state| Dynamic::from(state.to_owned()))
+        .collect();
+
+    let mut event = Map::new();
+    event.insert(
+        "bus".into(),
+        Dynamic::from(settings::encode_bus_type(input.bus_type).to_string()),
+    );
+    event.insert("hostname".into(), Dynamic::from(input.hostname.to_string()));
+    event.insert("labels".into(), Dynamic::from(labels));
+    event.insert(
+        "new_state".into(),
+        Dynamic::from(input.states.first().cloned().unwrap_or_default()),
+    );
+    event.insert(
+        "old_state".into(),
+        match input.states.get(1) {
+            Some(old_state) => Dynamic::from(old_state.to_owned()),
+            None => Dynamic::UNIT,
+        },
+    );
+    event.insert("properties".into(), Dynamic::from(properties));
+    event.insert(
+        "rule".into(),
+        Dynamic::from(input.rule_name.unwrap_or_default().to_string()),
+    );
+    event.insert(
+        "severity".into(),
+        Dynamic::from(String::from(input.severity)),
+    );
+    event.insert("states".into(), Dynamic::from(states));
+    event.insert("unit".into(), Dynamic::from(input.unit_name.to_string()));
+    event
+}
+
+// Interpret the value returned by a filter. Return a reason if it's invalid.
+fn parse_result(result: Dynamic) -> Result<FilterOutput, String> {
+    if let Ok(notify) = result.as_bool() {
+        return Ok(FilterOutput {
+            fields: HashMap::new(),
+            notify,
+        });
+    }
+    let type_name = result.type_name();
+    let mut result = match result.try_cast::<Map>() {
+        Some(result) => result,
+        None => return Err(format!("returned {} (expected bool or map)", type_name)),
+    };
+    let notify = match result.remove("notify") {
+        Some(notify) => notify
+            .as_bool()
+            .map_err(|type_name| format!("returned notify: {} (expected bool)", type_name))?,
+        None => true,
+    };
+    let mut fields: HashMap<String, String> = HashMap::new();
+    if let Some(field_map) = result.remove("fields") {
+        let type_name = field_map.type_name();
+        let field_map = field_map
+            .try_cast::<Map>()
+            .ok_or_else(|| format!("returned fields: {} (expected map)", type_name))?;
+        for (name, value) in field_map {
+            if !settings::is_valid_label_name(&name) {
+                return Err(format!("returned invalid field name: {}", name));
+            }
+            fields.insert(name.to_string(), value.to_string());
+        }
+    }
+    if let Some(key) = result.keys().next() {
+        return Err(format!("returned unknown key: {}", key));
+    }
+    Ok(FilterOutput { fields, notify })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_filter(source: &str, properties: &HashMap<String, PropertyValue>) -> FilterOutput {
+        let mut labels: HashMap<String, String> = HashMap::new();
+        labels.insert("team".to_string(), "ops".to_string());
+        let states = vec!["failed".to_string(), "active".to_string()];
+        let input = FilterInput {
+            bus_type: BusType::System,
+            hostname: "host",
+            labels: &labels,
+            properties,
+            rule_name: Some("web"),
+            severity: Severity::Warning,
+            states: &states,
+            unit_name: "foo.service",
+        };
+        let ast = compile(source).expect("Failed to compile filter.");
+        run(&ast, "Rule \"web\"", &input).expect("Failed to run filter.")
+    }
+
+    // compile()
+    #[test]
+    fn test_compile_invalid() {
+        match compile("if (") {
+            Err(CrateError::Settings(SettingsError::InvalidFilter(_))) => {}
+            _ => panic!("expected InvalidFilter; the filter doesn't parse"),
+        }
+    }
+
+    // run()
+    #[test]
+    fn test_run() {
+        let mut properties: HashMap<String, PropertyValue> = HashMap::new();
+        properties.insert("NRestarts".to_string(), PropertyValue::Number(3));
+        let output = run_filter(
+            r#"event.unit == "foo.service" && event.old_state == "active"
+                && event.labels.team == "ops" && event.properties.NRestarts > 2"#,
+            &properties,
+        );
+        assert!(output.notify);
+        assert!(output.fields.is_empty());
+
+        let output = run_filter(
+            r#"#{fields: #{restarts: event.properties.NRestarts, bus: event.bus}}"#,
+            &properties,
+        );
+        assert!(output.notify);
+        assert_eq!(output.fields["restarts"], "3");
+        assert_eq!(output.fields["bus"], "system");
+
+        let output = run_filter(r#"#{notify: event.severity != "warning"}"#, &properties);
+        assert!(!output.notify);
+    }
+
+    // run()
+    #[test]
+    fn test_run_failed() {
+        let ast = compile("loop {}").unwrap();
+        let input = FilterInput {
+            bus_type: BusType::Session,
+            hostname: "",
+            labels: &HashMap::new(),
+            properties: &HashMap::new(),
+            rule_name: None,
+            severity: Severity::Warning,
+            states: &["failed".to_string()],
+            unit_name: "foo.service",
+        };
+        match run(&ast, "Rule \"web\"", &input) {
+            Err(CrateError::Action(ActionError::FilterFailed(_, _))) => {}
+            _ => panic!("expected FilterFailed; the filter never finishes"),
+        }
+    }
+
+    // parse_result()
+    #[test]
+    fn test_parse_result() {
+        assert!(parse_result(Dynamic::from(42_i64)).is_err());
+        let mut result = Map::new();
+        result.insert("notfy".into(), Dynamic::from(false));
+        assert!(parse_result(Dynamic::from(result)).is_err());
+        let mut result = Map::new();
+        result.insert("fields".into(), Dynamic::from("x".to_string()));
+        assert!(parse_result(Dynamic::from(result)).is_err());
+        let mut fields = Map::new();
+        fields.insert("not a label".into(), Dynamic::from(1_i64));
+        let mut result = Map::new();
+        result.insert("fields".into(), Dynamic::from(fields));
+        assert!(parse_result(Dynamic::from(result)).is_err());
+    }
+}
@@ -2,671 +2,6986 @@
 
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use dbus::{BusName, BusType};
+use dbus::{BusName, BusType, Interface};
 use regex::Regex;
 use serde::Deserialize;
+use serde_json::{Map, Value};
 use xdg::BaseDirectories;
 
-use crate::error::Error as CrateError;
-use crate::unit::ActiveState;
+use crate::actions::{Action, SerdeAction};
+use crate::error::{Error as CrateError, SettingsError, StateError};
+use crate::property::{PropertyCondition, SerdePropertyCondition};
+use crate::schedule::{LocalTime, Recurrence, Window};
+#[cfg(feature = "scripting")]
+use crate::script;
+use crate::template::Template;
+use crate::timestamp::Clock;
+use crate::unit;
+use crate::unit::{ActiveState, LoadState, PortableState, SystemState, UnitFileState};
 
 // The expressions that a user may use to match unit names.
+//
+// A `Manager` expression matches no units. Instead, it names a property of the manager, i.e. of
+// systemd itself. Only "SystemState" is supported.
+//
+// A `PortableImage` expression names a portable service image. It matches the image's units, whose
+// names systemd requires to start with the image's name, followed by ".", "-" or "@".
 #[derive(Clone, Debug)]
 pub enum Expression {
+    Manager(String),
+    PortableImage(String),
     Regex(Regex),
     UnitName(String),
+    UnitTemplate(String),
     UnitType(String),
 }
 
 impl Expression {
     // Check whether this expression matches the given `unit_name`.
     //
-    // A `UnitName` expression matches unit names against a unit name. A `UnitType` expression
-    // matches unit names against a unit type. A `Regex` expression matches unit names against a
-    // regular expression.
+    // A `UnitName` expression matches unit names against a unit name. A `UnitTemplate` expression
+    // matches the instances of a template unit, e.g. "getty@.service" matches "getty@tty1.service".
+    // A `UnitType` expression matches unit names against a unit type. A `Regex` expression matches
+    // unit names against a regular expression.
     //
     // Regular expressions are implemented with the regex crate. See: https://docs.rs/regex/
     pub fn matches(&self, unit_name: &str) -> bool {
         match self {
+            Expression::Manager(_) => false,
+            Expression::PortableImage(expr) => unit_name
+                .strip_prefix(&expr[..])
+                .is_some_and(|rest| rest.starts_with(['.', '-', '@'])),
             Expression::Regex(expr) => expr.is_match(unit_name),
             Expression::UnitName(expr) => unit_name == expr,
+            Expression::UnitTemplate(expr) => unit::split_instance_name(unit_name)
+                .is_some_and(|(template_name, _)| &template_name == expr),
             Expression::UnitType(expr) => unit_name.ends_with(expr),
         }
     }
+
+    // Get the expression as written in the settings file.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Expression::Manager(expr) => expr,
+            Expression::PortableImage(expr) => expr,
+            Expression::Regex(expr) => expr.as_str(),
+            Expression::UnitName(expr) => expr,
+            Expression::UnitTemplate(expr) => expr,
+            Expression::UnitType(expr) => expr,
+        }
+    }
 }
 
-// A D-Bus service that may be contacted when an event of interest happens.
+// How important an event is.
 //
-// When an event of interest occurs, killjoy will connect to `bus_type` and send a message to
-// `bus_name`.
-#[derive(Clone, Debug)]
-pub struct Notifier {
-    bus_name: String,
-    pub bus_type: BusType,
+// Notifiers may use a rule's severity to decide how to present an event. For example, a desktop
+// notification notifier might map it to an urgency, and a logging notifier might map it to a
+// syslog priority. Severities are ordered from least to most important.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Debug,
+    Info,
+    Warning,
+    Critical,
 }
 
-impl Notifier {
-    // Create a new notifier.
-    //
-    // Return an error if any arguments are invalid.
-    pub fn new(bus_name: &str, bus_type: BusType) -> Result<Self, CrateError> {
-        let new_obj = Self {
-            bus_name: bus_name.to_owned(),
-            bus_type,
-        };
-        new_obj.maybe_get_bus_name()?;
-        Ok(new_obj)
-    }
-
-    // Get the `bus_name` attribute.
-    pub fn get_bus_name(&self) -> BusName {
-        self.maybe_get_bus_name().expect(
-            "bus_name is invalid. new() should have caught this. Please contact a developer.",
-        )
+impl Severity {
+    // Get the syslog priority corresponding to this severity, as per syslog(3).
+    pub fn syslog_priority(self) -> u8 {
+        match self {
+            Severity::Debug => 7,
+            Severity::Info => 6,
+            Severity::Warning => 4,
+            Severity::Critical => 2,
+        }
     }
 
-    fn maybe_get_bus_name(&self) -> Result<BusName, CrateError> {
-        BusName::new(&self.bus_name[..])
-            .map_err(|_| CrateError::InvalidBusName(self.bus_name.to_owned()))
+    // Get the urgency level corresponding to this severity, as per the Desktop Notifications
+    // Specification. (0 is low, 1 is normal, and 2 is critical.)
+    pub fn urgency(self) -> u8 {
+        match self {
+            Severity::Debug => 0,
+            Severity::Info => 1,
+            Severity::Warning => 1,
+            Severity::Critical => 2,
+        }
     }
 }
 
-impl TryFrom<SerdeNotifier> for Notifier {
+impl TryFrom<&str> for Severity {
     type Error = CrateError;
 
-    fn try_from(value: SerdeNotifier) -> Result<Self, Self::Error> {
-        let notifier = Notifier::new(&value.bus_name, decode_bus_type_str(&value.bus_type)?)?;
-        Ok(notifier)
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "debug" => Ok(Severity::Debug),
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(CrateError::Settings(SettingsError::InvalidSeverity(
+                value.to_string(),
+            ))),
+        }
     }
 }
 
-// Units to watch, and notifiers to contact when any of them enter a state of interest.
+impl From<Severity> for String {
+    fn from(value: Severity) -> String {
+        match value {
+            Severity::Debug => "debug".to_string(),
+            Severity::Info => "info".to_string(),
+            Severity::Warning => "warning".to_string(),
+            Severity::Critical => "critical".to_string(),
+        }
+    }
+}
+
+// How a rule contacts its notifiers.
 //
-// Upon startup, killjoy will connect to `bus_type`. It will watch all units whose name matches
-// `expression`. Whenever one of those units' ActiveState property transitions to one of the
-// `active_states` it will contact `notifiers`.
-#[derive(Clone, Debug)]
-pub struct Rule {
-    pub active_states: HashSet<ActiveState>,
-    pub bus_type: BusType,
-    pub expression: Expression,
-    pub notifiers: Vec<String>,
+// With `All`, every notifier is contacted. With `Fallback`, notifiers are tried in order, and
+// killjoy stops after the first one that is successfully contacted. This makes it possible to
+// declare a preferred notifier and backups for when it can't be reached.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NotifierPolicy {
+    All,
+    Fallback,
 }
 
-impl TryFrom<SerdeRule> for Rule {
+impl TryFrom<&str> for NotifierPolicy {
     type Error = CrateError;
 
-    fn try_from(value: SerdeRule) -> Result<Self, Self::Error> {
-        let mut active_states: HashSet<ActiveState> = HashSet::new();
-        for active_state_string in &value.active_states {
-            let active_state = ActiveState::try_from(&active_state_string[..])
-                .map_err(|_| CrateError::InvalidActiveState(active_state_string.to_owned()))?;
-            active_states.insert(active_state);
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "all" => Ok(NotifierPolicy::All),
+            "fallback" => Ok(NotifierPolicy::Fallback),
+            _ => Err(CrateError::Settings(SettingsError::InvalidNotifierPolicy(
+                value.to_string(),
+            ))),
         }
-        let active_states = active_states;
+    }
+}
 
-        let bus_type = decode_bus_type_str(&value.bus_type)?;
+impl From<NotifierPolicy> for String {
+    fn from(value: NotifierPolicy) -> String {
+        match value {
+            NotifierPolicy::All => "all".to_string(),
+            NotifierPolicy::Fallback => "fallback".to_string(),
+        }
+    }
+}
 
-        let expression: Expression = match &value.expression_type[..] {
-            "regex" => Regex::new(&value.expression[..])
-                .map(Expression::Regex)
-                .map_err(CrateError::InvalidRegex),
-            "unit name" => Ok(Expression::UnitName(value.expression.to_owned())),
-            "unit type" => Ok(Expression::UnitType(value.expression.to_owned())),
-            other => Err(CrateError::InvalidExpressionType(other.to_owned())),
-        }?;
+// What a rule groups its notifications by.
+//
+// With `Slice`, units are grouped by the slice they run in, according to their `Slice` property,
+// e.g. "user-1000.slice". With `Target`, units are grouped by the target that wants them,
+// according to their `WantedBy` property, e.g. "multi-user.target".
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GroupBy {
+    Slice,
+    Target,
+}
 
-        let notifiers = value.notifiers.to_owned();
+impl TryFrom<&str> for GroupBy {
+    type Error = CrateError;
 
-        Ok(Rule {
-            active_states,
-            bus_type,
-            expression,
-            notifiers,
-        })
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "slice" => Ok(GroupBy::Slice),
+            "target" => Ok(GroupBy::Target),
+            _ => Err(CrateError::Settings(SettingsError::InvalidGroupBy(
+                value.to_string(),
+            ))),
+        }
     }
 }
 
-// A deserialized copy of a configuration file.
+impl From<GroupBy> for String {
+    fn from(value: GroupBy) -> String {
+        match value {
+            GroupBy::Slice => "slice".to_string(),
+            GroupBy::Target => "target".to_string(),
+        }
+    }
+}
+
+// How long a rule which groups its notifications waits for more events by default, in seconds.
+pub const DEFAULT_GROUP_WINDOW: u64 = 10;
+
+// Which of the rules matching a state change fire.
 //
-// Beware that `Settings` instances may have semantically invalid values. For example, a notifier's
-// `bus_name` might be syntactically valid but may point to a non-existent entity.
-#[derive(Clone, Debug)]
-pub struct Settings {
-    pub notifiers: HashMap<String, Notifier>,
-    pub rules: Vec<Rule>,
+// With `All`, every matching rule fires. With `FirstMatch`, only the matching rule with the highest
+// `priority` fires, and ties go to the rule listed first. This makes it possible to declare a broad
+// rule as a catch-all, and override it for specific units.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RuleEvaluation {
+    All,
+    FirstMatch,
 }
 
-impl Settings {
-    // Create a new settings object.
-    //
-    // An error may be returned for one of two broad categories of reasons:
-    //
-    // *   Deserialization of the `reader` failed. Maybe the reader yielded non-unicode bytes; maybe
-    //     the bytes were valid unicode but not valid JSON; maybe the unicode was valid JSON but
-    //     didn't match the settings file schema; or so on.
-    // *   The settings object contained semantically invalid data. Maybe a `"bus_type"` key was set
-    //     to a value such as `"foo"`, or so on.
-    pub fn new<T: Read>(reader: T) -> Result<Self, CrateError> {
-        let serde_settings: SerdeSettings = serde_json::from_reader(reader)
-            .map_err(CrateError::SettingsFileDeserializationFailed)?;
-        Self::try_from(serde_settings)
+impl TryFrom<&str> for RuleEvaluation {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "all" => Ok(RuleEvaluation::All),
+            "first_match" => Ok(RuleEvaluation::FirstMatch),
+            _ => Err(CrateError::Settings(SettingsError::InvalidRuleEvaluation(
+                value.to_string(),
+            ))),
+        }
     }
 }
 
-impl TryFrom<SerdeSettings> for Settings {
+impl From<RuleEvaluation> for String {
+    fn from(value: RuleEvaluation) -> String {
+        match value {
+            RuleEvaluation::All => "all".to_string(),
+            RuleEvaluation::FirstMatch => "first_match".to_string(),
+        }
+    }
+}
+
+// How bus watchers talk to systemd.
+//
+// With `DBus`, they call systemd over the bus. With `Varlink`, they call systemd's Varlink
+// interfaces over its Unix sockets instead, which suits hosts without a D-Bus daemon, e.g. during
+// early boot, or in minimal containers. See `VarlinkSystemd`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Transport {
+    DBus,
+    Varlink,
+}
+
+impl TryFrom<&str> for Transport {
     type Error = CrateError;
 
-    fn try_from(value: SerdeSettings) -> Result<Self, Self::Error> {
-        let mut notifiers: HashMap<String, Notifier> = HashMap::new();
-        for (key, serde_notifier) in value.notifiers.into_iter() {
-            let notifier = Notifier::try_from(serde_notifier)?;
-            notifiers.insert(key, notifier);
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "dbus" => Ok(Transport::DBus),
+            "varlink" => Ok(Transport::Varlink),
+            _ => Err(CrateError::Settings(SettingsError::InvalidTransport(
+                value.to_string(),
+            ))),
         }
-        let notifiers = notifiers; // make immutable
+    }
+}
 
-        let mut rules: Vec<Rule> = Vec::new();
-        for serde_rule in value.rules.into_iter() {
-            let rule = Rule::try_from(serde_rule)?;
-            for notifier in &rule.notifiers {
-                if !notifiers.contains_key(notifier) {
-                    return Err(CrateError::InvalidNotifier(notifier.to_owned()));
-                }
-            }
-            rules.push(rule);
+impl From<Transport> for String {
+    fn from(value: Transport) -> String {
+        match value {
+            Transport::DBus => "dbus".to_string(),
+            Transport::Varlink => "varlink".to_string(),
         }
-        let rules = rules; // make immutable
-
-        Ok(Self { notifiers, rules })
     }
 }
 
-// See SerdeSettings.
-#[derive(Deserialize)]
-struct SerdeNotifier {
-    bus_name: String,
-    bus_type: String,
+// When a notifier is contacted.
+//
+// With `Realtime`, the notifier is contacted about each event as it happens. With `Digest`, events
+// are collected, and the notifier is sent a summary of them on its digest schedule. With `Both`,
+// the notifier is contacted in both ways.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NotifierMode {
+    Both,
+    Digest,
+    Realtime,
 }
 
-// See SerdeSettings.
-#[derive(Deserialize)]
-struct SerdeRule {
-    active_states: Vec<String>,
-    bus_type: String,
-    expression: String,
-    expression_type: String,
-    notifiers: Vec<String>,
+impl TryFrom<&str> for NotifierMode {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "both" => Ok(NotifierMode::Both),
+            "digest" => Ok(NotifierMode::Digest),
+            "realtime" => Ok(NotifierMode::Realtime),
+            _ => Err(CrateError::Settings(SettingsError::InvalidNotifierMode(
+                value.to_string(),
+            ))),
+        }
+    }
 }
 
-// Like a `Settings`, but fields are simple types instead of domain-specific types.
-//
-// The `SerdeSettings` object is composed of types from the standard library, such as strings and
-// vectors. This makes it easy to deserialize with serde, but the resulting object may contain
-// values that are syntactically correct but semantically incorrect. For example,
-// `settings.notifiers[i].bus_name` is a `String`, and it may be impossible to cast this string to a
-// `dbus::BusName` object.
-//
-// This makes `SerdeSettings` a bad choice for use throughout the rest of the application. If this
-// was done, killjoy could encounter semantic errors late in runtime. For example, killjoy might
-// notice an event of interest, decide to notify a notifier, and *then* discover that a `bus_name`
-// contains a malformed address.
-//
-// Calling SerdeSettings::to_settings forces values to be cast to more appropriate types. Although
-// type-casting doesn't guarantee that a settings object contains valid values, it gets closer to
-// the ideal.
-#[derive(Deserialize)]
-struct SerdeSettings {
-    notifiers: HashMap<String, SerdeNotifier>,
-    rules: Vec<SerdeRule>,
+impl From<NotifierMode> for String {
+    fn from(value: NotifierMode) -> String {
+        match value {
+            NotifierMode::Both => "both".to_string(),
+            NotifierMode::Digest => "digest".to_string(),
+            NotifierMode::Realtime => "realtime".to_string(),
+        }
+    }
 }
 
-// This struct is a hack. See get_bus_types().
-#[derive(PartialEq, Eq, Hash)]
-enum HashableBusType {
-    Session,
-    Starter,
-    System,
+// The versions of the notifier protocol.
+//
+// Version 1 notifiers implement `name.jerebear.KilljoyNotifier1`, whose `Notify` method accepts
+// positional arguments. Version 2 notifiers implement `name.jerebear.KilljoyNotifier2`, whose
+// `Notify` method accepts a single dictionary, which can grow new keys without breaking notifiers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
 }
 
-// This impl is a hack. See get_bus_types().
-impl From<BusType> for HashableBusType {
-    fn from(value: BusType) -> Self {
+impl TryFrom<u32> for ProtocolVersion {
+    type Error = CrateError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
         match value {
-            BusType::Session => HashableBusType::Session,
-            BusType::Starter => HashableBusType::Starter,
-            BusType::System => HashableBusType::System,
+            1 => Ok(ProtocolVersion::V1),
+            2 => Ok(ProtocolVersion::V2),
+            _ => Err(CrateError::Settings(SettingsError::InvalidProtocolVersion(
+                value,
+            ))),
         }
     }
 }
 
-// This impl is a hack. See get_bus_types().
-impl Into<BusType> for HashableBusType {
-    fn into(self) -> BusType {
-        match self {
-            HashableBusType::Session => BusType::Session,
-            HashableBusType::Starter => BusType::Starter,
-            HashableBusType::System => BusType::System,
+// The quality of service levels at which MQTT messages may be published.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MqttQos {
+    AtMostOnce,
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+impl TryFrom<u32> for MqttQos {
+    type Error = CrateError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MqttQos::AtMostOnce),
+            1 => Ok(MqttQos::AtLeastOnce),
+            2 => Ok(MqttQos::ExactlyOnce),
+            _ => Err(CrateError::Settings(SettingsError::InvalidMqttQos(value))),
         }
     }
 }
 
-pub fn decode_bus_type_str(bus_type_str: &str) -> Result<BusType, CrateError> {
-    match bus_type_str {
-        "session" => Ok(BusType::Session),
-        "starter" => Ok(BusType::Starter),
-        "system" => Ok(BusType::System),
-        other => Err(CrateError::InvalidBusType(other.to_owned())),
+impl From<MqttQos> for u8 {
+    fn from(value: MqttQos) -> u8 {
+        match value {
+            MqttQos::AtMostOnce => 0,
+            MqttQos::AtLeastOnce => 1,
+            MqttQos::ExactlyOnce => 2,
+        }
     }
 }
 
-// Get a deduplicated list of D-Bus bus types in the given list of rules.
-pub fn get_bus_types(rules: &[Rule]) -> Vec<BusType> {
-    // The conversion from BusType → HashableBusType → BusType is a hack. It's done because this
-    // method should deduplicate BusType values, but BusType doesn't implement the traits necessary
-    // to create a HashSet<BusType>.
-    rules
-        .iter()
-        .map(|rule: &Rule| HashableBusType::from(rule.bus_type))
-        .collect::<HashSet<HashableBusType>>()
-        .into_iter()
-        .map(|hashable_bus_type: HashableBusType| hashable_bus_type.into())
-        .collect()
+// The default topic to which MQTT notifiers publish events.
+pub const DEFAULT_MQTT_TOPIC: &str = "killjoy/{{hostname}}/{{unit}}";
+
+// An MQTT broker, to which events are published.
+//
+// Each event is published to the topic rendered from `topic`, with quality of service `qos`. If
+// `client_id` isn't set, one is generated. The connection is unencrypted, so settings with a
+// `username` or `password` are rejected unless they accept sending them in plain text.
+#[derive(Clone, Debug)]
+pub struct MqttBroker {
+    pub client_id: Option<String>,
+    pub host: String,
+    pub password: Option<String>,
+    pub port: u16,
+    pub qos: MqttQos,
+    pub retain: bool,
+    pub topic: Template,
+    pub username: Option<String>,
 }
 
-// Search several paths for a settings file, in order of preference.
+impl MqttBroker {
+    // Create a broker description with default settings.
+    pub fn new(host: &str) -> Self {
+        Self {
+            client_id: None,
+            host: host.to_owned(),
+            password: None,
+            port: 1883,
+            qos: MqttQos::AtMostOnce,
+            retain: false,
+            topic: Template::new(DEFAULT_MQTT_TOPIC).expect("Default MQTT topic is invalid."),
+            username: None,
+        }
+    }
+
+    // Set the topic template. Return an error if it's invalid, or if it contains MQTT wildcards,
+    // which may not be published to.
+    pub fn set_topic(&mut self, topic: &str) -> Result<(), CrateError> {
+        if topic.contains(&['+', '#'][..]) {
+            return Err(CrateError::Settings(SettingsError::InvalidMqttTopic(
+                topic.to_owned(),
+            )));
+        }
+        self.topic = Template::new(topic)?;
+        Ok(())
+    }
+}
+
+// An out-of-tree notifier, which killjoy runs as a helper program.
 //
-// If a file is found, return its path. Otherwise, return an error describing why.
-pub fn get_load_path() -> Result<PathBuf, CrateError> {
-    let prefix = "killjoy";
-    let suffix = "settings.json";
-    BaseDirectories::with_prefix(prefix)
-        .map_err(|_| CrateError::SettingsFileNotFound(format!("{}/{}", prefix, suffix)))?
-        .find_config_file(suffix)
-        .ok_or_else(|| CrateError::SettingsFileNotFound(format!("{}/{}", prefix, suffix)))
+// For each notification, `command` is run, and is sent a JSON request on stdin, which includes
+// `options`. See the `plugin` module for the protocol.
+#[derive(Clone, Debug)]
+pub struct Plugin {
+    pub command: Vec<String>,
+    pub options: Map<String, Value>,
 }
 
-// Read the configuration file into a Settings object.
+// The default port of SNMP managers, to which traps are sent.
+pub const DEFAULT_SNMP_PORT: u16 = 162;
+
+// The default root of the OIDs in traps sent by SNMP notifiers.
 //
-// An error may be returned for one of two broad categories of reasons:
+// killjoy has no enterprise number of its own, so by default, its MIB is placed in the arc that the
+// Net-SNMP project reserves for experimentation, as described by package/KILLJOY-MIB.txt.
+pub const DEFAULT_SNMP_OID: &str = "1.3.6.1.4.1.8072.9999.9999.1";
+
+// An SNMP manager, to which events are sent as SNMPv2c traps.
 //
-// *   The file couldn't be opened. Maybe a settings file couldn't be found; or maybe a settings
-//     file was found but could not be opened.
-// *   The file contained invalid contents.
-pub fn load(path_opt: Option<&Path>) -> Result<Settings, CrateError> {
-    let handle_res = match path_opt {
-        Some(path) => File::open(path),
-        None => File::open(get_load_path()?.as_path()),
-    };
-    let handle = handle_res.map_err(CrateError::SettingsFileNotReadable)?;
-    let reader = BufReader::new(handle);
-    Settings::new(reader)
+// Each trap is sent to `host` and `port` with the given `community`. The trap's OIDs are rooted at
+// `oid`, as per package/KILLJOY-MIB.txt.
+#[derive(Clone, Debug)]
+pub struct SnmpManager {
+    pub community: String,
+    pub host: String,
+    pub oid: Vec<u32>,
+    pub port: u16,
 }
 
-#[cfg(test)]
-pub mod test_utils {
-    use crate::settings::{Expression, Rule};
-    use dbus::BusType;
-    use std::collections::HashSet;
-
-    pub fn gen_session_rule() -> Rule {
-        Rule {
-            active_states: HashSet::new(),
-            bus_type: BusType::Session,
-            expression: Expression::UnitName("".to_string()),
-            notifiers: Vec::new(),
+impl SnmpManager {
+    // Create a manager description with default settings.
+    pub fn new(host: &str) -> Self {
+        Self {
+            community: "public".to_string(),
+            host: host.to_owned(),
+            oid: parse_oid(DEFAULT_SNMP_OID).expect("Default SNMP OID is invalid."),
+            port: DEFAULT_SNMP_PORT,
         }
     }
 
-    pub fn gen_system_rule() -> Rule {
-        Rule {
-            active_states: HashSet::new(),
-            bus_type: BusType::System,
-            expression: Expression::UnitName("".to_string()),
-            notifiers: Vec::new(),
-        }
+    // Set the root of the trap's OIDs. Return an error if it isn't a valid OID.
+    pub fn set_oid(&mut self, oid: &str) -> Result<(), CrateError> {
+        self.oid = parse_oid(oid)?;
+        Ok(())
+    }
+}
+
+// Parse a dotted-decimal OID, e.g. "1.3.6.1".
+//
+// As per X.690, the first arc must be 0, 1 or 2, and if it's 0 or 1, the second arc must be less
+// than 40.
+fn parse_oid(oid: &str) -> Result<Vec<u32>, CrateError> {
+    let invalid = || CrateError::Settings(SettingsError::InvalidSnmpOid(oid.to_owned()));
+    let arcs: Vec<u32> = oid
+        .split('.')
+        .map(|arc| arc.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    match arcs[..] {
+        [first, second, ..] if first < 2 && second < 40 => Ok(arcs),
+        [2, second, ..] if second <= u32::MAX - 80 => Ok(arcs),
+        _ => Err(invalid()),
+    }
+}
+
+// The syslog facilities that syslog notifiers may log to, as per RFC 5424, in order of their codes.
+//
+// Codes 12 to 15 have no widely agreed names, and are skipped.
+const SYSLOG_FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+// The default port of remote syslog servers, for both UDP and TCP.
+pub const DEFAULT_SYSLOG_PORT: u16 = 514;
+
+// The default socket of the local syslog server.
+pub const DEFAULT_SYSLOG_PATH: &str = "/dev/log";
+
+// How messages reach a syslog server.
+//
+// `Unix` messages are sent as datagrams to the socket at the given path. `Udp` and `Tcp` messages
+// are sent to the given host and port. Over TCP, messages are framed by octet counting, as per
+// RFC 6587.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SyslogTransport {
+    Tcp(String, u16),
+    Udp(String, u16),
+    Unix(PathBuf),
+}
+
+// A syslog server, to which events are logged.
+//
+// Each event is logged as an RFC 5424 message with the given `facility`, and with a severity
+// derived from the severity with which the notifier is contacted.
+#[derive(Clone, Debug)]
+pub struct SyslogServer {
+    pub facility: u8,
+    pub transport: SyslogTransport,
+}
+
+impl SyslogServer {
+    // Create a server description with default settings.
+    pub fn new(transport: SyslogTransport) -> Self {
+        Self {
+            facility: decode_syslog_facility("daemon").expect("Default facility is invalid."),
+            transport,
+        }
+    }
+}
+
+// Get the code of the syslog facility with the given name, e.g. 3 for "daemon".
+pub fn decode_syslog_facility(facility: &str) -> Result<u8, CrateError> {
+    SYSLOG_FACILITIES
+        .iter()
+        .find(|(name, _)| *name == facility)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| {
+            CrateError::Settings(SettingsError::InvalidSyslogFacility(facility.to_owned()))
+        })
+}
+
+// An Alertmanager, to which events are posted as alerts.
+//
+// Each event is posted to the Alertmanager API at `url`. Events in which a unit enters one of the
+// `resolve_states` resolve the unit's alert, and other events fire it.
+#[derive(Clone, Debug)]
+pub struct Alertmanager {
+    pub resolve_states: Vec<ActiveState>,
+    pub url: String,
+}
+
+impl Alertmanager {
+    // Create an Alertmanager description with default settings. Return an error if `url` isn't an
+    // HTTP or HTTPS URL.
+    pub fn new(url: &str) -> Result<Self, CrateError> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(CrateError::Settings(SettingsError::InvalidAlertmanagerUrl(
+                url.to_owned(),
+            )));
+        }
+        Ok(Self {
+            resolve_states: vec![ActiveState::Active],
+            url: url.to_owned(),
+        })
+    }
+}
+
+// How a notifier is reached.
+//
+// An `Alertmanager` notifier is a Prometheus Alertmanager, and killjoy posts alerts to it. A `DBus`
+// notifier is a service with the given bus name on the given bus, and killjoy calls it. An `Mqtt`
+// notifier is a broker, and killjoy publishes events to it. A `Plugin` notifier is a helper
+// program, and killjoy runs it. An `Snmp` notifier is an SNMP manager, and killjoy sends traps to
+// it. A `Syslog` notifier is a syslog server, and killjoy logs events to it.
+#[derive(Clone, Debug)]
+pub enum NotifierTarget {
+    Alertmanager(Alertmanager),
+    DBus(BusType, String),
+    Mqtt(MqttBroker),
+    Plugin(Plugin),
+    Snmp(SnmpManager),
+    Syslog(SyslogServer),
+}
+
+// A service that may be contacted when an event of interest happens.
+//
+// When an event of interest occurs, killjoy contacts the notifier's `target`. D-Bus notifiers are
+// sent a message using `protocol_version`, timestamped using `clock`. MQTT notifiers are sent a
+// JSON object, which has the same keys as a version 2 D-Bus message. If `message_template` is set,
+// the message also includes a human-readable description of the event, rendered from that
+// template. Plugins are sent the same JSON object. SNMP notifiers are sent a trap describing the
+// event, and syslog notifiers are sent only that description, or a default one. Alertmanagers are
+// sent an alert, whose summary is that description, or a default one.
+//
+// The `mode` states whether the notifier is contacted about events as they happen, or sent digests
+// of them at the times given by `digest_schedule`, or both.
+//
+// Contacting the notifier fails if it takes longer than `timeout`, e.g. because a D-Bus call isn't
+// answered, or a broker doesn't accept a connection.
+//
+// `accept_tags` and `reject_tags` let a notifier shared by several rules be contacted about only
+// some of their events. See `accepts`.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    pub target: NotifierTarget,
+    pub accept_tags: Vec<String>,
+    pub clock: Clock,
+    pub digest_schedule: Option<Recurrence>,
+    pub message_template: Option<Template>,
+    pub mode: NotifierMode,
+    pub protocol_version: ProtocolVersion,
+    pub reject_tags: Vec<String>,
+    pub timeout: Duration,
+}
+
+// How long contacting a notifier may take by default, in seconds.
+pub const DEFAULT_NOTIFIER_TIMEOUT: u64 = 5;
+
+impl Notifier {
+    // Create a new D-Bus notifier.
+    //
+    // Return an error if any arguments are invalid.
+    pub fn new(bus_name: &str, bus_type: BusType) -> Result<Self, CrateError> {
+        BusName::new(bus_name).map_err(|_| {
+            CrateError::Settings(SettingsError::InvalidBusName(bus_name.to_owned()))
+        })?;
+        Ok(Self::with_target(NotifierTarget::DBus(
+            bus_type,
+            bus_name.to_owned(),
+        )))
+    }
+
+    // Create a new Alertmanager notifier.
+    pub fn new_alertmanager(alertmanager: Alertmanager) -> Self {
+        Self::with_target(NotifierTarget::Alertmanager(alertmanager))
+    }
+
+    // Create a new MQTT notifier.
+    pub fn new_mqtt(broker: MqttBroker) -> Self {
+        Self::with_target(NotifierTarget::Mqtt(broker))
+    }
+
+    // Create a new plugin notifier.
+    pub fn new_plugin(plugin: Plugin) -> Self {
+        Self::with_target(NotifierTarget::Plugin(plugin))
+    }
+
+    // Create a new SNMP notifier.
+    pub fn new_snmp(manager: SnmpManager) -> Self {
+        Self::with_target(NotifierTarget::Snmp(manager))
+    }
+
+    // Create a new syslog notifier.
+    pub fn new_syslog(server: SyslogServer) -> Self {
+        Self::with_target(NotifierTarget::Syslog(server))
+    }
+
+    fn with_target(target: NotifierTarget) -> Self {
+        Self {
+            target,
+            accept_tags: Vec::new(),
+            clock: Clock::Realtime,
+            digest_schedule: None,
+            message_template: None,
+            mode: NotifierMode::Realtime,
+            protocol_version: ProtocolVersion::V1,
+            reject_tags: Vec::new(),
+            timeout: Duration::from_secs(DEFAULT_NOTIFIER_TIMEOUT),
+        }
+    }
+
+    // Tell whether this notifier may be contacted about an event with the given tags, i.e. the
+    // names of the rules which matched it.
+    //
+    // If `accept_tags` is non-empty, the event must have one of them. It mustn't have any of the
+    // `reject_tags`.
+    pub fn accepts(&self, tags: &[String]) -> bool {
+        (self.accept_tags.is_empty() || tags.iter().any(|tag| self.accept_tags.contains(tag)))
+            && !tags.iter().any(|tag| self.reject_tags.contains(tag))
+    }
+
+    // Get the bus and bus name of a D-Bus notifier, or `None` if this isn't a D-Bus notifier.
+    pub fn get_dbus_address(&self) -> Option<(BusType, BusName<'_>)> {
+        match &self.target {
+            NotifierTarget::DBus(bus_type, bus_name) => Some((
+                *bus_type,
+                BusName::new(&bus_name[..]).expect(
+                    "bus_name is invalid. new() should have caught this. Please contact a developer.",
+                ),
+            )),
+            NotifierTarget::Alertmanager(_)
+            | NotifierTarget::Mqtt(_)
+            | NotifierTarget::Plugin(_)
+            | NotifierTarget::Snmp(_)
+            | NotifierTarget::Syslog(_) => None,
+        }
+    }
+
+    // Describe where this notifier lives, e.g. "name.jerebear.Foo" or "mqtt://localhost:1883".
+    pub fn describe(&self) -> String {
+        match &self.target {
+            NotifierTarget::Alertmanager(alertmanager) => alertmanager.url.to_owned(),
+            NotifierTarget::DBus(_, bus_name) => bus_name.to_owned(),
+            NotifierTarget::Mqtt(broker) => format!("mqtt://{}:{}", broker.host, broker.port),
+            NotifierTarget::Plugin(plugin) => format!("plugin:{}", plugin.command[0]),
+            NotifierTarget::Snmp(manager) => format!("snmp://{}:{}", manager.host, manager.port),
+            NotifierTarget::Syslog(server) => match &server.transport {
+                SyslogTransport::Tcp(host, port) => format!("syslog+tcp://{}:{}", host, port),
+                SyslogTransport::Udp(host, port) => format!("syslog+udp://{}:{}", host, port),
+                SyslogTransport::Unix(path) => format!("syslog://{}", path.display()),
+            },
+        }
+    }
+}
+
+impl TryFrom<SerdeNotifier> for Notifier {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeNotifier) -> Result<Self, Self::Error> {
+        let mut notifier = match value.notifier_type.as_deref().unwrap_or("dbus") {
+            "alertmanager" => {
+                let url = get_secret("url", &value.url, &value.url_credential)?.ok_or(
+                    CrateError::Settings(SettingsError::MissingNotifierKey("alertmanager", "url")),
+                )?;
+                let mut alertmanager = Alertmanager::new(&url)?;
+                if let Some(resolve_states) = &value.resolve_states {
+                    alertmanager.resolve_states = resolve_states
+                        .iter()
+                        .map(|state| parse_active_state(state))
+                        .collect::<Result<_, _>>()?;
+                }
+                Notifier::new_alertmanager(alertmanager)
+            }
+            "dbus" => {
+                let bus_name = value.bus_name.as_deref().ok_or(CrateError::Settings(
+                    SettingsError::MissingNotifierKey("dbus", "bus_name"),
+                ))?;
+                let bus_type = value.bus_type.as_deref().ok_or(CrateError::Settings(
+                    SettingsError::MissingNotifierKey("dbus", "bus_type"),
+                ))?;
+                Notifier::new(bus_name, decode_bus_type_str(bus_type)?)?
+            }
+            "mqtt" => {
+                let host = value.host.as_deref().ok_or(CrateError::Settings(
+                    SettingsError::MissingNotifierKey("mqtt", "host"),
+                ))?;
+                let mut broker = MqttBroker::new(host);
+                broker.client_id = value.client_id.to_owned();
+                broker.password =
+                    get_secret("password", &value.password, &value.password_credential)?;
+                broker.username = value.username.to_owned();
+                if (broker.username.is_some() || broker.password.is_some())
+                    && !value.plaintext_credentials
+                {
+                    return Err(CrateError::Settings(
+                        SettingsError::PlaintextMqttCredentials(host.to_owned()),
+                    ));
+                }
+                if let Some(port) = value.port {
+                    broker.port = port;
+                }
+                if let Some(qos) = value.qos {
+                    broker.qos = MqttQos::try_from(qos)?;
+                }
+                broker.retain = value.retain;
+                if let Some(topic) = &value.topic {
+                    broker.set_topic(topic)?;
+                }
+                Notifier::new_mqtt(broker)
+            }
+            "plugin" => {
+                let command = match &value.command {
+                    Some(command) if !command.is_empty() => command.to_owned(),
+                    _ => {
+                        return Err(CrateError::Settings(SettingsError::MissingNotifierKey(
+                            "plugin", "command",
+                        )))
+                    }
+                };
+                Notifier::new_plugin(Plugin {
+                    command,
+                    options: value.options.to_owned().unwrap_or_default(),
+                })
+            }
+            "snmp" => {
+                let host = value.host.as_deref().ok_or(CrateError::Settings(
+                    SettingsError::MissingNotifierKey("snmp", "host"),
+                ))?;
+                let mut manager = SnmpManager::new(host);
+                if let Some(community) =
+                    get_secret("community", &value.community, &value.community_credential)?
+                {
+                    manager.community = community;
+                }
+                if let Some(oid) = &value.oid {
+                    manager.set_oid(oid)?;
+                }
+                if let Some(port) = value.port {
+                    manager.port = port;
+                }
+                Notifier::new_snmp(manager)
+            }
+            "syslog" => {
+                let get_remote = || -> Result<(String, u16), CrateError> {
+                    let host = value.host.as_deref().ok_or(CrateError::Settings(
+                        SettingsError::MissingNotifierKey("syslog", "host"),
+                    ))?;
+                    Ok((host.to_owned(), value.port.unwrap_or(DEFAULT_SYSLOG_PORT)))
+                };
+                let transport = match value.transport.as_deref().unwrap_or("unix") {
+                    "tcp" => {
+                        let (host, port) = get_remote()?;
+                        SyslogTransport::Tcp(host, port)
+                    }
+                    "udp" => {
+                        let (host, port) = get_remote()?;
+                        SyslogTransport::Udp(host, port)
+                    }
+                    "unix" => SyslogTransport::Unix(
+                        value
+                            .path
+                            .to_owned()
+                            .unwrap_or_else(|| PathBuf::from(DEFAULT_SYSLOG_PATH)),
+                    ),
+                    other => {
+                        return Err(CrateError::Settings(SettingsError::InvalidSyslogTransport(
+                            other.to_string(),
+                        )))
+                    }
+                };
+                let mut server = SyslogServer::new(transport);
+                if let Some(facility) = &value.facility {
+                    server.facility = decode_syslog_facility(facility)?;
+                }
+                Notifier::new_syslog(server)
+            }
+            other => {
+                return Err(CrateError::Settings(SettingsError::InvalidNotifierType(
+                    other.to_string(),
+                )))
+            }
+        };
+        notifier.accept_tags = value.accept_tags;
+        notifier.reject_tags = value.reject_tags;
+        if let Some(clock) = &value.clock {
+            notifier.clock = Clock::try_from(&clock[..])?;
+        }
+        notifier.message_template = value
+            .message_template
+            .as_deref()
+            .map(Template::new)
+            .transpose()?;
+        notifier.protocol_version = ProtocolVersion::try_from(value.protocol_version)?;
+        notifier.digest_schedule = value
+            .digest_schedule
+            .as_deref()
+            .map(Recurrence::try_from)
+            .transpose()?;
+        if let Some(mode) = &value.mode {
+            notifier.mode = NotifierMode::try_from(&mode[..])?;
+        }
+        match value.timeout {
+            Some(0) => return Err(CrateError::Settings(SettingsError::InvalidNotifierTimeout)),
+            Some(timeout) => notifier.timeout = Duration::from_secs(timeout),
+            None => {}
+        }
+        if notifier.mode != NotifierMode::Realtime && notifier.digest_schedule.is_none() {
+            return Err(CrateError::Settings(SettingsError::MissingDigestSchedule(
+                notifier.describe(),
+            )));
+        }
+        Ok(notifier)
+    }
+}
+
+// Units to watch, and notifiers to contact when any of them enter a state of interest.
+//
+// Upon startup, killjoy will connect to `bus_type`. It will watch all units whose name matches
+// `expression`. Whenever one of those units' ActiveState property transitions to one of the
+// `active_states`, or its LoadState property transitions to one of the `load_states`, it will
+// contact `notifiers`. If `include_dependents` is set, the notifiers are also told which units
+// (transitively) depend on the unit. If `journal_lines` is non-zero, the notifiers are also sent
+// that many of the unit's most recent log messages. Whenever the unit's ActiveState transitions to
+// one of the `active_states`, the applicable `actions` are taken, in order.
+//
+// A rule may have a `name` and `labels`, which are passed to notifiers, along with its `severity`.
+// Notifiers may use them to route or tag notifications, e.g. according to a "team" label. A rule's
+// `payload` is passed to version 2 notifiers verbatim, e.g. to link to a runbook.
+//
+// While the current time is within any of the `suppress` windows, the rule's notifications are
+// dropped, or sent with `suppress_severity` if it is set. Actions are taken regardless.
+//
+// The `notifier_policy` states whether all `notifiers` are contacted, or whether they are tried in
+// order until one is reached.
+//
+// If `for_duration` is set, the rule only fires once a unit has stayed in one of the
+// `active_states` for that long. If the unit changes state meanwhile, e.g. because it recovered,
+// the rule doesn't fire at all.
+//
+// If `startup_grace_period` is set, the rule's notifications are dropped until that long after the
+// service manager started, e.g. after boot, as many units flap while the host starts. Actions are
+// taken regardless.
+//
+// If `group_by` is set, the rule's notifications about units in the same group, e.g. the same
+// slice, are held back for `group_window` after the first of them, and then sent as one.
+//
+// If the rule has a `filter`, it's run each time the rule matches a state change, and may veto the
+// notification or add labels to it. Actions are taken regardless.
+//
+// If `unit_file_states` is non-empty, the unit files of matching units are periodically listed, and
+// `notifiers` are contacted whenever a unit file's state transitions to one of `unit_file_states`.
+// This catches units which aren't loaded, and so never change state.
+//
+// If `properties` is non-empty, the listed properties of matching units are watched, and
+// `notifiers` are contacted whenever one of the property conditions is met. If
+// `property_sample_interval` is set, matching units' properties are sampled that often instead
+// of as often as the settings' `polling` says. If `loop_timeout` is set, the bus watcher for the
+// rule's bus waits for messages at most that long, if that's less than it otherwise would, so that
+// e.g. `for_duration` is honoured more precisely. See `Polling`.
+//
+// If `treat_unsuccessful_inactive_as_failed` is set, a unit which becomes `inactive` although its
+// last run was unsuccessful, e.g. a oneshot service with `RemainAfterExit` whose command failed, is
+// matched as if it had become `failed`. Whether a run was unsuccessful is told by the `Result`
+// property of the unit's type-specific interface.
+//
+// If the rule's `expression` is a `Manager` expression, the manager's SystemState property is
+// watched instead of units, and `notifiers` are contacted whenever it transitions to one of the
+// `system_states`.
+//
+// If the rule's `expression` is a `PortableImage` expression, the image is periodically looked up,
+// and `notifiers` are contacted whenever it transitions to one of the `portable_states`, e.g. when
+// it's attached or detached.
+//
+// If `verify_mount_point` is set, the mount points of matching mount units which are `active` are
+// periodically looked up in the kernel's mount table, and `notifiers` are contacted whenever one is
+// missing. This catches mounts which systemd believes are active after they've gone away.
+//
+// If `sub_states` is non-empty, the rule only matches units whose SubState is one of the
+// `sub_states`, and `notifiers` are also contacted whenever a unit's SubState transitions to one of
+// them while its ActiveState is one of the `active_states`, e.g. when a service goes from "running"
+// to "exited" while remaining "active". The rule must list `active_states` too.
+//
+// If `ignore_transient` is set, units whose Transient property is true, i.e. units created at
+// runtime such as session scopes or `systemd-run` services, don't match the rule.
+//
+// If `on_appear` is set, `notifiers` are contacted whenever a matching unit is loaded by systemd,
+// whatever its state, unless it's one of the `allowed_units`. This catches unexpected units on
+// hosts where only known units should run.
+//
+// If the settings' `rule_evaluation` is `FirstMatch`, only the matching rule with the highest
+// `priority` fires for each state change. Otherwise, `priority` is ignored.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub actions: Vec<Action>,
+    pub active_states: HashSet<ActiveState>,
+    pub allowed_units: HashSet<String>,
+    pub bus_type: BusType,
+    pub expression: Expression,
+    pub filter: Option<Filter>,
+    pub for_duration: Option<Duration>,
+    pub group_by: Option<GroupBy>,
+    pub group_window: Duration,
+    pub ignore_transient: bool,
+    pub include_dependents: bool,
+    pub journal_lines: u32,
+    pub labels: HashMap<String, String>,
+    pub load_states: HashSet<LoadState>,
+    pub loop_timeout: Option<Duration>,
+    pub name: Option<String>,
+    pub notifier_policy: NotifierPolicy,
+    pub notifiers: Vec<String>,
+    pub on_appear: bool,
+    pub payload: HashMap<String, String>,
+    pub portable_states: HashSet<PortableState>,
+    pub priority: u32,
+    pub properties: Vec<PropertyCondition>,
+    pub property_sample_interval: Option<Duration>,
+    pub severity: Severity,
+    pub startup_grace_period: Option<Duration>,
+    pub sub_states: HashSet<String>,
+    pub suppress: Vec<Window>,
+    pub suppress_severity: Option<Severity>,
+    pub system_states: HashSet<SystemState>,
+    pub treat_unsuccessful_inactive_as_failed: bool,
+    pub unit_file_states: HashSet<UnitFileState>,
+    pub verify_mount_point: bool,
+}
+
+impl Rule {
+    // Describe this rule for a human, by name if it has one, or else by expression.
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("Rule \"{}\"", name),
+            None => format!("Rule for '{}'", self.expression.as_str()),
+        }
+    }
+
+    // Tell whether notifications are dropped because the service manager started less than the
+    // startup grace period ago. `uptime` is how long ago it started.
+    pub fn is_in_startup_grace_period(&self, uptime: Duration) -> bool {
+        self.startup_grace_period
+            .is_some_and(|grace_period| uptime < grace_period)
+    }
+
+    // Get the severity with which to notify at the given time, or `None` if notifications are
+    // suppressed.
+    pub fn get_severity_at(&self, time: LocalTime) -> Option<Severity> {
+        if self.suppress.iter().any(|window| window.contains(time)) {
+            self.suppress_severity
+        } else {
+            Some(self.severity)
+        }
+    }
+}
+
+impl TryFrom<SerdeRule> for Rule {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeRule) -> Result<Self, Self::Error> {
+        let active_state_strings = value.active_states.unwrap_or_default();
+        if active_state_strings.is_empty()
+            && value.load_states.is_empty()
+            && value.unit_file_states.is_empty()
+            && value.properties.is_empty()
+            && value.system_states.is_empty()
+            && value.portable_states.is_empty()
+            && !value.verify_mount_point
+            && !value.on_appear
+        {
+            return Err(CrateError::Settings(SettingsError::MissingStates(
+                value.expression,
+            )));
+        }
+        let mut active_states: HashSet<ActiveState> = HashSet::new();
+        for active_state_string in &active_state_strings {
+            active_states.extend(parse_active_states(active_state_string)?);
+        }
+        let active_states = active_states;
+
+        for sub_state in &value.sub_states {
+            if !unit::is_valid_state_name(sub_state) {
+                return Err(CrateError::Settings(SettingsError::InvalidSubState(
+                    sub_state.to_owned(),
+                )));
+            }
+        }
+        if !value.sub_states.is_empty() && active_states.is_empty() {
+            return Err(CrateError::Settings(
+                SettingsError::MissingActiveStatesForSubStates(value.expression),
+            ));
+        }
+
+        let mut load_states: HashSet<LoadState> = HashSet::new();
+        for load_state_string in &value.load_states {
+            load_states.insert(LoadState::try_from(&load_state_string[..])?);
+        }
+        let load_states = load_states;
+
+        let mut unit_file_states: HashSet<UnitFileState> = HashSet::new();
+        for unit_file_state_string in &value.unit_file_states {
+            unit_file_states.insert(UnitFileState::try_from(&unit_file_state_string[..])?);
+        }
+        let unit_file_states = unit_file_states;
+
+        let mut system_states: HashSet<SystemState> = HashSet::new();
+        for system_state_string in &value.system_states {
+            system_states.insert(SystemState::try_from(&system_state_string[..])?);
+        }
+        let system_states = system_states;
+
+        let mut portable_states: HashSet<PortableState> = HashSet::new();
+        for portable_state_string in &value.portable_states {
+            portable_states.insert(PortableState::try_from(&portable_state_string[..])?);
+        }
+        let portable_states = portable_states;
+
+        let bus_type = match &value.bus_type {
+            Some(bus_type) => decode_bus_type_str(bus_type)?,
+            None => {
+                return Err(CrateError::Settings(SettingsError::MissingBusType(
+                    value.expression,
+                )))
+            }
+        };
+
+        let expression: Expression = match &value.expression_type[..] {
+            "manager" => match &value.expression[..] {
+                "SystemState" => Ok(Expression::Manager(value.expression.to_owned())),
+                other => Err(CrateError::Settings(SettingsError::InvalidManagerProperty(
+                    other.to_owned(),
+                ))),
+            },
+            "portable image" => Ok(Expression::PortableImage(value.expression.to_owned())),
+            "regex" => Regex::new(&value.expression[..])
+                .map(Expression::Regex)
+                .map_err(|err| CrateError::Settings(SettingsError::InvalidRegex(err))),
+            "unit name" => Ok(Expression::UnitName(value.expression.to_owned())),
+            "unit template" => {
+                if is_valid_unit_template(&value.expression) {
+                    Ok(Expression::UnitTemplate(value.expression.to_owned()))
+                } else {
+                    Err(CrateError::Settings(SettingsError::InvalidUnitTemplate(
+                        value.expression.to_owned(),
+                    )))
+                }
+            }
+            "unit type" => Ok(Expression::UnitType(value.expression.to_owned())),
+            other => Err(CrateError::Settings(SettingsError::InvalidExpressionType(
+                other.to_owned(),
+            ))),
+        }?;
+
+        let filter = value.filter.as_deref().map(Filter::try_from).transpose()?;
+
+        let mut actions: Vec<Action> = Vec::new();
+        if let Some(action) = &value.action {
+            actions.push(Action::from_shorthand(
+                action,
+                value.action_max_attempts,
+                value.action_window,
+            )?);
+        }
+        for serde_action in value.actions.into_iter() {
+            actions.push(Action::try_from(serde_action)?);
+        }
+        let actions = actions; // make immutable
+
+        if let Some(name) = &value.name {
+            if name.trim().is_empty() {
+                return Err(CrateError::Settings(SettingsError::InvalidRuleName(
+                    name.to_owned(),
+                )));
+            }
+        }
+
+        for label_name in value.labels.keys() {
+            if !is_valid_label_name(label_name) {
+                return Err(CrateError::Settings(SettingsError::InvalidLabelName(
+                    label_name.to_owned(),
+                )));
+            }
+        }
+
+        let group_by = value
+            .group_by
+            .as_deref()
+            .map(GroupBy::try_from)
+            .transpose()?;
+        let group_window = match value.group_window {
+            Some(0) => return Err(CrateError::Settings(SettingsError::InvalidGroupWindow)),
+            Some(group_window) => Duration::from_secs(group_window),
+            None => Duration::from_secs(DEFAULT_GROUP_WINDOW),
+        };
+
+        let notifier_policy = match &value.notifier_policy {
+            Some(notifier_policy) => NotifierPolicy::try_from(&notifier_policy[..])?,
+            None => NotifierPolicy::All,
+        };
+
+        let notifiers = match value.notifiers {
+            Some(notifiers) => notifiers,
+            None => {
+                return Err(CrateError::Settings(SettingsError::MissingNotifiers(
+                    value.expression,
+                )))
+            }
+        };
+
+        let mut properties: Vec<PropertyCondition> = Vec::new();
+        for serde_property in value.properties.into_iter() {
+            properties.push(PropertyCondition::try_from(serde_property)?);
+        }
+        let properties = properties; // make immutable
+
+        let severity = match &value.severity {
+            Some(severity) => Severity::try_from(&severity[..])?,
+            None => Severity::Warning,
+        };
+
+        let mut suppress: Vec<Window> = Vec::new();
+        for window_str in &value.suppress {
+            suppress.push(Window::try_from(&window_str[..])?);
+        }
+        let suppress = suppress; // make immutable
+
+        let suppress_severity = value
+            .suppress_severity
+            .as_deref()
+            .map(Severity::try_from)
+            .transpose()?;
+
+        let loop_timeout = match value.loop_timeout {
+            Some(0) => {
+                return Err(CrateError::Settings(SettingsError::InvalidPollingInterval(
+                    "loop_timeout",
+                )))
+            }
+            loop_timeout => loop_timeout.map(Duration::from_millis),
+        };
+        let property_sample_interval = value
+            .property_sample_interval
+            .map(|interval| get_polling_interval("property_sample_interval", interval))
+            .transpose()?;
+
+        Ok(Rule {
+            actions,
+            active_states,
+            allowed_units: value.allowed_units.into_iter().collect(),
+            bus_type,
+            expression,
+            filter,
+            for_duration: value
+                .for_duration
+                .filter(|for_duration| *for_duration > 0)
+                .map(Duration::from_secs),
+            group_by,
+            group_window,
+            ignore_transient: value.ignore_transient.unwrap_or(false),
+            include_dependents: value.include_dependents,
+            journal_lines: value.journal_lines,
+            labels: value.labels,
+            load_states,
+            loop_timeout,
+            name: value.name,
+            notifier_policy,
+            notifiers,
+            on_appear: value.on_appear,
+            payload: value.payload,
+            portable_states,
+            priority: value.priority,
+            properties,
+            property_sample_interval,
+            severity,
+            startup_grace_period: value.startup_grace_period.map(Duration::from_secs),
+            sub_states: value.sub_states.into_iter().collect(),
+            suppress,
+            suppress_severity,
+            system_states,
+            treat_unsuccessful_inactive_as_failed: value.treat_unsuccessful_inactive_as_failed,
+            unit_file_states,
+            verify_mount_point: value.verify_mount_point,
+        })
+    }
+}
+
+// A rule's filter, a script which decides whether to notify about each state change.
+//
+// The script is compiled when the settings are loaded, so that syntax errors are reported early.
+// Filters are only available if killjoy is built with the `scripting` feature. See the `script`
+// module.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    pub source: String,
+    #[cfg(feature = "scripting")]
+    pub ast: rhai::AST,
+}
+
+impl TryFrom<&str> for Filter {
+    type Error = CrateError;
+
+    #[cfg(feature = "scripting")]
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(Filter {
+            source: value.to_owned(),
+            ast: script::compile(value)?,
+        })
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn try_from(_value: &str) -> Result<Self, Self::Error> {
+        Err(CrateError::Settings(SettingsError::FilterNotSupported))
+    }
+}
+
+// What to do once the system has finished booting.
+//
+// When systemd on the `bus_type` bus says that it has finished starting up, `notifiers` are sent a
+// summary of the units which have failed, and of the units matched by rules which aren't active.
+#[derive(Clone, Debug)]
+pub struct BootSummary {
+    pub bus_type: BusType,
+    pub notifiers: Vec<String>,
+}
+
+impl TryFrom<SerdeBootSummary> for BootSummary {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeBootSummary) -> Result<Self, Self::Error> {
+        let bus_type = match &value.bus_type {
+            Some(bus_type) => decode_bus_type_str(bus_type)?,
+            None => BusType::System,
+        };
+        Ok(BootSummary {
+            bus_type,
+            notifiers: value.notifiers,
+        })
+    }
+}
+
+// A cap on the units which each bus watcher keeps track of.
+//
+// Once a bus watcher keeps track of `max_units` units, and another comes along, it forgets the
+// least recently updated unit whose state no rule is interested in, to make room. `notifiers` are
+// warned the first time this happens.
+#[derive(Clone, Debug)]
+pub struct WatchLimit {
+    pub max_units: usize,
+    pub notifiers: Vec<String>,
+}
+
+impl From<SerdeWatchLimit> for WatchLimit {
+    fn from(value: SerdeWatchLimit) -> Self {
+        WatchLimit {
+            max_units: value.max_units,
+            notifiers: value.notifiers,
+        }
+    }
+}
+
+// The formats in which the event log may be written.
+//
+// With `Csv`, the log starts with a header line, and each event is a row of comma-separated values.
+// With `Jsonl`, each event is a JSON object on its own line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventLogFormat {
+    Csv,
+    Jsonl,
+}
+
+impl TryFrom<&str> for EventLogFormat {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "csv" => Ok(EventLogFormat::Csv),
+            "jsonl" => Ok(EventLogFormat::Jsonl),
+            _ => Err(CrateError::Settings(SettingsError::InvalidEventLogFormat(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+impl From<EventLogFormat> for String {
+    fn from(value: EventLogFormat) -> String {
+        match value {
+            EventLogFormat::Csv => "csv".to_string(),
+            EventLogFormat::Jsonl => "jsonl".to_string(),
+        }
+    }
+}
+
+// A file to which every observed state transition is appended.
+//
+// Once appending an event would make the file larger than `max_size` bytes, the file is rotated.
+#[derive(Clone, Debug)]
+pub struct EventLog {
+    pub format: EventLogFormat,
+    pub max_size: u64,
+    pub path: PathBuf,
+}
+
+// How large the event log may grow by default, in bytes, before it's rotated.
+pub const DEFAULT_EVENT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+impl TryFrom<SerdeEventLog> for EventLog {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeEventLog) -> Result<Self, Self::Error> {
+        let format = match &value.format {
+            Some(format) => EventLogFormat::try_from(&format[..])?,
+            None => EventLogFormat::Jsonl,
+        };
+        Ok(EventLog {
+            format,
+            max_size: value.max_size,
+            path: PathBuf::from(value.path),
+        })
+    }
+}
+
+// An SQLite database in which state transitions and attempts to contact notifiers are recorded.
+//
+// If `path` is `None`, the database is placed in `$XDG_STATE_HOME/killjoy`. The event store is only
+// available if killjoy is built with the `sqlite` feature.
+#[derive(Clone, Debug)]
+pub struct EventStore {
+    pub path: Option<PathBuf>,
+}
+
+impl From<SerdeEventStore> for EventStore {
+    fn from(value: SerdeEventStore) -> Self {
+        EventStore {
+            path: value.path.map(PathBuf::from),
+        }
+    }
+}
+
+// Where rules declared in unit files are discovered. See `discovery`.
+//
+// Loaded units on the `bus_type` bus are periodically checked for `X-KilljoyNotify=` directives,
+// and a rule is added for each one.
+#[derive(Clone, Debug)]
+pub struct Discovery {
+    pub bus_type: BusType,
+}
+
+impl TryFrom<SerdeDiscovery> for Discovery {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeDiscovery) -> Result<Self, Self::Error> {
+        let bus_type = match &value.bus_type {
+            Some(bus_type) => decode_bus_type_str(bus_type)?,
+            None => BusType::System,
+        };
+        Ok(Discovery { bus_type })
+    }
+}
+
+// A Unix domain socket on which killjoy accepts control requests. See `control`.
+//
+// If `path` is `None`, the socket is placed in `$XDG_RUNTIME_DIR/killjoy`. If killjoy is socket
+// activated, the socket it's passed is used instead.
+#[derive(Clone, Debug)]
+pub struct ControlSocket {
+    pub path: Option<PathBuf>,
+}
+
+impl From<SerdeControlSocket> for ControlSocket {
+    fn from(value: SerdeControlSocket) -> Self {
+        ControlSocket {
+            path: value.path.map(PathBuf::from),
+        }
+    }
+}
+
+// How often the heartbeat URL is requested by default, in seconds.
+pub const DEFAULT_HEARTBEAT_INTERVAL: u64 = 60;
+
+// A URL to request periodically while killjoy is healthy, e.g. a healthchecks.io check.
+//
+// killjoy is healthy while the watcher of every bus it monitors is connected and responsive. If
+// killjoy dies or loses a bus, the requests stop, and the service behind `url` raises the alarm.
+// `url` is requested every `interval`.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub url: String,
+}
+
+impl Heartbeat {
+    // Create a new heartbeat. Return an error if `url` isn't an HTTP or HTTPS URL, or if `interval`
+    // is zero.
+    pub fn new(url: &str, interval: Duration) -> Result<Self, CrateError> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(CrateError::Settings(SettingsError::InvalidHeartbeatUrl(
+                url.to_owned(),
+            )));
+        }
+        if interval.is_zero() {
+            return Err(CrateError::Settings(
+                SettingsError::InvalidHeartbeatInterval,
+            ));
+        }
+        Ok(Self {
+            interval,
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl TryFrom<SerdeHeartbeat> for Heartbeat {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeHeartbeat) -> Result<Self, Self::Error> {
+        let url = get_secret("url", &value.url, &value.url_credential)?.ok_or_else(|| {
+            CrateError::Settings(SettingsError::MissingSettingsKey(
+                "/heartbeat/url".to_string(),
+            ))
+        })?;
+        Heartbeat::new(&url, Duration::from_secs(value.interval))
+    }
+}
+
+// How the host is identified to notifiers.
+//
+// `hostname` replaces the host's name, e.g. with a fully qualified one. If `include_machine_id` is
+// set, the host's machine ID is sent too, which stays the same if the host is renamed. `tags` are
+// sent verbatim, e.g. to tell which site or cluster the host belongs to. Tag names are restricted
+// like label names, so that message templates may reference them as `tags.NAME`.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    pub hostname: Option<String>,
+    pub include_machine_id: bool,
+    pub tags: HashMap<String, String>,
+}
+
+impl TryFrom<SerdeIdentity> for Identity {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeIdentity) -> Result<Self, Self::Error> {
+        if let Some(hostname) = &value.hostname {
+            if hostname.is_empty() || hostname.contains(char::is_whitespace) {
+                return Err(CrateError::Settings(SettingsError::InvalidHostname(
+                    hostname.to_owned(),
+                )));
+            }
+        }
+        for tag_name in value.tags.keys() {
+            if !is_valid_label_name(tag_name) {
+                return Err(CrateError::Settings(SettingsError::InvalidLabelName(
+                    tag_name.to_owned(),
+                )));
+            }
+        }
+        Ok(Self {
+            hostname: value.hostname,
+            include_machine_id: value.include_machine_id,
+            tags: value.tags,
+        })
+    }
+}
+
+// How long killjoy waits for systemd to answer a call by default, in milliseconds.
+pub const DEFAULT_SYSTEMD_CALL_TIMEOUT: u64 = 1000;
+
+// How long killjoy waits before retrying a call to systemd by default, in milliseconds.
+pub const DEFAULT_SYSTEMD_CALL_RETRY_DELAY: u64 = 100;
+
+// The classes of calls that killjoy makes to systemd. Each class has its own timeout.
+//
+// `List` calls list every unit or unit file, and may be slow on hosts with many units. `Unit`
+// calls look up or act on a single unit, or on the manager itself. `Properties` calls read a
+// unit's properties.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SystemdCallClass {
+    List,
+    Properties,
+    Unit,
+}
+
+// How killjoy calls systemd.
+//
+// Calls time out after the timeout for their class. While killjoy enumerates units at startup,
+// calls which fail transiently, e.g. because they time out, are retried up to `retries` times,
+// `retry_delay` apart.
+#[derive(Clone, Debug)]
+pub struct SystemdCalls {
+    pub list_timeout: Duration,
+    pub properties_timeout: Duration,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub unit_timeout: Duration,
+}
+
+impl SystemdCalls {
+    // Get the timeout for calls of the given class.
+    pub fn get_timeout(&self, class: SystemdCallClass) -> Duration {
+        match class {
+            SystemdCallClass::List => self.list_timeout,
+            SystemdCallClass::Properties => self.properties_timeout,
+            SystemdCallClass::Unit => self.unit_timeout,
+        }
+    }
+}
+
+impl Default for SystemdCalls {
+    fn default() -> Self {
+        let timeout = Duration::from_millis(DEFAULT_SYSTEMD_CALL_TIMEOUT);
+        Self {
+            list_timeout: timeout,
+            properties_timeout: timeout,
+            retries: 0,
+            retry_delay: Duration::from_millis(DEFAULT_SYSTEMD_CALL_RETRY_DELAY),
+            unit_timeout: timeout,
+        }
+    }
+}
+
+impl TryFrom<SerdeSystemdCalls> for SystemdCalls {
+    type Error = CrateError;
+
+    // Class timeouts default to `timeout`. Return an error if any timeout is zero.
+    fn try_from(value: SerdeSystemdCalls) -> Result<Self, Self::Error> {
+        let get_timeout = |timeout: Option<u64>| match timeout.unwrap_or(value.timeout) {
+            0 => Err(CrateError::Settings(
+                SettingsError::InvalidSystemdCallTimeout,
+            )),
+            timeout => Ok(Duration::from_millis(timeout)),
+        };
+        Ok(Self {
+            list_timeout: get_timeout(value.list_timeout)?,
+            properties_timeout: get_timeout(value.properties_timeout)?,
+            retries: value.retries,
+            retry_delay: Duration::from_millis(value.retry_delay),
+            unit_timeout: get_timeout(value.unit_timeout)?,
+        })
+    }
+}
+
+// How often the properties of watched units are sampled by default, in seconds. See `Polling`.
+pub const DEFAULT_PROPERTY_SAMPLE_INTERVAL: u64 = 10;
+
+// How often unit files are listed by default, in seconds. See `Polling`.
+pub const DEFAULT_UNIT_FILE_SCAN_INTERVAL: u64 = 60;
+
+// How often the mount points of active mount units are looked up by default, in seconds. See
+// `Polling`.
+pub const DEFAULT_MOUNT_CHECK_INTERVAL: u64 = 30;
+
+// How often killjoy polls for changes which systemd doesn't signal.
+//
+// Bus watchers wait up to `loop_timeout` for a message before doing their periodic work, e.g.
+// firing rules whose `for_duration` has passed. If it's `None`, the `loop_timeout` of the
+// `RunOptions` is used. A rule with a lower `loop_timeout` lowers it for the bus it watches.
+//
+// Watched units' properties are sampled every `property_sample_interval`, if any rule has property
+// conditions, as is the system state, if any rule watches it. A rule may set its own
+// `property_sample_interval`, and each unit is sampled at the lowest interval of the rules
+// matching it. Unit files are listed every `unit_file_scan_interval`, if any rule is interested in
+// unit file states, and mount points are looked up every `mount_check_interval`, if any rule
+// verifies them.
+#[derive(Clone, Debug)]
+pub struct Polling {
+    pub loop_timeout: Option<Duration>,
+    pub mount_check_interval: Duration,
+    pub property_sample_interval: Duration,
+    pub unit_file_scan_interval: Duration,
+}
+
+impl Polling {
+    // Get the loop timeout in milliseconds, or `default` if none is set.
+    pub fn get_loop_timeout(&self, default: u32) -> u32 {
+        self.loop_timeout.map_or(default, duration_to_millis)
+    }
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Self {
+            loop_timeout: None,
+            mount_check_interval: Duration::from_secs(DEFAULT_MOUNT_CHECK_INTERVAL),
+            property_sample_interval: Duration::from_secs(DEFAULT_PROPERTY_SAMPLE_INTERVAL),
+            unit_file_scan_interval: Duration::from_secs(DEFAULT_UNIT_FILE_SCAN_INTERVAL),
+        }
+    }
+}
+
+impl TryFrom<SerdePolling> for Polling {
+    type Error = CrateError;
+
+    // Return an error if any interval is zero, as killjoy would then poll constantly.
+    fn try_from(value: SerdePolling) -> Result<Self, Self::Error> {
+        let loop_timeout = match value.loop_timeout {
+            Some(0) => {
+                return Err(CrateError::Settings(SettingsError::InvalidPollingInterval(
+                    "loop_timeout",
+                )))
+            }
+            loop_timeout => loop_timeout.map(Duration::from_millis),
+        };
+        Ok(Self {
+            loop_timeout,
+            mount_check_interval: get_polling_interval(
+                "mount_check_interval",
+                value.mount_check_interval,
+            )?,
+            property_sample_interval: get_polling_interval(
+                "property_sample_interval",
+                value.property_sample_interval,
+            )?,
+            unit_file_scan_interval: get_polling_interval(
+                "unit_file_scan_interval",
+                value.unit_file_scan_interval,
+            )?,
+        })
+    }
+}
+
+// Convert a polling interval from a settings file to a duration in seconds, or return an error if
+// it's zero. `key` names the setting.
+fn get_polling_interval(key: &'static str, interval: u64) -> Result<Duration, CrateError> {
+    match interval {
+        0 => Err(CrateError::Settings(SettingsError::InvalidPollingInterval(
+            key,
+        ))),
+        interval => Ok(Duration::from_secs(interval)),
+    }
+}
+
+// Convert a duration to milliseconds, saturating at `u32::MAX`, as used for loop timeouts.
+pub fn duration_to_millis(duration: Duration) -> u32 {
+    u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)
+}
+
+// How many events are remembered by default. See `Settings`.
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+// How often units are reconciled by default, in seconds. See `Settings`.
+pub const DEFAULT_RECONCILE_INTERVAL: u64 = 300;
+
+// How long notifications are held back after the host resumes by default, in seconds. See
+// `Settings`.
+pub const DEFAULT_RESUME_GRACE_PERIOD: u64 = 30;
+
+// A deserialized copy of a configuration file.
+//
+// The last `history_size` events are recorded in the history file, for `killjoy events` to list.
+// If `event_log` is set, every state transition is also appended to it. If `event_store` is set,
+// every state transition and every attempt to contact a notifier is also recorded in it. If
+// `heartbeat` is set, its URL is requested periodically while killjoy is healthy. `systemd_calls`
+// defines how long calls to systemd may take, and how they're retried. Every `reconcile_interval`,
+// units' states are read again, to catch transitions whose signals were missed. `None` disables
+// reconciliation. If `control_socket` is set, killjoy accepts control requests on it. If
+// `discovery` is set, rules declared in unit files are added to `rules` while killjoy runs. While
+// the host is suspended, and for `resume_grace_period` after it resumes, notifications are held
+// back. `None` disables this. If `deduplicate_notifications` is set, a notifier referenced by
+// several rules which match the same state change is contacted once.
+// `rule_evaluation` states whether all rules matching a state change fire, or only the first.
+// `polling` defines how often killjoy polls for changes which systemd doesn't signal. `identity` is
+// attached to every notification, so that a notifier serving many hosts may tell
+// them apart. `transport` states how bus watchers talk to systemd. Besides the interfaces which
+// killjoy needs, bus watchers receive changes to the properties of the
+// `properties_changed_interfaces`. If `watch_limit` is set, it caps how many units each bus
+// watcher keeps track of.
+//
+// Beware that `Settings` instances may have semantically invalid values. For example, a notifier's
+// `bus_name` might be syntactically valid but may point to a non-existent entity.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub boot_summary: Option<BootSummary>,
+    pub control_socket: Option<ControlSocket>,
+    pub deduplicate_notifications: bool,
+    pub discovery: Option<Discovery>,
+    pub event_log: Option<EventLog>,
+    pub event_store: Option<EventStore>,
+    pub heartbeat: Option<Heartbeat>,
+    pub history_size: usize,
+    pub identity: Identity,
+    pub notifiers: HashMap<String, Notifier>,
+    pub polling: Polling,
+    pub properties_changed_interfaces: Vec<String>,
+    pub reconcile_interval: Option<Duration>,
+    pub resume_grace_period: Option<Duration>,
+    pub rule_evaluation: RuleEvaluation,
+    pub rules: Vec<Rule>,
+    pub systemd_calls: SystemdCalls,
+    pub transport: Transport,
+    pub watch_limit: Option<WatchLimit>,
+}
+
+impl Settings {
+    // Create a new settings object.
+    //
+    // An error may be returned for one of two broad categories of reasons:
+    //
+    // *   Deserialization of the `reader` failed. Maybe the reader yielded non-unicode bytes; maybe
+    //     the bytes were valid unicode but not valid JSON; maybe the unicode was valid JSON but
+    //     didn't match the settings file schema; or so on.
+    // *   The settings object contained semantically invalid data. Maybe a `"bus_type"` key was set
+    //     to a value such as `"foo"`, or so on.
+    pub fn new<T: Read>(reader: T) -> Result<Self, CrateError> {
+        let serde_settings: SerdeSettings = serde_json::from_reader(reader).map_err(|err| {
+            CrateError::Settings(SettingsError::SettingsFileDeserializationFailed(err))
+        })?;
+        Self::try_from(serde_settings)
+    }
+}
+
+impl TryFrom<SerdeSettings> for Settings {
+    type Error = CrateError;
+
+    fn try_from(value: SerdeSettings) -> Result<Self, Self::Error> {
+        let mut notifiers: HashMap<String, Notifier> = HashMap::new();
+        for (key, serde_notifier) in value.notifiers.into_iter() {
+            let notifier = Notifier::try_from(serde_notifier)?;
+            notifiers.insert(key, notifier);
+        }
+        let notifiers = notifiers; // make immutable
+
+        let mut rules: Vec<Rule> = Vec::new();
+        for serde_rule in value.rules.into_iter() {
+            rules.push(Rule::try_from(serde_rule.with_defaults(&value.defaults))?);
+        }
+        let rules = rules; // make immutable
+
+        let boot_summary = value.boot_summary.map(BootSummary::try_from).transpose()?;
+        let event_log = value.event_log.map(EventLog::try_from).transpose()?;
+        let control_socket = value.control_socket.map(ControlSocket::from);
+        let discovery = value.discovery.map(Discovery::try_from).transpose()?;
+        let event_store = value.event_store.map(EventStore::from);
+        let heartbeat = value.heartbeat.map(Heartbeat::try_from).transpose()?;
+        let watch_limit = value.watch_limit.map(WatchLimit::from);
+        let identity = match value.identity {
+            Some(identity) => Identity::try_from(identity)?,
+            None => Identity::default(),
+        };
+        let polling = match value.polling {
+            Some(polling) => Polling::try_from(polling)?,
+            None => Polling::default(),
+        };
+        let systemd_calls = match value.systemd_calls {
+            Some(systemd_calls) => SystemdCalls::try_from(systemd_calls)?,
+            None => SystemdCalls::default(),
+        };
+
+        let rule_evaluation = match &value.rule_evaluation {
+            Some(rule_evaluation) => RuleEvaluation::try_from(&rule_evaluation[..])?,
+            None => RuleEvaluation::All,
+        };
+        let transport = match &value.transport {
+            Some(transport) => Transport::try_from(&transport[..])?,
+            None => Transport::DBus,
+        };
+        for interface in &value.properties_changed_interfaces {
+            if Interface::new(&interface[..]).is_err() {
+                return Err(CrateError::Settings(SettingsError::InvalidInterface(
+                    interface.to_owned(),
+                )));
+            }
+        }
+        let reconcile_interval = match value.reconcile_interval {
+            0 => None,
+            interval => Some(Duration::from_secs(interval)),
+        };
+        let resume_grace_period = match value.resume_grace_period {
+            0 => None,
+            period => Some(Duration::from_secs(period)),
+        };
+
+        Ok(Self {
+            control_socket,
+            deduplicate_notifications: value.deduplicate_notifications,
+            discovery,
+            identity,
+            polling,
+            properties_changed_interfaces: value.properties_changed_interfaces,
+            reconcile_interval,
+            resume_grace_period,
+            rule_evaluation,
+            systemd_calls,
+            transport,
+            ..Self::from_parts(
+                boot_summary,
+                event_log,
+                event_store,
+                heartbeat,
+                value.history_size,
+                notifiers,
+                rules,
+                watch_limit,
+            )?
+        })
+    }
+}
+
+impl Settings {
+    // Combine notifiers and rules into a settings object.
+    //
+    // Return an error if a rule, the boot summary or the watch limit references a non-existent
+    // notifier, if two rules have the same name, if the watch limit is zero, or if an event store
+    // is requested but killjoy was built without one.
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        boot_summary: Option<BootSummary>,
+        event_log: Option<EventLog>,
+        event_store: Option<EventStore>,
+        heartbeat: Option<Heartbeat>,
+        history_size: usize,
+        notifiers: HashMap<String, Notifier>,
+        rules: Vec<Rule>,
+        watch_limit: Option<WatchLimit>,
+    ) -> Result<Self, CrateError> {
+        if cfg!(not(feature = "sqlite")) && event_store.is_some() {
+            return Err(CrateError::State(StateError::EventStoreNotSupported));
+        }
+        if watch_limit
+            .as_ref()
+            .is_some_and(|limit| limit.max_units == 0)
+        {
+            return Err(CrateError::Settings(SettingsError::InvalidWatchLimit));
+        }
+        let summary_notifiers = boot_summary.iter().flat_map(|summary| &summary.notifiers);
+        let limit_notifiers = watch_limit.iter().flat_map(|limit| &limit.notifiers);
+        for notifier in summary_notifiers.chain(limit_notifiers) {
+            if !notifiers.contains_key(notifier) {
+                return Err(CrateError::Settings(SettingsError::InvalidNotifier(
+                    notifier.to_owned(),
+                )));
+            }
+        }
+        for (i, rule) in rules.iter().enumerate() {
+            for notifier in &rule.notifiers {
+                if !notifiers.contains_key(notifier) {
+                    return Err(CrateError::Settings(SettingsError::InvalidNotifier(
+                        notifier.to_owned(),
+                    )));
+                }
+            }
+            if let Some(name) = &rule.name {
+                if rules[..i]
+                    .iter()
+                    .any(|other| other.name.as_ref() == Some(name))
+                {
+                    return Err(CrateError::Settings(SettingsError::DuplicateRuleName(
+                        name.to_owned(),
+                    )));
+                }
+            }
+        }
+        Ok(Self {
+            boot_summary,
+            control_socket: None,
+            deduplicate_notifications: true,
+            discovery: None,
+            event_log,
+            event_store,
+            heartbeat,
+            history_size,
+            identity: Identity::default(),
+            notifiers,
+            polling: Polling::default(),
+            properties_changed_interfaces: Vec::new(),
+            reconcile_interval: Some(Duration::from_secs(DEFAULT_RECONCILE_INTERVAL)),
+            resume_grace_period: Some(Duration::from_secs(DEFAULT_RESUME_GRACE_PERIOD)),
+            rule_evaluation: RuleEvaluation::All,
+            rules,
+            systemd_calls: SystemdCalls::default(),
+            transport: Transport::DBus,
+            watch_limit,
+        })
+    }
+
+    // Parse a rule which is added while killjoy runs, e.g. with `killjoy rules add`.
+    //
+    // The rule is checked as if it were in the settings file's `rules` list, and every problem is
+    // reported. The settings file's `defaults` don't apply. Return an error if the rule references
+    // a notifier which these settings lack, or if one of these settings' rules has the same name.
+    pub fn parse_rule(&self, value: &Value) -> Result<Rule, Vec<CrateError>> {
+        let mut errs: Vec<CrateError> = Vec::new();
+        let notifier_names: Vec<&str> = self.notifiers.keys().map(String::as_str).collect();
+        let required = &["expression", "expression_type"];
+        if let Some(rule) = check_object(value, "", RULE_KEYS, required, &mut errs) {
+            check_rule_fields(rule, "", &notifier_names, &mut errs);
+        }
+        if !errs.is_empty() {
+            return Err(errs);
+        }
+
+        let serde_rule: SerdeRule = serde_json::from_value(value.clone())
+            .map_err(|err| vec![CrateError::State(StateError::ParseRuntimeRule(err))])?;
+        let rule = Rule::try_from(serde_rule).map_err(|err| vec![err])?;
+        if let Some(name) = &rule.name {
+            if self
+                .rules
+                .iter()
+                .any(|other| other.name.as_ref() == Some(name))
+            {
+                return Err(vec![CrateError::Settings(
+                    SettingsError::DuplicateRuleName(name.to_owned()),
+                )]);
+            }
+        }
+        Ok(rule)
+    }
+}
+
+// A builder for `Settings` objects, for programs which construct settings in code.
+//
+// Rules are built when `build` is called, so that all invalid values are reported in one place.
+// The same checks are applied as when a settings file is loaded.
+#[derive(Default)]
+pub struct SettingsBuilder {
+    boot_summary: Option<BootSummary>,
+    control_socket: Option<ControlSocket>,
+    deduplicate_notifications: Option<bool>,
+    discovery: Option<Discovery>,
+    event_log: Option<EventLog>,
+    event_store: Option<EventStore>,
+    heartbeat: Option<Heartbeat>,
+    history_size: Option<usize>,
+    identity: Option<Identity>,
+    notifiers: HashMap<String, Notifier>,
+    reconcile_interval: Option<Option<Duration>>,
+    resume_grace_period: Option<Option<Duration>>,
+    rule_evaluation: Option<RuleEvaluation>,
+    polling: Option<Polling>,
+    rules: Vec<RuleBuilder>,
+    systemd_calls: Option<SystemdCalls>,
+    transport: Option<Transport>,
+    watch_limit: Option<WatchLimit>,
+}
+
+impl SettingsBuilder {
+    // Create a builder with no notifiers and no rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a notifier, which rules may reference by `name`. A notifier with the same name is
+    // replaced.
+    pub fn notifier(mut self, name: &str, notifier: Notifier) -> Self {
+        self.notifiers.insert(name.to_owned(), notifier);
+        self
+    }
+
+    // Add a rule.
+    pub fn rule(mut self, rule: RuleBuilder) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    // Send a summary to the named notifiers once systemd on `bus_type` has finished booting.
+    pub fn boot_summary<I: IntoIterator<Item = String>>(
+        mut self,
+        bus_type: BusType,
+        notifiers: I,
+    ) -> Self {
+        self.boot_summary = Some(BootSummary {
+            bus_type,
+            notifiers: notifiers.into_iter().collect(),
+        });
+        self
+    }
+
+    // Keep track of at most `max_units` units per bus, and warn the named notifiers the first time
+    // a unit is forgotten to make room for another.
+    pub fn watch_limit<I: IntoIterator<Item = String>>(
+        mut self,
+        max_units: usize,
+        notifiers: I,
+    ) -> Self {
+        self.watch_limit = Some(WatchLimit {
+            max_units,
+            notifiers: notifiers.into_iter().collect(),
+        });
+        self
+    }
+
+    // Append every state transition to the file at `path`, rotating it once it would exceed
+    // `max_size` bytes.
+    pub fn event_log<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        format: EventLogFormat,
+        max_size: u64,
+    ) -> Self {
+        self.event_log = Some(EventLog {
+            format,
+            max_size,
+            path: path.into(),
+        });
+        self
+    }
+
+    // Record events in an SQLite database at `path`, or in the default location if `None`.
+    pub fn event_store(mut self, path: Option<PathBuf>) -> Self {
+        self.event_store = Some(EventStore { path });
+        self
+    }
+
+    // Accept control requests on a Unix domain socket at `path`, or in the default location if
+    // `None`.
+    pub fn control_socket(mut self, path: Option<PathBuf>) -> Self {
+        self.control_socket = Some(ControlSocket { path });
+        self
+    }
+
+    // Discover rules declared in the files of units on `bus_type`.
+    pub fn discovery(mut self, bus_type: BusType) -> Self {
+        self.discovery = Some(Discovery { bus_type });
+        self
+    }
+
+    // Request a URL periodically while killjoy is healthy.
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    // Set how many events are remembered. Zero disables the history file.
+    pub fn history_size(mut self, history_size: usize) -> Self {
+        self.history_size = Some(history_size);
+        self
+    }
+
+    // Set how the host is identified to notifiers.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    // Set whether a notifier is contacted once per state change, however many rules reference it.
+    pub fn deduplicate_notifications(mut self, deduplicate_notifications: bool) -> Self {
+        self.deduplicate_notifications = Some(deduplicate_notifications);
+        self
+    }
+
+    // Set how often units are reconciled. `None` disables reconciliation.
+    pub fn reconcile_interval(mut self, reconcile_interval: Option<Duration>) -> Self {
+        self.reconcile_interval = Some(reconcile_interval);
+        self
+    }
+
+    // Set how long notifications are held back after the host resumes. `None` disables this.
+    pub fn resume_grace_period(mut self, resume_grace_period: Option<Duration>) -> Self {
+        self.resume_grace_period = Some(resume_grace_period);
+        self
+    }
+
+    // Set which of the rules matching a state change fire.
+    pub fn rule_evaluation(mut self, rule_evaluation: RuleEvaluation) -> Self {
+        self.rule_evaluation = Some(rule_evaluation);
+        self
+    }
+
+    // Set how often killjoy polls for changes which systemd doesn't signal.
+    pub fn polling(mut self, polling: Polling) -> Self {
+        self.polling = Some(polling);
+        self
+    }
+
+    // Set how killjoy calls systemd.
+    pub fn systemd_calls(mut self, systemd_calls: SystemdCalls) -> Self {
+        self.systemd_calls = Some(systemd_calls);
+        self
+    }
+
+    // Set how bus watchers talk to systemd.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    // Build a settings object, or return an error if any values are invalid.
+    pub fn build(self) -> Result<Settings, CrateError> {
+        let mut rules: Vec<Rule> = Vec::new();
+        for rule in self.rules.into_iter() {
+            rules.push(rule.build()?);
+        }
+        let settings = Settings::from_parts(
+            self.boot_summary,
+            self.event_log,
+            self.event_store,
+            self.heartbeat,
+            self.history_size.unwrap_or(DEFAULT_HISTORY_SIZE),
+            self.notifiers,
+            rules,
+            self.watch_limit,
+        )?;
+        Ok(Settings {
+            control_socket: self.control_socket,
+            deduplicate_notifications: self
+                .deduplicate_notifications
+                .unwrap_or(settings.deduplicate_notifications),
+            discovery: self.discovery,
+            identity: self.identity.unwrap_or_default(),
+            polling: self.polling.unwrap_or_default(),
+            reconcile_interval: self
+                .reconcile_interval
+                .unwrap_or(settings.reconcile_interval),
+            resume_grace_period: self
+                .resume_grace_period
+                .unwrap_or(settings.resume_grace_period),
+            rule_evaluation: self.rule_evaluation.unwrap_or(settings.rule_evaluation),
+            systemd_calls: self.systemd_calls.unwrap_or_default(),
+            transport: self.transport.unwrap_or(settings.transport),
+            ..settings
+        })
+    }
+}
+
+// A builder for `Rule` objects, for programs which construct settings in code.
+//
+// The builder collects values much like a rule in a settings file, and `build` validates them in
+// the same way. For example, to watch all timers on the session bus, and to contact the "logfile"
+// notifier whenever one fails:
+//
+//     RuleBuilder::new(BusType::Session)
+//         .watch_unit_type(".timer")
+//         .on_states(vec![ActiveState::Failed])
+//         .notify("logfile")
+//         .build()
+pub struct RuleBuilder {
+    serde_rule: SerdeRule,
+}
+
+impl RuleBuilder {
+    // Create a builder for a rule which watches units on the given bus.
+    //
+    // Before building, call one of the `watch_*` methods to state which units to watch.
+    pub fn new(bus_type: BusType) -> Self {
+        RuleBuilder {
+            serde_rule: SerdeRule {
+                active_states: Some(Vec::new()),
+                bus_type: Some(encode_bus_type(bus_type).to_string()),
+                notifiers: Some(Vec::new()),
+                ..SerdeRule::default()
+            },
+        }
+    }
+
+    // Watch the unit with exactly this name, e.g. "foo.service".
+    pub fn watch_unit_name(self, unit_name: &str) -> Self {
+        self.watch("unit name", unit_name)
+    }
+
+    // Watch the instances of the template unit with this name, e.g. "getty@.service".
+    pub fn watch_unit_template(self, unit_template: &str) -> Self {
+        self.watch("unit template", unit_template)
+    }
+
+    // Watch units with names ending in this suffix, e.g. ".service".
+    pub fn watch_unit_type(self, unit_type: &str) -> Self {
+        self.watch("unit type", unit_type)
+    }
+
+    // Watch units with names matching this regex, e.g. "^f[aeiou]{2}\\.service$".
+    pub fn watch_regex(self, regex: &str) -> Self {
+        self.watch("regex", regex)
+    }
+
+    // Watch the state of the system as a whole, rather than units.
+    pub fn watch_system_state(self) -> Self {
+        self.watch("manager", "SystemState")
+    }
+
+    // Watch the portable service image with this name, and its units.
+    pub fn watch_portable_image(self, image_name: &str) -> Self {
+        self.watch("portable image", image_name)
+    }
+
+    // Add active states of interest.
+    pub fn on_states<I: IntoIterator<Item = ActiveState>>(mut self, active_states: I) -> Self {
+        self.serde_rule
+            .active_states
+            .get_or_insert_with(Vec::new)
+            .extend(active_states.into_iter().map(String::from));
+        self
+    }
+
+    // Add load states of interest.
+    pub fn on_load_states<I: IntoIterator<Item = LoadState>>(mut self, load_states: I) -> Self {
+        self.serde_rule
+            .load_states
+            .extend(load_states.into_iter().map(String::from));
+        self
+    }
+
+    // Add sub-states of interest. Only units in one of them match, and changes between them are
+    // reported. See `Rule`.
+    pub fn on_sub_states<I: IntoIterator<Item = String>>(mut self, sub_states: I) -> Self {
+        self.serde_rule.sub_states.extend(sub_states);
+        self
+    }
+
+    // Add system states of interest, for a rule which watches the system state.
+    pub fn on_system_states<I: IntoIterator<Item = SystemState>>(
+        mut self,
+        system_states: I,
+    ) -> Self {
+        self.serde_rule
+            .system_states
+            .extend(system_states.into_iter().map(String::from));
+        self
+    }
+
+    // Add portable image states of interest, for a rule which watches a portable image.
+    pub fn on_portable_states<I: IntoIterator<Item = PortableState>>(
+        mut self,
+        portable_states: I,
+    ) -> Self {
+        self.serde_rule
+            .portable_states
+            .extend(portable_states.into_iter().map(String::from));
+        self
+    }
+
+    // Add unit file states of interest.
+    pub fn on_unit_file_states<I: IntoIterator<Item = UnitFileState>>(
+        mut self,
+        unit_file_states: I,
+    ) -> Self {
+        self.serde_rule
+            .unit_file_states
+            .extend(unit_file_states.into_iter().map(String::from));
+        self
+    }
+
+    // Add a condition on a property, e.g. `on_property("MemoryCurrent", ">", Some(1 << 30))`.
+    //
+    // `condition` is one of "<", ">", "rate >" or "changed". All but the last require a `value`.
+    pub fn on_property(mut self, property: &str, condition: &str, value: Option<u64>) -> Self {
+        self.serde_rule.properties.push(SerdePropertyCondition {
+            condition: condition.to_owned(),
+            duration: 0,
+            interface: None,
+            property: property.to_owned(),
+            value,
+        });
+        self
+    }
+
+    // Add a threshold condition on a property which must hold for `duration` seconds, e.g.
+    // `on_sustained_property("MemoryCurrent", ">", 1 << 30, 300)`.
+    pub fn on_sustained_property(
+        mut self,
+        property: &str,
+        condition: &str,
+        value: u64,
+        duration: u64,
+    ) -> Self {
+        self.serde_rule.properties.push(SerdePropertyCondition {
+            condition: condition.to_owned(),
+            duration,
+            interface: None,
+            property: property.to_owned(),
+            value: Some(value),
+        });
+        self
+    }
+
+    // Set a filter script, which decides whether to notify about each state change. See the `script`
+    // module.
+    pub fn filter(mut self, source: &str) -> Self {
+        self.serde_rule.filter = Some(source.to_owned());
+        self
+    }
+
+    // Add a notifier to contact, by name.
+    pub fn notify(mut self, notifier_name: &str) -> Self {
+        self.serde_rule
+            .notifiers
+            .get_or_insert_with(Vec::new)
+            .push(notifier_name.to_owned());
+        self
+    }
+
+    // Set how the notifiers are contacted.
+    pub fn notifier_policy(mut self, notifier_policy: NotifierPolicy) -> Self {
+        self.serde_rule.notifier_policy = Some(String::from(notifier_policy));
+        self
+    }
+
+    // Only fire once a unit has stayed in one of the active states for the given time.
+    pub fn for_duration(mut self, for_duration: Duration) -> Self {
+        self.serde_rule.for_duration = Some(for_duration.as_secs());
+        self
+    }
+
+    // Group the notifications about units in the same group, waiting `window` for more events.
+    pub fn group_by(mut self, group_by: GroupBy, window: Duration) -> Self {
+        self.serde_rule.group_by = Some(String::from(group_by));
+        self.serde_rule.group_window = Some(window.as_secs());
+        self
+    }
+
+    // Set the rule's name.
+    pub fn name(mut self, name: &str) -> Self {
+        self.serde_rule.name = Some(name.to_owned());
+        self
+    }
+
+    // Add a label. A label with the same name is replaced.
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.serde_rule
+            .labels
+            .insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    // Add a payload field, which is passed to version 2 notifiers verbatim. A field with the same
+    // name is replaced.
+    pub fn payload_field(mut self, name: &str, value: &str) -> Self {
+        self.serde_rule
+            .payload
+            .insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    // Set the rule's severity.
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.serde_rule.severity = Some(String::from(severity));
+        self
+    }
+
+    // Add a window of quiet hours, e.g. "Sat..Sun" or "22:00-07:00".
+    pub fn suppress(mut self, window: &str) -> Self {
+        self.serde_rule.suppress.push(window.to_owned());
+        self
+    }
+
+    // Drop notifications until the given time after the service manager started.
+    pub fn startup_grace_period(mut self, grace_period: Duration) -> Self {
+        self.serde_rule.startup_grace_period = Some(grace_period.as_secs());
+        self
+    }
+
+    // Set the severity with which to notify during quiet hours.
+    pub fn suppress_severity(mut self, severity: Severity) -> Self {
+        self.serde_rule.suppress_severity = Some(String::from(severity));
+        self
+    }
+
+    // Don't match units which were created at runtime, e.g. session scopes.
+    pub fn ignore_transient(mut self, ignore_transient: bool) -> Self {
+        self.serde_rule.ignore_transient = Some(ignore_transient);
+        self
+    }
+
+    // Tell notifiers which units depend on the unit.
+    pub fn include_dependents(mut self, include_dependents: bool) -> Self {
+        self.serde_rule.include_dependents = include_dependents;
+        self
+    }
+
+    // Match the unit as `failed` if it becomes `inactive` after an unsuccessful run.
+    pub fn treat_unsuccessful_inactive_as_failed(mut self, treat: bool) -> Self {
+        self.serde_rule.treat_unsuccessful_inactive_as_failed = treat;
+        self
+    }
+
+    // Check that the unit's mount point is mounted whenever the unit is active.
+    pub fn verify_mount_point(mut self, verify: bool) -> Self {
+        self.serde_rule.verify_mount_point = verify;
+        self
+    }
+
+    // Notify whenever a matching unit is loaded, unless it's one of `allowed_units`.
+    pub fn on_appear<I: IntoIterator<Item = String>>(mut self, allowed_units: I) -> Self {
+        self.serde_rule.on_appear = true;
+        self.serde_rule.allowed_units = allowed_units.into_iter().collect();
+        self
+    }
+
+    // Wait for messages at most this long on the rule's bus. See `Rule`.
+    pub fn loop_timeout(mut self, loop_timeout: Duration) -> Self {
+        self.serde_rule.loop_timeout = Some(loop_timeout.as_millis() as u64);
+        self
+    }
+
+    // Sample the properties of matching units this often. See `Rule`.
+    pub fn property_sample_interval(mut self, interval: Duration) -> Self {
+        self.serde_rule.property_sample_interval = Some(interval.as_secs());
+        self
+    }
+
+    // Set the rule's priority, for use when only the first matching rule fires.
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.serde_rule.priority = priority;
+        self
+    }
+
+    // Send notifiers up to this many of the unit's most recent log messages.
+    pub fn journal_lines(mut self, journal_lines: u32) -> Self {
+        self.serde_rule.journal_lines = journal_lines;
+        self
+    }
+
+    // Build a rule, or return an error if any values are invalid.
+    //
+    // References to notifiers aren't checked until the rule is added to a `SettingsBuilder`.
+    pub fn build(self) -> Result<Rule, CrateError> {
+        Rule::try_from(self.serde_rule)
+    }
+
+    fn watch(mut self, expression_type: &str, expression: &str) -> Self {
+        self.serde_rule.expression_type = expression_type.to_owned();
+        self.serde_rule.expression = expression.to_owned();
+        self
+    }
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeNotifier {
+    #[serde(default)]
+    accept_tags: Vec<String>,
+    #[serde(default)]
+    bus_name: Option<String>,
+    #[serde(default)]
+    bus_type: Option<String>,
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    clock: Option<String>,
+    #[serde(default)]
+    command: Option<Vec<String>>,
+    #[serde(default)]
+    community: Option<String>,
+    #[serde(default)]
+    community_credential: Option<String>,
+    #[serde(default)]
+    digest_schedule: Option<String>,
+    #[serde(default)]
+    facility: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    message_template: Option<String>,
+    #[serde(default)]
+    mode: Option<String>,
+    #[serde(default)]
+    oid: Option<String>,
+    #[serde(default)]
+    options: Option<Map<String, Value>>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    password_credential: Option<String>,
+    #[serde(default)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    plaintext_credentials: bool,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default = "default_protocol_version")]
+    protocol_version: u32,
+    #[serde(default)]
+    qos: Option<u32>,
+    #[serde(default)]
+    reject_tags: Vec<String>,
+    #[serde(default)]
+    resolve_states: Option<Vec<String>>,
+    #[serde(default)]
+    retain: bool,
+    #[serde(default)]
+    topic: Option<String>,
+    #[serde(default)]
+    timeout: Option<u64>,
+    #[serde(default)]
+    transport: Option<String>,
+    #[serde(default, rename = "type")]
+    notifier_type: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    url_credential: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+}
+
+fn default_protocol_version() -> u32 {
+    1
+}
+
+fn default_history_size() -> usize {
+    DEFAULT_HISTORY_SIZE
+}
+
+fn default_deduplicate_notifications() -> bool {
+    true
+}
+
+fn default_reconcile_interval() -> u64 {
+    DEFAULT_RECONCILE_INTERVAL
+}
+
+fn default_resume_grace_period() -> u64 {
+    DEFAULT_RESUME_GRACE_PERIOD
+}
+
+fn default_event_log_max_size() -> u64 {
+    DEFAULT_EVENT_LOG_MAX_SIZE
+}
+
+// See SerdeSettings.
+#[derive(Default, Deserialize)]
+struct SerdeRule {
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default)]
+    action_max_attempts: Option<u32>,
+    #[serde(default)]
+    action_window: Option<u64>,
+    #[serde(default)]
+    actions: Vec<SerdeAction>,
+    #[serde(default)]
+    active_states: Option<Vec<String>>,
+    #[serde(default)]
+    allowed_units: Vec<String>,
+    #[serde(default)]
+    bus_type: Option<String>,
+    expression: String,
+    expression_type: String,
+    #[serde(default)]
+    filter: Option<String>,
+    #[serde(default, rename = "for")]
+    for_duration: Option<u64>,
+    #[serde(default)]
+    group_by: Option<String>,
+    #[serde(default)]
+    group_window: Option<u64>,
+    #[serde(default)]
+    ignore_transient: Option<bool>,
+    #[serde(default)]
+    include_dependents: bool,
+    #[serde(default)]
+    journal_lines: u32,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    load_states: Vec<String>,
+    #[serde(default)]
+    loop_timeout: Option<u64>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    notifier_policy: Option<String>,
+    #[serde(default)]
+    notifiers: Option<Vec<String>>,
+    #[serde(default)]
+    on_appear: bool,
+    #[serde(default)]
+    payload: HashMap<String, String>,
+    #[serde(default)]
+    portable_states: Vec<String>,
+    #[serde(default)]
+    priority: u32,
+    #[serde(default)]
+    properties: Vec<SerdePropertyCondition>,
+    #[serde(default)]
+    property_sample_interval: Option<u64>,
+    #[serde(default)]
+    severity: Option<String>,
+    #[serde(default)]
+    startup_grace_period: Option<u64>,
+    #[serde(default)]
+    sub_states: Vec<String>,
+    #[serde(default)]
+    suppress: Vec<String>,
+    #[serde(default)]
+    suppress_severity: Option<String>,
+    #[serde(default)]
+    system_states: Vec<String>,
+    #[serde(default)]
+    treat_unsuccessful_inactive_as_failed: bool,
+    #[serde(default)]
+    unit_file_states: Vec<String>,
+    #[serde(default)]
+    verify_mount_point: bool,
+}
+
+impl SerdeRule {
+    // Fill in the fields this rule omits from `defaults`.
+    //
+    // Only omitted fields are filled in. For example, a rule with `"notifiers": []` keeps its empty
+    // list of notifiers.
+    fn with_defaults(mut self, defaults: &SerdeDefaults) -> Self {
+        if self.active_states.is_none() {
+            self.active_states = defaults.active_states.clone();
+        }
+        if self.bus_type.is_none() {
+            self.bus_type = defaults.bus_type.clone();
+        }
+        if self.ignore_transient.is_none() {
+            self.ignore_transient = defaults.ignore_transient;
+        }
+        if self.notifiers.is_none() {
+            self.notifiers = defaults.notifiers.clone();
+        }
+        if self.startup_grace_period.is_none() {
+            self.startup_grace_period = defaults.startup_grace_period;
+        }
+        self
+    }
+}
+
+// See SerdeSettings.
+#[derive(Default, Deserialize)]
+struct SerdeDefaults {
+    #[serde(default)]
+    active_states: Option<Vec<String>>,
+    #[serde(default)]
+    bus_type: Option<String>,
+    #[serde(default)]
+    ignore_transient: Option<bool>,
+    #[serde(default)]
+    notifiers: Option<Vec<String>>,
+    #[serde(default)]
+    startup_grace_period: Option<u64>,
+}
+
+// Like a `Settings`, but fields are simple types instead of domain-specific types.
+//
+// The `SerdeSettings` object is composed of types from the standard library, such as strings and
+// vectors. This makes it easy to deserialize with serde, but the resulting object may contain
+// values that are syntactically correct but semantically incorrect. For example,
+// `settings.notifiers[i].bus_name` is a `String`, and it may be impossible to cast this string to a
+// `dbus::BusName` object.
+//
+// This makes `SerdeSettings` a bad choice for use throughout the rest of the application. If this
+// was done, killjoy could encounter semantic errors late in runtime. For example, killjoy might
+// notice an event of interest, decide to notify a notifier, and *then* discover that a `bus_name`
+// contains a malformed address.
+//
+// Calling SerdeSettings::to_settings forces values to be cast to more appropriate types. Although
+// type-casting doesn't guarantee that a settings object contains valid values, it gets closer to
+// the ideal.
+#[derive(Deserialize)]
+struct SerdeSettings {
+    #[serde(default)]
+    boot_summary: Option<SerdeBootSummary>,
+    #[serde(default)]
+    control_socket: Option<SerdeControlSocket>,
+    #[serde(default = "default_deduplicate_notifications")]
+    deduplicate_notifications: bool,
+    #[serde(default)]
+    defaults: SerdeDefaults,
+    #[serde(default)]
+    discovery: Option<SerdeDiscovery>,
+    #[serde(default)]
+    event_log: Option<SerdeEventLog>,
+    #[serde(default)]
+    event_store: Option<SerdeEventStore>,
+    #[serde(default)]
+    heartbeat: Option<SerdeHeartbeat>,
+    #[serde(default = "default_history_size")]
+    history_size: usize,
+    #[serde(default)]
+    identity: Option<SerdeIdentity>,
+    notifiers: HashMap<String, SerdeNotifier>,
+    #[serde(default = "default_reconcile_interval")]
+    reconcile_interval: u64,
+    #[serde(default = "default_resume_grace_period")]
+    resume_grace_period: u64,
+    #[serde(default)]
+    rule_evaluation: Option<String>,
+    #[serde(default)]
+    polling: Option<SerdePolling>,
+    #[serde(default)]
+    properties_changed_interfaces: Vec<String>,
+    rules: Vec<SerdeRule>,
+    #[serde(default)]
+    systemd_calls: Option<SerdeSystemdCalls>,
+    #[serde(default)]
+    transport: Option<String>,
+    #[serde(default)]
+    watch_limit: Option<SerdeWatchLimit>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeBootSummary {
+    #[serde(default)]
+    bus_type: Option<String>,
+    notifiers: Vec<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeWatchLimit {
+    max_units: usize,
+    #[serde(default)]
+    notifiers: Vec<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeControlSocket {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeDiscovery {
+    #[serde(default)]
+    bus_type: Option<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeEventLog {
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default = "default_event_log_max_size")]
+    max_size: u64,
+    path: String,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeEventStore {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeHeartbeat {
+    #[serde(default = "default_heartbeat_interval")]
+    interval: u64,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    url_credential: Option<String>,
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeIdentity {
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    include_machine_id: bool,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+fn default_heartbeat_interval() -> u64 {
+    DEFAULT_HEARTBEAT_INTERVAL
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdeSystemdCalls {
+    #[serde(default)]
+    list_timeout: Option<u64>,
+    #[serde(default)]
+    properties_timeout: Option<u64>,
+    #[serde(default)]
+    retries: u32,
+    #[serde(default = "default_systemd_call_retry_delay")]
+    retry_delay: u64,
+    #[serde(default = "default_systemd_call_timeout")]
+    timeout: u64,
+    #[serde(default)]
+    unit_timeout: Option<u64>,
+}
+
+fn default_systemd_call_retry_delay() -> u64 {
+    DEFAULT_SYSTEMD_CALL_RETRY_DELAY
+}
+
+fn default_systemd_call_timeout() -> u64 {
+    DEFAULT_SYSTEMD_CALL_TIMEOUT
+}
+
+// See SerdeSettings.
+#[derive(Deserialize)]
+struct SerdePolling {
+    #[serde(default)]
+    loop_timeout: Option<u64>,
+    #[serde(default = "default_mount_check_interval")]
+    mount_check_interval: u64,
+    #[serde(default = "default_property_sample_interval")]
+    property_sample_interval: u64,
+    #[serde(default = "default_unit_file_scan_interval")]
+    unit_file_scan_interval: u64,
+}
+
+fn default_mount_check_interval() -> u64 {
+    DEFAULT_MOUNT_CHECK_INTERVAL
+}
+
+fn default_property_sample_interval() -> u64 {
+    DEFAULT_PROPERTY_SAMPLE_INTERVAL
+}
+
+fn default_unit_file_scan_interval() -> u64 {
+    DEFAULT_UNIT_FILE_SCAN_INTERVAL
+}
+
+// This struct is a hack. See get_bus_types().
+#[derive(PartialEq, Eq, Hash)]
+enum HashableBusType {
+    Session,
+    Starter,
+    System,
+}
+
+// This impl is a hack. See get_bus_types().
+impl From<BusType> for HashableBusType {
+    fn from(value: BusType) -> Self {
+        match value {
+            BusType::Session => HashableBusType::Session,
+            BusType::Starter => HashableBusType::Starter,
+            BusType::System => HashableBusType::System,
+        }
+    }
+}
+
+// This impl is a hack. See get_bus_types().
+impl From<HashableBusType> for BusType {
+    fn from(value: HashableBusType) -> Self {
+        match value {
+            HashableBusType::Session => BusType::Session,
+            HashableBusType::Starter => BusType::Starter,
+            HashableBusType::System => BusType::System,
+        }
+    }
+}
+
+pub fn decode_bus_type_str(bus_type_str: &str) -> Result<BusType, CrateError> {
+    match bus_type_str {
+        "session" => Ok(BusType::Session),
+        "starter" => Ok(BusType::Starter),
+        "system" => Ok(BusType::System),
+        other => Err(CrateError::Settings(SettingsError::InvalidBusType(
+            other.to_owned(),
+        ))),
+    }
+}
+
+// Tell whether the given string names a template unit, e.g. "getty@.service".
+//
+// A template unit's name has a non-empty prefix, followed by "@", followed by a unit type suffix.
+fn is_valid_unit_template(unit_template: &str) -> bool {
+    match unit_template.split_once('@') {
+        Some((prefix, suffix)) => {
+            !prefix.is_empty()
+                && suffix.len() > 1
+                && suffix.starts_with('.')
+                && !suffix.contains('@')
+        }
+        None => false,
+    }
+}
+
+// Aliases which may be listed in place of active states in a settings file, and the states each
+// stands for. "*" stands for every state killjoy knows of.
+const ACTIVE_STATE_ALIASES: &[(&str, &[&str])] = &[
+    ("*", unit::KNOWN_ACTIVE_STATES),
+    ("not-active", &["deactivating", "failed", "inactive"]),
+    ("transitioning", &["activating", "deactivating"]),
+];
+
+// Parse an active state from a settings file.
+//
+// Rules may name states that killjoy doesn't know of, as newer versions of systemd may add states.
+// An unknown state that's close to a known one is taken to be a typo, though, e.g. "faild".
+pub fn parse_active_state(value: &str) -> Result<ActiveState, CrateError> {
+    let alias_names: Vec<&str> = ACTIVE_STATE_ALIASES.iter().map(|(name, _)| *name).collect();
+    match ActiveState::try_from(value)? {
+        ActiveState::Other(_)
+            if suggest(value, unit::KNOWN_ACTIVE_STATES).is_some()
+                || suggest(value, &alias_names).is_some() =>
+        {
+            Err(CrateError::Settings(SettingsError::InvalidActiveState(
+                value.to_owned(),
+            )))
+        }
+        active_state => Ok(active_state),
+    }
+}
+
+// Parse an active state or an alias for several from a settings file. See `ACTIVE_STATE_ALIASES`.
+//
+// An unknown state that's close to an alias is taken to be a typo too, e.g. "not-activ".
+pub fn parse_active_states(value: &str) -> Result<Vec<ActiveState>, CrateError> {
+    match ACTIVE_STATE_ALIASES.iter().find(|(name, _)| *name == value) {
+        Some((_, states)) => states
+            .iter()
+            .map(|state| ActiveState::try_from(*state))
+            .collect(),
+        None => parse_active_state(value).map(|active_state| vec![active_state]),
+    }
+}
+
+// Get a secret, which the settings file gives either inline, or as the name of a credential. See
+// `read_credential`. Return an error if it gives both.
+fn get_secret(
+    key: &str,
+    value: &Option<String>,
+    credential: &Option<String>,
+) -> Result<Option<String>, CrateError> {
+    match (value, credential) {
+        (Some(_), Some(_)) => Err(CrateError::Settings(SettingsError::ConflictingCredential(
+            key.to_owned(),
+        ))),
+        (Some(value), None) => Ok(Some(value.to_owned())),
+        (None, Some(name)) => read_credential(name).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+// Read the credential with the given name, which systemd passes to killjoy's service, e.g. with
+// `LoadCredential=`. See `read_credential_from`.
+//
+// systemd places credentials in the directory named by $CREDENTIALS_DIRECTORY, so that secrets
+// needn't be stored in the settings file. Return an error if it's unset.
+pub fn read_credential(name: &str) -> Result<String, CrateError> {
+    let dir = env::var_os("CREDENTIALS_DIRECTORY").ok_or_else(|| {
+        CrateError::Settings(SettingsError::CredentialsDirectoryNotSet(name.to_owned()))
+    })?;
+    read_credential_from(Path::new(&dir), name)
+}
+
+// Read the credential with the given name from the given directory.
+//
+// The name must be a plain file name. A trailing newline is removed, as files written by hand or
+// with echo(1) usually end with one.
+pub fn read_credential_from(dir: &Path, name: &str) -> Result<String, CrateError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(CrateError::Settings(SettingsError::InvalidCredentialName(
+            name.to_owned(),
+        )));
+    }
+    let mut secret = fs::read_to_string(dir.join(name))
+        .map_err(|err| CrateError::Settings(SettingsError::ReadCredential(name.to_owned(), err)))?;
+    if secret.ends_with('\n') {
+        secret.pop();
+        if secret.ends_with('\r') {
+            secret.pop();
+        }
+    }
+    Ok(secret)
+}
+
+// Tell whether the given string may be used as a label name.
+//
+// Label names consist of ASCII letters, digits and underscores, and don't start with a digit. This
+// lets them be referenced from message templates, and passed to most monitoring systems.
+pub fn is_valid_label_name(label_name: &str) -> bool {
+    let mut chars = label_name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// The inverse of `decode_bus_type_str`.
+pub fn encode_bus_type(bus_type: BusType) -> &'static str {
+    match bus_type {
+        BusType::Session => "session",
+        BusType::Starter => "starter",
+        BusType::System => "system",
+    }
+}
+
+// Get a deduplicated list of D-Bus bus types in the given list of rules.
+pub fn get_bus_types(rules: &[Rule]) -> Vec<BusType> {
+    // The conversion from BusType → HashableBusType → BusType is a hack. It's done because this
+    // method should deduplicate BusType values, but BusType doesn't implement the traits necessary
+    // to create a HashSet<BusType>.
+    rules
+        .iter()
+        .map(|rule: &Rule| HashableBusType::from(rule.bus_type))
+        .collect::<HashSet<HashableBusType>>()
+        .into_iter()
+        .map(|hashable_bus_type: HashableBusType| hashable_bus_type.into())
+        .collect()
+}
+
+// Search several paths for a settings file, in order of preference.
+//
+// If a file is found, return its path. Otherwise, return an error describing why.
+pub fn get_load_path() -> Result<PathBuf, CrateError> {
+    let prefix = "killjoy";
+    let suffix = "settings.json";
+    BaseDirectories::with_prefix(prefix)
+        .map_err(|_| {
+            CrateError::Settings(SettingsError::SettingsFileNotFound(format!(
+                "{}/{}",
+                prefix, suffix
+            )))
+        })?
+        .find_config_file(suffix)
+        .ok_or_else(|| {
+            CrateError::Settings(SettingsError::SettingsFileNotFound(format!(
+                "{}/{}",
+                prefix, suffix
+            )))
+        })
+}
+
+// Read the configuration file into a Settings object.
+//
+// An error may be returned for one of two broad categories of reasons:
+//
+// *   The file couldn't be opened. Maybe a settings file couldn't be found; or maybe a settings
+//     file was found but could not be opened.
+// *   The file contained invalid contents.
+pub fn load(path_opt: Option<&Path>) -> Result<Settings, CrateError> {
+    let reader = BufReader::new(open(path_opt)?);
+    Settings::new(reader)
+}
+
+// Read the configuration file into a Settings object, reporting every problem found.
+//
+// Unlike `load`, the file is first checked against the settings file schema. Each problem is
+// reported with a JSON pointer to the offending value, and with a hint if the value looks like a
+// typo. Unknown keys are also reported, as they are typically typos of optional keys. If the file
+// matches the schema, it's then converted in the same way as by `load`.
+pub fn validate(path_opt: Option<&Path>) -> Result<Settings, Vec<CrateError>> {
+    let mut contents = String::new();
+    open(path_opt)
+        .map_err(|err| vec![err])?
+        .read_to_string(&mut contents)
+        .map_err(|err| {
+            vec![CrateError::Settings(
+                SettingsError::SettingsFileNotReadable(err),
+            )]
+        })?;
+    validate_str(&contents)
+}
+
+// Like `validate`, but check the contents of a settings file rather than the file itself.
+pub fn validate_str(contents: &str) -> Result<Settings, Vec<CrateError>> {
+    let value: Value = serde_json::from_str(contents).map_err(|err| {
+        vec![CrateError::Settings(
+            SettingsError::SettingsFileDeserializationFailed(err),
+        )]
+    })?;
+    let errs = check_schema(&value);
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    Settings::new(contents.as_bytes()).map_err(|err| vec![err])
+}
+
+// Open the given settings file, or the one found by `get_load_path`.
+fn open(path_opt: Option<&Path>) -> Result<File, CrateError> {
+    let handle_res = match path_opt {
+        Some(path) => File::open(path),
+        None => File::open(get_load_path()?.as_path()),
+    };
+    handle_res.map_err(|err| CrateError::Settings(SettingsError::SettingsFileNotReadable(err)))
+}
+
+// The newest settings file version. Older settings files may be upgraded with `migrate`.
+pub const CURRENT_VERSION: u64 = 1;
+
+// A function which rewrites a settings file from one version to the next.
+type Migration = fn(&mut Map<String, Value>) -> Result<(), CrateError>;
+
+// The migrations between settings file versions, in order. `MIGRATIONS[i]` rewrites a settings file
+// from version `i + 1` to version `i + 2`, so there should be one fewer than `CURRENT_VERSION`.
+const MIGRATIONS: &[Migration] = &[];
+
+// Read a settings file, and return the equivalent settings file in the newest version, as JSON.
+//
+// The migrated file is checked in the same way as by `load` before it is returned. Keys are
+// written in alphabetical order.
+pub fn migrate(path_opt: Option<&Path>) -> Result<String, CrateError> {
+    let value: Value = serde_json::from_reader(BufReader::new(open(path_opt)?)).map_err(|err| {
+        CrateError::Settings(SettingsError::SettingsFileDeserializationFailed(err))
+    })?;
+    let value = migrate_value(value, MIGRATIONS)?;
+    let serde_settings: SerdeSettings = serde_json::from_value(value.clone()).map_err(|err| {
+        CrateError::Settings(SettingsError::SettingsFileDeserializationFailed(err))
+    })?;
+    Settings::try_from(serde_settings)?;
+    serde_json::to_string_pretty(&value)
+        .map_err(|err| CrateError::Settings(SettingsError::SettingsFileNotSerializable(err)))
+}
+
+// Apply `migrations` to a settings file, starting with the one for the file's version.
+fn migrate_value(mut value: Value, migrations: &[Migration]) -> Result<Value, CrateError> {
+    let settings = value.as_object_mut().ok_or_else(|| {
+        CrateError::Settings(SettingsError::WrongSettingsType(String::new(), "an object"))
+    })?;
+    let version = match settings.get("version") {
+        Some(version) => version.as_u64().ok_or_else(|| {
+            CrateError::Settings(SettingsError::WrongSettingsType(
+                "/version".to_string(),
+                "a non-negative integer",
+            ))
+        })?,
+        None => {
+            return Err(CrateError::Settings(SettingsError::MissingSettingsKey(
+                "/version".to_string(),
+            )))
+        }
+    };
+    if version < 1 || version > migrations.len() as u64 + 1 {
+        return Err(CrateError::Settings(
+            SettingsError::UnsupportedSettingsVersion(version),
+        ));
+    }
+    for (i, migration) in migrations.iter().enumerate().skip(version as usize - 1) {
+        migration(settings)?;
+        settings.insert("version".to_string(), Value::from(i as u64 + 2));
+    }
+    Ok(value)
+}
+
+// The keys each object in a settings file may have, and the values some keys may have.
+const SETTINGS_KEYS: &[&str] = &[
+    "boot_summary",
+    "control_socket",
+    "deduplicate_notifications",
+    "defaults",
+    "discovery",
+    "event_log",
+    "event_store",
+    "heartbeat",
+    "history_size",
+    "identity",
+    "notifiers",
+    "polling",
+    "properties_changed_interfaces",
+    "reconcile_interval",
+    "resume_grace_period",
+    "rule_evaluation",
+    "rules",
+    "systemd_calls",
+    "transport",
+    "version",
+    "watch_limit",
+];
+const BOOT_SUMMARY_KEYS: &[&str] = &["bus_type", "notifiers"];
+const WATCH_LIMIT_KEYS: &[&str] = &["max_units", "notifiers"];
+const CONTROL_SOCKET_KEYS: &[&str] = &["path"];
+const DISCOVERY_KEYS: &[&str] = &["bus_type"];
+const DEFAULTS_KEYS: &[&str] = &[
+    "active_states",
+    "bus_type",
+    "ignore_transient",
+    "notifiers",
+    "startup_grace_period",
+];
+const EVENT_LOG_KEYS: &[&str] = &["format", "max_size", "path"];
+const EVENT_STORE_KEYS: &[&str] = &["path"];
+const HEARTBEAT_KEYS: &[&str] = &["interval", "url", "url_credential"];
+const IDENTITY_KEYS: &[&str] = &["hostname", "include_machine_id", "tags"];
+const POLLING_KEYS: &[&str] = &[
+    "loop_timeout",
+    "mount_check_interval",
+    "property_sample_interval",
+    "unit_file_scan_interval",
+];
+const SYSTEMD_CALLS_KEYS: &[&str] = &[
+    "list_timeout",
+    "properties_timeout",
+    "retries",
+    "retry_delay",
+    "timeout",
+    "unit_timeout",
+];
+const RULE_KEYS: &[&str] = &[
+    "action",
+    "action_max_attempts",
+    "action_window",
+    "actions",
+    "active_states",
+    "allowed_units",
+    "bus_type",
+    "expression",
+    "expression_type",
+    "filter",
+    "for",
+    "group_by",
+    "group_window",
+    "ignore_transient",
+    "include_dependents",
+    "journal_lines",
+    "labels",
+    "load_states",
+    "loop_timeout",
+    "name",
+    "notifier_policy",
+    "notifiers",
+    "on_appear",
+    "payload",
+    "portable_states",
+    "priority",
+    "properties",
+    "property_sample_interval",
+    "severity",
+    "startup_grace_period",
+    "sub_states",
+    "suppress",
+    "suppress_severity",
+    "system_states",
+    "treat_unsuccessful_inactive_as_failed",
+    "unit_file_states",
+    "verify_mount_point",
+];
+const ACTION_KEYS: &[&str] = &[
+    "active_states",
+    "command",
+    "max_attempts",
+    "message",
+    "type",
+    "window",
+];
+const PROPERTY_KEYS: &[&str] = &["condition", "duration", "interface", "property", "value"];
+const NOTIFIER_KEYS: &[&str] = &[
+    "accept_tags",
+    "bus_name",
+    "bus_type",
+    "client_id",
+    "clock",
+    "command",
+    "community",
+    "community_credential",
+    "digest_schedule",
+    "facility",
+    "host",
+    "message_template",
+    "mode",
+    "oid",
+    "options",
+    "password",
+    "password_credential",
+    "path",
+    "plaintext_credentials",
+    "port",
+    "protocol_version",
+    "qos",
+    "reject_tags",
+    "resolve_states",
+    "retain",
+    "timeout",
+    "topic",
+    "transport",
+    "type",
+    "url",
+    "url_credential",
+    "username",
+];
+const NOTIFIER_TYPES: &[&str] = &["alertmanager", "dbus", "mqtt", "plugin", "snmp", "syslog"];
+const SYSLOG_TRANSPORTS: &[&str] = &["tcp", "udp", "unix"];
+const SHORTHAND_ACTION_TYPES: &[&str] = &["restart", "start", "stop"];
+const ACTION_TYPES: &[&str] = &[
+    "restart-unit",
+    "run-command",
+    "start-unit",
+    "stop-unit",
+    "write-journal-entry",
+];
+const ACTIVE_STATES: &[&str] = &["activating", "active", "deactivating", "failed", "inactive"];
+const ACTIVE_STATES_AND_ALIASES: &[&str] = &[
+    "*",
+    "activating",
+    "active",
+    "deactivating",
+    "failed",
+    "inactive",
+    "not-active",
+    "transitioning",
+];
+const BUS_TYPES: &[&str] = &["session", "starter", "system"];
+const CLOCKS: &[&str] = &["monotonic", "realtime"];
+const EVENT_LOG_FORMATS: &[&str] = &["csv", "jsonl"];
+const EXPRESSION_TYPES: &[&str] = &[
+    "manager",
+    "portable image",
+    "regex",
+    "unit name",
+    "unit template",
+    "unit type",
+];
+const LOAD_STATES: &[&str] = &[
+    "bad-setting",
+    "error",
+    "loaded",
+    "masked",
+    "merged",
+    "not-found",
+    "stub",
+];
+const NOTIFIER_MODES: &[&str] = &["both", "digest", "realtime"];
+const GROUPINGS: &[&str] = &["slice", "target"];
+const NOTIFIER_POLICIES: &[&str] = &["all", "fallback"];
+const RULE_EVALUATIONS: &[&str] = &["all", "first_match"];
+const TRANSPORTS: &[&str] = &["dbus", "varlink"];
+const PORTABLE_STATES: &[&str] = &[
+    "attached",
+    "attached-runtime",
+    "detached",
+    "enabled",
+    "enabled-runtime",
+    "running",
+    "running-runtime",
+];
+const PROPERTY_CONDITIONS: &[&str] = &["<", ">", "changed", "rate >"];
+const SEVERITIES: &[&str] = &["critical", "debug", "info", "warning"];
+const SYSTEM_STATES: &[&str] = &[
+    "degraded",
+    "initializing",
+    "maintenance",
+    "offline",
+    "running",
+    "starting",
+    "stopping",
+    "unknown",
+];
+const UNIT_FILE_STATES: &[&str] = &[
+    "alias",
+    "attached",
+    "attached-runtime",
+    "bad",
+    "disabled",
+    "enabled",
+    "enabled-runtime",
+    "generated",
+    "indirect",
+    "linked",
+    "linked-runtime",
+    "masked",
+    "masked-runtime",
+    "removed",
+    "static",
+    "transient",
+];
+const VERSIONS: &[&str] = &["1"];
+
+// Check a settings file against the settings file schema, and return every problem found.
+//
+// Only the file's structure is checked, along with values which must be one of a fixed set, such as
+// active states. Other values, such as regexes, are checked when the file is converted to a
+// `Settings` object.
+fn check_schema(value: &Value) -> Vec<CrateError> {
+    let mut errs: Vec<CrateError> = Vec::new();
+    let settings = match check_object(value, "", SETTINGS_KEYS, &["notifiers", "rules"], &mut errs)
+    {
+        Some(settings) => settings,
+        None => return errs,
+    };
+
+    if let Some(version) = settings.get("version") {
+        if version.as_u64().is_none() {
+            errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+                "/version".to_string(),
+                "a non-negative integer",
+            )));
+        } else {
+            check_choice(
+                &version.to_string(),
+                version,
+                "/version",
+                "version",
+                VERSIONS,
+                &mut errs,
+            );
+        }
+    }
+
+    let mut notifier_names: Vec<&str> = Vec::new();
+    if let Some(notifiers) = settings.get("notifiers") {
+        if let Some(notifiers) = check_map(notifiers, "/notifiers", &mut errs) {
+            for (name, notifier) in notifiers {
+                notifier_names.push(name);
+                check_notifier(notifier, &format!("/notifiers/{}", escape(name)), &mut errs);
+            }
+        }
+    }
+
+    if let Some(deduplicate) = settings.get("deduplicate_notifications") {
+        check_boolean(deduplicate, "/deduplicate_notifications", &mut errs);
+    }
+
+    if let Some(history_size) = settings.get("history_size") {
+        check_integer(history_size, "/history_size", &mut errs);
+    }
+
+    if let Some(polling) = settings.get("polling") {
+        let pointer = "/polling";
+        if let Some(polling) = check_object(polling, pointer, POLLING_KEYS, &[], &mut errs) {
+            for key in POLLING_KEYS {
+                if let Some(value) = polling.get(*key) {
+                    check_integer(value, &format!("{}/{}", pointer, key), &mut errs);
+                }
+            }
+        }
+    }
+
+    if let Some(interfaces) = settings.get("properties_changed_interfaces") {
+        let pointer = "/properties_changed_interfaces";
+        if let Some(interfaces) = check_array(interfaces, pointer, &mut errs) {
+            for (i, interface) in interfaces.iter().enumerate() {
+                check_string(interface, &format!("{}/{}", pointer, i), &mut errs);
+            }
+        }
+    }
+
+    if let Some(reconcile_interval) = settings.get("reconcile_interval") {
+        check_integer(reconcile_interval, "/reconcile_interval", &mut errs);
+    }
+
+    if let Some(resume_grace_period) = settings.get("resume_grace_period") {
+        check_integer(resume_grace_period, "/resume_grace_period", &mut errs);
+    }
+
+    if let Some(rule_evaluation) = settings.get("rule_evaluation") {
+        let pointer = "/rule_evaluation";
+        let kind = "rule evaluation mode";
+        check_string_choice(rule_evaluation, pointer, kind, RULE_EVALUATIONS, &mut errs);
+    }
+
+    if let Some(transport) = settings.get("transport") {
+        check_string_choice(transport, "/transport", "transport", TRANSPORTS, &mut errs);
+    }
+
+    if let Some(event_log) = settings.get("event_log") {
+        check_event_log(event_log, "/event_log", &mut errs);
+    }
+
+    if let Some(control_socket) = settings.get("control_socket") {
+        let pointer = "/control_socket";
+        if let Some(control_socket) =
+            check_object(control_socket, pointer, CONTROL_SOCKET_KEYS, &[], &mut errs)
+        {
+            if let Some(path) = control_socket.get("path") {
+                check_string(path, &format!("{}/path", pointer), &mut errs);
+            }
+        }
+    }
+
+    if let Some(event_store) = settings.get("event_store") {
+        let pointer = "/event_store";
+        if let Some(event_store) =
+            check_object(event_store, pointer, EVENT_STORE_KEYS, &[], &mut errs)
+        {
+            if let Some(path) = event_store.get("path") {
+                check_string(path, &format!("{}/path", pointer), &mut errs);
+            }
+        }
+    }
+
+    if let Some(heartbeat) = settings.get("heartbeat") {
+        let pointer = "/heartbeat";
+        let required: &[&str] = match heartbeat.get("url_credential") {
+            Some(_) => &[],
+            None => &["url"],
+        };
+        if let Some(heartbeat) =
+            check_object(heartbeat, pointer, HEARTBEAT_KEYS, required, &mut errs)
+        {
+            if let Some(interval) = heartbeat.get("interval") {
+                check_integer(interval, &format!("{}/interval", pointer), &mut errs);
+            }
+            for key in &["url", "url_credential"] {
+                if let Some(url) = heartbeat.get(*key) {
+                    check_string(url, &format!("{}/{}", pointer, key), &mut errs);
+                }
+            }
+        }
+    }
+
+    if let Some(identity) = settings.get("identity") {
+        let pointer = "/identity";
+        if let Some(identity) = check_object(identity, pointer, IDENTITY_KEYS, &[], &mut errs) {
+            if let Some(hostname) = identity.get("hostname") {
+                check_string(hostname, &format!("{}/hostname", pointer), &mut errs);
+            }
+            if let Some(include_machine_id) = identity.get("include_machine_id") {
+                let pointer = format!("{}/include_machine_id", pointer);
+                check_boolean(include_machine_id, &pointer, &mut errs);
+            }
+            if let Some(tags) = identity.get("tags") {
+                let pointer = format!("{}/tags", pointer);
+                if let Some(tags) = check_map(tags, &pointer, &mut errs) {
+                    for (name, tag) in tags {
+                        check_string(tag, &format!("{}/{}", pointer, escape(name)), &mut errs);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(systemd_calls) = settings.get("systemd_calls") {
+        let pointer = "/systemd_calls";
+        if let Some(systemd_calls) =
+            check_object(systemd_calls, pointer, SYSTEMD_CALLS_KEYS, &[], &mut errs)
+        {
+            for key in SYSTEMD_CALLS_KEYS {
+                if let Some(value) = systemd_calls.get(*key) {
+                    check_integer(value, &format!("{}/{}", pointer, key), &mut errs);
+                }
+            }
+        }
+    }
+
+    if let Some(boot_summary) = settings.get("boot_summary") {
+        let required = &["notifiers"];
+        let pointer = "/boot_summary";
+        if let Some(boot_summary) = check_object(
+            boot_summary,
+            pointer,
+            BOOT_SUMMARY_KEYS,
+            required,
+            &mut errs,
+        ) {
+            // Only known keys remain, and they're checked as for a rule.
+            check_rule_fields(boot_summary, pointer, &notifier_names, &mut errs);
+        }
+    }
+
+    if let Some(watch_limit) = settings.get("watch_limit") {
+        let pointer = "/watch_limit";
+        let required = &["max_units"];
+        if let Some(watch_limit) =
+            check_object(watch_limit, pointer, WATCH_LIMIT_KEYS, required, &mut errs)
+        {
+            if let Some(max_units) = watch_limit.get("max_units") {
+                check_integer(max_units, &format!("{}/max_units", pointer), &mut errs);
+            }
+            // The notifiers are checked as for a rule.
+            check_rule_fields(watch_limit, pointer, &notifier_names, &mut errs);
+        }
+    }
+
+    if let Some(discovery) = settings.get("discovery") {
+        let pointer = "/discovery";
+        if let Some(discovery) = check_object(discovery, pointer, DISCOVERY_KEYS, &[], &mut errs) {
+            check_rule_fields(discovery, pointer, &notifier_names, &mut errs);
+        }
+    }
+
+    if let Some(defaults) = settings.get("defaults") {
+        if let Some(defaults) = check_object(defaults, "/defaults", DEFAULTS_KEYS, &[], &mut errs) {
+            check_rule_fields(defaults, "/defaults", &notifier_names, &mut errs);
+        }
+    }
+
+    if let Some(rules) = settings.get("rules") {
+        if let Some(rules) = check_array(rules, "/rules", &mut errs) {
+            for (i, rule) in rules.iter().enumerate() {
+                let pointer = format!("/rules/{}", i);
+                let required = &["expression", "expression_type"];
+                if let Some(rule) = check_object(rule, &pointer, RULE_KEYS, required, &mut errs) {
+                    check_rule_fields(rule, &pointer, &notifier_names, &mut errs);
+                }
+            }
+        }
+    }
+    errs
+}
+
+// Check the fields of a rule, or of the defaults block.
+fn check_rule_fields(
+    rule: &Map<String, Value>,
+    pointer: &str,
+    notifier_names: &[&str],
+    errs: &mut Vec<CrateError>,
+) {
+    for (key, value) in rule {
+        let pointer = format!("{}/{}", pointer, escape(key));
+        match &key[..] {
+            "action" => {
+                check_string_choice(value, &pointer, "action type", SHORTHAND_ACTION_TYPES, errs)
+            }
+            "actions" => {
+                if let Some(actions) = check_array(value, &pointer, errs) {
+                    for (i, action) in actions.iter().enumerate() {
+                        check_action(action, &format!("{}/{}", pointer, i), errs);
+                    }
+                }
+            }
+            "active_states" => check_choices(
+                value,
+                &pointer,
+                "active state",
+                ACTIVE_STATES_AND_ALIASES,
+                errs,
+            ),
+            "bus_type" => check_string_choice(value, &pointer, "bus type", BUS_TYPES, errs),
+            "expression" | "filter" | "name" => check_string(value, &pointer, errs),
+            "expression_type" => {
+                check_string_choice(value, &pointer, "expression type", EXPRESSION_TYPES, errs)
+            }
+            "group_by" => check_string_choice(value, &pointer, "grouping", GROUPINGS, errs),
+            "ignore_transient"
+            | "include_dependents"
+            | "on_appear"
+            | "treat_unsuccessful_inactive_as_failed"
+            | "verify_mount_point" => check_boolean(value, &pointer, errs),
+            "action_max_attempts"
+            | "action_window"
+            | "for"
+            | "group_window"
+            | "journal_lines"
+            | "loop_timeout"
+            | "priority"
+            | "property_sample_interval"
+            | "startup_grace_period" => check_integer(value, &pointer, errs),
+            "labels" | "payload" => {
+                if let Some(labels) = check_map(value, &pointer, errs) {
+                    for (name, label) in labels {
+                        check_string(label, &format!("{}/{}", pointer, escape(name)), errs);
+                    }
+                }
+            }
+            "load_states" => check_choices(value, &pointer, "load state", LOAD_STATES, errs),
+            "notifier_policy" => {
+                check_string_choice(value, &pointer, "notifier policy", NOTIFIER_POLICIES, errs)
+            }
+            "notifiers" => check_choices(value, &pointer, "notifier", notifier_names, errs),
+            "properties" => {
+                if let Some(properties) = check_array(value, &pointer, errs) {
+                    for (i, property) in properties.iter().enumerate() {
+                        check_property(property, &format!("{}/{}", pointer, i), errs);
+                    }
+                }
+            }
+            "severity" | "suppress_severity" => {
+                check_string_choice(value, &pointer, "severity", SEVERITIES, errs)
+            }
+            "allowed_units" | "sub_states" | "suppress" => {
+                if let Some(windows) = check_array(value, &pointer, errs) {
+                    for (i, window) in windows.iter().enumerate() {
+                        check_string(window, &format!("{}/{}", pointer, i), errs);
+                    }
+                }
+            }
+            "portable_states" => check_choices(
+                value,
+                &pointer,
+                "portable image state",
+                PORTABLE_STATES,
+                errs,
+            ),
+            "system_states" => check_choices(value, &pointer, "system state", SYSTEM_STATES, errs),
+            "unit_file_states" => {
+                check_choices(value, &pointer, "unit file state", UNIT_FILE_STATES, errs)
+            }
+            _ => {} // Unknown keys are reported by check_object().
+        }
+    }
+}
+
+// Check an action in a rule.
+fn check_action(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    let action = match check_object(value, pointer, ACTION_KEYS, &["type"], errs) {
+        Some(action) => action,
+        None => return,
+    };
+    for (key, value) in action {
+        let pointer = format!("{}/{}", pointer, escape(key));
+        match &key[..] {
+            "active_states" => check_choices(
+                value,
+                &pointer,
+                "active state",
+                ACTIVE_STATES_AND_ALIASES,
+                errs,
+            ),
+            "command" => {
+                if let Some(args) = check_array(value, &pointer, errs) {
+                    for (i, arg) in args.iter().enumerate() {
+                        check_string(arg, &format!("{}/{}", pointer, i), errs);
+                    }
+                }
+            }
+            "max_attempts" | "window" => check_integer(value, &pointer, errs),
+            "message" => check_string(value, &pointer, errs),
+            "type" => check_string_choice(value, &pointer, "action type", ACTION_TYPES, errs),
+            _ => {}
+        }
+    }
+}
+
+// Check a property condition in a rule.
+fn check_property(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    let required = &["condition", "property"];
+    let property = match check_object(value, pointer, PROPERTY_KEYS, required, errs) {
+        Some(property) => property,
+        None => return,
+    };
+    for (key, value) in property {
+        let pointer = format!("{}/{}", pointer, escape(key));
+        match &key[..] {
+            "condition" => {
+                check_string_choice(value, &pointer, "condition", PROPERTY_CONDITIONS, errs)
+            }
+            "interface" | "property" => check_string(value, &pointer, errs),
+            "duration" | "value" => check_integer(value, &pointer, errs),
+            _ => {}
+        }
+    }
+}
+
+// Check the event log.
+fn check_event_log(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    let event_log = match check_object(value, pointer, EVENT_LOG_KEYS, &["path"], errs) {
+        Some(event_log) => event_log,
+        None => return,
+    };
+    for (key, value) in event_log {
+        let pointer = format!("{}/{}", pointer, escape(key));
+        match &key[..] {
+            "format" => check_string_choice(value, &pointer, "format", EVENT_LOG_FORMATS, errs),
+            "max_size" => check_integer(value, &pointer, errs),
+            "path" => check_string(value, &pointer, errs),
+            _ => {}
+        }
+    }
+}
+
+// Check a notifier.
+fn check_notifier(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    let required: &[&str] = match value.get("type").and_then(Value::as_str) {
+        Some("alertmanager") if value.get("url_credential").is_some() => &[],
+        Some("alertmanager") => &["url"],
+        Some("mqtt") | Some("snmp") => &["host"],
+        Some("plugin") => &["command"],
+        Some("syslog") => match value.get("transport").and_then(Value::as_str) {
+            Some("tcp") | Some("udp") => &["host"],
+            _ => &[],
+        },
+        _ => &["bus_name", "bus_type"],
+    };
+    let notifier = match check_object(value, pointer, NOTIFIER_KEYS, required, errs) {
+        Some(notifier) => notifier,
+        None => return,
+    };
+    for (key, value) in notifier {
+        let pointer = format!("{}/{}", pointer, escape(key));
+        match &key[..] {
+            "bus_name"
+            | "client_id"
+            | "community"
+            | "community_credential"
+            | "digest_schedule"
+            | "host"
+            | "message_template"
+            | "oid"
+            | "password"
+            | "password_credential"
+            | "path"
+            | "topic"
+            | "url"
+            | "url_credential"
+            | "username" => check_string(value, &pointer, errs),
+            "bus_type" => check_string_choice(value, &pointer, "bus type", BUS_TYPES, errs),
+            "clock" => check_string_choice(value, &pointer, "clock", CLOCKS, errs),
+            "accept_tags" | "command" | "reject_tags" => {
+                if let Some(items) = check_array(value, &pointer, errs) {
+                    for (i, item) in items.iter().enumerate() {
+                        check_string(item, &format!("{}/{}", pointer, i), errs);
+                    }
+                }
+            }
+            "facility" => {
+                let facilities: Vec<&str> =
+                    SYSLOG_FACILITIES.iter().map(|(name, _)| *name).collect();
+                check_string_choice(value, &pointer, "syslog facility", &facilities, errs)
+            }
+            "mode" => check_string_choice(value, &pointer, "notifier mode", NOTIFIER_MODES, errs),
+            "port" | "protocol_version" | "qos" | "timeout" => check_integer(value, &pointer, errs),
+            "options" => {
+                check_map(value, &pointer, errs);
+            }
+            "resolve_states" => check_choices(value, &pointer, "active state", ACTIVE_STATES, errs),
+            "plaintext_credentials" | "retain" => check_boolean(value, &pointer, errs),
+            "transport" => {
+                check_string_choice(value, &pointer, "syslog transport", SYSLOG_TRANSPORTS, errs)
+            }
+            "type" => check_string_choice(value, &pointer, "notifier type", NOTIFIER_TYPES, errs),
+            _ => {}
+        }
+    }
+}
+
+// Check that a value is an object with the given keys, and return it if so.
+fn check_object<'a>(
+    value: &'a Value,
+    pointer: &str,
+    keys: &[&str],
+    required: &[&str],
+    errs: &mut Vec<CrateError>,
+) -> Option<&'a Map<String, Value>> {
+    let object = check_map(value, pointer, errs)?;
+    for key in required {
+        if !object.contains_key(*key) {
+            errs.push(CrateError::Settings(SettingsError::MissingSettingsKey(
+                format!("{}/{}", pointer, escape(key)),
+            )));
+        }
+    }
+    for key in object.keys() {
+        if !keys.contains(&&key[..]) {
+            errs.push(CrateError::Settings(SettingsError::UnknownSettingsKey(
+                format!("{}/{}", pointer, escape(key)),
+                suggest(key, keys),
+            )));
+        }
+    }
+    Some(object)
+}
+
+// Check that a value is an object, and return it if so. Its keys aren't checked.
+fn check_map<'a>(
+    value: &'a Value,
+    pointer: &str,
+    errs: &mut Vec<CrateError>,
+) -> Option<&'a Map<String, Value>> {
+    let object = value.as_object();
+    if object.is_none() {
+        errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "an object",
+        )));
+    }
+    object
+}
+
+// Check that a value is an array, and return it if so.
+fn check_array<'a>(
+    value: &'a Value,
+    pointer: &str,
+    errs: &mut Vec<CrateError>,
+) -> Option<&'a Vec<Value>> {
+    let array = value.as_array();
+    if array.is_none() {
+        errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "an array",
+        )));
+    }
+    array
+}
+
+// Check that a value is a string.
+fn check_string(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    if !value.is_string() {
+        errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "a string",
+        )));
+    }
+}
+
+// Check that a value is a boolean.
+fn check_boolean(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    if !value.is_boolean() {
+        errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "a boolean",
+        )));
+    }
+}
+
+// Check that a value is a non-negative integer.
+fn check_integer(value: &Value, pointer: &str, errs: &mut Vec<CrateError>) {
+    if value.as_u64().is_none() {
+        errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "a non-negative integer",
+        )));
+    }
+}
+
+// Check that a value is a string from `choices`.
+fn check_string_choice(
+    value: &Value,
+    pointer: &str,
+    kind: &'static str,
+    choices: &[&str],
+    errs: &mut Vec<CrateError>,
+) {
+    match value.as_str() {
+        Some(choice) => check_choice(choice, value, pointer, kind, choices, errs),
+        None => errs.push(CrateError::Settings(SettingsError::WrongSettingsType(
+            pointer.to_string(),
+            "a string",
+        ))),
+    }
+}
+
+// Check that a value is an array of strings from `choices`.
+fn check_choices(
+    value: &Value,
+    pointer: &str,
+    kind: &'static str,
+    choices: &[&str],
+    errs: &mut Vec<CrateError>,
+) {
+    if let Some(array) = check_array(value, pointer, errs) {
+        for (i, item) in array.iter().enumerate() {
+            check_string_choice(item, &format!("{}/{}", pointer, i), kind, choices, errs);
+        }
+    }
+}
+
+// Check that `choice`, the string form of `value`, is one of `choices`.
+fn check_choice(
+    choice: &str,
+    value: &Value,
+    pointer: &str,
+    kind: &'static str,
+    choices: &[&str],
+    errs: &mut Vec<CrateError>,
+) {
+    if !choices.contains(&choice) {
+        errs.push(CrateError::Settings(SettingsError::InvalidSettingsValue(
+            pointer.to_string(),
+            value.to_string(),
+            kind,
+            suggest(choice, choices),
+        )));
+    }
+}
+
+// Escape a key for use in a JSON pointer, as per RFC 6901.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+// Get the candidate closest to `value`, if any is close enough to suggest that `value` is a typo.
+fn suggest(value: &str, candidates: &[&str]) -> Option<String> {
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(value, candidate), candidate))
+        .filter(|(distance, candidate)| *distance * 3 <= value.len().max(candidate.len()))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+// Get the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut prev_diag = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = prev_diag + usize::from(a_char != *b_char);
+            prev_diag = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(prev_diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use crate::settings::{Expression, NotifierPolicy, Rule, Severity};
+    use dbus::BusType;
+    use std::collections::{HashMap, HashSet};
+
+    pub fn gen_session_rule() -> Rule {
+        Rule {
+            actions: Vec::new(),
+            active_states: HashSet::new(),
+            allowed_units: HashSet::new(),
+            bus_type: BusType::Session,
+            expression: Expression::UnitName("".to_string()),
+            filter: None,
+            for_duration: None,
+            group_by: None,
+            group_window: Duration::from_secs(DEFAULT_GROUP_WINDOW),
+            ignore_transient: false,
+            include_dependents: false,
+            journal_lines: 0,
+            labels: HashMap::new(),
+            load_states: HashSet::new(),
+            loop_timeout: None,
+            name: None,
+            notifier_policy: NotifierPolicy::All,
+            notifiers: Vec::new(),
+            on_appear: false,
+            payload: HashMap::new(),
+            portable_states: HashSet::new(),
+            priority: 0,
+            properties: Vec::new(),
+            property_sample_interval: None,
+            severity: Severity::Warning,
+            startup_grace_period: None,
+            sub_states: HashSet::new(),
+            suppress: Vec::new(),
+            suppress_severity: None,
+            system_states: HashSet::new(),
+            treat_unsuccessful_inactive_as_failed: false,
+            unit_file_states: HashSet::new(),
+            verify_mount_point: false,
+        }
+    }
+
+    pub fn gen_system_rule() -> Rule {
+        Rule {
+            actions: Vec::new(),
+            active_states: HashSet::new(),
+            allowed_units: HashSet::new(),
+            bus_type: BusType::System,
+            expression: Expression::UnitName("".to_string()),
+            filter: None,
+            for_duration: None,
+            group_by: None,
+            group_window: Duration::from_secs(DEFAULT_GROUP_WINDOW),
+            ignore_transient: false,
+            include_dependents: false,
+            journal_lines: 0,
+            labels: HashMap::new(),
+            load_states: HashSet::new(),
+            loop_timeout: None,
+            name: None,
+            notifier_policy: NotifierPolicy::All,
+            notifiers: Vec::new(),
+            on_appear: false,
+            payload: HashMap::new(),
+            portable_states: HashSet::new(),
+            priority: 0,
+            properties: Vec::new(),
+            property_sample_interval: None,
+            severity: Severity::Warning,
+            startup_grace_period: None,
+            sub_states: HashSet::new(),
+            suppress: Vec::new(),
+            suppress_severity: None,
+            system_states: HashSet::new(),
+            treat_unsuccessful_inactive_as_failed: false,
+            unit_file_states: HashSet::new(),
+            verify_mount_point: false,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::actions::ActionType;
+    use crate::property::Comparison;
+
+    // parse_active_state()
+    #[test]
+    fn test_parse_active_state() {
+        assert_eq!(parse_active_state("failed").unwrap(), ActiveState::Failed);
+        assert_eq!(
+            parse_active_state("maintenance").unwrap(),
+            ActiveState::Other("maintenance".to_string())
+        );
+        match parse_active_state("activatng") {
+            Err(CrateError::Settings(SettingsError::InvalidActiveState(_))) => {}
+            _ => panic!("expected InvalidActiveState; an active state has been typo'd"),
+        }
+    }
+
+    // parse_active_states()
+    #[test]
+    fn test_parse_active_states() {
+        assert_eq!(
+            parse_active_states("failed").unwrap(),
+            vec![ActiveState::Failed]
+        );
+        assert_eq!(
+            parse_active_states("not-active").unwrap(),
+            vec![
+                ActiveState::Deactivating,
+                ActiveState::Failed,
+                ActiveState::Inactive
+            ]
+        );
+        assert_eq!(
+            parse_active_states("*").unwrap().len(),
+            unit::KNOWN_ACTIVE_STATES.len()
+        );
+        match parse_active_states("not-activ") {
+            Err(CrateError::Settings(SettingsError::InvalidActiveState(_))) => {}
+            _ => panic!("expected InvalidActiveState; an alias has been typo'd"),
+        }
+    }
+
+    // encode_bus_type()
+    #[test]
+    fn test_encode_bus_type() {
+        for bus_type_str in ["session", "starter", "system"] {
+            let bus_type = decode_bus_type_str(bus_type_str).expect("Failed to decode bus type.");
+            assert_eq!(encode_bus_type(bus_type), bus_type_str);
+        }
+    }
+
+    // get_bus_types()
+    #[test]
+    fn test_get_bus_types_v1() {
+        let settings = Settings {
+            boot_summary: None,
+            control_socket: None,
+            deduplicate_notifications: true,
+            discovery: None,
+            event_log: None,
+            event_store: None,
+            heartbeat: None,
+            history_size: 0,
+            identity: Identity::default(),
+            notifiers: HashMap::new(),
+            polling: Polling::default(),
+            properties_changed_interfaces: Vec::new(),
+            reconcile_interval: None,
+            resume_grace_period: None,
+            rule_evaluation: RuleEvaluation::All,
+            rules: Vec::new(),
+            systemd_calls: SystemdCalls::default(),
+            transport: Transport::DBus,
+            watch_limit: None,
+        };
+        let bus_types = get_bus_types(&settings.rules);
+        assert!(!bus_types.contains(&BusType::Session));
+        assert!(!bus_types.contains(&BusType::System));
+    }
+
+    // get_bus_types()
+    #[test]
+    fn test_get_bus_types_v2() {
+        let settings = Settings {
+            boot_summary: None,
+            control_socket: None,
+            deduplicate_notifications: true,
+            discovery: None,
+            event_log: None,
+            event_store: None,
+            heartbeat: None,
+            history_size: 0,
+            identity: Identity::default(),
+            notifiers: HashMap::new(),
+            polling: Polling::default(),
+            properties_changed_interfaces: Vec::new(),
+            reconcile_interval: None,
+            resume_grace_period: None,
+            rule_evaluation: RuleEvaluation::All,
+            rules: vec![test_utils::gen_session_rule()],
+            systemd_calls: SystemdCalls::default(),
+            transport: Transport::DBus,
+            watch_limit: None,
+        };
+        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
+        assert!(bus_types.contains(&BusType::Session));
+        assert!(!bus_types.contains(&BusType::System));
+    }
+
+    // get_bus_types()
+    #[test]
+    fn test_get_bus_types_v3() {
+        let settings = Settings {
+            boot_summary: None,
+            control_socket: None,
+            deduplicate_notifications: true,
+            discovery: None,
+            event_log: None,
+            event_store: None,
+            heartbeat: None,
+            history_size: 0,
+            identity: Identity::default(),
+            notifiers: HashMap::new(),
+            polling: Polling::default(),
+            properties_changed_interfaces: Vec::new(),
+            reconcile_interval: None,
+            resume_grace_period: None,
+            rule_evaluation: RuleEvaluation::All,
+            rules: vec![test_utils::gen_system_rule()],
+            systemd_calls: SystemdCalls::default(),
+            transport: Transport::DBus,
+            watch_limit: None,
+        };
+        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
+        assert!(!bus_types.contains(&BusType::Session));
+        assert!(bus_types.contains(&BusType::System));
+    }
+
+    // get_bus_types()
+    #[test]
+    fn test_get_bus_types_v4() {
+        let settings = Settings {
+            boot_summary: None,
+            control_socket: None,
+            deduplicate_notifications: true,
+            discovery: None,
+            event_log: None,
+            event_store: None,
+            heartbeat: None,
+            history_size: 0,
+            identity: Identity::default(),
+            notifiers: HashMap::new(),
+            polling: Polling::default(),
+            properties_changed_interfaces: Vec::new(),
+            reconcile_interval: None,
+            resume_grace_period: None,
+            rule_evaluation: RuleEvaluation::All,
+            rules: vec![
+                test_utils::gen_session_rule(),
+                test_utils::gen_system_rule(),
+            ],
+            systemd_calls: SystemdCalls::default(),
+            transport: Transport::DBus,
+            watch_limit: None,
+        };
+        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
+        assert!(bus_types.contains(&BusType::Session));
+        assert!(bus_types.contains(&BusType::System));
+    }
+
+    // Expression::UnitName::matches()
+    #[test]
+    fn test_expression_unit_name_matches_success() {
+        let unit_name = "aaa.service";
+        let expression = Expression::UnitName("aaa.service".to_string());
+        assert!(expression.matches(unit_name));
+    }
+
+    // Expression::UnitName::matches()
+    #[test]
+    fn test_expression_unit_name_matches_failure() {
+        let unit_name = "aaa.service";
+        let expression = Expression::UnitName("aa.service".to_string());
+        assert!(!expression.matches(unit_name));
+    }
+
+    // Expression::UnitType::matches()
+    #[test]
+    fn test_expression_unit_type_matches_success() {
+        let unit_name = "aaa.service";
+        let expression = Expression::UnitType(".service".to_string());
+        assert!(expression.matches(unit_name));
+    }
+
+    // Expression::UnitType::matches()
+    #[test]
+    fn test_expression_unit_type_matches_failure() {
+        let unit_name = "aaa.service";
+        let expression = Expression::UnitType(".mount".to_string());
+        assert!(!expression.matches(unit_name));
+    }
+
+    // Expression::UnitTemplate::matches()
+    #[test]
+    fn test_expression_unit_template_matches() {
+        let expression = Expression::UnitTemplate("getty@.service".to_string());
+        assert!(expression.matches("getty@tty1.service"));
+        assert!(!expression.matches("getty@.service"));
+        assert!(!expression.matches("getty@tty1.socket"));
+        assert!(!expression.matches("serial-getty@ttyS0.service"));
+        assert!(!expression.matches("getty.service"));
+    }
+
+    // is_valid_unit_template()
+    #[test]
+    fn test_is_valid_unit_template() {
+        assert!(is_valid_unit_template("getty@.service"));
+        assert!(!is_valid_unit_template("getty@tty1.service"));
+        assert!(!is_valid_unit_template("@.service"));
+        assert!(!is_valid_unit_template("getty@."));
+        assert!(!is_valid_unit_template("getty.service"));
+    }
+
+    // Expression::UnitRegex::matches()
+    #[test]
+    fn test_expression_regex_matches() {
+        let expression =
+            Expression::Regex(Regex::new(r"a\.service").expect("Failed to compile regex."));
+        assert!(!expression.matches(".service"));
+        assert!(expression.matches("a.service"));
+        assert!(expression.matches("aa.service"));
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_deserialization_failed() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session",
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::SettingsFileDeserializationFailed(_))) => {}
+            _ => panic!("expected DeserializationFailed; an extra comma has been added"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_active_state() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failedd"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidActiveState(_))) => {}
+            _ => panic!("expected InvalidActiveState; an active state has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_load_states() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": [],
+                        "bus_type": "session",
+                        "expression": ".service",
+                        "expression_type": "unit type",
+                        "load_states": ["bad-setting", "error", "not-found"],
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert_eq!(settings.rules[0].load_states.len(), 3);
+        assert!(settings.rules[0].load_states.contains(&LoadState::NotFound));
+        assert!(!settings.rules[0].include_dependents);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_include_dependents() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "include_dependents": true,
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert!(settings.rules[0].include_dependents);
+        assert_eq!(settings.rules[0].journal_lines, 0);
+        assert!(!settings.rules[0].treat_unsuccessful_inactive_as_failed);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_treat_unsuccessful_inactive_as_failed() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "backup.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"],
+                        "treat_unsuccessful_inactive_as_failed": true
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert!(settings.rules[0].treat_unsuccessful_inactive_as_failed);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_system_states() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "bus_type": "system",
+                        "expression": "SystemState",
+                        "expression_type": "manager",
+                        "notifiers": ["desktop popup"],
+                        "system_states": ["degraded", "running"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        let rule = &settings.rules[0];
+        assert!(!rule.expression.matches("SystemState"));
+        assert_eq!(rule.system_states.len(), 2);
+        assert!(rule.system_states.contains(&SystemState::Degraded));
+
+        let settings_str = settings_str.replace(
+            r#""expression": "SystemState""#,
+            r#""expression": "Version""#,
+        );
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidManagerProperty(_))) => {}
+            _ => panic!("expected InvalidManagerProperty; only SystemState may be watched"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_portable_states() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo",
+                        "expression_type": "portable image",
+                        "notifiers": [],
+                        "portable_states": ["attached", "detached"],
+                        "unit_file_states": ["attached", "removed"]
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        let rule = &settings.rules[0];
+        assert!(rule.expression.matches("foo.service"));
+        assert!(rule.expression.matches("foo-bar.socket"));
+        assert!(rule.expression.matches("foo@1.service"));
+        assert!(!rule.expression.matches("foobar.service"));
+        assert_eq!(rule.portable_states.len(), 2);
+        assert!(rule.portable_states.contains(&PortableState::Detached));
+        assert!(rule.unit_file_states.contains(&UnitFileState::Attached));
+
+        let settings_str = settings_str.replace(r#"["attached", "detached"]"#, r#"["atached"]"#);
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidPortableState(_))) => {}
+            _ => panic!("expected InvalidPortableState; a portable image state has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_verify_mount_point() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "bus_type": "system",
+                        "expression": "srv-nfs.mount",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"],
+                        "verify_mount_point": true
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert!(settings.rules[0].verify_mount_point);
+        assert!(settings.rules[0].active_states.is_empty());
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_polling() {
+        let settings_str = r###"
+            {
+                "polling": {"loop_timeout": 30000, "property_sample_interval": 60},
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "loop_timeout": 500,
+                        "notifiers": ["logfile"],
+                        "property_sample_interval": 5
+                }],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert_eq!(settings.polling.get_loop_timeout(1000), 30000);
+        assert_eq!(
+            settings.polling.property_sample_interval,
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            settings.polling.unit_file_scan_interval,
+            Duration::from_secs(DEFAULT_UNIT_FILE_SCAN_INTERVAL)
+        );
+        let rule = &settings.rules[0];
+        assert_eq!(rule.loop_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(rule.property_sample_interval, Some(Duration::from_secs(5)));
+        assert_eq!(Polling::default().get_loop_timeout(1000), 1000);
+
+        let invalid = settings_str.replace(
+            "\"property_sample_interval\": 5",
+            "\"property_sample_interval\": 0",
+        );
+        match Settings::new(invalid.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidPollingInterval(_))) => {}
+            _ => panic!("expected InvalidPollingInterval; an interval is zero"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_sub_states() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["active"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["logfile"],
+                        "sub_states": ["exited"]
+                }],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert!(settings.rules[0].sub_states.contains("exited"));
+
+        let invalid = settings_str.replace("\"exited\"", "\"Exited\"");
+        match Settings::new(invalid.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidSubState(_))) => {}
+            _ => panic!("expected InvalidSubState; a sub-state is invalid"),
+        }
+
+        let invalid = settings_str.replace("[\"active\"]", "[]");
+        match Settings::new(invalid.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingActiveStatesForSubStates(_))) => {}
+            _ => panic!("expected MissingActiveStatesForSubStates; active states are missing"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_on_appear() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "allowed_units": ["sshd.service"],
+                        "bus_type": "system",
+                        "expression": "\\.service$",
+                        "expression_type": "regex",
+                        "notifiers": ["desktop popup"],
+                        "on_appear": true
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert!(settings.rules[0].on_appear);
+        assert!(settings.rules[0].allowed_units.contains("sshd.service"));
+        assert!(settings.rules[0].active_states.is_empty());
+
+        let settings_str = settings_str.replace("[\"sshd.service\"]", "[1]");
+        let value: Value = serde_json::from_str(&settings_str).expect("Failed to parse JSON.");
+        let errs: Vec<String> = check_schema(&value)
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(errs, vec!["/rules/0/allowed_units/0: expected a string"]);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_journal_lines() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "journal_lines": 20,
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        assert_eq!(settings.rules[0].journal_lines, 20);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_load_state() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "load_states": ["not found"],
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidLoadState(_))) => {}
+            _ => panic!("expected InvalidLoadState; a load state has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_actions() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "actions": [
+                            {"type": "restart-unit", "max_attempts": 5},
+                            {"type": "write-journal-entry", "active_states": ["failed"]}
+                        ],
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings =
+            Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        let actions = &settings.rules[0].actions;
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].action_type, ActionType::RestartUnit);
+        assert_eq!(actions[0].max_attempts, 5);
+        assert_eq!(actions[1].action_type, ActionType::WriteJournalEntry(None));
+    }
+
+    // Settings::new(), validate_str(), for the `action` shorthand of `actions`.
+    #[test]
+    fn test_settings_new_action_shorthand() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "action": "restart",
+                        "action_max_attempts": 5,
+                        "actions": [{"type": "write-journal-entry"}],
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = validate_str(settings_str).expect("valid settings parsed as invalid");
+        let actions = &settings.rules[0].actions;
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[0].action_type, ActionType::RestartUnit);
+        assert_eq!(actions[0].max_attempts, 5);
+        assert_eq!(actions[0].window, Duration::from_secs(600));
+        assert!(actions[0].active_states.is_empty());
+        assert_eq!(actions[1].action_type, ActionType::WriteJournalEntry(None));
+
+        let settings_str = settings_str.replace("\"restart\"", "\"restart-unit\"");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidActionType(_))) => {}
+            _ => panic!("expected InvalidActionType; the shorthand has no restart-unit"),
+        }
+        let errs = validate_str(&settings_str).expect_err("invalid settings parsed as valid");
+        assert_eq!(errs.len(), 1);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_action_type() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "actions": [{"type": "reboot"}],
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidActionType(_))) => {}
+            _ => panic!("expected InvalidActionType; an unsupported action has been requested"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_bus_name() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name/jerebear/KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidBusName(_))) => {}
+            _ => panic!("expected InvalidBusName; a bus name has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_bus_type_v1() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "sessionn",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidBusType(_))) => {}
+            _ => panic!("expected InvalidBusType; a bus type has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_bus_type_v2() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "sessionn"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidBusType(_))) => {}
+            _ => panic!("expected InvalidBusType; a bus type has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_expression_type() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit namee",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidExpressionType(_))) => {}
+            _ => panic!("expected InvalidExpressionType; an expression type has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_unit_file_states() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "unit_file_states": ["masked", "removed"]
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let unit_file_states = &settings.rules[0].unit_file_states;
+        assert_eq!(unit_file_states.len(), 2);
+        assert!(unit_file_states.contains(&UnitFileState::Masked));
+        assert!(unit_file_states.contains(&UnitFileState::Removed));
+        assert!(settings.rules[0].active_states.is_empty());
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_properties() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "properties": [
+                            {
+                                "property": "MemoryCurrent",
+                                "condition": ">",
+                                "value": 1024,
+                                "duration": 300
+                            },
+                            {
+                                "interface": "org.freedesktop.systemd1.Unit",
+                                "property": "SubState",
+                                "condition": "changed"
+                            }
+                        ]
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let properties = &settings.rules[0].properties;
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties[0].comparison, Comparison::GreaterThan(1024));
+        assert_eq!(properties[0].duration, Duration::from_secs(300));
+        assert_eq!(
+            properties[0].get_interface("foo.service"),
+            Some("org.freedesktop.systemd1.Service".to_string())
+        );
+        assert_eq!(properties[1].comparison, Comparison::Changed);
+        assert_eq!(
+            properties[1].get_interface("foo.service"),
+            Some("org.freedesktop.systemd1.Unit".to_string())
+        );
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_unit_template() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "getty@tty1.service",
+                        "expression_type": "unit template",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidUnitTemplate(_))) => {}
+            _ => {
+                panic!("expected InvalidUnitTemplate; an instance was given instead of a template")
+            }
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_regex() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "{",
+                        "expression_type": "regex",
+                        "notifiers": ["desktop popup"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidRegex(_))) => {}
+            _ => panic!("expected InvalidRegex; a regex has been typo'd"),
+        }
+    }
+
+    // read_credential_from()
+    #[test]
+    fn test_read_credential_from() {
+        let dir = tempfile::TempDir::new().expect("Failed to create temporary directory.");
+        fs::write(dir.path().join("mqtt-password"), "hunter2\n").expect("Failed to write.");
+        assert_eq!(
+            read_credential_from(dir.path(), "mqtt-password").expect("Failed to read credential."),
+            "hunter2"
+        );
+        match read_credential_from(dir.path(), "missing") {
+            Err(CrateError::Settings(SettingsError::ReadCredential(..))) => {}
+            _ => panic!("expected ReadCredential; the credential doesn't exist"),
+        }
+        for name in &["", "..", "../mqtt-password"] {
+            match read_credential_from(dir.path(), name) {
+                Err(CrateError::Settings(SettingsError::InvalidCredentialName(..))) => {}
+                _ => panic!("expected InvalidCredentialName for {:?}", name),
+            }
+        }
+    }
+
+    // get_secret()
+    #[test]
+    fn test_get_secret() {
+        let secret = Some("hunter2".to_string());
+        assert_eq!(get_secret("password", &secret, &None).unwrap(), secret);
+        assert_eq!(get_secret("password", &None, &None).unwrap(), None);
+        match get_secret("password", &secret, &Some("mqtt-password".to_string())) {
+            Err(CrateError::Settings(SettingsError::ConflictingCredential(key))) => {
+                assert_eq!(key, "password")
+            }
+            _ => panic!("expected ConflictingCredential; both keys are set"),
+        }
+    }
+
+    // is_valid_label_name()
+    #[test]
+    fn test_is_valid_label_name() {
+        for label_name in ["team", "_private", "tier2", "Team_Name"] {
+            assert!(is_valid_label_name(label_name), "{}", label_name);
+        }
+        for label_name in ["", "2tier", "team-name", "team name", "équipe"] {
+            assert!(!is_valid_label_name(label_name), "{}", label_name);
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_name_and_labels() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "postgresql.service",
+                        "expression_type": "unit name",
+                        "labels": {"severity": "critical", "team": "db"},
+                        "name": "database down",
+                        "notifiers": [],
+                        "payload": {"runbook": "https://wiki.example.com/db down"}
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": ".timer",
+                        "expression_type": "unit type",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rules[0].name.as_deref(), Some("database down"));
+        assert_eq!(settings.rules[0].labels["team"], "db");
+        assert_eq!(settings.rules[1].name, None);
+        assert!(settings.rules[1].labels.is_empty());
+        assert_eq!(
+            settings.rules[0].payload["runbook"],
+            "https://wiki.example.com/db down"
+        );
+        assert!(settings.rules[1].payload.is_empty());
+    }
+
+    // Severity::try_from()
+    #[test]
+    fn test_severity_try_from() {
+        for severity_str in ["debug", "info", "warning", "critical"] {
+            let severity = Severity::try_from(severity_str)
+                .unwrap_or_else(|_| panic!("Failed to create Severity from {}", severity_str));
+            assert_eq!(String::from(severity), severity_str);
+        }
+        assert!(Severity::try_from("fatal").is_err());
+    }
+
+    // Severity::syslog_priority()
+    #[test]
+    fn test_severity_syslog_priority() {
+        assert!(Severity::Debug < Severity::Critical);
+        assert!(Severity::Debug.syslog_priority() > Severity::Critical.syslog_priority());
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_severity() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "postgresql.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "severity": "critical"
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": ".timer",
+                        "expression_type": "unit type",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rules[0].severity, Severity::Critical);
+        assert_eq!(settings.rules[1].severity, Severity::Warning);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_severity() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "severity": "fatal"
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidSeverity(_))) => {}
+            _ => panic!("expected InvalidSeverity; an unknown severity has been given"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_notifier_policy() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "postgresql.service",
+                        "expression_type": "unit name",
+                        "notifier_policy": "fallback",
+                        "notifiers": []
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": ".timer",
+                        "expression_type": "unit type",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rules[0].notifier_policy, NotifierPolicy::Fallback);
+        assert_eq!(settings.rules[1].notifier_policy, NotifierPolicy::All);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_notifier_policy() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifier_policy": "first",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidNotifierPolicy(_))) => {}
+            _ => panic!("expected InvalidNotifierPolicy; an unknown policy has been given"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_for_duration() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "for": 30,
+                        "notifiers": []
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "bar.service",
+                        "expression_type": "unit name",
+                        "for": 0,
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.rules[0].for_duration,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(settings.rules[1].for_duration, None);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_group_by() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": ".service",
+                        "expression_type": "unit type",
+                        "group_by": "slice",
+                        "group_window": 30,
+                        "notifiers": []
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": ".timer",
+                        "expression_type": "unit type",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rules[0].group_by, Some(GroupBy::Slice));
+        assert_eq!(settings.rules[0].group_window, Duration::from_secs(30));
+        assert_eq!(settings.rules[1].group_by, None);
+        assert_eq!(
+            settings.rules[1].group_window,
+            Duration::from_secs(DEFAULT_GROUP_WINDOW)
+        );
+
+        let bad_settings_str = settings_str.replace("\"slice\"", "\"cgroup\"");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidGroupBy(_))) => {}
+            _ => panic!("expected InvalidGroupBy; an unknown grouping has been given"),
+        }
+        let bad_settings_str = settings_str.replace("30", "0");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidGroupWindow)) => {}
+            _ => panic!("expected InvalidGroupWindow; the group window is zero"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_rule_evaluation() {
+        let settings_str = r###"
+            {
+                "rule_evaluation": "first_match",
+                "rules": [
+                    {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": ".service",
+                        "expression_type": "unit type",
+                        "notifiers": []
+                    },
+                    {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "priority": 10
+                    }
+                ],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rule_evaluation, RuleEvaluation::FirstMatch);
+        assert_eq!(settings.rules[0].priority, 0);
+        assert_eq!(settings.rules[1].priority, 10);
+
+        let settings_str = settings_str.replace("\"first_match\"", "\"first\"");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidRuleEvaluation(_))) => {}
+            _ => panic!("expected InvalidRuleEvaluation; an unknown mode has been given"),
+        }
+    }
+
+    // Settings::new(), with a transport
+    #[test]
+    fn test_settings_new_transport() {
+        let settings_str = r###"
+            {
+                "transport": "varlink",
+                "rules": [
+                    {
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                    }
+                ],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.transport, Transport::Varlink);
+
+        let settings_str = settings_str.replace("\"varlink\"", "\"grpc\"");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidTransport(_))) => {}
+            _ => panic!("expected InvalidTransport; an unknown transport has been given"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_properties_changed_interfaces() {
+        let settings_str = r###"
+            {
+                "properties_changed_interfaces": ["org.freedesktop.systemd1.Service"],
+                "rules": [
+                    {
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                    }
+                ],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.properties_changed_interfaces,
+            vec!["org.freedesktop.systemd1.Service".to_string()]
+        );
+
+        let settings_str = settings_str.replace("systemd1.Service", "systemd1..Service");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidInterface(_))) => {}
+            _ => panic!("expected InvalidInterface; a malformed interface name has been given"),
+        }
+    }
+
+    // Settings::parse_rule()
+    #[test]
+    fn test_settings_parse_rule() {
+        let settings_str = r###"
+            {
+                "rules": [
+                    {
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "name": "foo",
+                        "notifiers": []
+                    }
+                ],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let mut value = serde_json::json!({
+            "active_states": ["failed"],
+            "bus_type": "system",
+            "expression": "bar.service",
+            "expression_type": "unit name",
+            "name": "bar",
+            "notifiers": []
+        });
+        let rule = settings.parse_rule(&value).expect("Failed to parse rule.");
+        assert_eq!(rule.bus_type, BusType::System);
+
+        value["name"] = Value::from("foo");
+        match settings.parse_rule(&value).err().as_deref() {
+            Some([CrateError::Settings(SettingsError::DuplicateRuleName(_))]) => {}
+            _ => panic!("expected DuplicateRuleName; a rule named \"foo\" exists"),
+        }
+
+        value["notifiers"] = serde_json::json!(["nonexistent"]);
+        value["colour"] = Value::from("red");
+        match settings.parse_rule(&value).err().as_deref() {
+            Some(
+                [CrateError::Settings(SettingsError::InvalidSettingsValue(..)), CrateError::Settings(SettingsError::UnknownSettingsKey(..))],
+            )
+            | Some(
+                [CrateError::Settings(SettingsError::UnknownSettingsKey(..)), CrateError::Settings(SettingsError::InvalidSettingsValue(..))],
+            ) => {}
+            _ => panic!("expected two errors; a notifier and a key are unknown"),
+        }
+    }
+
+    // SettingsBuilder::build()
+    #[test]
+    fn test_settings_builder_build() {
+        let notifier = Notifier::new("name.jerebear.KilljoyNotifierLogfile1", BusType::Session)
+            .expect("Failed to create notifier.");
+        let settings = SettingsBuilder::new()
+            .notifier("logfile", notifier)
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_regex("^f[aeiou]{2}\\.service$")
+                    .on_states(vec![ActiveState::Failed, ActiveState::Inactive])
+                    .on_load_states(vec![LoadState::NotFound])
+                    .notify("logfile")
+                    .name("foo")
+                    .label("team", "db")
+                    .severity(Severity::Critical)
+                    .suppress("Sat..Sun")
+                    .journal_lines(5),
+            )
+            .build()
+            .expect("Failed to build settings.");
+        assert_eq!(settings.notifiers.len(), 1);
+        let rule = &settings.rules[0];
+        assert!(rule.expression.matches("foo.service"));
+        assert!(!rule.expression.matches("bar.service"));
+        assert_eq!(rule.active_states.len(), 2);
+        assert!(rule.load_states.contains(&LoadState::NotFound));
+        assert_eq!(rule.notifiers, vec!["logfile".to_string()]);
+        assert_eq!(rule.notifier_policy, NotifierPolicy::All);
+        assert_eq!(rule.name.as_deref(), Some("foo"));
+        assert_eq!(rule.labels["team"], "db");
+        assert_eq!(rule.severity, Severity::Critical);
+        assert_eq!(rule.suppress.len(), 1);
+        assert_eq!(rule.journal_lines, 5);
+    }
+
+    // SettingsBuilder::build()
+    #[test]
+    fn test_settings_builder_build_invalid_notifier() {
+        let result = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_name("foo.service")
+                    .on_states(vec![ActiveState::Failed])
+                    .notify("logfile"),
+            )
+            .build();
+        match result {
+            Err(CrateError::Settings(SettingsError::InvalidNotifier(_))) => {}
+            _ => panic!("expected InvalidNotifier; the rule references a missing notifier"),
+        }
+    }
+
+    // SettingsBuilder::build()
+    #[test]
+    fn test_settings_builder_build_duplicate_rule_name() {
+        let result = SettingsBuilder::new()
+            .rule(
+                RuleBuilder::new(BusType::Session)
+                    .watch_unit_type(".service")
+                    .on_states(vec![ActiveState::Failed])
+                    .name("foo"),
+            )
+            .rule(
+                RuleBuilder::new(BusType::System)
+                    .watch_unit_type(".timer")
+                    .on_states(vec![ActiveState::Failed])
+                    .name("foo"),
+            )
+            .build();
+        match result {
+            Err(CrateError::Settings(SettingsError::DuplicateRuleName(_))) => {}
+            _ => panic!("expected DuplicateRuleName; two rules have the same name"),
+        }
+    }
+
+    // RuleBuilder::build()
+    #[test]
+    fn test_rule_builder_build_invalid() {
+        match RuleBuilder::new(BusType::Session)
+            .watch_regex("(")
+            .on_states(vec![ActiveState::Failed])
+            .build()
+        {
+            Err(CrateError::Settings(SettingsError::InvalidRegex(_))) => {}
+            _ => panic!("expected InvalidRegex; the regex is malformed"),
+        }
+        match RuleBuilder::new(BusType::Session)
+            .on_states(vec![ActiveState::Failed])
+            .build()
+        {
+            Err(CrateError::Settings(SettingsError::InvalidExpressionType(_))) => {}
+            _ => panic!("expected InvalidExpressionType; no units are being watched"),
+        }
+        match RuleBuilder::new(BusType::Session)
+            .watch_unit_name("foo.service")
+            .on_states(vec![ActiveState::Failed])
+            .label("1st", "x")
+            .build()
+        {
+            Err(CrateError::Settings(SettingsError::InvalidLabelName(_))) => {}
+            _ => panic!("expected InvalidLabelName; a label name starts with a digit"),
+        }
+        match RuleBuilder::new(BusType::Session)
+            .watch_unit_name("foo.service")
+            .build()
+        {
+            Err(CrateError::Settings(SettingsError::MissingStates(_))) => {}
+            _ => panic!("expected MissingStates; no states are of interest"),
+        }
+    }
+
+    // Rule::get_severity_at()
+    #[test]
+    fn test_rule_get_severity_at() {
+        let saturday = LocalTime::new(5, 12 * 60);
+        let monday = LocalTime::new(0, 12 * 60);
+
+        let mut rule = test_utils::gen_session_rule();
+        rule.suppress = vec![Window::try_from("Sat..Sun").expect("Failed to parse window.")];
+        assert_eq!(rule.get_severity_at(monday), Some(Severity::Warning));
+        assert_eq!(rule.get_severity_at(saturday), None);
+
+        rule.suppress_severity = Some(Severity::Info);
+        assert_eq!(rule.get_severity_at(saturday), Some(Severity::Info));
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_suppress() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "suppress": ["Sat..Sun", "22:00-07:00"],
+                        "suppress_severity": "debug"
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.rules[0].suppress.len(), 2);
+        assert_eq!(settings.rules[0].suppress_severity, Some(Severity::Debug));
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_suppress() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": [],
+                        "suppress": ["22:00-7"]
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidSchedule(_, _))) => {}
+            _ => panic!("expected InvalidSchedule; a time window is malformed"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_duplicate_rule_name() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "name": "foo",
+                        "notifiers": []
+                }, {
+                        "active_states": ["failed"],
+                        "bus_type": "system",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "name": "foo",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::DuplicateRuleName(_))) => {}
+            _ => panic!("expected DuplicateRuleName; two rules share a name"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_rule_name() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "name": " ",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidRuleName(_))) => {}
+            _ => panic!("expected InvalidRuleName; a rule name is blank"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_label_name() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "labels": {"owning team": "db"},
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidLabelName(_))) => {}
+            _ => panic!("expected InvalidLabelName; a label name contains a space"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_notifier_mode() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "email": {
+                        "bus_name": "name.jerebear.KilljoyNotifierEmail1",
+                        "bus_type": "session",
+                        "digest_schedule": "Mon..Fri 08:00",
+                        "mode": "digest"
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.notifiers["email"].mode, NotifierMode::Digest);
+        assert!(settings.notifiers["email"].digest_schedule.is_some());
+        assert_eq!(settings.notifiers["logfile"].mode, NotifierMode::Realtime);
+
+        let settings_str = settings_str.replace(r#""digest_schedule": "Mon..Fri 08:00","#, "");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingDigestSchedule(_))) => {}
+            _ => panic!("expected MissingDigestSchedule; a digest notifier lacks a schedule"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_notifier_tags() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "pager": {
+                        "accept_tags": ["database down", "web down"],
+                        "bus_name": "name.jerebear.KilljoyNotifierPager1",
+                        "bus_type": "session",
+                        "reject_tags": ["staging"]
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.notifiers["pager"].accept_tags,
+            vec!["database down", "web down"]
+        );
+        assert_eq!(settings.notifiers["pager"].reject_tags, vec!["staging"]);
+        assert!(settings.notifiers["logfile"].accept_tags.is_empty());
+        assert!(settings.notifiers["logfile"].reject_tags.is_empty());
+    }
+
+    // Notifier::accepts()
+    #[test]
+    fn test_notifier_accepts() {
+        let tags =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+        let mut notifier = Notifier::new("name.jerebear.Foo", BusType::Session)
+            .expect("Failed to create notifier.");
+        assert!(notifier.accepts(&[]));
+        assert!(notifier.accepts(&tags(&["web down"])));
+
+        notifier.accept_tags = tags(&["web down"]);
+        notifier.reject_tags = tags(&["staging"]);
+        assert!(!notifier.accepts(&[]));
+        assert!(notifier.accepts(&tags(&["web down"])));
+        assert!(notifier.accepts(&tags(&["database down", "web down"])));
+        assert!(!notifier.accepts(&tags(&["database down"])));
+        assert!(!notifier.accepts(&tags(&["web down", "staging"])));
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_boot_summary() {
+        let settings_str = r###"
+            {
+                "boot_summary": {"notifiers": ["logfile"]},
+                "rules": [],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.history_size, DEFAULT_HISTORY_SIZE);
+        let boot_summary = settings.boot_summary.expect("Boot summary is missing.");
+        assert_eq!(boot_summary.bus_type, BusType::System);
+        assert_eq!(boot_summary.notifiers, vec!["logfile".to_string()]);
+
+        let settings_str = settings_str.replace("[\"logfile\"]", "[\"logfiel\"]");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidNotifier(_))) => {}
+            _ => panic!("expected InvalidNotifier; a boot summary notifier has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_watch_limit() {
+        let settings_str = r###"
+            {
+                "watch_limit": {"max_units": 1000, "notifiers": ["logfile"]},
+                "rules": [],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let watch_limit = settings.watch_limit.expect("Watch limit is missing.");
+        assert_eq!(watch_limit.max_units, 1000);
+        assert_eq!(watch_limit.notifiers, vec!["logfile".to_string()]);
+
+        let typo_str = settings_str.replace("[\"logfile\"]", "[\"logfiel\"]");
+        match Settings::new(typo_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidNotifier(_))) => {}
+            _ => panic!("expected InvalidNotifier; a watch limit notifier has been typo'd"),
+        }
+
+        let zero_str = settings_str.replace("1000", "0");
+        match Settings::new(zero_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidWatchLimit)) => {}
+            _ => panic!("expected InvalidWatchLimit; a limit of zero units has been given"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_notifier() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "bus_type": "session",
+                        "expression": "syncthing.service",
+                        "expression_type": "unit name",
+                        "notifiers": ["desktop popupp"]
+                }],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidNotifier(_))) => {}
+            _ => panic!("expected InvalidNotifier; a notifier has been typo'd"),
+        }
+    }
 
-    use super::*;
+    // Settings::new()
+    #[test]
+    fn test_settings_new_message_template() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session",
+                        "message_template": "{{unit}} on {{hostname}} is {{new_state}}"
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let template = settings.notifiers["desktop popup"]
+            .message_template
+            .as_ref()
+            .expect("Notifier lacks a message template.");
+        assert!(template.uses("hostname"));
+        assert!(settings.notifiers["logfile"].message_template.is_none());
+    }
 
-    // get_bus_types()
+    // Settings::new()
     #[test]
-    fn test_get_bus_types_v1() {
-        let settings = Settings {
-            notifiers: HashMap::new(),
-            rules: Vec::new(),
-        };
-        let bus_types = get_bus_types(&settings.rules);
-        assert!(!bus_types.contains(&BusType::Session));
-        assert!(!bus_types.contains(&BusType::System));
+    fn test_settings_new_clock() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session",
+                        "clock": "monotonic"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.notifiers["desktop popup"].clock, Clock::Realtime);
+        assert_eq!(settings.notifiers["logfile"].clock, Clock::Monotonic);
     }
 
-    // get_bus_types()
+    // Settings::new()
     #[test]
-    fn test_get_bus_types_v2() {
-        let settings = Settings {
-            notifiers: HashMap::new(),
-            rules: vec![test_utils::gen_session_rule()],
-        };
-        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
-        assert!(bus_types.contains(&BusType::Session));
-        assert!(!bus_types.contains(&BusType::System));
+    fn test_settings_new_protocol_version() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile2",
+                        "bus_type": "session",
+                        "protocol_version": 2
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.notifiers["desktop popup"].protocol_version,
+            ProtocolVersion::V1
+        );
+        assert_eq!(
+            settings.notifiers["logfile"].protocol_version,
+            ProtocolVersion::V2
+        );
     }
 
-    // get_bus_types()
+    // Settings::new()
     #[test]
-    fn test_get_bus_types_v3() {
-        let settings = Settings {
-            notifiers: HashMap::new(),
-            rules: vec![test_utils::gen_system_rule()],
-        };
-        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
-        assert!(!bus_types.contains(&BusType::Session));
-        assert!(bus_types.contains(&BusType::System));
+    fn test_settings_new_notifier_timeout() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session"
+                    },
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session",
+                        "timeout": 30
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.notifiers["desktop popup"].timeout,
+            Duration::from_secs(DEFAULT_NOTIFIER_TIMEOUT)
+        );
+        assert_eq!(
+            settings.notifiers["logfile"].timeout,
+            Duration::from_secs(30)
+        );
+
+        match Settings::new(settings_str.replace("30", "0").as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidNotifierTimeout)) => {}
+            _ => panic!("expected InvalidNotifierTimeout; a notifier's timeout is zero"),
+        }
     }
 
-    // get_bus_types()
+    // Settings::new()
     #[test]
-    fn test_get_bus_types_v4() {
-        let settings = Settings {
-            notifiers: HashMap::new(),
-            rules: vec![
-                test_utils::gen_session_rule(),
-                test_utils::gen_system_rule(),
-            ],
-        };
-        let bus_types: Vec<BusType> = get_bus_types(&settings.rules);
-        assert!(bus_types.contains(&BusType::Session));
-        assert!(bus_types.contains(&BusType::System));
+    fn test_settings_new_invalid_protocol_version() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session",
+                        "protocol_version": 3
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidProtocolVersion(3))) => {}
+            _ => panic!("expected InvalidProtocolVersion; an unknown protocol has been requested"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_clock() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session",
+                        "clock": "wall"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidClock(_))) => {}
+            _ => panic!("expected InvalidClock; a clock has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_invalid_message_template() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {
+                    "desktop popup": {
+                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
+                        "bus_type": "session",
+                        "message_template": "{{unit}} is {{state}}"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidTemplate(_, _))) => {}
+            _ => panic!("expected InvalidTemplate; a template variable has been typo'd"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_defaults() {
+        let settings_str = r###"
+            {
+                "defaults": {
+                    "active_states": ["failed"],
+                    "bus_type": "system",
+                    "ignore_transient": true,
+                    "notifiers": ["logfile"],
+                    "startup_grace_period": 120
+                },
+                "rules": [{
+                        "expression": "foo.service",
+                        "expression_type": "unit name"
+                    }, {
+                        "active_states": ["inactive"],
+                        "bus_type": "session",
+                        "expression": "bar.service",
+                        "expression_type": "unit name",
+                        "ignore_transient": false,
+                        "notifiers": [],
+                        "startup_grace_period": 30
+                }],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session"
+                    }
+                },
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+
+        let rule = &settings.rules[0];
+        assert!(rule.active_states.contains(&ActiveState::Failed));
+        assert_eq!(encode_bus_type(rule.bus_type), "system");
+        assert_eq!(rule.notifiers, vec!["logfile"]);
+        assert_eq!(rule.startup_grace_period, Some(Duration::from_secs(120)));
+        assert!(rule.ignore_transient);
+
+        let rule = &settings.rules[1];
+        assert!(rule.active_states.contains(&ActiveState::Inactive));
+        assert!(!rule.active_states.contains(&ActiveState::Failed));
+        assert_eq!(encode_bus_type(rule.bus_type), "session");
+        assert!(rule.notifiers.is_empty());
+        assert_eq!(rule.startup_grace_period, Some(Duration::from_secs(30)));
+        assert!(!rule.ignore_transient);
+    }
+
+    // Rule::is_in_startup_grace_period()
+    #[test]
+    fn test_rule_is_in_startup_grace_period() {
+        let mut rule = test_utils::gen_system_rule();
+        assert!(!rule.is_in_startup_grace_period(Duration::from_secs(0)));
+        rule.startup_grace_period = Some(Duration::from_secs(60));
+        assert!(rule.is_in_startup_grace_period(Duration::from_secs(59)));
+        assert!(!rule.is_in_startup_grace_period(Duration::from_secs(60)));
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_missing_bus_type() {
+        let settings_str = r###"
+            {
+                "rules": [{
+                        "active_states": ["failed"],
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "notifiers": []
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingBusType(_))) => {}
+            _ => {
+                panic!("expected MissingBusType; neither the rule nor the defaults set a bus type")
+            }
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_missing_notifiers() {
+        let settings_str = r###"
+            {
+                "defaults": {"bus_type": "session"},
+                "rules": [{
+                        "active_states": ["failed"],
+                        "expression": "foo.service",
+                        "expression_type": "unit name"
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingNotifiers(_))) => {}
+            _ => {
+                panic!("expected MissingNotifiers; neither the rule nor the defaults set notifiers")
+            }
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_missing_states() {
+        let settings_str = r###"
+            {
+                "defaults": {"bus_type": "session", "notifiers": []},
+                "rules": [{
+                        "expression": "foo.service",
+                        "expression_type": "unit name"
+                }],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingStates(_))) => {}
+            _ => panic!("expected MissingStates; neither the rule nor the defaults set states"),
+        }
+    }
+
+    // check_schema()
+    #[test]
+    fn test_check_schema_valid() {
+        let value: Value = serde_json::from_str(
+            r###"
+            {
+                "defaults": {"bus_type": "session", "notifiers": ["logfile"]},
+                "rules": [{
+                        "actions": [{"type": "restart-unit", "active_states": ["failed"]}],
+                        "active_states": ["failed"],
+                        "expression": "foo.service",
+                        "expression_type": "unit name",
+                        "labels": {"team": "db"},
+                        "properties": [{"property": "TasksCurrent", "condition": ">", "value": 9}],
+                        "severity": "critical"
+                }],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "session",
+                        "protocol_version": 2
+                    }
+                },
+                "version": 1
+            }
+        "###,
+        )
+        .expect("Failed to parse JSON.");
+        let errs = check_schema(&value);
+        assert!(errs.is_empty(), "{:?}", errs);
+    }
+
+    // check_schema()
+    #[test]
+    fn test_check_schema_invalid() {
+        let value: Value = serde_json::from_str(
+            r###"
+            {
+                "rules": [{
+                        "active_states": ["failedd"],
+                        "bus_type": "session",
+                        "expression_type": "unit name",
+                        "include_dependents": "yes",
+                        "notifier": ["logfile"],
+                        "notifiers": ["logfiel"]
+                }],
+                "notifiers": {
+                    "logfile": {
+                        "bus_name": "name.jerebear.KilljoyNotifierLogfile1",
+                        "bus_type": "sesion"
+                    }
+                },
+                "version": 1
+            }
+        "###,
+        )
+        .expect("Failed to parse JSON.");
+        let errs: Vec<String> = check_schema(&value)
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        let expected = vec![
+            r#"/notifiers/logfile/bus_type: "sesion" is not a valid bus type; did you mean "session"?"#,
+            r#"/rules/0/expression: required key is missing"#,
+            r#"/rules/0/notifier: unknown key; did you mean "notifiers"?"#,
+            r#"/rules/0/active_states/0: "failedd" is not a valid active state; did you mean "failed"?"#,
+            r#"/rules/0/include_dependents: expected a boolean"#,
+            r#"/rules/0/notifiers/0: "logfiel" is not a valid notifier; did you mean "logfile"?"#,
+        ];
+        assert_eq!(errs, expected);
+    }
+
+    // check_schema()
+    #[test]
+    fn test_check_schema_wrong_type() {
+        let value: Value = serde_json::from_str("[]").expect("Failed to parse JSON.");
+        let errs: Vec<String> = check_schema(&value)
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(errs, vec!["(top level): expected an object"]);
+    }
+
+    // suggest()
+    #[test]
+    fn test_suggest() {
+        assert_eq!(
+            suggest("failedd", ACTIVE_STATES),
+            Some("failed".to_string())
+        );
+        assert_eq!(
+            suggest("inactiv", ACTIVE_STATES),
+            Some("inactive".to_string())
+        );
+        assert_eq!(suggest("nope", ACTIVE_STATES), None);
+        assert_eq!(suggest("x", &[]), None);
+    }
+
+    // edit_distance()
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("failedd", "failed"), 1);
+    }
+
+    // escape()
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a/b~c"), "a~1b~0c");
+    }
+
+    // Rule::describe()
+    #[test]
+    fn test_rule_describe() {
+        let mut rule = test_utils::gen_session_rule();
+        rule.expression = Expression::UnitName("foo.service".to_string());
+        assert_eq!(rule.describe(), "Rule for 'foo.service'");
+        rule.name = Some("foo".to_string());
+        assert_eq!(rule.describe(), "Rule \"foo\"");
     }
 
-    // Expression::UnitName::matches()
+    // MIGRATIONS
     #[test]
-    fn test_expression_unit_name_matches_success() {
-        let unit_name = "aaa.service";
-        let expression = Expression::UnitName("aaa.service".to_string());
-        assert!(expression.matches(unit_name));
+    fn test_migrations_reach_current_version() {
+        assert_eq!(MIGRATIONS.len() as u64 + 1, CURRENT_VERSION);
     }
 
-    // Expression::UnitName::matches()
+    // migrate_value()
     #[test]
-    fn test_expression_unit_name_matches_failure() {
-        let unit_name = "aaa.service";
-        let expression = Expression::UnitName("aa.service".to_string());
-        assert!(!expression.matches(unit_name));
+    fn test_migrate_value() {
+        fn rename_foo(settings: &mut Map<String, Value>) -> Result<(), CrateError> {
+            let foo = settings.remove("foo").unwrap_or(Value::Null);
+            settings.insert("bar".to_string(), foo);
+            Ok(())
+        }
+        fn double_bar(settings: &mut Map<String, Value>) -> Result<(), CrateError> {
+            let bar = settings["bar"].as_u64().unwrap_or(0);
+            settings.insert("bar".to_string(), Value::from(bar * 2));
+            Ok(())
+        }
+        let migrations: &[Migration] = &[rename_foo, double_bar];
+
+        let value: Value = serde_json::from_str(r#"{"foo": 1, "version": 1}"#).unwrap();
+        let value = migrate_value(value, migrations).expect("Failed to migrate.");
+        assert_eq!(value, serde_json::json!({"bar": 2, "version": 3}));
+
+        let value: Value = serde_json::from_str(r#"{"bar": 5, "version": 2}"#).unwrap();
+        let value = migrate_value(value, migrations).expect("Failed to migrate.");
+        assert_eq!(value, serde_json::json!({"bar": 10, "version": 3}));
+
+        let value: Value = serde_json::from_str(r#"{"bar": 5, "version": 3}"#).unwrap();
+        let value = migrate_value(value, migrations).expect("Failed to migrate.");
+        assert_eq!(value, serde_json::json!({"bar": 5, "version": 3}));
     }
 
-    // Expression::UnitType::matches()
+    // migrate_value()
     #[test]
-    fn test_expression_unit_type_matches_success() {
-        let unit_name = "aaa.service";
-        let expression = Expression::UnitType(".service".to_string());
-        assert!(expression.matches(unit_name));
+    fn test_migrate_value_invalid_version() {
+        for (settings_str, version) in &[(r#"{"version": 0}"#, 0), (r#"{"version": 2}"#, 2)] {
+            let value: Value = serde_json::from_str(settings_str).unwrap();
+            match migrate_value(value, MIGRATIONS) {
+                Err(CrateError::Settings(SettingsError::UnsupportedSettingsVersion(v)))
+                    if v == *version => {}
+                _ => panic!("expected UnsupportedSettingsVersion; the version is out of range"),
+            }
+        }
+        let value: Value = serde_json::from_str(r#"{"rules": []}"#).unwrap();
+        match migrate_value(value, MIGRATIONS) {
+            Err(CrateError::Settings(SettingsError::MissingSettingsKey(_))) => {}
+            _ => panic!("expected MissingSettingsKey; the version is absent"),
+        }
     }
 
-    // Expression::UnitType::matches()
+    // Settings::new()
     #[test]
-    fn test_expression_unit_type_matches_failure() {
-        let unit_name = "aaa.service";
-        let expression = Expression::UnitType(".mount".to_string());
-        assert!(!expression.matches(unit_name));
+    fn test_settings_new_event_log() {
+        let settings_str = r###"
+            {
+                "event_log": {"path": "/var/log/killjoy.csv", "format": "csv"},
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let event_log = settings.event_log.expect("Event log is missing.");
+        assert_eq!(event_log.format, EventLogFormat::Csv);
+        assert_eq!(event_log.max_size, DEFAULT_EVENT_LOG_MAX_SIZE);
+        assert_eq!(event_log.path, PathBuf::from("/var/log/killjoy.csv"));
+
+        let settings_str = settings_str.replace("\"csv\"", "\"xml\"");
+        match Settings::new(settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidEventLogFormat(_))) => {}
+            _ => panic!("expected InvalidEventLogFormat; the event log format is bogus"),
+        }
     }
 
-    // Expression::UnitRegex::matches()
+    // Settings::new()
     #[test]
-    fn test_expression_regex_matches() {
-        let expression =
-            Expression::Regex(Regex::new(r"a\.service").expect("Failed to compile regex."));
-        assert!(!expression.matches(".service"));
-        assert!(expression.matches("a.service"));
-        assert!(expression.matches("aa.service"));
+    fn test_settings_new_control_socket() {
+        let settings_str = r###"
+            {
+                "control_socket": {"path": "/run/killjoy/control.sock"},
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let control_socket = Settings::new(settings_str.as_bytes())
+            .expect("Failed to parse settings.")
+            .control_socket
+            .expect("Control socket is missing.");
+        assert_eq!(
+            control_socket.path,
+            Some(PathBuf::from("/run/killjoy/control.sock"))
+        );
+
+        let settings_str = settings_str.replace("\"/run/killjoy/control.sock\"", "1");
+        let value: Value = serde_json::from_str(&settings_str).expect("Failed to parse JSON.");
+        let errs: Vec<String> = check_schema(&value)
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(errs, vec!["/control_socket/path: expected a string"]);
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new() {
+    fn test_settings_new_discovery() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
-                }],
-                "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
-                    }
-                },
+                "discovery": {"bus_type": "session"},
+                "rules": [],
+                "notifiers": {},
                 "version": 1
             }
         "###;
-        Settings::new(settings_str.as_bytes()).expect("valid settings parsed as invalid");
+        let discovery = Settings::new(settings_str.as_bytes())
+            .expect("Failed to parse settings.")
+            .discovery
+            .expect("Discovery is missing.");
+        assert_eq!(discovery.bus_type, BusType::Session);
+
+        let settings_str = settings_str.replace("\"session\"", "\"foo\"");
+        let value: Value = serde_json::from_str(&settings_str).expect("Failed to parse JSON.");
+        assert_eq!(check_schema(&value).len(), 1);
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_deserialization_failed() {
+    fn test_settings_new_event_store() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
-                }],
-                "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session",
-                    }
-                },
+                "event_store": {"path": "/var/lib/killjoy/events.sqlite3"},
+                "rules": [],
+                "notifiers": {},
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::SettingsFileDeserializationFailed(_)) => {}
-            _ => panic!("expected DeserializationFailed; an extra comma has been added"),
+        let result = Settings::new(settings_str.as_bytes());
+        if cfg!(feature = "sqlite") {
+            let event_store = result
+                .expect("Failed to parse settings.")
+                .event_store
+                .expect("Event store is missing.");
+            assert_eq!(
+                event_store.path,
+                Some(PathBuf::from("/var/lib/killjoy/events.sqlite3"))
+            );
+        } else {
+            match result {
+                Err(CrateError::State(StateError::EventStoreNotSupported)) => {}
+                _ => panic!("expected EventStoreNotSupported; the sqlite feature is disabled"),
+            }
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_active_state() {
+    fn test_settings_new_filter() {
         let settings_str = r###"
             {
                 "rules": [{
-                        "active_states": ["failedd"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
+                    "bus_type": "session",
+                    "expression": "foo.service",
+                    "expression_type": "unit name",
+                    "active_states": ["failed"],
+                    "filter": "event.old_state != \"activating\"",
+                    "notifiers": []
                 }],
-                "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
-                    }
-                },
+                "notifiers": {},
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidActiveState(_)) => {}
-            _ => panic!("expected InvalidActiveState; an active state has been typo'd"),
+        let result = Settings::new(settings_str.as_bytes());
+        if cfg!(feature = "scripting") {
+            let filter = result.expect("Failed to parse settings.").rules[0]
+                .filter
+                .take()
+                .expect("Filter is missing.");
+            assert_eq!(filter.source, "event.old_state != \"activating\"");
+            match Settings::new(settings_str.replace("!=", "!=!=").as_bytes()) {
+                Err(CrateError::Settings(SettingsError::InvalidFilter(_))) => {}
+                _ => panic!("expected InvalidFilter; the filter doesn't parse"),
+            }
+        } else {
+            match result {
+                Err(CrateError::Settings(SettingsError::FilterNotSupported)) => {}
+                _ => panic!("expected FilterNotSupported; the scripting feature is disabled"),
+            }
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_bus_name() {
+    fn test_settings_new_heartbeat() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
-                }],
-                "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name/jerebear/KilljoyNotifierNotification1",
-                        "bus_type": "session"
-                    }
+                "heartbeat": {"url": "https://hc-ping.com/abc"},
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let heartbeat = Settings::new(settings_str.as_bytes())
+            .expect("Failed to parse settings.")
+            .heartbeat
+            .expect("Heartbeat is missing.");
+        assert_eq!(heartbeat.url, "https://hc-ping.com/abc");
+        assert_eq!(
+            heartbeat.interval,
+            Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL)
+        );
+
+        let bad_settings_str = settings_str.replace("https:", "ftp:");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidHeartbeatUrl(_))) => {}
+            _ => panic!("expected InvalidHeartbeatUrl; heartbeats are sent over HTTP"),
+        }
+        let bad_settings_str = settings_str.replace("abc\"", "abc\", \"interval\": 0");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidHeartbeatInterval)) => {}
+            _ => panic!("expected InvalidHeartbeatInterval; the interval is zero"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_identity() {
+        let settings_str = r###"
+            {
+                "identity": {
+                    "hostname": "web1.example.com",
+                    "include_machine_id": true,
+                    "tags": {"site": "ams"}
                 },
+                "rules": [],
+                "notifiers": {},
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidBusName(_)) => {}
-            _ => panic!("expected InvalidBusName; a bus name has been typo'd"),
+        let identity = Settings::new(settings_str.as_bytes())
+            .expect("Failed to parse settings.")
+            .identity;
+        assert_eq!(identity.hostname.as_deref(), Some("web1.example.com"));
+        assert!(identity.include_machine_id);
+        assert_eq!(identity.tags["site"], "ams");
+
+        let bad_settings_str = settings_str.replace("web1.example.com", "web 1");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidHostname(_))) => {}
+            _ => panic!("expected InvalidHostname; the hostname contains a space"),
+        }
+        let bad_settings_str = settings_str.replace("\"site\"", "\"1site\"");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidLabelName(_))) => {}
+            _ => panic!("expected InvalidLabelName; a tag name starts with a digit"),
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_bus_type_v1() {
+    fn test_settings_new_reconcile_interval() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "sessionn",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
-                }],
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.reconcile_interval,
+            Some(Duration::from_secs(DEFAULT_RECONCILE_INTERVAL))
+        );
+
+        let settings_str =
+            settings_str.replace("\"rules\"", "\"reconcile_interval\": 0, \"rules\"");
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.reconcile_interval, None);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_resume_grace_period() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(
+            settings.resume_grace_period,
+            Some(Duration::from_secs(DEFAULT_RESUME_GRACE_PERIOD))
+        );
+
+        let settings_str =
+            settings_str.replace("\"rules\"", "\"resume_grace_period\": 0, \"rules\"");
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.resume_grace_period, None);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_deduplicate_notifications() {
+        let settings_str = r###"
+            {
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert!(settings.deduplicate_notifications);
+
+        let settings_str = settings_str.replace(
+            "\"rules\"",
+            "\"deduplicate_notifications\": false, \"rules\"",
+        );
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert!(!settings.deduplicate_notifications);
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_systemd_calls() {
+        let settings_str = r###"
+            {
+                "systemd_calls": {"timeout": 2000, "list_timeout": 10000, "retries": 3},
+                "rules": [],
+                "notifiers": {},
+                "version": 1
+            }
+        "###;
+        let systemd_calls = Settings::new(settings_str.as_bytes())
+            .expect("Failed to parse settings.")
+            .systemd_calls;
+        assert_eq!(
+            systemd_calls.get_timeout(SystemdCallClass::List),
+            Duration::from_millis(10000)
+        );
+        assert_eq!(
+            systemd_calls.get_timeout(SystemdCallClass::Properties),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(
+            systemd_calls.get_timeout(SystemdCallClass::Unit),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(systemd_calls.retries, 3);
+        assert_eq!(
+            systemd_calls.retry_delay,
+            Duration::from_millis(DEFAULT_SYSTEMD_CALL_RETRY_DELAY)
+        );
+
+        let bad_settings_str = settings_str.replace("10000", "0");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidSystemdCallTimeout)) => {}
+            _ => panic!("expected InvalidSystemdCallTimeout; the list timeout is zero"),
+        }
+    }
+
+    // Settings::new()
+    #[test]
+    fn test_settings_new_mqtt_notifier() {
+        let settings_str = r###"
+            {
+                "rules": [],
                 "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
+                    "home assistant": {
+                        "type": "mqtt",
+                        "host": "broker.local",
+                        "qos": 1,
+                        "topic": "systemd/{{unit}}"
                     }
                 },
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidBusType(_)) => {}
-            _ => panic!("expected InvalidBusType; a bus type has been typo'd"),
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let notifier = &settings.notifiers["home assistant"];
+        assert!(notifier.get_dbus_address().is_none());
+        assert_eq!(notifier.describe(), "mqtt://broker.local:1883");
+        match &notifier.target {
+            NotifierTarget::Mqtt(broker) => assert_eq!(broker.qos, MqttQos::AtLeastOnce),
+            _ => panic!("expected an MQTT notifier"),
         }
+
+        let bad_settings_str = settings_str.replace("\"qos\": 1", "\"qos\": 3");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidMqttQos(3))) => {}
+            _ => panic!("expected InvalidMqttQos; QoS levels only go up to 2"),
+        }
+        let bad_settings_str = settings_str.replace("{{unit}}", "#");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidMqttTopic(_))) => {}
+            _ => panic!("expected InvalidMqttTopic; events can't be published to wildcards"),
+        }
+        let bad_settings_str = settings_str.replace("\"host\": \"broker.local\",", "");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingNotifierKey("mqtt", "host"))) => {}
+            _ => panic!("expected MissingNotifierKey; an MQTT notifier has no host"),
+        }
+
+        let credentials_str = settings_str.replace("\"qos\": 1,", "\"username\": \"killjoy\",");
+        match Settings::new(credentials_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::PlaintextMqttCredentials(_))) => {}
+            _ => panic!("expected PlaintextMqttCredentials; credentials would be sent unencrypted"),
+        }
+        let credentials_str = credentials_str.replace(
+            "\"username\": \"killjoy\",",
+            "\"username\": \"killjoy\", \"plaintext_credentials\": true,",
+        );
+        Settings::new(credentials_str.as_bytes()).expect("Failed to parse settings.");
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_bus_type_v2() {
+    fn test_settings_new_alertmanager_notifier() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popup"]
-                }],
+                "rules": [],
                 "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "sessionn"
+                    "alertmanager": {
+                        "type": "alertmanager",
+                        "url": "http://alertmanager.local:9093",
+                        "resolve_states": ["active", "inactive"]
                     }
                 },
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidBusType(_)) => {}
-            _ => panic!("expected InvalidBusType; a bus type has been typo'd"),
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let notifier = &settings.notifiers["alertmanager"];
+        assert!(notifier.get_dbus_address().is_none());
+        assert_eq!(notifier.describe(), "http://alertmanager.local:9093");
+        match &notifier.target {
+            NotifierTarget::Alertmanager(alertmanager) => assert_eq!(
+                alertmanager.resolve_states,
+                vec![ActiveState::Active, ActiveState::Inactive]
+            ),
+            _ => panic!("expected an Alertmanager notifier"),
+        }
+
+        let bad_settings_str = settings_str.replace("http://", "ftp://");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidAlertmanagerUrl(_))) => {}
+            _ => panic!("expected InvalidAlertmanagerUrl; alerts can only be posted over HTTP"),
+        }
+        let bad_settings_str =
+            settings_str.replace("\"url\": \"http://alertmanager.local:9093\",", "");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingNotifierKey("alertmanager", "url"))) => {
+            }
+            _ => panic!("expected MissingNotifierKey; an Alertmanager notifier has no URL"),
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_expression_type() {
+    fn test_settings_new_syslog_notifier() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit namee",
-                        "notifiers": ["desktop popup"]
-                }],
+                "rules": [],
                 "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
+                    "local": {"type": "syslog"},
+                    "remote": {
+                        "type": "syslog",
+                        "transport": "tcp",
+                        "host": "logs.local",
+                        "facility": "local3"
                     }
                 },
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidExpressionType(_)) => {}
-            _ => panic!("expected InvalidExpressionType; an expression type has been typo'd"),
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        assert_eq!(settings.notifiers["local"].describe(), "syslog:///dev/log");
+        let notifier = &settings.notifiers["remote"];
+        assert_eq!(notifier.describe(), "syslog+tcp://logs.local:514");
+        match &notifier.target {
+            NotifierTarget::Syslog(server) => assert_eq!(server.facility, 19),
+            _ => panic!("expected a syslog notifier"),
+        }
+
+        let bad_settings_str = settings_str.replace("local3", "local8");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::InvalidSyslogFacility(_))) => {}
+            _ => panic!("expected InvalidSyslogFacility; there are only eight local facilities"),
+        }
+        let bad_settings_str = settings_str.replace("\"host\": \"logs.local\",", "");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingNotifierKey("syslog", "host"))) => {}
+            _ => panic!("expected MissingNotifierKey; a TCP syslog notifier has no host"),
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_regex() {
+    fn test_settings_new_snmp_notifier() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "{",
-                        "expression_type": "regex",
-                        "notifiers": ["desktop popup"]
-                }],
+                "rules": [],
                 "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
+                    "nms": {
+                        "type": "snmp",
+                        "host": "nms.local",
+                        "community": "private",
+                        "oid": "1.3.6.1.4.1.99999"
                     }
                 },
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidRegex(_)) => {}
-            _ => panic!("expected InvalidRegex; a regex has been typo'd"),
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let notifier = &settings.notifiers["nms"];
+        assert_eq!(notifier.describe(), "snmp://nms.local:162");
+        match &notifier.target {
+            NotifierTarget::Snmp(manager) => {
+                assert_eq!(manager.community, "private");
+                assert_eq!(manager.oid, vec![1, 3, 6, 1, 4, 1, 99999]);
+            }
+            _ => panic!("expected an SNMP notifier"),
+        }
+
+        for bad_oid in &["1", "1.40", "3.1", "1.3.x", "1..3"] {
+            let bad_settings_str = settings_str.replace("1.3.6.1.4.1.99999", bad_oid);
+            match Settings::new(bad_settings_str.as_bytes()) {
+                Err(CrateError::Settings(SettingsError::InvalidSnmpOid(_))) => {}
+                _ => panic!("expected InvalidSnmpOid; {} isn't a valid OID", bad_oid),
+            }
         }
     }
 
     // Settings::new()
     #[test]
-    fn test_settings_new_invalid_notifier() {
+    fn test_settings_new_plugin_notifier() {
         let settings_str = r###"
             {
-                "rules": [{
-                        "active_states": ["failed"],
-                        "bus_type": "session",
-                        "expression": "syncthing.service",
-                        "expression_type": "unit name",
-                        "notifiers": ["desktop popupp"]
-                }],
+                "rules": [],
                 "notifiers": {
-                    "desktop popup": {
-                        "bus_name": "name.jerebear.KilljoyNotifierNotification1",
-                        "bus_type": "session"
+                    "pager": {
+                        "type": "plugin",
+                        "command": ["/usr/lib/killjoy/pager", "--verbose"],
+                        "options": {"team": "ops"}
                     }
                 },
                 "version": 1
             }
         "###;
-        match Settings::new(settings_str.as_bytes()) {
-            Err(CrateError::InvalidNotifier(_)) => {}
-            _ => panic!("expected InvalidNotifier; a notifier has been typo'd"),
+        let settings = Settings::new(settings_str.as_bytes()).expect("Failed to parse settings.");
+        let notifier = &settings.notifiers["pager"];
+        assert_eq!(notifier.describe(), "plugin:/usr/lib/killjoy/pager");
+        match &notifier.target {
+            NotifierTarget::Plugin(plugin) => {
+                assert_eq!(plugin.command.len(), 2);
+                assert_eq!(plugin.options["team"], "ops");
+            }
+            _ => panic!("expected a plugin notifier"),
+        }
+
+        let bad_settings_str =
+            settings_str.replace(r#"["/usr/lib/killjoy/pager", "--verbose"]"#, "[]");
+        match Settings::new(bad_settings_str.as_bytes()) {
+            Err(CrateError::Settings(SettingsError::MissingNotifierKey("plugin", "command"))) => {}
+            _ => panic!("expected MissingNotifierKey; the plugin has no command"),
         }
     }
 }
@@ -0,0 +1,302 @@
+// Logic for temporarily silencing killjoy, e.g. during planned maintenance.
+//
+// Silences are stored in a file, so that they may be managed by one killjoy process (i.e. `killjoy
+// silence ...`) and obeyed by another (i.e. the one watching units).
+
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IOError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use xdg::BaseDirectories;
+
+use crate::error::{Error as CrateError, StateError};
+use crate::timestamp::RealtimeTimestamp;
+
+// A period of time during which events for matching units are ignored.
+//
+// `unit` is a glob pattern such as `postgresql*.service`, where `*` matches any sequence of
+// characters and `?` matches any one character. If `unit` is `None`, all units match. `expires` is
+// a realtime timestamp, in usec.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Silence {
+    pub id: u32,
+    pub expires: u64,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl Silence {
+    // Tell whether this silence applies to the given unit at the given time.
+    pub fn applies_to(&self, unit_name: &str, now: &RealtimeTimestamp) -> bool {
+        now.0 < self.expires
+            && self
+                .unit
+                .as_ref()
+                .map_or(true, |pattern| glob_matches(pattern, unit_name))
+    }
+}
+
+// A collection of silences, as stored in the silences file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Silences {
+    silences: Vec<Silence>,
+}
+
+impl Silences {
+    // Read silences from the given file. If the file doesn't exist, there are no silences.
+    pub fn load(path: &Path) -> Result<Self, CrateError> {
+        match File::open(path) {
+            Ok(handle) => serde_json::from_reader(BufReader::new(handle))
+                .map_err(|err| CrateError::State(StateError::DeserializeSilencesFile(err))),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CrateError::State(StateError::ReadSilencesFile(err))),
+        }
+    }
+
+    // Write silences to the given file.
+    //
+    // The file is replaced atomically, so that concurrent readers never see a partial file.
+    pub fn save(&self, path: &Path) -> Result<(), CrateError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|err| CrateError::State(StateError::SerializeSilencesFile(err)))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents)
+            .map_err(|err| CrateError::State(StateError::WriteSilencesFile(err)))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|err| CrateError::State(StateError::WriteSilencesFile(err)))
+    }
+
+    // Add a silence lasting `duration` from `now`, and return its ID.
+    pub fn add(
+        &mut self,
+        unit: Option<String>,
+        comment: Option<String>,
+        duration: Duration,
+        now: &RealtimeTimestamp,
+    ) -> u32 {
+        let id = self
+            .silences
+            .iter()
+            .map(|silence| silence.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.silences.push(Silence {
+            id,
+            expires: now.0.saturating_add(duration.as_micros() as u64),
+            unit,
+            comment,
+        });
+        id
+    }
+
+    // Remove the silence with the given ID. Return an error if there is no such silence.
+    pub fn remove(&mut self, id: u32) -> Result<(), CrateError> {
+        let len = self.silences.len();
+        self.silences.retain(|silence| silence.id != id);
+        if self.silences.len() == len {
+            return Err(CrateError::State(StateError::NoSuchSilence(id)));
+        }
+        Ok(())
+    }
+
+    // Forget silences which have expired.
+    pub fn prune(&mut self, now: &RealtimeTimestamp) {
+        self.silences.retain(|silence| now.0 < silence.expires);
+    }
+
+    // Tell whether any silence applies to the given unit at the given time.
+    pub fn silences(&self, unit_name: &str, now: &RealtimeTimestamp) -> bool {
+        self.silences
+            .iter()
+            .any(|silence| silence.applies_to(unit_name, now))
+    }
+
+    // Get all silences, in order of creation.
+    pub fn iter(&self) -> impl Iterator<Item = &Silence> {
+        self.silences.iter()
+    }
+}
+
+// Get the path to the silences file, creating parent directories if necessary.
+//
+// The file is placed in `$XDG_STATE_HOME/killjoy`, so that silences survive restarts.
+pub fn get_silences_path() -> Result<PathBuf, CrateError> {
+    BaseDirectories::with_prefix("killjoy")
+        .map_err(|err| CrateError::State(StateError::PlaceSilencesFile(IOError::other(err))))?
+        .place_state_file("silences.json")
+        .map_err(|err| CrateError::State(StateError::PlaceSilencesFile(err)))
+}
+
+// Parse a duration such as "90s", "30m", "1h30m" or "2d".
+//
+// Supported units are `s`, `m`, `h`, `d` and `w`. A bare number is a number of seconds.
+pub fn parse_duration(duration_str: &str) -> Result<Duration, CrateError> {
+    let invalid = || CrateError::State(StateError::InvalidDuration(duration_str.to_string()));
+    let mut secs: u64 = 0;
+    let mut digits = String::new();
+    for c in duration_str.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        secs = value
+            .checked_mul(multiplier)
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        secs = secs.checked_add(value).ok_or_else(invalid)?;
+    }
+    if secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+// Tell whether `text` matches the glob `pattern`, where `*` matches any sequence of characters and
+// `?` matches any one character.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None; // (index of last '*', text index it matched)
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    // glob_matches()
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("foo.service", "foo.service"));
+        assert!(glob_matches("*.service", "foo.service"));
+        assert!(glob_matches(
+            "postgresql@*.service",
+            "postgresql@14-main.service"
+        ));
+        assert!(glob_matches("f?o*", "foo.timer"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("*.service", "foo.timer"));
+        assert!(!glob_matches("foo", "foo.service"));
+        assert!(!glob_matches("f?o", "fo"));
+    }
+
+    // parse_duration()
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").ok(), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m").ok(), Some(Duration::from_secs(1800)));
+        assert_eq!(
+            parse_duration("1h30m").ok(),
+            Some(Duration::from_secs(5400))
+        );
+        assert_eq!(
+            parse_duration("2d").ok(),
+            Some(Duration::from_secs(172_800))
+        );
+        for duration_str in ["", "0", "1y", "h", "-1h"] {
+            match parse_duration(duration_str) {
+                Err(CrateError::State(StateError::InvalidDuration(_))) => {}
+                _ => panic!("expected InvalidDuration for {:?}", duration_str),
+            }
+        }
+    }
+
+    // Silences::add(), Silences::silences(), Silences::prune()
+    #[test]
+    fn test_silences() {
+        let mut silences = Silences::default();
+        let id1 = silences.add(
+            Some("foo*.service".to_string()),
+            None,
+            Duration::from_secs(60),
+            &RealtimeTimestamp(0),
+        );
+        let id2 = silences.add(None, None, Duration::from_secs(1), &RealtimeTimestamp(0));
+        assert_ne!(id1, id2);
+
+        assert!(silences.silences("foo-bar.service", &RealtimeTimestamp(0)));
+        assert!(silences.silences("baz.service", &RealtimeTimestamp(0)));
+        assert!(!silences.silences("baz.service", &RealtimeTimestamp(1_000_000)));
+        assert!(silences.silences("foo.service", &RealtimeTimestamp(59_999_999)));
+        assert!(!silences.silences("foo.service", &RealtimeTimestamp(60_000_000)));
+
+        silences.prune(&RealtimeTimestamp(1_000_000));
+        assert_eq!(silences.iter().count(), 1);
+    }
+
+    // Silences::remove()
+    #[test]
+    fn test_silences_remove() {
+        let mut silences = Silences::default();
+        let id = silences.add(None, None, Duration::from_secs(60), &RealtimeTimestamp(0));
+        silences.remove(id).expect("Failed to remove silence.");
+        match silences.remove(id) {
+            Err(CrateError::State(StateError::NoSuchSilence(_))) => {}
+            _ => panic!("expected NoSuchSilence; the silence has already been removed"),
+        }
+    }
+
+    // Silences::save(), Silences::load()
+    #[test]
+    fn test_silences_save_load() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let path = dir.path().join("silences.json");
+        assert_eq!(
+            Silences::load(&path)
+                .expect("Failed to load silences.")
+                .iter()
+                .count(),
+            0
+        );
+
+        let mut silences = Silences::default();
+        silences.add(
+            Some("foo.service".to_string()),
+            Some("upgrade".to_string()),
+            Duration::from_secs(60),
+            &RealtimeTimestamp(0),
+        );
+        silences.save(&path).expect("Failed to save silences.");
+        let loaded = Silences::load(&path).expect("Failed to load silences.");
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            silences.iter().collect::<Vec<_>>()
+        );
+    }
+}
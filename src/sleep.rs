@@ -0,0 +1,152 @@
+// Logic for noticing when the host suspends and resumes.
+//
+// logind broadcasts `PrepareForSleep(true)` on the system bus just before the host suspends, and
+// `PrepareForSleep(false)` once it has resumed. Units churn while the host suspends and resumes, so
+// bus watchers hold back notifications until the host has been awake for a grace period.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use dbus::{BusType, Connection, Error as DBusError, Message};
+
+use crate::error::{BusError, Error as CrateError};
+use crate::monitor::StopHandle;
+
+const BUS_NAME_FOR_LOGIN1: &str = "org.freedesktop.login1";
+const PATH_FOR_LOGIN1: &str = "/org/freedesktop/login1";
+const INTERFACE_FOR_LOGIN1_MANAGER: &str = "org.freedesktop.login1.Manager";
+const MEMBER_FOR_PREPARE_FOR_SLEEP: &str = "PrepareForSleep";
+
+// Whether the host is asleep, and when it last resumed.
+//
+// `wakes` counts how many times the host has resumed, so that bus watchers can tell when it has
+// resumed since they last checked. Clones share state, so a clone may be handed to each bus watcher
+// thread.
+#[derive(Clone, Debug, Default)]
+pub struct SleepState {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    asleep: bool,
+    resumed_at: Option<Instant>,
+    wakes: u64,
+}
+
+impl SleepState {
+    // Create a tracker for a host which is awake, and hasn't resumed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Record that the host is about to suspend, or that it has resumed.
+    pub fn set_asleep(&self, asleep: bool) {
+        let mut state = self.lock();
+        if state.asleep && !asleep {
+            state.resumed_at = Some(Instant::now());
+            state.wakes += 1;
+        }
+        state.asleep = asleep;
+    }
+
+    // Tell whether the host is asleep, or resumed less than `grace_period` ago.
+    pub fn is_settling(&self, grace_period: Duration) -> bool {
+        let state = self.lock();
+        state.asleep
+            || state
+                .resumed_at
+                .is_some_and(|resumed_at| resumed_at.elapsed() < grace_period)
+    }
+
+    // Get how many times the host has resumed.
+    pub fn wakes(&self) -> u64 {
+        self.lock().wakes
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Listen for logind's `PrepareForSleep` signal, and record it in `sleep`, until stopped.
+//
+// Whether to stop is checked at least once every `loop_timeout` milliseconds. Return an error if
+// unable to connect to the system bus, or to subscribe to the signal.
+pub fn run(sleep: &SleepState, loop_timeout: u32, stop: &StopHandle) -> Result<(), CrateError> {
+    let connection = Connection::get_private(BusType::System)
+        .map_err(|err| CrateError::Bus(BusError::ConnectToBus(err)))?;
+    let match_str = format!(
+        "type='signal',sender='{}',path='{}',interface='{}',member='{}'",
+        BUS_NAME_FOR_LOGIN1,
+        PATH_FOR_LOGIN1,
+        INTERFACE_FOR_LOGIN1_MANAGER,
+        MEMBER_FOR_PREPARE_FOR_SLEEP
+    );
+    connection
+        .add_match(&match_str)
+        .map_err(|err: DBusError| CrateError::Bus(BusError::AddSignalMatch(match_str, err)))?;
+    while !stop.is_stopped() {
+        for msg in connection.incoming(loop_timeout) {
+            if let Some(asleep) = parse_prepare_for_sleep(&msg) {
+                sleep.set_asleep(asleep);
+            }
+            if stop.is_stopped() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// If the given message is a `PrepareForSleep` signal, return its argument, i.e. whether the host is
+// about to suspend.
+fn parse_prepare_for_sleep(msg: &Message) -> Option<bool> {
+    let interface = msg.interface()?;
+    let member = msg.member()?;
+    if &*interface != INTERFACE_FOR_LOGIN1_MANAGER || &*member != MEMBER_FOR_PREPARE_FOR_SLEEP {
+        return None;
+    }
+    msg.read1::<bool>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SleepState::set_asleep(), SleepState::is_settling(), SleepState::wakes()
+    #[test]
+    fn test_sleep_state() {
+        let grace_period = Duration::from_secs(60);
+        let sleep = SleepState::new();
+        assert!(!sleep.is_settling(grace_period));
+        sleep.set_asleep(true);
+        assert!(sleep.clone().is_settling(grace_period));
+        assert_eq!(sleep.wakes(), 0);
+        sleep.set_asleep(false);
+        assert!(sleep.is_settling(grace_period));
+        assert!(!sleep.is_settling(Duration::from_secs(0)));
+        assert_eq!(sleep.wakes(), 1);
+        sleep.set_asleep(false);
+        assert_eq!(sleep.wakes(), 1);
+    }
+
+    // parse_prepare_for_sleep()
+    #[test]
+    fn test_parse_prepare_for_sleep() {
+        let msg = Message::new_signal(
+            PATH_FOR_LOGIN1,
+            INTERFACE_FOR_LOGIN1_MANAGER,
+            MEMBER_FOR_PREPARE_FOR_SLEEP,
+        )
+        .unwrap()
+        .append1(true);
+        assert_eq!(parse_prepare_for_sleep(&msg), Some(true));
+        let msg = Message::new_signal(PATH_FOR_LOGIN1, INTERFACE_FOR_LOGIN1_MANAGER, "Other")
+            .unwrap()
+            .append1(true);
+        assert_eq!(parse_prepare_for_sleep(&msg), None);
+    }
+}
@@ -0,0 +1,290 @@
+// A minimal SNMPv2c client, for sending traps to an SNMP manager.
+//
+// Traps are BER-encoded by hand, as only a handful of types are needed. Each trap carries the
+// variables defined by package/KILLJOY-MIB.txt, rooted at the manager's OID:
+//
+// *   `<oid>.0.1`: the killjoyUnitStateChange notification.
+// *   `<oid>.1.1` to `<oid>.1.4`: the unit's name, its old and new states, and the host name.
+//
+// See: RFC 3416 (protocol operations) and ITU-T X.690 (basic encoding rules).
+
+use std::io::{Error as IOError, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use crate::backend::{Notification, NotifierBackend};
+use crate::error::{Error as CrateError, NotifierError};
+use crate::settings::SnmpManager;
+
+// The OID of sysUpTime.0, which is the first variable of every trap.
+const SYS_UP_TIME: &[u32] = &[1, 3, 6, 1, 2, 1, 1, 3, 0];
+
+// The OID of snmpTrapOID.0, which is the second variable of every trap, and which names the trap.
+const SNMP_TRAP_OID: &[u32] = &[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0];
+
+// The version field of SNMPv2c messages.
+const VERSION_2C: i64 = 1;
+
+// BER tags.
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const TIME_TICKS: u8 = 0x43;
+const SNMPV2_TRAP: u8 = 0xa7;
+
+// The request ID of the next trap. Managers may use this to discard duplicate traps.
+static REQUEST_ID: AtomicI32 = AtomicI32::new(1);
+
+// The variables of a killjoyUnitStateChange trap.
+pub struct UnitStateChange<'a> {
+    pub unit: &'a str,
+    pub old_state: &'a str,
+    pub new_state: &'a str,
+    pub hostname: &'a str,
+}
+
+// Events are sent as killjoyUnitStateChange traps.
+impl NotifierBackend for SnmpManager {
+    fn name(&self) -> &'static str {
+        "snmp"
+    }
+
+    // Traps are sent over UDP, and aren't acknowledged, so there's nothing to wait for.
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        _timeout: Duration,
+    ) -> Result<(), CrateError> {
+        // TimeTicks are hundredths of a second, and wrap around.
+        let uptime = (notification.mono_ts.0 / 10_000) as u32;
+        let states = notification.states;
+        let change = UnitStateChange {
+            unit: notification.unit_name,
+            old_state: states.get(1).map(String::as_str).unwrap_or_default(),
+            new_state: states.first().map(String::as_str).unwrap_or_default(),
+            hostname: &notification.host.hostname,
+        };
+        send_trap(self, uptime, &change).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactSnmpManager(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+
+    fn ping(&self, notifier_name: &str, _timeout: Duration) -> Result<(), CrateError> {
+        ping(self).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactSnmpManager(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+}
+
+// Send a killjoyUnitStateChange trap to the given manager.
+//
+// `uptime` is the time at which the state changed, in hundredths of a second since boot.
+pub fn send_trap(
+    manager: &SnmpManager,
+    uptime: u32,
+    change: &UnitStateChange,
+) -> Result<(), IOError> {
+    let request_id = REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let message = gen_trap_message(manager, request_id, uptime, change);
+    let addr = resolve(manager)?;
+    let socket = match addr {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.send_to(&message, addr).map(|_| ())
+}
+
+// Check that the given manager's host name resolves.
+//
+// Traps are sent over UDP, and aren't acknowledged, so there's no way to tell whether the manager
+// is listening.
+pub fn ping(manager: &SnmpManager) -> Result<(), IOError> {
+    resolve(manager).map(|_| ())
+}
+
+// Generate an SNMPv2c message containing a killjoyUnitStateChange trap.
+fn gen_trap_message(
+    manager: &SnmpManager,
+    request_id: i32,
+    uptime: u32,
+    change: &UnitStateChange,
+) -> Vec<u8> {
+    let oid = |suffix: &[u32]| -> Vec<u32> { [&manager.oid[..], suffix].concat() };
+    let bindings: Vec<(Vec<u32>, Vec<u8>)> = vec![
+        (
+            SYS_UP_TIME.to_vec(),
+            encode(TIME_TICKS, &encode_unsigned(uptime)),
+        ),
+        (
+            SNMP_TRAP_OID.to_vec(),
+            encode(OBJECT_IDENTIFIER, &encode_oid(&oid(&[0, 1]))),
+        ),
+        (oid(&[1, 1]), encode(OCTET_STRING, change.unit.as_bytes())),
+        (
+            oid(&[1, 2]),
+            encode(OCTET_STRING, change.old_state.as_bytes()),
+        ),
+        (
+            oid(&[1, 3]),
+            encode(OCTET_STRING, change.new_state.as_bytes()),
+        ),
+        (
+            oid(&[1, 4]),
+            encode(OCTET_STRING, change.hostname.as_bytes()),
+        ),
+    ];
+    let bindings: Vec<u8> = bindings
+        .into_iter()
+        .flat_map(|(name, value)| {
+            let binding = [encode(OBJECT_IDENTIFIER, &encode_oid(&name)), value].concat();
+            encode(SEQUENCE, &binding)
+        })
+        .collect();
+
+    let pdu = [
+        encode(INTEGER, &encode_integer(request_id.into())),
+        encode(INTEGER, &encode_integer(0)), // error-status
+        encode(INTEGER, &encode_integer(0)), // error-index
+        encode(SEQUENCE, &bindings),
+    ]
+    .concat();
+    let message = [
+        encode(INTEGER, &encode_integer(VERSION_2C)),
+        encode(OCTET_STRING, manager.community.as_bytes()),
+        encode(SNMPV2_TRAP, &pdu),
+    ]
+    .concat();
+    encode(SEQUENCE, &message)
+}
+
+// Encode a value with the given tag and contents.
+//
+// The length of the contents is encoded in between. Lengths below 128 are encoded in one byte.
+// Longer lengths are encoded big-endian, preceded by a byte stating how many bytes they take.
+fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded: Vec<u8> = vec![tag];
+    if contents.len() < 128 {
+        encoded.push(contents.len() as u8);
+    } else {
+        let len_bytes = contents.len().to_be_bytes();
+        let len_bytes: Vec<u8> = len_bytes.iter().copied().skip_while(|&b| b == 0).collect();
+        encoded.push(0x80 | len_bytes.len() as u8);
+        encoded.extend_from_slice(&len_bytes);
+    }
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+// Encode the contents of an INTEGER, in as few two's complement bytes as possible.
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    // A leading byte is redundant if it and the next byte's sign bit are all zeros or all ones.
+    while start < bytes.len() - 1
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+// Encode the contents of an unsigned value, such as TimeTicks.
+fn encode_unsigned(value: u32) -> Vec<u8> {
+    encode_integer(value.into())
+}
+
+// Encode the contents of an OBJECT IDENTIFIER.
+//
+// The first two arcs are combined into one, and each arc is then encoded seven bits per byte, most
+// significant first, with the top bit set on all but the last byte.
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut arcs: Vec<u32> = vec![oid[0] * 40 + oid[1]];
+    arcs.extend_from_slice(&oid[2..]);
+    let mut encoded: Vec<u8> = Vec::new();
+    for arc in arcs {
+        let mut groups: Vec<u8> = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        encoded.extend(groups.iter().rev());
+    }
+    encoded
+}
+
+// Get the address of the given manager.
+fn resolve(manager: &SnmpManager) -> Result<SocketAddr, IOError> {
+    (&manager.host[..], manager.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| IOError::new(ErrorKind::NotFound, "host has no addresses"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // encode(), encode_integer(), encode_unsigned()
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(INTEGER, &encode_integer(0)), vec![0x02, 0x01, 0x00]);
+        assert_eq!(encode_integer(127), vec![0x7f]);
+        assert_eq!(encode_integer(128), vec![0x00, 0x80]);
+        assert_eq!(encode_integer(-129), vec![0xff, 0x7f]);
+        assert_eq!(
+            encode_unsigned(u32::MAX),
+            vec![0x00, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(&encode(OCTET_STRING, &[0; 200])[..3], &[0x04, 0x81, 200]);
+        assert_eq!(&encode(OCTET_STRING, &[0; 256])[..4], &[0x04, 0x82, 1, 0]);
+    }
+
+    // encode_oid()
+    #[test]
+    fn test_encode_oid() {
+        assert_eq!(
+            encode_oid(&[1, 3, 6, 1, 4, 1, 8072]),
+            vec![0x2b, 6, 1, 4, 1, 0xbf, 0x08]
+        );
+        assert_eq!(encode_oid(&[2, 100]), vec![0x81, 0x34]);
+    }
+
+    // gen_trap_message()
+    #[test]
+    fn test_gen_trap_message() {
+        let mut manager = SnmpManager::new("localhost");
+        manager.oid = vec![1, 3];
+        let change = UnitStateChange {
+            unit: "a",
+            old_state: "",
+            new_state: "b",
+            hostname: "c",
+        };
+        let message = gen_trap_message(&manager, 1, 0, &change);
+        let header: &[u8] = &[
+            0x30, 0x61, // message
+            0x02, 0x01, 0x01, // version
+            0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', // community
+            0xa7, 0x54, // trap
+            0x02, 0x01, 0x01, // request-id
+        ];
+        assert_eq!(&message[..header.len()], header);
+        assert_eq!(message.len(), 0x63);
+        // The last variable is <oid>.1.4 = "c".
+        assert_eq!(
+            &message[message.len() - 10..],
+            &[0x30, 0x08, 0x06, 0x03, 0x2b, 0x01, 0x04, 0x04, 0x01, b'c']
+        );
+    }
+}
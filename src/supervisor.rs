@@ -0,0 +1,345 @@
+// Logic for keeping bus watchers running.
+//
+// The supervisor spawns one thread per bus, or one for every bus, and owns the resources which
+// those threads share: the health tracker read by the heartbeat and the control socket, and the
+// metrics read by the control socket. If a bus watcher fails or panics, e.g. because systemd
+// restarted and dropped its connection, the error is reported on stderr, the bus is marked down,
+// and a new bus watcher is started after a delay. The delay doubles with each consecutive failure,
+// up to a limit, and is reset once a bus watcher has run for a while. A bus watcher which fails
+// before it ever got going, e.g. because systemd isn't on the bus, isn't restarted: retrying
+// wouldn't help, so its error is returned at once.
+//
+// The event log is shared too, but it's serialized by the `event_log` module itself. D-Bus
+// connections can't be shared between threads, so each thread keeps its own connections to
+// notifiers. See `bus::with_notifier_connection`.
+
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use dbus::BusType;
+use serde_json::{json, Value};
+
+use crate::bus::BusWatcher;
+use crate::error::Error as CrateError;
+use crate::heartbeat::Health;
+use crate::monitor::{RunOptions, StopHandle};
+use crate::settings::Settings;
+use crate::sleep::SleepState;
+
+// How long to wait before restarting a bus watcher which failed for the first time in a while.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// The longest that a bus watcher may wait to be restarted.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
+
+// How long a bus watcher must run before its failure is no longer considered consecutive.
+const STABLE_RUN_TIME: Duration = Duration::from_secs(600);
+
+// How often a waiting supervisor thread checks whether it should stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Counters describing what the bus watchers have done since killjoy started.
+//
+// Clones share state, so a clone may be handed to each bus watcher thread.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    events: Arc<AtomicU64>,
+    notifications_failed: Arc<AtomicU64>,
+    notifications_sent: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    // Create counters which are all zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Count a state transition which matched a rule.
+    pub fn record_event(&self) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Count an attempt to contact a notifier, and whether it succeeded.
+    pub fn record_notification(&self, succeeded: bool) {
+        let counter = if succeeded {
+            &self.notifications_sent
+        } else {
+            &self.notifications_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Describe the counters as a JSON object.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "events": self.events.load(Ordering::Relaxed),
+            "notifications_failed": self.notifications_failed.load(Ordering::Relaxed),
+            "notifications_sent": self.notifications_sent.load(Ordering::Relaxed),
+        })
+    }
+}
+
+// How long to wait before restarting a bus watcher.
+#[derive(Debug)]
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            delay: MIN_RESTART_DELAY,
+        }
+    }
+
+    // Get the delay after a bus watcher which ran for `uptime` failed, and double the next one.
+    fn next_delay(&mut self, uptime: Duration) -> Duration {
+        if uptime >= STABLE_RUN_TIME {
+            self.delay = MIN_RESTART_DELAY;
+        }
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_RESTART_DELAY);
+        delay
+    }
+}
+
+// Spawns bus watchers, restarts them when they fail, and owns what they share.
+#[derive(Clone, Debug)]
+pub struct Supervisor {
+    pub health: Health,
+    pub metrics: Metrics,
+}
+
+impl Supervisor {
+    // Create a supervisor for the watchers of the given buses, none of which have started yet.
+    pub fn new(bus_types: &[BusType]) -> Self {
+        Self {
+            health: Health::new(bus_types),
+            metrics: Metrics::new(),
+        }
+    }
+
+    // Spawn one supervised bus watcher thread per bus, or a single thread for every bus if
+    // `options.single_thread` is set.
+    //
+    // Each thread runs its bus watchers until `options.stop` is stopped, and restarts them with
+    // backoff whenever one fails, unless they failed before they ever started. If
+    // `options.loop_once` or `options.oneshot` is set, bus watchers aren't restarted, and the
+    // thread returns the bus watcher's error, if any, when joined. If `sleep` is given, each bus
+    // watcher holds back notifications while the host suspends and resumes.
+    pub fn spawn(
+        &self,
+        bus_types: Vec<BusType>,
+        settings: &Settings,
+        options: &RunOptions,
+        sleep: Option<SleepState>,
+    ) -> Vec<JoinHandle<Result<(), CrateError>>> {
+        let thread_bus_types: Vec<Vec<BusType>> = if options.single_thread && !bus_types.is_empty()
+        {
+            vec![bus_types]
+        } else {
+            bus_types
+                .into_iter()
+                .map(|bus_type| vec![bus_type])
+                .collect()
+        };
+        thread_bus_types
+            .into_iter()
+            .map(|bus_types| {
+                let supervisor = self.clone();
+                let settings = settings.clone();
+                let options = options.clone();
+                let sleep = sleep.clone();
+                thread::spawn(move || supervisor.supervise(&bus_types, &settings, &options, sleep))
+            })
+            .collect()
+    }
+
+    // Run bus watchers for the given buses, and restart them whenever one fails, until stopped.
+    //
+    // Several bus watchers run together in the calling thread. See `BusWatcher::run_together`. If
+    // one fails, they're all restarted.
+    fn supervise(
+        &self,
+        bus_types: &[BusType],
+        settings: &Settings,
+        options: &RunOptions,
+        sleep: Option<SleepState>,
+    ) -> Result<(), CrateError> {
+        self.supervise_with(bus_types, options, || {
+            let mut bus_watchers = bus_types
+                .iter()
+                .map(|bus_type| {
+                    BusWatcher::new(
+                        *bus_type,
+                        settings.clone(),
+                        options.clone(),
+                        None,
+                        Some(self.health.clone()),
+                        Some(self.metrics.clone()),
+                        sleep.clone(),
+                    )
+                })
+                .collect::<Result<Vec<BusWatcher>, CrateError>>()?;
+            match &mut bus_watchers[..] {
+                [bus_watcher] => bus_watcher.run(),
+                bus_watchers => BusWatcher::run_together(bus_watchers),
+            }
+        })
+    }
+
+    // Call `run_bus_watchers`, which runs the bus watchers for the given buses, and call it again
+    // whenever it fails, until stopped.
+    //
+    // The bus watchers have started once each of them has reported to `health`. Until then, a
+    // failure is returned rather than retried, as it's likely to be permanent, e.g. systemd being
+    // absent from the bus. Once they have started, every failure is retried.
+    fn supervise_with<F>(
+        &self,
+        bus_types: &[BusType],
+        options: &RunOptions,
+        mut run_bus_watchers: F,
+    ) -> Result<(), CrateError>
+    where
+        F: FnMut() -> Result<(), CrateError>,
+    {
+        let restart = !options.loop_once && !options.oneshot;
+        let mut backoff = Backoff::new();
+        let mut has_started = false;
+        loop {
+            let started = Instant::now();
+            let result = panic::catch_unwind(AssertUnwindSafe(&mut run_bus_watchers))
+                .unwrap_or_else(|err| Err(CrateError::MonitoringThreadPanicked(err)));
+            has_started |= bus_types
+                .iter()
+                .all(|bus_type| self.health.has_reported(*bus_type));
+            for bus_type in bus_types {
+                self.health.mark_down(*bus_type);
+            }
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(err) if !restart || !has_started || options.stop.is_stopped() => {
+                    return Err(err)
+                }
+                Err(err) => err,
+            };
+            let delay = backoff.next_delay(started.elapsed());
+            let bus_names: Vec<&str> = bus_types
+                .iter()
+                .map(|bus_type| crate::settings::encode_bus_type(*bus_type))
+                .collect();
+            eprintln!(
+                "Bus watcher for the {} bus failed, restarting in {}s: {}",
+                bus_names.join(" and "),
+                delay.as_secs(),
+                err.with_code()
+            );
+            if !wait(delay, &options.stop) {
+                return Ok(());
+            }
+            for bus_type in bus_types {
+                self.health.record_restart(*bus_type);
+            }
+        }
+    }
+}
+
+// Wait for `delay`, or until `stop` is stopped. Return whether the full delay elapsed.
+fn wait(delay: Duration, stop: &StopHandle) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if stop.is_stopped() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep(STOP_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::BusError;
+
+    // Backoff::next_delay()
+    #[test]
+    fn test_backoff_next_delay() {
+        let mut backoff = Backoff::new();
+        let quick = Duration::from_secs(0);
+        assert_eq!(backoff.next_delay(quick), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(quick), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(quick), Duration::from_secs(4));
+        for _ in 0..10 {
+            backoff.next_delay(quick);
+        }
+        assert_eq!(backoff.next_delay(quick), MAX_RESTART_DELAY);
+        assert_eq!(backoff.next_delay(STABLE_RUN_TIME), MIN_RESTART_DELAY);
+    }
+
+    // Metrics::record_event(), Metrics::record_notification(), Metrics::to_json()
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_event();
+        metrics.clone().record_notification(true);
+        metrics.record_notification(true);
+        metrics.record_notification(false);
+        assert_eq!(
+            metrics.to_json(),
+            json!({"events": 1, "notifications_failed": 1, "notifications_sent": 2})
+        );
+    }
+
+    // Supervisor::supervise_with()
+    #[test]
+    fn test_supervise_startup_failure() {
+        let supervisor = Supervisor::new(&[BusType::Session]);
+        let mut calls = 0;
+        let result = supervisor.supervise_with(&[BusType::Session], &RunOptions::default(), || {
+            calls += 1;
+            Err(BusError::MessageLacksPath.into())
+        });
+        assert!(matches!(
+            result,
+            Err(CrateError::Bus(BusError::MessageLacksPath))
+        ));
+        assert_eq!(calls, 1);
+        assert_eq!(supervisor.health.statuses(STABLE_RUN_TIME)[0].restarts, 0);
+    }
+
+    // Supervisor::supervise_with()
+    #[test]
+    fn test_supervise_restart() {
+        let supervisor = Supervisor::new(&[BusType::Session]);
+        let mut calls = 0;
+        let result = supervisor.supervise_with(&[BusType::Session], &RunOptions::default(), || {
+            calls += 1;
+            if calls == 1 {
+                supervisor.health.report(BusType::Session);
+                return Err(BusError::MessageLacksPath.into());
+            }
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+        assert_eq!(supervisor.health.statuses(STABLE_RUN_TIME)[0].restarts, 1);
+        assert!(!supervisor.health.has_reported(BusType::Session));
+    }
+
+    // wait()
+    #[test]
+    fn test_wait_stopped() {
+        let stop = StopHandle::new();
+        stop.stop();
+        assert!(!wait(Duration::from_secs(60), &stop));
+        assert!(wait(Duration::from_secs(0), &StopHandle::new()));
+    }
+}
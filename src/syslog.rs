@@ -0,0 +1,225 @@
+// A minimal syslog client, for logging events to a local or remote syslog server.
+//
+// Messages are formatted as per RFC 5424. Like MQTT events, each message is sent over a new socket,
+// so that nothing needs to be reconnected when the server restarts.
+//
+// See: https://www.rfc-editor.org/rfc/rfc5424 and https://www.rfc-editor.org/rfc/rfc6587
+
+use std::io::{Error as IOError, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+use crate::backend::{Notification, NotifierBackend};
+use crate::error::{Error as CrateError, NotifierError};
+use crate::settings::{Severity, SyslogServer, SyslogTransport};
+use crate::timestamp::RealtimeTimestamp;
+
+// The APP-NAME field of each message.
+const APP_NAME: &str = "killjoy";
+
+// Events are logged with the notification's message, or with a default description of the event.
+impl NotifierBackend for SyslogServer {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn send(
+        &self,
+        notifier_name: &str,
+        notification: &Notification,
+        timeout: Duration,
+    ) -> Result<(), CrateError> {
+        let default_text = format!(
+            "{} entered the {} state.",
+            notification.unit_name,
+            notification
+                .states
+                .first()
+                .map(String::as_str)
+                .unwrap_or_default()
+        );
+        let text = notification.message.as_deref().unwrap_or(&default_text);
+        let message = gen_message(
+            self,
+            notification.severity,
+            notification.real_ts,
+            &notification.host.hostname,
+            text,
+        );
+        send(self, &message, timeout).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactSyslogServer(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+
+    fn ping(&self, notifier_name: &str, timeout: Duration) -> Result<(), CrateError> {
+        ping(self, timeout).map_err(|err| {
+            CrateError::Notifier(NotifierError::ContactSyslogServer(
+                notifier_name.to_string(),
+                err,
+            ))
+        })
+    }
+}
+
+// Send a message to the given server.
+//
+// `timeout` is how long to wait for a remote server when connecting or writing.
+pub fn send(server: &SyslogServer, message: &str, timeout: Duration) -> Result<(), IOError> {
+    match &server.transport {
+        SyslogTransport::Tcp(host, port) => {
+            let mut stream = connect_tcp(host, *port, timeout)?;
+            stream.write_all(format!("{} {}", message.len(), message).as_bytes())
+        }
+        SyslogTransport::Udp(host, port) => {
+            let addr = resolve(host, *port)?[0];
+            bind_udp(&addr)?
+                .send_to(message.as_bytes(), addr)
+                .map(|_| ())
+        }
+        SyslogTransport::Unix(path) => UnixDatagram::unbound()?
+            .send_to(message.as_bytes(), path)
+            .map(|_| ()),
+    }
+}
+
+// Check that the given server accepts messages, as far as is possible without sending one.
+//
+// UDP is connectionless, so for UDP servers, this only checks that the host name resolves.
+pub fn ping(server: &SyslogServer, timeout: Duration) -> Result<(), IOError> {
+    match &server.transport {
+        SyslogTransport::Tcp(host, port) => connect_tcp(host, *port, timeout).map(|_| ()),
+        SyslogTransport::Udp(host, port) => resolve(host, *port).map(|_| ()),
+        SyslogTransport::Unix(path) => UnixDatagram::unbound()?.connect(path),
+    }
+}
+
+// Generate an RFC 5424 message.
+//
+// The message's priority combines the server's facility with the syslog priority of `severity`.
+// It has no MSGID or structured data.
+pub fn gen_message(
+    server: &SyslogServer,
+    severity: Severity,
+    timestamp: &RealtimeTimestamp,
+    hostname: &str,
+    text: &str,
+) -> String {
+    let pri = u32::from(server.facility) * 8 + u32::from(severity.syslog_priority());
+    let hostname = if hostname.is_empty() || hostname.contains(char::is_whitespace) {
+        "-"
+    } else {
+        hostname
+    };
+    format!(
+        "<{}>1 {} {} {} {} - - {}",
+        pri,
+        timestamp.to_iso8601(),
+        hostname,
+        APP_NAME,
+        process::id(),
+        text
+    )
+}
+
+// Open a connection to the given host and port, trying each of its addresses in turn.
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, IOError> {
+    let mut last_err = IOError::new(ErrorKind::NotFound, "host has no addresses");
+    for addr in resolve(host, port)? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+// Bind a UDP socket from which datagrams may be sent to `addr`.
+fn bind_udp(addr: &SocketAddr) -> Result<UdpSocket, IOError> {
+    match addr {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0"),
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0"),
+    }
+}
+
+// Get the addresses of the given host and port. Return an error if there are none.
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, IOError> {
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(IOError::new(ErrorKind::NotFound, "host has no addresses"));
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use tempfile::TempDir;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // gen_message()
+    #[test]
+    fn test_gen_message() {
+        let mut server = SyslogServer::new(SyslogTransport::Unix("/dev/log".into()));
+        server.facility = 16;
+        let message = gen_message(
+            &server,
+            Severity::Critical,
+            &RealtimeTimestamp(0),
+            "host",
+            "foo.service entered the failed state.",
+        );
+        assert_eq!(
+            message,
+            format!(
+                "<130>1 1970-01-01T00:00:00Z host killjoy {} - - foo.service entered the failed state.",
+                process::id()
+            )
+        );
+        let message = gen_message(&server, Severity::Info, &RealtimeTimestamp(0), "", "x");
+        assert!(message.starts_with("<134>1 1970-01-01T00:00:00Z - killjoy "));
+    }
+
+    // send()
+    #[test]
+    fn test_send_unix() {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let path = dir.path().join("log");
+        let socket = UnixDatagram::bind(&path).expect("Failed to bind socket.");
+        let server = SyslogServer::new(SyslogTransport::Unix(path));
+        ping(&server, TIMEOUT).expect("Failed to ping server.");
+        send(&server, "<30>1 - - killjoy 1 - - hi", TIMEOUT).expect("Failed to send message.");
+        let mut buf = [0u8; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"<30>1 - - killjoy 1 - - hi");
+    }
+
+    // send()
+    #[test]
+    fn test_send_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen.");
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+        let server = SyslogServer::new(SyslogTransport::Tcp("127.0.0.1".to_string(), port));
+        send(&server, "<30>1 hi", TIMEOUT).expect("Failed to send message.");
+        assert_eq!(handle.join().unwrap(), "8 <30>1 hi");
+    }
+}
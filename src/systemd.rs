@@ -0,0 +1,462 @@
+// Logic for exchanging messages with systemd.
+//
+// Bus watchers don't talk to a D-Bus connection directly. They talk to a `SystemdManager`, which
+// lists units, looks them up, gets their properties and subscribes to their changes, and to the
+// `SystemdBackend` beneath it, which sends method calls to systemd, and hands back its replies and
+// signals. A D-Bus connection is the usual backend. During early boot, it may be a connection to
+// systemd's private socket. See `EarlyBootConnection`. If the settings ask for it, systemd's
+// Varlink sockets are used instead. See `VarlinkSystemd`. With the `mock-bus` feature, a simulated
+// systemd may be used too, e.g. for development on hosts without systemd, or for deterministic
+// examples and tests. See `MockSystemd`.
+
+use std::time::Duration;
+
+use dbus::arg::{Get, Variant};
+use dbus::{BusName, BusType, ConnPath, Connection, Error as DBusError, Message, Path};
+
+use crate::bus;
+use crate::bus::{UnitProps, INTERFACE_FOR_PROPERTIES, INTERFACE_FOR_SYSTEMD_MANAGER};
+use crate::early_boot::EarlyBootConnection;
+use crate::error::Error as CrateError;
+use crate::generated::org_freedesktop_portable1::OrgFreedesktopPortable1Manager;
+use crate::generated::org_freedesktop_systemd1_units as unit_types;
+use crate::property;
+use crate::settings::Transport;
+use crate::varlink::VarlinkSystemd;
+
+const BUS_NAME_FOR_PORTABLED: &str = "org.freedesktop.portable1";
+const INTERFACE_FOR_PORTABLED_MANAGER: &str = "org.freedesktop.portable1.Manager";
+const INTERFACE_FOR_SYSTEMD_MOUNT: &str = "org.freedesktop.systemd1.Mount";
+const PATH_FOR_PORTABLED: &str = "/org/freedesktop/portable1";
+
+// A unit, as returned by `org.freedesktop.systemd1.Manager.ListUnits`: its name, description, load
+// state, active state, sub state, followed unit, object path, queued job ID, job type and job path.
+type ListedUnitRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    Path<'static>,
+    u32,
+    String,
+    Path<'static>,
+);
+
+// A portable image, as returned by `org.freedesktop.portable1.Manager.ListImages`: its name, type,
+// whether it's read-only, its creation and modification times, its disk usage, its state and its
+// object path.
+type ListedImageRow = (String, String, bool, u64, u64, u64, String, Path<'static>);
+
+// A unit, as listed by `org.freedesktop.systemd1.Manager.ListUnits`.
+#[derive(Clone, Debug)]
+pub struct ListedUnit {
+    pub active_state: String,
+    pub name: String,
+    pub path: Path<'static>,
+}
+
+// A way of exchanging D-Bus messages with systemd.
+//
+// Messages are `dbus::Message`s whichever the backend, so that bus watchers build calls and read
+// replies and signals in the same way. Backends needn't be `Send`, as each bus watcher stays on
+// the thread which created it.
+pub trait SystemdBackend {
+    // Send a method call, and wait up to `timeout_ms` for its reply. Return an error if the call
+    // fails, or if no reply arrives in time.
+    fn call(&self, msg: Message, timeout_ms: i32) -> Result<Message, DBusError>;
+
+    // Send a message without waiting for a reply, and return its serial, or `None` if it can't be
+    // sent. The reply, if any, is handed back by `pop_message` later, like any other message.
+    fn send(&self, msg: Message) -> Option<u32>;
+
+    // Get the next message which has arrived, waiting up to `timeout_ms` for one if none has.
+    // Return `None` if none arrives in time.
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message>;
+
+    // Ask to receive the signals matching the given match rule.
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError>;
+
+    // Stop receiving the signals matching the given match rule.
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError>;
+
+    // Get the file descriptors which become ready when messages may have arrived, for use with
+    // poll(2). A backend which returns none is still asked for messages at least once per loop
+    // timeout.
+    fn get_pollfds(&self) -> Vec<libc::pollfd>;
+
+    // Get how often the backend must be asked for messages, even if none of its file descriptors
+    // become ready, or `None` if it needn't be. A backend which notices changes by polling systemd
+    // needs this, so that bus watchers in low-power mode don't sleep indefinitely.
+    fn get_poll_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl SystemdBackend for Connection {
+    fn call(&self, msg: Message, timeout_ms: i32) -> Result<Message, DBusError> {
+        self.send_with_reply_and_block(msg, timeout_ms)
+    }
+
+    fn send(&self, msg: Message) -> Option<u32> {
+        Connection::send(self, msg).ok()
+    }
+
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message> {
+        self.incoming(timeout_ms).next()
+    }
+
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError> {
+        Connection::add_match(self, match_str)
+    }
+
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError> {
+        Connection::remove_match(self, match_str).map(|_| ())
+    }
+
+    // The file descriptors which libdbus wants the connection's socket polled on.
+    fn get_pollfds(&self) -> Vec<libc::pollfd> {
+        self.watch_fds()
+            .iter()
+            .map(|watch| {
+                let mut events: libc::c_short = 0;
+                if watch.readable() {
+                    events |= libc::POLLIN;
+                }
+                if watch.writable() {
+                    events |= libc::POLLOUT;
+                }
+                libc::pollfd {
+                    fd: watch.fd(),
+                    events,
+                    revents: 0,
+                }
+            })
+            .collect()
+    }
+}
+
+// The calls which bus watchers make to systemd's manager.
+//
+// Each method has a default implementation, which makes the D-Bus call of the same name through
+// the backend. A backend which doesn't speak D-Bus to systemd may override them, and needs only
+// translate its own replies. Signals to which `subscribe` subscribes are handed back by the
+// backend's `pop_message`, like any other message. A D-Bus connection overrides the calls which
+// have typed proxies in `crate::generated`, and makes them through those.
+pub trait SystemdManager: SystemdBackend {
+    // Call `org.freedesktop.systemd1.Manager.ListUnits`, and return the name, ActiveState and
+    // object path of every loaded unit.
+    fn list_units(&self, timeout_ms: i32) -> Result<Vec<ListedUnit>, DBusError> {
+        let msg = gen_manager_call("ListUnits");
+        let units: Vec<ListedUnitRow> = self.call(msg, timeout_ms)?.read1()?;
+        Ok(units
+            .into_iter()
+            .map(|unit| ListedUnit {
+                active_state: unit.3,
+                name: unit.0,
+                path: unit.6,
+            })
+            .collect())
+    }
+
+    // Call `org.freedesktop.systemd1.Manager.GetUnit`, and return the object path of the given
+    // unit, or an error if it isn't loaded.
+    fn get_unit(&self, unit_name: &str, timeout_ms: i32) -> Result<Path<'static>, DBusError> {
+        let msg = gen_manager_call("GetUnit").append1(unit_name);
+        Ok(self.call(msg, timeout_ms)?.read1()?)
+    }
+
+    // Call `org.freedesktop.DBus.Properties.GetAll` on the object at `path`, and return the
+    // properties of the given interface.
+    fn get_all_properties(
+        &self,
+        path: &Path,
+        interface_name: &str,
+        timeout_ms: i32,
+    ) -> Result<UnitProps, DBusError> {
+        let msg =
+            bus::gen_systemd_call(path, INTERFACE_FOR_PROPERTIES, "GetAll").append1(interface_name);
+        Ok(self.call(msg, timeout_ms)?.read1()?)
+    }
+
+    // Call `org.freedesktop.systemd1.Manager.Subscribe`, so that the manager emits signals about
+    // units, such as `UnitNew` and `UnitRemoved`. By default, it emits few signals.
+    fn subscribe(&self, timeout_ms: i32) -> Result<(), DBusError> {
+        self.call(gen_manager_call("Subscribe"), timeout_ms)
+            .map(|_| ())
+    }
+
+    // Get the `Result` property of the given unit, which is at `path`, from the interface of its
+    // type, e.g. `org.freedesktop.systemd1.Service`. Units of some types, e.g. devices, have no
+    // result, so getting it fails.
+    fn get_unit_result(
+        &self,
+        unit_name: &str,
+        path: &Path,
+        timeout_ms: i32,
+    ) -> Result<String, DBusError> {
+        let interface_name =
+            property::get_type_interface(unit_name).ok_or_else(|| gen_no_result(unit_name))?;
+        get_property(self, path, &interface_name, "Result", timeout_ms)
+    }
+
+    // Get the `Where` property of the mount unit at `path`, i.e. its mount point.
+    fn get_mount_point(&self, path: &Path, timeout_ms: i32) -> Result<String, DBusError> {
+        get_property(self, path, INTERFACE_FOR_SYSTEMD_MOUNT, "Where", timeout_ms)
+    }
+
+    // Call `org.freedesktop.portable1.Manager.ListImages`, and return each image's name and state.
+    fn list_images(&self, timeout_ms: i32) -> Result<Vec<(String, String)>, DBusError> {
+        let msg = Message::new_method_call(
+            BUS_NAME_FOR_PORTABLED,
+            PATH_FOR_PORTABLED,
+            INTERFACE_FOR_PORTABLED_MANAGER,
+            "ListImages",
+        )
+        .unwrap_or_else(|err| panic!("Failed to create ListImages call: {}", err));
+        let images: Vec<ListedImageRow> = self.call(msg, timeout_ms)?.read1()?;
+        Ok(images.into_iter().map(|image| (image.0, image.6)).collect())
+    }
+}
+
+impl SystemdManager for Connection {
+    fn get_unit_result(
+        &self,
+        unit_name: &str,
+        path: &Path,
+        timeout_ms: i32,
+    ) -> Result<String, DBusError> {
+        let conn_path = get_conn_path(self, path, timeout_ms);
+        match unit_name.rsplit_once('.').map(|(_, unit_type)| unit_type) {
+            Some("automount") => {
+                unit_types::OrgFreedesktopSystemd1Automount::get_result(&conn_path)
+            }
+            Some("mount") => unit_types::OrgFreedesktopSystemd1Mount::get_result(&conn_path),
+            Some("path") => unit_types::OrgFreedesktopSystemd1Path::get_result(&conn_path),
+            Some("scope") => unit_types::OrgFreedesktopSystemd1Scope::get_result(&conn_path),
+            Some("service") => unit_types::OrgFreedesktopSystemd1Service::get_result(&conn_path),
+            Some("socket") => unit_types::OrgFreedesktopSystemd1Socket::get_result(&conn_path),
+            Some("swap") => unit_types::OrgFreedesktopSystemd1Swap::get_result(&conn_path),
+            Some("timer") => unit_types::OrgFreedesktopSystemd1Timer::get_result(&conn_path),
+            _ => Err(gen_no_result(unit_name)),
+        }
+    }
+
+    fn get_mount_point(&self, path: &Path, timeout_ms: i32) -> Result<String, DBusError> {
+        unit_types::OrgFreedesktopSystemd1Mount::get_where(&get_conn_path(self, path, timeout_ms))
+    }
+
+    fn list_images(&self, timeout_ms: i32) -> Result<Vec<(String, String)>, DBusError> {
+        let path = Path::new(PATH_FOR_PORTABLED)
+            .unwrap_or_else(|_| panic!("Failed to create Path from '{}'", PATH_FOR_PORTABLED));
+        let bus_name = BusName::new(BUS_NAME_FOR_PORTABLED).unwrap_or_else(|_| {
+            panic!("Failed to create BusName from '{}'", BUS_NAME_FOR_PORTABLED)
+        });
+        ConnPath {
+            conn: self,
+            dest: bus_name,
+            path,
+            timeout: timeout_ms,
+        }
+        .list_images()
+        .map(|images| images.into_iter().map(|image| (image.0, image.6)).collect())
+    }
+}
+
+// The backend which a bus watcher connects with, as chosen by the settings' `transport`.
+pub enum SystemdConnection {
+    DBus(Connection),
+    EarlyBoot(EarlyBootConnection),
+    Varlink(VarlinkSystemd),
+}
+
+impl SystemdConnection {
+    // Connect to the systemd instance which manages the units of the given bus, with the given
+    // transport.
+    //
+    // If the system bus can't be reached over D-Bus, systemd's private socket is tried instead,
+    // e.g. during early boot. If that fails too, the error from the system bus is returned.
+    pub fn new(bus_type: BusType, transport: Transport) -> Result<Self, CrateError> {
+        match transport {
+            Transport::DBus => match bus::connect(bus_type) {
+                Err(err) if bus_type == BusType::System && EarlyBootConnection::is_available() => {
+                    match EarlyBootConnection::connect() {
+                        Ok(connection) => {
+                            eprintln!(
+                                "Watching units through systemd's private socket until the system \
+                                 bus is up: {}",
+                                err.with_code()
+                            );
+                            Ok(SystemdConnection::EarlyBoot(connection))
+                        }
+                        Err(_) => Err(err),
+                    }
+                }
+                result => result.map(SystemdConnection::DBus),
+            },
+            Transport::Varlink => VarlinkSystemd::connect(bus_type).map(SystemdConnection::Varlink),
+        }
+    }
+
+    // Get the backend which is connected.
+    fn get_backend(&self) -> &dyn SystemdManager {
+        match self {
+            SystemdConnection::DBus(connection) => connection,
+            SystemdConnection::EarlyBoot(connection) => connection,
+            SystemdConnection::Varlink(varlink) => varlink,
+        }
+    }
+}
+
+impl SystemdBackend for SystemdConnection {
+    fn call(&self, msg: Message, timeout_ms: i32) -> Result<Message, DBusError> {
+        self.get_backend().call(msg, timeout_ms)
+    }
+
+    fn send(&self, msg: Message) -> Option<u32> {
+        self.get_backend().send(msg)
+    }
+
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message> {
+        self.get_backend().pop_message(timeout_ms)
+    }
+
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError> {
+        self.get_backend().add_match(match_str)
+    }
+
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError> {
+        self.get_backend().remove_match(match_str)
+    }
+
+    fn get_pollfds(&self) -> Vec<libc::pollfd> {
+        self.get_backend().get_pollfds()
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        self.get_backend().get_poll_interval()
+    }
+}
+
+impl SystemdManager for SystemdConnection {
+    fn list_units(&self, timeout_ms: i32) -> Result<Vec<ListedUnit>, DBusError> {
+        self.get_backend().list_units(timeout_ms)
+    }
+
+    fn get_unit(&self, unit_name: &str, timeout_ms: i32) -> Result<Path<'static>, DBusError> {
+        self.get_backend().get_unit(unit_name, timeout_ms)
+    }
+
+    fn get_all_properties(
+        &self,
+        path: &Path,
+        interface_name: &str,
+        timeout_ms: i32,
+    ) -> Result<UnitProps, DBusError> {
+        self.get_backend()
+            .get_all_properties(path, interface_name, timeout_ms)
+    }
+
+    fn subscribe(&self, timeout_ms: i32) -> Result<(), DBusError> {
+        self.get_backend().subscribe(timeout_ms)
+    }
+
+    fn get_unit_result(
+        &self,
+        unit_name: &str,
+        path: &Path,
+        timeout_ms: i32,
+    ) -> Result<String, DBusError> {
+        self.get_backend()
+            .get_unit_result(unit_name, path, timeout_ms)
+    }
+
+    fn get_mount_point(&self, path: &Path, timeout_ms: i32) -> Result<String, DBusError> {
+        self.get_backend().get_mount_point(path, timeout_ms)
+    }
+
+    fn list_images(&self, timeout_ms: i32) -> Result<Vec<(String, String)>, DBusError> {
+        self.get_backend().list_images(timeout_ms)
+    }
+}
+
+// Tell whether one of the given match rules asks for signals with the given member from the given
+// path, whose first argument is `arg0`, if it's a string.
+//
+// For backends which filter signals themselves, rather than leaving it to a bus. Match rules are
+// told apart by their member, path and first argument only, which suffices for the match rules of
+// bus watchers.
+pub fn is_matched(matches: &[String], member: &str, path: &str, arg0: Option<&str>) -> bool {
+    let member = format!("member='{}'", member);
+    let path = format!("path='{}'", path);
+    let arg0 = arg0.map(|arg0| format!("arg0='{}'", arg0));
+    matches.iter().any(|match_str| {
+        match_str.contains(&member)
+            && (!match_str.contains("path=") || match_str.contains(&path))
+            && (!match_str.contains("arg0=")
+                || arg0.as_ref().map_or(false, |arg0| match_str.contains(arg0)))
+    })
+}
+
+// Create the NameAcquired signal which a bus sends new connections, for backends without a bus.
+// Bus watchers expect to receive it first.
+pub fn gen_name_acquired(unique_name: &str) -> Message {
+    Message::new_signal(
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "NameAcquired",
+    )
+    .expect("Failed to create NameAcquired signal.")
+    .append1(unique_name)
+}
+
+// Get a `ConnPath` for `org.freedesktop.systemd1` and the given object path, through which the
+// typed proxies make their calls.
+fn get_conn_path<'a>(
+    connection: &'a Connection,
+    path: &Path<'a>,
+    timeout_ms: i32,
+) -> ConnPath<'a, &'a Connection> {
+    ConnPath {
+        conn: connection,
+        dest: bus::wrap_bus_name_for_systemd(),
+        path: path.to_owned(),
+        timeout: timeout_ms,
+    }
+}
+
+// Call `org.freedesktop.DBus.Properties.Get` on the object at `path`, and return the value of the
+// given property of the given interface, or an error if the call fails or the value isn't a `T`.
+fn get_property<B, T>(
+    backend: &B,
+    path: &Path,
+    interface_name: &str,
+    property_name: &str,
+    timeout_ms: i32,
+) -> Result<T, DBusError>
+where
+    B: SystemdBackend + ?Sized,
+    T: for<'a> Get<'a>,
+{
+    let msg = bus::gen_systemd_call(path, INTERFACE_FOR_PROPERTIES, "Get")
+        .append2(interface_name, property_name);
+    Ok(backend.call(msg, timeout_ms)?.read1::<Variant<T>>()?.0)
+}
+
+// Create the error returned when asked for the result of a unit whose type has none.
+fn gen_no_result(unit_name: &str) -> DBusError {
+    DBusError::new_custom(
+        "org.freedesktop.DBus.Error.UnknownProperty",
+        &format!("Units like {} have no Result property", unit_name),
+    )
+}
+
+// Create a call to the given method of `org.freedesktop.systemd1.Manager`.
+fn gen_manager_call(member: &str) -> Message {
+    bus::gen_systemd_call(
+        &bus::wrap_path_for_systemd(),
+        INTERFACE_FOR_SYSTEMD_MANAGER,
+        member,
+    )
+}
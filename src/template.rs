@@ -0,0 +1,172 @@
+// Logic for rendering notification messages.
+
+use std::collections::HashMap;
+
+use crate::error::{Error as CrateError, SettingsError};
+
+// The variables that may be referenced by a template.
+//
+// In addition, `labels.NAME` references the label `NAME` of the rule that matched, and `tags.NAME`
+// references the tag `NAME` of the host.
+pub const VARIABLES: [&str; 13] = [
+    "description",
+    "hostname",
+    "instance",
+    "machine_id",
+    "new_state",
+    "old_state",
+    "rule",
+    "severity",
+    "template",
+    "timestamp",
+    "timestamp_iso8601",
+    "timestamp_monotonic",
+    "unit",
+];
+
+// The prefix of variables that reference a rule's labels.
+pub const LABELS_PREFIX: &str = "labels.";
+
+// The prefix of variables that reference the host's tags.
+pub const TAGS_PREFIX: &str = "tags.";
+
+// A piece of a template.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+// A message template, such as "{{unit}} is now {{new_state}}".
+//
+// Variables are written as `{{name}}`, where `name` is one of `VARIABLES`. Whitespace inside the
+// braces is ignored. All other text is copied verbatim.
+#[derive(Clone, Debug)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    // Parse a template.
+    //
+    // Return an error if a variable is unterminated or unknown.
+    pub fn new(source: &str) -> Result<Self, CrateError> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let after_start = &rest[start + 2..];
+            let end = after_start.find("}}").ok_or_else(|| {
+                CrateError::Settings(SettingsError::InvalidTemplate(
+                    source.to_string(),
+                    "unterminated variable".to_string(),
+                ))
+            })?;
+            let name = after_start[..end].trim();
+            if !VARIABLES.contains(&name)
+                && !name.starts_with(LABELS_PREFIX)
+                && !name.starts_with(TAGS_PREFIX)
+            {
+                return Err(CrateError::Settings(SettingsError::InvalidTemplate(
+                    source.to_string(),
+                    format!("unknown variable '{}'", name),
+                )));
+            }
+            segments.push(Segment::Variable(name.to_string()));
+            rest = &after_start[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Template { segments })
+    }
+
+    // Tell whether this template references the given variable.
+    pub fn uses(&self, variable: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| *segment == Segment::Variable(variable.to_string()))
+    }
+
+    // Render this template. Variables absent from `values` are rendered as empty strings.
+    pub fn render(&self, values: &HashMap<String, String>) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => &text[..],
+                Segment::Variable(name) => values.get(&name[..]).map_or("", |value| &value[..]),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Template::render()
+    #[test]
+    fn test_template_render() {
+        let template = Template::new("{{unit}} went from {{ old_state }} to {{new_state}}.")
+            .expect("Failed to parse template.");
+        let mut values: HashMap<String, String> = HashMap::new();
+        values.insert("unit".to_string(), "foo.service".to_string());
+        values.insert("new_state".to_string(), "failed".to_string());
+        assert_eq!(
+            template.render(&values),
+            "foo.service went from  to failed."
+        );
+    }
+
+    // Template::render()
+    #[test]
+    fn test_template_render_labels() {
+        let template =
+            Template::new("[{{labels.team}}] {{unit}}").expect("Failed to parse template.");
+        let mut values: HashMap<String, String> = HashMap::new();
+        values.insert("unit".to_string(), "foo.service".to_string());
+        values.insert("labels.team".to_string(), "db".to_string());
+        assert_eq!(template.render(&values), "[db] foo.service");
+
+        let template =
+            Template::new("[{{tags.site}}] {{unit}}").expect("Failed to parse template.");
+        values.insert("tags.site".to_string(), "ams".to_string());
+        assert_eq!(template.render(&values), "[ams] foo.service");
+    }
+
+    // Template::render()
+    #[test]
+    fn test_template_render_no_variables() {
+        let template = Template::new("Something happened.").expect("Failed to parse template.");
+        assert_eq!(template.render(&HashMap::new()), "Something happened.");
+    }
+
+    // Template::uses()
+    #[test]
+    fn test_template_uses() {
+        let template =
+            Template::new("{{unit}}: {{description}}").expect("Failed to parse template.");
+        assert!(template.uses("description"));
+        assert!(!template.uses("hostname"));
+    }
+
+    // Template::new()
+    #[test]
+    fn test_template_new_unknown_variable() {
+        match Template::new("{{unit}} on {{host}}") {
+            Err(CrateError::Settings(SettingsError::InvalidTemplate(_, _))) => {}
+            _ => panic!("expected InvalidTemplate; a variable has been typo'd"),
+        }
+    }
+
+    // Template::new()
+    #[test]
+    fn test_template_new_unterminated_variable() {
+        match Template::new("{{unit} failed") {
+            Err(CrateError::Settings(SettingsError::InvalidTemplate(_, _))) => {}
+            _ => panic!("expected InvalidTemplate; a variable is unterminated"),
+        }
+    }
+}
@@ -1,102 +1,385 @@
 // Logic for working with timestamps.
 
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
 use crate::bus::UnitProps;
-use crate::error::Error as CrateError;
+use crate::error::{BusError, Error as CrateError, SettingsError, StateError};
 use crate::unit::ActiveState;
 
 // The number of usec since an arbitrary point in the past.
 //
-// For details, research `CLOCK_MONOTONIC`.
-#[derive(Clone, Debug)]
+// For details, research `CLOCK_MONOTONIC`. Serialized as a bare number.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct MonotonicTimestamp(pub u64);
 
 // The number of usec since the epoch.
 //
-// For details, research `CLOCK_REALTIME`.
-#[derive(Debug)]
+// For details, research `CLOCK_REALTIME`. Serialized as a bare number.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct RealtimeTimestamp(pub u64);
 
+// The clocks that a timestamp may be read from.
+//
+// Monotonic timestamps are good for ordering events on a host, and realtime timestamps are good
+// for telling humans and other hosts when an event occurred.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Clock {
+    Monotonic,
+    Realtime,
+}
+
+impl TryFrom<&str> for Clock {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "monotonic" => Ok(Clock::Monotonic),
+            "realtime" => Ok(Clock::Realtime),
+            _ => Err(CrateError::Settings(SettingsError::InvalidClock(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+// Return a monotonic timestamp for the current instant.
+pub fn get_current_monotonic_timestamp() -> MonotonicTimestamp {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid, writable timespec, and CLOCK_MONOTONIC is always available on
+    // Linux.
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    MonotonicTimestamp(now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1000)
+}
+
+impl RealtimeTimestamp {
+    // Format this timestamp as an ISO 8601 date and time in UTC, like `2019-03-14T15:09:26Z`.
+    pub fn to_iso8601(&self) -> String {
+        let secs = self.0 / 1_000_000;
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs_of_day = secs % 86400;
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60
+        )
+    }
+
+    // Parse an ISO 8601 date and time in UTC, in the form produced by `to_iso8601`.
+    pub fn from_iso8601(value: &str) -> Result<Self, CrateError> {
+        let invalid = || CrateError::State(StateError::InvalidTimestamp(value.to_string()));
+        let bytes = value.as_bytes();
+        if bytes.len() != 20
+            || [
+                (4, b'-'),
+                (7, b'-'),
+                (10, b'T'),
+                (13, b':'),
+                (16, b':'),
+                (19, b'Z'),
+            ]
+            .iter()
+            .any(|(i, separator)| bytes[*i] != *separator)
+        {
+            return Err(invalid());
+        }
+        let field = |start: usize, end: usize| -> Result<u32, CrateError> {
+            let digits = &value[start..end];
+            if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse::<u32>().map_err(|_| invalid())
+        };
+        let (year, month, day) = (field(0, 4)?, field(5, 7)?, field(8, 10)?);
+        let (hour, minute, second) = (field(11, 13)?, field(14, 16)?, field(17, 19)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+            return Err(invalid());
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(invalid());
+        }
+        let days = days_from_civil(i64::from(year), month, day);
+        if civil_from_days(days) != (i64::from(year), month, day) {
+            return Err(invalid()); // e.g. February 30th
+        }
+        let secs = days as u64 * 86400 + u64::from(hour * 3600 + minute * 60 + second);
+        Ok(RealtimeTimestamp(secs * 1_000_000))
+    }
+}
+
+// Convert a date in the Gregorian calendar to a number of days since the epoch.
+//
+// See: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Convert a number of days since the epoch to a (year, month, day) tuple in the Gregorian calendar.
+//
+// See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Return a realtime timestamp for the current instant.
+//
+// Useful for events that systemd doesn't timestamp, such as LoadState changes.
+pub fn get_current_realtime_timestamp() -> RealtimeTimestamp {
+    let usec = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0);
+    RealtimeTimestamp(usec)
+}
+
 // Return the monotonic timestamp indicating when the given state was most recently entered.
 pub fn get_monotonic_timestamp(
-    active_state: ActiveState,
+    active_state: &ActiveState,
     unit_props: &UnitProps,
 ) -> Result<MonotonicTimestamp, CrateError> {
     let timestamp_key: &'static str = get_monotonic_timestamp_key(active_state);
     unit_props
         .get(timestamp_key)
-        .ok_or_else(|| CrateError::PropertiesLacksTimestamp(active_state, timestamp_key))?
+        .ok_or_else(|| {
+            CrateError::Bus(BusError::PropertiesLacksTimestamp(
+                active_state.clone(),
+                timestamp_key,
+            ))
+        })?
         .0
         .as_u64()
-        .ok_or_else(|| CrateError::CastOrgFreedesktopSystemd1UnitTimestamp(timestamp_key))
+        .ok_or_else(|| {
+            CrateError::Bus(BusError::CastOrgFreedesktopSystemd1UnitTimestamp(
+                timestamp_key,
+            ))
+        })
         .map(MonotonicTimestamp)
 }
 
 // Return name of the monotonic timestamp indicating when the given state was most recently entered.
-fn get_monotonic_timestamp_key(active_state: ActiveState) -> &'static str {
+//
+// There's no such timestamp for states that killjoy doesn't know of, so the time of the unit's
+// latest state change of any kind is used instead.
+pub fn get_monotonic_timestamp_key(active_state: &ActiveState) -> &'static str {
     match active_state {
         ActiveState::Activating => "InactiveExitTimestampMonotonic",
         ActiveState::Active => "ActiveEnterTimestampMonotonic",
         ActiveState::Deactivating => "ActiveExitTimestampMonotonic",
         ActiveState::Failed => "InactiveEnterTimestampMonotonic",
         ActiveState::Inactive => "InactiveEnterTimestampMonotonic",
+        ActiveState::Other(_) => "StateChangeTimestampMonotonic",
     }
 }
 
 // Return the realtime timestamp indicating when the given state was most recently entered.
 pub fn get_realtime_timestamp(
-    active_state: ActiveState,
+    active_state: &ActiveState,
     unit_props: &UnitProps,
 ) -> Result<RealtimeTimestamp, CrateError> {
     let timestamp_key: &'static str = get_realtime_timestamp_key(active_state);
     unit_props
         .get(timestamp_key)
-        .ok_or_else(|| CrateError::PropertiesLacksTimestamp(active_state, timestamp_key))?
+        .ok_or_else(|| {
+            CrateError::Bus(BusError::PropertiesLacksTimestamp(
+                active_state.clone(),
+                timestamp_key,
+            ))
+        })?
         .0
         .as_u64()
-        .ok_or_else(|| CrateError::CastOrgFreedesktopSystemd1UnitTimestamp(timestamp_key))
+        .ok_or_else(|| {
+            CrateError::Bus(BusError::CastOrgFreedesktopSystemd1UnitTimestamp(
+                timestamp_key,
+            ))
+        })
         .map(RealtimeTimestamp)
 }
 
 // Return name of the realtime timestamp indicating when the given state was most recently entered.
-fn get_realtime_timestamp_key(active_state: ActiveState) -> &'static str {
+//
+// As for `get_monotonic_timestamp_key`, the time of the unit's latest state change is used for
+// unknown states.
+pub fn get_realtime_timestamp_key(active_state: &ActiveState) -> &'static str {
     match active_state {
         ActiveState::Activating => "InactiveExitTimestamp",
         ActiveState::Active => "ActiveEnterTimestamp",
         ActiveState::Deactivating => "ActiveExitTimestamp",
         ActiveState::Failed => "InactiveEnterTimestamp",
         ActiveState::Inactive => "InactiveEnterTimestamp",
+        ActiveState::Other(_) => "StateChangeTimestamp",
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::{prop_assert_eq, proptest};
+
     use super::*;
 
+    // MonotonicTimestamp::cmp(), RealtimeTimestamp::cmp()
+    #[test]
+    fn test_timestamp_cmp() {
+        assert!(MonotonicTimestamp(1) < MonotonicTimestamp(2));
+        assert!(RealtimeTimestamp(2) > RealtimeTimestamp(1));
+        assert_eq!(RealtimeTimestamp(3), RealtimeTimestamp(3));
+    }
+
+    // MonotonicTimestamp::serialize(), RealtimeTimestamp::deserialize()
+    #[test]
+    fn test_timestamp_serde() {
+        assert_eq!(
+            serde_json::to_string(&MonotonicTimestamp(7)).expect("Failed to serialize."),
+            "7"
+        );
+        assert_eq!(
+            serde_json::from_str::<RealtimeTimestamp>("9").expect("Failed to deserialize."),
+            RealtimeTimestamp(9)
+        );
+    }
+
+    // RealtimeTimestamp::to_iso8601()
+    #[test]
+    fn test_realtime_timestamp_to_iso8601() {
+        assert_eq!(RealtimeTimestamp(0).to_iso8601(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            RealtimeTimestamp(1_552_576_166_999_999).to_iso8601(),
+            "2019-03-14T15:09:26Z"
+        );
+        assert_eq!(
+            RealtimeTimestamp(951_782_400_000_000).to_iso8601(),
+            "2000-02-29T00:00:00Z"
+        );
+    }
+
+    // RealtimeTimestamp::from_iso8601()
+    #[test]
+    fn test_realtime_timestamp_from_iso8601() {
+        for usec in [0, 1_552_576_166_000_000, 951_782_400_000_000] {
+            let iso8601 = RealtimeTimestamp(usec).to_iso8601();
+            assert_eq!(
+                RealtimeTimestamp::from_iso8601(&iso8601).ok(),
+                Some(RealtimeTimestamp(usec))
+            );
+        }
+        for iso8601 in [
+            "2019-03-14",
+            "2019-03-14 15:09:26Z",
+            "2019-02-30T00:00:00Z",
+            "2019-03-14T24:00:00Z",
+            "2019-+3-14T15:09:26Z",
+        ] {
+            match RealtimeTimestamp::from_iso8601(iso8601) {
+                Err(CrateError::State(StateError::InvalidTimestamp(_))) => {}
+                _ => panic!("expected InvalidTimestamp; {} is malformed", iso8601),
+            }
+        }
+    }
+
+    // Clock::try_from()
+    #[test]
+    fn test_clock_try_from() {
+        assert_eq!(Clock::try_from("monotonic").ok(), Some(Clock::Monotonic));
+        assert_eq!(Clock::try_from("realtime").ok(), Some(Clock::Realtime));
+        assert!(Clock::try_from("wall").is_err());
+    }
+
+    // get_current_monotonic_timestamp()
+    #[test]
+    fn test_get_current_monotonic_timestamp() {
+        let first = get_current_monotonic_timestamp();
+        let second = get_current_monotonic_timestamp();
+        assert!(first.0 <= second.0);
+    }
+
     // get_monotonic_timestamp_key()
     #[test]
     fn test_get_monotonic_timestamp_key() {
-        for act_st in vec![
+        for act_st in [
             ActiveState::Activating,
             ActiveState::Active,
             ActiveState::Deactivating,
             ActiveState::Failed,
             ActiveState::Inactive,
+            ActiveState::Other("maintenance".to_string()),
         ] {
-            assert!(get_monotonic_timestamp_key(act_st).contains("Monotonic"));
+            assert!(get_monotonic_timestamp_key(&act_st).contains("Monotonic"));
         }
     }
 
     // get_realtime_timestamp_key()
     #[test]
     fn test_get_realtime_timestamp_key() {
-        for act_st in vec![
+        for act_st in [
             ActiveState::Activating,
             ActiveState::Active,
             ActiveState::Deactivating,
             ActiveState::Failed,
             ActiveState::Inactive,
+            ActiveState::Other("maintenance".to_string()),
         ] {
-            assert!(!get_realtime_timestamp_key(act_st).contains("Monotonic"));
+            assert!(!get_realtime_timestamp_key(&act_st).contains("Monotonic"));
+        }
+    }
+
+    proptest! {
+        // days_from_civil(), civil_from_days()
+        #[test]
+        fn test_civil_days_round_trip(days in -1_000_000i64..1_000_000) {
+            let (year, month, day) = civil_from_days(days);
+            prop_assert_eq!(days_from_civil(year, month, day), days);
+        }
+
+        // RealtimeTimestamp::to_iso8601(), RealtimeTimestamp::from_iso8601()
+        //
+        // Only whole seconds survive the round trip, and years must have four digits.
+        #[test]
+        fn test_iso8601_round_trip(secs in 0u64..253_402_300_800) {
+            let timestamp = RealtimeTimestamp(secs * 1_000_000);
+            prop_assert_eq!(
+                RealtimeTimestamp::from_iso8601(&timestamp.to_iso8601()).ok(),
+                Some(timestamp)
+            );
+        }
+
+        // RealtimeTimestamp::from_iso8601(), with arbitrary and nearly valid strings
+        #[test]
+        fn test_from_iso8601_any(
+            value in "\\PC*|[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}Z"
+        ) {
+            if let Ok(timestamp) = RealtimeTimestamp::from_iso8601(&value) {
+                prop_assert_eq!(timestamp.to_iso8601(), value);
+            }
         }
     }
 }
@@ -2,9 +2,12 @@
 
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
 
-use crate::error::Error as CrateError;
-use crate::timestamp::MonotonicTimestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error as CrateError, SettingsError};
+use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
 
 // The possible values for a unit's `ActiveState` attribute.
 //
@@ -16,15 +19,26 @@ use crate::timestamp::MonotonicTimestamp;
 // *   Search for "ActiveState" in [The D-Bus API of systemd/PID
 //     1](https://www.freedesktop.org/wiki/Software/systemd/dbus/)
 // *   Read the "CONCEPTS" section in systemd(1).
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+//
+// Newer versions of systemd may add states, e.g. "maintenance" or "refreshing". They're kept as
+// `Other`, so that units in those states are still tracked, and rules may still match them.
+//
+// States are serialized in the same way as systemd writes them, e.g. "failed".
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum ActiveState {
     Activating,
     Active,
     Deactivating,
     Failed,
     Inactive,
+    Other(String),
 }
 
+// The states that killjoy knows of, i.e. all but `ActiveState::Other`.
+pub const KNOWN_ACTIVE_STATES: &[&str] =
+    &["activating", "active", "deactivating", "failed", "inactive"];
+
 impl Display for ActiveState {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         let msg = match self {
@@ -33,12 +47,15 @@ impl Display for ActiveState {
             ActiveState::Deactivating => "deactivating",
             ActiveState::Failed => "failed",
             ActiveState::Inactive => "inactive",
+            ActiveState::Other(state) => state,
         };
         write!(f, "{}", msg)
     }
 }
 
-// Useful when reading from a configuration file.
+// Useful when reading from a bus or configuration file.
+//
+// Unknown states are accepted if they look like systemd's, i.e. lowercase words joined by hyphens.
 impl TryFrom<&str> for ActiveState {
     type Error = CrateError;
 
@@ -49,11 +66,30 @@ impl TryFrom<&str> for ActiveState {
             "deactivating" => Ok(ActiveState::Deactivating),
             "failed" => Ok(ActiveState::Failed),
             "inactive" => Ok(ActiveState::Inactive),
-            _ => Err(CrateError::InvalidActiveState(value.to_string())),
+            _ if is_valid_state_name(value) => Ok(ActiveState::Other(value.to_string())),
+            _ => Err(CrateError::Settings(SettingsError::InvalidActiveState(
+                value.to_string(),
+            ))),
         }
     }
 }
 
+impl TryFrom<String> for ActiveState {
+    type Error = CrateError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(&value[..])
+    }
+}
+
+impl FromStr for ActiveState {
+    type Err = CrateError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
 // Useful when writing to a bus or configuration file.
 impl From<ActiveState> for String {
     fn from(value: ActiveState) -> String {
@@ -63,21 +99,109 @@ impl From<ActiveState> for String {
             ActiveState::Deactivating => "deactivating".to_string(),
             ActiveState::Failed => "failed".to_string(),
             ActiveState::Inactive => "inactive".to_string(),
+            ActiveState::Other(state) => state,
         }
     }
 }
 
+// Check whether the given string looks like a systemd state, e.g. "failed" or "bad-setting".
+pub fn is_valid_state_name(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .split('-')
+            .all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+// The possible values for a unit's `LoadState` attribute.
+//
+// A unit's LoadState describes whether systemd managed to load the unit's configuration. A unit
+// that fails to load (because its unit file is missing, malformed, or so on) never reaches the
+// "failed" ActiveState, so it must be watched separately.
+//
+// For conceptual information on the LoadState property, search for "LoadState" in [The D-Bus API
+// of systemd/PID 1](https://www.freedesktop.org/wiki/Software/systemd/dbus/).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LoadState {
+    BadSetting,
+    Error,
+    Loaded,
+    Masked,
+    Merged,
+    NotFound,
+    Stub,
+}
+
+impl Display for LoadState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+// Useful when reading from a configuration file.
+impl TryFrom<&str> for LoadState {
+    type Error = CrateError;
+
+    // `Self::Error` would be ambiguous with the `LoadState::Error` variant.
+    fn try_from(value: &str) -> Result<Self, CrateError> {
+        match value {
+            "bad-setting" => Ok(LoadState::BadSetting),
+            "error" => Ok(LoadState::Error),
+            "loaded" => Ok(LoadState::Loaded),
+            "masked" => Ok(LoadState::Masked),
+            "merged" => Ok(LoadState::Merged),
+            "not-found" => Ok(LoadState::NotFound),
+            "stub" => Ok(LoadState::Stub),
+            _ => Err(CrateError::Settings(SettingsError::InvalidLoadState(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+// Useful when writing to a bus or configuration file.
+impl From<LoadState> for String {
+    fn from(value: LoadState) -> String {
+        match value {
+            LoadState::BadSetting => "bad-setting".to_string(),
+            LoadState::Error => "error".to_string(),
+            LoadState::Loaded => "loaded".to_string(),
+            LoadState::Masked => "masked".to_string(),
+            LoadState::Merged => "merged".to_string(),
+            LoadState::NotFound => "not-found".to_string(),
+            LoadState::Stub => "stub".to_string(),
+        }
+    }
+}
+
+// A unit's SubState, e.g. "running" or "exited", and when the unit's state last changed in any way.
+//
+// A unit's SubState may change while its ActiveState doesn't, e.g. when a service's main process
+// exits but the service remains active. Systemd doesn't timestamp SubState transitions as such, so
+// the time of the unit's latest state change is used instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubState {
+    pub name: String,
+    pub mono_ts: MonotonicTimestamp,
+    pub real_ts: RealtimeTimestamp,
+}
+
 #[derive(Debug)]
 pub struct UnitStateMachine {
     active_state: ActiveState,
     mono_ts: MonotonicTimestamp,
+    real_ts: RealtimeTimestamp,
+    sub_state: Option<SubState>,
 }
 
 impl UnitStateMachine {
     // Initialize the state machine's attributes and call `on_change()`.
+    //
+    // The unit's SubState is only tracked if `sub_state` is given.
     pub fn new<T>(
         active_state: ActiveState,
+        sub_state: Option<SubState>,
         mono_ts: MonotonicTimestamp,
+        real_ts: RealtimeTimestamp,
         on_change: &T,
     ) -> Result<Self, CrateError>
     where
@@ -86,56 +210,576 @@ impl UnitStateMachine {
         let usm = UnitStateMachine {
             active_state,
             mono_ts,
+            real_ts,
+            sub_state,
         };
         on_change(&usm, None)?;
         Ok(usm)
     }
 
-    // Optionally update the state machine's attributes and call `on_change()`.
+    // Optionally update the state machine's attributes and call `on_change()` or `on_sub_change()`.
     //
     // If the given `mono_ts` is newer than the one currently in the state machine, then update
     // the state machine's attributes. If the `active_state` change, call `on_change()`.
-    pub fn update<T>(
+    //
+    // Otherwise, if the given `sub_state` is newer than the one currently in the state machine,
+    // then update it. If its name changes, call `on_sub_change()` with the old name. A `sub_state`
+    // is ignored unless one was given when the state machine was created.
+    //
+    // Only the monotonic clock is used for ordering, as the realtime clock may jump. The realtime
+    // timestamp is tracked for the benefit of notifiers.
+    pub fn update<T, U>(
         &mut self,
         active_state: ActiveState,
+        sub_state: Option<SubState>,
         mono_ts: MonotonicTimestamp,
+        real_ts: RealtimeTimestamp,
         on_change: &T,
+        on_sub_change: &U,
     ) -> Result<(), CrateError>
     where
         T: Fn(&UnitStateMachine, Option<ActiveState>) -> Result<(), CrateError>,
+        U: Fn(&UnitStateMachine, String) -> Result<(), CrateError>,
     {
+        let sub_state = sub_state.filter(|_| self.sub_state.is_some());
         if self.mono_ts.0 < mono_ts.0 {
             self.mono_ts = mono_ts;
+            self.real_ts = real_ts;
             if self.active_state != active_state {
-                let old_state = self.active_state;
-                self.active_state = active_state;
-                on_change(&self, Some(old_state))?;
+                if sub_state.is_some() {
+                    self.sub_state = sub_state;
+                }
+                let old_state = std::mem::replace(&mut self.active_state, active_state);
+                return on_change(self, Some(old_state));
             }
         }
+        let old_sub_state = match (&mut self.sub_state, sub_state) {
+            (Some(old), Some(new)) if old.mono_ts.0 < new.mono_ts.0 => std::mem::replace(old, new),
+            _ => return Ok(()),
+        };
+        match &self.sub_state {
+            Some(sub_state) if sub_state.name != old_sub_state.name => {
+                on_sub_change(self, old_sub_state.name)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn active_state(&self) -> &ActiveState {
+        &self.active_state
+    }
+
+    // Get the unit's SubState, if it's tracked.
+    pub fn sub_state(&self) -> Option<&SubState> {
+        self.sub_state.as_ref()
+    }
+
+    // Get the monotonic timestamp at which the unit entered its current state.
+    pub fn mono_ts(&self) -> &MonotonicTimestamp {
+        &self.mono_ts
+    }
+
+    // Get the realtime timestamp at which the unit entered its current state.
+    pub fn real_ts(&self) -> &RealtimeTimestamp {
+        &self.real_ts
+    }
+}
+
+// Like a `UnitStateMachine`, but tracks a unit's LoadState instead of its ActiveState.
+//
+// Systemd doesn't timestamp LoadState transitions, so updates are applied in the order they are
+// received.
+#[derive(Debug)]
+pub struct LoadStateMachine {
+    load_state: LoadState,
+}
+
+impl LoadStateMachine {
+    // Initialize the state machine's attributes and call `on_change()`.
+    pub fn new<T>(load_state: LoadState, on_change: &T) -> Result<Self, CrateError>
+    where
+        T: Fn(&LoadStateMachine, Option<LoadState>) -> Result<(), CrateError>,
+    {
+        let lsm = LoadStateMachine { load_state };
+        on_change(&lsm, None)?;
+        Ok(lsm)
+    }
+
+    // Update the state machine's attributes, and call `on_change()` if the `load_state` changes.
+    pub fn update<T>(&mut self, load_state: LoadState, on_change: &T) -> Result<(), CrateError>
+    where
+        T: Fn(&LoadStateMachine, Option<LoadState>) -> Result<(), CrateError>,
+    {
+        if self.load_state != load_state {
+            let old_state = self.load_state;
+            self.load_state = load_state;
+            on_change(self, Some(old_state))?;
+        }
         Ok(())
     }
 
-    pub fn active_state(&self) -> ActiveState {
-        self.active_state
+    pub fn load_state(&self) -> LoadState {
+        self.load_state
+    }
+}
+
+// The possible states of a unit's file, as reported by `org.freedesktop.systemd1.Manager`.
+//
+// Unit files are listed whether or not their units are loaded, so a unit file's state reveals
+// problems with units which never generate events, e.g. because they have been disabled or masked.
+// `Removed` isn't reported by systemd: it means that a unit file was expected but not listed.
+// `Attached` and `AttachedRuntime` unit files belong to portable service images.
+//
+// For conceptual information on unit file states, search for "UnitFileState" in [The D-Bus API of
+// systemd/PID 1](https://www.freedesktop.org/wiki/Software/systemd/dbus/).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum UnitFileState {
+    Alias,
+    Attached,
+    AttachedRuntime,
+    Bad,
+    Disabled,
+    Enabled,
+    EnabledRuntime,
+    Generated,
+    Indirect,
+    Linked,
+    LinkedRuntime,
+    Masked,
+    MaskedRuntime,
+    Removed,
+    Static,
+    Transient,
+}
+
+impl Display for UnitFileState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+// Useful when reading from a configuration file.
+impl TryFrom<&str> for UnitFileState {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "alias" => Ok(UnitFileState::Alias),
+            "attached" => Ok(UnitFileState::Attached),
+            "attached-runtime" => Ok(UnitFileState::AttachedRuntime),
+            "bad" => Ok(UnitFileState::Bad),
+            "disabled" => Ok(UnitFileState::Disabled),
+            "enabled" => Ok(UnitFileState::Enabled),
+            "enabled-runtime" => Ok(UnitFileState::EnabledRuntime),
+            "generated" => Ok(UnitFileState::Generated),
+            "indirect" => Ok(UnitFileState::Indirect),
+            "linked" => Ok(UnitFileState::Linked),
+            "linked-runtime" => Ok(UnitFileState::LinkedRuntime),
+            "masked" => Ok(UnitFileState::Masked),
+            "masked-runtime" => Ok(UnitFileState::MaskedRuntime),
+            "removed" => Ok(UnitFileState::Removed),
+            "static" => Ok(UnitFileState::Static),
+            "transient" => Ok(UnitFileState::Transient),
+            _ => Err(CrateError::Settings(SettingsError::InvalidUnitFileState(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+// Useful when writing to a bus or configuration file.
+impl From<UnitFileState> for String {
+    fn from(value: UnitFileState) -> String {
+        match value {
+            UnitFileState::Alias => "alias".to_string(),
+            UnitFileState::Attached => "attached".to_string(),
+            UnitFileState::AttachedRuntime => "attached-runtime".to_string(),
+            UnitFileState::Bad => "bad".to_string(),
+            UnitFileState::Disabled => "disabled".to_string(),
+            UnitFileState::Enabled => "enabled".to_string(),
+            UnitFileState::EnabledRuntime => "enabled-runtime".to_string(),
+            UnitFileState::Generated => "generated".to_string(),
+            UnitFileState::Indirect => "indirect".to_string(),
+            UnitFileState::Linked => "linked".to_string(),
+            UnitFileState::LinkedRuntime => "linked-runtime".to_string(),
+            UnitFileState::Masked => "masked".to_string(),
+            UnitFileState::MaskedRuntime => "masked-runtime".to_string(),
+            UnitFileState::Removed => "removed".to_string(),
+            UnitFileState::Static => "static".to_string(),
+            UnitFileState::Transient => "transient".to_string(),
+        }
+    }
+}
+
+// The possible values for the manager's `SystemState` attribute, i.e. the state of the system as a
+// whole, as reported by `systemctl is-system-running`.
+//
+// For conceptual information, search for "SystemState" in [The D-Bus API of systemd/PID
+// 1](https://www.freedesktop.org/wiki/Software/systemd/dbus/), or read the description of
+// `is-system-running` in systemctl(1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SystemState {
+    Degraded,
+    Initializing,
+    Maintenance,
+    Offline,
+    Running,
+    Starting,
+    Stopping,
+    Unknown,
+}
+
+impl Display for SystemState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+// Useful when reading from a bus or configuration file.
+impl TryFrom<&str> for SystemState {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "degraded" => Ok(SystemState::Degraded),
+            "initializing" => Ok(SystemState::Initializing),
+            "maintenance" => Ok(SystemState::Maintenance),
+            "offline" => Ok(SystemState::Offline),
+            "running" => Ok(SystemState::Running),
+            "starting" => Ok(SystemState::Starting),
+            "stopping" => Ok(SystemState::Stopping),
+            "unknown" => Ok(SystemState::Unknown),
+            _ => Err(CrateError::Settings(SettingsError::InvalidSystemState(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+// Useful when writing to a bus or configuration file.
+impl From<SystemState> for String {
+    fn from(value: SystemState) -> String {
+        match value {
+            SystemState::Degraded => "degraded".to_string(),
+            SystemState::Initializing => "initializing".to_string(),
+            SystemState::Maintenance => "maintenance".to_string(),
+            SystemState::Offline => "offline".to_string(),
+            SystemState::Running => "running".to_string(),
+            SystemState::Starting => "starting".to_string(),
+            SystemState::Stopping => "stopping".to_string(),
+            SystemState::Unknown => "unknown".to_string(),
+        }
+    }
+}
+
+// The possible states of a portable service image, as reported by
+// `org.freedesktop.portable1.Manager`.
+//
+// An image is `Detached` if its unit files aren't installed, and otherwise `Attached`, `Enabled` or
+// `Running`, depending on the state of its units. The `Runtime` variants are attached until the
+// next reboot only. Images which are no longer listed are considered `Detached`. See portablectl(1).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PortableState {
+    Attached,
+    AttachedRuntime,
+    Detached,
+    Enabled,
+    EnabledRuntime,
+    Running,
+    RunningRuntime,
+}
+
+impl Display for PortableState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", String::from(*self))
+    }
+}
+
+// Useful when reading from a bus or configuration file.
+impl TryFrom<&str> for PortableState {
+    type Error = CrateError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "attached" => Ok(PortableState::Attached),
+            "attached-runtime" => Ok(PortableState::AttachedRuntime),
+            "detached" => Ok(PortableState::Detached),
+            "enabled" => Ok(PortableState::Enabled),
+            "enabled-runtime" => Ok(PortableState::EnabledRuntime),
+            "running" => Ok(PortableState::Running),
+            "running-runtime" => Ok(PortableState::RunningRuntime),
+            _ => Err(CrateError::Settings(SettingsError::InvalidPortableState(
+                value.to_string(),
+            ))),
+        }
+    }
+}
+
+// Useful when writing to a bus or configuration file.
+impl From<PortableState> for String {
+    fn from(value: PortableState) -> String {
+        match value {
+            PortableState::Attached => "attached".to_string(),
+            PortableState::AttachedRuntime => "attached-runtime".to_string(),
+            PortableState::Detached => "detached".to_string(),
+            PortableState::Enabled => "enabled".to_string(),
+            PortableState::EnabledRuntime => "enabled-runtime".to_string(),
+            PortableState::Running => "running".to_string(),
+            PortableState::RunningRuntime => "running-runtime".to_string(),
+        }
+    }
+}
+
+// Split the name of a template unit's instance into the template's name and the instance string.
+//
+// For example, "getty@tty1.service" is split into "getty@.service" and "tty1". Return `None` if
+// the unit isn't an instance of a template, which includes template units themselves, such as
+// "getty@.service". See systemd.unit(5).
+pub fn split_instance_name(unit_name: &str) -> Option<(String, String)> {
+    let (prefix, rest) = unit_name.split_once('@')?;
+    let dot = rest.rfind('.')?;
+    let (instance, suffix) = rest.split_at(dot);
+    if prefix.is_empty() || instance.is_empty() {
+        return None;
+    }
+    Some((format!("{}@{}", prefix, suffix), instance.to_string()))
+}
+
+// The object path under which systemd serves units, each at a path named after the unit.
+const PATH_FOR_UNITS: &str = "/org/freedesktop/systemd1/unit/";
+
+// Get the object path at which systemd serves the given unit.
+//
+// For example, "foo.service" is served at "/org/freedesktop/systemd1/unit/foo_2eservice". Like
+// systemd, keep ASCII letters and digits, replace every other byte with "_" and two lowercase hex
+// digits, and escape an empty name as "_".
+pub fn get_unit_path(unit_name: &str) -> String {
+    let mut unit_path = String::with_capacity(PATH_FOR_UNITS.len() + unit_name.len() * 3);
+    unit_path.push_str(PATH_FOR_UNITS);
+    if unit_name.is_empty() {
+        unit_path.push('_');
+    }
+    for byte in unit_name.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            unit_path.push(char::from(byte));
+        } else {
+            unit_path.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    unit_path
+}
+
+// Get the name of the unit which systemd serves at the given object path.
+//
+// This is the inverse of `get_unit_path`. Return `None` if the path doesn't name a unit, if it's
+// escaped incorrectly, or if the unit name it encodes isn't UTF-8.
+pub fn get_unit_name(unit_path: &str) -> Option<String> {
+    let label = unit_path.strip_prefix(PATH_FOR_UNITS)?.as_bytes();
+    if label == b"_" {
+        return Some(String::new());
+    }
+    let mut unit_name: Vec<u8> = Vec::with_capacity(label.len());
+    let mut bytes = label.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte.is_ascii_alphanumeric() {
+            unit_name.push(byte);
+            continue;
+        }
+        if byte != b'_' {
+            return None;
+        }
+        let high = char::from(*bytes.next()?).to_digit(16)?;
+        let low = char::from(*bytes.next()?).to_digit(16)?;
+        unit_name.push((high * 16 + low) as u8);
     }
+    if unit_name.is_empty() {
+        return None;
+    }
+    String::from_utf8(unit_name).ok()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+
+    use proptest::{prop_assert_eq, proptest};
+
     use super::*;
 
     fn null_on_change(_: &UnitStateMachine, _: Option<ActiveState>) -> Result<(), CrateError> {
         Ok(())
     }
 
+    fn null_on_sub_change(_: &UnitStateMachine, _: String) -> Result<(), CrateError> {
+        Ok(())
+    }
+
+    // UnitFileState::try_from()
+    #[test]
+    fn test_unit_file_state_try_from() {
+        for state_str in &["enabled", "masked-runtime", "removed"] {
+            let state = UnitFileState::try_from(*state_str).expect("Failed to parse state.");
+            assert_eq!(String::from(state), *state_str);
+        }
+        match UnitFileState::try_from("maskd") {
+            Err(CrateError::Settings(SettingsError::InvalidUnitFileState(_))) => {}
+            _ => panic!("expected InvalidUnitFileState; a unit file state has been typo'd"),
+        }
+    }
+
+    // SystemState::try_from()
+    #[test]
+    fn test_system_state_try_from() {
+        for state_str in &["degraded", "running", "maintenance"] {
+            let state = SystemState::try_from(*state_str).expect("Failed to parse state.");
+            assert_eq!(String::from(state), *state_str);
+        }
+        match SystemState::try_from("degradd") {
+            Err(CrateError::Settings(SettingsError::InvalidSystemState(_))) => {}
+            _ => panic!("expected InvalidSystemState; a system state has been typo'd"),
+        }
+    }
+
+    // PortableState::try_from()
+    #[test]
+    fn test_portable_state_try_from() {
+        for state_str in &["attached", "detached", "running-runtime"] {
+            let state = PortableState::try_from(*state_str).expect("Failed to parse state.");
+            assert_eq!(String::from(state), *state_str);
+        }
+        match PortableState::try_from("atached") {
+            Err(CrateError::Settings(SettingsError::InvalidPortableState(_))) => {}
+            _ => panic!("expected InvalidPortableState; a portable image state has been typo'd"),
+        }
+    }
+
+    // split_instance_name()
+    #[test]
+    fn test_split_instance_name() {
+        assert_eq!(
+            split_instance_name("getty@tty1.service"),
+            Some(("getty@.service".to_string(), "tty1".to_string()))
+        );
+        assert_eq!(
+            split_instance_name("systemd-fsck@dev-disk-by\\x2dlabel-a.b.service"),
+            Some((
+                "systemd-fsck@.service".to_string(),
+                "dev-disk-by\\x2dlabel-a.b".to_string()
+            ))
+        );
+        assert_eq!(split_instance_name("getty@.service"), None);
+        assert_eq!(split_instance_name("@tty1.service"), None);
+        assert_eq!(split_instance_name("foo.service"), None);
+    }
+
+    // ActiveState::from_str()
+    #[test]
+    fn test_active_state_from_str() {
+        assert_eq!(
+            "failed".parse::<ActiveState>().ok(),
+            Some(ActiveState::Failed)
+        );
+        assert_eq!(
+            "maintenance".parse::<ActiveState>().ok(),
+            Some(ActiveState::Other("maintenance".to_string()))
+        );
+        match "Broken!".parse::<ActiveState>() {
+            Err(CrateError::Settings(SettingsError::InvalidActiveState(_))) => {}
+            _ => panic!("expected InvalidActiveState; the state isn't a systemd state name"),
+        }
+    }
+
+    // ActiveState::serialize(), ActiveState::deserialize()
+    #[test]
+    fn test_active_state_serde() {
+        for active_state in [
+            ActiveState::Activating,
+            ActiveState::Active,
+            ActiveState::Deactivating,
+            ActiveState::Failed,
+            ActiveState::Inactive,
+            ActiveState::Other("maintenance".to_string()),
+        ] {
+            let serialized = serde_json::to_string(&active_state).expect("Failed to serialize.");
+            assert_eq!(serialized, format!("\"{}\"", active_state));
+            let deserialized: ActiveState =
+                serde_json::from_str(&serialized).expect("Failed to deserialize.");
+            assert_eq!(deserialized, active_state);
+        }
+    }
+
+    proptest! {
+        // ActiveState::try_from(), String::from(ActiveState)
+        #[test]
+        fn test_active_state_round_trip(state in "[a-z]{1,12}(-[a-z]{1,12}){0,3}") {
+            let active_state = ActiveState::try_from(&state[..]);
+            prop_assert_eq!(active_state.map(String::from).ok(), Some(state));
+        }
+
+        // ActiveState::try_from(), String::from(ActiveState), with strings from a hostile bus
+        #[test]
+        fn test_active_state_from_any(state in "\\PC*") {
+            if let Ok(active_state) = ActiveState::try_from(&state[..]) {
+                prop_assert_eq!(String::from(active_state), state);
+            }
+        }
+
+        // get_unit_path(), get_unit_name()
+        #[test]
+        fn test_unit_path_round_trip(unit_name in "\\PC*") {
+            prop_assert_eq!(get_unit_name(&get_unit_path(&unit_name)), Some(unit_name));
+        }
+    }
+
+    // get_unit_path()
+    #[test]
+    fn test_get_unit_path() {
+        assert_eq!(
+            get_unit_path("foo.service"),
+            "/org/freedesktop/systemd1/unit/foo_2eservice"
+        );
+        assert_eq!(
+            get_unit_path("getty@tty1.service"),
+            "/org/freedesktop/systemd1/unit/getty_40tty1_2eservice"
+        );
+        assert_eq!(
+            get_unit_path("-.mount"),
+            "/org/freedesktop/systemd1/unit/_2d_2emount"
+        );
+        assert_eq!(get_unit_path(""), "/org/freedesktop/systemd1/unit/_");
+    }
+
+    // get_unit_name()
+    #[test]
+    fn test_get_unit_name() {
+        let unit_name =
+            |path: &str| get_unit_name(&format!("/org/freedesktop/systemd1/unit/{}", path));
+        assert_eq!(unit_name("foo_2eservice").as_deref(), Some("foo.service"));
+        assert_eq!(unit_name("foo_2Eservice").as_deref(), Some("foo.service"));
+        assert_eq!(unit_name("_").as_deref(), Some(""));
+        for path in ["", "foo_2", "foo_zzservice", "foo.service", "_ff"] {
+            assert_eq!(unit_name(path), None, "{}", path);
+        }
+        assert_eq!(
+            get_unit_name("/org/freedesktop/systemd1/foo_2eservice"),
+            None
+        );
+    }
+
     // Pass a unit state and a timestamp.
     #[test]
     fn test_usm_new() {
-        let usm =
-            UnitStateMachine::new(ActiveState::Failed, MonotonicTimestamp(10), &null_on_change)
-                .expect("Failed to create UnitStateMachine.");
+        let usm = UnitStateMachine::new(
+            ActiveState::Failed,
+            None,
+            MonotonicTimestamp(10),
+            RealtimeTimestamp(1000),
+            &null_on_change,
+        )
+        .expect("Failed to create UnitStateMachine.");
         assert_eq!(usm.active_state, ActiveState::Failed);
         assert_eq!(usm.mono_ts.0, 10);
+        assert_eq!(usm.real_ts.0, 1000);
     }
 
     // Unsuccessfully update the state machine.
@@ -143,24 +787,37 @@ mod tests {
     fn test_usm_update_v1() {
         let mut usm = UnitStateMachine::new(
             ActiveState::Inactive,
+            None,
             MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
             &null_on_change,
         )
         .expect("Failed to create UnitStateMachine.");
 
         usm.update(
             ActiveState::Activating,
+            None,
             MonotonicTimestamp(24),
+            RealtimeTimestamp(2400),
             &null_on_change,
+            &null_on_sub_change,
         )
         .expect("Failed to update UnitStateMachine.");
         assert_eq!(usm.active_state, ActiveState::Inactive);
         assert_eq!(usm.mono_ts.0, 25);
 
-        usm.update(ActiveState::Active, MonotonicTimestamp(25), &null_on_change)
-            .expect("Failed to update UnitStateMachine.");
+        usm.update(
+            ActiveState::Active,
+            None,
+            MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
+            &null_on_change,
+            &null_on_sub_change,
+        )
+        .expect("Failed to update UnitStateMachine.");
         assert_eq!(usm.active_state, ActiveState::Inactive);
         assert_eq!(usm.mono_ts.0, 25);
+        assert_eq!(usm.real_ts.0, 2500);
     }
 
     // Successfully update the state machine.
@@ -168,24 +825,102 @@ mod tests {
     fn test_usm_update_v2() {
         let mut usm = UnitStateMachine::new(
             ActiveState::Inactive,
+            None,
             MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
             &null_on_change,
         )
         .expect("Failed to create UnitStateMachine.");
 
         usm.update(
             ActiveState::Activating,
+            None,
             MonotonicTimestamp(26),
+            RealtimeTimestamp(2600),
             &null_on_change,
+            &null_on_sub_change,
         )
         .expect("Failed to update UnitStateMachine.");
         assert_eq!(usm.active_state, ActiveState::Activating);
         assert_eq!(usm.mono_ts.0, 26);
 
-        usm.update(ActiveState::Active, MonotonicTimestamp(27), &null_on_change)
-            .expect("Failed to update UnitStateMachine.");
+        usm.update(
+            ActiveState::Active,
+            None,
+            MonotonicTimestamp(27),
+            RealtimeTimestamp(2700),
+            &null_on_change,
+            &null_on_sub_change,
+        )
+        .expect("Failed to update UnitStateMachine.");
         assert_eq!(usm.active_state, ActiveState::Active);
         assert_eq!(usm.mono_ts.0, 27);
+        assert_eq!(usm.real_ts.0, 2700);
+    }
+
+    // Track a unit's SubState along with its ActiveState.
+    #[test]
+    fn test_usm_update_sub_state() {
+        let sub_state = |name: &str, ts: u64| {
+            Some(SubState {
+                name: name.to_string(),
+                mono_ts: MonotonicTimestamp(ts),
+                real_ts: RealtimeTimestamp(ts * 100),
+            })
+        };
+        let changes: RefCell<Vec<String>> = RefCell::new(Vec::new());
+        let on_sub_change = |usm: &UnitStateMachine, old: String| -> Result<(), CrateError> {
+            let new = &usm.sub_state().expect("SubState isn't tracked.").name;
+            changes.borrow_mut().push(format!("{}->{}", old, new));
+            Ok(())
+        };
+        let mut usm = UnitStateMachine::new(
+            ActiveState::Active,
+            sub_state("running", 25),
+            MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
+            &null_on_change,
+        )
+        .expect("Failed to create UnitStateMachine.");
+
+        // A stale SubState is ignored.
+        usm.update(
+            ActiveState::Active,
+            sub_state("exited", 24),
+            MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
+            &null_on_change,
+            &on_sub_change,
+        )
+        .expect("Failed to update UnitStateMachine.");
+        assert_eq!(usm.sub_state().unwrap().name, "running");
+
+        // A SubState change within the same ActiveState is reported.
+        usm.update(
+            ActiveState::Active,
+            sub_state("exited", 26),
+            MonotonicTimestamp(25),
+            RealtimeTimestamp(2500),
+            &null_on_change,
+            &on_sub_change,
+        )
+        .expect("Failed to update UnitStateMachine.");
+        assert_eq!(usm.sub_state().unwrap().name, "exited");
+        assert_eq!(usm.mono_ts.0, 25);
+        assert_eq!(*changes.borrow(), vec!["running->exited".to_string()]);
+
+        // A SubState change along with an ActiveState change is reported as the latter.
+        usm.update(
+            ActiveState::Inactive,
+            sub_state("dead", 27),
+            MonotonicTimestamp(27),
+            RealtimeTimestamp(2700),
+            &null_on_change,
+            &on_sub_change,
+        )
+        .expect("Failed to update UnitStateMachine.");
+        assert_eq!(usm.sub_state().unwrap().name, "dead");
+        assert_eq!(changes.borrow().len(), 1);
     }
 
     // Convert "activating" to an ActiveState.
@@ -193,7 +928,7 @@ mod tests {
     fn test_active_state_from_activating() {
         let active_state_str = "activating";
         let active_state = ActiveState::try_from(active_state_str)
-            .expect(&format!("Failed to create ActiveState from {}", active_state_str)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create ActiveState from {}", active_state_str));
         assert_eq!(active_state, ActiveState::Activating);
     }
 
@@ -202,7 +937,7 @@ mod tests {
     fn test_active_state_from_active() {
         let active_state_str = "active";
         let active_state = ActiveState::try_from(active_state_str)
-            .expect(&format!("Failed to create ActiveState from {}", active_state_str)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create ActiveState from {}", active_state_str));
         assert_eq!(active_state, ActiveState::Active);
     }
 
@@ -211,7 +946,7 @@ mod tests {
     fn test_active_state_from_deactivating() {
         let active_state_str = "deactivating";
         let active_state = ActiveState::try_from(active_state_str)
-            .expect(&format!("Failed to create ActiveState from {}", active_state_str)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create ActiveState from {}", active_state_str));
         assert_eq!(active_state, ActiveState::Deactivating);
     }
 
@@ -220,7 +955,7 @@ mod tests {
     fn test_active_state_from_failed() {
         let active_state_str = "failed";
         let active_state = ActiveState::try_from(active_state_str)
-            .expect(&format!("Failed to create ActiveState from {}", active_state_str)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create ActiveState from {}", active_state_str));
         assert_eq!(active_state, ActiveState::Failed);
     }
 
@@ -229,14 +964,25 @@ mod tests {
     fn test_active_state_from_inactive() {
         let active_state_str = "inactive";
         let active_state = ActiveState::try_from(active_state_str)
-            .expect(&format!("Failed to create ActiveState from {}", active_state_str)[..]);
+            .unwrap_or_else(|_| panic!("Failed to create ActiveState from {}", active_state_str));
         assert_eq!(active_state, ActiveState::Inactive);
     }
 
+    // Convert an unknown state to an ActiveState.
+    #[test]
+    fn test_active_state_from_unknown() {
+        assert_eq!(
+            ActiveState::try_from("re-freshing").unwrap(),
+            ActiveState::Other("re-freshing".to_string())
+        );
+    }
+
     // Convert some other string to an ActiveState. (It should fail.)
     #[test]
     fn test_active_state_from_other() {
-        ActiveState::try_from("foo").expect_err("Conversion should have failed.");
+        for other in ["", "foo bar", "Failed", "-foo", "foo--bar"] {
+            ActiveState::try_from(other).expect_err("Conversion should have failed.");
+        }
     }
 
     #[test]
@@ -250,4 +996,53 @@ mod tests {
     fn test_string_from_active_state() {
         assert_eq!(String::from(ActiveState::Deactivating), "deactivating");
     }
+
+    // Round-trip every LoadState through a string.
+    #[test]
+    fn test_load_state_round_trip() {
+        for load_state in [
+            LoadState::BadSetting,
+            LoadState::Error,
+            LoadState::Loaded,
+            LoadState::Masked,
+            LoadState::Merged,
+            LoadState::NotFound,
+            LoadState::Stub,
+        ] {
+            let load_state_str = String::from(load_state);
+            assert_eq!(
+                LoadState::try_from(&load_state_str[..]).ok(),
+                Some(load_state)
+            );
+            assert_eq!(format!("{}", load_state), load_state_str);
+        }
+    }
+
+    // Convert some other string to a LoadState. (It should fail.)
+    #[test]
+    fn test_load_state_from_other() {
+        LoadState::try_from("not found").expect_err("Conversion should have failed.");
+    }
+
+    // Update the load state machine, and check that on_change is only called upon a change.
+    #[test]
+    fn test_lsm_update() {
+        let calls = std::cell::Cell::new(0);
+        let on_change = |_: &LoadStateMachine, _: Option<LoadState>| -> Result<(), CrateError> {
+            calls.set(calls.get() + 1);
+            Ok(())
+        };
+        let mut lsm = LoadStateMachine::new(LoadState::Loaded, &on_change)
+            .expect("Failed to create LoadStateMachine.");
+        assert_eq!(calls.get(), 1);
+
+        lsm.update(LoadState::Loaded, &on_change)
+            .expect("Failed to update LoadStateMachine.");
+        assert_eq!(calls.get(), 1);
+
+        lsm.update(LoadState::NotFound, &on_change)
+            .expect("Failed to update LoadStateMachine.");
+        assert_eq!(calls.get(), 2);
+        assert_eq!(lsm.load_state(), LoadState::NotFound);
+    }
 }
@@ -0,0 +1,596 @@
+// Logic for talking to systemd over Varlink, rather than D-Bus.
+//
+// Newer versions of systemd serve Varlink interfaces on Unix sockets, which work without a D-Bus
+// daemon, e.g. during early boot, or in minimal containers. Varlink messages are JSON objects, each
+// terminated by a NUL byte. See: https://varlink.org/
+//
+// Bus watchers speak D-Bus, so `VarlinkSystemd` translates. Units are listed with
+// `io.systemd.Unit.List`, and the manager calls which bus watchers make are answered from that
+// list. systemd sends no signals over Varlink, so units are listed again every
+// `VARLINK_POLL_INTERVAL`, and the signals which systemd would have emitted over D-Bus are made up
+// from the differences. Only what Varlink exposes is available: unit files, portable images, jobs
+// and the properties of interfaces other than org.freedesktop.systemd1.Unit aren't, and calls
+// about them fail. Timestamps which systemd doesn't report are taken when a change is noticed.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::env;
+use std::io::{BufRead, BufReader, Error as IOError, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::{BusType, Error as DBusError, Message, Path, SignalArgs};
+use serde_json::{json, Value};
+
+use crate::bus::{UnitProps, INTERFACE_FOR_PROPERTIES, INTERFACE_FOR_SYSTEMD_MANAGER};
+use crate::error::{BusError, Error as CrateError};
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopDBusPropertiesPropertiesChanged as PropertiesChanged;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitNew as UnitNew;
+use crate::generated::org_freedesktop_systemd1::OrgFreedesktopSystemd1ManagerUnitRemoved as UnitRemoved;
+use crate::systemd;
+use crate::systemd::{SystemdBackend, SystemdManager};
+use crate::timestamp;
+use crate::unit;
+use crate::unit::ActiveState;
+
+const PATH_FOR_SYSTEMD: &str = "/org/freedesktop/systemd1";
+const INTERFACE_FOR_SYSTEMD_UNIT: &str = "org.freedesktop.systemd1.Unit";
+
+// The socket on which the system's systemd serves its Varlink interfaces. A user's systemd serves
+// them on `systemd/io.systemd.Manager`, relative to `$XDG_RUNTIME_DIR`.
+const SYSTEM_SOCKET_PATH: &str = "/run/systemd/io.systemd.Manager";
+const USER_SOCKET_PATH: &str = "systemd/io.systemd.Manager";
+
+// How often units are listed again, to notice changes.
+const VARLINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long a Varlink call may take while polling.
+const VARLINK_POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The unique name which bus watchers are told they were given, as if by a bus.
+const UNIQUE_NAME: &str = ":varlink";
+
+// The timestamps of org.freedesktop.systemd1.Unit, each of which is also reported as a monotonic
+// timestamp, e.g. `ActiveEnterTimestampMonotonic`.
+const TIMESTAMP_PROPERTIES: [&str; 5] = [
+    "ActiveEnterTimestamp",
+    "ActiveExitTimestamp",
+    "InactiveEnterTimestamp",
+    "InactiveExitTimestamp",
+    "StateChangeTimestamp",
+];
+
+// The properties of org.freedesktop.systemd1.Unit which are read from a unit's Varlink context or
+// runtime, under the same names.
+const PLAIN_PROPERTIES: [&str; 5] = [
+    "ActiveState",
+    "Description",
+    "LoadState",
+    "SubState",
+    "Transient",
+];
+
+// A unit's properties, as D-Bus would name them, keyed by property name.
+type Properties = BTreeMap<String, Value>;
+
+// A backend which talks to systemd over Varlink. See the top of this module.
+//
+// Bus watchers are handed the signals made up while polling by `pop_message`. As there's no file
+// descriptor to wait on, bus watchers ask for messages at least once per `VARLINK_POLL_INTERVAL`.
+pub struct VarlinkSystemd {
+    socket_path: PathBuf,
+    state: RefCell<VarlinkState>,
+}
+
+// The state of a `VarlinkSystemd`.
+//
+// `units` holds the properties of every unit listed last, keyed by unit name. `polled` states
+// when units were listed last.
+struct VarlinkState {
+    matches: Vec<String>,
+    polled: Instant,
+    queue: VecDeque<Message>,
+    serial: u32,
+    units: BTreeMap<String, Properties>,
+}
+
+impl VarlinkSystemd {
+    // Connect to the systemd instance which manages the units of the given bus, and list its units.
+    //
+    // Return an error if the socket can't be reached, or if it doesn't serve `io.systemd.Unit`,
+    // e.g. because systemd is too old.
+    pub fn connect(bus_type: BusType) -> Result<Self, CrateError> {
+        let socket_path = get_socket_path(bus_type);
+        let mut queue = VecDeque::new();
+        queue.push_back(systemd::gen_name_acquired(UNIQUE_NAME));
+        let varlink = VarlinkSystemd {
+            socket_path,
+            state: RefCell::new(VarlinkState {
+                matches: Vec::new(),
+                polled: Instant::now(),
+                queue,
+                serial: 0,
+                units: BTreeMap::new(),
+            }),
+        };
+        let units = varlink.list(VARLINK_POLL_TIMEOUT).map_err(|err| {
+            CrateError::Bus(BusError::ConnectToVarlink(
+                varlink.socket_path.display().to_string(),
+                err,
+            ))
+        })?;
+        varlink.state.borrow_mut().units = units;
+        Ok(varlink)
+    }
+
+    // Call `io.systemd.Unit.List`, and return the properties of every unit, keyed by unit name.
+    //
+    // Timestamps which systemd doesn't report are copied from the last listing, or taken now if the
+    // unit has changed state since.
+    fn list(&self, timeout: Duration) -> Result<BTreeMap<String, Properties>, DBusError> {
+        let replies = match self.call_varlink("io.systemd.Unit.List", json!({}), timeout) {
+            Err(err) if err.name() == Some("io.systemd.Unit.NoSuchUnit") => Vec::new(),
+            result => result?,
+        };
+        let state = self.state.borrow();
+        let now_mono = timestamp::get_current_monotonic_timestamp().0;
+        let now_real = timestamp::get_current_realtime_timestamp().0;
+        let mut units = BTreeMap::new();
+        for reply in replies {
+            let (unit_name, mut properties) = match read_unit(&reply) {
+                Some(unit) => unit,
+                None => continue,
+            };
+            let old_properties = state.units.get(&unit_name);
+            let changed = old_properties.map_or(true, |old_properties| {
+                old_properties.get("ActiveState") != properties.get("ActiveState")
+            });
+            let active_state = properties
+                .get("ActiveState")
+                .and_then(Value::as_str)
+                .and_then(|active_state| ActiveState::try_from(active_state).ok());
+            for name in &TIMESTAMP_PROPERTIES {
+                let entered = changed
+                    && active_state.as_ref().map_or(false, |active_state| {
+                        *name == "StateChangeTimestamp"
+                            || *name == timestamp::get_realtime_timestamp_key(active_state)
+                    });
+                let monotonic = format!("{}Monotonic", name);
+                for (key, now) in &[(name.to_string(), now_real), (monotonic, now_mono)] {
+                    if properties.contains_key(key) {
+                        continue;
+                    }
+                    let value = if entered {
+                        json!(now)
+                    } else {
+                        old_properties
+                            .and_then(|old_properties| old_properties.get(key))
+                            .cloned()
+                            .unwrap_or_else(|| json!(0))
+                    };
+                    properties.insert(key.to_owned(), value);
+                }
+            }
+            units.insert(unit_name, properties);
+        }
+        Ok(units)
+    }
+
+    // List units if `VARLINK_POLL_INTERVAL` has passed since they were listed last, and queue the
+    // signals which systemd would have emitted about the changes since, if match rules ask for
+    // them. A failure is ignored: units are listed again later, and calls report it meanwhile.
+    fn poll(&self) {
+        if self.state.borrow().polled.elapsed() < VARLINK_POLL_INTERVAL {
+            return;
+        }
+        let units = self.list(VARLINK_POLL_TIMEOUT);
+        let mut guard = self.state.borrow_mut();
+        let state = &mut *guard;
+        state.polled = Instant::now();
+        let units = match units {
+            Ok(units) => units,
+            Err(_) => return,
+        };
+        let manager_path = gen_path(PATH_FOR_SYSTEMD);
+        for (unit_name, properties) in &units {
+            let unit_path = unit::get_unit_path(unit_name);
+            match state.units.get(unit_name) {
+                None => {
+                    if state.is_matched("UnitNew", PATH_FOR_SYSTEMD, unit_name) {
+                        let signal = UnitNew {
+                            arg0: unit_name.to_owned(),
+                            arg1: gen_path(&unit_path),
+                        };
+                        state.queue.push_back(signal.to_emit_message(&manager_path));
+                    }
+                }
+                Some(old_properties) => {
+                    let changed: Properties = properties
+                        .iter()
+                        .filter(|(name, value)| old_properties.get(*name) != Some(value))
+                        .map(|(name, value)| (name.to_owned(), value.clone()))
+                        .collect();
+                    if !changed.is_empty()
+                        && state.is_matched(
+                            "PropertiesChanged",
+                            &unit_path,
+                            INTERFACE_FOR_SYSTEMD_UNIT,
+                        )
+                    {
+                        let signal = PropertiesChanged {
+                            interface: INTERFACE_FOR_SYSTEMD_UNIT.to_string(),
+                            changed_properties: gen_props(&changed),
+                            invalidated_properties: Vec::new(),
+                        };
+                        state
+                            .queue
+                            .push_back(signal.to_emit_message(&gen_path(&unit_path)));
+                    }
+                }
+            }
+        }
+        let removed: Vec<String> = state
+            .units
+            .keys()
+            .filter(|unit_name| !units.contains_key(*unit_name))
+            .cloned()
+            .collect();
+        for unit_name in removed {
+            if state.is_matched("UnitRemoved", PATH_FOR_SYSTEMD, &unit_name) {
+                let signal = UnitRemoved {
+                    arg1: gen_path(&unit::get_unit_path(&unit_name)),
+                    arg0: unit_name,
+                };
+                state.queue.push_back(signal.to_emit_message(&manager_path));
+            }
+        }
+        state.units = units;
+    }
+
+    // Answer a D-Bus method call to systemd, as systemd would, from the units listed last.
+    fn answer(&self, msg: &Message) -> Result<Message, DBusError> {
+        let state = self.state.borrow();
+        let path = msg.path().map(|path| path.to_string()).unwrap_or_default();
+        let interface = msg.interface().map(|interface| interface.to_string());
+        let member = msg.member().map(|member| member.to_string());
+        let reply = Message::new_method_return(msg).ok_or_else(|| {
+            DBusError::new_custom(
+                "org.freedesktop.DBus.Error.Failed",
+                "Failed to create reply",
+            )
+        })?;
+        match (interface.as_deref(), member.as_deref()) {
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("Subscribe")) => Ok(reply),
+            (Some(INTERFACE_FOR_SYSTEMD_MANAGER), Some("GetUnit")) => {
+                let unit_name: &str = msg.read1()?;
+                if !state.units.contains_key(unit_name) {
+                    return Err(DBusError::new_custom(
+                        "org.freedesktop.systemd1.NoSuchUnit",
+                        &format!("Unit {} not loaded.", unit_name),
+                    ));
+                }
+                Ok(reply.append1(gen_path(&unit::get_unit_path(unit_name))))
+            }
+            (Some(INTERFACE_FOR_PROPERTIES), Some("GetAll")) => {
+                let interface_name: &str = msg.read1()?;
+                let properties = state.get_properties(&path, interface_name)?;
+                Ok(reply.append1(gen_props(&properties)))
+            }
+            (Some(INTERFACE_FOR_PROPERTIES), Some("Get")) => {
+                let (interface_name, property_name): (&str, &str) = msg.read2()?;
+                let value = state
+                    .get_properties(&path, interface_name)?
+                    .get(property_name)
+                    .and_then(to_ref_arg)
+                    .ok_or_else(|| {
+                        DBusError::new_custom(
+                            "org.freedesktop.DBus.Error.UnknownProperty",
+                            &format!("Varlink doesn't report property {}.", property_name),
+                        )
+                    })?;
+                Ok(reply.append1(Variant(value)))
+            }
+            _ => Err(gen_not_supported(member.as_deref().unwrap_or_default())),
+        }
+    }
+
+    // Make a Varlink call, and return the parameters of each reply.
+    //
+    // Calls ask for more than one reply, as `io.systemd.Unit.List` sends one per unit. An error
+    // reply is returned as a D-Bus error of the same name.
+    fn call_varlink(
+        &self,
+        method: &str,
+        parameters: Value,
+        timeout: Duration,
+    ) -> Result<Vec<Value>, DBusError> {
+        let mut stream = UnixStream::connect(&self.socket_path).map_err(gen_io_error)?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .map_err(gen_io_error)?;
+        let request = json!({"method": method, "parameters": parameters, "more": true});
+        let mut request = request.to_string().into_bytes();
+        request.push(0);
+        stream.write_all(&request).map_err(gen_io_error)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut replies = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            reader.read_until(0, &mut buf).map_err(gen_io_error)?;
+            if buf.pop() != Some(0) {
+                return Err(DBusError::new_custom(
+                    "org.freedesktop.DBus.Error.Disconnected",
+                    "systemd closed the Varlink connection before replying",
+                ));
+            }
+            let mut reply: Value = serde_json::from_slice(&buf).map_err(|err| {
+                DBusError::new_custom("org.freedesktop.DBus.Error.InvalidArgs", &err.to_string())
+            })?;
+            if let Some(error) = reply.get("error").and_then(Value::as_str) {
+                let parameters = reply.get("parameters").cloned().unwrap_or_default();
+                return Err(DBusError::new_custom(error, &parameters.to_string()));
+            }
+            let continues = reply.get("continues").and_then(Value::as_bool) == Some(true);
+            let parameters = reply.get_mut("parameters").map(Value::take);
+            replies.push(parameters.unwrap_or_default());
+            if !continues {
+                return Ok(replies);
+            }
+        }
+    }
+}
+
+impl VarlinkState {
+    // Tell whether a match rule asks for signals with the given member from the given path, whose
+    // first argument is `arg0`.
+    fn is_matched(&self, member: &str, path: &str, arg0: &str) -> bool {
+        systemd::is_matched(&self.matches, member, path, Some(arg0))
+    }
+
+    // Get the properties of the given interface of the unit at the given object path.
+    //
+    // Varlink only reports the properties of org.freedesktop.systemd1.Unit, so other interfaces of
+    // a unit have no properties.
+    fn get_properties(&self, path: &str, interface: &str) -> Result<Properties, DBusError> {
+        let properties = self
+            .units
+            .iter()
+            .find(|(unit_name, _)| unit::get_unit_path(unit_name) == path)
+            .map(|(_, properties)| properties)
+            .ok_or_else(|| {
+                DBusError::new_custom(
+                    "org.freedesktop.DBus.Error.UnknownObject",
+                    &format!("Unknown object '{}'.", path),
+                )
+            })?;
+        match interface {
+            INTERFACE_FOR_SYSTEMD_UNIT => Ok(properties.clone()),
+            _ => Ok(Properties::new()),
+        }
+    }
+
+    // Get the serial for the next call.
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+}
+
+impl SystemdBackend for VarlinkSystemd {
+    fn call(&self, mut msg: Message, _timeout_ms: i32) -> Result<Message, DBusError> {
+        msg.set_serial(self.state.borrow_mut().next_serial());
+        self.answer(&msg)
+    }
+
+    fn send(&self, mut msg: Message) -> Option<u32> {
+        let serial = self.state.borrow_mut().next_serial();
+        msg.set_serial(serial);
+        let reply = match self.answer(&msg) {
+            Ok(reply) => reply,
+            Err(err) => Message::new_error(
+                &msg,
+                err.name().unwrap_or("org.freedesktop.DBus.Error.Failed"),
+                err.message().unwrap_or_default(),
+            )?,
+        };
+        self.state.borrow_mut().queue.push_back(reply);
+        Some(serial)
+    }
+
+    // Wait for a change to be noticed, listing units each time `VARLINK_POLL_INTERVAL` passes.
+    fn pop_message(&self, timeout_ms: u32) -> Option<Message> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.into());
+        loop {
+            self.poll();
+            if let Some(msg) = self.state.borrow_mut().queue.pop_front() {
+                return Some(msg);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            let next_poll = self.state.borrow().polled + VARLINK_POLL_INTERVAL;
+            thread::sleep(deadline.min(next_poll).saturating_duration_since(now));
+        }
+    }
+
+    fn add_match(&self, match_str: &str) -> Result<(), DBusError> {
+        self.state.borrow_mut().matches.push(match_str.to_string());
+        Ok(())
+    }
+
+    fn remove_match(&self, match_str: &str) -> Result<(), DBusError> {
+        let mut state = self.state.borrow_mut();
+        match state.matches.iter().position(|other| other == match_str) {
+            Some(i) => {
+                state.matches.remove(i);
+                Ok(())
+            }
+            None => Err(DBusError::new_custom(
+                "org.freedesktop.DBus.Error.MatchRuleNotFound",
+                "The given match rule wasn't found and can't be removed",
+            )),
+        }
+    }
+
+    fn get_pollfds(&self) -> Vec<libc::pollfd> {
+        Vec::new()
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        Some(VARLINK_POLL_INTERVAL)
+    }
+}
+
+// The D-Bus calls which aren't answered from a Varlink listing, like ListUnitFiles, fail.
+impl SystemdManager for VarlinkSystemd {}
+
+// Get the socket of the systemd instance which manages the units of the given bus.
+fn get_socket_path(bus_type: BusType) -> PathBuf {
+    match bus_type {
+        BusType::Session => env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                // SAFETY: `getuid` always succeeds.
+                PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() }))
+            })
+            .join(USER_SOCKET_PATH),
+        BusType::Starter | BusType::System => PathBuf::from(SYSTEM_SOCKET_PATH),
+    }
+}
+
+// Read a unit's name and properties from the parameters of a reply to `io.systemd.Unit.List`.
+//
+// The parameters hold the unit's `context`, i.e. its configuration, and its `runtime`, i.e. its
+// state. Timestamps are objects with a `realtime` and a `monotonic` member. Return `None` if the
+// unit has no name.
+fn read_unit(parameters: &Value) -> Option<(String, Properties)> {
+    let context = parameters.get("context");
+    let runtime = parameters.get("runtime");
+    let lookup = |name: &str| {
+        runtime
+            .and_then(|runtime| runtime.get(name))
+            .or_else(|| context.and_then(|context| context.get(name)))
+    };
+    let unit_name = lookup("ID")?.as_str()?.to_string();
+    let mut properties = Properties::new();
+    properties.insert("Id".to_string(), json!(unit_name));
+    for name in &PLAIN_PROPERTIES {
+        if let Some(value) = lookup(name) {
+            properties.insert(name.to_string(), value.clone());
+        }
+    }
+    for name in &TIMESTAMP_PROPERTIES {
+        if let Some(value) = lookup(name) {
+            if let Some(realtime) = value.get("realtime").and_then(Value::as_u64) {
+                properties.insert(name.to_string(), json!(realtime));
+            }
+            if let Some(monotonic) = value.get("monotonic").and_then(Value::as_u64) {
+                properties.insert(format!("{}Monotonic", name), json!(monotonic));
+            }
+        }
+    }
+    Some((unit_name, properties))
+}
+
+// Turn a property's JSON value into a D-Bus value, or return `None` if it's of a type which
+// killjoy doesn't read.
+fn to_ref_arg(value: &Value) -> Option<Box<dyn RefArg>> {
+    match value {
+        Value::Bool(value) => Some(Box::new(*value)),
+        Value::Number(value) => value
+            .as_u64()
+            .map(|value| Box::new(value) as Box<dyn RefArg>),
+        Value::String(value) => Some(Box::new(value.clone())),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(str::to_string))
+            .collect::<Option<Vec<String>>>()
+            .map(|values| Box::new(values) as Box<dyn RefArg>),
+        Value::Null | Value::Object(_) => None,
+    }
+}
+
+// Turn properties into D-Bus properties, skipping those which can't be turned.
+fn gen_props(properties: &Properties) -> UnitProps {
+    properties
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_owned(), Variant(to_ref_arg(value)?))))
+        .collect()
+}
+
+// Create an object path from a string which is known to be valid.
+fn gen_path(path: &str) -> Path<'static> {
+    Path::new(path.to_string()).unwrap_or_else(|_| panic!("Failed to create Path from '{}'", path))
+}
+
+// Describe a failure to talk to the Varlink socket.
+fn gen_io_error(err: IOError) -> DBusError {
+    DBusError::new_custom("org.freedesktop.DBus.Error.IOError", &err.to_string())
+}
+
+// Describe a call which can't be answered over Varlink.
+fn gen_not_supported(member: &str) -> DBusError {
+    DBusError::new_custom(
+        "org.freedesktop.DBus.Error.NotSupported",
+        &format!("{} isn't available over Varlink.", member),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // read_unit()
+    #[test]
+    fn test_read_unit() {
+        let parameters = json!({
+            "context": {"ID": "foo.service", "Description": "Foo"},
+            "runtime": {
+                "ActiveState": "failed",
+                "LoadState": "loaded",
+                "SubState": "failed",
+                "InactiveEnterTimestamp": {"realtime": 20, "monotonic": 10}
+            }
+        });
+        let (unit_name, properties) = read_unit(&parameters).expect("Failed to read unit.");
+        assert_eq!(unit_name, "foo.service");
+        assert_eq!(properties["Id"], json!("foo.service"));
+        assert_eq!(properties["ActiveState"], json!("failed"));
+        assert_eq!(properties["Description"], json!("Foo"));
+        assert_eq!(properties["InactiveEnterTimestamp"], json!(20));
+        assert_eq!(properties["InactiveEnterTimestampMonotonic"], json!(10));
+        assert!(!properties.contains_key("ActiveEnterTimestamp"));
+
+        assert!(read_unit(&json!({"runtime": {"ActiveState": "failed"}})).is_none());
+    }
+
+    // to_ref_arg()
+    #[test]
+    fn test_to_ref_arg() {
+        let value = to_ref_arg(&json!(7)).expect("Failed to convert number.");
+        assert_eq!(value.as_u64(), Some(7));
+        let value = to_ref_arg(&json!("active")).expect("Failed to convert string.");
+        assert_eq!(value.as_str(), Some("active"));
+        assert!(to_ref_arg(&json!(["a", "b"])).is_some());
+        assert!(to_ref_arg(&json!([1])).is_none());
+        assert!(to_ref_arg(&json!({"realtime": 1})).is_none());
+        assert!(to_ref_arg(&Value::Null).is_none());
+    }
+
+    // get_socket_path()
+    #[test]
+    fn test_get_socket_path() {
+        assert_eq!(
+            get_socket_path(BusType::System),
+            PathBuf::from(SYSTEM_SOCKET_PATH)
+        );
+        assert!(get_socket_path(BusType::Session).ends_with(USER_SOCKET_PATH));
+    }
+}
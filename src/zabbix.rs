@@ -0,0 +1,235 @@
+// A minimal Zabbix sender, and Zabbix low-level discovery, for feeding units' states to Zabbix.
+//
+// Each unit is discovered with the `{#BUS}` and `{#UNIT}` macros, and its state is sent as the
+// value of the trapper item `killjoy.unit.state[{#BUS},{#UNIT}]`.
+//
+// See: https://www.zabbix.com/documentation/current/en/manual/appendix/protocols/zabbix_sender
+
+use std::io::{Error as IOError, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::monitor::Event;
+use crate::settings;
+
+// How long to wait for the Zabbix server when connecting, reading or writing.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The header which starts every Zabbix protocol message: a signature, and flags.
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+// The largest response accepted from a Zabbix server, in bytes.
+const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+
+// Generate low-level discovery data for the given units.
+pub fn gen_discovery(units: &[Event]) -> Value {
+    Value::Array(
+        units
+            .iter()
+            .map(|unit| {
+                json!({
+                    "{#BUS}": settings::encode_bus_type(unit.bus_type),
+                    "{#UNIT}": unit.unit_name,
+                })
+            })
+            .collect(),
+    )
+}
+
+// Generate the key of the item holding a unit's state.
+pub fn gen_item_key(unit: &Event) -> String {
+    format!(
+        "killjoy.unit.state[{},{}]",
+        settings::encode_bus_type(unit.bus_type),
+        unit.unit_name
+    )
+}
+
+// Send each unit's state to the given Zabbix server, as an item of the given Zabbix host.
+//
+// Return an error if the server can't be reached, or if it doesn't report success. Items which the
+// server doesn't know about are ignored by the server, and aren't an error.
+pub fn send(
+    server: &str,
+    port: u16,
+    host: &str,
+    units: &[Event],
+    timeout: Duration,
+) -> Result<(), IOError> {
+    let mut stream = connect(server, port, timeout)?;
+    stream.write_all(&gen_message(&gen_sender_data(host, units)))?;
+    let response = read_message(&mut stream)?;
+    match response.get("response").and_then(Value::as_str) {
+        Some("success") => Ok(()),
+        _ => Err(IOError::other(format!(
+            "server didn't accept the data: {}",
+            response
+                .get("info")
+                .and_then(Value::as_str)
+                .unwrap_or("no reason given")
+        ))),
+    }
+}
+
+// Open a connection to the given server.
+fn connect(server: &str, port: u16, timeout: Duration) -> Result<TcpStream, IOError> {
+    let mut last_err = IOError::new(ErrorKind::NotFound, "host has no addresses");
+    for addr in (server, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+// Generate a "sender data" request, holding one item per unit.
+fn gen_sender_data(host: &str, units: &[Event]) -> Value {
+    let data: Vec<Value> = units
+        .iter()
+        .map(|unit| {
+            json!({
+                "host": host,
+                "key": gen_item_key(unit),
+                "value": unit.new_state.to_string(),
+            })
+        })
+        .collect();
+    json!({"request": "sender data", "data": data})
+}
+
+// Generate a message with the given body.
+//
+// The header is followed by the length of the body, as a 32-bit little-endian integer, and by
+// four reserved bytes.
+fn gen_message(body: &Value) -> Vec<u8> {
+    let body = body.to_string().into_bytes();
+    let mut message: Vec<u8> = HEADER.to_vec();
+    message.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    message.extend_from_slice(&[0; 4]);
+    message.extend_from_slice(&body);
+    message
+}
+
+// Read a message, and return its body.
+fn read_message<R: Read>(reader: &mut R) -> Result<Value, IOError> {
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)?;
+    if &header[..5] != HEADER {
+        return Err(gen_protocol_error("unexpected header"));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[5..9]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_RESPONSE_LEN {
+        return Err(gen_protocol_error("response is too long"));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map_err(|_| gen_protocol_error("response isn't JSON"))
+}
+
+// Generate an error explaining how the server broke the protocol.
+fn gen_protocol_error(reason: &str) -> IOError {
+    IOError::new(
+        ErrorKind::InvalidData,
+        format!("Zabbix protocol error: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::thread;
+
+    use dbus::BusType;
+
+    use crate::timestamp::{MonotonicTimestamp, RealtimeTimestamp};
+    use crate::unit::ActiveState;
+
+    fn gen_unit(unit_name: &str, new_state: ActiveState) -> Event {
+        Event {
+            bus_type: BusType::System,
+            mono_ts: MonotonicTimestamp(0),
+            new_state,
+            old_state: None,
+            real_ts: RealtimeTimestamp(0),
+            unit_name: unit_name.to_string(),
+        }
+    }
+
+    // gen_discovery(), gen_item_key()
+    #[test]
+    fn test_gen_discovery() {
+        let units = vec![gen_unit("foo.service", ActiveState::Failed)];
+        assert_eq!(
+            gen_discovery(&units),
+            json!([{"{#BUS}": "system", "{#UNIT}": "foo.service"}])
+        );
+        assert_eq!(
+            gen_item_key(&units[0]),
+            "killjoy.unit.state[system,foo.service]"
+        );
+    }
+
+    // gen_message(), read_message()
+    #[test]
+    fn test_gen_read_message() {
+        let body = json!({"response": "success"});
+        let message = gen_message(&body);
+        assert_eq!(&message[..13], b"ZBXD\x01\x16\0\0\0\0\0\0\0");
+        assert_eq!(read_message(&mut &message[..]).unwrap(), body);
+        assert!(read_message(&mut &b"HTTP/1.1 400 Bad Request\r\n"[..]).is_err());
+    }
+
+    // send()
+    #[test]
+    fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen.");
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_message(&mut stream).unwrap();
+            let response = json!({"response": "success", "info": "processed: 1"});
+            stream.write_all(&gen_message(&response)).unwrap();
+            request
+        });
+        let units = vec![gen_unit("foo.service", ActiveState::Failed)];
+        send("127.0.0.1", port, "web1", &units, DEFAULT_TIMEOUT).expect("Failed to send.");
+        assert_eq!(
+            handle.join().unwrap(),
+            json!({
+                "request": "sender data",
+                "data": [{
+                    "host": "web1",
+                    "key": "killjoy.unit.state[system,foo.service]",
+                    "value": "failed",
+                }],
+            })
+        );
+    }
+
+    // send()
+    #[test]
+    fn test_send_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to listen.");
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_message(&mut stream).unwrap();
+            let response = json!({"response": "failed", "info": "no such host"});
+            stream.write_all(&gen_message(&response)).unwrap();
+        });
+        let err = send("127.0.0.1", port, "web1", &[], DEFAULT_TIMEOUT).unwrap_err();
+        handle.join().unwrap();
+        assert!(err.to_string().contains("no such host"));
+    }
+}
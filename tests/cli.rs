@@ -8,8 +8,268 @@ use std::process::Command;
 
 use assert_cmd::cargo;
 use assert_cmd::prelude::OutputAssertExt;
+use killjoy::error::EXIT_MONITORING;
 use tempfile::{NamedTempFile, TempDir};
 
+// Call `killjoy check` and expect failure, as systemd can't be reached.
+//
+// Failing to call systemd is a failure while watching units, not a unit of concern, so the exit
+// status is `EXIT_MONITORING` rather than 1, and the error is a D-Bus and systemd one.
+#[test]
+fn test_check_failure() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    let output = Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "check"])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stderr = String::from_utf8(output.stderr.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(EXIT_MONITORING);
+    assert!(stderr.contains("[KJ2"));
+}
+
+// Call `killjoy check --format nagios` and expect an UNKNOWN report, as systemd can't be reached.
+#[test]
+fn test_check_nagios_failure() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    let output = Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "check",
+            "--format",
+            "nagios",
+        ])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stdout = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(3);
+    assert!(stdout.starts_with("KILLJOY UNKNOWN - "));
+}
+
+// Call `killjoy check` and `killjoy check --nagios`, and expect success, as there are no rules.
+#[test]
+fn test_check_success() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    settings_file
+        .write_all(br#"{"version": 1, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "check"])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0)
+        .stdout("");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "check", "--nagios"])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0)
+        .stdout("KILLJOY OK - no units of concern | units=0;;;0 failed=0;;;0 activating=0;;;0\n");
+}
+
+// Call `killjoy check --format zabbix-lld` and expect empty discovery data, as there are no rules.
+#[test]
+fn test_check_zabbix_lld_success() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    settings_file
+        .write_all(br#"{"version": 1, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "check",
+            "--format",
+            "zabbix-lld",
+        ])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0)
+        .stdout("[]\n");
+}
+
+// Call `killjoy install --user` twice, and expect the second call to fail unless forced.
+#[test]
+fn test_install_user() {
+    let config_dir = TempDir::new().expect("Failed to create xdg_config_home.");
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    for (args, code) in [
+        (vec!["install", "--user"], 0),
+        (vec!["install", "--user"], 1),
+        (vec!["install", "--user", "--force"], 0),
+    ] {
+        Command::new(killjoy_path_as_string())
+            .env("XDG_CONFIG_HOME", config_dir_str)
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+            .assert()
+            .code(code);
+    }
+    let unit = fs::read_to_string(config_dir.path().join("systemd/user/killjoy.service"))
+        .expect("Failed to read service unit.");
+    assert!(unit.contains(&format!("ExecStart={}\n", killjoy_path_as_string())));
+}
+
+// Call `killjoy man --output-dir $dir`, and expect a page for each subcommand, including nested ones.
+#[test]
+fn test_man_output_dir() {
+    let output_dir = TempDir::new().expect("Failed to create output_dir.");
+    Command::new(killjoy_path_as_string())
+        .args([
+            "man",
+            "--output-dir",
+            output_dir
+                .path()
+                .to_str()
+                .expect("Failed to convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0);
+    for name in [
+        "killjoy.1",
+        "killjoy-check.1",
+        "killjoy-settings-validate.1",
+    ] {
+        assert!(
+            output_dir.path().join(name).is_file(),
+            "{} is missing",
+            name
+        );
+    }
+    assert!(!output_dir.path().join("killjoy-help.1").exists());
+    let page = fs::read_to_string(output_dir.path().join("killjoy.1")).unwrap();
+    assert!(page.contains("loop\\-timeout"));
+}
+
+// Call `killjoy notifiers check` and expect failure due to the notifier being unreachable.
+#[test]
+fn test_notifiers_check_failure() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "notifiers", "check"])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(5);
+}
+
+// Call `killjoy notifiers check` and expect success, as there are no notifiers to check.
+#[test]
+fn test_notifiers_check_success() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    settings_file
+        .write_all(br#"{"version": 1, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "notifiers", "check"])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0)
+        .stdout("");
+}
+
+// Call `killjoy notifiers test` and expect failure due to the notifier being unreachable.
+#[test]
+fn test_notifiers_test_failure_v1() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "notifiers",
+            "test",
+            "desktop popup",
+        ])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(5);
+}
+
+// Call `killjoy notifiers test` and expect failure due to the notifier not being configured.
+#[test]
+fn test_notifiers_test_failure_v2() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    let output = Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "notifiers",
+            "test",
+            "nope",
+        ])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stderr = String::from_utf8(output.stderr.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(2);
+    assert!(stderr.contains("nope"));
+}
+
 // Call `killjoy settings load-path` and expect failure.
 #[test]
 fn test_settings_load_path_failure() {
@@ -25,11 +285,11 @@ fn test_settings_load_path_failure() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "load-path"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "load-path"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
-        .code(1);
+        .code(2);
 }
 
 // Call `killjoy settings load-path` and expect success.
@@ -43,7 +303,7 @@ fn test_settings_load_path_success() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "load-path"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "load-path"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
@@ -61,11 +321,11 @@ fn test_settings_validate_failure_v1() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "validate"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "validate"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
-        .code(1);
+        .code(2);
 }
 
 // Call `killjoy settings validate` and expect failure due to the settings file being absent.
@@ -83,11 +343,11 @@ fn test_settings_validate_failure_v2() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "validate"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "validate"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
-        .code(1);
+        .code(2);
 }
 
 // Call `killjoy settings validate` and expect failure due to the settings file being unreadable.
@@ -102,7 +362,7 @@ fn test_settings_validate_failure_v3() {
     Command::new("chmod")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&[
+        .args([
             "000",
             Path::new(&settings_dir)
                 .join("settings.json")
@@ -116,11 +376,11 @@ fn test_settings_validate_failure_v3() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "validate"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "validate"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
-        .code(1);
+        .code(2);
 }
 
 // Call `killjoy settings validate` and expect success.
@@ -135,7 +395,7 @@ fn test_settings_validate_success() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..], "settings", "validate"])
+        .args(["--", &killjoy_path_as_string()[..], "settings", "validate"])
         .output()
         .expect("Failed to run killjoy.")
         .assert()
@@ -148,7 +408,7 @@ fn test_settings_validate_path_failure() {
     let settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
 
     Command::new("dbus-run-session")
-        .args(&[
+        .args([
             "--",
             &killjoy_path_as_string()[..],
             "settings",
@@ -161,7 +421,46 @@ fn test_settings_validate_path_failure() {
         .output()
         .expect("Failed to run killjoy.")
         .assert()
-        .code(1);
+        .code(2);
+}
+
+// Call `killjoy settings validate $path` and expect failure, with a hint for a typo'd state.
+#[test]
+fn test_settings_validate_path_hint() {
+    let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
+    settings_file
+        .write_all(
+            br#"{
+                "version": 1,
+                "rules": [{
+                    "active_states": ["failedd"],
+                    "bus_type": "session",
+                    "expression": "foo.service",
+                    "expression_type": "unit name",
+                    "notifiers": []
+                }],
+                "notifiers": {}
+            }"#,
+        )
+        .expect("Failed to populate settings file.");
+
+    let output = Command::new("dbus-run-session")
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "settings",
+            "validate",
+            settings_file
+                .path()
+                .to_str()
+                .expect("Failed convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stderr = String::from_utf8(output.stderr.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(2);
+    assert!(stderr.contains("/rules/0/active_states/0"));
+    assert!(stderr.contains("did you mean \"failed\"?"));
 }
 
 // Call `killjoy settings validate $path` and expect success.
@@ -170,7 +469,7 @@ fn test_settings_validate_path_success() {
     let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
     write_session_settings(&mut settings_file);
     Command::new("dbus-run-session")
-        .args(&[
+        .args([
             "--",
             &killjoy_path_as_string()[..],
             "settings",
@@ -186,6 +485,100 @@ fn test_settings_validate_path_success() {
         .code(0);
 }
 
+// Call `killjoy settings validate --strict $path` and expect failure, as nothing owns the
+// notifier's bus name on a stand-alone session bus. systemd can't be asked about units there
+// either, and that failure decides the exit status.
+#[test]
+fn test_settings_validate_strict_failure() {
+    let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
+    write_session_settings(&mut settings_file);
+    let output = Command::new("dbus-run-session")
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "settings",
+            "validate",
+            "--strict",
+            settings_file
+                .path()
+                .to_str()
+                .expect("Failed to convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stderr = String::from_utf8(output.stderr.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(4);
+    assert!(stderr.contains("desktop popup"));
+}
+
+// Call `killjoy settings validate --strict $path` and expect success, as there is nothing to check.
+#[test]
+fn test_settings_validate_strict_success() {
+    let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
+    settings_file
+        .write_all(br#"{"version": 1, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    Command::new("dbus-run-session")
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "settings",
+            "validate",
+            "--strict",
+            settings_file
+                .path()
+                .to_str()
+                .expect("Failed to convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(0);
+}
+
+// Call `killjoy settings migrate $path` and expect the current version to be printed.
+#[test]
+fn test_settings_migrate_path_success() {
+    let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
+    write_session_settings(&mut settings_file);
+    let output = Command::new(killjoy_path_as_string())
+        .args([
+            "settings",
+            "migrate",
+            settings_file
+                .path()
+                .to_str()
+                .expect("Failed to convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.");
+    let stdout = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+    assert!(stdout.contains(r#""version": 1"#));
+}
+
+// Call `killjoy settings migrate $path` and expect failure, as the version is from the future.
+#[test]
+fn test_settings_migrate_path_failure() {
+    let mut settings_file = NamedTempFile::new().expect("Failed to create a named temporary file.");
+    settings_file
+        .write_all(br#"{"version": 999, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    Command::new(killjoy_path_as_string())
+        .args([
+            "settings",
+            "migrate",
+            settings_file
+                .path()
+                .to_str()
+                .expect("Failed to convert path to string."),
+        ])
+        .output()
+        .expect("Failed to run killjoy.")
+        .assert()
+        .code(2);
+}
+
 // Prevent killjoy's worker threads from contacting systemd.
 //
 // This test makes that happen by starting a temporary stand-alone session D-Bus instance, where
@@ -201,11 +594,11 @@ fn test_no_systemd() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&["--", &killjoy_path_as_string()[..]])
+        .args(["--", &killjoy_path_as_string()[..]])
         .output()
         .expect("failed to run executable")
         .assert()
-        .code(1);
+        .code(EXIT_MONITORING);
 }
 
 // Call `killjoy`, and let the settings be invalid.
@@ -219,7 +612,7 @@ fn test_run_settings_failure() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&[
+        .args([
             "--",
             &killjoy_path_as_string()[..],
             "--loop-once",
@@ -229,7 +622,7 @@ fn test_run_settings_failure() {
         .output()
         .expect("Failed to run killjoy")
         .assert()
-        .code(1);
+        .code(2);
 }
 
 // Call `killjoy`, and let the settings be valid.
@@ -244,9 +637,35 @@ fn test_run_settings_success() {
     Command::new("dbus-run-session")
         .env("XDG_CONFIG_HOME", config_dir_str)
         .env("XDG_CONFIG_DIRS", config_dir_str)
-        .args(&[
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "--loop-once",
+            "--loop-timeout",
+            "0",
+        ])
+        .output()
+        .expect("Failed to run killjoy")
+        .assert()
+        .code(0);
+}
+
+// Call `killjoy --low-power`, and expect it to stop after one wait, like in normal mode.
+#[test]
+fn test_run_low_power() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_system_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
             "--",
             &killjoy_path_as_string()[..],
+            "--low-power",
             "--loop-once",
             "--loop-timeout",
             "0",
@@ -257,6 +676,216 @@ fn test_run_settings_success() {
         .code(0);
 }
 
+// Call `killjoy --single-thread`, and expect it to stop after one wait, like in normal mode.
+#[test]
+fn test_run_single_thread() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_system_settings(&mut settings_file);
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args([
+            "--",
+            &killjoy_path_as_string()[..],
+            "--single-thread",
+            "--loop-once",
+            "--loop-timeout",
+            "0",
+        ])
+        .output()
+        .expect("Failed to run killjoy")
+        .assert()
+        .code(0);
+}
+
+// Call `killjoy --low-power --oneshot`, and expect an error, as the two conflict.
+#[test]
+fn test_run_low_power_oneshot() {
+    Command::new(killjoy_path_as_string())
+        .args(["--low-power", "--oneshot"])
+        .output()
+        .expect("Failed to run killjoy")
+        .assert()
+        .code(2);
+}
+
+// Call `killjoy --oneshot` and expect it to exit, as there are no units to handle.
+#[test]
+fn test_run_oneshot() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    settings_file
+        .write_all(br#"{"version": 1, "rules": [], "notifiers": {}}"#)
+        .expect("Failed to populate settings file.");
+    let config_dir_str = config_dir
+        .path()
+        .to_str()
+        .expect("Failed to convert path to string.");
+    Command::new("dbus-run-session")
+        .env("XDG_CONFIG_HOME", config_dir_str)
+        .env("XDG_CONFIG_DIRS", config_dir_str)
+        .args(["--", &killjoy_path_as_string()[..], "--oneshot"])
+        .output()
+        .expect("Failed to run killjoy")
+        .assert()
+        .code(0);
+}
+
+// Call `killjoy silence add`, `list` and `remove`, and expect the silence to come and go.
+#[test]
+fn test_silence_add_list_remove() {
+    let state_dir = TempDir::new().expect("Failed to create temporary directory.");
+    let killjoy = |args: &[&str]| {
+        Command::new(killjoy_path_as_string())
+            .env("XDG_STATE_HOME", state_dir.path())
+            .arg("silence")
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+    };
+
+    let output = killjoy(&["add", "--duration", "1h", "--unit", "foo*.service"]);
+    let id = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+
+    let output = killjoy(&["list"]);
+    let listing = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+    assert!(listing.starts_with(&format!("{}\t", id.trim())));
+    assert!(listing.contains("foo*.service"));
+
+    killjoy(&["remove", id.trim()]).assert().code(0);
+    killjoy(&["remove", id.trim()]).assert().code(1);
+    killjoy(&["list"]).assert().code(0).stdout("");
+    killjoy(&["add", "--duration", "soon"]).assert().code(2);
+}
+
+// Call `killjoy pause` and `resume`, and expect the bus to be paused and resumed.
+#[test]
+fn test_pause_resume() {
+    let state_dir = TempDir::new().expect("Failed to create temporary directory.");
+    let killjoy = |args: &[&str]| {
+        Command::new(killjoy_path_as_string())
+            .env("XDG_STATE_HOME", state_dir.path())
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+    };
+
+    killjoy(&["pause", "session"]).assert().code(0);
+    killjoy(&["pause", "session"]).assert().code(0);
+    killjoy(&["pause"]).assert().code(0).stdout("session\n");
+    killjoy(&["resume", "session"]).assert().code(0);
+    killjoy(&["pause"]).assert().code(0).stdout("");
+    killjoy(&["pause", "bogus"]).assert().code(2);
+}
+
+// Call `killjoy rules add`, `list` and `remove`, and expect the rule to come and go.
+#[test]
+fn test_rules_add_list_remove() {
+    let (config_dir, _, mut settings_file) = create_skeleton_config();
+    write_session_settings(&mut settings_file);
+    let state_dir = TempDir::new().expect("Failed to create temporary directory.");
+    let killjoy = |args: &[&str]| {
+        Command::new(killjoy_path_as_string())
+            .env("XDG_CONFIG_HOME", config_dir.path())
+            .env("XDG_CONFIG_DIRS", config_dir.path())
+            .env("XDG_STATE_HOME", state_dir.path())
+            .arg("rules")
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+    };
+
+    let rule = r#"{"active_states": ["failed"], "bus_type": "session", "expression": "foo.service",
+        "expression_type": "unit name", "notifiers": ["desktop popup"]}"#;
+    let output = killjoy(&["add", rule]);
+    let id = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+
+    let output = killjoy(&["list"]);
+    let listing = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+    assert!(listing.starts_with(&format!("{}\t", id.trim())));
+    assert!(listing.contains("foo.service"));
+
+    killjoy(&["remove", id.trim()]).assert().code(0);
+    killjoy(&["remove", id.trim()]).assert().code(1);
+    killjoy(&["list"]).assert().code(0).stdout("");
+    killjoy(&["add", &rule.replace("desktop popup", "nonexistent")])
+        .assert()
+        .code(2);
+    killjoy(&["add", "{"]).assert().code(2);
+}
+
+// Execute `killjoy events`, with and without a history file.
+#[test]
+fn test_events() {
+    let state_dir = TempDir::new().expect("Failed to create temporary directory.");
+    let killjoy = |args: &[&str]| {
+        Command::new(killjoy_path_as_string())
+            .env("XDG_STATE_HOME", state_dir.path())
+            .arg("events")
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+    };
+    killjoy(&[]).assert().code(0).stdout("");
+
+    let history_dir = state_dir.path().join("killjoy");
+    fs::create_dir_all(&history_dir).expect("Failed to create history directory.");
+    let history = r#"{"events": [
+        {"bus_type": "session", "unit": "foo.service", "new_state": "active", "timestamp": 0},
+        {
+            "bus_type": "system",
+            "unit": "bar.service",
+            "old_state": "active",
+            "new_state": "failed",
+            "timestamp": 1000000
+        }
+    ]}"#;
+    fs::write(history_dir.join("history.json"), history).expect("Failed to write history file.");
+
+    let output = killjoy(&["--last", "1"]);
+    let listing = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(0);
+    assert_eq!(listing.lines().count(), 1);
+    assert!(listing.ends_with("\tsystem\tbar.service\tactive\tfailed\n"));
+
+    let output = killjoy(&[]);
+    let listing = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    assert_eq!(listing.lines().count(), 2);
+    assert!(listing.starts_with("1970-01-01T00:00:00Z\tsession\tfoo.service\t-\tactive\n"));
+}
+
+// Execute `killjoy events query` against an empty event store.
+#[test]
+fn test_events_query() {
+    let (config_dir, settings_dir, mut settings_file) = create_skeleton_config();
+    let event_store_path = settings_dir.join("events.sqlite3");
+    let settings = format!(
+        r#"{{"version": 1, "rules": [], "notifiers": {{}}, "event_store": {{"path": "{}"}}}}"#,
+        event_store_path.display()
+    );
+    settings_file
+        .write_all(settings.as_bytes())
+        .expect("Failed to populate settings file.");
+    let output = Command::new(killjoy_path_as_string())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env("XDG_CONFIG_DIRS", config_dir.path())
+        .args(["events", "query", "--since", "1h", "--unit", "*.service"])
+        .output()
+        .expect("Failed to run killjoy.");
+    if cfg!(feature = "sqlite") {
+        output.assert().code(0).stdout("");
+    } else {
+        output.assert().code(2);
+    }
+}
+
 // Create a temporary directory containing "killjoy/settings.json".
 //
 // The settings file isempty. The returned tuple is of the form `(temp_dir, settings_dir,
@@ -270,7 +899,7 @@ fn create_skeleton_config() -> (TempDir, PathBuf, File) {
     let settings_file = File::create(settings_dir.join(Path::new("settings.json")))
         .expect("Failed to create settings_file.");
 
-    (xdg_config_home, PathBuf::from(settings_dir), settings_file)
+    (xdg_config_home, settings_dir, settings_file)
 }
 
 // Get the path to killjoy, as a string.
@@ -0,0 +1,433 @@
+// Support for tests which run killjoy against a mock systemd.
+//
+// `TestBus` runs a private dbus-daemon for the duration of a test. `MockSystemd` claims
+// org.freedesktop.systemd1 on that bus, answers the calls which killjoy makes while watching units,
+// and emits the UnitNew, UnitRemoved and PropertiesChanged signals on demand. To point killjoy at
+// the mock, set DBUS_SESSION_BUS_ADDRESS to `TestBus::address`, and let the rules watch the
+// session bus. `MockNotifier` records the notifications which killjoy sends, so that tests may check
+// them. See the `notifier` module.
+
+#![allow(dead_code)]
+
+mod notifier;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::tree::{Factory, MethodErr};
+use dbus::{Connection, Message, NameFlag, Path};
+
+pub use self::notifier::{MockNotifier, ReceivedNotification, NOTIFIER_BUS_NAME};
+
+const BUS_NAME_FOR_SYSTEMD: &str = "org.freedesktop.systemd1";
+const PATH_FOR_SYSTEMD: &str = "/org/freedesktop/systemd1";
+const INTERFACE_FOR_MANAGER: &str = "org.freedesktop.systemd1.Manager";
+const INTERFACE_FOR_PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const INTERFACE_FOR_UNIT: &str = "org.freedesktop.systemd1.Unit";
+
+// How long the mock waits for a message before checking for changes to make, in ms.
+const LOOP_TIMEOUT: u32 = 10;
+
+// The timestamps which killjoy may read, depending on a unit's ActiveState.
+const TIMESTAMP_KEYS: [&str; 5] = [
+    "ActiveEnterTimestamp",
+    "ActiveExitTimestamp",
+    "InactiveEnterTimestamp",
+    "InactiveExitTimestamp",
+    "StateChangeTimestamp",
+];
+
+// A unit's properties, as returned by org.freedesktop.DBus.Properties.GetAll.
+type UnitProps = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+// A dbus-daemon which is killed when dropped.
+pub struct TestBus {
+    pub address: String,
+    daemon: Child,
+}
+
+impl TestBus {
+    // Start a dbus-daemon with the session bus configuration, and learn its address.
+    pub fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start dbus-daemon.");
+        let stdout = daemon
+            .stdout
+            .take()
+            .expect("Failed to get dbus-daemon stdout.");
+        let mut address = String::new();
+        BufReader::new(stdout)
+            .read_line(&mut address)
+            .expect("Failed to read bus address.");
+        TestBus {
+            address: address.trim().to_string(),
+            daemon,
+        }
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+// A unit known to the mock systemd.
+//
+// A unit which isn't loaded isn't listed, and can't be looked up by name, until it's loaded with
+// `MockSystemd::load_unit`.
+#[derive(Clone, Debug)]
+pub struct MockUnit {
+    pub name: String,
+    pub active_state: String,
+    pub loaded: bool,
+}
+
+impl MockUnit {
+    // Describe a loaded unit which is in the given ActiveState.
+    pub fn new(name: &str, active_state: &str) -> Self {
+        MockUnit {
+            name: name.to_string(),
+            active_state: active_state.to_string(),
+            loaded: true,
+        }
+    }
+
+    // Make the unit start out unloaded.
+    pub fn unloaded(mut self) -> Self {
+        self.loaded = false;
+        self
+    }
+}
+
+// A change for the mock systemd to make, and announce.
+enum Change {
+    Load(String),
+    SetActiveState(String, String),
+    Unload(String),
+}
+
+// A unit, as tracked by the mock systemd. `changed` is when its state last changed, in usec.
+struct UnitState {
+    unit: MockUnit,
+    changed: u64,
+}
+
+// A fake systemd, which serves a fixed set of units from a thread of its own.
+//
+// The mock stops when dropped.
+pub struct MockSystemd {
+    changes: Option<Sender<Change>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockSystemd {
+    // Claim org.freedesktop.systemd1 on the given bus, and serve the given units.
+    //
+    // Return once the mock is ready to answer calls.
+    pub fn start(bus: &TestBus, units: &[MockUnit]) -> Self {
+        let address = bus.address.clone();
+        let units = units.to_vec();
+        let (changes, receiver) = mpsc::channel::<Change>();
+        let (ready_sender, ready) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || serve(&address, units, &receiver, &ready_sender));
+        ready.recv().expect("Mock systemd failed to start.");
+        MockSystemd {
+            changes: Some(changes),
+            handle: Some(handle),
+        }
+    }
+
+    // Load a unit, and emit UnitNew.
+    pub fn load_unit(&self, name: &str) {
+        self.send(Change::Load(name.to_string()));
+    }
+
+    // Unload a unit, and emit UnitRemoved.
+    pub fn unload_unit(&self, name: &str) {
+        self.send(Change::Unload(name.to_string()));
+    }
+
+    // Change a unit's ActiveState, and emit PropertiesChanged.
+    pub fn set_active_state(&self, name: &str, active_state: &str) {
+        self.send(Change::SetActiveState(
+            name.to_string(),
+            active_state.to_string(),
+        ));
+    }
+
+    fn send(&self, change: Change) {
+        self.changes
+            .as_ref()
+            .expect("Mock systemd has stopped.")
+            .send(change)
+            .expect("Mock systemd has stopped.");
+    }
+}
+
+impl Drop for MockSystemd {
+    fn drop(&mut self) {
+        self.changes.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Get the path at which systemd serves the given unit, e.g.
+// "/org/freedesktop/systemd1/unit/foo_2eservice".
+pub fn get_unit_path(unit_name: &str) -> String {
+    let mut path = format!("{}/unit/", PATH_FOR_SYSTEMD);
+    for byte in unit_name.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            path.push(byte as char);
+        } else {
+            path.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    path
+}
+
+// Serve the given units on the bus at `address`, until `changes` is closed.
+fn serve(address: &str, units: Vec<MockUnit>, changes: &Receiver<Change>, ready: &Sender<()>) {
+    let conn = Connection::open_private(address).expect("Failed to connect to the test bus.");
+    conn.register()
+        .expect("Failed to register with the test bus.");
+    conn.register_name(BUS_NAME_FOR_SYSTEMD, NameFlag::DoNotQueue as u32)
+        .expect("Failed to claim org.freedesktop.systemd1.");
+
+    let states: Rc<RefCell<HashMap<String, UnitState>>> = Rc::new(RefCell::new(
+        units
+            .iter()
+            .map(|unit| {
+                let state = UnitState {
+                    unit: unit.clone(),
+                    changed: get_timestamp(0),
+                };
+                (unit.name.clone(), state)
+            })
+            .collect(),
+    ));
+
+    let f = Factory::new_fn::<()>();
+    let manager = {
+        let list_states = states.clone();
+        let get_states = states.clone();
+        f.interface(INTERFACE_FOR_MANAGER, ())
+            .add_m(f.method("Subscribe", (), |m| Ok(vec![m.msg.method_return()])))
+            .add_m(f.method("ListUnits", (), move |m| {
+                let listed: Vec<_> = list_states
+                    .borrow()
+                    .values()
+                    .filter(|state| state.unit.loaded)
+                    .map(|state| gen_listed_unit(&state.unit))
+                    .collect();
+                Ok(vec![m.msg.method_return().append1(listed)])
+            }))
+            .add_m(f.method("GetUnit", (), move |m| {
+                let unit_name: &str = m.msg.read1()?;
+                match get_states.borrow().get(unit_name) {
+                    Some(state) if state.unit.loaded => {
+                        let unit_path = Path::new(get_unit_path(unit_name))
+                            .map_err(|err| MethodErr::failed(&err))?;
+                        Ok(vec![m.msg.method_return().append1(unit_path)])
+                    }
+                    _ => Err(MethodErr::failed(&format!(
+                        "Unit {} not loaded.",
+                        unit_name
+                    ))),
+                }
+            }))
+    };
+    let mut tree = f.tree(()).add(
+        f.object_path(PATH_FOR_SYSTEMD, ())
+            .introspectable()
+            .add(manager),
+    );
+    for unit in &units {
+        let get_states = states.clone();
+        let get_name = unit.name.clone();
+        let get_all_states = states.clone();
+        let get_all_name = unit.name.clone();
+        let properties = f
+            .interface(INTERFACE_FOR_PROPERTIES, ())
+            .add_m(f.method("Get", (), move |m| {
+                let (interface_name, property_name): (&str, &str) = m.msg.read2()?;
+                let mut props = get_props(&get_states, &get_name, interface_name)?;
+                match props.remove(property_name) {
+                    Some(variant) => Ok(vec![m.msg.method_return().append1(variant)]),
+                    None => Err(MethodErr::failed(&format!(
+                        "No property named {}.",
+                        property_name
+                    ))),
+                }
+            }))
+            .add_m(f.method("GetAll", (), move |m| {
+                let interface_name: &str = m.msg.read1()?;
+                let props = get_props(&get_all_states, &get_all_name, interface_name)?;
+                Ok(vec![m.msg.method_return().append1(props)])
+            }));
+        tree = tree.add(f.object_path(get_unit_path(&unit.name), ()).add(properties));
+    }
+    tree.set_registered(&conn, true)
+        .expect("Failed to register object paths.");
+    conn.add_handler(tree);
+    ready.send(()).expect("Failed to announce readiness.");
+
+    loop {
+        for _ in conn.incoming(LOOP_TIMEOUT) {}
+        loop {
+            match changes.try_recv() {
+                Ok(change) => apply(&conn, &states, change),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+// Make a change to the units, and emit the signal which announces it.
+fn apply(conn: &Connection, states: &RefCell<HashMap<String, UnitState>>, change: Change) {
+    let mut states = states.borrow_mut();
+    let msg = match change {
+        Change::Load(unit_name) => {
+            get_state(&mut states, &unit_name).unit.loaded = true;
+            gen_manager_signal("UnitNew", &unit_name)
+        }
+        Change::Unload(unit_name) => {
+            get_state(&mut states, &unit_name).unit.loaded = false;
+            gen_manager_signal("UnitRemoved", &unit_name)
+        }
+        Change::SetActiveState(unit_name, active_state) => {
+            let state = get_state(&mut states, &unit_name);
+            state.unit.active_state = active_state;
+            state.changed = get_timestamp(state.changed);
+            let mut changed_props = gen_unit_props(state);
+            changed_props.remove("Id");
+            changed_props.remove("Description");
+            Message::new_signal(
+                get_unit_path(&unit_name),
+                INTERFACE_FOR_PROPERTIES,
+                "PropertiesChanged",
+            )
+            .expect("Failed to create signal.")
+            .append3(INTERFACE_FOR_UNIT, changed_props, Vec::<String>::new())
+        }
+    };
+    conn.send(msg).expect("Failed to send signal.");
+}
+
+// Get the state of the given unit, which the mock must know of.
+fn get_state<'a>(states: &'a mut HashMap<String, UnitState>, unit_name: &str) -> &'a mut UnitState {
+    states
+        .get_mut(unit_name)
+        .unwrap_or_else(|| panic!("Mock systemd doesn't know of {}.", unit_name))
+}
+
+// Get the current time in usec, or one more than `after`, whichever is later.
+fn get_timestamp(after: u64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock is before the epoch.")
+        .as_micros() as u64;
+    now.max(after + 1)
+}
+
+// Get a unit's properties on the given interface.
+fn get_props(
+    states: &RefCell<HashMap<String, UnitState>>,
+    unit_name: &str,
+    interface_name: &str,
+) -> Result<UnitProps, MethodErr> {
+    let states = states.borrow();
+    let state = states
+        .get(unit_name)
+        .ok_or_else(|| MethodErr::failed(&format!("Unit {} not found.", unit_name)))?;
+    if interface_name == INTERFACE_FOR_UNIT {
+        Ok(gen_unit_props(state))
+    } else {
+        Ok(UnitProps::new())
+    }
+}
+
+// Generate the properties of org.freedesktop.systemd1.Unit which killjoy reads.
+fn gen_unit_props(state: &UnitState) -> UnitProps {
+    let mut props = UnitProps::new();
+    let load_state = if state.unit.loaded {
+        "loaded"
+    } else {
+        "not-found"
+    };
+    props.insert("Id".to_string(), Variant(Box::new(state.unit.name.clone())));
+    props.insert(
+        "Description".to_string(),
+        Variant(Box::new(state.unit.name.clone())),
+    );
+    props.insert(
+        "ActiveState".to_string(),
+        Variant(Box::new(state.unit.active_state.clone())),
+    );
+    props.insert(
+        "LoadState".to_string(),
+        Variant(Box::new(load_state.to_string())),
+    );
+    for key in TIMESTAMP_KEYS {
+        props.insert(key.to_string(), Variant(Box::new(state.changed)));
+        props.insert(
+            format!("{}Monotonic", key),
+            Variant(Box::new(state.changed)),
+        );
+    }
+    props
+}
+
+// Describe a unit as listed by org.freedesktop.systemd1.Manager.ListUnits.
+#[allow(clippy::type_complexity)]
+fn gen_listed_unit(
+    unit: &MockUnit,
+) -> (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    Path<'static>,
+    u32,
+    String,
+    Path<'static>,
+) {
+    (
+        unit.name.clone(),
+        unit.name.clone(),
+        "loaded".to_string(),
+        unit.active_state.clone(),
+        unit.active_state.clone(),
+        String::new(),
+        Path::new(get_unit_path(&unit.name)).expect("Failed to create unit path."),
+        0,
+        String::new(),
+        Path::new("/").expect("Failed to create job path."),
+    )
+}
+
+// Generate a UnitNew or UnitRemoved signal for the given unit.
+fn gen_manager_signal(member: &str, unit_name: &str) -> Message {
+    let unit_path = Path::new(get_unit_path(unit_name)).expect("Failed to create unit path.");
+    Message::new_signal(PATH_FOR_SYSTEMD, INTERFACE_FOR_MANAGER, member)
+        .expect("Failed to create signal.")
+        .append2(unit_name, unit_path)
+}
@@ -0,0 +1,150 @@
+// A mock notifier, which records the notifications that killjoy sends it.
+//
+// `MockNotifier` claims `NOTIFIER_BUS_NAME` on a `TestBus`, and implements version 1 of the
+// notifier protocol, `name.jerebear.KilljoyNotifier1.Notify`. Each call is parsed and stored, so
+// that tests may assert on what killjoy sent. To point killjoy at the mock, add a notifier whose
+// `bus_type` is "session" and whose `bus_name` is `NOTIFIER_BUS_NAME`.
+
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use dbus::tree::Factory;
+use dbus::{Connection, NameFlag};
+
+use super::{TestBus, LOOP_TIMEOUT};
+
+// The bus name which the mock notifier claims.
+pub const NOTIFIER_BUS_NAME: &str = "name.jerebear.KilljoyNotifier1";
+
+const INTERFACE_FOR_NOTIFIER: &str = "name.jerebear.KilljoyNotifier1";
+
+// The arguments of one call to `Notify`.
+//
+// The trailing arguments are optional in the protocol, so they're `None` if killjoy didn't send
+// them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReceivedNotification {
+    pub timestamp: u64,
+    pub unit_name: String,
+    pub states: Vec<String>,
+    pub dependents: Option<Vec<String>>,
+    pub journal_lines: Option<Vec<String>>,
+    pub message: Option<String>,
+}
+
+// A fake notifier, which serves from a thread of its own.
+//
+// The mock stops when dropped.
+pub struct MockNotifier {
+    received: Arc<Mutex<Vec<ReceivedNotification>>>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockNotifier {
+    // Claim `NOTIFIER_BUS_NAME` on the given bus, and start recording notifications.
+    //
+    // Return once the mock is ready to answer calls.
+    pub fn start(bus: &TestBus) -> Self {
+        let address = bus.address.clone();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let serve_received = received.clone();
+        let (stop, receiver) = mpsc::channel::<()>();
+        let (ready_sender, ready) = mpsc::channel::<()>();
+        let handle =
+            thread::spawn(move || serve(&address, &serve_received, &receiver, &ready_sender));
+        ready.recv().expect("Mock notifier failed to start.");
+        MockNotifier {
+            received,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    // Get the notifications received so far, oldest first.
+    pub fn received(&self) -> Vec<ReceivedNotification> {
+        self.received
+            .lock()
+            .expect("Mock notifier panicked.")
+            .clone()
+    }
+
+    // Wait until a notification about the given unit is received, or panic after `timeout`.
+    pub fn wait_for(&self, unit_name: &str, timeout: Duration) -> ReceivedNotification {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            let found = self
+                .received()
+                .into_iter()
+                .find(|notification| notification.unit_name == unit_name);
+            if let Some(notification) = found {
+                return notification;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("Mock notifier wasn't notified about {}", unit_name);
+    }
+}
+
+impl Drop for MockNotifier {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Record notifications sent over the bus at `address` in `received`, until `stop` is closed.
+fn serve(
+    address: &str,
+    received: &Arc<Mutex<Vec<ReceivedNotification>>>,
+    stop: &Receiver<()>,
+    ready: &Sender<()>,
+) {
+    let conn = Connection::open_private(address).expect("Failed to connect to the test bus.");
+    conn.register()
+        .expect("Failed to register with the test bus.");
+    conn.register_name(NOTIFIER_BUS_NAME, NameFlag::DoNotQueue as u32)
+        .expect("Failed to claim the notifier's bus name.");
+
+    let f = Factory::new_fn::<()>();
+    let notify_received = received.clone();
+    let notifier =
+        f.interface(INTERFACE_FOR_NOTIFIER, ())
+            .add_m(f.method("Notify", (), move |m| {
+                let mut args = m.msg.iter_init();
+                let notification = ReceivedNotification {
+                    timestamp: args.read()?,
+                    unit_name: args.read()?,
+                    states: args.read()?,
+                    dependents: args.read().ok(),
+                    journal_lines: args.read().ok(),
+                    message: args.read().ok(),
+                };
+                notify_received
+                    .lock()
+                    .expect("Test panicked while holding notifications.")
+                    .push(notification);
+                Ok(vec![m.msg.method_return()])
+            }));
+    let path = format!("/{}", NOTIFIER_BUS_NAME.replace('.', "/"));
+    let tree = f
+        .tree(())
+        .add(f.object_path(path, ()).introspectable().add(notifier));
+    tree.set_registered(&conn, true)
+        .expect("Failed to register object paths.");
+    conn.add_handler(tree);
+    ready.send(()).expect("Failed to announce readiness.");
+
+    loop {
+        for _ in conn.incoming(LOOP_TIMEOUT) {}
+        if let Err(TryRecvError::Disconnected) = stop.try_recv() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// Tests which run killjoy against a mock systemd on a private bus.
+//
+// Unlike the tests for the CLI, these tests can exercise the paths taken when systemd is reachable:
+// listing units, matching them against rules, and following their signals.
+
+mod support;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use assert_cmd::cargo;
+use assert_cmd::prelude::OutputAssertExt;
+use serde_json::Value;
+use tempfile::TempDir;
+
+use support::{MockNotifier, MockSystemd, MockUnit, TestBus, NOTIFIER_BUS_NAME};
+
+// How long to wait for killjoy to react to a change.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Call `killjoy check`, and expect it to report only the failed unit which a rule matches.
+#[test]
+fn test_check_finds_failed_unit() {
+    let bus = TestBus::start();
+    let _systemd = MockSystemd::start(
+        &bus,
+        &[
+            MockUnit::new("foo.service", "failed"),
+            MockUnit::new("bar.service", "active"),
+            MockUnit::new("baz.timer", "failed"),
+        ],
+    );
+    let env = TestEnv::new(r#"{"expression": ".service", "expression_type": "unit type"}"#);
+    let output = env.run_killjoy(&bus, &["check"]);
+    let stdout = String::from_utf8(output.stdout.clone()).expect("Output isn't UTF-8.");
+    output.assert().code(1);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", stdout);
+    assert!(
+        lines[0].ends_with("\tsession\tfoo.service\tfailed\tinfo"),
+        "{}",
+        stdout
+    );
+}
+
+// Call `killjoy check`, and expect success, as no unit matched by a rule has failed.
+#[test]
+fn test_check_finds_nothing() {
+    let bus = TestBus::start();
+    let _systemd = MockSystemd::start(&bus, &[MockUnit::new("foo.service", "active")]);
+    let env = TestEnv::new(r#"{"expression": "foo.service", "expression_type": "unit name"}"#);
+    env.run_killjoy(&bus, &["check"])
+        .assert()
+        .code(0)
+        .stdout("");
+}
+
+// Run killjoy, change a unit's state, and expect killjoy to follow it.
+#[test]
+fn test_run_properties_changed() {
+    let bus = TestBus::start();
+    let systemd = MockSystemd::start(&bus, &[MockUnit::new("foo.service", "active")]);
+    let env = TestEnv::new(r#"{"expression": "foo.service", "expression_type": "unit name"}"#);
+    let _killjoy = env.spawn_killjoy(&bus);
+
+    env.wait_for_event("foo.service", None, "active");
+    systemd.set_active_state("foo.service", "failed");
+    env.wait_for_event("foo.service", Some("active"), "failed");
+}
+
+// Run killjoy, load a unit, and expect killjoy to start watching it.
+#[test]
+fn test_run_unit_new() {
+    let bus = TestBus::start();
+    let systemd = MockSystemd::start(
+        &bus,
+        &[
+            MockUnit::new("foo.service", "active"),
+            MockUnit::new("bar.service", "failed").unloaded(),
+        ],
+    );
+    let env = TestEnv::new(r#"{"expression": ".service", "expression_type": "unit type"}"#);
+    let _killjoy = env.spawn_killjoy(&bus);
+
+    env.wait_for_event("foo.service", None, "active");
+    systemd.load_unit("bar.service");
+    env.wait_for_event("bar.service", None, "failed");
+    systemd.set_active_state("bar.service", "active");
+    env.wait_for_event("bar.service", Some("failed"), "active");
+}
+
+// Run killjoy, unload a unit, and expect killjoy to stop watching it, but to keep going.
+#[test]
+fn test_run_unit_removed() {
+    let bus = TestBus::start();
+    let systemd = MockSystemd::start(
+        &bus,
+        &[
+            MockUnit::new("foo.service", "active"),
+            MockUnit::new("bar.service", "active"),
+        ],
+    );
+    let env = TestEnv::new(r#"{"expression": ".service", "expression_type": "unit type"}"#);
+    let _killjoy = env.spawn_killjoy(&bus);
+
+    env.wait_for_event("foo.service", None, "active");
+    env.wait_for_event("bar.service", None, "active");
+    systemd.unload_unit("foo.service");
+    systemd.set_active_state("bar.service", "failed");
+    env.wait_for_event("bar.service", Some("active"), "failed");
+}
+
+// Run killjoy, fail a unit, and expect the notifier to be told about it.
+#[test]
+fn test_run_notify() {
+    let bus = TestBus::start();
+    let systemd = MockSystemd::start(
+        &bus,
+        &[
+            MockUnit::new("foo.service", "active"),
+            MockUnit::new("bar.service", "active"),
+        ],
+    );
+    let notifier = MockNotifier::start(&bus);
+    let env = TestEnv::new(r#"{"expression": ".service", "expression_type": "unit type"}"#);
+    let _killjoy = env.spawn_killjoy(&bus);
+
+    env.wait_for_event("foo.service", None, "active");
+    env.wait_for_event("bar.service", None, "active");
+    systemd.set_active_state("foo.service", "failed");
+    let notification = notifier.wait_for("foo.service", TIMEOUT);
+    assert_eq!(notification.states[0], "failed");
+    assert!(notification.timestamp > 0);
+    assert_eq!(notification.message, None);
+    let received = notifier.received();
+    assert_eq!(received.len(), 1, "{:?}", received);
+}
+
+// A killjoy process which is killed when dropped.
+struct Killjoy(Child);
+
+impl Drop for Killjoy {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Temporary XDG directories, with a settings file which logs every event to `event_log`.
+//
+// The rule in the settings file notifies a `MockNotifier`, if one is running.
+struct TestEnv {
+    dir: TempDir,
+    event_log: PathBuf,
+}
+
+impl TestEnv {
+    // Create a settings file with one rule, which watches the session bus for failed units.
+    //
+    // `rule` is a JSON object which gives the rule's expression and expression type.
+    fn new(rule: &str) -> Self {
+        let dir = TempDir::new().expect("Failed to create temporary directory.");
+        let settings_dir = dir.path().join("config").join("killjoy");
+        fs::create_dir_all(&settings_dir).expect("Failed to create settings directory.");
+        let event_log = dir.path().join("events.jsonl");
+        let mut rule: Value = serde_json::from_str(rule).expect("Failed to parse rule.");
+        rule["active_states"] = serde_json::json!(["failed"]);
+        rule["bus_type"] = serde_json::json!("session");
+        rule["notifiers"] = serde_json::json!(["mock"]);
+        let settings = serde_json::json!({
+            "version": 1,
+            "rules": [rule],
+            "notifiers": {
+                "mock": {"bus_type": "session", "bus_name": NOTIFIER_BUS_NAME}
+            },
+            "event_log": {"path": event_log},
+        });
+        fs::write(settings_dir.join("settings.json"), settings.to_string())
+            .expect("Failed to write settings file.");
+        TestEnv { dir, event_log }
+    }
+
+    // Prepare to run killjoy in this environment, on the given bus.
+    fn killjoy(&self, bus: &TestBus) -> Command {
+        let config_dir = self.dir.path().join("config");
+        let mut command = Command::new(cargo::cargo_bin("killjoy"));
+        command
+            .env("DBUS_SESSION_BUS_ADDRESS", &bus.address)
+            .env("XDG_CONFIG_HOME", &config_dir)
+            .env("XDG_CONFIG_DIRS", &config_dir)
+            .env("XDG_RUNTIME_DIR", self.dir.path().join("runtime"))
+            .env("XDG_STATE_HOME", self.dir.path().join("state"));
+        command
+    }
+
+    // Run killjoy with the given arguments, and wait for it to exit.
+    fn run_killjoy(&self, bus: &TestBus, args: &[&str]) -> Output {
+        self.killjoy(bus)
+            .args(args)
+            .output()
+            .expect("Failed to run killjoy.")
+    }
+
+    // Run killjoy as a daemon, until the returned handle is dropped.
+    fn spawn_killjoy(&self, bus: &TestBus) -> Killjoy {
+        let child = self
+            .killjoy(bus)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to run killjoy.");
+        Killjoy(child)
+    }
+
+    // Wait until killjoy logs the given transition, or panic after a while.
+    fn wait_for_event(&self, unit: &str, old_state: Option<&str>, new_state: &str) {
+        let started = Instant::now();
+        while started.elapsed() < TIMEOUT {
+            if has_event(&self.event_log, unit, old_state, new_state) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!(
+            "killjoy didn't see {} go from {:?} to {}",
+            unit, old_state, new_state
+        );
+    }
+}
+
+// Tell whether the given event log, in JSONL format, records the given transition.
+fn has_event(event_log: &Path, unit: &str, old_state: Option<&str>, new_state: &str) -> bool {
+    let contents = fs::read_to_string(event_log).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .any(|event| {
+            event["unit"] == unit
+                && event["old_state"].as_str() == old_state
+                && event["new_state"] == new_state
+        })
+}